@@ -12,7 +12,7 @@ pub struct DbQuery {
     db: String,
 }
 
-impl<'tp> DbQuery {
+impl DbQuery {
     /// ### Initialize a new empty database
     /// #### Usage
     /// ```rust
@@ -78,7 +78,7 @@ impl<'tp> DbQuery {
     /// let mut foo = DatabaseQuery::new();
     /// foo.list()
     /// ```
-    pub fn list(&self) -> &'tp [u8] {
+    pub fn list(&self) -> &[u8] {
         from_op(&TuringOp::DbList)
     }
 }
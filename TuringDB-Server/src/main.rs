@@ -1,205 +1,163 @@
 #![forbid(unsafe_code)]
-#![deny(missing_docs)]
 
-//! TuringDB-Server is a key-value database written using async code and backed by `sled.rs`  embedded key/value store.
+//! TuringDB-Server serves a TuringDB repo to clients over the wire protocol of `turingdb::TuringServer`.
 //! This is the server version, the library can be found by searching crates-io for `turingdb`
 //! or checking under the Github repository https://github.com/charleschege/TuringDB/TuringDB/
 //!
-//!
-//! This codebase uses `sled` as the underlying key/value store and builds upon that
-//! to provide other functionality like
-//!
-//! 1. in-memory keys,
-//! 2. async-locks for increased acid guarantees
-//! 3. Insert operations will fail if a key already exists, use `modify()` method on a key to change its value
-//! 4. in-memory locks to ensure that document locks are not dropped until the application is halted
-//!
-//! Some features that are under development include
-//!
-//! 1. Replication
-//! 2. Multi-cluster queries
-//! 3. Changefeeds without polling, inspired by RethinkDB
-//! 4. Running the server as a daemon
-//! 5. Loggin
-//!
 //! To install the server, run `cargo install turingdb-server`
 //!
-//! To run the server, run `turingdb-server` from a terminal
-
-use anyhow::Result;
-use async_dup::Arc;
-use async_net::{TcpListener, TcpStream};
-use custom_codes::DbOps;
-use futures_lite::*;
-use smol::Task;
-use std::net::{Shutdown, SocketAddr};
-use turingdb::TuringEngine;
-use turingdb_helpers::{to_op, TuringOp};
-
-mod repo_query;
-use repo_query::*;
-
-mod db_query;
-use db_query::*;
-
-mod document_query;
-use document_query::*;
-
-mod field_query;
-use field_query::*;
-
-mod errors;
-
-const BUFFER_CAPACITY: usize = 64 * 1024; //16Kb
-const BUFFER_DATA_CAPACITY: usize = 1024 * 1024 * 16; // Db cannot hold data more than 16MB in size
-
-// FIXME Create a heartbeat of 100ms to check for when a repository is deliberately manipulated in the
-// file system by the OS. Or acquire a lock to prevent modification by another process
-//FIXME 1. ENABLE LISTENING FOR SIGNIT and other SIGNALS OVER CTRL-C AND NETWORKED SIGNALS OVER 4340
-//FIXME 2. ENABLE RECORDING OF UNDERGOING OPERATIONS
-//FIXME 5. LOGGING OF ERRORS
-fn main() -> anyhow::Result<()> {
-    // Initialize here to prevent issues with borrowing
-    let storage = Arc::new(TuringEngine::new());
-
-    smol::run(async {
-        let storage = Arc::clone(&storage);
-        match storage.repo_init().await {
-            Ok(_) => (),
-            Err(e) => {
-                eprintln!("[TuringDB::<INIT>::(ERROR)-{:?}]", e); //FIXME log!()
-                std::process::exit(1);
-            }
-        };
-
-        let listener = TcpListener::bind("127.0.0.1:4343").await?;
-        println!("Listening on {}", listener.local_addr()?);
-
-        while let Some(stream) = listener.incoming().next().await {
-            let stream = stream?;
-            let storage = Arc::clone(&storage);
+//! To run the server, run `turingdb-server` from a terminal, `turingdb-server --help` lists its flags.
+//! The repo is created when it does not exist yet. The server stops on SIGINT or SIGTERM,
+//! once the connections finished the commands they are running and the repo is committed.
+//! Started with `--replica-of`, the server follows another one and serves reads until it is promoted.
+//! Started with `--raft-id`, the server is a node of a Raft cluster along with its `--raft-peers`.
+//! Started with `--writer-id`, the server takes writes along with its `--writer-peers` and sends them to each other.
+//! Started with `--shards`, the server routes the commands of clients to the servers holding each document.
+//! Started with `--gossip-address`, the server finds the other nodes of its cluster from its `--gossip-seeds`
+//! and tells clients which nodes are up.
+//! Started with `--repair-from`, the server copies the documents it holds differently from another one in the background
+
+mod settings;
+use settings::{Command, LogLevel, Settings, USAGE};
+
+use async_executor::Executor;
+use futures_lite::future;
+use std::{process, sync::Arc};
+use turingdb::{
+    termination_signal, AntiEntropy, Replica, TuringEngine, TuringResult, TuringServer,
+};
+
+fn main() {
+    let settings = match Settings::load() {
+        Ok(Command::Serve(settings)) => *settings,
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+
+            return;
+        }
+        Ok(Command::Version) => {
+            println!("turingdb-server {}", env!("CARGO_PKG_VERSION"));
 
-            Task::spawn(async move {
-                match handle_client(stream, storage).await {
-                    Ok(addr) => {
-                        println!("x[TERMINATED] device[{}:{}]", addr.ip(), addr.port())
-                        //FIXME log!()
-                    }
-                    Err(error) => {
-                        eprintln!("{:?}", error); //FIXME log!()
-                    }
-                }
-            })
-            .await;
+            return;
+        }
+        Err(error) => {
+            eprintln!("turingdb-server: {}\n\n{}", error, USAGE);
+            process::exit(2);
         }
+    };
 
-        Ok(())
-    })
+    if let Err(error) = serve(&settings) {
+        settings.log(LogLevel::Error, format!("{:?}", error));
+        process::exit(1);
+    }
 }
 
-async fn handle_client(mut stream: TcpStream, storage: Arc<TuringEngine>) -> Result<SocketAddr> {
-    println!("↓[CONNECTED] device[{}]", stream.peer_addr()?);
-
-    let mut buffer = [0; BUFFER_CAPACITY];
-    let mut container_buffer: Vec<u8> = Vec::new();
-    let mut bytes_read: usize;
-
-    loop {
-        //check the buffer size is not more that 16MB in size to avoid DoS attack by using huge memory
-        if container_buffer.len() > BUFFER_DATA_CAPACITY {
-            handle_response(
-                &mut stream,
-                DbOps::EncounteredErrors(
-                    "[TuringDB::<GLOBAL>::(ERROR)-BUFFER_CAPACITY_EXCEEDED_16MB]".into(),
-                ),
-            )
-            .await?;
-        }
-
-        bytes_read = stream.read(&mut buffer).await?;
+fn serve(settings: &Settings) -> TuringResult<()> {
+    let executor = Executor::new();
 
-        if bytes_read == 0 {
-            let peer = stream.peer_addr()?;
-            //Shutdown the TCP address
-            stream.shutdown(Shutdown::Both)?;
-            // Terminate the stream if the client terminates the connection by sending 0 bytes
-            return Ok(peer);
+    future::block_on(executor.run(async {
+        let mut engine = match settings.get_repo() {
+            Some(repo) => TuringEngine::with_path(repo).await?,
+            None => TuringEngine::new().await?,
+        };
+        let repo_dir = engine.get_repo_dir().await.clone();
+        if !repo_dir.exists() {
+            engine.repo_create().await?;
+            settings.log(LogLevel::Info, format!("Created the repo at {}", repo_dir));
+        }
+        engine.repo_init().await?;
+
+        let engine = Arc::new(engine);
+        // Dropping a task cancels it, the maintenance tasks run until the process exits
+        let maintenance = vec![
+            engine.spawn_compaction(&executor),
+            engine.spawn_reaper(&executor),
+            engine.spawn_vacuum(&executor),
+            engine.spawn_scrubber(&executor),
+            engine.spawn_archiver(&executor),
+            engine.spawn_watchdog(&executor),
+        ];
+        for task in maintenance.into_iter().flatten() {
+            task.detach();
         }
+        engine.spawn_index_rebuild(&executor).detach();
 
-        // Check if the current stream is less than the buffer capacity, if so all data has been received
-        if buffer[..bytes_read].len() < BUFFER_CAPACITY {
-            // Ensure that the data is appended before being deserialized by bincode
-            container_buffer.append(&mut buffer[..bytes_read].to_owned());
-            let op = to_op(&[container_buffer[0]]);
-            let op_result = process_op(&op, storage.clone(), &container_buffer[1..]).await;
-            handle_response(&mut stream, op_result).await?;
+        if let Some(primary) = settings.get_replica_of() {
+            let mut replica = Replica::new(primary);
+            if let Some(admin_token) = settings.get_admin_token() {
+                replica = replica.set_admin_token(admin_token);
+            }
+
+            let engine = Arc::clone(&engine);
+            executor
+                .spawn(async move {
+                    match replica.run(engine).await {
+                        Ok(()) => settings.log(LogLevel::Info, "Promoted, writes are accepted"),
+                        Err(error) => settings.log(
+                            LogLevel::Error,
+                            format!("Stopped following {}: {:?}", primary, error),
+                        ),
+                    }
+                })
+                .detach();
+            settings.log(LogLevel::Info, format!("Following {}", primary));
         }
-        // Append data to buffer
-        container_buffer.append(&mut buffer[..bytes_read].to_owned());
-    }
-}
 
-async fn process_op(op: &TuringOp, storage: Arc<TuringEngine>, value: &[u8]) -> DbOps {
-    match op {
-        &TuringOp::RepoCreate => RepoQuery::create(storage).await,
-        &TuringOp::RepoDrop => RepoQuery::drop(storage).await,
-        &TuringOp::DbCreate => DbQuery::create(storage, value).await,
-        &TuringOp::DbList => DbQuery::list(storage).await,
-        &TuringOp::DbDrop => DbQuery::drop(storage, value).await,
-        &TuringOp::DocumentCreate => DocumentQuery::create(storage, value).await,
-        &TuringOp::DocumentList => DocumentQuery::list(storage, value).await,
-        &TuringOp::DocumentDrop => DocumentQuery::drop(storage, value).await,
-        &TuringOp::FieldInsert => FieldQuery::insert(storage, value).await,
-        &TuringOp::FieldGet => FieldQuery::get(storage, value).await,
-        &TuringOp::FieldRemove => FieldQuery::remove(storage, value).await,
-        &TuringOp::FieldModify => FieldQuery::modify(storage, value).await,
-        &TuringOp::FieldList => FieldQuery::list(storage, value).await,
-        &TuringOp::NotSupported => DbOps::NotExecuted,
-    }
-}
+        if let Some(peer) = settings.get_repair_from() {
+            let mut anti_entropy = AntiEntropy::new(peer);
+            if let Some(admin_token) = settings.get_admin_token() {
+                anti_entropy = anti_entropy.set_admin_token(admin_token);
+            }
 
-async fn handle_response(stream: &mut TcpStream, ops: DbOps) -> Result<()> {
-    let ops_to_bytes = bincode::serialize::<DbOps>(&ops)?;
-    stream.write(&ops_to_bytes).await?;
-    stream.flush().await?;
+            let engine = Arc::clone(&engine);
+            executor
+                .spawn(async move {
+                    if let Err(error) = anti_entropy.run(engine).await {
+                        settings.log(
+                            LogLevel::Error,
+                            format!("Stopped repairing from {}: {:?}", peer, error),
+                        );
+                    }
+                })
+                .detach();
+            settings.log(LogLevel::Info, format!("Repairing from {}", peer));
+        }
 
-    Ok(())
-}
+        let shutdown = termination_signal()?;
+        settings.log(
+            LogLevel::Info,
+            format!(
+                "Serving the repo at {} on {}",
+                repo_dir,
+                settings.get_listen()
+            ),
+        );
+
+        let mut server = TuringServer::new().set_address(settings.get_listen());
+        if let Some(admin_token) = settings.get_admin_token() {
+            server = server.set_admin_token(admin_token);
+        }
+        if let Some(raft) = settings.get_raft() {
+            server = server.set_raft(raft.clone());
+        }
+        if let Some(multi_writer) = settings.get_multi_writer() {
+            server = server.set_multi_writer(multi_writer.clone());
+        }
+        if let Some(shards) = settings.get_shards() {
+            server = server.set_router(shards.clone());
+        }
+        if let Some(gossip) = settings.get_gossip() {
+            server = server.set_gossip(gossip.clone());
+        }
 
-/*let (signal_sender, signal_receiver) = signal_msg::new();
-signal_sender.prepare_signals();
+        server
+            .run_until(Arc::clone(&engine), &executor, async {
+                shutdown.await;
+                settings.log(LogLevel::Info, "Shutting down");
+            })
+            .await?;
+        settings.log(LogLevel::Info, "The repo is committed and closed");
 
-#[derive(Debug, PartialEq, Eq)]
-enum State {
-    Running,
-    Terminate,
-}
- let mut state = State::Running;
-
-    match signal_receiver.listen() {
-        Ok(signal) => {
-            println!("Received {:?} Terminating....", signal);
-            state = State::Terminate;
-        },
-        Err(e) => eprintln!("[SIGNAL_MSG_ERROR] - {:?}", e), //FIXME
-    }*/
-
-/*
-/// Create a new repository/directory that contains the databases
-async fn create_ops_log_file(&self) -> Result<()> {
-    unblock!(OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .open("TuringDB_Repo/ops.log"))?;
-    Ok(())
-}
-/// Create a new repository/directory that contains the databases
-async fn create_errors_log_file(&self) -> Result<()> {
-    unblock!(OpenOptions::new()
-        .create_new(true)
-        .write(true)
-        .open("TuringDB_Repo/errors.log"))?;
-
-    Ok(())
+        Ok(())
+    }))
 }
-*/
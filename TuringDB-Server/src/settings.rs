@@ -0,0 +1,482 @@
+use std::{
+    env,
+    fmt::{self, Display},
+    fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use turingdb::{Gossip, HashRing, MultiWriter, Raft, TuringServer};
+
+/// Printed for `--help` and along with a flag that cannot be read
+pub(crate) const USAGE: &str = "\
+Usage: turingdb-server [OPTIONS]
+
+Options:
+    -l, --listen <ADDRESS>   The address to listen on [env: TURINGDB_LISTEN] [default: 127.0.0.1:4343]
+    -r, --repo <PATH>        The directory of the repo, created when it does not exist [env: TURINGDB_REPO]
+                             [default: the TuringDB directory of the user]
+    -c, --config <FILE>      A file of `key = value` lines setting `listen`, `repo`, `log_level`,
+                             `admin_token`, `replica_of`, `raft_id`, `raft_peers`, `writer_id`,
+                             `writer_peers`, `shards`, `gossip_address`, `gossip_seeds` or `repair_from`
+                             [env: TURINGDB_CONFIG]
+        --log-level <LEVEL>  error, warn, info or debug [env: TURINGDB_LOG_LEVEL] [default: info]
+        --admin-token <TOKEN>
+                             Serve admin commands to clients authenticating with the token,
+                             prefer the variable or the config file over the flag [env: TURINGDB_ADMIN_TOKEN]
+        --replica-of <ADDRESS>
+                             Follow the server at the address and refuse writes until promoted,
+                             the admin token has to be the one of that server [env: TURINGDB_REPLICA_OF]
+        --raft-id <ID>       Run as the node of a Raft cluster with the id, unique within the cluster,
+                             every node shares the admin token [env: TURINGDB_RAFT_ID]
+        --raft-peers <PEERS> The other nodes of the Raft cluster as `ID=ADDRESS` separated by commas
+                             [env: TURINGDB_RAFT_PEERS]
+        --writer-id <ID>     Run as a writer of a multi-writer cluster with the id, unique within the cluster,
+                             every writer shares the admin token [env: TURINGDB_WRITER_ID]
+        --writer-peers <ADDRESSES>
+                             The other writers of the cluster separated by commas [env: TURINGDB_WRITER_PEERS]
+        --shards <ADDRESSES> Run as the router of a sharded cluster, forwarding commands to the servers
+                             at the addresses separated by commas [env: TURINGDB_SHARDS]
+        --gossip-address <ADDRESS>
+                             Gossip with the other nodes of the cluster, which reach the server at the address,
+                             every node shares the admin token [env: TURINGDB_GOSSIP_ADDRESS]
+        --gossip-seeds <ADDRESSES>
+                             Nodes of the cluster to gossip with until others are known, separated by commas
+                             [env: TURINGDB_GOSSIP_SEEDS]
+        --repair-from <ADDRESS>
+                             Compare the databases with the server at the address in the background and copy
+                             the documents that differ, the admin token has to be the one of that server
+                             [env: TURINGDB_REPAIR_FROM]
+    -h, --help               Print this message
+    -V, --version            Print the version
+
+A flag takes precedence over its environment variable, which takes precedence over the config file
+";
+
+/// How much the server reports on stderr, every level includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level.to_ascii_lowercase().as_str() {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(format!("`{}` is not a log level", level)),
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = match self {
+            LogLevel::Error => "ERROR",
+            LogLevel::Warn => "WARN",
+            LogLevel::Info => "INFO",
+            LogLevel::Debug => "DEBUG",
+        };
+
+        f.write_str(level)
+    }
+}
+
+/// What the command line asks the server to do
+#[derive(Debug)]
+pub(crate) enum Command {
+    Serve(Box<Settings>),
+    Help,
+    Version,
+}
+
+/// The settings as given by one source, a flag, the environment or the config file
+#[derive(Debug, Default)]
+struct Given {
+    listen: Option<String>,
+    repo: Option<String>,
+    config: Option<String>,
+    log_level: Option<String>,
+    admin_token: Option<String>,
+    replica_of: Option<String>,
+    raft_id: Option<String>,
+    raft_peers: Option<String>,
+    writer_id: Option<String>,
+    writer_peers: Option<String>,
+    shards: Option<String>,
+    gossip_address: Option<String>,
+    gossip_seeds: Option<String>,
+    repair_from: Option<String>,
+}
+
+impl Given {
+    fn flags(args: Vec<String>) -> Result<Given, String> {
+        let mut given = Given::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline) = match arg.find('=') {
+                Some(index) if arg.starts_with("--") => {
+                    (&arg[..index], Some(arg[index + 1..].to_owned()))
+                }
+                _ => (arg.as_str(), None),
+            };
+            let slot = match flag {
+                "-l" | "--listen" => &mut given.listen,
+                "-r" | "--repo" => &mut given.repo,
+                "-c" | "--config" => &mut given.config,
+                "--log-level" => &mut given.log_level,
+                "--admin-token" => &mut given.admin_token,
+                "--replica-of" => &mut given.replica_of,
+                "--raft-id" => &mut given.raft_id,
+                "--raft-peers" => &mut given.raft_peers,
+                "--writer-id" => &mut given.writer_id,
+                "--writer-peers" => &mut given.writer_peers,
+                "--shards" => &mut given.shards,
+                "--gossip-address" => &mut given.gossip_address,
+                "--gossip-seeds" => &mut given.gossip_seeds,
+                "--repair-from" => &mut given.repair_from,
+                _ => return Err(format!("unknown flag `{}`", flag)),
+            };
+
+            *slot = match inline.or_else(|| args.next()) {
+                Some(value) => Some(value),
+                None => return Err(format!("`{}` needs a value", flag)),
+            };
+        }
+
+        Ok(given)
+    }
+
+    fn environment() -> Given {
+        Given {
+            listen: env::var("TURINGDB_LISTEN").ok(),
+            repo: env::var("TURINGDB_REPO").ok(),
+            config: env::var("TURINGDB_CONFIG").ok(),
+            log_level: env::var("TURINGDB_LOG_LEVEL").ok(),
+            admin_token: env::var("TURINGDB_ADMIN_TOKEN").ok(),
+            replica_of: env::var("TURINGDB_REPLICA_OF").ok(),
+            raft_id: env::var("TURINGDB_RAFT_ID").ok(),
+            raft_peers: env::var("TURINGDB_RAFT_PEERS").ok(),
+            writer_id: env::var("TURINGDB_WRITER_ID").ok(),
+            writer_peers: env::var("TURINGDB_WRITER_PEERS").ok(),
+            shards: env::var("TURINGDB_SHARDS").ok(),
+            gossip_address: env::var("TURINGDB_GOSSIP_ADDRESS").ok(),
+            gossip_seeds: env::var("TURINGDB_GOSSIP_SEEDS").ok(),
+            repair_from: env::var("TURINGDB_REPAIR_FROM").ok(),
+        }
+    }
+    /// Lines are `key = value`, the value may be quoted. Empty lines and lines starting with `#` are skipped
+    fn config_file(path: &Path) -> Result<Given, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("cannot read `{}`: {}", path.display(), error))?;
+        let mut given = Given::default();
+
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(equals) => (line[..equals].trim(), line[equals + 1..].trim()),
+                None => {
+                    return Err(format!(
+                        "{}:{} is not a `key = value` line",
+                        path.display(),
+                        index + 1
+                    ))
+                }
+            };
+            let value = value.trim_matches('"').to_owned();
+
+            match key {
+                "listen" => given.listen = Some(value),
+                "repo" => given.repo = Some(value),
+                "log_level" => given.log_level = Some(value),
+                "admin_token" => given.admin_token = Some(value),
+                "replica_of" => given.replica_of = Some(value),
+                "raft_id" => given.raft_id = Some(value),
+                "raft_peers" => given.raft_peers = Some(value),
+                "writer_id" => given.writer_id = Some(value),
+                "writer_peers" => given.writer_peers = Some(value),
+                "shards" => given.shards = Some(value),
+                "gossip_address" => given.gossip_address = Some(value),
+                "gossip_seeds" => given.gossip_seeds = Some(value),
+                "repair_from" => given.repair_from = Some(value),
+                _ => {
+                    return Err(format!(
+                        "{}:{} sets `{}` which is not a setting",
+                        path.display(),
+                        index + 1,
+                        key
+                    ))
+                }
+            }
+        }
+
+        Ok(given)
+    }
+    /// Keep the settings of `self`, taking the ones it lacks from `fallback`
+    fn or(self, fallback: Given) -> Given {
+        Given {
+            listen: self.listen.or(fallback.listen),
+            repo: self.repo.or(fallback.repo),
+            config: self.config.or(fallback.config),
+            log_level: self.log_level.or(fallback.log_level),
+            admin_token: self.admin_token.or(fallback.admin_token),
+            replica_of: self.replica_of.or(fallback.replica_of),
+            raft_id: self.raft_id.or(fallback.raft_id),
+            raft_peers: self.raft_peers.or(fallback.raft_peers),
+            writer_id: self.writer_id.or(fallback.writer_id),
+            writer_peers: self.writer_peers.or(fallback.writer_peers),
+            shards: self.shards.or(fallback.shards),
+            gossip_address: self.gossip_address.or(fallback.gossip_address),
+            gossip_seeds: self.gossip_seeds.or(fallback.gossip_seeds),
+            repair_from: self.repair_from.or(fallback.repair_from),
+        }
+    }
+}
+
+/// What the server is started with
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub(crate) struct Settings {
+///     listen: SocketAddr,
+///     repo: Option<PathBuf>,
+///     log_level: LogLevel,
+///     admin_token: Option<String>,
+///     replica_of: Option<SocketAddr>,
+///     raft: Option<Raft>,
+///     multi_writer: Option<MultiWriter>,
+///     shards: Option<HashRing>,
+///     gossip: Option<Gossip>,
+///     repair_from: Option<SocketAddr>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Settings {
+    listen: SocketAddr,
+    // `None` for the TuringDB directory of the user
+    repo: Option<PathBuf>,
+    log_level: LogLevel,
+    admin_token: Option<String>,
+    // The primary the repo follows
+    replica_of: Option<SocketAddr>,
+    raft: Option<Raft>,
+    multi_writer: Option<MultiWriter>,
+    // Set on a router
+    shards: Option<HashRing>,
+    gossip: Option<Gossip>,
+    // The peer the databases are repaired from
+    repair_from: Option<SocketAddr>,
+}
+
+impl Settings {
+    /// Read the flags, then the environment, then the config file either of them points to
+    pub(crate) fn load() -> Result<Command, String> {
+        let args = env::args().skip(1).collect::<Vec<String>>();
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            return Ok(Command::Help);
+        }
+        if args.iter().any(|arg| arg == "-V" || arg == "--version") {
+            return Ok(Command::Version);
+        }
+
+        let mut given = Given::flags(args)?.or(Given::environment());
+        if let Some(config) = given.config.take() {
+            given = given.or(Given::config_file(Path::new(&config))?);
+        }
+
+        let listen = match given.listen {
+            None => TuringServer::new().get_address(),
+            Some(listen) => listen
+                .parse::<SocketAddr>()
+                .map_err(|_| format!("`{}` is not a socket address", listen))?,
+        };
+        let replica_of = match given.replica_of {
+            None => None,
+            Some(replica_of) => Some(
+                replica_of
+                    .parse::<SocketAddr>()
+                    .map_err(|_| format!("`{}` is not a socket address", replica_of))?,
+            ),
+        };
+        let repair_from = match given.repair_from {
+            None => None,
+            Some(repair_from) => Some(
+                repair_from
+                    .parse::<SocketAddr>()
+                    .map_err(|_| format!("`{}` is not a socket address", repair_from))?,
+            ),
+        };
+        let raft = match given.raft_id {
+            None if given.raft_peers.is_some() => {
+                return Err("`--raft-peers` needs `--raft-id`".into())
+            }
+            None => None,
+            Some(raft_id) => {
+                let raft_id = raft_id
+                    .parse::<u64>()
+                    .map_err(|_| format!("`{}` is not a Raft node id", raft_id))?;
+                let mut raft = Raft::new(raft_id);
+                if let Some(admin_token) = &given.admin_token {
+                    raft = raft.set_admin_token(admin_token);
+                }
+
+                for peer in given.raft_peers.iter().flat_map(|peers| peers.split(',')) {
+                    let (id, address) = match peer.find('=') {
+                        Some(equals) => (peer[..equals].trim(), peer[equals + 1..].trim()),
+                        None => return Err(format!("`{}` is not an `ID=ADDRESS` peer", peer)),
+                    };
+                    raft = raft.add_peer(
+                        id.parse::<u64>()
+                            .map_err(|_| format!("`{}` is not a Raft node id", id))?,
+                        address
+                            .parse::<SocketAddr>()
+                            .map_err(|_| format!("`{}` is not a socket address", address))?,
+                    );
+                }
+
+                Some(raft)
+            }
+        };
+        let multi_writer = match given.writer_id {
+            None if given.writer_peers.is_some() => {
+                return Err("`--writer-peers` needs `--writer-id`".into())
+            }
+            None => None,
+            Some(_) if raft.is_some() => {
+                return Err("`--writer-id` cannot be used along with `--raft-id`".into())
+            }
+            Some(writer_id) => {
+                let writer_id = writer_id
+                    .parse::<u64>()
+                    .map_err(|_| format!("`{}` is not a writer id", writer_id))?;
+                let mut multi_writer = MultiWriter::new(writer_id);
+                if let Some(admin_token) = &given.admin_token {
+                    multi_writer = multi_writer.set_admin_token(admin_token);
+                }
+
+                for peer in given.writer_peers.iter().flat_map(|peers| peers.split(',')) {
+                    multi_writer = multi_writer.add_peer(
+                        peer.trim()
+                            .parse::<SocketAddr>()
+                            .map_err(|_| format!("`{}` is not a socket address", peer))?,
+                    );
+                }
+
+                Some(multi_writer)
+            }
+        };
+        let shards = match given.shards {
+            None => None,
+            Some(shards) => {
+                let mut ring = HashRing::new();
+                for shard in shards.split(',') {
+                    ring = ring.add_node(
+                        shard
+                            .trim()
+                            .parse::<SocketAddr>()
+                            .map_err(|_| format!("`{}` is not a socket address", shard))?,
+                    );
+                }
+
+                Some(ring)
+            }
+        };
+        let gossip = match given.gossip_address {
+            None if given.gossip_seeds.is_some() => {
+                return Err("`--gossip-seeds` needs `--gossip-address`".into())
+            }
+            None => None,
+            Some(gossip_address) => {
+                let gossip_address = gossip_address
+                    .parse::<SocketAddr>()
+                    .map_err(|_| format!("`{}` is not a socket address", gossip_address))?;
+                // A router forwards the commands of clients and holds no shard
+                let mut gossip = Gossip::new(gossip_address).set_shard(shards.is_none());
+                if let Some(admin_token) = &given.admin_token {
+                    gossip = gossip.set_admin_token(admin_token);
+                }
+
+                for seed in given.gossip_seeds.iter().flat_map(|seeds| seeds.split(',')) {
+                    gossip = gossip.add_seed(
+                        seed.trim()
+                            .parse::<SocketAddr>()
+                            .map_err(|_| format!("`{}` is not a socket address", seed))?,
+                    );
+                }
+
+                Some(gossip)
+            }
+        };
+        let log_level = match given.log_level {
+            None => LogLevel::Info,
+            Some(log_level) => log_level.parse::<LogLevel>()?,
+        };
+
+        Ok(Command::Serve(Box::new(Settings {
+            listen,
+            repo: given.repo.map(PathBuf::from),
+            log_level,
+            admin_token: given.admin_token,
+            replica_of,
+            raft,
+            multi_writer,
+            shards,
+            gossip,
+            repair_from,
+        })))
+    }
+
+    pub(crate) fn get_listen(&self) -> SocketAddr {
+        self.listen
+    }
+
+    pub(crate) fn get_repo(&self) -> Option<&Path> {
+        self.repo.as_deref()
+    }
+
+    pub(crate) fn get_admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+
+    pub(crate) fn get_replica_of(&self) -> Option<SocketAddr> {
+        self.replica_of
+    }
+
+    pub(crate) fn get_raft(&self) -> Option<&Raft> {
+        self.raft.as_ref()
+    }
+
+    pub(crate) fn get_multi_writer(&self) -> Option<&MultiWriter> {
+        self.multi_writer.as_ref()
+    }
+
+    pub(crate) fn get_shards(&self) -> Option<&HashRing> {
+        self.shards.as_ref()
+    }
+
+    pub(crate) fn get_gossip(&self) -> Option<&Gossip> {
+        self.gossip.as_ref()
+    }
+
+    pub(crate) fn get_repair_from(&self) -> Option<SocketAddr> {
+        self.repair_from
+    }
+    /// Print `message` to stderr when `level` is within the log level
+    pub(crate) fn log(&self, level: LogLevel, message: impl Display) {
+        if level <= self.log_level {
+            eprintln!("[{}] {}", level, message);
+        }
+    }
+}
@@ -1,9 +1,14 @@
-use async_lock::Mutex;
 use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
 use sled::IVec;
-use std::io::ErrorKind;
+use std::{io::ErrorKind, net::SocketAddr};
+use tai64::TAI64N;
 
-use crate::TuringDB;
+use crate::{
+    AggregateGroup, BackupManifest, Compression, Conflict, Crdt, DbStats, DocumentTimes, FieldData,
+    IndexKind, IndexStats, IntegrityReport, Matched, Neighbour, Partitioning, Populated, QueryPlan,
+    Revision, SchemaViolation, SearchHit, Value, Version, WireError,
+};
 
 const REPO_NAME: &str = "TuringDB-Repo";
 
@@ -19,6 +24,7 @@ pub enum TuringDbError {
     DbNotFound,
     DocumentNotFound,
     KeyAlreadyExists,
+    FieldNotFound,
     InvalidPathUnicodeName,
     NotFound,
     PermissionDenied,
@@ -42,6 +48,48 @@ pub enum TuringDbError {
     SystemViolation(String),
     Bug(String),
     DocumentCorrupted { at: Option<sled::DiskPtr>, bt: () },
+    Serialization(String),
+    RepoLocked { pid: u32 },
+    SnapshotNotFound,
+    Compression(String),
+    EphemeralRepo,
+    RevisionNotFound,
+    UnsupportedFormat { found: u32, supported: u32 },
+    StreamedField,
+    ChunkCorrupted { chunk: u64 },
+    QuotaExceeded { quota: u64, used: u64 },
+    PartitionNotFound,
+    Storage(String),
+    QuerySyntax { at: usize, reason: String },
+    InvalidContinuation,
+    FieldTypeMismatch,
+    NumericOverflow,
+    RevisionConflict { current: u64 },
+    ConditionNotMet,
+    CursorNotFound,
+    InvalidPattern(String),
+    SchemaViolation(Vec<SchemaViolation>),
+    NotVectorDatabase,
+    ViewNotFound,
+    IndexNotFound,
+    UniqueKeyNotSet,
+    FrameTooLarge { len: u64, max: u64 },
+    Server(WireError),
+    Tls(String),
+    TooManyConnections { max: u64 },
+    Throttled { retry_after_ms: u64 },
+    TransactionNotFound,
+    SavepointNotFound,
+    DocumentLocked,
+    LockNotHeld,
+    LogGap,
+    ReadOnlyReplica,
+    NotLeader { leader: Option<SocketAddr> },
+    NotReplicated,
+    NotSharded,
+    NotClustered,
+    StaleReplica { staleness_ms: Option<u64> },
+    CrdtMismatch,
 }
 
 impl From<std::io::Error> for TuringDbError {
@@ -87,21 +135,162 @@ impl From<sled::Error> for TuringDbError {
     }
 }
 
+impl From<bincode::Error> for TuringDbError {
+    fn from(error: bincode::Error) -> Self {
+        TuringDbError::Serialization(error.to_string())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum OpsOutcome {
     /// A temporary value for testing
     OpsOutcomePlaceholder,
     RepoCreated,
     RepoInitialized,
+    RepoCommitted,
+    RepoClosed,
+    RepoRecovered {
+        quarantined: Vec<Utf8PathBuf>,
+    },
+    RepoMigrated {
+        from: u32,
+        to: u32,
+    },
     RepoEmpty,
     DbCreated,
     DbDropped,
+    DbCompressionSet,
+    DbStructureSet,
+    DbAttached {
+        name: Utf8PathBuf,
+    },
+    DbDetached {
+        path: Utf8PathBuf,
+    },
     DbList(Vec<Utf8PathBuf>),
     DbEmpty,
     DocumentList(Vec<Utf8PathBuf>),
     DocumentCreated,
     DocumentDropped,
+    DocumentExpirySet,
+    DocumentsExpired(Vec<(Utf8PathBuf, Utf8PathBuf)>),
+    DocumentsArchived(Vec<(Utf8PathBuf, Utf8PathBuf)>),
+    DocumentMatches {
+        documents: Vec<Matched>,
+        continuation: Option<String>,
+    },
+    Aggregated(Vec<AggregateGroup>),
+    DocumentPatched,
+    DocumentInserted,
+    DocumentReplaced {
+        previous: Vec<(Vec<u8>, FieldData)>,
+    },
+    BatchWritten {
+        ops: usize,
+    },
+    DocumentUpdated {
+        revision: u64,
+    },
+    Incremented(Value),
+    CursorOpened {
+        cursor: u64,
+    },
+    CursorBatch {
+        documents: Vec<Matched>,
+        exhausted: bool,
+    },
+    CursorClosed,
+    CursorsClosed {
+        closed: usize,
+    },
+    DocumentTimes(Vec<(Utf8PathBuf, DocumentTimes)>),
+    Neighbours(Vec<Neighbour>),
+    DocumentPopulated(Populated),
     FieldInserted,
+    FieldContents(FieldData),
+    FieldModified,
+    FieldRemoved,
+    FieldStreamed {
+        len: u64,
+    },
+    Tombstones(Vec<(Vec<u8>, TAI64N)>),
+    Vacuumed {
+        purged: usize,
+    },
+    DocumentHistory(Vec<Revision>),
+    DocumentRevision {
+        revision: u64,
+        fields: Vec<(Vec<u8>, FieldData)>,
+    },
+    LogCompacted {
+        removed: usize,
+    },
+    SnapshotTaken {
+        name: String,
+        lsn: Option<u64>,
+    },
+    SnapshotRestored {
+        name: String,
+        lsn: Option<u64>,
+    },
+    BackupTaken(BackupManifest),
+    DbStats(DbStats),
+    RepoStats(Vec<(Utf8PathBuf, DbStats)>),
+    Scrubbed(IntegrityReport),
+    PartitionDropped {
+        documents: Vec<Utf8PathBuf>,
+    },
+    ViewCreated,
+    ViewDropped,
+    ViewList(Vec<Utf8PathBuf>),
+    DbTrashSet,
+    DocumentRestored,
+    TrashList(Vec<(Utf8PathBuf, TAI64N)>),
+    TrashPurged {
+        documents: Vec<Utf8PathBuf>,
+    },
+    DbCollationSet,
+    IndexCreated,
+    IndexDropped,
+    IndexList(Vec<(Vec<Vec<u8>>, IndexKind)>),
+    DbUniqueKeySet,
+    DbTextIndexSet,
+    SearchHits(Vec<SearchHit>),
+    IndexesRebuilt(Vec<Utf8PathBuf>),
+    DbTtlIndexSet,
+    QueryPlan(QueryPlan),
+    IndexStats(Vec<IndexStats>),
+    DbPrefixIndexSet,
+    Suggestions(Vec<String>),
+    TransactionBegun {
+        transaction: u64,
+    },
+    TransactionStaged {
+        ops: usize,
+    },
+    TransactionRolledBack {
+        ops: usize,
+    },
+    SavepointSet,
+    TransactionsClosed {
+        closed: usize,
+    },
+    ReplicaSeeded {
+        lsn: Option<u64>,
+    },
+    ReplicaPromoted {
+        lsn: Option<u64>,
+    },
+    DocumentLeased {
+        token: u64,
+    },
+    DocumentUnlocked,
+    FieldMerged,
+    FieldConflicts {
+        version: Option<Version>,
+        conflicts: Vec<Conflict>,
+    },
+    CrdtState(Crdt),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -129,40 +318,52 @@ impl RepoPath {
 pub type DBName = Utf8PathBuf;
 pub type RepoName = Utf8PathBuf;
 pub type DocumentName = Utf8PathBuf;
-pub type FieldKey = DataType;
-pub type FieldValue = DataType;
-
-pub struct TuringDBOps(DBName);
+pub type FieldKey = Vec<u8>;
+pub type FieldValue = TDBCell;
 
-impl Default for TuringDBOps {
-    fn default() -> Self {
-        Self(Utf8PathBuf::default())
-    }
+#[derive(Default)]
+pub struct TuringDBOps {
+    db_name: DBName,
+    compression: Compression,
+    partitioning: Partitioning,
 }
 
 impl TuringDBOps {
     pub fn set_db_name(mut self, db_name: &str) -> Self {
-        self.0 = Utf8Path::new(&db_name).to_path_buf();
+        self.db_name = Utf8Path::new(&db_name).to_path_buf();
+
+        self
+    }
+    /// How the values of fields in the database are compressed on disk
+    pub fn set_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+
+        self
+    }
+    /// How the documents of a new database are laid out on disk
+    pub fn set_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.partitioning = partitioning;
 
         self
     }
 
     pub fn get_db_name(&self) -> Utf8PathBuf {
-        self.0.to_owned()
+        self.db_name.to_owned()
+    }
+
+    pub fn get_compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn get_partitioning(&self) -> Partitioning {
+        self.partitioning
     }
 }
+#[derive(Default)]
 pub struct TuringDBDocumentOps {
     db_name: DBName,
     document_name: DocumentName,
-}
-
-impl Default for TuringDBDocumentOps {
-    fn default() -> Self {
-        Self {
-            db_name: DBName::default(),
-            document_name: DocumentName::default(),
-        }
-    }
+    expires_at: Option<TAI64N>,
 }
 
 impl TuringDBDocumentOps {
@@ -177,6 +378,12 @@ impl TuringDBDocumentOps {
 
         self
     }
+    /// The time after which the document is removed by the expiry reaper
+    pub fn set_expires_at(mut self, expires_at: TAI64N) -> Self {
+        self.expires_at = Some(expires_at);
+
+        self
+    }
 
     pub fn get_db_name(&self) -> Utf8PathBuf {
         self.db_name.to_owned()
@@ -185,8 +392,13 @@ impl TuringDBDocumentOps {
     pub fn get_document_name(&self) -> Utf8PathBuf {
         self.document_name.to_owned()
     }
+
+    pub fn get_expires_at(&self) -> Option<TAI64N> {
+        self.expires_at
+    }
 }
 
+#[derive(Default)]
 pub struct TuringDBFieldOps {
     db_name: DBName,
     document_name: DocumentName,
@@ -207,6 +419,18 @@ impl TuringDBFieldOps {
         self
     }
 
+    pub fn key(mut self, field_name: &[u8]) -> Self {
+        self.field_name = field_name.to_owned();
+
+        self
+    }
+
+    pub fn value(mut self, data_type: DataType, data: &[u8]) -> Self {
+        self.field_value = TDBCell::new(data_type, data);
+
+        self
+    }
+
     pub fn get_db_name(&self) -> Utf8PathBuf {
         self.db_name.to_owned()
     }
@@ -216,11 +440,11 @@ impl TuringDBFieldOps {
     }
 
     pub fn get_key(&self) -> FieldKey {
-        self.field_name
+        self.field_name.to_owned()
     }
 
     pub fn get_value(&self) -> FieldValue {
-        self.field_value
+        self.field_value.to_owned()
     }
 }
 
@@ -246,7 +470,7 @@ enum SerDerType {
 }
 */
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum DataType {
     Boolean = 0x00,
     U8 = 0x01,
@@ -283,17 +507,41 @@ pub enum DataType {
     CHACHAPOLY1305 = 0x32,
     XCHACHABLAKE3SIV = 0x33,
     AES256GCM = 0x34,
+    VECTOR = 0x35,
+    REFERENCE = 0x36,
 }
 
-const TRUE: u8 = 1;
-const FALSE: u8 = 1;
-
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TDBCell {
     data_type: DataType,
     data: Vec<u8>,
 }
 
+impl Default for TDBCell {
+    fn default() -> Self {
+        Self {
+            data_type: DataType::BINARY,
+            data: Vec::default(),
+        }
+    }
+}
+
 impl TDBCell {
+    pub fn new(data_type: DataType, data: &[u8]) -> Self {
+        Self {
+            data_type,
+            data: data.to_owned(),
+        }
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+
     pub fn data_type(&mut self, value: DataType) -> &mut Self {
         self.data_type = value;
 
@@ -0,0 +1,258 @@
+use crate::{FieldSource, Filter, TuringResult, Value};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What an aggregation computes over the documents of a group
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Accumulator {
+///     Count,
+///     Sum(Vec<u8>),
+///     Avg(Vec<u8>),
+///     Min(Vec<u8>),
+///     Max(Vec<u8>),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Accumulator {
+    /// The number of documents in the group
+    Count,
+    /// The sum of the numbers in a field, an integer unless a float was added or the sum overflowed
+    Sum(Vec<u8>),
+    /// The mean of the numbers in a field as a float
+    Avg(Vec<u8>),
+    /// The smallest value of a field, in the order values are sorted in
+    Min(Vec<u8>),
+    /// The largest value of a field, in the order values are sorted in
+    Max(Vec<u8>),
+}
+
+impl Accumulator {
+    pub(crate) fn key(&self) -> Option<&[u8]> {
+        match self {
+            Accumulator::Count => None,
+            Accumulator::Sum(key)
+            | Accumulator::Avg(key)
+            | Accumulator::Min(key)
+            | Accumulator::Max(key) => Some(key),
+        }
+    }
+}
+
+/// Groups the documents of a database matched by a filter and computes accumulators over each group
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub struct Aggregation {
+///     db: Utf8PathBuf,
+///     filter: Filter,
+///     group_by: Option<Vec<u8>>,
+///     accumulators: Vec<Accumulator>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Aggregation {
+    db: Utf8PathBuf,
+    filter: Filter,
+    // `None` puts every matching document in a single group
+    group_by: Option<Vec<u8>>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl Aggregation {
+    /// Aggregate every document of the database `db` into a single group
+    pub fn new(db: &Utf8Path) -> Self {
+        Self {
+            db: db.into(),
+            filter: Filter::All,
+            group_by: None,
+            accumulators: Vec::new(),
+        }
+    }
+
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+
+        self
+    }
+    /// Put documents with equal values of the field in the same group
+    pub fn set_group_by(mut self, key: &[u8]) -> Self {
+        self.group_by = Some(key.into());
+
+        self
+    }
+    /// Add an accumulator, each group holds its results in the order accumulators were added
+    pub fn add_accumulator(mut self, accumulator: Accumulator) -> Self {
+        self.accumulators.push(accumulator);
+
+        self
+    }
+
+    pub fn get_db(&self) -> &Utf8Path {
+        &self.db
+    }
+
+    pub fn get_filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn get_group_by(&self) -> Option<&[u8]> {
+        self.group_by.as_deref()
+    }
+
+    pub fn get_accumulators(&self) -> &[Accumulator] {
+        &self.accumulators
+    }
+    /// Add a matching document to the group it belongs to
+    pub(crate) fn accumulate<S: FieldSource + ?Sized>(
+        &self,
+        groups: &mut BTreeMap<Option<Value>, Vec<Accumulated>>,
+        source: &S,
+    ) -> TuringResult<()> {
+        let group = match &self.group_by {
+            None => None,
+            Some(key) => Aggregation::read(source, key)?,
+        };
+
+        let accumulated = groups
+            .entry(group)
+            .or_insert_with(|| vec![Accumulated::default(); self.accumulators.len()]);
+
+        for (accumulator, accumulated) in self.accumulators.iter().zip(accumulated.iter_mut()) {
+            let value = match accumulator.key() {
+                None => None,
+                Some(key) => Aggregation::read(source, key)?,
+            };
+
+            accumulated.add(accumulator, value);
+        }
+
+        Ok(())
+    }
+    /// Finish the accumulators of every group, groups are sorted by their value
+    pub(crate) fn finish(
+        &self,
+        groups: BTreeMap<Option<Value>, Vec<Accumulated>>,
+    ) -> Vec<AggregateGroup> {
+        groups
+            .into_iter()
+            .map(|(group, accumulated)| AggregateGroup {
+                group,
+                values: self
+                    .accumulators
+                    .iter()
+                    .zip(accumulated)
+                    .map(|(accumulator, accumulated)| accumulated.finish(accumulator))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn read<S: FieldSource + ?Sized>(source: &S, key: &[u8]) -> TuringResult<Option<Value>> {
+        Ok(source
+            .read_field(key)?
+            .map(|field_data| Value::from_field(&field_data)))
+    }
+}
+
+/// The running state of an accumulator over a group
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Accumulated {
+    documents: u64,
+    numbers: u64,
+    // `None` once a float was added or the integer sum overflowed
+    int_sum: Option<i128>,
+    float_sum: f64,
+    extreme: Option<Value>,
+}
+
+impl Accumulated {
+    fn add(&mut self, accumulator: &Accumulator, value: Option<Value>) {
+        self.documents += 1;
+
+        let value = match value {
+            None => return,
+            Some(value) => value,
+        };
+
+        match accumulator {
+            Accumulator::Count => (),
+            Accumulator::Sum(_) | Accumulator::Avg(_) => {
+                let (int, float) = match value {
+                    Value::Int(int) => (Some(int), int as f64),
+                    Value::UInt(uint) => (Some(uint as i128).filter(|int| *int >= 0), uint as f64),
+                    Value::Float(float) => (None, float),
+                    _ => return,
+                };
+
+                self.int_sum = match (self.numbers, self.int_sum, int) {
+                    (0, _, int) => int,
+                    (_, Some(sum), Some(int)) => sum.checked_add(int),
+                    _ => None,
+                };
+                self.float_sum += float;
+                self.numbers += 1;
+            }
+            Accumulator::Min(_) => {
+                if self.extreme.as_ref().is_none_or(|min| value < *min) {
+                    self.extreme = Some(value);
+                }
+            }
+            Accumulator::Max(_) => {
+                if self.extreme.as_ref().is_none_or(|max| value > *max) {
+                    self.extreme = Some(value);
+                }
+            }
+        }
+    }
+    /// Documents without the field, or without a number in it for sums and means, are left out.
+    /// An accumulator that was left with nothing has no value
+    fn finish(self, accumulator: &Accumulator) -> Option<Value> {
+        match accumulator {
+            Accumulator::Count => Some(Value::UInt(self.documents as u128)),
+            Accumulator::Sum(_) => {
+                if self.numbers == 0 {
+                    return None;
+                }
+
+                match self.int_sum {
+                    Some(sum) => Some(Value::Int(sum)),
+                    None => Some(Value::Float(self.float_sum)),
+                }
+            }
+            Accumulator::Avg(_) => {
+                if self.numbers == 0 {
+                    return None;
+                }
+
+                Some(Value::Float(self.float_sum / self.numbers as f64))
+            }
+            Accumulator::Min(_) | Accumulator::Max(_) => self.extreme,
+        }
+    }
+}
+
+/// The results of the accumulators of an aggregation over one group
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct AggregateGroup {
+///     group: Option<Value>,
+///     values: Vec<Option<Value>>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct AggregateGroup {
+    // The value of the field documents were grouped by, `None` for the documents without it
+    group: Option<Value>,
+    values: Vec<Option<Value>>,
+}
+
+impl AggregateGroup {
+    pub fn get_group(&self) -> Option<&Value> {
+        self.group.as_ref()
+    }
+    /// The result of each accumulator in the order they were added
+    pub fn get_values(&self) -> &[Option<Value>] {
+        &self.values
+    }
+}
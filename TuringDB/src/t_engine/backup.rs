@@ -0,0 +1,153 @@
+use crate::{MetaEncoding, MetaFile, Quarantine, TuringDbError, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tai64::TAI64N;
+
+const BACKUP_MANIFEST_NAME: &str = "BACKUP.manifest";
+
+/// A file in a backup together with its checksum
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct BackupFile {
+///     path: Utf8PathBuf,
+///     len: u64,
+///     checksum: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BackupFile {
+    path: Utf8PathBuf,
+    len: u64,
+    checksum: u64,
+}
+
+impl BackupFile {
+    /// The path of the file relative to the backup directory
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+    /// The length of the file in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    /// Check whether the file is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+    /// The SeaHash checksum of the contents of the file
+    pub fn checksum(&self) -> u64 {
+        self.checksum
+    }
+}
+
+/// Lists every file written by a backup so that the copy can be verified before it is restored.
+/// The files are laid out like a snapshot so a backup copied into `.snapshots/<name>` can be restored
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct BackupManifest {
+///     taken: TAI64N,
+///     lsn: Option<u64>,
+///     files: Vec<BackupFile>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BackupManifest {
+    taken: TAI64N,
+    lsn: Option<u64>,
+    files: Vec<BackupFile>,
+}
+
+impl BackupManifest {
+    /// Checksum the files written to `backup_dir`
+    pub(crate) async fn build(
+        backup_dir: &Utf8Path,
+        lsn: Option<u64>,
+        paths: &[Utf8PathBuf],
+    ) -> TuringResult<Self> {
+        let mut files = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let contents = async_fs::read(path).await?;
+
+            let relative_path = match path.strip_prefix(backup_dir) {
+                Ok(relative_path) => relative_path.to_path_buf(),
+                Err(_) => return Err(TuringDbError::InvalidInput),
+            };
+
+            files.push(BackupFile {
+                path: relative_path,
+                len: contents.len() as u64,
+                checksum: seahash::hash(&contents),
+            });
+        }
+
+        Ok(Self {
+            taken: TAI64N::now(),
+            lsn,
+            files,
+        })
+    }
+    /// The time the backup finished
+    pub fn taken(&self) -> TAI64N {
+        self.taken
+    }
+    /// The last log sequence number the backup is consistent with once restored
+    pub fn lsn(&self) -> Option<u64> {
+        self.lsn
+    }
+    /// The files in the backup
+    pub fn files(&self) -> &[BackupFile] {
+        &self.files
+    }
+    /// Check every file in `backup_dir` against the manifest, returning the files
+    /// that are missing or whose contents no longer match their checksum
+    pub async fn verify(&self, backup_dir: &Utf8Path) -> TuringResult<Vec<Utf8PathBuf>> {
+        let mut mismatched = Vec::new();
+
+        for file in self.files.iter() {
+            let mut path: Utf8PathBuf = backup_dir.into();
+            path.push(&file.path);
+
+            match async_fs::read(&path).await {
+                Ok(contents) => {
+                    if contents.len() as u64 != file.len
+                        || seahash::hash(&contents) != file.checksum
+                    {
+                        mismatched.push(file.path.clone());
+                    }
+                }
+                Err(error) => {
+                    if error.kind() != std::io::ErrorKind::NotFound {
+                        return Err(error.into());
+                    }
+
+                    mismatched.push(file.path.clone());
+                }
+            }
+        }
+
+        Ok(mismatched)
+    }
+    /// Read the manifest of the backup in `backup_dir`
+    pub async fn load(
+        backup_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<BackupManifest>> {
+        MetaFile::read::<BackupManifest>(&BackupManifest::path(backup_dir), quarantine).await
+    }
+
+    pub(crate) async fn persist(&self, backup_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(
+            &BackupManifest::path(backup_dir),
+            &MetaEncoding::Bincode.encode(self)?,
+        )
+        .await
+    }
+
+    fn path(backup_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = backup_dir.into();
+        path.push(BACKUP_MANIFEST_NAME);
+
+        path
+    }
+}
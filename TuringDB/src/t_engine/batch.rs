@@ -0,0 +1,152 @@
+use crate::{LogOp, Patch, Structure, TDBCell, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+/// A write to a document of the database a batch is applied to
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum WriteOp {
+///     DocumentCreate { document: Utf8PathBuf },
+///     DocumentDrop { document: Utf8PathBuf },
+///     DocumentUpsert { document: Utf8PathBuf, fields: Vec<(Vec<u8>, TDBCell)> },
+///     DocumentPatch { document: Utf8PathBuf, patch: Patch },
+///     FieldInsert { document: Utf8PathBuf, key: Vec<u8>, value: TDBCell },
+///     FieldModify { document: Utf8PathBuf, key: Vec<u8>, value: TDBCell },
+///     FieldRemove { document: Utf8PathBuf, key: Vec<u8> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteOp {
+    DocumentCreate {
+        document: Utf8PathBuf,
+    },
+    DocumentDrop {
+        document: Utf8PathBuf,
+    },
+    DocumentUpsert {
+        document: Utf8PathBuf,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    },
+    DocumentPatch {
+        document: Utf8PathBuf,
+        patch: Patch,
+    },
+    FieldInsert {
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldModify {
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldRemove {
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+}
+
+impl WriteOp {
+    /// The document the write is made to
+    pub fn document(&self) -> &Utf8Path {
+        match self {
+            WriteOp::DocumentCreate { document }
+            | WriteOp::DocumentDrop { document }
+            | WriteOp::DocumentUpsert { document, .. }
+            | WriteOp::DocumentPatch { document, .. }
+            | WriteOp::FieldInsert { document, .. }
+            | WriteOp::FieldModify { document, .. }
+            | WriteOp::FieldRemove { document, .. } => document,
+        }
+    }
+    /// The number of bytes the write may add to the database
+    pub(crate) fn len(&self) -> u64 {
+        match self {
+            WriteOp::DocumentCreate { .. }
+            | WriteOp::DocumentDrop { .. }
+            | WriteOp::FieldRemove { .. } => 0,
+            WriteOp::DocumentUpsert { fields, .. } => fields
+                .iter()
+                .map(|(key, value)| (key.len() + value.get_data().len()) as u64)
+                .sum(),
+            WriteOp::DocumentPatch { patch, .. } => patch.len(),
+            WriteOp::FieldInsert { key, value, .. } | WriteOp::FieldModify { key, value, .. } => {
+                (key.len() + value.get_data().len()) as u64
+            }
+        }
+    }
+    /// The write with the values it writes checked against the structure of the database,
+    /// coerced to their declared types and filled in with defaults as the schema asks
+    pub(crate) fn check(self, structure: &Structure) -> TuringResult<WriteOp> {
+        match self {
+            WriteOp::DocumentUpsert { document, fields } => Ok(WriteOp::DocumentUpsert {
+                document,
+                fields: structure.check_fields(fields)?,
+            }),
+            WriteOp::FieldInsert {
+                document,
+                key,
+                value,
+            } => Ok(WriteOp::FieldInsert {
+                value: structure.check_value(&key, value)?,
+                document,
+                key,
+            }),
+            WriteOp::FieldModify {
+                document,
+                key,
+                value,
+            } => Ok(WriteOp::FieldModify {
+                value: structure.check_value(&key, value)?,
+                document,
+                key,
+            }),
+            op => Ok(op),
+        }
+    }
+    /// The operation the write is applied as, in the database `db`
+    pub(crate) fn log_op(&self, db: &Utf8Path) -> LogOp {
+        let db = db.to_path_buf();
+
+        match self.clone() {
+            WriteOp::DocumentCreate { document } => LogOp::DocumentCreate {
+                db,
+                document,
+                expires_at: None,
+            },
+            WriteOp::DocumentDrop { document } => LogOp::DocumentDrop { db, document },
+            WriteOp::DocumentUpsert { document, fields } => LogOp::DocumentUpsert {
+                db,
+                document,
+                fields,
+            },
+            WriteOp::DocumentPatch { document, patch } => LogOp::DocumentPatch {
+                db,
+                document,
+                patch,
+            },
+            WriteOp::FieldInsert {
+                document,
+                key,
+                value,
+            } => LogOp::FieldInsert {
+                db,
+                document,
+                key,
+                value,
+            },
+            WriteOp::FieldModify {
+                document,
+                key,
+                value,
+            } => LogOp::FieldModify {
+                db,
+                document,
+                key,
+                value,
+            },
+            WriteOp::FieldRemove { document, key } => LogOp::FieldRemove { db, document, key },
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{MetaEncoding, MetaFile, Quarantine, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tai64::TAI64N;
+
+const BLOOM_META_NAME: &str = "BLOOM.meta";
+/// The share of lookups of missing documents still sent to disk
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+/// The names of the documents of a partition hashed into a bit set, so a lookup of a document
+/// that is not there is answered without listing the partition. A name never inserted may still
+/// be reported, never the other way around
+/// ```
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// pub(crate) struct BloomFilter {
+///     bits: Vec<u64>,
+///     hashes: u32,
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct BloomFilter {
+    bits: Vec<u64>,
+    hashes: u32,
+}
+
+impl BloomFilter {
+    /// A filter sized for `expected` names at `FALSE_POSITIVE_RATE`
+    pub(crate) fn with_capacity(expected: usize) -> Self {
+        let expected = expected.max(1) as f64;
+        let ln_2 = std::f64::consts::LN_2;
+        let bits = (-expected * FALSE_POSITIVE_RATE.ln() / (ln_2 * ln_2)).ceil() as usize;
+        let hashes = ((bits as f64 / expected) * ln_2).round().max(1.0) as u32;
+
+        Self {
+            bits: vec![0; bits.div_ceil(64)],
+            hashes,
+        }
+    }
+    /// Build a filter holding every name
+    pub(crate) fn build<'a, I: ExactSizeIterator<Item = &'a Utf8PathBuf>>(names: I) -> Self {
+        let mut filter = BloomFilter::with_capacity(names.len());
+        for document_name in names {
+            filter.insert(document_name);
+        }
+
+        filter
+    }
+
+    pub(crate) fn insert(&mut self, document_name: &Utf8Path) {
+        for bit in self.bits_of(document_name) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+    /// `false` only when the name was never inserted
+    pub(crate) fn may_contain(&self, document_name: &Utf8Path) -> bool {
+        self.bits_of(document_name)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+    // Double hashing, each probe is `first + i * second` over the bit set
+    fn bits_of(&self, document_name: &Utf8Path) -> impl Iterator<Item = usize> {
+        let bytes = document_name.as_str().as_bytes();
+        let first = seahash::hash(bytes);
+        let second = seahash::hash_seeded(bytes, 1, 2, 3, 4) | 1;
+        let len = (self.bits.len() * 64) as u64;
+
+        (0..self.hashes as u64)
+            .map(move |probe| (first.wrapping_add(probe.wrapping_mul(second)) % len) as usize)
+    }
+    /// Load the filters of the partitions of a database last committed at `committed`. Filters
+    /// persisted by another commit may miss documents written since, so none are used then
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+        committed: TAI64N,
+    ) -> TuringResult<Vec<Option<BloomFilter>>> {
+        let persisted = MetaFile::read::<(TAI64N, Vec<Option<BloomFilter>>)>(
+            &BloomFilter::path(db_dir),
+            quarantine,
+        )
+        .await?;
+
+        match persisted {
+            Some((stamp, filters)) if stamp == committed => Ok(filters),
+            _ => Ok(Vec::new()),
+        }
+    }
+    /// Persist the filters of the partitions along with a commit of their database made at `committed`
+    pub(crate) async fn persist(
+        db_dir: &Utf8Path,
+        filters: &[Option<BloomFilter>],
+        committed: TAI64N,
+    ) -> TuringResult<()> {
+        MetaFile::write(
+            &BloomFilter::path(db_dir),
+            &MetaEncoding::Bincode.encode(&(committed, filters))?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(BLOOM_META_NAME);
+
+        path
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{LogOp, LogRecord};
+use async_channel::{Receiver, Sender, TrySendError};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+
+/// How many changes a subscriber may fall behind before it is dropped
+pub const CHANGE_BUFFER: usize = 1024;
+
+/// The writes applied to a database, or to one document of it, from the time of the subscription
+/// on. Every change is the record of the operation in the ops log
+/// ```
+/// #[derive(Debug)]
+/// pub struct Subscription {
+///     changes: Receiver<LogRecord>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Subscription {
+    changes: Receiver<LogRecord>,
+}
+
+impl Subscription {
+    pub(crate) fn new(changes: Receiver<LogRecord>) -> Self {
+        Self { changes }
+    }
+    /// Wait for the next change, `None` once the subscriber fell more than `CHANGE_BUFFER` changes
+    /// behind and was dropped. Changes are never skipped without the subscription ending
+    pub async fn next(&self) -> Option<LogRecord> {
+        self.changes.recv().await.ok()
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    db: Utf8PathBuf,
+    // `None` for every document of the database and the database itself
+    document: Option<Utf8PathBuf>,
+    sender: Sender<LogRecord>,
+}
+
+impl Subscriber {
+    fn follows(&self, op: &LogOp) -> bool {
+        if op.db() != self.db.as_path() {
+            return false;
+        }
+
+        match (&self.document, op.documents()) {
+            (None, _) | (Some(_), None) => true,
+            (Some(document), Some(documents)) => documents.contains(&document.as_path()),
+        }
+    }
+}
+
+/// The subscribers to the changes of the databases of a repo
+#[derive(Debug, Default)]
+pub(crate) struct ChangeFeed {
+    subscribers: Mutex<Vec<Subscriber>>,
+}
+
+impl ChangeFeed {
+    /// Send the changes to a database, or to `document` of it, through `sender`.
+    /// Several subscriptions may share a sender, all of them end once one falls behind
+    pub(crate) async fn subscribe(
+        &self,
+        db: &Utf8Path,
+        document: Option<&Utf8Path>,
+        sender: Sender<LogRecord>,
+    ) {
+        self.subscribers.lock().await.push(Subscriber {
+            db: db.to_path_buf(),
+            document: document.map(Utf8Path::to_path_buf),
+            sender,
+        });
+    }
+    /// Send a record that was just applied to the subscribers following it
+    pub(crate) async fn publish(&self, record: &LogRecord) {
+        let mut subscribers = self.subscribers.lock().await;
+
+        subscribers.retain(|subscriber| {
+            if !subscriber.follows(record.op()) {
+                return !subscriber.sender.is_closed();
+            }
+
+            match subscriber.sender.try_send(record.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    subscriber.sender.close();
+
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            }
+        });
+    }
+}
@@ -0,0 +1,1001 @@
+#[cfg(feature = "tls")]
+use crate::ClientTls;
+use crate::{
+    deadline, wire_compression, wire_encoding, BackupManifest, ClientHello, Compression, Conflict,
+    Crdt, CrdtOp, DbStats, FieldData, Frame, LogRecord, MerkleTree, PayloadEncoding, Query,
+    ReplicaSnapshot, ServerHello, ServerInfo, TDBCell, Topology, TuringCommand, TuringDbError,
+    TuringResponse, TuringResult, Version, WireError, WireMessage, WriteOp, DEFAULT_MAX_FRAME_LEN,
+};
+use async_io::Async;
+use camino::Utf8PathBuf;
+use futures_lite::{
+    future,
+    io::{self, AsyncRead, AsyncWrite},
+};
+use std::{
+    fmt::Debug,
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+
+/// How long a request may take by default before it fails with `TimedOut`
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a connection may sit idle by default before it is pinged ahead of its next request
+const DEFAULT_KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A connection to a `TuringServer` speaking the frame protocol for its user.
+///
+/// Every connection starts with a handshake, a server speaking another version of the protocol
+/// fails the connection with `TuringDbError::Server` holding the `Unsupported` error code.
+/// A connection that fails is dropped and the next request opens a new one. Requests that
+/// only read are sent again once on a new connection, requests that write are not since
+/// the server may have applied them before the connection failed. A connection left idle
+/// for the keep-alive interval is pinged before it is used again, so a connection the server
+/// or the network dropped in the meantime is replaced before a write is lost on it
+/// ```
+/// #[derive(Debug)]
+/// pub struct TuringClient {
+///     address: SocketAddr,
+///     stream: Option<Box<dyn Connection>>,
+///     timeout: Duration,
+///     max_frame_len: u32,
+///     features: Vec<String>,
+///     next_request_id: u64,
+///     keep_alive_interval: Duration,
+///     last_used: Instant,
+///     admin_token: Option<String>,
+///     encoding: PayloadEncoding,
+///     max_staleness: Option<Duration>,
+///     #[cfg(feature = "tls")]
+///     tls: Option<ClientTls>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct TuringClient {
+    address: SocketAddr,
+    stream: Option<Box<dyn Connection>>,
+    timeout: Duration,
+    max_frame_len: u32,
+    // Agreed on by the last handshake
+    features: Vec<String>,
+    // Request ids start at 1, the server answers a frame it could not read with 0
+    next_request_id: u64,
+    keep_alive_interval: Duration,
+    // When a response was last read from the connection
+    last_used: Instant,
+    // Sent again on every new connection once the server accepted it
+    admin_token: Option<String>,
+    // Asked for in the hello, the connection falls back to bincode when the server does not agree
+    encoding: PayloadEncoding,
+    // Reads are sent in `TuringCommand::Bounded` when set
+    max_staleness: Option<Duration>,
+    // Every connection is wrapped in TLS when set
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTls>,
+}
+
+/// A connection to the server, over TLS or not
+trait Connection: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send + Sync + Debug> Connection for T {}
+
+impl TuringClient {
+    /// Connect to the server listening on `address`
+    pub async fn connect(address: SocketAddr) -> TuringResult<TuringClient> {
+        let mut client = TuringClient {
+            address,
+            stream: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            features: Vec::new(),
+            next_request_id: 1,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            last_used: Instant::now(),
+            admin_token: None,
+            encoding: PayloadEncoding::Bincode,
+            max_staleness: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+        };
+        client.stream = Some(client.open().await?);
+
+        Ok(client)
+    }
+    /// Connect to the server listening on `address` over TLS, checking its certificate as `tls` sets
+    #[cfg(feature = "tls")]
+    pub async fn connect_tls(address: SocketAddr, tls: ClientTls) -> TuringResult<TuringClient> {
+        let mut client = TuringClient {
+            address,
+            stream: None,
+            timeout: DEFAULT_REQUEST_TIMEOUT,
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            features: Vec::new(),
+            next_request_id: 1,
+            keep_alive_interval: DEFAULT_KEEP_ALIVE_INTERVAL,
+            last_used: Instant::now(),
+            admin_token: None,
+            encoding: PayloadEncoding::Bincode,
+            max_staleness: None,
+            tls: Some(tls),
+        };
+        client.stream = Some(client.open().await?);
+
+        Ok(client)
+    }
+    /// How long connecting, sending a request and reading its response may take together
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+    /// The largest response accepted from the server
+    pub fn set_max_frame_len(mut self, max_frame_len: u32) -> Self {
+        self.max_frame_len = max_frame_len;
+
+        self
+    }
+    /// How long a connection may sit idle before it is pinged ahead of its next request,
+    /// shorter than the idle timeout of the server to keep the connection open
+    pub fn set_keep_alive_interval(mut self, keep_alive_interval: Duration) -> Self {
+        self.keep_alive_interval = keep_alive_interval;
+
+        self
+    }
+    /// How commands and responses are encoded, the open connection is closed so the next
+    /// request asks the server for the encoding in a new handshake
+    pub fn set_payload_encoding(mut self, encoding: PayloadEncoding) -> Self {
+        self.encoding = encoding;
+        self.stream = None;
+
+        self
+    }
+
+    /// Only read from a server whose data lags behind its primary by at most `max_staleness`,
+    /// a replica lagging further fails the read with the `Unavailable` error code so it can be
+    /// sent to the primary or again later. Writes and admin commands are sent as they are
+    pub fn set_max_staleness(mut self, max_staleness: Duration) -> Self {
+        self.max_staleness = Some(max_staleness);
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    pub fn get_max_frame_len(&self) -> u32 {
+        self.max_frame_len
+    }
+
+    pub fn get_keep_alive_interval(&self) -> Duration {
+        self.keep_alive_interval
+    }
+
+    pub fn get_payload_encoding(&self) -> PayloadEncoding {
+        self.encoding
+    }
+
+    pub fn get_max_staleness(&self) -> Option<Duration> {
+        self.max_staleness
+    }
+    /// The optional features of the protocol both the client and the server speak
+    pub fn get_features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Check the server still answers, returning how long the round trip took.
+    /// Pinging an idle connection keeps the server from closing it
+    pub async fn ping(&mut self) -> TuringResult<Duration> {
+        let started = Instant::now();
+
+        match self.request(TuringCommand::Ping).await? {
+            TuringResponse::Pong => Ok(started.elapsed()),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn db_create(&mut self, db: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DbCreate { db: db.into() })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn db_drop(&mut self, db: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DbDrop { db: db.into() })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// The databases of the repo sorted by name
+    pub async fn db_list(&mut self) -> TuringResult<Vec<Utf8PathBuf>> {
+        match self.request(TuringCommand::DbList).await? {
+            TuringResponse::Names(names) => Ok(names),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn document_create(&mut self, db: &str, document: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DocumentCreate {
+                db: db.into(),
+                document: document.into(),
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn document_drop(&mut self, db: &str, document: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DocumentDrop {
+                db: db.into(),
+                document: document.into(),
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// The documents of a database sorted by name
+    pub async fn document_list(&mut self, db: &str) -> TuringResult<Vec<Utf8PathBuf>> {
+        match self
+            .request(TuringCommand::DocumentList { db: db.into() })
+            .await?
+        {
+            TuringResponse::Names(names) => Ok(names),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn field_insert(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+        value: TDBCell,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::FieldInsert {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+                value,
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn field_get(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+    ) -> TuringResult<FieldData> {
+        match self
+            .request(TuringCommand::FieldGet {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+            })
+            .await?
+        {
+            TuringResponse::Field(field_data) => Ok(field_data),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn field_modify(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+        value: TDBCell,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::FieldModify {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+                value,
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn field_remove(&mut self, db: &str, document: &str, key: &[u8]) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::FieldRemove {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// The documents matching the query along with the continuation token of a full page
+    pub async fn select(
+        &mut self,
+        query: &Query,
+    ) -> TuringResult<(
+        Vec<(Utf8PathBuf, Vec<(Vec<u8>, FieldData)>)>,
+        Option<String>,
+    )> {
+        match self.request(TuringCommand::Query(query.clone())).await? {
+            TuringResponse::Documents {
+                documents,
+                continuation,
+            } => Ok((documents, continuation)),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// Run a TuringQL statement, the response depends on the kind of statement
+    pub async fn execute(&mut self, statement: &str) -> TuringResult<TuringResponse> {
+        self.request(TuringCommand::Execute(statement.into())).await
+    }
+    /// The fields of a document along with the revision they are at, to be passed
+    /// to `document_update_if`
+    pub async fn document_get(
+        &mut self,
+        db: &str,
+        document: &str,
+    ) -> TuringResult<(u64, Vec<(Vec<u8>, FieldData)>)> {
+        match self
+            .request(TuringCommand::DocumentGet {
+                db: db.into(),
+                document: document.into(),
+            })
+            .await?
+        {
+            TuringResponse::Document { revision, fields } => Ok((revision, fields)),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// Replace every field of a document only if no other write reached it since it was read
+    /// at `expected_revision`, returning the revision the write left. A lost race fails with
+    /// `TuringDbError::Server` holding the `Conflict` error code, the document is read again
+    /// before the update is retried
+    pub async fn document_update_if(
+        &mut self,
+        db: &str,
+        document: &str,
+        expected_revision: u64,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    ) -> TuringResult<u64> {
+        match self
+            .request(TuringCommand::DocumentUpdateIf {
+                db: db.into(),
+                document: document.into(),
+                expected_revision,
+                fields,
+            })
+            .await?
+        {
+            TuringResponse::Revision { revision } => Ok(revision),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The version a field holds on a writer of a multi-writer cluster along with the versions
+    /// of it that lost to a concurrent write, oldest first
+    pub async fn field_conflicts(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+    ) -> TuringResult<(Option<Version>, Vec<Conflict>)> {
+        match self
+            .request(TuringCommand::FieldConflicts {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+            })
+            .await?
+        {
+            TuringResponse::Conflicts { version, conflicts } => Ok((version, conflicts)),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// Write the value picked for a field holding conflicts, `None` removes the field.
+    /// The write settles the conflicts older than itself on every writer of the cluster
+    pub async fn field_resolve(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+        value: Option<TDBCell>,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::FieldResolve {
+                db: db.into(),
+                document: document.into(),
+                key: key.to_vec(),
+                value,
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Change a CRDT field, returning the contents of the field after the change
+    pub async fn crdt_update(
+        &mut self,
+        db: &str,
+        document: &str,
+        key: &[u8],
+        op: CrdtOp,
+    ) -> TuringResult<Crdt> {
+        match self
+            .request(TuringCommand::CrdtUpdate {
+                db: db.into(),
+                document: document.into(),
+                key: key.into(),
+                op,
+            })
+            .await?
+        {
+            TuringResponse::Crdt(state) => Ok(state),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The contents of a CRDT field, fails with `CrdtMismatch` for a field that holds no CRDT
+    pub async fn crdt_get(&mut self, db: &str, document: &str, key: &[u8]) -> TuringResult<Crdt> {
+        match self
+            .request(TuringCommand::CrdtGet {
+                db: db.into(),
+                document: document.into(),
+                key: key.into(),
+            })
+            .await?
+        {
+            TuringResponse::Crdt(state) => Ok(state),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The nodes of the cluster the server gossips with, fails with `NotClustered`
+    /// on a server that does not take part in gossip
+    pub async fn topology(&mut self) -> TuringResult<Topology> {
+        match self.request(TuringCommand::Topology).await? {
+            TuringResponse::Topology(topology) => Ok(topology),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// Take an advisory lock on a document for `ttl`, returning the fencing token of the lease.
+    /// The lease lives on the connection, a connection replaced before it expires releases it
+    pub async fn document_lock(
+        &mut self,
+        db: &str,
+        document: &str,
+        ttl: Duration,
+    ) -> TuringResult<u64> {
+        match self
+            .request(TuringCommand::DocumentLock {
+                db: db.into(),
+                document: document.into(),
+                ttl_ms: ttl.as_millis() as u64,
+            })
+            .await?
+        {
+            TuringResponse::Lease { token } => Ok(token),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn document_unlock(
+        &mut self,
+        db: &str,
+        document: &str,
+        token: u64,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DocumentUnlock {
+                db: db.into(),
+                document: document.into(),
+                token,
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Begin a transaction on `db`, returning the id its writes are staged under. The transaction
+    /// lives on the connection, a connection replaced before the commit rolls it back and the commit
+    /// then fails with `TransactionNotFound`
+    pub async fn transaction_begin(&mut self, db: &str) -> TuringResult<u64> {
+        match self
+            .request(TuringCommand::TransactionBegin { db: db.into() })
+            .await?
+        {
+            TuringResponse::Transaction { transaction } => Ok(transaction),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    pub async fn transaction_write(&mut self, transaction: u64, op: WriteOp) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionWrite { transaction, op })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Stage a write to `db` in a transaction that began on another database,
+    /// the commit then applies the writes to every database or to none
+    pub async fn transaction_write_to(
+        &mut self,
+        transaction: u64,
+        db: &str,
+        op: WriteOp,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionWriteTo {
+                transaction,
+                db: db.into(),
+                op,
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Apply every write the transaction staged atomically
+    pub async fn transaction_commit(&mut self, transaction: u64) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionCommit { transaction })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn transaction_rollback(&mut self, transaction: u64) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionRollback { transaction })
+            .await?;
+
+        TuringClient::done(response)
+    }
+
+    pub async fn transaction_savepoint(
+        &mut self,
+        transaction: u64,
+        name: &str,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionSavepoint {
+                transaction,
+                name: name.into(),
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Drop the writes staged since the savepoint `name`, to retry a step of the transaction
+    pub async fn transaction_rollback_to(
+        &mut self,
+        transaction: u64,
+        name: &str,
+    ) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::TransactionRollbackTo {
+                transaction,
+                name: name.into(),
+            })
+            .await?;
+
+        TuringClient::done(response)
+    }
+    /// Make the connection an admin one, every connection the client opens afterwards
+    /// is authenticated with the same token. A wrong token fails with `PermissionDenied`
+    pub async fn authenticate(&mut self, token: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::Authenticate {
+                token: token.into(),
+            })
+            .await?;
+        TuringClient::done(response)?;
+        self.admin_token = Some(token.into());
+
+        Ok(())
+    }
+    /// The stats of every database sorted by name, an admin command
+    pub async fn repo_stats(&mut self) -> TuringResult<Vec<(Utf8PathBuf, DbStats)>> {
+        match self.request(TuringCommand::RepoStats).await? {
+            TuringResponse::RepoStats(stats) => Ok(stats),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// An admin command
+    pub async fn db_stats(&mut self, db: &str) -> TuringResult<DbStats> {
+        match self
+            .request(TuringCommand::DbStats { db: db.into() })
+            .await?
+        {
+            TuringResponse::DbStats(stats) => Ok(stats),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// How many fields the document holds and the bytes of their keys and values, an admin command
+    pub async fn document_stats(&mut self, db: &str, document: &str) -> TuringResult<(usize, u64)> {
+        match self
+            .request(TuringCommand::DocumentStats {
+                db: db.into(),
+                document: document.into(),
+            })
+            .await?
+        {
+            TuringResponse::DocumentStats { fields, bytes } => Ok((fields, bytes)),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// An admin command
+    pub async fn commit(&mut self) -> TuringResult<()> {
+        let response = self.request(TuringCommand::Commit).await?;
+
+        TuringClient::done(response)
+    }
+    /// An admin command
+    pub async fn compact(&mut self) -> TuringResult<()> {
+        let response = self.request(TuringCommand::Compact).await?;
+
+        TuringClient::done(response)
+    }
+    /// Back the repo up into `target_dir` on the server, an admin command
+    pub async fn backup(&mut self, target_dir: &str) -> TuringResult<BackupManifest> {
+        match self
+            .request(TuringCommand::Backup {
+                target_dir: target_dir.into(),
+            })
+            .await?
+        {
+            TuringResponse::Backup(manifest) => Ok(manifest),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// An admin command
+    pub async fn server_info(&mut self) -> TuringResult<ServerInfo> {
+        match self.request(TuringCommand::ServerInfo).await? {
+            TuringResponse::ServerInfo(info) => Ok(info),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The databases and documents of the server to seed a replica with, an admin command
+    pub async fn replica_snapshot(&mut self) -> TuringResult<ReplicaSnapshot> {
+        match self.request(TuringCommand::ReplicaSnapshot).await? {
+            TuringResponse::ReplicaSnapshot(snapshot) => Ok(snapshot),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// At most `limit` records of the ops log of the server following `after`, an admin command
+    pub async fn log_read(
+        &mut self,
+        after: Option<u64>,
+        limit: usize,
+    ) -> TuringResult<Vec<LogRecord>> {
+        match self
+            .request(TuringCommand::LogRead {
+                after,
+                limit: limit as u32,
+            })
+            .await?
+        {
+            TuringResponse::Records(records) => Ok(records),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The Merkle tree over the documents of a database, an admin command
+    pub async fn merkle_tree(&mut self, db: &str) -> TuringResult<MerkleTree> {
+        match self
+            .request(TuringCommand::MerkleTree { db: db.into() })
+            .await?
+        {
+            TuringResponse::MerkleTree(tree) => Ok(tree),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The documents of a leaf of the Merkle tree of a database with their hashes, an admin command
+    pub async fn merkle_leaf(
+        &mut self,
+        db: &str,
+        leaf: usize,
+    ) -> TuringResult<Vec<(Utf8PathBuf, u64)>> {
+        match self
+            .request(TuringCommand::MerkleLeaf {
+                db: db.into(),
+                leaf: leaf as u32,
+            })
+            .await?
+        {
+            TuringResponse::DocumentHashes(hashes) => Ok(hashes),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// Make a replica stop following its primary and accept writes, an admin command
+    pub async fn replica_promote(&mut self) -> TuringResult<()> {
+        let response = self.request(TuringCommand::ReplicaPromote).await?;
+
+        TuringClient::done(response)
+    }
+    /// Send every command without waiting for the responses in between. A server that agreed on
+    /// `PIPELINING` runs them concurrently, any other one after the other. The responses are in
+    /// the order of the commands and each one may fail on its own, while a failed connection
+    /// fails the whole batch. No command is sent again since some of them may have been applied
+    pub async fn pipeline(
+        &mut self,
+        commands: &[TuringCommand],
+    ) -> TuringResult<Vec<TuringResult<TuringResponse>>> {
+        let mut stream = self.connection().await?;
+        let first_request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(commands.len() as u64);
+        let max_frame_len = self.max_frame_len;
+        let compression = self.compression();
+        let encoding = self.encoding();
+
+        let (_, responses) = deadline(self.timeout, async {
+            // Responses are read while commands are still sent, so neither side waits on the other
+            let (mut reader, mut writer) = io::split(&mut stream);
+
+            future::try_zip(
+                TuringClient::send_batch(
+                    &mut writer,
+                    commands,
+                    first_request_id,
+                    compression,
+                    encoding,
+                ),
+                TuringClient::read_batch(
+                    &mut reader,
+                    commands.len(),
+                    first_request_id,
+                    max_frame_len,
+                    compression,
+                    encoding,
+                ),
+            )
+            .await
+        })
+        .await?;
+        self.stream = Some(stream);
+        self.last_used = Instant::now();
+
+        Ok(responses)
+    }
+    /// Send a command and read its response, an error sent by the server is `TuringDbError::Server`
+    /// holding its `WireError` and a command the server throttled is `TuringDbError::Throttled`
+    pub(crate) async fn request(&mut self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        let command = match self.max_staleness {
+            Some(max_staleness) if command.is_read() => TuringCommand::Bounded {
+                max_staleness_ms: max_staleness.as_millis() as u64,
+                command: Box::new(command),
+            },
+            _ => command,
+        };
+
+        let response = match self.round_trip(&command).await {
+            Err(error) if error != TuringDbError::TimedOut && TuringClient::reads(&command) => {
+                self.round_trip(&command).await?
+            }
+            outcome => outcome?,
+        };
+
+        TuringClient::outcome(response)
+    }
+    // The connection is only kept once a whole response was read from it,
+    // after a failure it may be left in the middle of a frame
+    async fn round_trip(&mut self, command: &TuringCommand) -> TuringResult<TuringResponse> {
+        let mut stream = self.connection().await?;
+        let response = self.exchange(&mut stream, command).await?;
+        self.stream = Some(stream);
+        self.last_used = Instant::now();
+
+        Ok(response)
+    }
+    /// The open connection, pinged first when it sat idle for the keep-alive interval.
+    /// One that does not answer is replaced by a new connection
+    async fn connection(&mut self) -> TuringResult<Box<dyn Connection>> {
+        match self.stream.take() {
+            Some(stream) if self.last_used.elapsed() < self.keep_alive_interval => Ok(stream),
+            Some(mut stream) => match self.exchange(&mut stream, &TuringCommand::Ping).await {
+                Ok(TuringResponse::Pong) => Ok(stream),
+                _ => self.open().await,
+            },
+            None => self.open().await,
+        }
+    }
+
+    // The command is encoded for the connection it is sent on, a new one may have agreed
+    // on another encoding than the one it replaced
+    async fn exchange(
+        &mut self,
+        stream: &mut Box<dyn Connection>,
+        command: &TuringCommand,
+    ) -> TuringResult<TuringResponse> {
+        let max_frame_len = self.max_frame_len;
+        let compression = self.compression();
+        let encoding = self.encoding();
+        let request_id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+
+        deadline(self.timeout, async {
+            Frame::encode_with(command, encoding)?
+                .set_request_id(request_id)
+                .compress(compression)?
+                .write(stream)
+                .await?;
+
+            match Frame::read(stream, max_frame_len).await? {
+                None => Err(TuringDbError::ConnectionReset),
+                Some(frame) if frame.request_id() == request_id || frame.request_id() == 0 => frame
+                    .decompress(compression, max_frame_len)?
+                    .decode_with::<TuringResponse>(encoding),
+                Some(_) => Err(TuringDbError::InvalidData),
+            }
+        })
+        .await
+    }
+    /// Connect and shake hands with the server, keeping the features it agreed on,
+    /// then authenticate the connection when the client was authenticated before
+    async fn open(&mut self) -> TuringResult<Box<dyn Connection>> {
+        let (mut stream, hello) = deadline(self.timeout, async {
+            let stream = Async::<TcpStream>::connect(self.address).await?;
+
+            #[cfg(feature = "tls")]
+            let mut stream = match &self.tls {
+                Some(tls) => Box::new(
+                    tls.connector()
+                        .await?
+                        .connect(tls.dns_name()?, stream)
+                        .await?,
+                ) as Box<dyn Connection>,
+                None => Box::new(stream) as Box<dyn Connection>,
+            };
+            #[cfg(not(feature = "tls"))]
+            let mut stream = Box::new(stream) as Box<dyn Connection>;
+
+            let hello = self.handshake(&mut stream).await?;
+
+            Ok((stream, hello))
+        })
+        .await?;
+        self.features = hello.get_features().to_vec();
+
+        if let Some(token) = self.admin_token.clone() {
+            let authenticate = TuringCommand::Authenticate { token };
+            let response = self.exchange(&mut stream, &authenticate).await?;
+            TuringClient::done(TuringClient::outcome(response)?)?;
+        }
+
+        Ok(stream)
+    }
+    /// A server that refuses the client answers the hello with an error
+    async fn handshake(&self, stream: &mut Box<dyn Connection>) -> TuringResult<ServerHello> {
+        Frame::encode(&ClientHello::new(self.encoding))?
+            .write(stream)
+            .await?;
+
+        let frame = match Frame::read(stream, self.max_frame_len).await? {
+            None => return Err(TuringDbError::ConnectionReset),
+            Some(frame) => frame,
+        };
+        if frame.opcode()
+            == TuringResponse::Error(WireError::from(&TuringDbError::InvalidData)).opcode()
+        {
+            return match frame.decode::<TuringResponse>()? {
+                TuringResponse::Error(error) => Err(TuringDbError::Server(error)),
+                _ => Err(TuringDbError::InvalidData),
+            };
+        }
+
+        let hello = frame.decode::<ServerHello>()?;
+        // No credentials can be sent yet
+        if hello.get_auth_required() {
+            return Err(TuringDbError::PermissionDenied);
+        }
+
+        Ok(hello)
+    }
+
+    async fn send_batch<W: AsyncWrite + Unpin>(
+        writer: &mut W,
+        commands: &[TuringCommand],
+        first_request_id: u64,
+        compression: Option<Compression>,
+        encoding: PayloadEncoding,
+    ) -> TuringResult<()> {
+        for (index, command) in commands.iter().enumerate() {
+            Frame::encode_with(command, encoding)?
+                .set_request_id(first_request_id.wrapping_add(index as u64))
+                .compress(compression)?
+                .write(writer)
+                .await?;
+        }
+
+        Ok(())
+    }
+    /// Put every response in the place of its command, whatever order they arrive in
+    async fn read_batch<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        len: usize,
+        first_request_id: u64,
+        max_frame_len: u32,
+        compression: Option<Compression>,
+        encoding: PayloadEncoding,
+    ) -> TuringResult<Vec<TuringResult<TuringResponse>>> {
+        let mut responses: Vec<Option<TuringResult<TuringResponse>>> =
+            (0..len).map(|_| None).collect();
+
+        for _ in 0..len {
+            let frame = match Frame::read(reader, max_frame_len).await? {
+                None => return Err(TuringDbError::ConnectionReset),
+                Some(frame) => frame.decompress(compression, max_frame_len)?,
+            };
+            let response = TuringClient::outcome(frame.decode_with::<TuringResponse>(encoding)?);
+
+            // The server could not read a frame and closes the connection
+            if frame.request_id() == 0 {
+                return Err(response.err().unwrap_or(TuringDbError::InvalidData));
+            }
+
+            let index = frame.request_id().wrapping_sub(first_request_id) as usize;
+            match responses.get_mut(index) {
+                Some(slot) if slot.is_none() => *slot = Some(response),
+                // A response to no command of the batch or a second one to the same command
+                _ => return Err(TuringDbError::InvalidData),
+            }
+        }
+
+        Ok(responses.into_iter().flatten().collect())
+    }
+
+    fn outcome(response: TuringResponse) -> TuringResult<TuringResponse> {
+        match response {
+            TuringResponse::Error(error) => Err(TuringDbError::Server(error)),
+            TuringResponse::Throttled { retry_after_ms } => {
+                Err(TuringDbError::Throttled { retry_after_ms })
+            }
+            response => Ok(response),
+        }
+    }
+
+    fn compression(&self) -> Option<Compression> {
+        wire_compression(&self.features)
+    }
+    /// The encoding the last handshake agreed on, not the one asked for
+    fn encoding(&self) -> PayloadEncoding {
+        wire_encoding(&self.features)
+    }
+
+    fn reads(command: &TuringCommand) -> bool {
+        matches!(
+            command,
+            TuringCommand::FieldGet { .. }
+                | TuringCommand::DocumentGet { .. }
+                | TuringCommand::FieldConflicts { .. }
+                | TuringCommand::CrdtGet { .. }
+                | TuringCommand::Topology
+                | TuringCommand::Bounded { .. }
+                | TuringCommand::Query(_)
+                | TuringCommand::DbList
+                | TuringCommand::DocumentList { .. }
+                | TuringCommand::Ping
+                | TuringCommand::Authenticate { .. }
+                | TuringCommand::RepoStats
+                | TuringCommand::DbStats { .. }
+                | TuringCommand::DocumentStats { .. }
+                | TuringCommand::ServerInfo
+                | TuringCommand::ReplicaSnapshot
+                | TuringCommand::LogRead { .. }
+                | TuringCommand::MerkleTree { .. }
+                | TuringCommand::MerkleLeaf { .. }
+        )
+    }
+
+    fn done(response: TuringResponse) -> TuringResult<()> {
+        match response {
+            TuringResponse::Done => Ok(()),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+}
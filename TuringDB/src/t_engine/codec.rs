@@ -0,0 +1,79 @@
+use crate::{TuringDbError, TuringResult};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Encodes and decodes the metadata that the engine persists about a repo
+pub trait MetaCodec {
+    /// Written before the encoded bytes so that a file can still be decoded after the configured codec changes
+    const TAG: u8;
+    /// Encode a value into bytes
+    fn encode<T: Serialize>(value: &T) -> TuringResult<Vec<u8>>;
+    /// Decode a value from bytes
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> TuringResult<T>;
+}
+
+/// Compact binary encoding using `bincode`
+#[derive(Debug, Clone, Copy)]
+pub struct BincodeCodec;
+
+impl MetaCodec for BincodeCodec {
+    const TAG: u8 = 0x00;
+
+    fn encode<T: Serialize>(value: &T) -> TuringResult<Vec<u8>> {
+        Ok(bincode::serialize::<T>(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> TuringResult<T> {
+        Ok(bincode::deserialize::<T>(bytes)?)
+    }
+}
+
+/// Human readable encoding using `ron`, useful when debugging a repo
+#[derive(Debug, Clone, Copy)]
+pub struct RonCodec;
+
+impl MetaCodec for RonCodec {
+    const TAG: u8 = 0x01;
+
+    fn encode<T: Serialize>(value: &T) -> TuringResult<Vec<u8>> {
+        match ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()) {
+            Ok(encoded) => Ok(encoded.into_bytes()),
+            Err(error) => Err(TuringDbError::Serialization(error.to_string())),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(bytes: &[u8]) -> TuringResult<T> {
+        match ron::de::from_bytes::<T>(bytes) {
+            Ok(value) => Ok(value),
+            Err(error) => Err(TuringDbError::Serialization(error.to_string())),
+        }
+    }
+}
+
+/// The codec used to persist metadata, selected using `TuringConfig`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum MetaEncoding {
+    #[default]
+    Bincode,
+    Ron,
+}
+
+impl MetaEncoding {
+    /// Encode a value prefixed with the tag of the codec
+    pub fn encode<T: Serialize>(&self, value: &T) -> TuringResult<Vec<u8>> {
+        let (tag, mut encoded) = match self {
+            MetaEncoding::Bincode => (BincodeCodec::TAG, BincodeCodec::encode(value)?),
+            MetaEncoding::Ron => (RonCodec::TAG, RonCodec::encode(value)?),
+        };
+        encoded.insert(0, tag);
+
+        Ok(encoded)
+    }
+    /// Decode a value using the codec named by its tag
+    pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> TuringResult<T> {
+        match bytes.split_first() {
+            Some((tag, encoded)) if *tag == BincodeCodec::TAG => BincodeCodec::decode(encoded),
+            Some((tag, encoded)) if *tag == RonCodec::TAG => RonCodec::decode(encoded),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use crate::{
+    Compression, Document, MetaEncoding, MetaFile, Quarantine, SnapshotDocument, TuringDbError,
+    TuringResult, STREAM_TREE,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use tai64::TAI64N;
+
+/// The cold tier of a partition lives next to its documents, reserved so it is never loaded as a document
+pub(crate) const COLD_DIR: &str = ".cold";
+pub(crate) const COLD_EXTENSION: &str = "cold";
+/// A document being moved into the cold tier, removed once its archive is in place
+pub(crate) const ARCHIVING_EXTENSION: &str = "archiving";
+/// A document being restored from the cold tier, moved into place once it is complete
+pub(crate) const REHYDRATING_EXTENSION: &str = "rehydrating";
+/// Archives are read rarely so they favour size over speed
+const COLD_COMPRESSION: Compression = Compression::Zstd { level: 9 };
+
+/// A document moved into the cold tier, holding the compressed contents of every tree in it
+/// ```
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct ColdDocument {
+///     archived: TAI64N,
+///     last_written: TAI64N,
+///     entries: u64,
+///     contents: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ColdDocument {
+    archived: TAI64N,
+    last_written: TAI64N,
+    // The fields and streams the document held, so it can be counted without rehydrating it
+    entries: u64,
+    contents: Vec<u8>,
+}
+
+impl ColdDocument {
+    /// Compress the contents of a document last written at `last_written`
+    pub(crate) fn capture(document: &Document, last_written: TAI64N) -> TuringResult<Self> {
+        let entries = (document.len() + document.open_tree(STREAM_TREE)?.len()) as u64;
+        let snapshot = SnapshotDocument::capture(document)?;
+
+        Ok(Self {
+            archived: TAI64N::now(),
+            last_written,
+            entries,
+            contents: COLD_COMPRESSION.compress(&bincode::serialize(&snapshot)?)?,
+        })
+    }
+    /// Decompress the contents of the document
+    pub(crate) fn snapshot(&self) -> TuringResult<SnapshotDocument> {
+        Ok(bincode::deserialize::<SnapshotDocument>(
+            &Compression::decompress(&self.contents)?,
+        )?)
+    }
+    /// The time the document was archived
+    pub(crate) fn archived(&self) -> TAI64N {
+        self.archived
+    }
+    /// The time the document was last written before it was archived
+    pub(crate) fn last_written(&self) -> TAI64N {
+        self.last_written
+    }
+
+    pub(crate) fn entries(&self) -> u64 {
+        self.entries
+    }
+    /// The archive as it is written to disk, so it can be stored elsewhere and written back as is
+    pub(crate) fn seal(&self) -> TuringResult<Vec<u8>> {
+        Ok(MetaFile::seal(&MetaEncoding::Bincode.encode(self)?))
+    }
+
+    pub(crate) async fn load(
+        document_path: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<ColdDocument> {
+        match MetaFile::read::<ColdDocument>(&ColdDocument::path(document_path), quarantine).await?
+        {
+            Some(cold_document) => Ok(cold_document),
+            None => Err(TuringDbError::DocumentCorrupted { at: None, bt: () }),
+        }
+    }
+
+    pub(crate) async fn persist(&self, document_path: &Utf8Path) -> TuringResult<()> {
+        let path = ColdDocument::path(document_path);
+
+        if let Some(parent) = path.parent() {
+            async_fs::DirBuilder::new()
+                .recursive(true)
+                .create(parent)
+                .await?;
+        }
+
+        MetaFile::write(&path, &MetaEncoding::Bincode.encode(self)?).await
+    }
+    /// Remove the archive of a document along with its previous good copy
+    pub(crate) async fn remove(document_path: &Utf8Path) -> TuringResult<()> {
+        let path = ColdDocument::path(document_path);
+
+        for candidate in [path.clone(), Utf8PathBuf::from(format!("{}.bak", path))].iter() {
+            if let Err(error) = async_fs::remove_file(candidate).await {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+    /// The archive of the document at `document_path`
+    pub(crate) fn path(document_path: &Utf8Path) -> Utf8PathBuf {
+        ColdDocument::sibling(document_path, COLD_EXTENSION)
+    }
+    /// Where a document is staged while it moves between tiers
+    pub(crate) fn staging_path(document_path: &Utf8Path, extension: &str) -> Utf8PathBuf {
+        ColdDocument::sibling(document_path, extension)
+    }
+
+    fn sibling(document_path: &Utf8Path, extension: &str) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = match document_path.parent() {
+            Some(parent) => parent.into(),
+            None => Utf8PathBuf::new(),
+        };
+        path.push(COLD_DIR);
+        path.push(format!(
+            "{}.{}",
+            document_path.file_name().unwrap_or_default(),
+            extension
+        ));
+
+        path
+    }
+}
+
+/// The contents of a document held for copying, a document in the cold tier is copied
+/// from its archive rather than rehydrated
+#[derive(Debug)]
+pub(crate) enum DocumentContents {
+    Hot(Document),
+    Cold(ColdDocument),
+}
+
+impl DocumentContents {
+    pub(crate) fn capture(&self) -> TuringResult<SnapshotDocument> {
+        match self {
+            DocumentContents::Hot(document) => SnapshotDocument::capture(document),
+            DocumentContents::Cold(cold_document) => cold_document.snapshot(),
+        }
+    }
+}
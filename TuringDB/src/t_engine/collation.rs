@@ -0,0 +1,253 @@
+use crate::{MetaEncoding, MetaFile, Quarantine, TuringDbError, TuringResult, Value};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+const COLLATION_META_NAME: &str = "COLLATION.meta";
+
+/// How text is ordered when it is sorted or compared by a range filter.
+/// Equality is never affected, text is only equal to the same text
+/// ```
+/// #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Collation {
+///     #[default]
+///     Binary,
+///     Locale(Locale),
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Collation {
+    /// By the bytes of the text, so `B` sorts before `a` and `é` after `z`
+    #[default]
+    Binary,
+    /// By the letters of the text first, ignoring accents and case, then by accents, then by case.
+    /// `apple`, `Äpfel` and `Banana` sort the way a reader of the language expects
+    Locale(Locale),
+}
+
+/// The languages whose alphabets order some letters differently from the root order
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Locale {
+///     Root,
+///     Danish,
+///     Spanish,
+///     Swedish,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Locale {
+    /// Accented letters sort with the letter they are based on, which suits most languages
+    Root,
+    /// `æ`, `ø` and `å` are letters of their own after `z`, also for Norwegian
+    Danish,
+    /// `ñ` is a letter of its own after `n`
+    Spanish,
+    /// `å`, `ä` and `ö` are letters of their own after `z`, also for Finnish
+    Swedish,
+}
+
+impl Collation {
+    /// The collation of a language tag such as `sv` or `es-MX`, `binary` orders text by its bytes.
+    /// Languages without letters of their own sort by the root order
+    pub fn from_tag(tag: &str) -> TuringResult<Collation> {
+        let language = tag.split(['-', '_']).next();
+
+        let language = match language {
+            Some(language) if language.eq_ignore_ascii_case("binary") => {
+                return Ok(Collation::Binary)
+            }
+            Some(language)
+                if (2..=3).contains(&language.len())
+                    && language.chars().all(|letter| letter.is_ascii_alphabetic()) =>
+            {
+                language.to_ascii_lowercase()
+            }
+            Some("root") => return Ok(Collation::Locale(Locale::Root)),
+            _ => return Err(TuringDbError::InvalidInput),
+        };
+
+        let locale = match language.as_str() {
+            "da" | "nb" | "nn" | "no" => Locale::Danish,
+            "es" => Locale::Spanish,
+            "sv" | "fi" => Locale::Swedish,
+            _ => Locale::Root,
+        };
+
+        Ok(Collation::Locale(locale))
+    }
+    /// Compare two values, text by the collation and every other value as `Value::compare` does
+    pub(crate) fn compare(&self, left: &Value, right: &Value) -> Option<Ordering> {
+        match (self, left, right) {
+            (Collation::Locale(locale), Value::Text(left), Value::Text(right)) => {
+                Some(locale.compare(left, right))
+            }
+            _ => left.compare(right),
+        }
+    }
+    /// Order two values, text by the collation and every other value as `Value` is ordered
+    pub(crate) fn order(&self, left: &Value, right: &Value) -> Ordering {
+        match (self, left, right) {
+            (Collation::Locale(locale), Value::Text(left), Value::Text(right)) => {
+                locale.compare(left, right)
+            }
+            _ => left.cmp(right),
+        }
+    }
+
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Collation> {
+        Ok(
+            MetaFile::read::<Collation>(&Collation::path(db_dir), quarantine)
+                .await?
+                .unwrap_or_default(),
+        )
+    }
+
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(
+            &Collation::path(db_dir),
+            &MetaEncoding::Bincode.encode(self)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(COLLATION_META_NAME);
+
+        path
+    }
+}
+
+/// The weights a character is compared by at each level
+#[derive(Debug, Clone, Copy)]
+struct Weights {
+    letter: u32,
+    accent: u32,
+    upper: bool,
+}
+
+impl Locale {
+    /// Compare by letters, then accents, then case and lastly by the bytes so that
+    /// only the same text compares as equal
+    fn compare(&self, left: &str, right: &str) -> Ordering {
+        let left_weights = self.weights(left);
+        let right_weights = self.weights(right);
+
+        let letters = left_weights
+            .iter()
+            .map(|weights| weights.letter)
+            .cmp(right_weights.iter().map(|weights| weights.letter));
+        let accents = || {
+            left_weights
+                .iter()
+                .map(|weights| weights.accent)
+                .cmp(right_weights.iter().map(|weights| weights.accent))
+        };
+        // Lowercase sorts before uppercase
+        let case = || {
+            left_weights
+                .iter()
+                .map(|weights| weights.upper)
+                .cmp(right_weights.iter().map(|weights| weights.upper))
+        };
+
+        letters
+            .then_with(accents)
+            .then_with(case)
+            .then_with(|| left.cmp(right))
+    }
+
+    fn weights(&self, text: &str) -> Vec<Weights> {
+        let mut weights = Vec::with_capacity(text.len());
+
+        for character in text.chars() {
+            let upper = character.is_uppercase();
+
+            for lower in character.to_lowercase() {
+                if let Some(letter) = self.tailored(lower) {
+                    weights.push(Weights {
+                        letter,
+                        accent: 0,
+                        upper,
+                    });
+                    continue;
+                }
+
+                match Locale::base(lower) {
+                    None => weights.push(Weights {
+                        letter: Locale::letter(lower),
+                        accent: 0,
+                        upper,
+                    }),
+                    Some(base) => {
+                        for letter in base.chars() {
+                            weights.push(Weights {
+                                letter: Locale::letter(letter),
+                                accent: lower as u32,
+                                upper,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        weights
+    }
+    /// Characters keep their order among themselves, leaving room after each of them for up to
+    /// three letters a language adds to its alphabet
+    fn letter(character: char) -> u32 {
+        character as u32 * 4
+    }
+    /// The weight of a letter a language sorts as a letter of its own
+    fn tailored(&self, character: char) -> Option<u32> {
+        let after = |letter: char, position: u32| Some(Locale::letter(letter) + 1 + position);
+
+        match (self, character) {
+            (Locale::Danish, 'æ') | (Locale::Danish, 'ä') => after('z', 0),
+            (Locale::Danish, 'ø') | (Locale::Danish, 'ö') => after('z', 1),
+            (Locale::Danish, 'å') => after('z', 2),
+            (Locale::Spanish, 'ñ') => after('n', 0),
+            (Locale::Swedish, 'å') => after('z', 0),
+            (Locale::Swedish, 'ä') | (Locale::Swedish, 'æ') => after('z', 1),
+            (Locale::Swedish, 'ö') | (Locale::Swedish, 'ø') => after('z', 2),
+            _ => None,
+        }
+    }
+    /// The letters an accented or joined lowercase Latin letter sorts as
+    fn base(character: char) -> Option<&'static str> {
+        let base = match character {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'æ' => "ae",
+            'ç' | 'ć' | 'ĉ' | 'ċ' | 'č' => "c",
+            'ď' | 'đ' | 'ð' => "d",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ĕ' | 'ė' | 'ę' | 'ě' => "e",
+            'ĝ' | 'ğ' | 'ġ' | 'ģ' => "g",
+            'ĥ' | 'ħ' => "h",
+            'ì' | 'í' | 'î' | 'ï' | 'ĩ' | 'ī' | 'ĭ' | 'į' | 'ı' => "i",
+            'ĳ' => "ij",
+            'ĵ' => "j",
+            'ķ' | 'ĸ' => "k",
+            'ĺ' | 'ļ' | 'ľ' | 'ŀ' | 'ł' => "l",
+            'ñ' | 'ń' | 'ņ' | 'ň' | 'ŉ' | 'ŋ' => "n",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ŏ' | 'ő' => "o",
+            'œ' => "oe",
+            'ŕ' | 'ŗ' | 'ř' => "r",
+            'ś' | 'ŝ' | 'ş' | 'š' | 'ſ' => "s",
+            'ß' => "ss",
+            'ţ' | 'ť' | 'ŧ' => "t",
+            'þ' => "th",
+            'ù' | 'ú' | 'û' | 'ü' | 'ũ' | 'ū' | 'ŭ' | 'ů' | 'ű' | 'ų' => "u",
+            'ŵ' => "w",
+            'ý' | 'ÿ' | 'ŷ' => "y",
+            'ź' | 'ż' | 'ž' => "z",
+            _ => return None,
+        };
+
+        Some(base)
+    }
+}
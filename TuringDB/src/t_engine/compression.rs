@@ -0,0 +1,106 @@
+use crate::{TuringDbError, TuringResult};
+use serde::{Deserialize, Serialize};
+use std::{convert::TryInto, io::Read};
+
+const TAG_NONE: u8 = 0x00;
+const TAG_LZ4: u8 = 0x01;
+const TAG_ZSTD: u8 = 0x02;
+
+/// How the values of fields in a database are compressed on disk.
+/// Every stored value starts with a tag naming the compression it was written with,
+/// so changing the compression of a database never makes existing values unreadable
+/// ```
+/// #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Compression {
+///     #[default]
+///     None,
+///     Lz4,
+///     Zstd { level: i32 },
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz4,
+    Zstd {
+        level: i32,
+    },
+}
+
+impl Compression {
+    /// Compress `bytes`, prefixing them with the tag of this compression
+    pub(crate) fn compress(&self, bytes: &[u8]) -> TuringResult<Vec<u8>> {
+        let mut compressed = Vec::with_capacity(bytes.len() + 1);
+
+        match self {
+            Compression::None => {
+                compressed.push(TAG_NONE);
+                compressed.extend_from_slice(bytes);
+            }
+            Compression::Lz4 => {
+                compressed.push(TAG_LZ4);
+                compressed.extend_from_slice(&lz4_flex::compress_prepend_size(bytes));
+            }
+            Compression::Zstd { level } => {
+                compressed.push(TAG_ZSTD);
+                compressed.extend_from_slice(&zstd::stream::encode_all(bytes, *level)?);
+            }
+        }
+
+        Ok(compressed)
+    }
+    /// Decompress `bytes` with the compression named by their tag
+    pub(crate) fn decompress(bytes: &[u8]) -> TuringResult<Vec<u8>> {
+        match bytes.split_first() {
+            Some((&TAG_NONE, stored)) => Ok(stored.to_vec()),
+            Some((&TAG_LZ4, stored)) => match lz4_flex::decompress_size_prepended(stored) {
+                Ok(decompressed) => Ok(decompressed),
+                Err(error) => Err(TuringDbError::Compression(error.to_string())),
+            },
+            Some((&TAG_ZSTD, stored)) => Ok(zstd::stream::decode_all(stored)?),
+            Some((tag, _)) => Err(TuringDbError::Compression(format!(
+                "Unknown compression tag `{:#04x}`",
+                tag
+            ))),
+            None => Err(TuringDbError::Compression("Empty value".into())),
+        }
+    }
+    /// Like `decompress` but never produces more than `max_len` bytes,
+    /// for bytes sent by a peer rather than read back from disk
+    pub(crate) fn decompress_within(bytes: &[u8], max_len: usize) -> TuringResult<Vec<u8>> {
+        let too_large = || {
+            TuringDbError::Compression(format!(
+                "Decompressed data is larger than {} bytes",
+                max_len
+            ))
+        };
+
+        match bytes.split_first() {
+            Some((&TAG_LZ4, stored)) => {
+                // The length of the decompressed data is prepended as a little endian `u32`
+                let len = match stored.get(..4).map(|len| len.try_into()) {
+                    Some(Ok(len)) => u32::from_le_bytes(len) as usize,
+                    _ => return Err(TuringDbError::Compression("Truncated value".into())),
+                };
+                if len > max_len {
+                    return Err(too_large());
+                }
+
+                Compression::decompress(bytes)
+            }
+            Some((&TAG_ZSTD, stored)) => {
+                let mut decompressed = Vec::new();
+                zstd::stream::read::Decoder::new(stored)?
+                    .take(max_len as u64 + 1)
+                    .read_to_end(&mut decompressed)?;
+                if decompressed.len() > max_len {
+                    return Err(too_large());
+                }
+
+                Ok(decompressed)
+            }
+            _ => Compression::decompress(bytes),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::MetaEncoding;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{collections::BTreeMap, time::Duration};
+
+/// How often the ops log is compacted by default
+const DEFAULT_COMPACTION_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// How often expired documents are removed by default
+const DEFAULT_REAP_INTERVAL: Duration = Duration::from_secs(60);
+/// How many revisions of every document are kept by default
+const DEFAULT_HISTORY_DEPTH: usize = 16;
+/// How often tombstones are vacuumed by default
+const DEFAULT_VACUUM_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// How often the integrity scrubber checks the repo by default
+const DEFAULT_SCRUB_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+/// How long the tombstone of a removed field is kept by default
+const DEFAULT_TOMBSTONE_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// How long a document is left unwritten before it is moved into the cold tier by default
+const DEFAULT_COLD_AFTER: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+/// How often the watchdog looks for abandoned transactions by default
+const DEFAULT_WATCHDOG_INTERVAL: Duration = Duration::from_secs(30);
+/// How long a transaction is left unused before the watchdog rolls it back by default
+const DEFAULT_TRANSACTION_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// When a write is acknowledged relative to the ops log reaching the disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WriteACKs {
+    /// Sync the ops log before every write is acknowledged
+    #[default]
+    Synced,
+    /// Sync the ops log at most once every `interval` and acknowledge every write
+    /// appended before a sync once it completes
+    GroupCommit { interval: Duration },
+    /// Acknowledge a write once it has been handed to the OS.
+    /// A crash of the machine can lose the writes made since the last commit
+    Buffered,
+}
+
+/// How the ops log is written to disk
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum IoBackend {
+    /// Blocking file calls on the thread pool of `async-fs`
+    #[default]
+    ThreadPool,
+    /// Submit appends to io_uring, keeping the log open between them.
+    /// Documents go through io_uring whenever the `io_uring` feature is enabled
+    /// since sled chooses how it does I/O when it is compiled
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    IoUring,
+}
+
+/// Configuration of a `TuringEngine`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct TuringConfig {
+///     meta_encoding: MetaEncoding,
+///     compaction_interval: Option<Duration>,
+///     reap_interval: Option<Duration>,
+///     history_depth: usize,
+///     vacuum_interval: Option<Duration>,
+///     tombstone_retention: Duration,
+///     db_quota: Option<u64>,
+///     scrub_interval: Option<Duration>,
+///     write_acks: WriteACKs,
+///     db_write_acks: BTreeMap<Utf8PathBuf, WriteACKs>,
+///     archive_interval: Option<Duration>,
+///     cold_after: Duration,
+///     io_backend: IoBackend,
+///     watchdog_interval: Option<Duration>,
+///     transaction_ttl: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuringConfig {
+    meta_encoding: MetaEncoding,
+    compaction_interval: Option<Duration>,
+    reap_interval: Option<Duration>,
+    history_depth: usize,
+    vacuum_interval: Option<Duration>,
+    tombstone_retention: Duration,
+    db_quota: Option<u64>,
+    scrub_interval: Option<Duration>,
+    write_acks: WriteACKs,
+    db_write_acks: BTreeMap<Utf8PathBuf, WriteACKs>,
+    archive_interval: Option<Duration>,
+    cold_after: Duration,
+    io_backend: IoBackend,
+    watchdog_interval: Option<Duration>,
+    transaction_ttl: Duration,
+}
+
+impl Default for TuringConfig {
+    fn default() -> Self {
+        Self {
+            meta_encoding: MetaEncoding::default(),
+            compaction_interval: Some(DEFAULT_COMPACTION_INTERVAL),
+            reap_interval: Some(DEFAULT_REAP_INTERVAL),
+            history_depth: DEFAULT_HISTORY_DEPTH,
+            vacuum_interval: Some(DEFAULT_VACUUM_INTERVAL),
+            tombstone_retention: DEFAULT_TOMBSTONE_RETENTION,
+            db_quota: None,
+            scrub_interval: Some(DEFAULT_SCRUB_INTERVAL),
+            write_acks: WriteACKs::default(),
+            db_write_acks: BTreeMap::new(),
+            archive_interval: None,
+            cold_after: DEFAULT_COLD_AFTER,
+            io_backend: IoBackend::default(),
+            watchdog_interval: Some(DEFAULT_WATCHDOG_INTERVAL),
+            transaction_ttl: DEFAULT_TRANSACTION_TTL,
+        }
+    }
+}
+
+impl TuringConfig {
+    pub fn set_meta_encoding(mut self, meta_encoding: MetaEncoding) -> Self {
+        self.meta_encoding = meta_encoding;
+
+        self
+    }
+    /// How long the background compaction task waits between compactions of the ops log
+    pub fn set_compaction_interval(mut self, compaction_interval: Duration) -> Self {
+        self.compaction_interval = Some(compaction_interval);
+
+        self
+    }
+    /// Never compact the ops log in the background
+    pub fn disable_compaction(mut self) -> Self {
+        self.compaction_interval = None;
+
+        self
+    }
+
+    /// How long the background expiry reaper waits between scans for expired documents
+    pub fn set_reap_interval(mut self, reap_interval: Duration) -> Self {
+        self.reap_interval = Some(reap_interval);
+
+        self
+    }
+    /// Never remove expired documents in the background
+    pub fn disable_reaper(mut self) -> Self {
+        self.reap_interval = None;
+
+        self
+    }
+
+    /// How many revisions of every document are kept, at least the current one is always kept
+    pub fn set_history_depth(mut self, history_depth: usize) -> Self {
+        self.history_depth = history_depth.max(1);
+
+        self
+    }
+
+    /// How long the background vacuum task waits between purges of old tombstones
+    pub fn set_vacuum_interval(mut self, vacuum_interval: Duration) -> Self {
+        self.vacuum_interval = Some(vacuum_interval);
+
+        self
+    }
+    /// Never purge tombstones in the background
+    pub fn disable_vacuum(mut self) -> Self {
+        self.vacuum_interval = None;
+
+        self
+    }
+    /// How long the tombstone of a removed field is kept before it can be vacuumed
+    pub fn set_tombstone_retention(mut self, tombstone_retention: Duration) -> Self {
+        self.tombstone_retention = tombstone_retention;
+
+        self
+    }
+
+    /// The most bytes any database may take up on disk, writes that would exceed it are rejected.
+    /// There is no quota by default
+    pub fn set_db_quota(mut self, db_quota: u64) -> Self {
+        self.db_quota = Some(db_quota);
+
+        self
+    }
+    /// Let databases grow without a limit
+    pub fn disable_db_quota(mut self) -> Self {
+        self.db_quota = None;
+
+        self
+    }
+
+    /// How long the background integrity scrubber waits between passes over the repo
+    pub fn set_scrub_interval(mut self, scrub_interval: Duration) -> Self {
+        self.scrub_interval = Some(scrub_interval);
+
+        self
+    }
+    /// Never check the integrity of the repo in the background
+    pub fn disable_scrubber(mut self) -> Self {
+        self.scrub_interval = None;
+
+        self
+    }
+
+    /// When writes to the repo are acknowledged, unless overridden for their database
+    pub fn set_write_acks(mut self, write_acks: WriteACKs) -> Self {
+        self.write_acks = write_acks;
+
+        self
+    }
+    /// When writes to a single database are acknowledged
+    pub fn set_db_write_acks(mut self, db_name: &str, write_acks: WriteACKs) -> Self {
+        self.db_write_acks.insert(db_name.into(), write_acks);
+
+        self
+    }
+
+    /// How long the background archiver waits between passes over the repo.
+    /// Documents are only moved into the cold tier once an interval is set
+    pub fn set_archive_interval(mut self, archive_interval: Duration) -> Self {
+        self.archive_interval = Some(archive_interval);
+
+        self
+    }
+    /// Never move documents into the cold tier in the background
+    pub fn disable_archiver(mut self) -> Self {
+        self.archive_interval = None;
+
+        self
+    }
+    /// How long a document is left unwritten before the archiver moves it into the cold tier
+    pub fn set_cold_after(mut self, cold_after: Duration) -> Self {
+        self.cold_after = cold_after;
+
+        self
+    }
+
+    /// How appends to the ops log reach the disk
+    pub fn set_io_backend(mut self, io_backend: IoBackend) -> Self {
+        self.io_backend = io_backend;
+
+        self
+    }
+
+    /// How long the background watchdog waits between passes over the open transactions
+    pub fn set_watchdog_interval(mut self, watchdog_interval: Duration) -> Self {
+        self.watchdog_interval = Some(watchdog_interval);
+
+        self
+    }
+    /// Never roll back abandoned transactions in the background
+    pub fn disable_watchdog(mut self) -> Self {
+        self.watchdog_interval = None;
+
+        self
+    }
+    /// How long a transaction is left without a write, savepoint or rollback to a savepoint
+    /// before the watchdog rolls it back
+    pub fn set_transaction_ttl(mut self, transaction_ttl: Duration) -> Self {
+        self.transaction_ttl = transaction_ttl;
+
+        self
+    }
+
+    pub fn get_meta_encoding(&self) -> MetaEncoding {
+        self.meta_encoding
+    }
+
+    pub fn get_compaction_interval(&self) -> Option<Duration> {
+        self.compaction_interval
+    }
+
+    pub fn get_reap_interval(&self) -> Option<Duration> {
+        self.reap_interval
+    }
+
+    pub fn get_history_depth(&self) -> usize {
+        self.history_depth
+    }
+
+    pub fn get_vacuum_interval(&self) -> Option<Duration> {
+        self.vacuum_interval
+    }
+
+    pub fn get_tombstone_retention(&self) -> Duration {
+        self.tombstone_retention
+    }
+
+    pub fn get_db_quota(&self) -> Option<u64> {
+        self.db_quota
+    }
+
+    pub fn get_scrub_interval(&self) -> Option<Duration> {
+        self.scrub_interval
+    }
+
+    pub fn get_write_acks(&self) -> WriteACKs {
+        self.write_acks
+    }
+    pub fn get_archive_interval(&self) -> Option<Duration> {
+        self.archive_interval
+    }
+
+    pub fn get_cold_after(&self) -> Duration {
+        self.cold_after
+    }
+
+    pub fn get_io_backend(&self) -> IoBackend {
+        self.io_backend
+    }
+
+    pub fn get_watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog_interval
+    }
+
+    pub fn get_transaction_ttl(&self) -> Duration {
+        self.transaction_ttl
+    }
+    /// When writes to `db_name` are acknowledged, falling back to the policy of the repo
+    pub fn get_db_write_acks(&self, db_name: &Utf8Path) -> WriteACKs {
+        match self.db_write_acks.get(db_name) {
+            Some(write_acks) => *write_acks,
+            None => self.write_acks,
+        }
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    DataType, Document, FieldData, TDBCell, TuringDB, TuringDbError, TuringResult, Version,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// A change to a CRDT field, the first one creates the field with the kind of CRDT it changes
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum CrdtOp {
+///     MapSet { entry: Vec<u8>, value: TDBCell },
+///     MapRemove { entry: Vec<u8> },
+///     SetAdd { element: Vec<u8> },
+///     SetRemove { element: Vec<u8> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CrdtOp {
+    /// Set an entry of an `LwwMap`
+    MapSet { entry: Vec<u8>, value: TDBCell },
+    /// Remove an entry of an `LwwMap`
+    MapRemove { entry: Vec<u8> },
+    /// Add an element to an `OrSet`
+    SetAdd { element: Vec<u8> },
+    /// Remove the additions of an element to an `OrSet` the node has seen
+    SetRemove { element: Vec<u8> },
+}
+
+/// The contents of a CRDT field. Merging two of them gives the same result whatever their order
+/// and merging one again changes nothing, so the writers of a multi-writer cluster that saw the
+/// same changes hold the same field without a `Conflict` to settle. A change is sent to the other
+/// nodes as the small `Crdt` it merges into the field. Stored in the field as `DataType::BINARY`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub enum Crdt {
+///     Map(LwwMap),
+///     Set(OrSet),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Crdt {
+    Map(LwwMap),
+    Set(OrSet),
+}
+
+impl Crdt {
+    /// The changes `op` makes to `current`, to be merged into it. Made by the node `node`
+    /// as the write producing the revision `revision` of the document
+    pub(crate) fn delta(
+        current: Option<&Crdt>,
+        op: CrdtOp,
+        node: u64,
+        revision: u64,
+    ) -> TuringResult<Crdt> {
+        match op {
+            CrdtOp::MapSet { entry, value } => {
+                let map = Crdt::map_of(current)?;
+                let version =
+                    Version::next(node, revision, map.and_then(|map| map.version(&entry)));
+                let value =
+                    FieldData::new_at(value.get_data_type(), value.get_data(), version.timestamp());
+
+                Ok(Crdt::Map(LwwMap::of(entry, version, Some(value))))
+            }
+            CrdtOp::MapRemove { entry } => {
+                let map = Crdt::map_of(current)?;
+                let version =
+                    Version::next(node, revision, map.and_then(|map| map.version(&entry)));
+
+                Ok(Crdt::Map(LwwMap::of(entry, version, None)))
+            }
+            CrdtOp::SetAdd { element } => {
+                Crdt::set_of(current)?;
+                let mut added = BTreeMap::new();
+                added.insert(
+                    element,
+                    vec![Version::next(node, revision, None)]
+                        .into_iter()
+                        .collect(),
+                );
+
+                Ok(Crdt::Set(OrSet {
+                    added,
+                    removed: BTreeSet::new(),
+                }))
+            }
+            CrdtOp::SetRemove { element } => {
+                let removed = match Crdt::set_of(current)? {
+                    None => BTreeSet::new(),
+                    Some(set) => set.added.get(&element).cloned().unwrap_or_default(),
+                };
+
+                Ok(Crdt::Set(OrSet {
+                    added: BTreeMap::new(),
+                    removed,
+                }))
+            }
+        }
+    }
+    /// Take in the changes of `other`, fails with `CrdtMismatch` when it is another kind of CRDT
+    pub(crate) fn merge(&mut self, other: &Crdt) -> TuringResult<()> {
+        match (self, other) {
+            (Crdt::Map(map), Crdt::Map(other)) => map.merge(other),
+            (Crdt::Set(set), Crdt::Set(other)) => set.merge(other),
+            _ => return Err(TuringDbError::CrdtMismatch),
+        }
+
+        Ok(())
+    }
+    /// The CRDT a field of the document holds, `None` for a field it does not hold
+    pub(crate) fn read(sled_db: &Document, key: &[u8]) -> TuringResult<Option<Crdt>> {
+        match sled_db.get(key)? {
+            None => Ok(None),
+            Some(stored) => Ok(Some(Crdt::decode(&TuringDB::decode_field(&stored)?)?)),
+        }
+    }
+    /// Read the contents of a field, fails with `CrdtMismatch` when the field holds no CRDT
+    pub(crate) fn decode(field_data: &FieldData) -> TuringResult<Crdt> {
+        if field_data.data_type() != DataType::BINARY {
+            return Err(TuringDbError::CrdtMismatch);
+        }
+
+        bincode::deserialize::<Crdt>(field_data.data()).map_err(|_| TuringDbError::CrdtMismatch)
+    }
+
+    pub(crate) fn encode(&self) -> TuringResult<Vec<u8>> {
+        Ok(bincode::serialize::<Crdt>(self)?)
+    }
+
+    pub fn as_map(&self) -> Option<&LwwMap> {
+        match self {
+            Crdt::Map(map) => Some(map),
+            Crdt::Set(_) => None,
+        }
+    }
+
+    pub fn as_set(&self) -> Option<&OrSet> {
+        match self {
+            Crdt::Set(set) => Some(set),
+            Crdt::Map(_) => None,
+        }
+    }
+
+    fn map_of(current: Option<&Crdt>) -> TuringResult<Option<&LwwMap>> {
+        match current {
+            None => Ok(None),
+            Some(Crdt::Map(map)) => Ok(Some(map)),
+            Some(Crdt::Set(_)) => Err(TuringDbError::CrdtMismatch),
+        }
+    }
+
+    fn set_of(current: Option<&Crdt>) -> TuringResult<Option<&OrSet>> {
+        match current {
+            None => Ok(None),
+            Some(Crdt::Set(set)) => Ok(Some(set)),
+            Some(Crdt::Map(_)) => Err(TuringDbError::CrdtMismatch),
+        }
+    }
+}
+
+/// A map whose entries are each kept from the write with the greatest `Version`.
+/// A removed entry is kept as a tombstone so an older write reaching a node later does not bring it back
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct LwwMap {
+///     entries: BTreeMap<Vec<u8>, (Version, Option<FieldData>)>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LwwMap {
+    entries: BTreeMap<Vec<u8>, (Version, Option<FieldData>)>,
+}
+
+impl LwwMap {
+    fn of(entry: Vec<u8>, version: Version, value: Option<FieldData>) -> Self {
+        let mut entries = BTreeMap::new();
+        entries.insert(entry, (version, value));
+
+        Self { entries }
+    }
+    /// The value of an entry, `None` for one that was never set or was removed
+    pub fn get(&self, entry: &[u8]) -> Option<&FieldData> {
+        self.entries
+            .get(entry)
+            .and_then(|(_, value)| value.as_ref())
+    }
+    /// The entries that are set along with their values, sorted by entry
+    pub fn entries(&self) -> Vec<(&[u8], &FieldData)> {
+        self.entries
+            .iter()
+            .filter_map(|(entry, (_, value))| value.as_ref().map(|value| (entry.as_slice(), value)))
+            .collect()
+    }
+    /// The version of the last write to an entry, removals included
+    pub fn version(&self, entry: &[u8]) -> Option<Version> {
+        self.entries.get(entry).map(|(version, _)| *version)
+    }
+
+    fn merge(&mut self, other: &LwwMap) {
+        for (entry, (version, value)) in &other.entries {
+            match self.entries.get(entry) {
+                Some((current, _)) if current >= version => (),
+                _ => {
+                    self.entries
+                        .insert(entry.clone(), (*version, value.clone()));
+                }
+            }
+        }
+    }
+}
+
+/// A set where an addition wins over a concurrent removal. Every addition of an element is tagged
+/// with a `Version` and a removal only cancels the tags the node removing it had seen, the tags
+/// of removals are kept so an addition they cancel reaching a node later stays cancelled
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct OrSet {
+///     added: BTreeMap<Vec<u8>, BTreeSet<Version>>,
+///     removed: BTreeSet<Version>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct OrSet {
+    added: BTreeMap<Vec<u8>, BTreeSet<Version>>,
+    removed: BTreeSet<Version>,
+}
+
+impl OrSet {
+    pub fn contains(&self, element: &[u8]) -> bool {
+        self.added.contains_key(element)
+    }
+    /// The elements of the set, sorted
+    pub fn elements(&self) -> Vec<&[u8]> {
+        self.added.keys().map(Vec::as_slice).collect()
+    }
+
+    fn merge(&mut self, other: &OrSet) {
+        self.removed.extend(other.removed.iter().copied());
+        for (element, tags) in &other.added {
+            self.added
+                .entry(element.clone())
+                .or_default()
+                .extend(tags.iter().copied());
+        }
+
+        let removed = &self.removed;
+        self.added.retain(|_, tags| {
+            tags.retain(|tag| !removed.contains(tag));
+
+            !tags.is_empty()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make a change on a node, returning the delta sent to the other nodes
+    fn change(replica: &mut Option<Crdt>, op: CrdtOp, node: u64) -> Crdt {
+        let delta = Crdt::delta(replica.as_ref(), op, node, 1).unwrap();
+        match replica {
+            None => *replica = Some(delta.clone()),
+            Some(current) => current.merge(&delta).unwrap(),
+        }
+
+        delta
+    }
+
+    fn merged(replica: &Option<Crdt>, deltas: &[&Crdt]) -> Crdt {
+        let mut merged = replica.clone().unwrap();
+        for delta in deltas {
+            merged.merge(delta).unwrap();
+        }
+
+        merged
+    }
+
+    fn set(entry: &[u8], value: &str) -> CrdtOp {
+        CrdtOp::MapSet {
+            entry: entry.to_vec(),
+            value: TDBCell::new(DataType::STRING, value.as_bytes()),
+        }
+    }
+
+    fn add(element: &[u8]) -> CrdtOp {
+        CrdtOp::SetAdd {
+            element: element.to_vec(),
+        }
+    }
+
+    fn remove(element: &[u8]) -> CrdtOp {
+        CrdtOp::SetRemove {
+            element: element.to_vec(),
+        }
+    }
+
+    #[test]
+    fn concurrent_map_writes_converge_in_any_order() {
+        let (mut first, mut second) = (None, None);
+        let from_first = change(&mut first, set(b"colour", "red"), 1);
+        let from_second = change(&mut second, set(b"colour", "blue"), 2);
+        let removed = change(
+            &mut second,
+            CrdtOp::MapRemove {
+                entry: b"size".to_vec(),
+            },
+            2,
+        );
+        let sized = change(&mut first, set(b"size", "large"), 1);
+
+        let on_first = merged(&first, &[&from_second, &removed]);
+        let on_second = merged(&second, &[&sized, &from_first]);
+        assert_eq!(on_first, on_second);
+        // Merging a change again leaves the map as it is
+        assert_eq!(merged(&Some(on_first.clone()), &[&from_second]), on_first);
+
+        let map = on_first.as_map().unwrap();
+        // The entry keeps the write with the greatest version, the second node wrote last
+        assert_eq!(map.get(b"colour").unwrap().data(), b"blue");
+        assert_eq!(map.get(b"size").unwrap().data(), b"large");
+    }
+
+    #[test]
+    fn an_addition_wins_over_a_concurrent_removal() {
+        let mut first = None;
+        let added = change(&mut first, add(b"tag"), 1);
+        let mut second = Some(added.clone());
+
+        // The second node removes the addition it saw while the first adds the element again
+        let removed = change(&mut second, remove(b"tag"), 2);
+        assert!(!second.as_ref().unwrap().as_set().unwrap().contains(b"tag"));
+        let added_again = change(&mut first, add(b"tag"), 1);
+
+        let on_first = merged(&first, &[&removed]);
+        let on_second = merged(&second, &[&added_again, &added]);
+        assert_eq!(on_first, on_second);
+        assert_eq!(
+            on_first.as_set().unwrap().elements(),
+            vec![b"tag".as_slice()]
+        );
+
+        // A removal that saw every addition removes the element everywhere
+        let mut third = Some(on_first.clone());
+        let removed = change(&mut third, remove(b"tag"), 3);
+        assert!(!merged(&Some(on_second), &[&removed])
+            .as_set()
+            .unwrap()
+            .contains(b"tag"));
+    }
+
+    #[test]
+    fn a_map_and_a_set_do_not_merge() {
+        let mut map = None;
+        change(&mut map, set(b"colour", "red"), 1);
+        let set = Crdt::delta(None, add(b"tag"), 1, 1).unwrap();
+
+        assert!(matches!(
+            map.unwrap().merge(&set),
+            Err(TuringDbError::CrdtMismatch)
+        ));
+    }
+}
@@ -0,0 +1,107 @@
+use crate::Query;
+use async_lock::Mutex;
+use dashmap::DashMap;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use tai64::TAI64N;
+
+/// The number of documents a scan reads from the database at a time
+pub(crate) const SCAN_BATCH: usize = 256;
+
+/// The position of a scan over the documents a query matches. Only the query that reads the next
+/// batch is kept, it continues after the last document read so nothing already read is held
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct Cursor {
+///     query: Option<Query>,
+///     last_read: TAI64N,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct Cursor {
+    // `None` once every matching document has been read
+    query: Option<Query>,
+    last_read: TAI64N,
+}
+
+impl Cursor {
+    pub(crate) fn new(query: Query) -> Self {
+        Self {
+            query: Some(query),
+            last_read: TAI64N::now(),
+        }
+    }
+    /// The query reading the next `count` documents, `None` once the cursor is exhausted
+    pub(crate) fn next_query(&mut self, count: usize) -> Option<Query> {
+        self.last_read = TAI64N::now();
+
+        self.query
+            .as_ref()
+            .map(|query| query.clone().set_limit(count))
+    }
+    /// Move the cursor past the batch just read given the continuation token the batch returned.
+    /// The offset of the query only applies to the first batch
+    pub(crate) fn advance(&mut self, continuation: Option<String>) {
+        self.query = match (self.query.take(), continuation) {
+            (Some(query), Some(token)) => Some(query.set_offset(0).set_after(&token)),
+            _ => None,
+        };
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.query.is_none()
+    }
+}
+
+/// Cursors kept open for clients reading over the wire, each read hands back the next batch
+/// ```
+/// #[derive(Debug, Default)]
+/// pub(crate) struct Cursors {
+///     next_id: AtomicU64,
+///     open: DashMap<u64, Arc<Mutex<Cursor>>>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct Cursors {
+    next_id: AtomicU64,
+    // A cursor is locked while a batch is read from it so two reads never return the same batch
+    open: DashMap<u64, Arc<Mutex<Cursor>>>,
+}
+
+impl Cursors {
+    /// Open a cursor over `query` and return its id
+    pub(crate) fn open(&self, query: Query) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open
+            .insert(id, Arc::new(Mutex::new(Cursor::new(query))));
+
+        id
+    }
+
+    pub(crate) fn get(&self, id: u64) -> Option<Arc<Mutex<Cursor>>> {
+        self.open.get(&id).map(|cursor| Arc::clone(cursor.value()))
+    }
+    /// Close a cursor, returning whether it was open
+    pub(crate) fn close(&self, id: u64) -> bool {
+        self.open.remove(&id).is_some()
+    }
+    /// Close every cursor that has not been read since `cutoff`, returning how many were closed
+    pub(crate) async fn close_idle(&self, cutoff: TAI64N) -> usize {
+        let open = self
+            .open
+            .iter()
+            .map(|cursor| (*cursor.key(), Arc::clone(cursor.value())))
+            .collect::<Vec<(u64, Arc<Mutex<Cursor>>)>>();
+
+        let mut closed = 0_usize;
+        for (id, cursor) in open {
+            if cursor.lock().await.last_read < cutoff && self.close(id) {
+                closed += 1;
+            }
+        }
+
+        closed
+    }
+}
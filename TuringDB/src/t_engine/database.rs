@@ -1,168 +1,3279 @@
-use crate::{Document, OpsOutcome, TuringDbError, TuringResult};
+use crate::{
+    Aggregation, BloomFilter, ChunkedStream, ColdDocument, Collation, Compression, Conflict, Crdt,
+    DataType, DbMeta, DbStats, DbUsage, Document, DocumentContents, DocumentHashes, DocumentIndex,
+    DocumentView, Embedding, FieldData, FieldIndex, FieldSource, Filter, History, Hnsw,
+    IndexDeclaration, Indexes, IntegrityFinding, IntegrityIssue, IntegrityReport, LogOp, Matched,
+    MerkleTree, MetaEncoding, MetaFile, Neighbour, OpsOutcome, Partitioning, Patch, PatchOp,
+    PrefixIndex, Quarantine, Query, QueryPlan, Revision, RevisionPins, Shuffle, Stamp,
+    StoredRevision, StreamManifest, Structure, TDBCell, TextIndex, TextIndexDefinition, TimeField,
+    TimeIndex, Trash, TtlIndex, TuringDbError, TuringResult, UniqueKey, Value, VectorSpace,
+    WriteOp, ARCHIVING_EXTENSION, CONFLICT_TREE, CURRENT_REVISION_KEY, DELTA_RUN_TREE,
+    HISTORY_TREE, REHYDRATING_EXTENSION, STREAM_TREE, TOMBSTONE_TREE, VERSION_TREE,
+};
 use async_fs::DirBuilder;
 use async_lock::Mutex;
 use camino::{Utf8Path, Utf8PathBuf};
-use sled::IVec;
-use std::collections::hash_map::HashMap;
+use futures_lite::io::AsyncWrite;
+use sled::{
+    transaction::{ConflictableTransactionError, TransactionError},
+    Transactional,
+};
+use std::{
+    collections::{hash_map::HashMap, BTreeMap, BTreeSet, BinaryHeap},
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tai64::TAI64N;
+
+/// #### A document whose sled database is only opened the first time it is accessed
+/// ```
+/// #[derive(Debug)]
+/// struct LazyDocument {
+///     path: Utf8PathBuf,
+///     document: Mutex<DocumentState>,
+///     quarantine: Arc<Quarantine>,
+///     pins: Arc<RevisionPins>,
+/// }
+///```
+#[derive(Debug)]
+pub(crate) struct LazyDocument {
+    path: Utf8PathBuf,
+    document: Mutex<DocumentState>,
+    quarantine: Arc<Quarantine>,
+    // The revisions open views are reading at
+    pins: Arc<RevisionPins>,
+}
+
+#[derive(Debug)]
+enum DocumentState {
+    Unopened,
+    Opened(Document),
+    // sled reported the document as corrupted and it was moved into the quarantine
+    Quarantined,
+    // The document was moved into the cold tier and is rehydrated the next time it is opened
+    Cold,
+}
+
+impl LazyDocument {
+    /// Point to a document on disk without opening it
+    pub(crate) fn new(path: Utf8PathBuf, quarantine: Arc<Quarantine>) -> Self {
+        Self {
+            path,
+            document: Mutex::new(DocumentState::Unopened),
+            quarantine,
+            pins: Arc::default(),
+        }
+    }
+    /// Hold a document that has already been opened
+    pub(crate) fn opened(
+        path: Utf8PathBuf,
+        document: Document,
+        quarantine: Arc<Quarantine>,
+    ) -> Self {
+        Self {
+            path,
+            document: Mutex::new(DocumentState::Opened(document)),
+            quarantine,
+            pins: Arc::default(),
+        }
+    }
+    /// Point to a document in the cold tier without rehydrating it
+    pub(crate) fn cold(path: Utf8PathBuf, quarantine: Arc<Quarantine>) -> Self {
+        Self {
+            path,
+            document: Mutex::new(DocumentState::Cold),
+            quarantine,
+            pins: Arc::default(),
+        }
+    }
+    /// Get the document, opening it if this is the first access.
+    /// A document in the cold tier is rehydrated first and one that sled reports as corrupted is quarantined
+    pub(crate) async fn open(&self) -> TuringResult<Document> {
+        let mut document = self.document.lock().await;
+
+        self.open_state(&mut document).await
+    }
+
+    async fn open_state(&self, document: &mut DocumentState) -> TuringResult<Document> {
+        match &*document {
+            DocumentState::Opened(sled_db) => Ok(sled_db.clone()),
+            DocumentState::Quarantined => {
+                Err(TuringDbError::DocumentCorrupted { at: None, bt: () })
+            }
+            DocumentState::Cold => match self.rehydrate().await {
+                Ok(sled_db) => {
+                    *document = DocumentState::Opened(sled_db.clone());
+
+                    Ok(sled_db)
+                }
+                Err(error) => {
+                    // Both copies of the archive failed their checksum and were quarantined
+                    if let TuringDbError::DocumentCorrupted { .. } = error {
+                        *document = DocumentState::Quarantined;
+                    }
+
+                    Err(error)
+                }
+            },
+            DocumentState::Unopened => {
+                match sled::Config::default()
+                    .path(&self.path)
+                    .create_new(false)
+                    .open()
+                {
+                    Ok(sled_db) => {
+                        *document = DocumentState::Opened(sled_db.clone());
+
+                        Ok(sled_db)
+                    }
+                    Err(error) => {
+                        let error = TuringDbError::from(error);
+
+                        if let TuringDbError::DocumentCorrupted { .. } = error {
+                            self.quarantine.isolate(&self.path).await?;
+                            *document = DocumentState::Quarantined;
+                        }
+
+                        Err(error)
+                    }
+                }
+            }
+        }
+    }
+    /// Get the document only if it has already been opened
+    pub(crate) async fn loaded(&self) -> Option<Document> {
+        match &*self.document.lock().await {
+            DocumentState::Opened(sled_db) => Some(sled_db.clone()),
+            _ => None,
+        }
+    }
+
+    pub(crate) async fn is_cold(&self) -> bool {
+        matches!(&*self.document.lock().await, DocumentState::Cold)
+    }
+    /// Get the contents of the document for copying, reading the archive of a document in the cold tier
+    pub(crate) async fn contents(&self) -> TuringResult<DocumentContents> {
+        let mut document = self.document.lock().await;
+
+        match &*document {
+            DocumentState::Cold => Ok(DocumentContents::Cold(
+                ColdDocument::load(&self.path, &self.quarantine).await?,
+            )),
+            _ => Ok(DocumentContents::Hot(self.open_state(&mut document).await?)),
+        }
+    }
+    /// The archive of the document if it is in the cold tier along with how many bytes it takes up
+    pub(crate) async fn cold_archive(&self) -> TuringResult<Option<(ColdDocument, u64)>> {
+        match &*self.document.lock().await {
+            DocumentState::Cold => {
+                let cold_document = ColdDocument::load(&self.path, &self.quarantine).await?;
+                let disk_bytes = async_fs::metadata(ColdDocument::path(&self.path))
+                    .await?
+                    .len();
+
+                Ok(Some((cold_document, disk_bytes)))
+            }
+            _ => Ok(None),
+        }
+    }
+    /// Move the document into the cold tier if it has not been written since `cutoff`,
+    /// returning whether it was moved. Documents read by an open view and documents
+    /// that were never written are left where they are
+    pub(crate) async fn archive_if_idle(&self, cutoff: TAI64N) -> TuringResult<bool> {
+        let mut document = self.document.lock().await;
+
+        let unopened = match &*document {
+            DocumentState::Cold | DocumentState::Quarantined => return Ok(false),
+            DocumentState::Unopened => true,
+            DocumentState::Opened(_) => false,
+        };
+        if self.pins.oldest()?.is_some() {
+            return Ok(false);
+        }
+
+        let sled_db = match self.open_state(&mut document).await {
+            Ok(sled_db) => sled_db,
+            // Corrupted documents are quarantined when opened and left to the scrubber
+            Err(TuringDbError::DocumentCorrupted { .. }) => return Ok(false),
+            Err(error) => return Err(error),
+        };
+
+        let last_written = match History::last_written(&sled_db)? {
+            Some(last_written) if last_written <= cutoff => last_written,
+            _ => {
+                // Only keep the document open if it was open before it was checked
+                if unopened {
+                    *document = DocumentState::Unopened;
+                }
+
+                return Ok(false);
+            }
+        };
+
+        ColdDocument::capture(&sled_db, last_written)?
+            .persist(&self.path)
+            .await?;
+
+        // The document is closed before its directory is moved aside and removed,
+        // a crash before it is removed leaves a complete archive to rehydrate from
+        *document = DocumentState::Cold;
+        drop(sled_db);
+
+        let archiving = ColdDocument::staging_path(&self.path, ARCHIVING_EXTENSION);
+        async_fs::rename(&self.path, &archiving).await?;
+        async_fs::remove_dir_all(&archiving).await?;
+
+        Ok(true)
+    }
+    /// Seal an archive of the document for a storage backend unless the copy pushed last,
+    /// reflecting the write at `pushed`, is still current. A document that has not been opened
+    /// cannot have been written and an archived one cannot change, so both are only sealed when
+    /// they were never pushed or `every_document` is set
+    pub(crate) async fn archive_for_push(
+        &self,
+        pushed: Option<Option<TAI64N>>,
+        every_document: bool,
+    ) -> TuringResult<Option<(Option<TAI64N>, Vec<u8>)>> {
+        let mut document = self.document.lock().await;
+
+        match &*document {
+            DocumentState::Quarantined => Ok(None),
+            DocumentState::Unopened if pushed.is_some() && !every_document => Ok(None),
+            DocumentState::Cold if pushed.is_some() && !every_document => Ok(None),
+            DocumentState::Cold => {
+                let cold_document = ColdDocument::load(&self.path, &self.quarantine).await?;
+                let last_written = Some(cold_document.last_written());
+
+                if pushed == Some(last_written) {
+                    return Ok(None);
+                }
+
+                Ok(Some((last_written, cold_document.seal()?)))
+            }
+            _ => {
+                let sled_db = self.open_state(&mut document).await?;
+                let last_written = History::last_written(&sled_db)?;
+
+                if pushed == Some(last_written) {
+                    return Ok(None);
+                }
+
+                let cold_document =
+                    ColdDocument::capture(&sled_db, last_written.unwrap_or_else(TAI64N::now))?;
+
+                Ok(Some((last_written, cold_document.seal()?)))
+            }
+        }
+    }
+    /// Remove the document from disk, whichever tier it is in
+    pub(crate) async fn remove_files(&self) -> TuringResult<()> {
+        match &*self.document.lock().await {
+            DocumentState::Cold => ColdDocument::remove(&self.path).await,
+            _ => Ok(async_fs::remove_dir_all(&self.path).await?),
+        }
+    }
+    /// Restore the document from its archive next to the archive and move it into place,
+    /// the archive is only removed once the document is back where it belongs
+    async fn rehydrate(&self) -> TuringResult<Document> {
+        let cold_document = ColdDocument::load(&self.path, &self.quarantine).await?;
+
+        let rehydrating = ColdDocument::staging_path(&self.path, REHYDRATING_EXTENSION);
+        if let Err(error) = async_fs::remove_dir_all(&rehydrating).await {
+            if error.kind() != ErrorKind::NotFound {
+                return Err(error.into());
+            }
+        }
+
+        {
+            let staged = sled::Config::default()
+                .path(&rehydrating)
+                .create_new(true)
+                .open()?;
+            cold_document.snapshot()?.restore_into(&staged)?;
+            staged.flush_async().await?;
+        }
+
+        async_fs::rename(&rehydrating, &self.path).await?;
+        ColdDocument::remove(&self.path).await?;
+
+        Ok(sled::Config::default()
+            .path(&self.path)
+            .create_new(false)
+            .open()?)
+    }
+}
 
 /// #### Contains the list of documents and databases in-memory
 /// ```
-/// #[derive(Debug, Clone)]
+/// #[derive(Debug)]
 /// struct TuringDB {
-///     list: HashMap<Utf8Utf8PathBuf, Document>,
+///     list: DocumentIndex,
+///     meta: Mutex<DbMeta>,
+///     dirty: AtomicBool,
+///     ephemeral: bool,
+///     usage: DbUsage,
+///     times: Mutex<TimeIndex>,
+///     structure: Mutex<Structure>,
+///     vectors: Mutex<Option<Hnsw>>,
+///     trash: Mutex<Trash>,
+///     collation: Mutex<Collation>,
+///     indexes: Mutex<Indexes>,
+///     unique: Mutex<Option<UniqueKey>>,
+///     text: Mutex<Option<TextIndex>>,
+///     ttl: Mutex<Option<TtlIndex>>,
+///     prefix: Mutex<Option<PrefixIndex>>,
+///     merkle: Mutex<DocumentHashes>,
 /// }
 ///```
 #[derive(Debug)]
 pub(crate) struct TuringDB {
-    pub(crate) list: HashMap<Utf8PathBuf, Document>,
+    pub(crate) list: DocumentIndex,
+    pub(crate) meta: Mutex<DbMeta>,
+    // Set when the database has changed since it was last committed
+    dirty: AtomicBool,
+    // The documents of an ephemeral database are never written to the repo directory
+    ephemeral: bool,
+    // How many bytes the database takes up, checked against the quota before writes
+    usage: DbUsage,
+    // When each document was created and last written, in time order
+    times: Mutex<TimeIndex>,
+    // The schema writes to the documents are checked against before they are logged
+    pub(crate) structure: Mutex<Structure>,
+    // The HNSW index of a vector database, `None` until the database is first searched
+    vectors: Mutex<Option<Hnsw>>,
+    // The documents dropped from the database while it soft deletes
+    trash: Mutex<Trash>,
+    // How the text of the documents is sorted and compared by range filters, unless a query says otherwise
+    pub(crate) collation: Mutex<Collation>,
+    // The secondary indexes on the fields of the documents
+    indexes: Mutex<Indexes>,
+    // The field no two documents may hold the same value of
+    unique: Mutex<Option<UniqueKey>>,
+    // The full-text index over the text fields of the documents
+    text: Mutex<Option<TextIndex>>,
+    // The timestamp field the documents expire by
+    ttl: Mutex<Option<TtlIndex>>,
+    // The text fields whose values are suggested as they are typed
+    prefix: Mutex<Option<PrefixIndex>>,
+    // The hashes of the documents the Merkle tree of the database is built from
+    merkle: Mutex<DocumentHashes>,
 }
 
 impl TuringDB {
     /// Create a new in-memory database
     pub(crate) fn new() -> Self {
         Self {
-            list: { HashMap::default() },
+            list: DocumentIndex::default(),
+            meta: Mutex::new(DbMeta::default()),
+            dirty: AtomicBool::new(true),
+            ephemeral: false,
+            usage: DbUsage::default(),
+            times: Mutex::new(TimeIndex::default()),
+            structure: Mutex::new(Structure::Schemaless),
+            vectors: Mutex::new(None),
+            trash: Mutex::new(Trash::default()),
+            collation: Mutex::new(Collation::Binary),
+            indexes: Mutex::new(Indexes::default()),
+            unique: Mutex::new(None),
+            text: Mutex::new(None),
+            ttl: Mutex::new(None),
+            prefix: Mutex::new(None),
+            merkle: Mutex::new(DocumentHashes::default()),
+        }
+    }
+    /// Create a new database whose documents are kept in memory
+    pub(crate) fn ephemeral() -> Self {
+        Self {
+            list: DocumentIndex::default(),
+            meta: Mutex::new(DbMeta::default()),
+            dirty: AtomicBool::new(false),
+            ephemeral: true,
+            usage: DbUsage::default(),
+            times: Mutex::new(TimeIndex::default()),
+            structure: Mutex::new(Structure::Schemaless),
+            vectors: Mutex::new(None),
+            trash: Mutex::new(Trash::default()),
+            collation: Mutex::new(Collation::Binary),
+            indexes: Mutex::new(Indexes::default()),
+            unique: Mutex::new(None),
+            text: Mutex::new(None),
+            ttl: Mutex::new(None),
+            prefix: Mutex::new(None),
+            merkle: Mutex::new(DocumentHashes::default()),
+        }
+    }
+    /// Hold a database whose metadata was loaded from disk
+    pub(crate) fn with_meta(meta: DbMeta) -> Self {
+        Self {
+            list: DocumentIndex::default(),
+            meta: Mutex::new(meta),
+            dirty: AtomicBool::new(false),
+            ephemeral: false,
+            usage: DbUsage::default(),
+            times: Mutex::new(TimeIndex::default()),
+            structure: Mutex::new(Structure::Schemaless),
+            vectors: Mutex::new(None),
+            trash: Mutex::new(Trash::default()),
+            collation: Mutex::new(Collation::Binary),
+            indexes: Mutex::new(Indexes::default()),
+            unique: Mutex::new(None),
+            text: Mutex::new(None),
+            ttl: Mutex::new(None),
+            prefix: Mutex::new(None),
+            merkle: Mutex::new(DocumentHashes::default()),
         }
     }
+    /// Spread the documents of a new database over partitions
+    pub(crate) fn set_partitioning(mut self, partitioning: Partitioning) -> Self {
+        self.list = DocumentIndex::new(partitioning);
 
-    /// Create a database
-    pub(crate) async fn db_create(
-        mut self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
-    ) -> Result<OpsOutcome, TuringDbError> {
-        let path = Self::build_path(repo_dir, db_name);
-        DirBuilder::new().recursive(false).create(path).await?;
+        self
+    }
+    /// Use the documents of a database listed from disk
+    pub(crate) fn set_documents(mut self, documents: DocumentIndex) -> Self {
+        self.list = documents;
 
-        let new_document = sled::Config::default()
-            .path("temp")
-            .temporary(true)
-            .open()?;
+        self
+    }
+    /// Use the time index of a database loaded from disk
+    pub(crate) fn set_times(mut self, times: TimeIndex) -> Self {
+        self.times = Mutex::new(times);
 
-        self.list.insert(db_name.into(), new_document);
+        self
+    }
+    /// Use the structure of a database loaded from disk
+    pub(crate) fn set_structure(mut self, structure: Structure) -> Self {
+        self.structure = Mutex::new(structure);
 
-        Ok(OpsOutcome::DbCreated)
+        self
     }
+    /// Use the trash of a database loaded from disk
+    pub(crate) fn set_trash(mut self, trash: Trash) -> Self {
+        self.trash = Mutex::new(trash);
 
-    /// Drop the database
-    pub(crate) async fn db_drop(
+        self
+    }
+    /// Use the collation of a database loaded from disk
+    pub(crate) fn set_collation(mut self, collation: Collation) -> Self {
+        self.collation = Mutex::new(collation);
+
+        self
+    }
+    /// Use the secondary indexes of a database loaded from disk
+    pub(crate) fn set_indexes(mut self, indexes: Indexes) -> Self {
+        self.indexes = Mutex::new(indexes);
+
+        self
+    }
+    /// Use the unique key of a database loaded from disk
+    pub(crate) fn set_unique(mut self, unique: Option<UniqueKey>) -> Self {
+        self.unique = Mutex::new(unique);
+
+        self
+    }
+    /// Use the full-text index of a database loaded from disk
+    pub(crate) fn set_text(mut self, text: Option<TextIndex>) -> Self {
+        self.text = Mutex::new(text);
+
+        self
+    }
+    /// Use the TTL index of a database loaded from disk
+    pub(crate) fn set_ttl(mut self, ttl: Option<TtlIndex>) -> Self {
+        self.ttl = Mutex::new(ttl);
+
+        self
+    }
+    /// Use the prefix index of a database loaded from disk
+    pub(crate) fn set_prefix(mut self, prefix: Option<PrefixIndex>) -> Self {
+        self.prefix = Mutex::new(prefix);
+
+        self
+    }
+    /// Change the structure of the database, the HNSW index built for the old one is dropped
+    pub(crate) async fn restructure(&self, structure: Structure) {
+        *self.structure.lock().await = structure;
+        *self.vectors.lock().await = None;
+    }
+    /// Bring the HNSW index up to date with the documents an operation wrote to, by reading their embeddings
+    /// again once it has been applied. An index that can not be brought up to date is dropped and built again
+    /// the next time the database is searched
+    pub(crate) async fn record_vectors(&self, op: &LogOp) {
+        let space = match &*self.structure.lock().await {
+            Structure::Vector(space) if space.get_hnsw().is_some() => space.clone(),
+            _ => return,
+        };
+        let mut vectors = self.vectors.lock().await;
+
+        let document_names = match op.documents() {
+            Some(document_names) => document_names,
+            None => {
+                *vectors = None;
+                return;
+            }
+        };
+
+        if let Some(index) = vectors.as_mut() {
+            for document_name in document_names {
+                if self
+                    .index_embedding(index, &space, document_name)
+                    .await
+                    .is_err()
+                {
+                    *vectors = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn index_embedding(
         &self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
-    ) -> Result<OpsOutcome, TuringDbError> {
-        let path = Self::build_path(repo_dir, db_name);
-        async_fs::remove_dir_all(path).await?;
+        index: &mut Hnsw,
+        space: &VectorSpace,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            index.remove(document_name);
 
-        Ok(OpsOutcome::DbDropped)
+            return Ok(());
+        }
+
+        match Embedding::read(&self.document(document_name).await?, space.get_key())? {
+            Some(vector) => index.insert(document_name, vector),
+            None => index.remove(document_name),
+        }
+
+        Ok(())
     }
-    /// List all the documents in the repo
-    pub fn document_list(db: &Self) -> OpsOutcome {
-        let mut list: Vec<Utf8PathBuf> = Vec::new();
+    /// Find the `k` documents whose embeddings are nearest to `query`, nearest first. Documents without
+    /// an embedding are left out. Without an HNSW index every embedding is compared to the query
+    pub(crate) async fn knn(&self, query: &[f32], k: usize) -> TuringResult<OpsOutcome> {
+        let space = match &*self.structure.lock().await {
+            Structure::Vector(space) => space.clone(),
+            _ => return Err(TuringDbError::NotVectorDatabase),
+        };
+        if query.len() != space.get_dimensions() as usize {
+            return Err(TuringDbError::InvalidInput);
+        }
+        if k == 0 {
+            return Ok(OpsOutcome::Neighbours(Vec::new()));
+        }
 
-        db.list.iter().for_each(|document_name| {
-            list.push(document_name.0.into());
-        });
+        if let Some(params) = space.get_hnsw() {
+            let mut vectors = self.vectors.lock().await;
+            if vectors.is_none() {
+                *vectors = Some(Hnsw::build(&space, params, self.list.documents().await?).await?);
+            }
 
-        if list.is_empty() {
-            OpsOutcome::DbEmpty
-        } else {
-            OpsOutcome::DocumentList(list)
+            return match vectors.as_ref() {
+                Some(index) => Ok(OpsOutcome::Neighbours(index.search(query, k))),
+                None => Err(TuringDbError::Bug("HNSW index missing after build".into())),
+            };
+        }
+
+        // The `k` nearest so far, the furthest of them on top
+        let mut nearest: BinaryHeap<Neighbour> = BinaryHeap::with_capacity(k + 1);
+        for (document_name, document) in self.list.documents().await? {
+            if let Some(vector) = Embedding::read(&document.open().await?, space.get_key())? {
+                nearest.push(Neighbour::new(
+                    document_name,
+                    space.get_distance().between(query, &vector),
+                ));
+                if nearest.len() > k {
+                    nearest.pop();
+                }
+            }
         }
+
+        Ok(OpsOutcome::Neighbours(nearest.into_sorted_vec()))
     }
-    /// List all documents in a database sorted alphabetically
-    //TODO Check if uppercase and lowercase and other characters appear sorted
-    pub fn document_list_sorted(db: &Self) -> OpsOutcome {
-        let mut list: Vec<Utf8PathBuf> = Vec::new();
+    /// Bring the secondary indexes up to date with the documents an operation wrote to, by reading their
+    /// indexed fields once it has been applied. Indexes that can not be brought up to date are built again
+    /// the next time a filter uses them
+    pub(crate) async fn record_indexes(&self, op: &LogOp) {
+        let mut indexes = self.indexes.lock().await;
+        if indexes.is_empty() {
+            return;
+        }
 
-        db.list.iter().for_each(|document_name| {
-            list.push(document_name.0.into());
-        });
+        let document_names = match op.documents() {
+            Some(document_names) => document_names,
+            None => {
+                indexes.reset();
+                return;
+            }
+        };
 
-        list.sort();
+        for document_name in document_names {
+            if self
+                .index_document(&mut indexes, document_name)
+                .await
+                .is_err()
+            {
+                indexes.reset();
+                return;
+            }
+        }
+    }
 
-        if list.is_empty() {
-            OpsOutcome::DbEmpty
-        } else {
-            OpsOutcome::DocumentList(list)
+    async fn index_document(
+        &self,
+        indexes: &mut Indexes,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            return indexes.record(document_name, None);
         }
+
+        indexes.record(document_name, Some(&self.document(document_name).await?))
     }
-    /// Create a new document
-    pub(crate) async fn document_create(
-        &mut self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
+    /// Bring the full-text index up to date with the documents an operation wrote to, by reading their
+    /// indexed fields once it has been applied. An index that can not be brought up to date is built again
+    /// the next time the database is searched
+    pub(crate) async fn record_text(&self, op: &LogOp) {
+        let mut text = self.text.lock().await;
+        let held = match text.as_mut() {
+            Some(held) if held.is_built() => held,
+            _ => return,
+        };
+
+        let document_names = match op.documents() {
+            Some(document_names) => document_names,
+            None => {
+                held.reset();
+                return;
+            }
+        };
+
+        for document_name in document_names {
+            if self.text_document(held, document_name).await.is_err() {
+                held.reset();
+                return;
+            }
+        }
+    }
+
+    async fn text_document(
+        &self,
+        text: &mut TextIndex,
         document_name: &Utf8Path,
-    ) -> TuringResult<OpsOutcome> {
-        match self.list.get(document_name) {
-            Some(_) => Err(TuringDbError::AlreadyExists),
+    ) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            return text.record(document_name, None);
+        }
+
+        text.record(document_name, Some(&self.document(document_name).await?))
+    }
+    /// Index the text fields of the documents as `definition` says, `None` drops the full-text index.
+    /// The index is built in the background without holding up writes
+    pub(crate) async fn set_text_index(
+        &self,
+        definition: Option<TextIndexDefinition>,
+    ) -> OpsOutcome {
+        *self.text.lock().await = definition.map(|definition| {
+            let mut text = TextIndex::new(definition);
+            text.build_in_background();
+
+            text
+        });
+
+        OpsOutcome::DbTextIndexSet
+    }
+
+    pub(crate) async fn text_index(&self) -> Option<TextIndexDefinition> {
+        self.text
+            .lock()
+            .await
+            .as_ref()
+            .map(|text| text.definition().clone())
+    }
+    /// Rank the documents holding any term of `text` by how well they match it, best first, allowing up to
+    /// `max_edits` misspelled characters in each term. The full-text index is built first if it has not been yet
+    pub(crate) async fn search(&self, text: &str, max_edits: usize) -> TuringResult<OpsOutcome> {
+        let mut index = self.text.lock().await;
+        let index = match index.as_mut() {
+            None => return Err(TuringDbError::IndexNotFound),
+            Some(index) => index,
+        };
+
+        // While the index is built again in the background the documents are scanned instead
+        if index.is_rebuilding() {
+            let mut scanned = TextIndex::new(index.definition().clone());
+            scanned.build(self.list.documents().await?).await?;
+
+            return Ok(OpsOutcome::SearchHits(scanned.search(text, max_edits)?));
+        }
+
+        if !index.is_built() {
+            index.build(self.list.documents().await?).await?;
+            // The built index is persisted along with the database
+            self.mark_dirty();
+        }
+
+        Ok(OpsOutcome::SearchHits(index.search(text, max_edits)?))
+    }
+    /// Bring the TTL index up to date with the documents an operation wrote to, by reading their
+    /// timestamp field once it has been applied. An index that can not be brought up to date is read
+    /// again from every document the next time the reaper runs
+    pub(crate) async fn record_ttl(&self, op: &LogOp) {
+        let mut ttl = self.ttl.lock().await;
+        let held = match ttl.as_mut() {
+            Some(held) if held.is_built() => held,
+            _ => return,
+        };
+
+        let document_names = match op.documents() {
+            Some(document_names) => document_names,
             None => {
-                let path = TuringDB::build_document_path(repo_dir, db_name, document_name);
+                held.reset();
+                return;
+            }
+        };
 
-                let document = sled::Config::default()
-                    .create_new(false)
-                    .path(&path)
-                    .open()?;
+        for document_name in document_names {
+            if self.ttl_document(held, document_name).await.is_err() {
+                held.reset();
+                return;
+            }
+        }
+    }
 
-                self.list.insert(document_name.to_path_buf(), document);
+    async fn ttl_document(&self, ttl: &mut TtlIndex, document_name: &Utf8Path) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            return ttl.record(document_name, None);
+        }
+
+        ttl.record(document_name, Some(&self.document(document_name).await?))
+    }
+    /// Expire the documents `expire_after` past the time their field `key` holds, `None` drops the
+    /// TTL index. Changing only how long after that time they expire keeps the documents indexed
+    /// and takes effect the next time the reaper runs
+    pub(crate) async fn set_ttl_index(&self, index: Option<(Vec<u8>, Duration)>) -> OpsOutcome {
+        let mut ttl = self.ttl.lock().await;
 
-                Ok(OpsOutcome::DocumentCreated)
+        match (ttl.as_mut(), index) {
+            (Some(held), Some((key, expire_after))) if held.key() == key.as_slice() => {
+                held.set_expire_after(expire_after)
+            }
+            (_, index) => {
+                *ttl = index.map(|(key, expire_after)| TtlIndex::new(&key, expire_after));
             }
         }
+
+        OpsOutcome::DbTtlIndexSet
     }
-    /// Drop a document
-    pub(crate) async fn document_drop(
-        &mut self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
-        document_name: &Utf8Path,
-    ) -> TuringResult<OpsOutcome> {
-        let path = TuringDB::build_document_path(repo_dir, db_name, document_name);
 
-        async_fs::remove_dir_all(path).await?;
+    pub(crate) async fn ttl_index(&self) -> Option<(Vec<u8>, Duration)> {
+        self.ttl
+            .lock()
+            .await
+            .as_ref()
+            .map(|ttl| (ttl.key().to_vec(), ttl.expire_after()))
+    }
+    /// The documents whose timestamp field is at least the TTL before `now`. The TTL index is read
+    /// from every document first if it is not held
+    pub(crate) async fn ttl_expired(&self, now: TAI64N) -> TuringResult<Vec<Utf8PathBuf>> {
+        let mut ttl = self.ttl.lock().await;
+        let ttl = match ttl.as_mut() {
+            None => return Ok(Vec::new()),
+            Some(ttl) => ttl,
+        };
 
-        self.list.remove(document_name);
+        if !ttl.is_built() {
+            ttl.build(self.list.documents().await?).await?;
+            // The built index is persisted along with the database
+            self.mark_dirty();
+        }
 
-        Ok(OpsOutcome::DocumentDropped)
+        Ok(ttl.expired(now))
     }
-    /// Field Insert
-    pub(crate) async fn field_set(
-        &mut self,
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
+    /// Bring the prefix index up to date with the documents an operation wrote to, by reading their
+    /// text fields once it has been applied. An index that can not be brought up to date is read
+    /// again from every document the next time suggestions are asked for
+    pub(crate) async fn record_prefix(&self, op: &LogOp) {
+        let mut prefix = self.prefix.lock().await;
+        let held = match prefix.as_mut() {
+            Some(held) if held.is_built() => held,
+            _ => return,
+        };
+
+        let document_names = match op.documents() {
+            Some(document_names) => document_names,
+            None => {
+                held.reset();
+                return;
+            }
+        };
+
+        for document_name in document_names {
+            if self.prefix_document(held, document_name).await.is_err() {
+                held.reset();
+                return;
+            }
+        }
+    }
+
+    async fn prefix_document(
+        &self,
+        prefix: &mut PrefixIndex,
         document_name: &Utf8Path,
-        key: IVec,
-        value: IVec,
+    ) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            return prefix.record(document_name, None);
+        }
+
+        prefix.record(document_name, Some(&self.document(document_name).await?))
+    }
+    /// Suggest the values of the text fields `keys` as they are typed, no fields drops the prefix index.
+    /// The text held is kept when the fields do not change
+    pub(crate) async fn set_prefix_index(&self, keys: BTreeSet<Vec<u8>>) -> OpsOutcome {
+        let mut prefix = self.prefix.lock().await;
+
+        match prefix.as_ref() {
+            Some(held) if *held.keys() == keys => (),
+            _ if keys.is_empty() => *prefix = None,
+            _ => *prefix = Some(PrefixIndex::new(keys)),
+        }
+
+        OpsOutcome::DbPrefixIndexSet
+    }
+
+    pub(crate) async fn prefix_index(&self) -> BTreeSet<Vec<u8>> {
+        self.prefix
+            .lock()
+            .await
+            .as_ref()
+            .map(|prefix| prefix.keys().clone())
+            .unwrap_or_default()
+    }
+    /// At most `limit` distinct values of the field `key` starting with `prefix` ignoring case, in the order
+    /// of their text. The prefix index is read from every document first if it is not held. Fails with
+    /// `IndexNotFound` unless the field is in the prefix index
+    pub(crate) async fn suggest(
+        &self,
+        key: &[u8],
+        prefix: &str,
+        limit: usize,
     ) -> TuringResult<OpsOutcome> {
-        match self.list.get(&document_name.to_path_buf()) {
-            None => Err(TuringDbError::DocumentNotFound),
-            Some(sled_db) => {
-                if !sled_db.contains_key(&key)? {
-                    sled_db.insert(key, value)?;
+        let mut index = self.prefix.lock().await;
+        let index = match index.as_mut() {
+            Some(index) if index.keys().contains(key) => index,
+            _ => return Err(TuringDbError::IndexNotFound),
+        };
 
-                    Ok(OpsOutcome::FieldInserted)
-                } else {
-                    Err(TuringDbError::KeyAlreadyExists)
-                }
-            }
+        if !index.is_built() {
+            index.build(self.list.documents().await?).await?;
+            // The built index is persisted along with the database
+            self.mark_dirty();
         }
+
+        Ok(OpsOutcome::Suggestions(index.suggest(key, prefix, limit)))
     }
+    /// Build the indexes waiting to be built in the background, those just created and those whose entries
+    /// were lost when the database was loaded, returning whether there were any. Each index is built from
+    /// the documents listed when it starts without holding up writes, the documents written meanwhile
+    /// are read again once it is built and it is then made live at once. An index that fails to build
+    /// is left to be built the next time a query uses it
+    pub(crate) async fn rebuild_indexes(&self) -> TuringResult<bool> {
+        let rebuilding = self.indexes.lock().await.rebuilding();
+        let rebuilt = !rebuilding.is_empty();
 
-    fn build_path(repo_dir: &Utf8Path, db_name: &Utf8Path) -> Utf8PathBuf {
-        let mut path: Utf8PathBuf = repo_dir.into();
-        path.push(db_name);
+        for (keys, declaration) in rebuilding {
+            let built = match self.list.documents().await {
+                Ok(documents) => FieldIndex::build(&keys, &declaration, documents).await,
+                Err(error) => Err(error),
+            };
 
-        path
+            let mut indexes = self.indexes.lock().await;
+            // The index was dropped or its entries reset while it was built
+            let written = match indexes.take_rebuilding(&keys) {
+                None => continue,
+                Some(written) => written,
+            };
+
+            let mut index = built?;
+            for document_name in written {
+                let sled_db = match self.list.contains(&document_name).await? {
+                    false => None,
+                    true => Some(self.document(&document_name).await?),
+                };
+                index.record(&keys, &document_name, sled_db.as_ref())?;
+            }
+            indexes.set_built(&keys, index);
+            self.mark_dirty();
+        }
+
+        Ok(self.rebuild_text().await? || rebuilt)
     }
 
-    fn build_document_path(
-        repo_dir: &Utf8Path,
-        db_name: &Utf8Path,
-        document_name: &Utf8Path,
-    ) -> Utf8PathBuf {
-        let mut path: Utf8PathBuf = repo_dir.into();
-        path.push(db_name);
-        path.push(document_name);
+    async fn rebuild_text(&self) -> TuringResult<bool> {
+        let mut rebuilt = match self.text.lock().await.as_ref() {
+            Some(text) if text.is_rebuilding() => TextIndex::new(text.definition().clone()),
+            _ => return Ok(false),
+        };
+        let built = match self.list.documents().await {
+            Ok(documents) => rebuilt.build(documents).await,
+            Err(error) => Err(error),
+        };
 
-        path
+        let mut text = self.text.lock().await;
+        // The index was dropped, defined again or its postings reset while it was built
+        let held = match text.as_mut() {
+            Some(held) if held.definition() == rebuilt.definition() => held,
+            _ => return Ok(true),
+        };
+        let written = match held.take_rebuilding() {
+            None => return Ok(true),
+            Some(written) => written,
+        };
+
+        built?;
+        for document_name in written {
+            self.text_document(&mut rebuilt, &document_name).await?;
+        }
+        *held = rebuilt;
+        self.mark_dirty();
+
+        Ok(true)
+    }
+    /// Make `key` the unique key of the database, reading the field from every document.
+    /// Fails with `AlreadyExists` if two documents already hold the same value. `None` drops the unique key
+    pub(crate) async fn set_unique_key(&self, key: Option<&[u8]>) -> TuringResult<OpsOutcome> {
+        let unique = match key {
+            None => None,
+            Some(key) => Some(UniqueKey::build(key, self.list.documents().await?).await?),
+        };
+        *self.unique.lock().await = unique;
+
+        Ok(OpsOutcome::DbUniqueKeySet)
+    }
+
+    pub(crate) async fn unique_key(&self) -> Option<Vec<u8>> {
+        self.unique
+            .lock()
+            .await
+            .as_ref()
+            .map(|unique| unique.key().to_vec())
+    }
+    /// The document holding `value` in its unique key
+    pub(crate) async fn unique_owner(&self, value: &Value) -> TuringResult<Utf8PathBuf> {
+        match self.unique.lock().await.as_ref() {
+            None => Err(TuringDbError::UniqueKeyNotSet),
+            Some(unique) => match unique.owner(value) {
+                None => Err(TuringDbError::DocumentNotFound),
+                Some(document_name) => Ok(document_name.to_path_buf()),
+            },
+        }
+    }
+    /// Check that an operation leaves no two documents holding the same value of the unique key.
+    /// It has to be applied before another write to the database is checked
+    pub(crate) async fn unique_check(
+        &self,
+        repo_dir: &Utf8Path,
+        op: &LogOp,
+        quarantine: &Quarantine,
+    ) -> TuringResult<()> {
+        let unique = self.unique.lock().await;
+        let unique = match unique.as_ref() {
+            None => return Ok(()),
+            Some(unique) => unique,
+        };
+
+        let mut written = BTreeMap::new();
+        match op {
+            LogOp::WriteBatch { db, ops } => {
+                for write_op in ops {
+                    self.unique_written(
+                        &mut written,
+                        unique.key(),
+                        repo_dir,
+                        &write_op.log_op(db),
+                        quarantine,
+                    )
+                    .await?;
+                }
+            }
+            op => {
+                self.unique_written(&mut written, unique.key(), repo_dir, op, quarantine)
+                    .await?
+            }
+        }
+
+        unique.check(
+            &written
+                .into_iter()
+                .map(|(document_name, field_data)| {
+                    (document_name, field_data.as_ref().map(Value::from_field))
+                })
+                .collect(),
+        )
+    }
+    /// Work out the field `key` an operation leaves a document holding, on top of the fields
+    /// the writes before it in the same batch left in `written`
+    async fn unique_written(
+        &self,
+        written: &mut BTreeMap<Utf8PathBuf, Option<FieldData>>,
+        key: &[u8],
+        repo_dir: &Utf8Path,
+        op: &LogOp,
+        quarantine: &Quarantine,
+    ) -> TuringResult<()> {
+        let time = TAI64N::now();
+        let cell =
+            |value: &TDBCell| FieldData::new_at(value.get_data_type(), value.get_data(), time);
+
+        let (document_name, field_data) = match op {
+            LogOp::DocumentCreate { document, .. } | LogOp::DocumentDrop { document, .. } => {
+                (document, None)
+            }
+            LogOp::FieldInsert {
+                document,
+                key: written_key,
+                value,
+                ..
+            }
+            | LogOp::FieldModify {
+                document,
+                key: written_key,
+                value,
+                ..
+            } if written_key.as_slice() == key => (document, Some(cell(value))),
+            LogOp::FieldRemove {
+                document,
+                key: removed,
+                ..
+            } if removed.as_slice() == key => (document, None),
+            LogOp::FieldMerge {
+                document,
+                key: merged,
+                value,
+                ..
+            } if merged.as_slice() == key => (document, value.as_ref().map(cell)),
+            // The value of a stream is never held in memory so it can not be checked
+            LogOp::FieldInsertStream { key: streamed, .. } if streamed.as_slice() == key => {
+                return Err(TuringDbError::StreamedField)
+            }
+            LogOp::DocumentUpsert {
+                document, fields, ..
+            }
+            | LogOp::DocumentUpdateIf {
+                document, fields, ..
+            } => (
+                document,
+                fields
+                    .iter()
+                    .find(|(field_key, _)| field_key.as_slice() == key)
+                    .map(|(_, value)| cell(value)),
+            ),
+            LogOp::DocumentPatch {
+                document, patch, ..
+            }
+            | LogOp::DocumentPatchIf {
+                document, patch, ..
+            } => {
+                let mut field_data = self.unique_previous(written, document, key).await?;
+                for patch_op in patch.get_ops() {
+                    if patch_op.key() == key {
+                        field_data = patch_op.apply(field_data, time)?;
+                    }
+                }
+
+                (document, field_data)
+            }
+            LogOp::DocumentIncrement {
+                document,
+                key: incremented,
+                by,
+                ..
+            } if incremented.as_slice() == key => {
+                let previous = self.unique_previous(written, document, key).await?;
+                let increment = PatchOp::Increment {
+                    key: key.into(),
+                    by: by.clone(),
+                };
+
+                (document, increment.apply(previous, time)?)
+            }
+            LogOp::DocumentRestore { db, document } => {
+                let db_dir = TuringDB::build_path(repo_dir, db);
+                let snapshot = self
+                    .trash
+                    .lock()
+                    .await
+                    .archive(&db_dir, document, quarantine)
+                    .await?
+                    .snapshot()?;
+
+                let field_data = match snapshot.field(key) {
+                    None => None,
+                    Some(stored) => Some(TuringDB::decode_field(stored)?),
+                };
+
+                (document, field_data)
+            }
+            _ => return Ok(()),
+        };
+
+        written.insert(document_name.clone(), field_data);
+
+        Ok(())
+    }
+    /// The field `key` of a document before an operation writes to it
+    async fn unique_previous(
+        &self,
+        written: &BTreeMap<Utf8PathBuf, Option<FieldData>>,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<Option<FieldData>> {
+        if let Some(field_data) = written.get(document_name) {
+            return Ok(field_data.clone());
+        }
+        if !self.list.contains(document_name).await? {
+            return Ok(None);
+        }
+
+        self.document(document_name).await?.read_field(key)
+    }
+    /// Record the values of the unique key the documents an operation wrote to hold once it has been applied
+    pub(crate) async fn record_unique(&self, op: &LogOp) {
+        let mut unique = self.unique.lock().await;
+        let held = match unique.as_mut() {
+            None => return,
+            Some(held) => held,
+        };
+
+        let recorded = match op.documents() {
+            Some(document_names) => {
+                let mut recorded = Ok(());
+                for document_name in document_names {
+                    recorded = self.unique_document(held, document_name).await;
+                    if recorded.is_err() {
+                        break;
+                    }
+                }
+
+                recorded
+            }
+            None => self.list.names().await.map(|names| {
+                let names = names.into_iter().collect::<BTreeSet<Utf8PathBuf>>();
+                held.retain(|document_name| names.contains(document_name));
+            }),
+        };
+
+        // The unique key is never left out of date, it is read from every document again instead
+        if recorded.is_err() {
+            if let Ok(documents) = self.list.documents().await {
+                if let Ok(rebuilt) = UniqueKey::build(held.key(), documents).await {
+                    *held = rebuilt;
+                }
+            }
+        }
+    }
+
+    async fn unique_document(
+        &self,
+        unique: &mut UniqueKey,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        if !self.list.contains(document_name).await? {
+            unique.record(document_name, None);
+
+            return Ok(());
+        }
+
+        let field_data = self
+            .document(document_name)
+            .await?
+            .read_field(unique.key())?;
+        unique.record(document_name, field_data.as_ref().map(Value::from_field));
+
+        Ok(())
+    }
+    /// Index fields of the documents in the order they are given
+    pub(crate) async fn index_create(
+        &self,
+        keys: &[Vec<u8>],
+        declaration: IndexDeclaration,
+    ) -> TuringResult<OpsOutcome> {
+        self.indexes.lock().await.declare(keys, declaration)?;
+
+        Ok(OpsOutcome::IndexCreated)
+    }
+
+    pub(crate) async fn index_drop(&self, keys: &[Vec<u8>]) -> TuringResult<OpsOutcome> {
+        self.indexes.lock().await.drop_index(keys)?;
+
+        Ok(OpsOutcome::IndexDropped)
+    }
+
+    pub(crate) async fn index_exists(&self, keys: &[Vec<u8>]) -> bool {
+        self.indexes.lock().await.contains(keys)
+    }
+    /// The predicate of the documents a partial index holds, `None` for an index holding every document
+    pub(crate) async fn index_predicate(&self, keys: &[Vec<u8>]) -> TuringResult<Option<Filter>> {
+        match self.indexes.lock().await.declaration(keys) {
+            None => Err(TuringDbError::IndexNotFound),
+            Some(declaration) => Ok(declaration.get_predicate().cloned()),
+        }
+    }
+    /// List the fields and the kind of each index, the indexes in the order of the names of their fields
+    pub(crate) async fn index_list(&self) -> OpsOutcome {
+        OpsOutcome::IndexList(self.indexes.lock().await.list())
+    }
+    /// How each index is declared and how many queries and aggregations it narrowed down the documents of
+    pub(crate) async fn index_stats(&self) -> OpsOutcome {
+        OpsOutcome::IndexStats(self.indexes.lock().await.stats())
+    }
+    /// The writes that create the database `db_name` again as it is now, with its settings,
+    /// indexes and the fields and expiry of its documents. The trash and the history of the
+    /// documents are not kept
+    pub(crate) async fn creation_ops(&self, db_name: &Utf8Path) -> TuringResult<Vec<LogOp>> {
+        let db = db_name.to_path_buf();
+        let db_meta = self.meta.lock().await.clone();
+
+        let mut ops = vec![
+            match self.list.partitioning() {
+                Partitioning::Flat => LogOp::DbCreate {
+                    db: db.clone(),
+                    compression: db_meta.compression(),
+                },
+                partitioning => LogOp::DbCreatePartitioned {
+                    db: db.clone(),
+                    compression: db_meta.compression(),
+                    partitioning,
+                },
+            },
+            LogOp::DbSetStructure {
+                db: db.clone(),
+                structure: self.structure.lock().await.clone(),
+            },
+            LogOp::DbSetCollation {
+                db: db.clone(),
+                collation: *self.collation.lock().await,
+            },
+        ];
+        if let Some(retention) = self.trash_retention().await {
+            ops.push(LogOp::DbSetTrash {
+                db: db.clone(),
+                retention: Some(retention),
+            });
+        }
+        for (keys, declaration) in self.indexes.lock().await.declarations() {
+            ops.push(match declaration.get_predicate() {
+                None => LogOp::CompoundIndexCreate {
+                    db: db.clone(),
+                    keys,
+                    kind: declaration.get_kind(),
+                },
+                Some(predicate) => LogOp::PartialIndexCreate {
+                    db: db.clone(),
+                    keys,
+                    kind: declaration.get_kind(),
+                    predicate: predicate.clone(),
+                },
+            });
+        }
+        if let Some(key) = self.unique_key().await {
+            ops.push(LogOp::DbSetUniqueKey {
+                db: db.clone(),
+                key: Some(key),
+            });
+        }
+        if let Some(definition) = self.text_index().await {
+            ops.push(LogOp::DbSetTextIndex {
+                db: db.clone(),
+                definition: Some(definition),
+            });
+        }
+        if let Some(index) = self.ttl_index().await {
+            ops.push(LogOp::DbSetTtlIndex {
+                db: db.clone(),
+                index: Some(index),
+            });
+        }
+        let prefix_keys = self.prefix_index().await;
+        if !prefix_keys.is_empty() {
+            ops.push(LogOp::DbSetPrefixIndex {
+                db: db.clone(),
+                keys: prefix_keys,
+            });
+        }
+
+        for document_name in self.list.names().await? {
+            let fields = self.document_view(&document_name).await?.field_scan()?;
+
+            ops.push(LogOp::DocumentUpsert {
+                db: db.clone(),
+                document: document_name.clone(),
+                fields: fields
+                    .into_iter()
+                    .map(|(key, field_data)| {
+                        (key, TDBCell::new(field_data.data_type(), field_data.data()))
+                    })
+                    .collect(),
+            });
+            if let Some(expires_at) = db_meta.expires_at(&document_name) {
+                ops.push(LogOp::DocumentExpire {
+                    db: db.clone(),
+                    document: document_name,
+                    expires_at: Some(expires_at),
+                });
+            }
+        }
+
+        Ok(ops)
+    }
+    /// The documents a filter may match, narrowed down by the secondary indexes when they can be.
+    /// The indexes the filter uses are built first if they have not been yet
+    async fn scan(
+        &self,
+        filter: &Filter,
+        collation: Collation,
+        plan: &mut QueryPlan,
+    ) -> TuringResult<Vec<(Utf8PathBuf, Arc<LazyDocument>)>> {
+        let candidates = {
+            let mut indexes = self.indexes.lock().await;
+            self.build_indexes(&mut indexes, filter, None, collation)
+                .await?;
+
+            let mut used = BTreeSet::new();
+            let candidates = indexes.candidates(filter, collation, &mut used);
+            indexes.hit(&used);
+            plan.set_indexes(used.into_iter().collect(), false);
+
+            candidates
+        };
+
+        let candidates = match candidates {
+            None => {
+                let documents = self.list.documents().await?;
+                plan.set_estimated(documents.len());
+
+                return Ok(documents);
+            }
+            Some(candidates) => candidates,
+        };
+
+        let mut documents = Vec::with_capacity(candidates.len());
+        for document_name in candidates {
+            if let Some(document) = self.list.get(&document_name).await? {
+                documents.push((document_name, document));
+            }
+        }
+        plan.set_estimated(documents.len());
+
+        Ok(documents)
+    }
+    /// The documents a sorted query may match in the order it sorts them by, when an index holds them in
+    /// that order. The indexes the query uses are built first if they have not been yet
+    async fn scan_sorted(
+        &self,
+        query: &Query,
+        plan: &mut QueryPlan,
+    ) -> TuringResult<Option<Vec<(Utf8PathBuf, Arc<LazyDocument>)>>> {
+        let (key, order) = match query.get_order() {
+            None => return Ok(None),
+            Some(order) => order,
+        };
+
+        let sorted = {
+            let mut indexes = self.indexes.lock().await;
+            let collation = query.get_collation().unwrap_or_default();
+            self.build_indexes(&mut indexes, query.get_filter(), Some(key), collation)
+                .await?;
+
+            let mut used = BTreeSet::new();
+            let sorted = indexes.sorted(query.get_filter(), key, order, collation, &mut used);
+            indexes.hit(&used);
+            plan.set_indexes(used.into_iter().collect(), true);
+
+            sorted
+        };
+
+        let (document_names, exhaustive) = match sorted {
+            None => return Ok(None),
+            Some(sorted) => sorted,
+        };
+
+        let mut documents = Vec::with_capacity(document_names.len());
+        for document_name in document_names.iter() {
+            if let Some(document) = self.list.get(document_name).await? {
+                documents.push((document_name.clone(), document));
+            }
+        }
+
+        // The documents the index does not hold do not hold the field and sort last
+        if !exhaustive {
+            let held = document_names
+                .into_iter()
+                .collect::<BTreeSet<Utf8PathBuf>>();
+            let mut missing = self
+                .list
+                .documents()
+                .await?
+                .into_iter()
+                .filter(|(document_name, _)| !held.contains(document_name))
+                .collect::<Vec<(Utf8PathBuf, Arc<LazyDocument>)>>();
+            missing.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+            documents.append(&mut missing);
+        }
+        plan.set_estimated(documents.len());
+
+        Ok(Some(documents))
+    }
+    /// Build the indexes over any field a filter reads or a query is sorted by that have not been built yet
+    async fn build_indexes(
+        &self,
+        indexes: &mut Indexes,
+        filter: &Filter,
+        order: Option<&[u8]>,
+        collation: Collation,
+    ) -> TuringResult<()> {
+        let unbuilt = indexes.unbuilt(filter, order, collation);
+        if unbuilt.is_empty() {
+            return Ok(());
+        }
+
+        let documents = self.list.documents().await?;
+        for (keys, declaration) in unbuilt {
+            let index = FieldIndex::build(&keys, &declaration, documents.clone()).await?;
+            indexes.set_built(&keys, index);
+        }
+        // The built indexes are persisted along with the database
+        self.mark_dirty();
+
+        Ok(())
+    }
+    /// Record in the time index the writes an operation made at `time`
+    pub(crate) async fn record_times(&self, op: &LogOp, time: TAI64N) {
+        self.times.lock().await.record(op, time);
+    }
+    /// Mark the documents an operation wrote to, they are hashed again when the Merkle tree is next read
+    pub(crate) async fn record_merkle(&self, op: &LogOp) {
+        self.merkle.lock().await.record(op);
+    }
+
+    pub(crate) async fn merkle_tree(&self) -> TuringResult<MerkleTree> {
+        self.merkle.lock().await.tree(&self.list).await
+    }
+
+    pub(crate) async fn merkle_leaf(&self, leaf: usize) -> TuringResult<Vec<(Utf8PathBuf, u64)>> {
+        self.merkle.lock().await.leaf(&self.list, leaf).await
+    }
+    /// Build the time index again by opening every document, for a database written before it was kept
+    pub(crate) async fn rebuild_times(&self) -> TuringResult<()> {
+        let times = TimeIndex::build(self.list.documents().await?).await?;
+        *self.times.lock().await = times;
+
+        Ok(())
+    }
+    /// List the documents created or modified from `from` up to but not including `to`, oldest first.
+    /// Only the time index is read, no document is opened
+    pub(crate) async fn documents_between(
+        &self,
+        field: TimeField,
+        from: TAI64N,
+        to: TAI64N,
+    ) -> TuringResult<OpsOutcome> {
+        let in_range = self.times.lock().await.range(field, from, to);
+
+        // Documents dropped along with their partition are only left out here
+        let mut documents = Vec::with_capacity(in_range.len());
+        for (document_name, document_times) in in_range {
+            if self.list.contains(&document_name).await? {
+                documents.push((document_name, document_times));
+            }
+        }
+
+        Ok(OpsOutcome::DocumentTimes(documents))
+    }
+    /// Mark the database as changed since it was last committed
+    pub(crate) fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+    /// Check whether the database has changed since it was last committed, clearing the flag
+    pub(crate) fn take_dirty(&self) -> bool {
+        self.dirty.swap(false, Ordering::SeqCst)
+    }
+
+    /// Create a database
+    pub(crate) async fn db_create(
+        self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+    ) -> Result<OpsOutcome, TuringDbError> {
+        let path = Self::build_path(repo_dir, db_name);
+        DirBuilder::new().recursive(false).create(path).await?;
+
+        Ok(OpsOutcome::DbCreated)
+    }
+
+    /// Drop the database
+    pub(crate) async fn db_drop(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+    ) -> Result<OpsOutcome, TuringDbError> {
+        let path = Self::build_path(repo_dir, db_name);
+        async_fs::remove_dir_all(path).await?;
+
+        Ok(OpsOutcome::DbDropped)
+    }
+    /// List all the documents in the repo
+    pub async fn document_list(db: &Self) -> TuringResult<OpsOutcome> {
+        let list = db.list.names().await?;
+
+        if list.is_empty() {
+            Ok(OpsOutcome::DbEmpty)
+        } else {
+            Ok(OpsOutcome::DocumentList(list))
+        }
+    }
+    /// List all documents in a database sorted alphabetically
+    //TODO Check if uppercase and lowercase and other characters appear sorted
+    pub async fn document_list_sorted(db: &Self) -> TuringResult<OpsOutcome> {
+        let mut list = db.list.names().await?;
+
+        list.sort();
+
+        if list.is_empty() {
+            Ok(OpsOutcome::DbEmpty)
+        } else {
+            Ok(OpsOutcome::DocumentList(list))
+        }
+    }
+    /// Create a new document
+    pub(crate) async fn document_create(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        quarantine: &Arc<Quarantine>,
+    ) -> TuringResult<OpsOutcome> {
+        if self.list.contains(document_name).await? {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        let path = self.document_path(repo_dir, db_name, document_name);
+
+        let document = if self.ephemeral {
+            sled::Config::default().temporary(true).open()?
+        } else {
+            // sled creates the partition directory along with the document
+            sled::Config::default()
+                .create_new(false)
+                .path(&path)
+                .open()?
+        };
+
+        if !self
+            .list
+            .insert(
+                document_name,
+                LazyDocument::opened(path, document, Arc::clone(quarantine)),
+            )
+            .await?
+        {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        Ok(OpsOutcome::DocumentCreated)
+    }
+    /// Drop a document, moving it into the trash at `time` when the database soft deletes.
+    /// A document in the cold tier goes into the trash as it was archived
+    pub(crate) async fn document_drop(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        time: TAI64N,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+
+        if self.trash_retention().await.is_some() && !self.ephemeral {
+            let cold_document = match document.contents().await? {
+                DocumentContents::Cold(cold_document) => cold_document,
+                DocumentContents::Hot(sled_db) => ColdDocument::capture(
+                    &sled_db,
+                    History::last_written(&sled_db)?.unwrap_or(time),
+                )?,
+            };
+
+            self.trash
+                .lock()
+                .await
+                .put(
+                    &TuringDB::build_path(repo_dir, db_name),
+                    document_name,
+                    &cold_document,
+                    time,
+                )
+                .await?;
+        }
+
+        if !self.ephemeral {
+            document.remove_files().await?;
+        }
+
+        if self.list.remove(document_name).await?.is_none() {
+            return Err(TuringDbError::DocumentNotFound);
+        }
+        self.meta.lock().await.set_expiry(document_name, None);
+        self.usage.invalidate();
+
+        Ok(OpsOutcome::DocumentDropped)
+    }
+    /// Keep the documents dropped from the database in its trash for `retention`, or remove them outright with `None`
+    pub(crate) async fn set_trash_retention(&self, retention: Option<Duration>) {
+        self.trash.lock().await.set_retention(retention);
+    }
+    /// How long dropped documents are kept in the trash of the database
+    pub(crate) async fn trash_retention(&self) -> Option<Duration> {
+        self.trash.lock().await.retention()
+    }
+    /// The documents in the trash along with when they were dropped, oldest first
+    pub(crate) async fn trash_list(&self) -> OpsOutcome {
+        OpsOutcome::TrashList(self.trash.lock().await.documents())
+    }
+    /// Check whether a document can be taken out of the trash
+    pub(crate) async fn restore_check(&self, document_name: &Utf8Path) -> TuringResult<()> {
+        if !self.trash.lock().await.contains(document_name) {
+            return Err(TuringDbError::DocumentNotFound);
+        }
+        if self.list.contains(document_name).await? {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        Ok(())
+    }
+    /// Take a document out of the trash, restoring it next to where it belongs then moving it into place.
+    /// It is only removed from the trash once it is back in the database
+    pub(crate) async fn document_restore(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        quarantine: &Arc<Quarantine>,
+    ) -> TuringResult<OpsOutcome> {
+        self.restore_check(document_name).await?;
+
+        let db_dir = TuringDB::build_path(repo_dir, db_name);
+        let cold_document = self
+            .trash
+            .lock()
+            .await
+            .archive(&db_dir, document_name, quarantine)
+            .await?;
+
+        let path = self.document_path(repo_dir, db_name, document_name);
+        let restoring = ColdDocument::staging_path(&path, REHYDRATING_EXTENSION);
+        if let Err(error) = async_fs::remove_dir_all(&restoring).await {
+            if error.kind() != ErrorKind::NotFound {
+                return Err(error.into());
+            }
+        }
+
+        {
+            let staged = sled::Config::default()
+                .path(&restoring)
+                .create_new(true)
+                .open()?;
+            cold_document.snapshot()?.restore_into(&staged)?;
+            staged.flush_async().await?;
+        }
+
+        async_fs::rename(&restoring, &path).await?;
+        let document = sled::Config::default()
+            .path(&path)
+            .create_new(false)
+            .open()?;
+
+        if !self
+            .list
+            .insert(
+                document_name,
+                LazyDocument::opened(path, document, Arc::clone(quarantine)),
+            )
+            .await?
+        {
+            return Err(TuringDbError::AlreadyExists);
+        }
+        self.trash
+            .lock()
+            .await
+            .purge(&db_dir, document_name)
+            .await?;
+        self.usage.invalidate();
+
+        Ok(OpsOutcome::DocumentRestored)
+    }
+    /// Purge the documents that were moved into the trash at or before `before`
+    pub(crate) async fn trash_purge(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        before: TAI64N,
+    ) -> TuringResult<OpsOutcome> {
+        let db_dir = TuringDB::build_path(repo_dir, db_name);
+
+        let mut trash = self.trash.lock().await;
+        let documents = trash.trashed_before(before);
+        for document_name in documents.iter() {
+            trash.purge(&db_dir, document_name).await?;
+        }
+
+        Ok(OpsOutcome::TrashPurged { documents })
+    }
+    /// Check whether any document has been in the trash since `before`
+    pub(crate) async fn has_trashed_before(&self, before: TAI64N) -> bool {
+        !self.trash.lock().await.trashed_before(before).is_empty()
+    }
+    /// Drop every document in a partition by removing the directory of the partition,
+    /// returning the names of the documents that were dropped
+    pub(crate) async fn partition_drop(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        partition: u16,
+    ) -> TuringResult<OpsOutcome> {
+        let partitioning = self.list.partitioning();
+
+        // The only partition of a flat database is the database directory itself
+        if partitioning == Partitioning::Flat || partition >= partitioning.partitions() {
+            return Err(TuringDbError::PartitionNotFound);
+        }
+
+        // Removing the documents from the index closes them before their directory is removed
+        let documents = self.list.clear_partition(partition).await?;
+
+        if !self.ephemeral {
+            let path =
+                partitioning.partition_dir(&TuringDB::build_path(repo_dir, db_name), partition);
+
+            if let Err(error) = async_fs::remove_dir_all(path).await {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+            }
+        }
+
+        let mut meta = self.meta.lock().await;
+        for document_name in documents.iter() {
+            meta.set_expiry(document_name, None);
+        }
+        drop(meta);
+        self.usage.invalidate();
+
+        Ok(OpsOutcome::PartitionDropped { documents })
+    }
+    /// Set or clear the time after which a document expires
+    pub(crate) async fn document_expire(
+        &self,
+        document_name: &Utf8Path,
+        expires_at: Option<TAI64N>,
+    ) -> TuringResult<OpsOutcome> {
+        if !self.list.contains(document_name).await? {
+            return Err(TuringDbError::DocumentNotFound);
+        }
+
+        self.meta.lock().await.set_expiry(document_name, expires_at);
+
+        Ok(OpsOutcome::DocumentExpirySet)
+    }
+    /// Insert a field, failing if the key already exists
+    pub(crate) async fn field_insert(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &TDBCell,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+
+        self.write_revision(
+            &document,
+            key,
+            time,
+            history_depth,
+            |previous| match previous {
+                Some(_) => Err(TuringDbError::KeyAlreadyExists),
+                None => Ok(Some(FieldData::new_at(
+                    value.get_data_type(),
+                    value.get_data(),
+                    time,
+                ))),
+            },
+        )
+        .await?;
+
+        Ok(OpsOutcome::FieldInserted)
+    }
+    /// Apply the operations of a patch to a document in one transaction, each changed field
+    /// is written as its own revision. A patch replayed from the ops log after it already
+    /// reached the document is not applied again, since an increment or a push is not idempotent
+    pub(crate) async fn document_patch(
+        &self,
+        document_name: &Utf8Path,
+        patch: &Patch,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+
+        if History::written_at(&document.open().await?, time)? {
+            return Ok(OpsOutcome::DocumentPatched);
+        }
+
+        let ops = patch.get_ops();
+
+        self.write_revisions(
+            &document,
+            &patch.keys(),
+            time,
+            history_depth,
+            None,
+            |index, previous| ops[index].apply(previous, time),
+        )
+        .await?;
+
+        Ok(OpsOutcome::DocumentPatched)
+    }
+    /// Add `by` to a numeric field in one transaction and return the value it holds afterwards,
+    /// a missing field is created holding `by`. The increment is not applied again when it is
+    /// replayed from the ops log after it already reached the document
+    pub(crate) async fn document_increment(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        by: &Value,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+
+        if History::written_at(&sled_db, time)? {
+            return match sled_db.get(key)? {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(stored) => Ok(OpsOutcome::Incremented(Value::from_field(
+                    &TuringDB::decode_field(&stored)?,
+                ))),
+            };
+        }
+
+        let op = PatchOp::Increment {
+            key: key.into(),
+            by: by.clone(),
+        };
+        // The transaction may run the update more than once, the last run is the one committed
+        let counter = std::sync::Mutex::new(None);
+
+        self.write_revision(&document, key, time, history_depth, |previous| {
+            let current = op.apply(previous, time)?;
+            if let Ok(mut counter) = counter.lock() {
+                *counter = current.clone();
+            }
+
+            Ok(current)
+        })
+        .await?;
+
+        match counter.into_inner() {
+            Ok(Some(field_data)) => Ok(OpsOutcome::Incremented(Value::from_field(&field_data))),
+            _ => Err(TuringDbError::Bug("Increment wrote no counter".into())),
+        }
+    }
+    /// Write a field the way a writer of a multi-writer cluster stamped it, the write with the
+    /// greatest version is kept. Of a write and the version the field holds that are concurrent,
+    /// the one that loses is kept as a conflict of the field. Merging a write again changes nothing
+    pub(crate) async fn field_merge(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: Option<&TDBCell>,
+        stamp: &Stamp,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+        let versions = sled_db.open_tree(VERSION_TREE)?;
+        let conflicts = sled_db.open_tree(CONFLICT_TREE)?;
+        let version = stamp.version();
+
+        let current = Stamp::read(&sled_db, key)?;
+        match current {
+            Some(current) if current.version() == version => {
+                return Ok(OpsOutcome::FieldMerged);
+            }
+            Some(current) if current.version() > version => {
+                // The write is older than the one the field holds, a conflict unless that one replaced it
+                if current.replaces() != Some(version) {
+                    let value = value.map(|value| {
+                        FieldData::new_at(value.get_data_type(), value.get_data(), time)
+                    });
+                    let conflict = Conflict::new(key, version, value);
+                    conflicts.insert(conflict.tree_key(), conflict.encode()?)?;
+                }
+
+                return Ok(OpsOutcome::FieldMerged);
+            }
+            Some(current) if stamp.replaces() != Some(current.version()) => {
+                let previous = match sled_db.get(key)? {
+                    None => None,
+                    Some(stored) => Some(TuringDB::decode_field(&stored)?),
+                };
+                let conflict = Conflict::new(key, current.version(), previous);
+                conflicts.insert(conflict.tree_key(), conflict.encode()?)?;
+            }
+            _ => (),
+        }
+
+        self.write_revision(&document, key, time, history_depth, |previous| {
+            Ok(value.map(|value| match previous {
+                Some(mut field_data) => {
+                    field_data.update_at(value.get_data_type(), value.get_data(), time);
+
+                    field_data
+                }
+                None => FieldData::new_at(value.get_data_type(), value.get_data(), time),
+            }))
+        })
+        .await?;
+        versions.insert(key, stamp.encode()?)?;
+
+        if stamp.resolves() {
+            for entry in conflicts.scan_prefix(key) {
+                let (tree_key, stored) = entry?;
+                let conflict = Conflict::decode(&stored)?;
+                if conflict.key() == key && conflict.version() < version {
+                    conflicts.remove(tree_key)?;
+                }
+            }
+        }
+
+        Ok(OpsOutcome::FieldMerged)
+    }
+    /// The version a field holds along with the versions of it that lost to a concurrent write, oldest first
+    pub(crate) async fn field_conflicts(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+
+        let mut conflicts = Vec::new();
+        for entry in sled_db.open_tree(CONFLICT_TREE)?.scan_prefix(key) {
+            let (_, stored) = entry?;
+            let conflict = Conflict::decode(&stored)?;
+            // A longer key starting with `key` is another field
+            if conflict.key() == key {
+                conflicts.push(conflict);
+            }
+        }
+
+        Ok(OpsOutcome::FieldConflicts {
+            version: Stamp::read(&sled_db, key)?.map(|stamp| stamp.version()),
+            conflicts,
+        })
+    }
+    /// Merge changes into a CRDT field, creating the field when the document does not hold it.
+    /// Merging changes the field already took in writes nothing
+    pub(crate) async fn crdt_merge(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        delta: &Crdt,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let current = Crdt::read(&document.open().await?, key)?;
+
+        let mut state = current.clone().unwrap_or_else(|| delta.clone());
+        state.merge(delta)?;
+        if current.as_ref() == Some(&state) {
+            return Ok(OpsOutcome::CrdtState(state));
+        }
+
+        let stored = state.encode()?;
+        self.write_revision(&document, key, time, history_depth, |previous| {
+            Ok(Some(match previous {
+                Some(mut field_data) => {
+                    field_data.update_at(DataType::BINARY, &stored, time);
+
+                    field_data
+                }
+                None => FieldData::new_at(DataType::BINARY, &stored, time),
+            }))
+        })
+        .await?;
+
+        Ok(OpsOutcome::CrdtState(state))
+    }
+
+    pub(crate) async fn crdt_get(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<OpsOutcome> {
+        match Crdt::read(&self.document(document_name).await?, key)? {
+            None => Err(TuringDbError::FieldNotFound),
+            Some(state) => Ok(OpsOutcome::CrdtState(state)),
+        }
+    }
+    /// Check that a patch applies to the current contents of a document without writing anything,
+    /// so a patch that is bound to fail never reaches the ops log
+    pub(crate) async fn patch_check(
+        &self,
+        document_name: &Utf8Path,
+        patch: &Patch,
+    ) -> TuringResult<()> {
+        let sled_db = self.document(document_name).await?;
+        let streams = sled_db.open_tree(STREAM_TREE)?;
+        let time = TAI64N::now();
+
+        let mut patched: HashMap<&[u8], Option<FieldData>> = HashMap::new();
+
+        for op in patch.get_ops() {
+            if streams.contains_key(op.key())? {
+                return Err(TuringDbError::StreamedField);
+            }
+
+            let previous = match patched.get(op.key()) {
+                Some(field_data) => field_data.clone(),
+                None => match sled_db.get(op.key())? {
+                    None => None,
+                    Some(stored) => Some(TuringDB::decode_field(&stored)?),
+                },
+            };
+
+            patched.insert(op.key(), op.apply(previous, time)?);
+        }
+
+        self.structure.lock().await.check_written(
+            patched
+                .iter()
+                .map(|(key, field_data)| (*key, field_data.as_ref())),
+        )
+    }
+    /// Replace every field of a document with `fields` in one transaction, returning the fields it replaced.
+    /// Fields not in `fields` are removed, streamed fields are left as they are
+    pub(crate) async fn document_replace(
+        &self,
+        document_name: &Utf8Path,
+        fields: &[(Vec<u8>, TDBCell)],
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<Vec<(Vec<u8>, FieldData)>> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+
+        // A write landing between reading the fields and replacing them sends the replace back to read them again
+        loop {
+            let (revision, previous) = History::at_rest(&sled_db, |_| {
+                let mut previous = Vec::new();
+                for entry in sled_db.iter() {
+                    let (key, stored) = entry?;
+                    previous.push((key.to_vec(), TuringDB::decode_field(&stored)?));
+                }
+
+                Ok(previous)
+            })?;
+
+            let outcome = self
+                .replace_fields(&document, revision, &previous, fields, time, history_depth)
+                .await;
+
+            match outcome {
+                Ok(_) => return Ok(previous),
+                Err(TuringDbError::RevisionConflict { .. }) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    /// Replace every field of a document with `fields` only if the document is still at `expected_revision`,
+    /// failing with `RevisionConflict` otherwise. Returns the revision the document is at after the write
+    pub(crate) async fn document_replace_if(
+        &self,
+        document_name: &Utf8Path,
+        expected_revision: u64,
+        fields: &[(Vec<u8>, TDBCell)],
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+
+        let (revision, previous) = History::at_rest(&sled_db, |_| {
+            let mut previous = Vec::new();
+            for entry in sled_db.iter() {
+                let (key, stored) = entry?;
+                previous.push((key.to_vec(), TuringDB::decode_field(&stored)?));
+            }
+
+            Ok(previous)
+        })?;
+        if revision != expected_revision {
+            return Err(TuringDbError::RevisionConflict { current: revision });
+        }
+
+        let revision = self
+            .replace_fields(&document, revision, &previous, fields, time, history_depth)
+            .await?;
+
+        Ok(OpsOutcome::DocumentUpdated { revision })
+    }
+    /// Check that a document is at `expected_revision` without writing anything
+    pub(crate) async fn revision_check(
+        &self,
+        document_name: &Utf8Path,
+        expected_revision: u64,
+    ) -> TuringResult<()> {
+        let current = History::current_revision(&self.document(document_name).await?)?;
+
+        if current != expected_revision {
+            return Err(TuringDbError::RevisionConflict { current });
+        }
+
+        Ok(())
+    }
+    /// Apply a patch to a document only if the document matches `condition` when the patch is written,
+    /// failing with `ConditionNotMet` otherwise. The condition and the patch see the same revision
+    pub(crate) async fn document_patch_if(
+        &self,
+        document_name: &Utf8Path,
+        condition: &Filter,
+        patch: &Patch,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+
+        if History::written_at(&sled_db, time)? {
+            return Ok(OpsOutcome::DocumentPatched);
+        }
+
+        let ops = patch.get_ops();
+
+        // A write landing between checking the condition and patching sends the patch back to check it again
+        loop {
+            let (revision, matches) = History::at_rest(&sled_db, |_| condition.matches(&sled_db))?;
+            if !matches {
+                return Err(TuringDbError::ConditionNotMet);
+            }
+
+            let outcome = self
+                .write_revisions(
+                    &document,
+                    &patch.keys(),
+                    time,
+                    history_depth,
+                    Some(revision),
+                    |index, previous| ops[index].apply(previous, time),
+                )
+                .await;
+
+            match outcome {
+                Ok(_) => return Ok(OpsOutcome::DocumentPatched),
+                Err(TuringDbError::RevisionConflict { .. }) => continue,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    /// Check that a document matches `condition` without writing anything
+    pub(crate) async fn condition_check(
+        &self,
+        document_name: &Utf8Path,
+        condition: &Filter,
+    ) -> TuringResult<()> {
+        if !condition.matches(&self.document(document_name).await?)? {
+            return Err(TuringDbError::ConditionNotMet);
+        }
+
+        Ok(())
+    }
+    /// Remove the fields of `previous` that are not in `fields` then write `fields`,
+    /// as long as the document is still at `revision`
+    async fn replace_fields(
+        &self,
+        document: &LazyDocument,
+        revision: u64,
+        previous: &[(Vec<u8>, FieldData)],
+        fields: &[(Vec<u8>, TDBCell)],
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<u64> {
+        let mut keys = previous
+            .iter()
+            .map(|(key, _)| key.as_slice())
+            .filter(|key| {
+                !fields
+                    .iter()
+                    .any(|(replacing, _)| replacing.as_slice() == *key)
+            })
+            .collect::<Vec<&[u8]>>();
+        let removed = keys.len();
+        keys.extend(fields.iter().map(|(key, _)| key.as_slice()));
+
+        self.write_revisions(
+            document,
+            &keys,
+            time,
+            history_depth,
+            Some(revision),
+            |index, current| {
+                if index < removed {
+                    return Ok(None);
+                }
+
+                let (_, value) = &fields[index - removed];
+                match current {
+                    None => Ok(Some(FieldData::new_at(
+                        value.get_data_type(),
+                        value.get_data(),
+                        time,
+                    ))),
+                    Some(mut field_data) => {
+                        field_data.update_at(value.get_data_type(), value.get_data(), time);
+
+                        Ok(Some(field_data))
+                    }
+                }
+            },
+        )
+        .await
+    }
+    /// Check that every write of a batch applies, in order, to the current contents of the database
+    /// without writing anything. Each write sees what the writes before it in the batch would leave
+    pub(crate) async fn batch_check(&self, ops: &[WriteOp]) -> TuringResult<()> {
+        // Documents the batch creates, replaces or drops, only the fields the batch writes count for them
+        let mut documents: HashMap<&Utf8Path, bool> = HashMap::new();
+        let mut fields: HashMap<(&Utf8Path, &[u8]), Option<FieldData>> = HashMap::new();
+        let time = TAI64N::now();
+
+        for op in ops {
+            let document_name = op.document();
+            let exists = match documents.get(document_name) {
+                Some(exists) => *exists,
+                None => self.list.contains(document_name).await?,
+            };
+
+            match op {
+                WriteOp::DocumentCreate { .. } => {
+                    if exists {
+                        return Err(TuringDbError::AlreadyExists);
+                    }
+                    documents.insert(document_name, true);
+                }
+                WriteOp::DocumentDrop { .. } => {
+                    if !exists {
+                        return Err(TuringDbError::DocumentNotFound);
+                    }
+                    documents.insert(document_name, false);
+                    fields.retain(|(written, _), _| *written != document_name);
+                }
+                WriteOp::DocumentUpsert {
+                    fields: replacing, ..
+                } => {
+                    documents.insert(document_name, true);
+                    fields.retain(|(written, _), _| *written != document_name);
+
+                    for (key, value) in replacing {
+                        let field_data =
+                            FieldData::new_at(value.get_data_type(), value.get_data(), time);
+                        fields.insert((document_name, key), Some(field_data));
+                    }
+                }
+                WriteOp::DocumentPatch { patch, .. } => {
+                    if !exists {
+                        return Err(TuringDbError::DocumentNotFound);
+                    }
+
+                    for patch_op in patch.get_ops() {
+                        let (previous, streamed) = self
+                            .batch_field(&documents, &fields, document_name, patch_op.key())
+                            .await?;
+                        if streamed {
+                            return Err(TuringDbError::StreamedField);
+                        }
+
+                        fields.insert(
+                            (document_name, patch_op.key()),
+                            patch_op.apply(previous, time)?,
+                        );
+                    }
+                }
+                WriteOp::FieldInsert { key, value, .. } => {
+                    if !exists {
+                        return Err(TuringDbError::DocumentNotFound);
+                    }
+
+                    let (previous, streamed) = self
+                        .batch_field(&documents, &fields, document_name, key)
+                        .await?;
+                    if previous.is_some() || streamed {
+                        return Err(TuringDbError::KeyAlreadyExists);
+                    }
+
+                    let field_data =
+                        FieldData::new_at(value.get_data_type(), value.get_data(), time);
+                    fields.insert((document_name, key), Some(field_data));
+                }
+                WriteOp::FieldModify { key, value, .. } => {
+                    if !exists {
+                        return Err(TuringDbError::DocumentNotFound);
+                    }
+
+                    match self
+                        .batch_field(&documents, &fields, document_name, key)
+                        .await?
+                    {
+                        (Some(mut field_data), _) => {
+                            field_data.update_at(value.get_data_type(), value.get_data(), time);
+                            fields.insert((document_name, key), Some(field_data));
+                        }
+                        (None, _) => return Err(TuringDbError::FieldNotFound),
+                    }
+                }
+                WriteOp::FieldRemove { key, .. } => {
+                    if !exists {
+                        return Err(TuringDbError::DocumentNotFound);
+                    }
+
+                    let (previous, streamed) = self
+                        .batch_field(&documents, &fields, document_name, key)
+                        .await?;
+                    if previous.is_none() && !streamed {
+                        return Err(TuringDbError::FieldNotFound);
+                    }
+
+                    fields.insert((document_name, key), None);
+                }
+            }
+        }
+
+        // The schema applies to what the whole batch leaves behind, not to each write on its way there
+        let structure = self.structure.lock().await;
+        structure.check_written(
+            fields
+                .iter()
+                .map(|((_, key), field_data)| (*key, field_data.as_ref())),
+        )?;
+        for (document_name, exists) in &documents {
+            if *exists {
+                structure.check_required(|key| {
+                    matches!(fields.get(&(*document_name, key)), Some(Some(_)))
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+    /// A field as the writes of a batch checked so far would leave it, along with whether it is streamed
+    async fn batch_field(
+        &self,
+        documents: &HashMap<&Utf8Path, bool>,
+        fields: &HashMap<(&Utf8Path, &[u8]), Option<FieldData>>,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<(Option<FieldData>, bool)> {
+        if let Some(field_data) = fields.get(&(document_name, key)) {
+            return Ok((field_data.clone(), false));
+        }
+        if documents.contains_key(document_name) {
+            return Ok((None, false));
+        }
+
+        let sled_db = self.document(document_name).await?;
+        let streamed = sled_db.open_tree(STREAM_TREE)?.contains_key(key)?;
+
+        match sled_db.get(key)? {
+            None => Ok((None, streamed)),
+            Some(stored) => Ok((Some(TuringDB::decode_field(&stored)?), streamed)),
+        }
+    }
+    /// Check whether a field exists in a document
+    pub(crate) async fn field_exists(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<bool> {
+        let sled_db = self.document(document_name).await?;
+
+        Ok(sled_db.contains_key(key)? || sled_db.open_tree(STREAM_TREE)?.contains_key(key)?)
+    }
+    /// Get the contents of a field
+    pub(crate) async fn field_get(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+
+        match sled_db.get(key)? {
+            None => {
+                if sled_db.open_tree(STREAM_TREE)?.contains_key(key)? {
+                    Err(TuringDbError::StreamedField)
+                } else {
+                    Err(TuringDbError::FieldNotFound)
+                }
+            }
+            Some(field_bytes) => {
+                let field_data = TuringDB::decode_field(&field_bytes)?;
+
+                Ok(OpsOutcome::FieldContents(field_data))
+            }
+        }
+    }
+    /// Make a streamed field whose chunks are already on disk visible under `key`
+    pub(crate) async fn field_link_stream(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        manifest: &StreamManifest,
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+
+        if sled_db.contains_key(key)? {
+            return Err(TuringDbError::KeyAlreadyExists);
+        }
+
+        let streams = sled_db.open_tree(STREAM_TREE)?;
+        let tombstones = sled_db.open_tree(TOMBSTONE_TREE)?;
+
+        match streams.compare_and_swap(key, None as Option<&[u8]>, Some(manifest.encode()?))? {
+            Ok(_) => {
+                tombstones.remove(key)?;
+                self.usage.record_write(manifest.len());
+
+                Ok(OpsOutcome::FieldInserted)
+            }
+            Err(_) => Err(TuringDbError::KeyAlreadyExists),
+        }
+    }
+    /// Write the value of a streamed field to `writer`, returning its length
+    pub(crate) async fn field_get_stream(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        writer: impl AsyncWrite + Unpin,
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+
+        match sled_db.open_tree(STREAM_TREE)?.get(key)? {
+            None => Err(TuringDbError::FieldNotFound),
+            Some(stored) => {
+                let manifest = StreamManifest::decode(&stored)?;
+                let len = ChunkedStream::read(&sled_db, &manifest, writer).await?;
+
+                Ok(OpsOutcome::FieldStreamed { len })
+            }
+        }
+    }
+    /// Modify the contents of an existing field
+    pub(crate) async fn field_modify(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        value: &TDBCell,
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+
+        self.write_revision(
+            &document,
+            key,
+            time,
+            history_depth,
+            |previous| match previous {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(mut field_data) => {
+                    field_data.update_at(value.get_data_type(), value.get_data(), time);
+
+                    Ok(Some(field_data))
+                }
+            },
+        )
+        .await?;
+
+        Ok(OpsOutcome::FieldModified)
+    }
+    /// Remove a field, leaving a tombstone recording when it was removed
+    pub(crate) async fn field_remove(
+        &self,
+        document_name: &Utf8Path,
+        key: &[u8],
+        time: TAI64N,
+        history_depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let document = self.lazy_document(document_name).await?;
+        let sled_db = document.open().await?;
+
+        let streams = sled_db.open_tree(STREAM_TREE)?;
+        if let Some(stored) = streams.remove(key)? {
+            let manifest = StreamManifest::decode(&stored)?;
+            ChunkedStream::discard(&sled_db, manifest.upload_id())?;
+            sled_db
+                .open_tree(TOMBSTONE_TREE)?
+                .insert(key, &time.to_bytes()[..])?;
+
+            return Ok(OpsOutcome::FieldRemoved);
+        }
+
+        self.write_revision(
+            &document,
+            key,
+            time,
+            history_depth,
+            |previous| match previous {
+                None => Err(TuringDbError::FieldNotFound),
+                Some(_) => Ok(None),
+            },
+        )
+        .await?;
+
+        Ok(OpsOutcome::FieldRemoved)
+    }
+    /// List the fields removed from a document whose tombstones have not been vacuumed yet
+    pub(crate) async fn document_tombstones(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<OpsOutcome> {
+        let tombstones = self
+            .document(document_name)
+            .await?
+            .open_tree(TOMBSTONE_TREE)?;
+
+        let mut removed = Vec::new();
+
+        for tombstone in tombstones.iter() {
+            let (key, removed_at) = tombstone?;
+            removed.push((key.to_vec(), TuringDB::decode_time(&removed_at)?));
+        }
+
+        Ok(OpsOutcome::Tombstones(removed))
+    }
+    /// Purge the tombstones of fields removed at or before `cutoff` from every document,
+    /// returning how many were purged
+    pub(crate) async fn vacuum(&self, cutoff: TAI64N) -> TuringResult<usize> {
+        let mut purged = 0_usize;
+
+        for (_, document) in self.list.documents().await? {
+            // Archived documents keep their tombstones until they are rehydrated
+            if document.is_cold().await {
+                continue;
+            }
+
+            let tombstones = document.open().await?.open_tree(TOMBSTONE_TREE)?;
+
+            for tombstone in tombstones.iter() {
+                let (key, removed_at) = tombstone?;
+
+                if TuringDB::decode_time(&removed_at)? <= cutoff {
+                    // Only purge the tombstone if the field was not removed again since it was read
+                    if tombstones
+                        .compare_and_swap(&key, Some(&removed_at), None as Option<&[u8]>)?
+                        .is_ok()
+                    {
+                        purged += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(purged)
+    }
+    /// Whether a document may be in the database, without listing its partition
+    pub(crate) async fn document_may_exist(&self, document_name: &Utf8Path) -> TuringResult<bool> {
+        self.list.may_contain(document_name).await
+    }
+    /// List the revisions of a document that are still kept, oldest first
+    pub(crate) async fn document_history(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+
+        Ok(OpsOutcome::DocumentHistory(History::revisions(&sled_db)?))
+    }
+    /// Rebuild the fields of a document as they were at `revision` by undoing every later write.
+    /// Revision `0` is the document before its first write
+    pub(crate) async fn document_get_revision(
+        &self,
+        document_name: &Utf8Path,
+        revision: u64,
+    ) -> TuringResult<OpsOutcome> {
+        let sled_db = self.document(document_name).await?;
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        let current_revision = match history.get(CURRENT_REVISION_KEY)? {
+            None => 0,
+            Some(stored) => Revision::decode_number(&stored),
+        };
+        if revision > current_revision {
+            return Err(TuringDbError::RevisionNotFound);
+        }
+
+        // The oldest kept revision can be undone to reach the one before it
+        if let Some(entry) = history.range(Revision::encode_number(0)..).next() {
+            let (oldest_revision, _) = entry?;
+
+            if revision + 1 < Revision::decode_number(&oldest_revision) {
+                return Err(TuringDbError::RevisionNotFound);
+            }
+        }
+
+        let fields = History::fields_at(&sled_db, revision)?;
+
+        Ok(OpsOutcome::DocumentRevision {
+            revision,
+            fields: fields.into_iter().collect(),
+        })
+    }
+    /// Find the documents matched by a query along with the fields it selects. Every document
+    /// the secondary indexes do not rule out is opened to check it, which rehydrates documents in the cold tier
+    pub(crate) async fn query(&self, query: &Query) -> TuringResult<OpsOutcome> {
+        self.run_query(query, &mut QueryPlan::default()).await
+    }
+    /// Run a query to tell how it is run: the indexes that narrowed down the documents it checked,
+    /// and how many documents were left to check, opened and returned
+    pub(crate) async fn explain(&self, query: &Query) -> TuringResult<OpsOutcome> {
+        let mut plan = QueryPlan::default();
+        self.run_query(query, &mut plan).await?;
+
+        Ok(OpsOutcome::QueryPlan(plan))
+    }
+
+    async fn run_query(&self, query: &Query, plan: &mut QueryPlan) -> TuringResult<OpsOutcome> {
+        let query = &query.collated(*self.collation.lock().await);
+
+        if let Some(sample) = query.get_sample() {
+            return self.query_sample(query, sample, plan).await;
+        }
+
+        let keyset = query.keyset()?;
+
+        let sorted = self.scan_sorted(query, plan).await?;
+        let in_order = sorted.is_some();
+
+        let documents = match sorted {
+            Some(documents) => documents,
+            None => {
+                let mut documents = self
+                    .scan(
+                        query.get_filter(),
+                        query.get_collation().unwrap_or_default(),
+                        plan,
+                    )
+                    .await?;
+                documents.sort_by(|(left, _), (right, _)| left.cmp(right));
+
+                documents
+            }
+        };
+
+        let mut matches: Vec<(Option<Value>, Matched)> = Vec::new();
+
+        for (document_name, document) in documents {
+            if query.is_complete(matches.len()) {
+                break;
+            }
+            // Without an order the documents before the token are skipped without opening them
+            if query.get_order().is_none() {
+                if let Some(keyset) = &keyset {
+                    if !query.follows(keyset, &None, &document_name) {
+                        continue;
+                    }
+                }
+            }
+
+            let sled_db = document.open().await?;
+            plan.record_examined();
+            let sort_key = query.sort_key(&sled_db)?;
+
+            // Documents read in the order they are sorted by can only join a full page while they tie with
+            // its last match
+            if in_order
+                && query.is_filled(matches.len())
+                && matches.last().map(|(last, _)| last) != Some(&sort_key)
+            {
+                break;
+            }
+
+            if !query.matches(&sled_db)? {
+                continue;
+            }
+
+            if let Some(keyset) = &keyset {
+                if !query.follows(keyset, &sort_key, &document_name) {
+                    continue;
+                }
+            }
+
+            matches.push((
+                sort_key,
+                (document_name, TuringDB::selected_fields(query, &sled_db)?),
+            ));
+        }
+
+        let (documents, continuation) = query.page(matches)?;
+        plan.set_returned(documents.len());
+
+        Ok(OpsOutcome::DocumentMatches {
+            documents,
+            continuation,
+        })
+    }
+    /// Draw a uniform random sample of the documents a query matches. The documents are opened in a random
+    /// order until `sample` of them matched, so a sample of a database most of whose documents match
+    /// opens about as many documents as it returns
+    async fn query_sample(
+        &self,
+        query: &Query,
+        sample: usize,
+        plan: &mut QueryPlan,
+    ) -> TuringResult<OpsOutcome> {
+        query.keyset()?;
+
+        let documents = self
+            .scan(
+                query.get_filter(),
+                query.get_collation().unwrap_or_default(),
+                plan,
+            )
+            .await?;
+
+        let mut matches = Vec::new();
+
+        for index in Shuffle::new(documents.len()) {
+            if matches.len() >= sample {
+                break;
+            }
+
+            let (document_name, document) = &documents[index];
+            let sled_db = document.open().await?;
+            plan.record_examined();
+
+            if !query.matches(&sled_db)? {
+                continue;
+            }
+
+            matches.push((
+                query.sort_key(&sled_db)?,
+                (
+                    document_name.clone(),
+                    TuringDB::selected_fields(query, &sled_db)?,
+                ),
+            ));
+        }
+
+        let (documents, continuation) = query.page(matches)?;
+        plan.set_returned(documents.len());
+
+        Ok(OpsOutcome::DocumentMatches {
+            documents,
+            continuation,
+        })
+    }
+
+    fn selected_fields(
+        query: &Query,
+        sled_db: &Document,
+    ) -> TuringResult<Vec<(Vec<u8>, FieldData)>> {
+        let mut fields = Vec::new();
+        for entry in sled_db.iter() {
+            let (key, stored) = entry?;
+
+            if query.selects(&key) {
+                fields.push((key.to_vec(), TuringDB::decode_field(&stored)?));
+            }
+        }
+
+        Ok(fields)
+    }
+    /// Compute the accumulators of an aggregation over each group of the matching documents. Every document
+    /// the secondary indexes do not rule out is opened to check it, which rehydrates documents in the cold tier
+    pub(crate) async fn aggregate(&self, aggregation: &Aggregation) -> TuringResult<OpsOutcome> {
+        let mut groups = BTreeMap::new();
+
+        for (_, document) in self
+            .scan(
+                aggregation.get_filter(),
+                Collation::Binary,
+                &mut QueryPlan::default(),
+            )
+            .await?
+        {
+            let sled_db = document.open().await?;
+
+            if aggregation.get_filter().matches(&sled_db)? {
+                aggregation.accumulate(&mut groups, &sled_db)?;
+            }
+        }
+
+        Ok(OpsOutcome::Aggregated(aggregation.finish(groups)))
+    }
+    /// Read every field of a document, streamed fields are left out
+    pub(crate) async fn document_fields(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<Vec<(Vec<u8>, FieldData)>> {
+        let sled_db = self.document(document_name).await?;
+
+        let mut fields = Vec::new();
+        for entry in sled_db.iter() {
+            let (key, stored) = entry?;
+            fields.push((key.to_vec(), TuringDB::decode_field(&stored)?));
+        }
+
+        Ok(fields)
+    }
+    /// Open a view of a document frozen at its current revision
+    pub(crate) async fn document_view(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<DocumentView> {
+        let document = self.lazy_document(document_name).await?;
+
+        DocumentView::open(document.open().await?, &document.pins)
+    }
+    /// Write a field and record the write as the next revision of the document in one transaction,
+    /// keeping only the last `history_depth` revisions and every revision an open view still needs.
+    /// The replaced contents are stored as a delta against the new contents with a full copy every few revisions.
+    /// `update` receives the current contents of the field and returns its new contents, `None` removes it
+    async fn write_revision<F>(
+        &self,
+        document: &LazyDocument,
+        key: &[u8],
+        time: TAI64N,
+        history_depth: usize,
+        update: F,
+    ) -> TuringResult<u64>
+    where
+        F: Fn(Option<FieldData>) -> TuringResult<Option<FieldData>>,
+    {
+        self.write_revisions(
+            document,
+            &[key],
+            time,
+            history_depth,
+            None,
+            |_, previous| update(previous),
+        )
+        .await
+    }
+    /// Write several fields in one transaction as consecutive revisions of the document, in the order of `keys`.
+    /// `update` receives the index of the key along with the current contents of the field, a key may appear
+    /// more than once and sees what the earlier writes left. Removing a field that does not exist writes nothing.
+    /// With `expected_revision` nothing is written unless the document is still at that revision
+    async fn write_revisions<F>(
+        &self,
+        document: &LazyDocument,
+        keys: &[&[u8]],
+        time: TAI64N,
+        history_depth: usize,
+        expected_revision: Option<u64>,
+        update: F,
+    ) -> TuringResult<u64>
+    where
+        F: Fn(usize, Option<FieldData>) -> TuringResult<Option<FieldData>>,
+    {
+        let sled_db = document.open().await?;
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+        let tombstones = sled_db.open_tree(TOMBSTONE_TREE)?;
+        let delta_runs = sled_db.open_tree(DELTA_RUN_TREE)?;
+        let compression = self.meta.lock().await.compression();
+
+        let outcome = (&*sled_db, &history, &tombstones, &delta_runs).transaction(
+            |(fields, history, tombstones, delta_runs)| {
+                let mut revision = match history.get(CURRENT_REVISION_KEY)? {
+                    None => 0,
+                    Some(stored) => Revision::decode_number(&stored),
+                };
+                if let Some(expected_revision) = expected_revision {
+                    if revision != expected_revision {
+                        return Err(ConflictableTransactionError::Abort(
+                            TuringDbError::RevisionConflict { current: revision },
+                        ));
+                    }
+                }
+                let mut written = 0_u64;
+
+                for (index, key) in keys.iter().enumerate() {
+                    let key = *key;
+
+                    let previous = match fields.get(key)? {
+                        None => None,
+                        Some(stored) => Some(
+                            TuringDB::decode_field(&stored)
+                                .map_err(ConflictableTransactionError::Abort)?,
+                        ),
+                    };
+                    let current = update(index, previous.clone())
+                        .map_err(ConflictableTransactionError::Abort)?;
+
+                    if previous.is_none() && current.is_none() {
+                        continue;
+                    }
+                    revision += 1;
+
+                    let field_len = match &current {
+                        Some(field_data) => {
+                            let stored = TuringDB::encode_field(compression, field_data)
+                                .map_err(ConflictableTransactionError::Abort)?;
+                            let field_len = key.len() + stored.len();
+                            fields.insert(key, stored)?;
+                            tombstones.remove(key)?;
+
+                            field_len
+                        }
+                        None => {
+                            fields.remove(key)?;
+                            tombstones.insert(key, &time.to_bytes()[..])?;
+
+                            0
+                        }
+                    };
+
+                    let delta_run = match delta_runs.get(key)? {
+                        None => 0,
+                        Some(stored) => Revision::decode_number(&stored),
+                    };
+                    let (entry, delta_run) = StoredRevision::new(
+                        revision,
+                        time,
+                        key,
+                        previous,
+                        current.as_ref(),
+                        delta_run as u32,
+                    );
+                    if delta_run == 0 {
+                        delta_runs.remove(key)?;
+                    } else {
+                        delta_runs.insert(key, &Revision::encode_number(delta_run as u64)[..])?;
+                    }
+
+                    let entry = entry
+                        .encode(compression)
+                        .map_err(ConflictableTransactionError::Abort)?;
+                    written += (field_len + entry.len()) as u64;
+                    history.insert(&Revision::encode_number(revision)[..], entry)?;
+                    history.insert(CURRENT_REVISION_KEY, &Revision::encode_number(revision)[..])?;
+                }
+
+                Ok((revision, written))
+            },
+        );
+
+        let revision = match outcome {
+            Ok((revision, written)) => {
+                self.usage.record_write(written);
+
+                revision
+            }
+            Err(TransactionError::Abort(error)) => return Err(error),
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+        };
+
+        let history_depth = history_depth.max(1) as u64;
+        if revision > history_depth {
+            let oldest_kept = revision - history_depth + 1;
+            let oldest_kept = match document.pins.oldest()? {
+                None => oldest_kept,
+                Some(pinned) => oldest_kept.min(pinned + 1),
+            };
+
+            for entry in
+                history.range(Revision::encode_number(0)..Revision::encode_number(oldest_kept))
+            {
+                let (pruned, _) = entry?;
+                history.remove(pruned)?;
+            }
+        }
+
+        Ok(revision)
+    }
+
+    fn decode_time(stored: &[u8]) -> TuringResult<TAI64N> {
+        match TAI64N::from_slice(stored) {
+            Ok(time) => Ok(time),
+            Err(_) => Err(TuringDbError::Serialization(
+                "Invalid TAI64N timestamp".into(),
+            )),
+        }
+    }
+
+    /// Serialize a field and compress it with the compression of the database
+    fn encode_field(compression: Compression, field_data: &FieldData) -> TuringResult<Vec<u8>> {
+        let field_bytes = bincode::serialize::<FieldData>(field_data)?;
+
+        compression.compress(&field_bytes)
+    }
+    /// Decompress a stored field and deserialize it
+    pub(crate) fn decode_field(stored: &[u8]) -> TuringResult<FieldData> {
+        let field_bytes = Compression::decompress(stored)?;
+
+        Ok(bincode::deserialize::<FieldData>(&field_bytes)?)
+    }
+
+    /// Count the fields in every document of the database and measure how much space they take up
+    /// on disk. Every document is opened to measure it, apart from archived documents which are
+    /// counted from their archive
+    pub(crate) async fn stats(&self) -> TuringResult<DbStats> {
+        let documents = self.list.documents().await?;
+
+        let mut entries = 0_u64;
+        let mut disk_bytes = 0_u64;
+
+        for (_, document) in documents.iter() {
+            if let Some((cold_document, archive_bytes)) = document.cold_archive().await? {
+                entries += cold_document.entries();
+                disk_bytes += archive_bytes;
+
+                continue;
+            }
+
+            let sled_db = document.open().await?;
+
+            entries += (sled_db.len() + sled_db.open_tree(STREAM_TREE)?.len()) as u64;
+            disk_bytes += sled_db.size_on_disk()?;
+        }
+
+        self.usage.measured(disk_bytes);
+
+        Ok(DbStats::new(documents.len(), entries, disk_bytes))
+    }
+    /// Check the metadata file and every document of the database, recording what is wrong in `report`.
+    /// A metadata file that fails its checksum is quarantined and the database is marked as changed
+    /// so that the next commit writes it again from memory
+    pub(crate) async fn scrub(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        quarantine: &Quarantine,
+        report: &mut IntegrityReport,
+    ) -> TuringResult<()> {
+        if !self.ephemeral {
+            let meta_path = DbMeta::path(&TuringDB::build_path(repo_dir, db_name));
+            report.file_checked();
+
+            for (path, quarantined) in MetaFile::scrub(&meta_path, quarantine).await? {
+                report.record(
+                    IntegrityFinding::new(&path, IntegrityIssue::MetaCorrupted)
+                        .set_quarantined(quarantined),
+                );
+                self.mark_dirty();
+            }
+        }
+
+        for (document_name, document) in self.list.documents().await? {
+            let path = self.document_path(repo_dir, db_name, &document_name);
+            report.document_checked();
+
+            // Only the checksum of an archive is checked so that scrubbing does not rehydrate it
+            if document.is_cold().await {
+                let archive_path = ColdDocument::path(&path);
+
+                for (copy_path, quarantined) in MetaFile::scrub(&archive_path, quarantine).await? {
+                    report.record(
+                        IntegrityFinding::new(&copy_path, IntegrityIssue::DocumentCorrupted)
+                            .set_quarantined(quarantined),
+                    );
+                }
+
+                continue;
+            }
+
+            // Opening a corrupted document quarantines it
+            let sled_db = match document.open().await {
+                Ok(sled_db) => sled_db,
+                Err(TuringDbError::DocumentCorrupted { .. }) => {
+                    report.record(IntegrityFinding::new(
+                        &path,
+                        IntegrityIssue::DocumentCorrupted,
+                    ));
+                    continue;
+                }
+                Err(error) => return Err(error),
+            };
+
+            for field in sled_db.iter() {
+                let (key, stored) = field?;
+
+                if TuringDB::decode_field(&stored).is_err() {
+                    report.record(IntegrityFinding::new(
+                        &path,
+                        IntegrityIssue::FieldUndecodable { key: key.to_vec() },
+                    ));
+                }
+            }
+
+            let corrupt_chunks = ChunkedStream::corrupt_chunks(&sled_db)?;
+
+            if !corrupt_chunks.is_empty() {
+                let mut streamed_keys = HashMap::new();
+
+                for stream in sled_db.open_tree(STREAM_TREE)?.iter() {
+                    let (key, stored) = stream?;
+                    streamed_keys
+                        .insert(StreamManifest::decode(&stored)?.upload_id(), key.to_vec());
+                }
+
+                for (upload_id, chunk) in corrupt_chunks {
+                    report.record(IntegrityFinding::new(
+                        &path,
+                        IntegrityIssue::ChunkCorrupted {
+                            key: streamed_keys.get(&upload_id).cloned(),
+                            chunk,
+                        },
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+    /// Reject a write of `incoming` bytes that would take the database over `quota` bytes
+    pub(crate) async fn check_quota(&self, quota: Option<u64>, incoming: u64) -> TuringResult<()> {
+        let quota = match quota {
+            None => return Ok(()),
+            Some(quota) => quota,
+        };
+
+        if !self.usage.is_measured() {
+            self.stats().await?;
+        }
+
+        let used = self.usage.disk_bytes();
+
+        if used.saturating_add(incoming) > quota {
+            return Err(TuringDbError::QuotaExceeded { quota, used });
+        }
+
+        Ok(())
+    }
+    /// Get a document, opening it from disk if it has not been accessed yet
+    pub(crate) async fn document(&self, document_name: &Utf8Path) -> TuringResult<Document> {
+        self.lazy_document(document_name).await?.open().await
+    }
+    /// Get the contents of a document for copying without rehydrating it from the cold tier
+    pub(crate) async fn document_contents(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<DocumentContents> {
+        self.lazy_document(document_name).await?.contents().await
+    }
+    /// Move the documents that have not been written since `cutoff` into the cold tier,
+    /// returning their names. The documents of an ephemeral database are never archived
+    pub(crate) async fn archive_idle(&self, cutoff: TAI64N) -> TuringResult<Vec<Utf8PathBuf>> {
+        let mut archived = Vec::new();
+
+        if self.ephemeral {
+            return Ok(archived);
+        }
+
+        for (document_name, document) in self.list.documents().await? {
+            if document.archive_if_idle(cutoff).await? {
+                archived.push(document_name);
+            }
+        }
+
+        if !archived.is_empty() {
+            self.usage.invalidate();
+        }
+
+        Ok(archived)
+    }
+    /// Get a document without opening it, listing its partition if it has not been listed yet
+    async fn lazy_document(&self, document_name: &Utf8Path) -> TuringResult<Arc<LazyDocument>> {
+        match self.list.get(document_name).await? {
+            None => Err(TuringDbError::DocumentNotFound),
+            Some(document) => Ok(document),
+        }
+    }
+    /// Move the revisions of every document written before delta encoding into the current history tree
+    pub(crate) async fn upgrade_histories(&self) -> TuringResult<()> {
+        for (_, document) in self.list.documents().await? {
+            let compression = self.meta.lock().await.compression();
+            History::upgrade(&document.open().await?, compression)?;
+        }
+
+        Ok(())
+    }
+    /// Flush all the documents in the database that have been opened to disk.
+    /// Partitions that have not been listed hold no opened documents
+    pub(crate) async fn flush(&self) -> TuringResult<()> {
+        for (_, document) in self.list.loaded().await {
+            if let Some(sled_db) = document.loaded().await {
+                sled_db.flush_async().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush the opened documents and persist the metadata of the database
+    pub(crate) async fn commit(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        meta_encoding: MetaEncoding,
+    ) -> TuringResult<()> {
+        self.flush().await?;
+
+        // The count from the last commit is kept until every partition has been listed
+        let documents = match self.list.loaded_len().await {
+            Some(documents) => documents,
+            None => self.meta.lock().await.documents(),
+        };
+
+        let mut meta = self.meta.lock().await.clone();
+        meta.stamp(documents);
+
+        let db_dir = Self::build_path(repo_dir, db_name);
+        self.times.lock().await.persist(&db_dir).await?;
+        self.structure.lock().await.persist(&db_dir).await?;
+        self.trash.lock().await.persist(&db_dir).await?;
+        self.collation.lock().await.persist(&db_dir).await?;
+        // The entries of the indexes are stamped with the commit, entries left from another one are built again
+        self.indexes
+            .lock()
+            .await
+            .persist(&db_dir, meta.committed())
+            .await?;
+        UniqueKey::persist(&db_dir, self.unique.lock().await.as_ref()).await?;
+        TextIndex::persist(&db_dir, self.text.lock().await.as_ref(), meta.committed()).await?;
+        TtlIndex::persist(&db_dir, self.ttl.lock().await.as_ref(), meta.committed()).await?;
+        PrefixIndex::persist(&db_dir, self.prefix.lock().await.as_ref(), meta.committed()).await?;
+        BloomFilter::persist(&db_dir, &self.list.filters().await, meta.committed()).await?;
+
+        meta.persist(&db_dir, meta_encoding).await
+    }
+
+    pub(crate) fn build_path(repo_dir: &Utf8Path, db_name: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(db_name);
+
+        path
+    }
+
+    fn document_path(
+        &self,
+        repo_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+    ) -> Utf8PathBuf {
+        self.list
+            .partitioning()
+            .document_path(&TuringDB::build_path(repo_dir, db_name), document_name)
     }
 }
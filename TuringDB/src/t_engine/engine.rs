@@ -1,15 +1,40 @@
 use crate::{
-    Document, OpsOutcome, RepoPath, TuringDB, TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps,
-    TuringDbError, TuringResult,
+    snapshot_dir, Aggregation, BackupManifest, BloomFilter, ChangeFeed, ChunkedStream, Collation,
+    Crdt, CrdtOp, Cursor, Cursors, DbMeta, DocumentContents, DocumentIndex, DocumentLocks,
+    DocumentView, ErrorCode, Filter, History, IndexDeclaration, IndexKind, Indexes,
+    IntegrityFinding, IntegrityIssue, IntegrityReport, LogOp, LogRecord, Matched, MaterializedView,
+    MerkleTree, MetaFile, Migrator, OpsLog, OpsOutcome, Partitioning, Patch, Populated,
+    PrefixIndex, Quarantine, Query, Reference, RemoteRepo, ReplicaSnapshot, RepoLock, RepoMeta,
+    RepoPath, Resolution, SnapshotDocument, SnapshotMeta, Stamp, Statement, StorageBackend,
+    Structure, Subscription, TDBCell, TextIndex, TextIndexDefinition, TimeField, TimeIndex,
+    Transaction, Transactions, Trash, TtlIndex, TuringConfig, TuringDB, TuringDBDocumentOps,
+    TuringDBFieldOps, TuringDBOps, TuringDbError, TuringQL, TuringResult, UniqueKey, Value,
+    Version, ViewDefinition, Views, WireError, WriteACKs, WriteOp, CHANGE_BUFFER,
+    DELTA_HISTORY_FORMAT, FORMAT_VERSION, MAX_FUZZY_EDITS, MAX_POPULATED, MAX_POPULATE_DEPTH,
+    RESERVED_DIR_PREFIX, SCAN_BATCH, TIME_INDEX_FORMAT,
 };
-use anyhow::Result;
-use async_fs::{self, DirBuilder, ReadDir};
-use async_lock::Mutex;
+use async_executor::{Executor, Task};
+use async_fs::{self, DirBuilder};
+use async_io::Timer;
+use async_lock::{Mutex, RwLock, RwLockWriteGuard};
 use camino::{Utf8Path, Utf8PathBuf};
-use dashmap::DashMap;
-use futures_lite::stream::StreamExt;
-use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, ffi::OsString, io::ErrorKind};
+use dashmap::{mapref::entry::Entry, DashMap};
+use futures_lite::{
+    io::{AsyncRead, AsyncWrite},
+    stream::{self, Stream, StreamExt},
+};
+use std::{
+    collections::{BTreeSet, VecDeque},
+    ffi::OsString,
+    future::Future,
+    path::Path,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tai64::TAI64N;
 
 // TODO use custom_codes errors to give actual errors
@@ -21,189 +46,3926 @@ use tai64::TAI64N;
 /// ```
 /// #[derive(Debug, Clone)]
 /// pub struct TuringEngine {
-///     dbs: DashMap<Utf8Path, Tdb>, // Repo<DatabaseName, Databases>
+///     dbs: DashMap<Utf8Path, Arc<Tdb>>, // Repo<DatabaseName, Databases>
+///     repo_dir: Utf8PathBuf,
+///     ops_log: OpsLog,
+///     commit_gate: RwLock<()>,
+///     compaction_gate: RwLock<()>,
+///     db_gates: DashMap<Utf8PathBuf, Arc<RwLock<()>>>,
+///     config: TuringConfig,
+///     quarantine: Arc<Quarantine>,
+///     repo_lock: Mutex<Option<RepoLock>>,
+///     ephemeral: bool,
+///     integrity_report: Mutex<Option<IntegrityReport>>,
+///     remote: Option<RemoteRepo>,
+///     cursors: Cursors,
+///     transactions: Transactions,
+///     locks: DocumentLocks,
+///     views: DashMap<Utf8PathBuf, MaterializedView>,
+///     changes: ChangeFeed,
+///     replica: AtomicBool,
+///     replica_synced: Mutex<Option<TAI64N>>,
 /// }
 /// ```
 #[derive(Debug)]
 pub struct TuringEngine {
-    dbs: DashMap<Utf8PathBuf, TuringDB>, // Repo<DatabaseName, Databases>
+    dbs: DashMap<Utf8PathBuf, Arc<TuringDB>>, // Repo<DatabaseName, Databases>
     repo_dir: Utf8PathBuf,
+    ops_log: OpsLog,
+    // Held for reading while an operation is logged and applied so that a commit
+    // never records a checkpoint for an operation that has not been applied yet
+    commit_gate: RwLock<()>,
+    // Held for reading by a snapshot so that the records it needs are not compacted away
+    compaction_gate: RwLock<()>,
+    // One per database, held for reading while a write to the database is logged and applied
+    // and while a query or an aggregation reads it.
+    // A write that is checked against the contents of the database first, a batch or a write to
+    // a database with a unique key, holds it for writing so the next one is checked against what
+    // it left. Writes to different databases never wait on each other
+    db_gates: DashMap<Utf8PathBuf, Arc<RwLock<()>>>,
+    config: TuringConfig,
+    quarantine: Arc<Quarantine>,
+    repo_lock: Mutex<Option<RepoLock>>,
+    // Nothing is read from or written to the repo directory
+    ephemeral: bool,
+    // The outcome of the last pass of the integrity scrubber
+    integrity_report: Mutex<Option<IntegrityReport>>,
+    // Holds the durable copy of the repo when the repo directory is only a local cache
+    remote: Option<RemoteRepo>,
+    // Cursors kept open for clients that read query results a batch at a time
+    cursors: Cursors,
+    // Writes staged by transactions that have not committed yet
+    transactions: Transactions,
+    // The advisory locks clients hold on documents
+    locks: DocumentLocks,
+    // The materialized views of the repo by name, maintained as their databases are written to
+    views: DashMap<Utf8PathBuf, MaterializedView>,
+    // The subscribers to the writes applied to the databases of the repo
+    changes: ChangeFeed,
+    // Set while the repo follows a primary, writes then only come from the ops log of the primary
+    replica: AtomicBool,
+    // The replica held every write of its primary made up to that time, `None` until it applied one
+    replica_synced: Mutex<Option<TAI64N>>,
 }
 impl TuringEngine {
     /// Create a new in-memory repo
     pub async fn new() -> TuringResult<TuringEngine> {
         let path = RepoPath::access_dir().await?;
 
+        TuringEngine::with_path(path).await
+    }
+
+    /// Create a new in-memory repo whose directory is at `path` instead of the user's home directory
+    pub async fn with_path(path: impl AsRef<Path>) -> TuringResult<TuringEngine> {
+        let path = match Utf8Path::from_path(path.as_ref()) {
+            None => return Err(TuringDbError::PathReadIsNotUtf8Path),
+            Some(path) => path.to_path_buf(),
+        };
+
         Ok(Self {
             dbs: DashMap::new(),
+            ops_log: OpsLog::new(&path),
+            quarantine: Arc::new(Quarantine::new(&path)),
+            repo_dir: path,
+            commit_gate: RwLock::new(()),
+            compaction_gate: RwLock::new(()),
+            db_gates: DashMap::new(),
+            config: TuringConfig::default(),
+            repo_lock: Mutex::new(None),
+            ephemeral: false,
+            integrity_report: Mutex::new(None),
+            remote: None,
+            cursors: Cursors::default(),
+            transactions: Transactions::default(),
+            locks: DocumentLocks::default(),
+            views: DashMap::new(),
+            changes: ChangeFeed::default(),
+            replica: AtomicBool::new(false),
+            replica_synced: Mutex::new(None),
+        })
+    }
+    /// Create a repo that lives only in memory, for tests and caches.
+    /// Creating, initializing and committing it never touch the disk and snapshots are not supported
+    pub fn ephemeral() -> TuringEngine {
+        let path = Utf8PathBuf::new();
+
+        Self {
+            dbs: DashMap::new(),
+            ops_log: OpsLog::ephemeral(),
+            quarantine: Arc::new(Quarantine::new(&path)),
             repo_dir: path,
+            commit_gate: RwLock::new(()),
+            compaction_gate: RwLock::new(()),
+            db_gates: DashMap::new(),
+            config: TuringConfig::default().disable_compaction(),
+            repo_lock: Mutex::new(None),
+            ephemeral: true,
+            integrity_report: Mutex::new(None),
+            remote: None,
+            cursors: Cursors::default(),
+            transactions: Transactions::default(),
+            locks: DocumentLocks::default(),
+            views: DashMap::new(),
+            changes: ChangeFeed::default(),
+            replica: AtomicBool::new(false),
+            replica_synced: Mutex::new(None),
+        }
+    }
+    /// Check whether the repo lives only in memory
+    pub fn is_ephemeral(&self) -> bool {
+        self.ephemeral
+    }
+
+    /// Replace the default configuration of the engine
+    pub fn set_config(mut self, config: TuringConfig) -> Self {
+        self.ops_log.set_io_backend(config.get_io_backend());
+        self.config = config;
+
+        self
+    }
+
+    pub fn get_config(&self) -> &TuringConfig {
+        &self.config
+    }
+    /// Keep the durable copy of the repo in `backend`, using the repo directory as a local cache
+    /// of its metadata and hot documents. Every commit pushes what changed to the backend.
+    /// Ignored by an ephemeral repo
+    pub fn set_storage(mut self, backend: Arc<dyn StorageBackend>) -> Self {
+        if !self.ephemeral {
+            self.remote = Some(RemoteRepo::new(backend));
+        }
+
+        self
+    }
+
+    /// All the files that have failed their integrity checks and been moved out of the repo
+    pub async fn quarantined(&self) -> Vec<Utf8PathBuf> {
+        self.quarantine.isolated().await
+    }
+
+    pub async fn get_repo_dir(&self) -> &Utf8PathBuf {
+        &self.repo_dir
+    }
+
+    /// Create a repo
+    pub async fn repo_create(&self) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Ok(OpsOutcome::RepoCreated);
+        }
+
+        if let Some(remote) = &self.remote {
+            if remote.exists().await? {
+                return Err(TuringDbError::AlreadyExists);
+            }
+        }
+
+        DirBuilder::new()
+            .recursive(false)
+            .create(&self.repo_dir)
+            .await?;
+
+        self.lock_repo().await?;
+
+        Ok(OpsOutcome::RepoCreated)
+    }
+    /// Prevent other processes from opening the repo while this engine is using it
+    async fn lock_repo(&self) -> TuringResult<()> {
+        let mut repo_lock = self.repo_lock.lock().await;
+
+        if repo_lock.is_none() {
+            *repo_lock = Some(RepoLock::acquire(&self.repo_dir).await?);
+        }
+
+        Ok(())
+    }
+    /// Check if the repository is empty
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+    pub async fn repo_init(&mut self) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Ok(OpsOutcome::RepoInitialized);
+        }
+
+        // An empty cache is filled from the storage backend before anything is read from it
+        if let Some(remote) = &self.remote {
+            DirBuilder::new()
+                .recursive(true)
+                .create(&self.repo_dir)
+                .await?;
+
+            remote.pull(&self.repo_dir, &self.quarantine).await?;
+        }
+
+        let mut repo = async_fs::read_dir(&self.repo_dir).await?;
+
+        self.lock_repo().await?;
+
+        let migrated_from = Migrator::new(
+            &self.repo_dir,
+            &self.ops_log,
+            &self.quarantine,
+            self.config.get_meta_encoding(),
+        )
+        .migrate()
+        .await?;
+
+        while let Some(database_entry) = repo.try_next().await? {
+            let database_name_raw = database_entry.file_name();
+
+            if database_entry.file_type().await?.is_dir() {
+                let database_name: Utf8PathBuf = TuringEngine::to_utf8_path(database_name_raw)?;
+
+                if database_name.as_str().starts_with(RESERVED_DIR_PREFIX) {
+                    continue;
+                }
+
+                let current_db = self.load_db(&database_name).await?;
+
+                self.dbs.insert(database_name, Arc::new(current_db));
+            }
+        }
+
+        for (view_name, definition) in Views::load(&self.repo_dir, &self.quarantine).await? {
+            self.views
+                .insert(view_name, MaterializedView::new(definition));
+        }
+
+        let checkpoint_lsn = match RepoMeta::load(&self.repo_dir, &self.quarantine).await? {
+            Some(repo_meta) => repo_meta.checkpoint_lsn(),
+            None => None,
+        };
+
+        // Redo any operations that were logged after the last commit since they may not have reached the databases
+        for record in self.ops_log.replay().await? {
+            if let Some(checkpoint_lsn) = checkpoint_lsn {
+                if record.lsn() <= checkpoint_lsn {
+                    continue;
+                }
+            }
+
+            match self.replay(&record).await {
+                Ok(_) => (),
+                Err(error) => {
+                    if !TuringEngine::is_already_applied(&error) {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        self.ops_log.resume_after(checkpoint_lsn).await;
+
+        self.repo_commit().await?;
+
+        let quarantined = self.quarantine.isolated().await;
+
+        if !quarantined.is_empty() {
+            Ok(OpsOutcome::RepoRecovered { quarantined })
+        } else if let Some(from) = migrated_from {
+            Ok(OpsOutcome::RepoMigrated {
+                from,
+                to: FORMAT_VERSION,
+            })
+        } else {
+            Ok(OpsOutcome::RepoInitialized)
+        }
+    }
+    /// Read the metadata and list the documents of a database in the repo directory
+    async fn load_db(&self, database_name: &Utf8Path) -> TuringResult<TuringDB> {
+        let database_path = TuringDB::build_path(&self.repo_dir, database_name);
+
+        if !async_fs::metadata(&database_path).await?.is_dir() {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        let mut current_db = match DbMeta::load(&database_path, &self.quarantine).await? {
+            Some(db_meta) => TuringDB::with_meta(db_meta),
+            None => TuringDB::new(),
+        };
+        let committed = current_db.meta.get_mut().committed();
+
+        // The documents of each partition are listed the first time one of them is accessed,
+        // lookups of documents the filters show are missing never list it
+        let partitioning = Partitioning::load(&database_path, &self.quarantine).await?;
+        let filters = BloomFilter::load(&database_path, &self.quarantine, committed).await?;
+        let documents = DocumentIndex::unloaded(partitioning, &database_path, &self.quarantine)
+            .set_filters(filters);
+        let times = TimeIndex::load(&database_path, &self.quarantine).await?;
+        let structure = Structure::load(&database_path, &self.quarantine).await?;
+        let trash = Trash::load(&database_path, &self.quarantine).await?;
+        let collation = Collation::load(&database_path, &self.quarantine).await?;
+        let indexes = Indexes::load(&database_path, &self.quarantine, committed).await?;
+        let unique = UniqueKey::load(&database_path, &self.quarantine).await?;
+        let text = TextIndex::load(&database_path, &self.quarantine, committed).await?;
+        let ttl = TtlIndex::load(&database_path, &self.quarantine, committed).await?;
+        let prefix = PrefixIndex::load(&database_path, &self.quarantine, committed).await?;
+
+        Ok(current_db
+            .set_documents(documents)
+            .set_times(times)
+            .set_structure(structure)
+            .set_trash(trash)
+            .set_collation(collation)
+            .set_indexes(indexes)
+            .set_unique(unique)
+            .set_text(text)
+            .set_ttl(ttl)
+            .set_prefix(prefix))
+    }
+    /// Flush every document to disk and record the position in the ops log that the repo now reflects
+    pub async fn repo_commit(&self) -> TuringResult<OpsOutcome> {
+        self.commit_checkpoint().await?;
+
+        Ok(OpsOutcome::RepoCommitted)
+    }
+    /// Commit the repo then release its lock so another process may open it, for when the engine
+    /// shuts down. Writes made after closing are not protected by the lock
+    pub async fn repo_close(&self) -> TuringResult<OpsOutcome> {
+        self.commit_checkpoint().await?;
+        self.repo_lock.lock().await.take();
+
+        Ok(OpsOutcome::RepoClosed)
+    }
+    /// Commit the repo, returning the checkpoint that was recorded
+    async fn commit_checkpoint(&self) -> TuringResult<Option<u64>> {
+        if self.ephemeral {
+            return Ok(self.ops_log.last_lsn().await);
+        }
+
+        let checkpoint_lsn = {
+            let _gate = self.commit_gate.write().await;
+
+            self.ops_log.last_lsn().await
+        };
+
+        // Writes acknowledged before they were synced are durable once the repo is committed
+        self.ops_log.sync().await?;
+
+        // Only databases that changed since the last commit are written to disk
+        for db in self.dbs.iter() {
+            if db.take_dirty() {
+                if let Err(error) = db
+                    .commit(&self.repo_dir, db.key(), self.config.get_meta_encoding())
+                    .await
+                {
+                    db.mark_dirty();
+
+                    return Err(error);
+                }
+            }
+        }
+
+        let view_definitions = self
+            .views
+            .iter()
+            .map(|view| (view.key().clone(), view.definition().clone()))
+            .collect();
+        Views::persist(&self.repo_dir, &view_definitions).await?;
+
+        RepoMeta::new(checkpoint_lsn)
+            .persist(&self.repo_dir, self.config.get_meta_encoding())
+            .await?;
+
+        if let Some(remote) = &self.remote {
+            remote.push(&self.repo_dir, &self.dbs).await?;
+        }
+
+        if let Some(repo_lock) = &*self.repo_lock.lock().await {
+            repo_lock.refresh().await?;
+        }
+
+        Ok(checkpoint_lsn)
+    }
+    /// Commit the repo then remove the records it no longer needs from the ops log
+    pub async fn log_compact(&self) -> TuringResult<OpsOutcome> {
+        let _compaction = self.compaction_gate.write().await;
+
+        let checkpoint_lsn = self.commit_checkpoint().await?;
+        let removed = self.ops_log.compact(checkpoint_lsn).await?;
+
+        Ok(OpsOutcome::LogCompacted { removed })
+    }
+    /// Every database, document and view of the repo as the writes that create them again,
+    /// and the position in the ops log they hold every write up to. A replica applies them then
+    /// follows the ops log from that position
+    pub async fn replica_snapshot(&self) -> TuringResult<ReplicaSnapshot> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let (lsn, ops) = self.repo_ops().await?;
+
+        Ok(ReplicaSnapshot::new(lsn, ops))
+    }
+    /// The writes that create every database and view of the repo again as of the last record
+    /// of the ops log, along with that record. Writers wait while the repo is read as they do
+    /// while the checkpoint of a commit is taken, so the writes hold none of the later records
+    async fn repo_ops(&self) -> TuringResult<(Option<u64>, Vec<LogOp>)> {
+        let _gate = self.commit_gate.write().await;
+
+        let lsn = self.ops_log.last_lsn().await;
+
+        let databases = self
+            .dbs
+            .iter()
+            .map(|db| (db.key().to_path_buf(), Arc::clone(db.value())))
+            .collect::<Vec<(Utf8PathBuf, Arc<TuringDB>)>>();
+
+        let mut ops = Vec::new();
+        for (db_name, db) in databases {
+            ops.extend(db.creation_ops(&db_name).await?);
+        }
+        // Views are created once the databases they read from are
+        for view in self.views.iter() {
+            ops.push(LogOp::ViewCreate {
+                name: view.key().clone(),
+                definition: view.definition().clone(),
+            });
+        }
+
+        Ok((lsn, ops))
+    }
+    /// Read at most `limit` records logged after `after`, for a replica following the ops log.
+    /// Fails with `LogGap` once compaction removed records the replica has not read yet,
+    /// the replica then copies the repo again with `replica_resync`
+    pub async fn log_read(&self, after: Option<u64>, limit: usize) -> TuringResult<Vec<LogRecord>> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let last_lsn = self.ops_log.last_lsn().await;
+        let mut records = self.ops_log.read_range(after, last_lsn).await?;
+
+        // Compaction may also have left out writes to documents dropped later in the log,
+        // which is reported as a gap as well
+        let expected = after.map_or(0, |after| after + 1);
+        let missing = match records.first() {
+            Some(record) => record.lsn() > expected,
+            None => last_lsn.is_some_and(|last_lsn| last_lsn >= expected),
+        };
+        if missing {
+            return Err(TuringDbError::LogGap);
+        }
+        records.truncate(limit);
+
+        Ok(records)
+    }
+    /// Whether the repo follows a primary and refuses writes
+    pub fn is_replica(&self) -> bool {
+        self.replica.load(Ordering::Acquire)
+    }
+    /// Refuse writes until `replica_promote`, for a `Replica` following a primary
+    pub(crate) fn replica_follow(&self) {
+        self.replica.store(true, Ordering::Release);
+    }
+    /// Make a replica accept writes, the `Replica` following the primary stops.
+    /// The old primary has to be kept from taking writes before a replica is promoted
+    pub async fn replica_promote(&self) -> TuringResult<OpsOutcome> {
+        self.replica.store(false, Ordering::Release);
+
+        Ok(OpsOutcome::ReplicaPromoted {
+            lsn: self.ops_log.last_lsn().await,
+        })
+    }
+    /// How far the replica may lag behind its primary, `None` until it applied a write of the primary.
+    /// Measured from the time of the last write it applied or, once it applied every write,
+    /// from the time it asked for more, so the clocks of the primary and the replica are compared
+    /// for a replica catching up. Zero for a repo that does not follow a primary
+    pub async fn replica_staleness(&self) -> Option<Duration> {
+        if !self.is_replica() {
+            return Some(Duration::default());
+        }
+
+        self.replica_synced
+            .lock()
+            .await
+            .map(|synced| TAI64N::now().duration_since(&synced).unwrap_or_default())
+    }
+    /// Fail with `StaleReplica` when the replica may lag behind its primary by more than `max_staleness`
+    pub async fn replica_within(&self, max_staleness: Duration) -> TuringResult<()> {
+        match self.replica_staleness().await {
+            Some(staleness) if staleness <= max_staleness => Ok(()),
+            staleness => Err(TuringDbError::StaleReplica {
+                staleness_ms: staleness.map(|staleness| staleness.as_millis() as u64),
+            }),
+        }
+    }
+    /// The replica holds every write its primary made up to `synced`, an earlier time is ignored
+    pub(crate) async fn replica_synced(&self, synced: TAI64N) {
+        let mut replica_synced = self.replica_synced.lock().await;
+
+        if replica_synced.is_none_or(|current| current < synced) {
+            *replica_synced = Some(synced);
+        }
+    }
+    /// The position in the ops log of the primary the replica has applied up to
+    pub async fn replica_lsn(&self) -> Option<u64> {
+        self.ops_log.last_lsn().await
+    }
+    /// The position of the last record of the ops log once every record up to it is on disk,
+    /// for a Raft node to note how far the entries it applied reached
+    pub(crate) async fn synced_lsn(&self) -> TuringResult<Option<u64>> {
+        self.ops_log.sync().await?;
+
+        Ok(self.ops_log.last_lsn().await)
+    }
+    /// Load the databases, documents and views a replica copied from its primary as of `lsn`,
+    /// the ops log of the replica then continues after it. Only an empty repo is seeded,
+    /// a replica whose seeding was interrupted has to start over from an empty repo
+    pub async fn replica_seed(
+        &self,
+        lsn: Option<u64>,
+        ops: Vec<LogOp>,
+    ) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        {
+            let _gate = self.commit_gate.write().await;
+
+            if !self.dbs.is_empty()
+                || !self.views.is_empty()
+                || self.ops_log.last_lsn().await.is_some()
+            {
+                return Err(TuringDbError::AlreadyExists);
+            }
+
+            match lsn {
+                // The primary has not logged anything yet
+                None => return Ok(OpsOutcome::ReplicaSeeded { lsn: None }),
+                Some(lsn) => self.replica_load(lsn, ops).await?,
+            }
+        }
+
+        // The checkpoint of the commit is the position the replica follows the primary from
+        self.repo_commit().await?;
+
+        Ok(OpsOutcome::ReplicaSeeded {
+            lsn: self.ops_log.last_lsn().await,
+        })
+    }
+    /// Replace every view and database of a replica that fell behind the ops log its primary
+    /// still holds with the ones it copied again as of `lsn`, settings and indexes included.
+    /// The records the replica logged before stay in its ops log until compaction,
+    /// recovery only replays the records after `lsn`
+    pub async fn replica_resync(
+        &self,
+        lsn: Option<u64>,
+        ops: Vec<LogOp>,
+    ) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        {
+            let _gate = self.commit_gate.write().await;
+
+            let timestamp = TAI64N::now();
+            let dropped_lsn = self.ops_log.last_lsn().await.unwrap_or_default();
+            let views = self
+                .views
+                .iter()
+                .map(|view| (view.definition().get_db().to_path_buf(), view.key().clone()))
+                .collect::<Vec<(Utf8PathBuf, Utf8PathBuf)>>();
+            for (db, name) in views {
+                self.apply(&LogRecord::new(
+                    dropped_lsn,
+                    timestamp,
+                    LogOp::ViewDrop { db, name },
+                ))
+                .await?;
+            }
+            let db_names = self
+                .dbs
+                .iter()
+                .map(|db| db.key().to_path_buf())
+                .collect::<Vec<Utf8PathBuf>>();
+            for db in db_names {
+                let db_gate = self.db_gate(&db);
+                let _db_gate = db_gate.write().await;
+
+                self.apply(&LogRecord::new(
+                    dropped_lsn,
+                    timestamp,
+                    LogOp::DbDrop { db },
+                ))
+                .await?;
+            }
+
+            if let Some(lsn) = lsn {
+                self.replica_load(lsn, ops).await?;
+            }
+        }
+
+        self.repo_commit().await?;
+
+        Ok(OpsOutcome::ReplicaSeeded {
+            lsn: self.ops_log.last_lsn().await,
+        })
+    }
+    /// Apply the ops copied from the primary at `lsn` and number the next records of the ops log after it
+    async fn replica_load(&self, lsn: u64, ops: Vec<LogOp>) -> TuringResult<()> {
+        self.ops_log.resume_after(Some(lsn)).await;
+
+        let timestamp = TAI64N::now();
+        for op in ops {
+            self.apply(&LogRecord::new(lsn, timestamp, op)).await?;
+        }
+
+        Ok(())
+    }
+    /// Log then apply a record read from the ops log of the primary at the position the primary
+    /// gave it. A record the replica already holds is skipped, the writes of a transaction over several
+    /// databases are applied once the commit record arrives
+    pub async fn replica_apply(&self, record: &LogRecord) -> TuringResult<()> {
+        let _gate = self.commit_gate.read().await;
+        let db_gate = self.db_gate(record.op().db());
+        let _db_gate = db_gate.write().await;
+
+        if let Some(last_lsn) = self.ops_log.last_lsn().await {
+            if record.lsn() <= last_lsn {
+                return Ok(());
+            }
+        }
+
+        let write_acks = self.config.get_db_write_acks(record.op().db());
+        self.ops_log.append_record(record, write_acks).await?;
+
+        let outcome = match record.op() {
+            LogOp::TransactionPrepare { .. } => return Ok(()),
+            LogOp::TransactionCommit { prepared, .. } => {
+                let prepare = self
+                    .ops_log
+                    .read_range(prepared.checked_sub(1), Some(*prepared))
+                    .await?
+                    .into_iter()
+                    .find(|logged| logged.lsn() == *prepared);
+
+                match prepare {
+                    None => {
+                        return Err(TuringDbError::Bug(
+                            "A transaction committed without its prepare in the ops log".into(),
+                        ))
+                    }
+                    Some(prepare) => self.replay(&prepare).await,
+                }
+            }
+            _ => self.replay(record).await,
+        };
+
+        // The primary logs a write before applying it, a write that failed there fails here too
+        match outcome {
+            Err(error) if !TuringEngine::is_already_applied(&error) => Err(error),
+            _ => {
+                self.replica_synced(record.timestamp()).await;
+
+                Ok(())
+            }
+        }
+    }
+    /// Writes from clients are refused while the repo follows a primary
+    fn writable(&self) -> TuringResult<()> {
+        if self.is_replica() {
+            return Err(TuringDbError::ReadOnlyReplica);
+        }
+
+        Ok(())
+    }
+    /// Every database and view of the repo as the writes that create them again,
+    /// for a Raft leader to send to a follower missing entries the leader no longer keeps
+    pub(crate) async fn dump(&self) -> TuringResult<Vec<LogOp>> {
+        let (_, ops) = self.repo_ops().await?;
+
+        Ok(ops)
+    }
+    /// Drop every view and database of the repo then log and apply `ops`,
+    /// as `dump` returned them on another node
+    pub(crate) async fn restore_from(&self, ops: Vec<LogOp>) -> TuringResult<()> {
+        let views = self
+            .views
+            .iter()
+            .map(|view| (view.definition().get_db().to_path_buf(), view.key().clone()))
+            .collect::<Vec<(Utf8PathBuf, Utf8PathBuf)>>();
+        for (db, name) in views {
+            self.log_and_apply(LogOp::ViewDrop { db, name }).await?;
+        }
+
+        let db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
+        for db_name in db_names {
+            self.log_and_apply(LogOp::DbDrop { db: db_name }).await?;
+        }
+
+        for op in ops {
+            self.log_and_apply(op).await?;
+        }
+
+        Ok(())
+    }
+    /// Spawn a task that compacts the ops log at the interval set in the configuration.
+    /// The task ends with the first error it encounters and is not spawned when compaction is disabled
+    pub fn spawn_compaction<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        let compaction_interval = self.config.get_compaction_interval()?;
+
+        Some(executor.spawn(Arc::clone(self).compaction_loop(compaction_interval)))
+    }
+
+    async fn compaction_loop(self: Arc<Self>, compaction_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(compaction_interval).await;
+
+            self.log_compact().await?;
+        }
+    }
+
+    /// Copy the repo into `.snapshots/<name>` without blocking writers.
+    /// The documents are copied between two positions in the ops log and the operations logged
+    /// in between are kept with the snapshot so that restoring it yields a consistent repo
+    pub async fn snapshot(&self, name: &str) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let snapshot_dir = snapshot_dir(&self.repo_dir, name)?;
+
+        TuringEngine::create_target_dir(&snapshot_dir).await?;
+
+        let (snapshot_meta, _) = self.copy_repo(&snapshot_dir, name).await?;
+
+        Ok(OpsOutcome::SnapshotTaken {
+            name: name.into(),
+            lsn: snapshot_meta.end_lsn(),
+        })
+    }
+    /// Copy the repo into `target_dir` without blocking writers, the same way `snapshot` does.
+    /// The directory must not exist yet. The returned manifest holds the checksum of every
+    /// file written so the copy can be verified with `BackupManifest::verify` before it is used
+    pub async fn backup_to(&self, target_dir: &Utf8Path) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        TuringEngine::create_target_dir(target_dir).await?;
+
+        let name = match target_dir.file_name() {
+            None => return Err(TuringDbError::InvalidInput),
+            Some(name) => name,
+        };
+
+        let (snapshot_meta, written) = self.copy_repo(target_dir, name).await?;
+
+        let manifest = BackupManifest::build(target_dir, snapshot_meta.end_lsn(), &written).await?;
+        manifest.persist(target_dir).await?;
+
+        Ok(OpsOutcome::BackupTaken(manifest))
+    }
+
+    async fn create_target_dir(target_dir: &Utf8Path) -> TuringResult<()> {
+        if let Some(parent_dir) = target_dir.parent() {
+            DirBuilder::new().recursive(true).create(parent_dir).await?;
+        }
+        DirBuilder::new()
+            .recursive(false)
+            .create(target_dir)
+            .await?;
+
+        Ok(())
+    }
+    /// Copy every document into `target_dir` between two positions in the ops log,
+    /// returning the metadata of the copy and the paths of the files written
+    async fn copy_repo(
+        &self,
+        target_dir: &Utf8Path,
+        name: &str,
+    ) -> TuringResult<(SnapshotMeta, Vec<Utf8PathBuf>)> {
+        let _compaction = self.compaction_gate.read().await;
+
+        let start_lsn = {
+            let _gate = self.commit_gate.write().await;
+
+            self.ops_log.last_lsn().await
+        };
+
+        let (databases, documents) = self.document_handles().await?;
+
+        let mut written = Vec::with_capacity(documents.len() + 1);
+
+        for (db_name, document_name, document) in documents.iter() {
+            document
+                .capture()?
+                .persist(target_dir, db_name, document_name)
+                .await?;
+
+            written.push(SnapshotDocument::path(target_dir, db_name, document_name));
+        }
+
+        let end_lsn = {
+            let _gate = self.commit_gate.write().await;
+
+            self.ops_log.last_lsn().await
+        };
+
+        let redo = self.ops_log.read_range(start_lsn, end_lsn).await?;
+
+        let documents = documents
+            .into_iter()
+            .map(|(db_name, document_name, _)| (db_name, document_name))
+            .collect();
+
+        let snapshot_meta = SnapshotMeta::new(name, start_lsn, end_lsn, databases, documents, redo);
+        snapshot_meta.persist(target_dir).await?;
+
+        written.push(SnapshotMeta::path(target_dir));
+
+        Ok((snapshot_meta, written))
+    }
+    /// Replace every database in the repo with the contents of a snapshot.
+    /// Writers are blocked until the snapshot is restored and committed
+    pub async fn restore(&self, name: &str) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let snapshot_dir = snapshot_dir(&self.repo_dir, name)?;
+
+        let snapshot_meta = match SnapshotMeta::load(&snapshot_dir, &self.quarantine).await? {
+            Some(snapshot_meta) => snapshot_meta,
+            None => return Err(TuringDbError::SnapshotNotFound),
+        };
+
+        {
+            let _gate = self.commit_gate.write().await;
+
+            // Dropping the databases closes their documents before their directories are removed
+            let current_dbs = self
+                .dbs
+                .iter()
+                .map(|db| db.key().to_path_buf())
+                .collect::<Vec<Utf8PathBuf>>();
+            self.dbs.clear();
+
+            for db_name in current_dbs.iter() {
+                TuringDB::new().db_drop(&self.repo_dir, db_name).await?;
+            }
+
+            for (db_name, db_meta) in snapshot_meta.databases() {
+                TuringDB::new().db_create(&self.repo_dir, db_name).await?;
+
+                let restored_db = TuringDB::with_meta(db_meta.clone());
+                restored_db.mark_dirty();
+                self.dbs
+                    .insert(db_name.to_path_buf(), Arc::new(restored_db));
+            }
+
+            for (db_name, document_name) in snapshot_meta.documents() {
+                let snapshot_document =
+                    SnapshotDocument::load(&snapshot_dir, db_name, document_name, &self.quarantine)
+                        .await?;
+
+                let restored_db = self.db(db_name)?;
+                restored_db
+                    .document_create(&self.repo_dir, db_name, document_name, &self.quarantine)
+                    .await?;
+
+                let restored_document = restored_db.document(document_name).await?;
+                snapshot_document.restore_into(&restored_document)?;
+                // Snapshots taken before delta encoding hold the revisions as full copies
+                let compression = restored_db.meta.lock().await.compression();
+                History::upgrade(&restored_document, compression)?;
+            }
+
+            // Operations that raced with the copy may or may not have reached it
+            for record in snapshot_meta.redo() {
+                match self.replay(record).await {
+                    Ok(_) => (),
+                    Err(error) => {
+                        if !TuringEngine::is_already_applied(&error) {
+                            return Err(error);
+                        }
+                    }
+                }
+            }
+        }
+
+        // The checkpoint moves past every operation in the log so the ones
+        // rolled back by the restore are never replayed
+        self.repo_commit().await?;
+
+        Ok(OpsOutcome::SnapshotRestored {
+            name: name.into(),
+            lsn: snapshot_meta.end_lsn(),
+        })
+    }
+    /// Open every document in the repo so they can be read without holding any locks on the databases.
+    /// Documents in the cold tier are read from their archives instead of being rehydrated
+    async fn document_handles(
+        &self,
+    ) -> TuringResult<(
+        Vec<(Utf8PathBuf, DbMeta)>,
+        Vec<(Utf8PathBuf, Utf8PathBuf, DocumentContents)>,
+    )> {
+        let databases = self.db_metas().await;
+
+        let mut documents = Vec::new();
+
+        for (db_name, _) in databases.iter() {
+            let document_names = match self.dbs.get(db_name) {
+                None => continue,
+                Some(db) => db.list.names().await?,
+            };
+
+            for document_name in document_names {
+                let document = match self.dbs.get(db_name) {
+                    None => break,
+                    Some(db) => match db.document_contents(&document_name).await {
+                        Ok(document) => document,
+                        // Dropped since the names were listed
+                        Err(TuringDbError::DocumentNotFound) => continue,
+                        Err(error) => return Err(error),
+                    },
+                };
+
+                documents.push((db_name.clone(), document_name, document));
+            }
+        }
+
+        Ok((databases, documents))
+    }
+
+    pub async fn db_create(&self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if self.dbs.contains_key(&db_path) || self.views.contains_key(&db_path) {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        match ops.get_partitioning() {
+            Partitioning::Flat => {
+                self.log_and_apply(LogOp::DbCreate {
+                    db: db_path,
+                    compression: ops.get_compression(),
+                })
+                .await
+            }
+            partitioning => {
+                if partitioning.partitions() < 2 {
+                    return Err(TuringDbError::InvalidInput);
+                }
+
+                self.log_and_apply(LogOp::DbCreatePartitioned {
+                    db: db_path,
+                    compression: ops.get_compression(),
+                    partitioning,
+                })
+                .await
+            }
+        }
+    }
+
+    pub async fn db_drop(&self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::NotFound);
+        }
+
+        self.log_and_apply(LogOp::DbDrop { db: db_path }).await
+    }
+    /// Change how new values of fields in a database are compressed to the compression in `ops`.
+    /// Values that were already written keep the compression they were written with
+    pub async fn db_set_compression(&self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetCompression {
+            db: db_path,
+            compression: ops.get_compression(),
+        })
+        .await
+    }
+    /// Declare the schema the documents of a database are held to, or make the database schemaless again.
+    /// Only writes made afterwards are checked, the documents already in the database are left as they are
+    pub async fn db_set_structure(
+        &self,
+        ops: &TuringDBOps,
+        structure: Structure,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        structure.check()?;
+
+        self.log_and_apply(LogOp::DbSetStructure {
+            db: db_path,
+            structure,
+        })
+        .await
+    }
+    /// Get the structure the documents of a database are held to
+    pub async fn db_structure(&self, ops: &TuringDBOps) -> TuringResult<Structure> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.structure.lock().await.clone()),
+        }
+    }
+    /// Make a database soft delete, moving the documents dropped from it into its trash where they are kept
+    /// for `retention` before the reaper purges them. `None` removes dropped documents outright again,
+    /// the documents already in the trash stay until they are purged. Ephemeral repos do not soft delete
+    pub async fn db_set_trash(
+        &self,
+        ops: &TuringDBOps,
+        retention: Option<Duration>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if self.ephemeral && retention.is_some() {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetTrash {
+            db: db_path,
+            retention,
+        })
+        .await
+    }
+    /// List the documents in the trash of a database along with when they were dropped, oldest first
+    pub async fn trash_list(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.trash_list().await),
+        }
+    }
+    /// Purge every document in the trash of a database
+    pub async fn trash_purge(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::TrashPurge {
+            db: db_path,
+            before: TAI64N::now(),
+        })
+        .await
+    }
+    /// Sort the text of a database and compare it in range filters by `collation`,
+    /// for the queries that do not set a collation of their own
+    pub async fn db_set_collation(
+        &self,
+        ops: &TuringDBOps,
+        collation: Collation,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetCollation {
+            db: db_path,
+            collation,
+        })
+        .await
+    }
+    /// Get the collation the queries of a database run with unless they set their own
+    pub async fn db_collation(&self, ops: &TuringDBOps) -> TuringResult<Collation> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(*db.collation.lock().await),
+        }
+    }
+    /// Index a field of the documents of a database, so that queries and aggregations filtering on it
+    /// only open the documents the index does not rule out. The index is built in the background by
+    /// `index_rebuild` without holding up writes, queries scan the documents until it is live, and it is
+    /// kept up to date on every write from then on. A hash index only serves filters requiring
+    /// a value of the field, an ordered one also serves ranges and queries sorted by it.
+    /// An inverted index over an `ARRAY` field serves `Filter::Contains` and combinations of it,
+    /// a geo index over a `GeoPoint` field serves `Filter::Near` and `Filter::Within`
+    pub async fn index_create(
+        &self,
+        ops: &TuringDBOps,
+        key: &[u8],
+        kind: IndexKind,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        match self.dbs.get(&db_path) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if db.index_exists(&[key.to_vec()]).await {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::IndexCreate {
+            db: db_path,
+            key: key.into(),
+            kind,
+        })
+        .await
+    }
+
+    pub async fn index_drop(&self, ops: &TuringDBOps, key: &[u8]) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        match self.dbs.get(&db_path) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if !db.index_exists(&[key.to_vec()]).await {
+                    return Err(TuringDbError::IndexNotFound);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::IndexDrop {
+            db: db_path,
+            key: key.into(),
+        })
+        .await
+    }
+    /// Index several fields of the documents of a database together, in the order they are given.
+    /// The documents are kept in the order of the first field, then of the second among those holding
+    /// the same value of the first and so on, so one index serves a filter requiring a value of each of
+    /// the leading fields along with a range on the field after them, or a query sorted by that field.
+    /// A hash index only serves a filter requiring a value of each of the fields. The index is built in
+    /// the background by `index_rebuild`. Fails with `InvalidInput` without fields, with the same field twice
+    /// or for an inverted index, which is over a single field
+    pub async fn compound_index_create(
+        &self,
+        ops: &TuringDBOps,
+        keys: &[&[u8]],
+        kind: IndexKind,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+        let keys = keys
+            .iter()
+            .map(|key| key.to_vec())
+            .collect::<Vec<Vec<u8>>>();
+
+        match self.dbs.get(&db_path) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if db.index_exists(&keys).await {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::CompoundIndexCreate {
+            db: db_path,
+            keys,
+            kind,
+        })
+        .await
+    }
+
+    pub async fn compound_index_drop(
+        &self,
+        ops: &TuringDBOps,
+        keys: &[&[u8]],
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+        let keys = keys
+            .iter()
+            .map(|key| key.to_vec())
+            .collect::<Vec<Vec<u8>>>();
+
+        match self.dbs.get(&db_path) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if !db.index_exists(&keys).await {
+                    return Err(TuringDbError::IndexNotFound);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::CompoundIndexDrop { db: db_path, keys })
+            .await
+    }
+    /// Index several fields of the documents of a database matching `predicate` only, as
+    /// `compound_index_create` does. The index is smaller and cheaper to keep up to date than one over
+    /// every document, but only serves filters requiring what the predicate requires, such as a filter
+    /// requiring it along with other conditions. It is dropped with `compound_index_drop`
+    pub async fn partial_index_create(
+        &self,
+        ops: &TuringDBOps,
+        keys: &[&[u8]],
+        kind: IndexKind,
+        predicate: Filter,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+        let keys = keys
+            .iter()
+            .map(|key| key.to_vec())
+            .collect::<Vec<Vec<u8>>>();
+
+        match self.dbs.get(&db_path) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if db.index_exists(&keys).await {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::PartialIndexCreate {
+            db: db_path,
+            keys,
+            kind,
+            predicate,
+        })
+        .await
+    }
+    /// Get the predicate of the documents an index over `keys` holds, `None` for an index holding every document
+    pub async fn index_predicate(
+        &self,
+        ops: &TuringDBOps,
+        keys: &[&[u8]],
+    ) -> TuringResult<Option<Filter>> {
+        let keys = keys
+            .iter()
+            .map(|key| key.to_vec())
+            .collect::<Vec<Vec<u8>>>();
+
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.index_predicate(&keys).await,
+        }
+    }
+    /// List the fields and the kind of each index of a database, the indexes in the order of the names of their fields
+    pub async fn index_list(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.index_list().await),
+        }
+    }
+    /// List how each index of a database is declared, how many documents it holds and how many queries and
+    /// aggregations it narrowed down the documents of since the database was loaded, to find the indexes
+    /// that cost writes without serving any query
+    pub async fn db_index_stats(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.index_stats().await),
+        }
+    }
+    /// Make a field the unique key of a database, no two documents may hold the same value of it.
+    /// Fails with `AlreadyExists` if two documents already do. Writes that would leave two documents
+    /// holding the same value fail with `AlreadyExists` before they are logged. `None` drops the unique key
+    pub async fn db_set_unique_key(
+        &self,
+        ops: &TuringDBOps,
+        key: Option<&[u8]>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetUniqueKey {
+            db: db_path,
+            key: key.map(|key| key.to_vec()),
+        })
+        .await
+    }
+    /// Get the unique key of a database, `None` if it has none
+    pub async fn db_unique_key(&self, ops: &TuringDBOps) -> TuringResult<Option<Vec<u8>>> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.unique_key().await),
+        }
+    }
+    /// Open a view of the document holding `value` in the unique key of a database
+    pub async fn get_by_key(&self, ops: &TuringDBOps, value: &Value) -> TuringResult<DocumentView> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let document_name = db.unique_owner(value).await?;
+
+                db.document_view(&document_name).await
+            }
+        }
+    }
+    /// Index the text held in some fields of the documents of a database so it can be searched by its words.
+    /// The index is built in the background by `index_rebuild` without holding up writes, searches scan
+    /// the documents until it is, and it is kept up to date on every write from then on. `None` drops the
+    /// full-text index
+    pub async fn db_set_text_index(
+        &self,
+        ops: &TuringDBOps,
+        definition: Option<TextIndexDefinition>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetTextIndex {
+            db: db_path,
+            definition,
+        })
+        .await
+    }
+    /// Build the indexes created since the last build and those whose entries were lost, failed their checksum
+    /// or were left from another commit when their databases were loaded, returning the databases that had any.
+    /// Writes go on while an index is built, queries scan the documents of a database until it is live
+    pub async fn index_rebuild(&self) -> TuringResult<OpsOutcome> {
+        let db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        let mut rebuilt = Vec::new();
+        for db_name in db_names {
+            if let Some(db) = self.dbs.get(&db_name) {
+                if db.rebuild_indexes().await? {
+                    rebuilt.push(db_name);
+                }
+            }
+        }
+
+        Ok(OpsOutcome::IndexesRebuilt(rebuilt))
+    }
+    /// Spawn a task that builds the indexes waiting to be built, once the repo is initialized or after
+    /// creating indexes. The task ends once every index is built or with the first error it encounters
+    pub fn spawn_index_rebuild<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Task<TuringResult<OpsOutcome>> {
+        let engine = Arc::clone(self);
+
+        executor.spawn(async move { engine.index_rebuild().await })
+    }
+    /// Make a timestamp field the TTL index of a database, its documents expire `expire_after` past the time
+    /// the field holds and are dropped by the reaper, which finds them without scanning the database.
+    /// Changing `expire_after` takes effect the next time the reaper runs. `None` drops the TTL index
+    pub async fn db_set_ttl_index(
+        &self,
+        ops: &TuringDBOps,
+        index: Option<(&[u8], Duration)>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetTtlIndex {
+            db: db_path,
+            index: index.map(|(key, expire_after)| (key.to_vec(), expire_after)),
+        })
+        .await
+    }
+    /// Get the timestamp field of the TTL index of a database along with how long after it the documents
+    /// expire, `None` if it has none
+    pub async fn db_ttl_index(
+        &self,
+        ops: &TuringDBOps,
+    ) -> TuringResult<Option<(Vec<u8>, Duration)>> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.ttl_index().await),
+        }
+    }
+    /// Suggest the values of text fields of a database as they are typed, by `suggest`. The values are
+    /// read from every document the first time suggestions are asked for and kept up to date on every
+    /// write from then on. No fields drops the prefix index
+    pub async fn db_set_prefix_index(
+        &self,
+        ops: &TuringDBOps,
+        keys: &[&[u8]],
+    ) -> TuringResult<OpsOutcome> {
+        let db_path = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_path) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DbSetPrefixIndex {
+            db: db_path,
+            keys: keys.iter().map(|key| key.to_vec()).collect(),
+        })
+        .await
+    }
+    /// Get the fields of the prefix index of a database, none if it has none
+    pub async fn db_prefix_index(&self, ops: &TuringDBOps) -> TuringResult<BTreeSet<Vec<u8>>> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.prefix_index().await),
+        }
+    }
+    /// Suggest at most `limit` distinct values of the field `key` starting with `prefix`, ignoring case,
+    /// in the order of their text for typeahead. Fails with `IndexNotFound` unless the field is in the
+    /// prefix index of the database
+    pub async fn suggest(
+        &self,
+        ops: &TuringDBOps,
+        key: &[u8],
+        prefix: &str,
+        limit: usize,
+    ) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.suggest(key, prefix, limit).await,
+        }
+    }
+    /// Get the definition of the full-text index of a database, `None` if it has none
+    pub async fn db_text_index(
+        &self,
+        ops: &TuringDBOps,
+    ) -> TuringResult<Option<TextIndexDefinition>> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(db.text_index().await),
+        }
+    }
+    /// Find the documents of a database holding any of the words of `text` in the fields of its full-text
+    /// index, ranked by how well they match, best first. Fails with `IndexNotFound` without a full-text index
+    pub async fn search(&self, ops: &TuringDBOps, text: &str) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.search(text, 0).await,
+        }
+    }
+    /// Search the full-text index of a database like `search`, also finding the terms a word of `text`
+    /// is a misspelling of: up to `max_edits` characters inserted, deleted or substituted, at most
+    /// `MAX_FUZZY_EDITS`. Terms found this way rank below the exact ones, and short words allow fewer edits.
+    /// Fails with `InvalidInput` past `MAX_FUZZY_EDITS`
+    pub async fn fuzzy_search(
+        &self,
+        ops: &TuringDBOps,
+        text: &str,
+        max_edits: usize,
+    ) -> TuringResult<OpsOutcome> {
+        if max_edits > MAX_FUZZY_EDITS {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.search(text, max_edits).await,
+        }
+    }
+    /// Drop every document in one partition of a hash partitioned database at once
+    /// by removing the directory of the partition
+    pub async fn partition_drop(
+        &self,
+        ops: &TuringDBOps,
+        partition: u16,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                let partitioning = db.list.partitioning();
+
+                if partitioning == Partitioning::Flat || partition >= partitioning.partitions() {
+                    return Err(TuringDbError::PartitionNotFound);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::PartitionDrop {
+            db: db_name,
+            partition,
+        })
+        .await
+    }
+    /// Move a database directory created elsewhere, or restored from a backup, into the repo
+    /// and start serving it under the name of the directory.
+    /// The directory must be on the same filesystem as the repo since it is moved, not copied
+    pub async fn attach(&self, path: &Utf8Path) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let db_name = match path.file_name() {
+            None => return Err(TuringDbError::InvalidInput),
+            Some(db_name) => Utf8PathBuf::from(db_name),
+        };
+        if db_name.as_str().starts_with(RESERVED_DIR_PREFIX) {
+            return Err(TuringDbError::InvalidInput);
+        }
+        if !async_fs::metadata(path).await?.is_dir() {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        let _gate = self.checkpointed_gate().await?;
+
+        if self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        let db_path = TuringDB::build_path(&self.repo_dir, &db_name);
+        async_fs::rename(path, &db_path).await?;
+
+        let attached_db = match self.load_db(&db_name).await {
+            Ok(attached_db) => attached_db,
+            Err(error) => {
+                // Leave the directory where it was found
+                async_fs::rename(&db_path, path).await?;
+
+                return Err(error);
+            }
+        };
+        // Databases detached before delta encoding hold their revisions as full copies
+        if let Some((format_version, _)) =
+            MetaFile::read_versioned::<DbMeta>(&DbMeta::path(&db_path), &self.quarantine).await?
+        {
+            if format_version < DELTA_HISTORY_FORMAT {
+                attached_db.upgrade_histories().await?;
+            }
+            if format_version < TIME_INDEX_FORMAT {
+                attached_db.rebuild_times().await?;
+            }
+        }
+        attached_db.mark_dirty();
+        self.dbs.insert(db_name.clone(), Arc::new(attached_db));
+
+        Ok(OpsOutcome::DbAttached { name: db_name })
+    }
+    /// Stop serving a database and move its directory out of the repo to `target_dir`,
+    /// which must not exist yet. The database is committed first so it can be attached again
+    pub async fn detach(
+        &self,
+        ops: TuringDBOps,
+        target_dir: &Utf8Path,
+    ) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
+
+        let db_name = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        if async_fs::metadata(target_dir).await.is_ok() {
+            return Err(TuringDbError::AlreadyExists);
+        }
+
+        let _gate = self.checkpointed_gate().await?;
+
+        match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.commit(&self.repo_dir, &db_name, self.config.get_meta_encoding())
+                    .await?
+            }
+        }
+
+        // Dropping the database closes its documents before its directory is moved
+        self.dbs.remove(&db_name);
+
+        let db_path = TuringDB::build_path(&self.repo_dir, &db_name);
+        if let Err(error) = async_fs::rename(&db_path, target_dir).await {
+            let restored_db = self.load_db(&db_name).await?;
+            self.dbs.insert(db_name, Arc::new(restored_db));
+
+            return Err(error.into());
+        }
+
+        Ok(OpsOutcome::DbDetached {
+            path: target_dir.to_path_buf(),
+        })
+    }
+    /// Commit the repo and hold the commit gate with nothing logged after the checkpoint.
+    /// Attaching or detaching a database changes the directories that are loaded on startup,
+    /// so no operation logged before it may be replayed against the directories after it
+    async fn checkpointed_gate(&self) -> TuringResult<RwLockWriteGuard<'_, ()>> {
+        loop {
+            let checkpoint_lsn = self.commit_checkpoint().await?;
+            let gate = self.commit_gate.write().await;
+
+            if self.ops_log.last_lsn().await == checkpoint_lsn {
+                return Ok(gate);
+            }
+        }
+    }
+    /// Count the documents and fields of a database and measure how much space it takes up on disk
+    pub async fn db_stats(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => Ok(OpsOutcome::DbStats(db.stats().await?)),
+        }
+    }
+    /// The Merkle tree over the documents of a database, compared with the one a peer holds by `AntiEntropy`
+    pub async fn merkle_tree(&self, ops: &TuringDBOps) -> TuringResult<MerkleTree> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.merkle_tree().await,
+        }
+    }
+    /// The documents of a leaf of the Merkle tree of a database along with their hashes, sorted by name
+    pub async fn merkle_leaf(
+        &self,
+        ops: &TuringDBOps,
+        leaf: usize,
+    ) -> TuringResult<Vec<(Utf8PathBuf, u64)>> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.merkle_leaf(leaf).await,
+        }
+    }
+    /// Apply the writes `AntiEntropy` made to bring documents in line with a peer, returning how many
+    /// were applied. A replica applies them without logging them since its ops log follows the one
+    /// of its primary, then commits the repo so they outlive a restart. Any other repo logs them
+    pub async fn merkle_repair(&self, ops: Vec<LogOp>) -> TuringResult<usize> {
+        let repaired = ops.len();
+        if repaired == 0 {
+            return Ok(0);
+        }
+
+        if !self.is_replica() {
+            for op in ops {
+                match self.log_and_apply(op).await {
+                    Err(error) if !TuringEngine::is_already_applied(&error) => return Err(error),
+                    _ => (),
+                }
+            }
+
+            return Ok(repaired);
+        }
+
+        {
+            let _gate = self.commit_gate.write().await;
+
+            let timestamp = TAI64N::now();
+            let lsn = self.ops_log.last_lsn().await.unwrap_or_default();
+            for op in ops {
+                let db_gate = self.db_gate(op.db());
+                let _db_gate = db_gate.write().await;
+
+                match self.replay(&LogRecord::new(lsn, timestamp, op)).await {
+                    Err(error) if !TuringEngine::is_already_applied(&error) => return Err(error),
+                    _ => (),
+                }
+            }
+        }
+
+        self.repo_commit().await?;
+
+        Ok(repaired)
+    }
+    /// The stats of every database in the repo, sorted by the name of the database
+    pub async fn stats(&self) -> TuringResult<OpsOutcome> {
+        let mut db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
+        db_names.sort();
+
+        let mut stats = Vec::with_capacity(db_names.len());
+
+        for db_name in db_names {
+            match self.dbs.get(&db_name) {
+                // Dropped since the names were listed
+                None => continue,
+                Some(db) => {
+                    let db_stats = db.stats().await?;
+                    stats.push((db_name, db_stats));
+                }
+            }
+        }
+
+        Ok(OpsOutcome::RepoStats(stats))
+    }
+    /// List all the databases in the repo
+    pub fn db_list(&self) -> OpsOutcome {
+        let list = self
+            .dbs
+            .iter()
+            .map(|db| db.key().into())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        if list.is_empty() {
+            OpsOutcome::RepoEmpty
+        } else {
+            OpsOutcome::DbList(list)
+        }
+    }
+    /// List all the databases in the repo
+    pub fn db_list_sorted(&self) -> OpsOutcome {
+        let mut list = self
+            .dbs
+            .iter()
+            .map(|db| db.key().into())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        list.sort();
+
+        if list.is_empty() {
+            OpsOutcome::RepoEmpty
+        } else {
+            OpsOutcome::DbList(list)
+        }
+    }
+    /// List all the documents in the database in any order.
+    /// Every partition of the database that has not been listed yet is listed
+    pub async fn document_list(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.dbs.get(&db_name.to_path_buf()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => TuringDB::document_list(&db).await,
+        }
+    }
+    /// List all documents in a database sorted alphabetically
+    pub async fn document_list_sorted(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.dbs.get(&db_name.to_path_buf()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => TuringDB::document_list_sorted(&db).await,
+        }
+    }
+    /// Find the documents in a database whose fields match `filter` and return them with their fields
+    pub async fn query(&self, ops: &TuringDBOps, filter: &Filter) -> TuringResult<OpsOutcome> {
+        let query = Query::new(&ops.get_db_name()).set_filter(filter.clone());
+
+        self.select(&query).await
+    }
+    /// Run a query, returning the matching documents with the fields it selects.
+    /// A query on the name of a materialized view runs over the rows of the view.
+    /// A write batch or a transaction committed to the database while the query runs is seen
+    /// in full or not at all, the query and the batch wait on each other through the gate of the database
+    pub async fn select(&self, query: &Query) -> TuringResult<OpsOutcome> {
+        let db_gate = self.db_gate(&self.source_db(query.get_db()));
+        let _shared = db_gate.read().await;
+
+        if let Some(db) = self.dbs.get(query.get_db()) {
+            return db.query(query).await;
+        }
+
+        match self.views.get(query.get_db()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(view) => match self.dbs.get(view.definition().get_db()) {
+                None => Err(TuringDbError::DbNotFound),
+                Some(db) => view.query(&db, query).await,
+            },
+        }
+    }
+    /// Run a query on a database to tell how it is run: the indexes that narrowed down the documents it checked,
+    /// none when it checked every one, and how many documents were left to check, opened and returned.
+    /// Each index used counts a hit in `db_index_stats`. A materialized view has no indexes to explain
+    pub async fn explain(&self, query: &Query) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(query.get_db()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.explain(query).await,
+        }
+    }
+    /// Return a uniform random sample of `n` documents of a database with all their fields,
+    /// opening only the documents drawn rather than every document in the database
+    pub async fn sample(&self, ops: &TuringDBOps, n: usize) -> TuringResult<OpsOutcome> {
+        let query = Query::new(&ops.get_db_name()).set_sample(n);
+
+        self.select(&query).await
+    }
+    /// Group the matching documents of a database and compute the accumulators of each group.
+    /// An aggregation on the name of a materialized view runs over the rows of the view.
+    /// Like `select` it never sees part of a write batch
+    pub async fn aggregate(&self, aggregation: &Aggregation) -> TuringResult<OpsOutcome> {
+        let db_gate = self.db_gate(&self.source_db(aggregation.get_db()));
+        let _shared = db_gate.read().await;
+
+        if let Some(db) = self.dbs.get(aggregation.get_db()) {
+            return db.aggregate(aggregation).await;
+        }
+
+        match self.views.get(aggregation.get_db()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(view) => match self.dbs.get(view.definition().get_db()) {
+                None => Err(TuringDbError::DbNotFound),
+                Some(db) => view.aggregate(&db, aggregation).await,
+            },
+        }
+    }
+    /// Create a materialized view named `name` holding the documents of a database matched by `definition`.
+    /// The view is read from the database the first time it is read then kept up to date as the
+    /// database is written to. It is queried by its name like a database that can not be written to
+    pub async fn view_create(
+        &self,
+        name: &Utf8Path,
+        definition: ViewDefinition,
+    ) -> TuringResult<OpsOutcome> {
+        if self.dbs.contains_key(name) || self.views.contains_key(name) {
+            return Err(TuringDbError::AlreadyExists);
+        }
+        if !self.dbs.contains_key(definition.get_db()) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::ViewCreate {
+            name: name.into(),
+            definition,
+        })
+        .await
+    }
+
+    pub async fn view_drop(&self, name: &Utf8Path) -> TuringResult<OpsOutcome> {
+        let db = match self.views.get(name) {
+            None => return Err(TuringDbError::ViewNotFound),
+            Some(view) => view.definition().get_db().to_path_buf(),
+        };
+
+        self.log_and_apply(LogOp::ViewDrop {
+            db,
+            name: name.into(),
+        })
+        .await
+    }
+    /// List the materialized views in the repo sorted alphabetically
+    pub fn view_list(&self) -> OpsOutcome {
+        let mut list = self
+            .views
+            .iter()
+            .map(|view| view.key().into())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        list.sort();
+
+        OpsOutcome::ViewList(list)
+    }
+    /// Read every row of a materialized view, or the groups of its accumulators when it has any
+    pub async fn view_read(&self, name: &Utf8Path) -> TuringResult<OpsOutcome> {
+        let aggregation = match self.views.get(name) {
+            None => return Err(TuringDbError::ViewNotFound),
+            Some(view) => view.definition().aggregation(name),
+        };
+
+        match aggregation {
+            Some(aggregation) => self.aggregate(&aggregation).await,
+            None => self.select(&Query::new(name)).await,
+        }
+    }
+    /// Parse a TuringQL statement and run the query or aggregation it describes
+    pub async fn execute_statement(&self, statement: &str) -> TuringResult<OpsOutcome> {
+        match TuringQL::parse(statement)? {
+            Statement::Select(query) => self.select(&query).await,
+            Statement::Aggregate(aggregation) => self.aggregate(&aggregation).await,
+        }
+    }
+    /// Subscribe to the writes applied to a database from now on, each one as its record in the ops log.
+    /// Writes replayed from the ops log when the repo is opened are not sent
+    pub async fn subscribe(&self, ops: &TuringDBOps) -> TuringResult<Subscription> {
+        let (sender, changes) = async_channel::bounded(CHANGE_BUFFER);
+        self.subscribe_with(&ops.get_db_name(), None, sender)
+            .await?;
+
+        Ok(Subscription::new(changes))
+    }
+    /// Subscribe to the writes applied to one document from now on,
+    /// along with the writes that may have changed any document of its database such as dropping it
+    pub async fn subscribe_document(
+        &self,
+        ops: &TuringDBDocumentOps,
+    ) -> TuringResult<Subscription> {
+        let (sender, changes) = async_channel::bounded(CHANGE_BUFFER);
+        self.subscribe_with(
+            &ops.get_db_name(),
+            Some(ops.get_document_name().as_path()),
+            sender,
+        )
+        .await?;
+
+        Ok(Subscription::new(changes))
+    }
+    /// Send the writes to a database, or to `document` of it, through `sender` which may be shared
+    pub(crate) async fn subscribe_with(
+        &self,
+        db_name: &Utf8Path,
+        document_name: Option<&Utf8Path>,
+        sender: async_channel::Sender<LogRecord>,
+    ) -> TuringResult<()> {
+        if !self.dbs.contains_key(db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.changes.subscribe(db_name, document_name, sender).await;
+
+        Ok(())
+    }
+    /// List the documents of a database created or modified from `from` up to but not including `to`,
+    /// oldest first, along with their times. The documents are found through the time index of
+    /// the database so incremental consumers can pick up recent changes without a scan
+    pub async fn documents_between(
+        &self,
+        ops: &TuringDBOps,
+        field: TimeField,
+        from: TAI64N,
+        to: TAI64N,
+    ) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.documents_between(field, from, to).await,
+        }
+    }
+    /// Find the `k` documents of a vector database whose embeddings are nearest to `query`
+    /// by the distance of its `VectorSpace`, nearest first
+    pub async fn knn(
+        &self,
+        ops: &TuringDBOps,
+        query: &[f32],
+        k: usize,
+    ) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.knn(query, k).await,
+        }
+    }
+    /// Stream the documents a query matches one at a time. They are read from the database
+    /// `SCAN_BATCH` at a time so only one batch is ever held in memory. Each of them is read as
+    /// `select` reads, a write batch committed between two of them is only seen by the later ones.
+    /// The limit of the query is ignored, use `StreamExt::take` to stop early
+    pub fn scan(&self, query: Query) -> impl Stream<Item = TuringResult<Matched>> + '_ {
+        stream::unfold(
+            (Cursor::new(query), VecDeque::new()),
+            move |(mut cursor, mut batch)| async move {
+                loop {
+                    if let Some(document) = batch.pop_front() {
+                        return Some((Ok(document), (cursor, batch)));
+                    }
+
+                    let query = cursor.next_query(SCAN_BATCH)?;
+                    match self.select(&query).await {
+                        Ok(OpsOutcome::DocumentMatches {
+                            documents,
+                            continuation,
+                        }) => {
+                            batch.extend(documents);
+                            cursor.advance(continuation);
+                        }
+                        Ok(_) => {
+                            cursor.advance(None);
+                            let error = TuringDbError::Bug("Query returned no matches".into());
+
+                            return Some((Err(error), (cursor, batch)));
+                        }
+                        Err(error) => {
+                            cursor.advance(None);
+
+                            return Some((Err(error), (cursor, batch)));
+                        }
+                    }
+                }
+            },
+        )
+    }
+    /// Open a cursor over the documents a query matches for a client that reads them over the wire.
+    /// Read it with `cursor_next` and close it with `cursor_close` or `cursor_close_idle`.
+    /// The limit of the query is ignored
+    pub async fn cursor_open(&self, query: Query) -> TuringResult<OpsOutcome> {
+        if !self.dbs.contains_key(query.get_db()) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        query.keyset()?;
+
+        Ok(OpsOutcome::CursorOpened {
+            cursor: self.cursors.open(query),
+        })
+    }
+    /// Read the next `count` documents of a cursor, the cursor is closed once every document has been read
+    pub async fn cursor_next(&self, cursor: u64, count: usize) -> TuringResult<OpsOutcome> {
+        let open = match self.cursors.get(cursor) {
+            None => return Err(TuringDbError::CursorNotFound),
+            Some(open) => open,
+        };
+        let mut open = open.lock().await;
+
+        let query = match open.next_query(count) {
+            None => {
+                return Ok(OpsOutcome::CursorBatch {
+                    documents: Vec::new(),
+                    exhausted: true,
+                })
+            }
+            Some(query) => query,
+        };
+        let documents = match self.select(&query).await? {
+            OpsOutcome::DocumentMatches {
+                documents,
+                continuation,
+            } => {
+                open.advance(continuation);
+
+                documents
+            }
+            _ => return Err(TuringDbError::Bug("Query returned no matches".into())),
+        };
+
+        let exhausted = open.is_exhausted();
+        if exhausted {
+            self.cursors.close(cursor);
+        }
+
+        Ok(OpsOutcome::CursorBatch {
+            documents,
+            exhausted,
+        })
+    }
+    /// Close a cursor before every document has been read
+    pub async fn cursor_close(&self, cursor: u64) -> TuringResult<OpsOutcome> {
+        if !self.cursors.close(cursor) {
+            return Err(TuringDbError::CursorNotFound);
+        }
+
+        Ok(OpsOutcome::CursorClosed)
+    }
+    /// Close the cursors that have not been read since `cutoff`, for clients that went away without closing them
+    pub async fn cursor_close_idle(&self, cutoff: TAI64N) -> TuringResult<OpsOutcome> {
+        Ok(OpsOutcome::CursorsClosed {
+            closed: self.cursors.close_idle(cutoff).await,
+        })
+    }
+    /// Take an advisory lock on a document for `ttl`, answered with `DocumentLeased` holding the fencing
+    /// token of the lease. Fails with `DocumentLocked` while another lease on the document has not expired.
+    /// The lock keeps no write out, see `document_unlock` to release it before it expires
+    pub async fn document_lock(
+        &self,
+        ops: &TuringDBDocumentOps,
+        ttl: Duration,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        Ok(OpsOutcome::DocumentLeased {
+            token: self
+                .locks
+                .acquire(&db_name, &ops.get_document_name(), ttl)?,
+        })
+    }
+    /// Release the lease `token` on a document, failing with `LockNotHeld` when it expired
+    /// and the document was locked again since
+    pub async fn document_unlock(
+        &self,
+        ops: &TuringDBDocumentOps,
+        token: u64,
+    ) -> TuringResult<OpsOutcome> {
+        self.locks
+            .release(&ops.get_db_name(), &ops.get_document_name(), token)?;
+
+        Ok(OpsOutcome::DocumentUnlocked)
+    }
+    /// Release a lease that may already be gone, for a connection going away
+    pub(crate) fn lease_discard(&self, db: &Utf8Path, document: &Utf8Path, token: u64) {
+        self.locks.release(db, document, token).ok();
+    }
+    /// Roll back the transactions that have not been used since `cutoff`, for clients that went away
+    /// or stalled without finishing them. A later commit of one of them fails with `TransactionNotFound`
+    pub async fn transaction_close_idle(&self, cutoff: TAI64N) -> TuringResult<OpsOutcome> {
+        Ok(OpsOutcome::TransactionsClosed {
+            closed: self.transactions.close_idle(cutoff),
+        })
+    }
+    /// Open a transaction on the database of `ops`, see `Transaction`
+    pub async fn begin(&self, ops: &TuringDBOps) -> TuringResult<Transaction<'_>> {
+        match self.transaction_begin(ops).await? {
+            OpsOutcome::TransactionBegun { transaction } => Ok(Transaction::new(self, transaction)),
+            _ => Err(TuringDbError::Bug(
+                "A transaction began with an outcome other than its id".into(),
+            )),
+        }
+    }
+    /// Open a transaction for a client that stages its writes over the wire by the id returned.
+    /// Finish it with `transaction_commit` or `transaction_rollback`
+    pub async fn transaction_begin(&self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        Ok(OpsOutcome::TransactionBegun {
+            transaction: self.transactions.begin(db_name),
+        })
+    }
+    /// Stage a write in a transaction, nothing is checked or applied until it commits
+    pub async fn transaction_write(
+        &self,
+        transaction: u64,
+        op: WriteOp,
+    ) -> TuringResult<OpsOutcome> {
+        Ok(OpsOutcome::TransactionStaged {
+            ops: self.transactions.stage(transaction, None, op)?,
+        })
+    }
+    /// Stage a write to the database of `ops` in a transaction that began on another database,
+    /// see `Transaction::write_to`
+    pub async fn transaction_write_to(
+        &self,
+        transaction: u64,
+        ops: &TuringDBOps,
+        op: WriteOp,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        Ok(OpsOutcome::TransactionStaged {
+            ops: self.transactions.stage(transaction, Some(db_name), op)?,
+        })
+    }
+    /// Set a savepoint in a transaction, see `Transaction::savepoint`
+    pub async fn transaction_savepoint(
+        &self,
+        transaction: u64,
+        name: &str,
+    ) -> TuringResult<OpsOutcome> {
+        self.transactions.savepoint(transaction, name)?;
+
+        Ok(OpsOutcome::SavepointSet)
+    }
+    /// Drop the writes a transaction staged since a savepoint, see `Transaction::rollback_to`
+    pub async fn transaction_rollback_to(
+        &self,
+        transaction: u64,
+        name: &str,
+    ) -> TuringResult<OpsOutcome> {
+        Ok(OpsOutcome::TransactionRolledBack {
+            ops: self.transactions.rollback_to(transaction, name)?,
+        })
+    }
+    /// Apply the writes a transaction staged through `write_batches`. A commit refused by a conflict,
+    /// or by an error that may not happen again, leaves the transaction open to be committed again
+    /// or rolled back, any other outcome closes it
+    pub async fn transaction_commit(&self, transaction: u64) -> TuringResult<OpsOutcome> {
+        let staged = match self.transactions.take(transaction) {
+            None => return Err(TuringDbError::TransactionNotFound),
+            Some(staged) => staged,
+        };
+        let batches = staged
+            .clone()
+            .into_batches()
+            .into_iter()
+            .map(|(db_name, batch)| (TuringDBOps::default().set_db_name(db_name.as_str()), batch))
+            .collect();
+
+        let outcome = self.write_batches(batches).await;
+        if let Err(error) = &outcome {
+            let error = WireError::from(error);
+            if error.is_retryable() || error.get_code() == ErrorCode::Conflict {
+                self.transactions.reopen(transaction, staged);
+            }
+        }
+
+        outcome
+    }
+    /// Whether a transaction is still open, it is closed once it commits or rolls back
+    pub(crate) fn transaction_open(&self, transaction: u64) -> bool {
+        self.transactions.is_open(transaction)
+    }
+    /// Close a transaction without applying the writes it staged
+    pub async fn transaction_rollback(&self, transaction: u64) -> TuringResult<OpsOutcome> {
+        match self.transactions.take(transaction) {
+            None => Err(TuringDbError::TransactionNotFound),
+            Some(staged) => Ok(OpsOutcome::TransactionRolledBack { ops: staged.len() }),
+        }
+    }
+    /// Drop a transaction that may already be closed, for a `Transaction` or a connection going away
+    pub(crate) fn transaction_discard(&self, transaction: u64) {
+        self.transactions.take(transaction);
+    }
+    /// Create a document. In a database with a schema the document is created holding the defaults
+    /// of the schema, creating it fails if the schema requires a field that has no default
+    pub async fn document_create(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        let defaults = match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if db.list.contains(&document_name).await? {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+
+                db.structure.lock().await.check_fields(Vec::new())?
+            }
+        };
+
+        if defaults.is_empty() {
+            return self
+                .log_and_apply(LogOp::DocumentCreate {
+                    db: db_name,
+                    document: document_name,
+                    expires_at: ops.get_expires_at(),
+                })
+                .await;
+        }
+
+        // The document and its defaults are written together so the document is never seen without them
+        let mut batch = vec![WriteOp::DocumentCreate {
+            document: document_name.clone(),
+        }];
+        batch.extend(
+            defaults
+                .into_iter()
+                .map(|(key, value)| WriteOp::FieldInsert {
+                    document: document_name.clone(),
+                    key,
+                    value,
+                }),
+        );
+        self.apply_batch(db_name, batch).await?;
+
+        if ops.get_expires_at().is_some() {
+            self.document_expire(ops).await?;
+        }
+
+        Ok(OpsOutcome::DocumentCreated)
+    }
+    /// Drop a document
+    /// Drop a document, into the trash of its database when the database soft deletes
+    pub async fn document_drop(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+
+        self.log_and_apply(LogOp::DocumentDrop {
+            db: db_name,
+            document: ops.get_document_name(),
+        })
+        .await
+    }
+    /// Take a document out of the trash of its database, as it was when it was dropped.
+    /// A document of the same name created since it was dropped has to be dropped first
+    pub async fn document_restore(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.restore_check(&document_name).await?,
+        }
+
+        self.log_and_apply(LogOp::DocumentRestore {
+            db: db_name,
+            document: document_name,
+        })
+        .await
+    }
+    /// Set the expiry time of a document to the one in `ops`, clearing it if `ops` has none
+    pub async fn document_expire(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                if !db.list.contains(&document_name).await? {
+                    return Err(TuringDbError::DocumentNotFound);
+                }
+            }
+        }
+
+        self.log_and_apply(LogOp::DocumentExpire {
+            db: db_name,
+            document: document_name,
+            expires_at: ops.get_expires_at(),
+        })
+        .await
+    }
+    /// Drop every document whose expiry time has passed.
+    /// The drops go through the ops log so that they survive a restart
+    pub async fn reap_expired(&self) -> TuringResult<OpsOutcome> {
+        // A replica drops expired documents as the primary logs the drops
+        if self.is_replica() {
+            return Ok(OpsOutcome::DocumentsExpired(Vec::new()));
+        }
+        let now = TAI64N::now();
+
+        let db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        let mut expired = Vec::new();
+        for db_name in db_names {
+            if let Ok(db) = self.db(&db_name) {
+                // A document may expire both by its own expiry time and by its TTL field
+                let mut document_names = db
+                    .meta
+                    .lock()
+                    .await
+                    .expired(now)
+                    .into_iter()
+                    .collect::<BTreeSet<Utf8PathBuf>>();
+                document_names.extend(db.ttl_expired(now).await?);
+
+                expired.extend(
+                    document_names
+                        .into_iter()
+                        .map(|document_name| (db_name.clone(), document_name)),
+                );
+            }
+        }
+
+        let mut reaped = Vec::new();
+
+        for (db_name, document_name) in expired {
+            let outcome = self
+                .log_and_apply(LogOp::DocumentDrop {
+                    db: db_name.clone(),
+                    document: document_name.clone(),
+                })
+                .await;
+
+            match outcome {
+                Ok(_) => reaped.push((db_name, document_name)),
+                Err(error) => {
+                    // Dropped by someone else since the scan
+                    if !TuringEngine::is_already_applied(&error) {
+                        return Err(error);
+                    }
+                }
+            }
+        }
+
+        // Documents that have been in a trash for longer than its retention are purged along with them
+        let databases = self
+            .dbs
+            .iter()
+            .map(|db| (db.key().to_path_buf(), Arc::clone(db.value())))
+            .collect::<Vec<(Utf8PathBuf, Arc<TuringDB>)>>();
+
+        for (db_name, db) in databases {
+            let before = match db.trash_retention().await {
+                None => continue,
+                Some(retention) => now - retention,
+            };
+            if !db.has_trashed_before(before).await {
+                continue;
+            }
+
+            if let Err(error) = self
+                .log_and_apply(LogOp::TrashPurge {
+                    db: db_name,
+                    before,
+                })
+                .await
+            {
+                if !TuringEngine::is_already_applied(&error) {
+                    return Err(error);
+                }
+            }
+        }
+
+        Ok(OpsOutcome::DocumentsExpired(reaped))
+    }
+    /// Spawn a task that removes expired documents at the interval set in the configuration.
+    /// The task ends with the first error it encounters and is not spawned when the reaper is disabled
+    pub fn spawn_reaper<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        let reap_interval = self.config.get_reap_interval()?;
+
+        Some(executor.spawn(Arc::clone(self).reaper_loop(reap_interval)))
+    }
+
+    async fn reaper_loop(self: Arc<Self>, reap_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(reap_interval).await;
+
+            self.reap_expired().await?;
+        }
+    }
+    /// Whether a document may be in a database, answered without touching the disk so peers can
+    /// cheaply skip documents they are missing. `false` only when the document is certainly not there
+    pub async fn document_may_exist(&self, ops: &TuringDBDocumentOps) -> TuringResult<bool> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.document_may_exist(&ops.get_document_name()).await,
+        }
+    }
+    /// List the revisions of a document that are still kept, oldest first
+    pub async fn document_history(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.document_history(&ops.get_document_name()).await,
+        }
+    }
+    /// Get the fields of a document as they were at `revision`
+    pub async fn document_get_revision(
+        &self,
+        ops: &TuringDBDocumentOps,
+        revision: u64,
+    ) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.document_get_revision(&ops.get_document_name(), revision)
+                    .await
+            }
+        }
+    }
+    /// Open a read-only view of a document frozen at its current revision.
+    /// Long scans should read through a view so they see a consistent document
+    /// without holding any lock that writers wait on
+    pub async fn document_view(&self, ops: &TuringDBDocumentOps) -> TuringResult<DocumentView> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.document_view(&ops.get_document_name()).await,
+        }
+    }
+    /// Read a document along with the documents its reference fields point to, following references
+    /// up to `depth` levels deep, at most `MAX_POPULATE_DEPTH`. A document that refers back to one
+    /// of the documents above it is not followed again, see `Resolution`
+    pub async fn document_populate(
+        &self,
+        ops: &TuringDBDocumentOps,
+        depth: usize,
+    ) -> TuringResult<OpsOutcome> {
+        let root = Reference::new(&ops.get_db_name(), &ops.get_document_name());
+        let mut path = vec![root.clone()];
+        let mut populated = 0_usize;
+
+        let document = self
+            .populate(
+                &root,
+                depth.min(MAX_POPULATE_DEPTH),
+                &mut path,
+                &mut populated,
+            )
+            .await?;
+
+        Ok(OpsOutcome::DocumentPopulated(document))
+    }
+    /// Read a document and resolve its references, `path` holds the documents being populated above it
+    fn populate<'a>(
+        &'a self,
+        target: &'a Reference,
+        depth: usize,
+        path: &'a mut Vec<Reference>,
+        populated: &'a mut usize,
+    ) -> Pin<Box<dyn Future<Output = TuringResult<Populated>> + 'a>> {
+        Box::pin(async move {
+            // The database is let go of before the references are followed into other databases
+            let fields = match self.dbs.get(target.db()) {
+                None => return Err(TuringDbError::DbNotFound),
+                Some(db) => db.document_fields(target.document()).await?,
+            };
+            *populated += 1;
+
+            let mut references = Vec::new();
+            for (key, field_data) in &fields {
+                let reference = match Reference::decode(field_data) {
+                    Ok(reference) => reference,
+                    Err(_) => continue,
+                };
+
+                let resolution = if depth == 0 {
+                    Resolution::DepthReached
+                } else if path.contains(&reference) {
+                    Resolution::Cycle
+                } else if *populated >= MAX_POPULATED {
+                    Resolution::LimitReached
+                } else {
+                    path.push(reference.clone());
+                    let outcome = self.populate(&reference, depth - 1, path, populated).await;
+                    path.pop();
+
+                    match outcome {
+                        Ok(document) => Resolution::Resolved(Box::new(document)),
+                        Err(TuringDbError::DbNotFound) | Err(TuringDbError::DocumentNotFound) => {
+                            Resolution::NotFound
+                        }
+                        Err(error) => return Err(error),
+                    }
+                };
+
+                references.push((key.clone(), resolution));
+            }
+
+            Ok(Populated::new(fields, references))
+        })
+    }
+    /// Apply a patch to the fields of a document as a single write.
+    /// A patch that does not apply to the current contents of the document fails before it is logged
+    pub async fn document_patch(
+        &self,
+        ops: &TuringDBDocumentOps,
+        patch: &Patch,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        self.check_quota(&db_name, patch.len()).await?;
+
+        self.log_and_apply(LogOp::DocumentPatch {
+            db: db_name,
+            document: document_name,
+            patch: patch.clone(),
+        })
+        .await
+    }
+    /// Create a document holding `fields`, or replace every field of the document if it exists,
+    /// as a single write. Returns `DocumentInserted` or `DocumentReplaced` along with the fields it replaced
+    pub async fn document_upsert(
+        &self,
+        ops: &TuringDBDocumentOps,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        let fields = self
+            .check_structure(&db_name, |structure| structure.check_fields(fields))
+            .await?;
+        let incoming = fields
+            .iter()
+            .map(|(key, value)| (key.len() + value.get_data().len()) as u64)
+            .sum();
+        self.check_quota(&db_name, incoming).await?;
+
+        self.log_and_apply(LogOp::DocumentUpsert {
+            db: db_name,
+            document: ops.get_document_name(),
+            fields,
+        })
+        .await
+    }
+    /// Apply a batch of writes to the documents of a database atomically, either every write is applied or none is.
+    /// The whole batch is checked against the current contents of the database before it is written to
+    /// the ops log as a single record, no other write is applied while that happens
+    pub async fn write_batch(
+        &self,
+        ops: &TuringDBOps,
+        batch: Vec<WriteOp>,
+    ) -> TuringResult<OpsOutcome> {
+        if batch.is_empty() {
+            return Ok(OpsOutcome::BatchWritten { ops: 0 });
+        }
+
+        let db_name = ops.get_db_name();
+        let batch = self
+            .check_structure(&db_name, |structure| {
+                batch
+                    .into_iter()
+                    .map(|op| op.check(structure))
+                    .collect::<TuringResult<Vec<WriteOp>>>()
+            })
+            .await?;
+
+        self.apply_batch(db_name, batch).await
+    }
+    /// Apply batches of writes to several databases of the repo atomically, either every write
+    /// to every database is applied or none is. A single database is written as with `write_batch`,
+    /// more are logged as a `TransactionPrepare` holding every write then a `TransactionCommit`
+    /// so recovery never applies the writes to only some of the databases
+    pub async fn write_batches(
+        &self,
+        batches: Vec<(TuringDBOps, Vec<WriteOp>)>,
+    ) -> TuringResult<OpsOutcome> {
+        let mut checked: Vec<(Utf8PathBuf, Vec<WriteOp>)> = Vec::new();
+
+        for (ops, batch) in batches {
+            if batch.is_empty() {
+                continue;
+            }
+
+            let db_name = ops.get_db_name();
+            let batch = self
+                .check_structure(&db_name, |structure| {
+                    batch
+                        .into_iter()
+                        .map(|op| op.check(structure))
+                        .collect::<TuringResult<Vec<WriteOp>>>()
+                })
+                .await?;
+
+            match checked
+                .iter_mut()
+                .find(|(checked_db, _)| checked_db == &db_name)
+            {
+                Some((_, checked_batch)) => checked_batch.extend(batch),
+                None => checked.push((db_name, batch)),
+            }
+        }
+
+        match checked.len() {
+            0 => Ok(OpsOutcome::BatchWritten { ops: 0 }),
+            1 => {
+                let (db_name, batch) = checked.remove(0);
+
+                self.apply_batch(db_name, batch).await
+            }
+            _ => self.apply_prepared(checked).await,
+        }
+    }
+    /// Check batches to several databases like `apply_batch` does, then log them as a prepare
+    /// and a commit record and apply them
+    async fn apply_prepared(
+        &self,
+        batches: Vec<(Utf8PathBuf, Vec<WriteOp>)>,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _gate = self.commit_gate.read().await;
+        // Gates are taken in the order of the names of their databases so two transactions
+        // over the same databases never each hold a gate the other waits on
+        let mut dbs = batches
+            .iter()
+            .map(|(db_name, _)| db_name.clone())
+            .collect::<Vec<Utf8PathBuf>>();
+        dbs.sort();
+        let db_gates = dbs
+            .iter()
+            .map(|db_name| self.db_gate(db_name))
+            .collect::<Vec<Arc<RwLock<()>>>>();
+        let mut _db_gates = Vec::with_capacity(db_gates.len());
+        for db_gate in &db_gates {
+            _db_gates.push(db_gate.write().await);
+        }
+
+        for (db_name, batch) in &batches {
+            match self.dbs.get(db_name) {
+                None => return Err(TuringDbError::DbNotFound),
+                Some(db) => db.batch_check(batch).await?,
+            }
+            self.check_quota(db_name, batch.iter().map(|op| op.len()).sum())
+                .await?;
+            self.unique_check(&LogOp::WriteBatch {
+                db: db_name.clone(),
+                ops: batch.clone(),
+            })
+            .await?;
+        }
+
+        // The commit is as durable as the strictest of the databases asks,
+        // syncing it syncs the prepare logged before it
+        let write_acks = dbs
+            .iter()
+            .map(|db_name| self.config.get_db_write_acks(db_name))
+            .min_by_key(|write_acks| match write_acks {
+                WriteACKs::Synced => 0,
+                WriteACKs::GroupCommit { .. } => 1,
+                WriteACKs::Buffered => 2,
+            })
+            .unwrap_or_default();
+
+        let prepare = self
+            .ops_log
+            .append(LogOp::TransactionPrepare { batches }, WriteACKs::Buffered)
+            .await?;
+        self.ops_log
+            .append(
+                LogOp::TransactionCommit {
+                    dbs,
+                    prepared: prepare.lsn(),
+                },
+                write_acks,
+            )
+            .await?;
+
+        self.apply(&prepare).await
+    }
+    /// Check a batch whose values were already checked against the schema of the database
+    /// then log and apply it as a single record
+    async fn apply_batch(
+        &self,
+        db_name: Utf8PathBuf,
+        batch: Vec<WriteOp>,
+    ) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _gate = self.commit_gate.read().await;
+        let db_gate = self.db_gate(&db_name);
+        let _db_gate = db_gate.write().await;
+
+        match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.batch_check(&batch).await?,
+        }
+        self.check_quota(&db_name, batch.iter().map(|op| op.len()).sum())
+            .await?;
+
+        let op = LogOp::WriteBatch {
+            db: db_name,
+            ops: batch,
+        };
+        // No other write to the database is applied while the batch holds its gate
+        self.unique_check(&op).await?;
+
+        let write_acks = self.config.get_db_write_acks(op.db());
+        let record = self.ops_log.append(op, write_acks).await?;
+
+        self.apply(&record).await
+    }
+    /// Replace every field of a document with `fields` only if the document is still at `expected_revision`,
+    /// as read from `DocumentView::revision`. Fails with `RevisionConflict` carrying the current revision
+    /// if another write got there first, otherwise returns the revision the document is at after the write
+    pub async fn document_update_if(
+        &self,
+        ops: &TuringDBDocumentOps,
+        expected_revision: u64,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        let fields = match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.structure.lock().await.check_fields(fields)?,
+        };
+        let incoming = fields
+            .iter()
+            .map(|(key, value)| (key.len() + value.get_data().len()) as u64)
+            .sum();
+        self.check_quota(&db_name, incoming).await?;
+
+        self.log_and_apply(LogOp::DocumentUpdateIf {
+            db: db_name,
+            document: document_name,
+            expected_revision,
+            fields,
+        })
+        .await
+    }
+    /// Apply a patch to a document only if the document matches `condition` at the moment the patch
+    /// is written, failing with `ConditionNotMet` otherwise
+    pub async fn document_patch_if(
+        &self,
+        ops: &TuringDBDocumentOps,
+        condition: &Filter,
+        patch: &Patch,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        self.check_quota(&db_name, patch.len()).await?;
+
+        self.log_and_apply(LogOp::DocumentPatchIf {
+            db: db_name,
+            document: document_name,
+            condition: condition.clone(),
+            patch: patch.clone(),
+        })
+        .await
+    }
+    /// Add `delta` to a numeric counter field as a single write and return the value the field holds afterwards.
+    /// Concurrent increments never lose an update, a missing field is created holding `delta`.
+    /// The field keeps its `DataType`, an increment that does not fit it fails with `NumericOverflow`
+    pub async fn document_increment(
+        &self,
+        ops: &TuringDBDocumentOps,
+        key: &[u8],
+        delta: Value,
+    ) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+        let document_name = ops.get_document_name();
+
+        if !self.dbs.contains_key(&db_name) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        self.check_quota(&db_name, Patch::new().increment(key, delta.clone()).len())
+            .await?;
+
+        self.log_and_apply(LogOp::DocumentIncrement {
+            db: db_name,
+            document: document_name,
+            key: key.into(),
+            by: delta,
+        })
+        .await
+    }
+    /// Insert a field and its value, failing if the field already exists
+    pub async fn field_insert(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        if self.field_exists(ops).await? {
+            return Err(TuringDbError::KeyAlreadyExists);
+        }
+        let value = self
+            .check_structure(&ops.get_db_name(), |structure| {
+                structure.check_value(&ops.get_key(), ops.get_value())
+            })
+            .await?;
+        self.check_quota(&ops.get_db_name(), TuringEngine::field_len(ops))
+            .await?;
+
+        self.log_and_apply(LogOp::FieldInsert {
+            db: ops.get_db_name(),
+            document: ops.get_document_name(),
+            key: ops.get_key(),
+            value,
+        })
+        .await
+    }
+    /// Get the contents of a field
+    pub async fn field_get(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        let db_name = ops.get_db_name();
+
+        match self.dbs.get(&db_name) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.field_get(&ops.get_document_name(), &ops.get_key()).await,
+        }
+    }
+    /// Modify the value of an existing field
+    pub async fn field_modify(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        if !self.dbs.contains_key(&ops.get_db_name()) {
+            return Err(TuringDbError::DbNotFound);
+        }
+        let value = self
+            .check_structure(&ops.get_db_name(), |structure| {
+                structure.check_value(&ops.get_key(), ops.get_value())
+            })
+            .await?;
+        self.check_quota(&ops.get_db_name(), TuringEngine::field_len(ops))
+            .await?;
+
+        self.log_and_apply(LogOp::FieldModify {
+            db: ops.get_db_name(),
+            document: ops.get_document_name(),
+            key: ops.get_key(),
+            value,
+        })
+        .await
+    }
+
+    /// Insert a field whose value is read from `reader` in fixed size chunks so that
+    /// it never has to be held in memory. The type of the value is taken from `ops`.
+    /// The chunks are written to the document first and only the manifest describing them
+    /// goes through the ops log, read the value back with `field_get_stream`
+    pub async fn field_insert_stream(
+        &self,
+        ops: &TuringDBFieldOps,
+        reader: impl AsyncRead + Unpin,
+    ) -> TuringResult<OpsOutcome> {
+        if self.field_exists(ops).await? {
+            return Err(TuringDbError::KeyAlreadyExists);
+        }
+        // A stream is never coerced, its value is not held in memory
+        self.check_structure(&ops.get_db_name(), |structure| {
+            structure.check_stream(&ops.get_key(), ops.get_value().get_data_type())
+        })
+        .await?;
+
+        let document = match self.dbs.get(&ops.get_db_name()) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.document(&ops.get_document_name()).await?,
+        };
+
+        let manifest =
+            ChunkedStream::write(&document, ops.get_value().get_data_type(), reader).await?;
+        let upload_id = manifest.upload_id();
+
+        // The length of a stream is only known once it has been written
+        if let Err(error) = self
+            .check_quota(
+                &ops.get_db_name(),
+                ops.get_key().len() as u64 + manifest.len(),
+            )
+            .await
+        {
+            ChunkedStream::discard(&document, upload_id)?;
+
+            return Err(error);
+        }
+
+        let outcome = self
+            .log_and_apply(LogOp::FieldInsertStream {
+                db: ops.get_db_name(),
+                document: ops.get_document_name(),
+                key: ops.get_key(),
+                manifest,
+            })
+            .await;
+
+        if outcome.is_err() {
+            ChunkedStream::discard(&document, upload_id)?;
+        }
+
+        outcome
+    }
+    /// Write the value of a field inserted with `field_insert_stream` to `writer`
+    pub async fn field_get_stream(
+        &self,
+        ops: &TuringDBFieldOps,
+        writer: impl AsyncWrite + Unpin,
+    ) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.field_get_stream(&ops.get_document_name(), &ops.get_key(), writer)
+                    .await
+            }
+        }
+    }
+    /// Remove a field. The removal is kept as a tombstone until it is vacuumed
+    pub async fn field_remove(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        if !self.field_exists(ops).await? {
+            return Err(TuringDbError::FieldNotFound);
+        }
+        self.check_structure(&ops.get_db_name(), |structure| {
+            structure.check_written(vec![(ops.get_key().as_slice(), None)])
+        })
+        .await?;
+
+        self.log_and_apply(LogOp::FieldRemove {
+            db: ops.get_db_name(),
+            document: ops.get_document_name(),
+            key: ops.get_key(),
+        })
+        .await
+    }
+    /// Write a field as the node `node` of a multi-writer cluster, `None` removes it. The write is stamped
+    /// with a version later than the one the field holds, a resolving write settles the conflicts
+    /// of the field. Returns the value as it was written along with the stamp, for the other nodes
+    pub async fn field_stamp(
+        &self,
+        ops: &TuringDBFieldOps,
+        value: Option<TDBCell>,
+        node: u64,
+        resolves: bool,
+    ) -> TuringResult<(Option<TDBCell>, Stamp)> {
+        let db_name = ops.get_db_name();
+        let key = ops.get_key();
+        let value = match value {
+            None => {
+                self.check_structure(&db_name, |structure| {
+                    structure.check_written(vec![(key.as_slice(), None)])
+                })
+                .await?;
+
+                None
+            }
+            Some(value) => {
+                let len = (key.len() + value.get_data().len()) as u64;
+                let value = self
+                    .check_structure(&db_name, |structure| structure.check_value(&key, value))
+                    .await?;
+                self.check_quota(&db_name, len).await?;
+
+                Some(value)
+            }
+        };
+
+        let sled_db = match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.document(&ops.get_document_name()).await?,
+        };
+        let current = Stamp::read(&sled_db, &key)?;
+        let version = Version::next(
+            node,
+            History::current_revision(&sled_db)? + 1,
+            current.map(|current| current.version()),
+        );
+        let stamp = Stamp::new(version, current.map(|current| current.version()), resolves);
+
+        self.log_and_apply(LogOp::FieldMerge {
+            db: db_name,
+            document: ops.get_document_name(),
+            key,
+            value: value.clone(),
+            stamp,
+        })
+        .await?;
+
+        Ok((value, stamp))
+    }
+    /// Apply a write to a field another node of a multi-writer cluster stamped, `None` removes it
+    pub async fn field_merge(
+        &self,
+        ops: &TuringDBFieldOps,
+        value: Option<TDBCell>,
+        stamp: Stamp,
+    ) -> TuringResult<OpsOutcome> {
+        self.log_and_apply(LogOp::FieldMerge {
+            db: ops.get_db_name(),
+            document: ops.get_document_name(),
+            key: ops.get_key(),
+            value,
+            stamp,
+        })
+        .await
+    }
+    /// The version of a field written under multi-writer replication along with the versions of it
+    /// that lost to a concurrent write and were not settled yet
+    pub async fn field_conflicts(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => {
+                db.field_conflicts(&ops.get_document_name(), &ops.get_key())
+                    .await
+            }
+        }
+    }
+    /// Change a CRDT field as the node `node`, 0 outside a multi-writer cluster. Returns the contents
+    /// of the field after the change along with the changes to merge into it on the other nodes
+    pub async fn crdt_update(
+        &self,
+        ops: &TuringDBFieldOps,
+        op: CrdtOp,
+        node: u64,
+    ) -> TuringResult<(Crdt, Crdt)> {
+        let db_name = ops.get_db_name();
+        let key = ops.get_key();
+        let sled_db = match self.dbs.get(&db_name) {
+            None => return Err(TuringDbError::DbNotFound),
+            Some(db) => db.document(&ops.get_document_name()).await?,
+        };
+        let delta = Crdt::delta(
+            Crdt::read(&sled_db, &key)?.as_ref(),
+            op,
+            node,
+            History::current_revision(&sled_db)? + 1,
+        )?;
+        self.check_quota(&db_name, (key.len() + delta.encode()?.len()) as u64)
+            .await?;
+
+        match self.crdt_merge(ops, delta.clone()).await? {
+            OpsOutcome::CrdtState(state) => Ok((state, delta)),
+            _ => Err(TuringDbError::Bug(
+                "A CRDT merge returned no contents".into(),
+            )),
+        }
+    }
+    /// Merge changes into a CRDT field, the ones another node of a multi-writer cluster made
+    pub async fn crdt_merge(
+        &self,
+        ops: &TuringDBFieldOps,
+        delta: Crdt,
+    ) -> TuringResult<OpsOutcome> {
+        self.log_and_apply(LogOp::CrdtMerge {
+            db: ops.get_db_name(),
+            document: ops.get_document_name(),
+            key: ops.get_key(),
+            delta,
         })
+        .await
     }
-
-    pub async fn get_repo_dir(&self) -> &Utf8PathBuf {
-        &self.repo_dir
+    /// The contents of a CRDT field, fails with `CrdtMismatch` for a field that holds no CRDT
+    pub async fn crdt_get(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.crdt_get(&ops.get_document_name(), &ops.get_key()).await,
+        }
+    }
+    /// List the fields removed from a document whose tombstones have not been vacuumed yet
+    pub async fn document_tombstones(&self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
+        match self.dbs.get(&ops.get_db_name()) {
+            None => Err(TuringDbError::DbNotFound),
+            Some(db) => db.document_tombstones(&ops.get_document_name()).await,
+        }
     }
+    /// Purge the tombstones older than the retention window in the configuration.
+    /// Purging does not change what the repo contains so it is not written to the ops log
+    pub async fn vacuum(&self) -> TuringResult<OpsOutcome> {
+        let cutoff = TAI64N::now() - self.config.get_tombstone_retention();
 
-    /// Create a repo
-    pub async fn repo_create(&self) -> TuringResult<OpsOutcome> {
-        DirBuilder::new()
-            .recursive(false)
-            .create(&self.repo_dir)
-            .await?;
+        let db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
 
-        Ok(OpsOutcome::RepoCreated)
+        let mut purged = 0_usize;
+
+        for db_name in db_names {
+            if let Some(db) = self.dbs.get(&db_name) {
+                purged += db.vacuum(cutoff).await?;
+            }
+        }
+
+        Ok(OpsOutcome::Vacuumed { purged })
     }
-    /// Check if the repository is empty
-    pub fn is_empty(&self) -> bool {
-        self.dbs.is_empty()
+    /// Spawn a task that purges old tombstones at the interval set in the configuration.
+    /// The task ends with the first error it encounters and is not spawned when vacuuming is disabled
+    pub fn spawn_vacuum<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        let vacuum_interval = self.config.get_vacuum_interval()?;
+
+        Some(executor.spawn(Arc::clone(self).vacuum_loop(vacuum_interval)))
     }
-    pub async fn repo_init(&mut self) -> TuringResult<OpsOutcome> {
-        let mut repo = async_fs::read_dir(&self.repo_dir).await?;
 
-        while let Some(database_entry) = repo.try_next().await? {
-            let database_name_raw = database_entry.file_name();
+    async fn vacuum_loop(self: Arc<Self>, vacuum_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(vacuum_interval).await;
 
-            if database_entry.file_type().await?.is_dir() {
-                let mut repo = async_fs::read_dir(&database_entry.path()).await?;
-                let mut current_db = TuringDB::new();
+            self.vacuum().await?;
+        }
+    }
 
-                while let Some(document_entry) = repo.try_next().await? {
-                    if document_entry.file_type().await?.is_dir() {
-                        let document_name_raw = document_entry.file_name();
-                        let document_name: Utf8PathBuf =
-                            TuringEngine::to_utf8_path(document_name_raw)?;
+    /// Move the documents that have not been written since `cutoff` into the cold tier.
+    /// Archived documents are rehydrated transparently the next time they are accessed.
+    /// Archiving does not change what the repo contains so it is not written to the ops log
+    pub async fn archive_cold(&self, cutoff: TAI64N) -> TuringResult<OpsOutcome> {
+        if self.ephemeral {
+            return Err(TuringDbError::EphemeralRepo);
+        }
 
-                        let db = sled::Config::default()
-                            .path(document_entry.path())
-                            .create_new(false)
-                            .open()?;
+        // Every write made before the cutoff reaches the storage backend before its document
+        // is archived, since archived documents are not checked for changes when pushing
+        if self.remote.is_some() {
+            self.commit_checkpoint().await?;
+        }
 
-                        current_db.list.insert(document_name.into(), db);
-                    }
-                }
+        let db_names = self
+            .dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
 
-                let database_name: Utf8PathBuf = TuringEngine::to_utf8_path(database_name_raw)?;
-                self.dbs
-                    .insert(Utf8PathBuf::from(database_name), current_db);
+        let mut archived = Vec::new();
+
+        for db_name in db_names {
+            // No write may land in a document while it is being archived
+            let _gate = self.commit_gate.write().await;
+
+            if let Some(db) = self.dbs.get(&db_name) {
+                archived.extend(
+                    db.archive_idle(cutoff)
+                        .await?
+                        .into_iter()
+                        .map(|document_name| (db_name.clone(), document_name)),
+                );
             }
         }
 
-        Ok(OpsOutcome::RepoInitialized)
+        Ok(OpsOutcome::DocumentsArchived(archived))
     }
+    /// Spawn a task that rolls back the transactions left unused for longer than the transaction TTL
+    /// of the configuration and forgets the document leases that expired.
+    /// The task is not spawned when the watchdog is disabled
+    pub fn spawn_watchdog<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        let watchdog_interval = self.config.get_watchdog_interval()?;
 
-    pub async fn db_create(&mut self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
-        let db_path = ops.get_db_name();
-        let db = TuringDB::new();
-
-        let dbop = db.db_create(&self.repo_dir, &db_path).await?;
+        Some(executor.spawn(Arc::clone(self).watchdog_loop(watchdog_interval)))
+    }
 
-        self.dbs.insert(db_path.into(), TuringDB::new());
+    async fn watchdog_loop(self: Arc<Self>, watchdog_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(watchdog_interval).await;
 
-        Ok(dbop)
+            self.transaction_close_idle(TAI64N::now() - self.config.get_transaction_ttl())
+                .await?;
+            self.locks.remove_expired(TAI64N::now());
+        }
     }
+    /// Spawn a task that archives the documents left unwritten for longer than the configuration allows.
+    /// The task ends with the first error it encounters and is not spawned when archiving is disabled
+    pub fn spawn_archiver<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        if self.ephemeral {
+            return None;
+        }
 
-    pub async fn db_drop(&mut self, ops: TuringDBOps) -> TuringResult<OpsOutcome> {
-        let db_path = ops.get_db_name();
-        let db = TuringDB::new();
+        let archive_interval = self.config.get_archive_interval()?;
+
+        Some(executor.spawn(Arc::clone(self).archive_loop(archive_interval)))
+    }
 
-        let dbop = db.db_drop(&self.repo_dir, &db_path).await?;
+    async fn archive_loop(self: Arc<Self>, archive_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(archive_interval).await;
 
-        match self.dbs.remove(&db_path) {
-            Some(_) => Ok(dbop),
-            None => Err(TuringDbError::NotFound),
+            self.archive_cold(TAI64N::now() - self.config.get_cold_after())
+                .await?;
         }
     }
-    /// List all the databases in the repo
-    pub fn db_list(&self) -> OpsOutcome {
-        let list = self
+
+    /// Check the checksums of the metadata files and the contents of every document in the repo.
+    /// Metadata files that fail are quarantined and written again from memory by a commit,
+    /// corrupted documents are quarantined and everything found is reported
+    pub async fn scrub(&self) -> TuringResult<OpsOutcome> {
+        let mut report = IntegrityReport::new();
+
+        if !self.ephemeral {
+            report.file_checked();
+
+            for (path, quarantined) in
+                MetaFile::scrub(&RepoMeta::path(&self.repo_dir), &self.quarantine).await?
+            {
+                report.record(
+                    IntegrityFinding::new(&path, IntegrityIssue::MetaCorrupted)
+                        .set_quarantined(quarantined),
+                );
+            }
+        }
+
+        let db_names = self
             .dbs
             .iter()
-            .map(|db| db.key().into())
+            .map(|db| db.key().to_path_buf())
             .collect::<Vec<Utf8PathBuf>>();
 
-        if list.is_empty() {
-            OpsOutcome::RepoEmpty
-        } else {
-            OpsOutcome::DbList(list)
+        for db_name in db_names {
+            match self.dbs.get(&db_name) {
+                // Dropped since the names were listed
+                None => continue,
+                Some(db) => {
+                    db.scrub(&self.repo_dir, &db_name, &self.quarantine, &mut report)
+                        .await?
+                }
+            }
         }
-    }
-    /// List all the databases in the repo
-    pub fn db_list_sorted(&self) -> OpsOutcome {
-        let mut list = self
-            .dbs
+
+        // Rewrite the quarantined metadata files from memory
+        if report
+            .findings()
             .iter()
-            .map(|db| db.key().into())
-            .collect::<Vec<Utf8PathBuf>>();
+            .any(|finding| finding.issue() == &IntegrityIssue::MetaCorrupted)
+        {
+            self.repo_commit().await?;
+        }
 
-        list.sort();
+        let report = report.finish();
+        *self.integrity_report.lock().await = Some(report.clone());
 
-        if list.is_empty() {
-            OpsOutcome::RepoEmpty
-        } else {
-            OpsOutcome::DbList(list)
+        Ok(OpsOutcome::Scrubbed(report))
+    }
+    /// The report of the last pass of the integrity scrubber, `None` if it has not run yet
+    pub async fn integrity_report(&self) -> Option<IntegrityReport> {
+        self.integrity_report.lock().await.clone()
+    }
+    /// Spawn a task that scrubs the repo at the interval set in the configuration.
+    /// The task ends with the first error it encounters and is not spawned when the scrubber is disabled
+    pub fn spawn_scrubber<'a>(
+        self: &Arc<Self>,
+        executor: &Executor<'a>,
+    ) -> Option<Task<TuringResult<()>>> {
+        let scrub_interval = self.config.get_scrub_interval()?;
+
+        Some(executor.spawn(Arc::clone(self).scrub_loop(scrub_interval)))
+    }
+
+    async fn scrub_loop(self: Arc<Self>, scrub_interval: Duration) -> TuringResult<()> {
+        loop {
+            Timer::after(scrub_interval).await;
+
+            self.scrub().await?;
         }
     }
-    /// List all the documents in the database in any order
-    pub fn document_list(&mut self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
-        let db_name = ops.get_db_name();
 
-        match self.dbs.get(&db_name.to_path_buf()) {
+    /// Reject a write of `incoming` bytes that would take a database over the configured quota
+    async fn check_quota(&self, db_name: &Utf8Path, incoming: u64) -> TuringResult<()> {
+        match self.dbs.get(db_name) {
             None => Err(TuringDbError::DbNotFound),
-            Some(db) => Ok(TuringDB::document_list(&db)),
+            Some(db) => db.check_quota(self.config.get_db_quota(), incoming).await,
         }
     }
-    /// List all documents in a database sorted alphabetically
-    pub fn document_list_sorted(&mut self, ops: &TuringDBOps) -> TuringResult<OpsOutcome> {
-        let db_name = ops.get_db_name();
 
-        match self.dbs.get(&db_name.to_path_buf()) {
+    /// Run `check` against the structure of a database before a write to it is logged
+    async fn check_structure<T, F>(&self, db_name: &Utf8Path, check: F) -> TuringResult<T>
+    where
+        F: FnOnce(&Structure) -> TuringResult<T>,
+    {
+        let db = self.db(db_name)?;
+        let structure = db.structure.lock().await;
+
+        check(&structure)
+    }
+
+    fn field_len(ops: &TuringDBFieldOps) -> u64 {
+        (ops.get_key().len() + ops.get_value().get_data().len()) as u64
+    }
+
+    async fn field_exists(&self, ops: &TuringDBFieldOps) -> TuringResult<bool> {
+        match self.dbs.get(&ops.get_db_name()) {
             None => Err(TuringDbError::DbNotFound),
-            Some(db) => Ok(TuringDB::document_list_sorted(&db)),
+            Some(db) => {
+                db.field_exists(&ops.get_document_name(), &ops.get_key())
+                    .await
+            }
         }
     }
-    /// Create a document
-    pub async fn document_create(&mut self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
-        let db_name = ops.get_db_name();
-
-        match self.dbs.get_mut(&db_name.to_path_buf()) {
+    /// Check that a write leaves no two documents of its database holding the same value of its unique key
+    async fn unique_check(&self, op: &LogOp) -> TuringResult<()> {
+        match self.dbs.get(op.db()) {
             None => Err(TuringDbError::DbNotFound),
-            Some(mut db) => {
-                db.document_create(&self.repo_dir, &ops.get_db_name(), &ops.get_document_name())
+            Some(db) => db.unique_check(&self.repo_dir, op, &self.quarantine).await,
+        }
+    }
+    /// Check that the document a patch, an increment, a conditional write or a field modify is for
+    /// still allows it, an operation failing its check is never logged
+    async fn precondition_check(&self, op: &LogOp) -> TuringResult<()> {
+        match op {
+            LogOp::DocumentPatch {
+                db,
+                document,
+                patch,
+            } => self.db(db)?.patch_check(document, patch).await,
+            LogOp::DocumentUpdateIf {
+                db,
+                document,
+                expected_revision,
+                ..
+            } => {
+                self.db(db)?
+                    .revision_check(document, *expected_revision)
                     .await
             }
+            LogOp::DocumentPatchIf {
+                db,
+                document,
+                condition,
+                patch,
+            } => {
+                let current_db = self.db(db)?;
+                current_db.condition_check(document, condition).await?;
+
+                current_db.patch_check(document, patch).await
+            }
+            LogOp::DocumentIncrement {
+                db,
+                document,
+                key,
+                by,
+            } => {
+                let patch = Patch::new().increment(key, by.clone());
+
+                self.db(db)?.patch_check(document, &patch).await
+            }
+            LogOp::FieldModify {
+                db, document, key, ..
+            } => match self.db(db)?.field_exists(document, key).await? {
+                true => Ok(()),
+                false => Err(TuringDbError::FieldNotFound),
+            },
+            _ => Ok(()),
         }
     }
-    /// Create a document
-    pub async fn document_drop(&mut self, ops: &TuringDBDocumentOps) -> TuringResult<OpsOutcome> {
-        let db_name = ops.get_db_name();
+    /// Write an operation to the ops log then apply it
+    async fn log_and_apply(&self, op: LogOp) -> TuringResult<OpsOutcome> {
+        self.writable()?;
+        let _gate = self.commit_gate.read().await;
+
+        let has_unique_key = match self.dbs.get(op.db()) {
+            None => false,
+            Some(db) => db.unique_key().await.is_some(),
+        };
+        // Creating or dropping a database checks whether it exists then writes to the disk
+        // before the map of databases changes, two of them for the same name never overlap.
+        // A merge reads the version a field holds before writing it so merges never overlap either.
+        // Neither do writes that add or remove documents or change the settings of the database.
+        // A patch, an increment, a conditional write or a field modify checks its document under the gate,
+        // so no other write changes the document between the check and the write
+        let creates_or_drops = matches!(
+            op,
+            LogOp::DbCreate { .. }
+                | LogOp::DbCreatePartitioned { .. }
+                | LogOp::DbDrop { .. }
+                | LogOp::FieldMerge { .. }
+                | LogOp::CrdtMerge { .. }
+                | LogOp::DbSetCompression { .. }
+                | LogOp::DocumentCreate { .. }
+                | LogOp::DocumentDrop { .. }
+                | LogOp::DocumentExpire { .. }
+                | LogOp::PartitionDrop { .. }
+                | LogOp::DocumentUpsert { .. }
+                | LogOp::DbSetStructure { .. }
+                | LogOp::DbSetTrash { .. }
+                | LogOp::DocumentRestore { .. }
+                | LogOp::TrashPurge { .. }
+                | LogOp::DbSetCollation { .. }
+                | LogOp::IndexCreate { .. }
+                | LogOp::IndexDrop { .. }
+                | LogOp::DbSetUniqueKey { .. }
+                | LogOp::CompoundIndexCreate { .. }
+                | LogOp::CompoundIndexDrop { .. }
+                | LogOp::DbSetTextIndex { .. }
+                | LogOp::DbSetTtlIndex { .. }
+                | LogOp::DbSetPrefixIndex { .. }
+                | LogOp::PartialIndexCreate { .. }
+                | LogOp::DocumentPatch { .. }
+                | LogOp::DocumentUpdateIf { .. }
+                | LogOp::DocumentPatchIf { .. }
+                | LogOp::DocumentIncrement { .. }
+                | LogOp::FieldModify { .. }
+        );
+        let db_gate = self.db_gate(op.db());
+        let (_shared, _exclusive) = match has_unique_key || creates_or_drops {
+            false => (Some(db_gate.read().await), None),
+            true => {
+                let exclusive = db_gate.write().await;
+                if has_unique_key {
+                    self.unique_check(&op).await?;
+                }
+                self.precondition_check(&op).await?;
+
+                (None, Some(exclusive))
+            }
+        };
+
+        let write_acks = self.config.get_db_write_acks(op.db());
+        let record = self.ops_log.append(op, write_acks).await?;
+
+        self.apply(&record).await
+    }
+    /// The database a query on `name` reads, the database of the view when `name` names a materialized view
+    fn source_db(&self, name: &Utf8Path) -> Utf8PathBuf {
+        if self.dbs.contains_key(name) {
+            return name.to_path_buf();
+        }
+
+        match self.views.get(name) {
+            Some(view) => view.definition().get_db().to_path_buf(),
+            None => name.to_path_buf(),
+        }
+    }
+    /// Every database of the repo along with its metadata
+    async fn db_metas(&self) -> Vec<(Utf8PathBuf, DbMeta)> {
+        let databases = self
+            .dbs
+            .iter()
+            .map(|db| (db.key().to_path_buf(), Arc::clone(db.value())))
+            .collect::<Vec<(Utf8PathBuf, Arc<TuringDB>)>>();
+
+        let mut metas = Vec::with_capacity(databases.len());
+        for (db_name, db) in databases {
+            let db_meta = db.meta.lock().await.clone();
+            metas.push((db_name, db_meta));
+        }
 
-        match self.dbs.get_mut(&db_name.to_path_buf()) {
+        metas
+    }
+    /// The database `db`, cloned out of the map so that no guard of the map is held while it is written to
+    fn db(&self, db: &Utf8Path) -> TuringResult<Arc<TuringDB>> {
+        match self.dbs.get(db) {
             None => Err(TuringDbError::DbNotFound),
-            Some(mut db) => {
-                db.document_drop(&self.repo_dir, &ops.get_db_name(), &ops.get_document_name())
+            Some(current_db) => Ok(Arc::clone(current_db.value())),
+        }
+    }
+    /// The gate of the database `db`, created the first time a write to the database asks for it
+    fn db_gate(&self, db: &Utf8Path) -> Arc<RwLock<()>> {
+        let gate = self
+            .db_gates
+            .entry(db.to_path_buf())
+            .or_insert_with(|| Arc::new(RwLock::new(())));
+
+        Arc::clone(gate.value())
+    }
+    /// Apply an operation that has already been written to the ops log
+    async fn apply(&self, record: &LogRecord) -> TuringResult<OpsOutcome> {
+        self.apply_logged(record, false).await
+    }
+    /// Apply an operation of the ops log again. Its effects may already have reached the documents,
+    /// so the errors `is_already_applied` accepts are taken to mean the operation went through
+    async fn replay(&self, record: &LogRecord) -> TuringResult<OpsOutcome> {
+        self.apply_logged(record, true).await
+    }
+
+    async fn apply_logged(&self, record: &LogRecord, replaying: bool) -> TuringResult<OpsOutcome> {
+        match record.op() {
+            // The writes of a transaction over several databases are applied as a batch per database,
+            // recovery only replays a prepare whose commit was logged
+            LogOp::TransactionPrepare { batches } => {
+                let mut written = 0;
+                for (db, ops) in batches {
+                    let batch = record.with_op(LogOp::WriteBatch {
+                        db: db.clone(),
+                        ops: ops.clone(),
+                    });
+                    self.apply_record(&batch, replaying).await?;
+                    written += ops.len();
+                }
+
+                Ok(OpsOutcome::BatchWritten { ops: written })
+            }
+            LogOp::TransactionCommit { .. } => Ok(OpsOutcome::BatchWritten { ops: 0 }),
+            _ => self.apply_record(record, replaying).await,
+        }
+    }
+
+    async fn apply_record(&self, record: &LogRecord, replaying: bool) -> TuringResult<OpsOutcome> {
+        let outcome = self.execute(record, replaying).await;
+
+        // A write replayed after it already reached its document fails, yet the time index
+        // may not hold it if the database was not committed since. A live write that failed
+        // changed nothing, so nothing is recorded for it
+        match &outcome {
+            Ok(_) => (),
+            Err(error) if replaying && TuringEngine::is_already_applied(error) => (),
+            Err(_) => return outcome,
+        }
+
+        let db = self.db(record.op().db()).ok();
+        if let Some(db) = &db {
+            db.record_times(record.op(), record.timestamp()).await;
+            db.record_merkle(record.op()).await;
+            db.record_vectors(record.op()).await;
+            db.record_indexes(record.op()).await;
+            db.record_unique(record.op()).await;
+            db.record_text(record.op()).await;
+            db.record_ttl(record.op()).await;
+            db.record_prefix(record.op()).await;
+            db.mark_dirty();
+        }
+
+        for view in self.views.iter() {
+            if view.definition().get_db() == record.op().db() {
+                view.refresh(db.as_deref(), record.op()).await;
+            }
+        }
+
+        if outcome.is_ok() {
+            self.changes.publish(record).await;
+        }
+
+        outcome
+    }
+
+    async fn execute(&self, record: &LogRecord, replaying: bool) -> TuringResult<OpsOutcome> {
+        match record.op() {
+            // Each write gets its own timestamp so that writes to the same document land as distinct revisions.
+            // When a batch is replayed the writes that already made it to disk are skipped
+            LogOp::WriteBatch { db, ops } => {
+                for (index, op) in ops.iter().enumerate() {
+                    let time = record.timestamp() + Duration::from_nanos(index as u64);
+
+                    match self.execute_op(&op.log_op(db), time).await {
+                        Err(error) if !(replaying && TuringEngine::is_already_applied(&error)) => {
+                            return Err(error)
+                        }
+                        _ => (),
+                    }
+                }
+
+                Ok(OpsOutcome::BatchWritten { ops: ops.len() })
+            }
+            op => self.execute_op(op, record.timestamp()).await,
+        }
+    }
+
+    async fn execute_op(&self, op: &LogOp, time: TAI64N) -> TuringResult<OpsOutcome> {
+        match op {
+            LogOp::DbCreate { db, compression } => {
+                if self.dbs.contains_key(db) {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+
+                let mut new_db = if self.ephemeral {
+                    TuringDB::ephemeral()
+                } else {
+                    TuringDB::new().db_create(&self.repo_dir, db).await?;
+
+                    TuringDB::new()
+                };
+                new_db.meta.get_mut().set_compression(*compression);
+
+                match self.dbs.entry(db.to_owned()) {
+                    Entry::Occupied(_) => Err(TuringDbError::AlreadyExists),
+                    Entry::Vacant(entry) => {
+                        entry.insert(Arc::new(new_db));
+
+                        Ok(OpsOutcome::DbCreated)
+                    }
+                }
+            }
+            LogOp::DbDrop { db } => {
+                if !self.ephemeral {
+                    TuringDB::new().db_drop(&self.repo_dir, db).await?;
+                }
+
+                match self.dbs.remove(db) {
+                    Some(_) => Ok(OpsOutcome::DbDropped),
+                    None => Err(TuringDbError::NotFound),
+                }
+            }
+            LogOp::DbSetCompression { db, compression } => {
+                self.db(db)?.meta.lock().await.set_compression(*compression);
+
+                Ok(OpsOutcome::DbCompressionSet)
+            }
+            LogOp::DocumentCreate {
+                db,
+                document,
+                expires_at,
+            } => {
+                let current_db = self.db(db)?;
+
+                let outcome = current_db
+                    .document_create(&self.repo_dir, db, document, &self.quarantine)
+                    .await?;
+
+                if expires_at.is_some() {
+                    current_db.document_expire(document, *expires_at).await?;
+                }
+
+                Ok(outcome)
+            }
+            LogOp::DocumentDrop { db, document } => {
+                self.db(db)?
+                    .document_drop(&self.repo_dir, db, document, time)
+                    .await
+            }
+            LogOp::DocumentExpire {
+                db,
+                document,
+                expires_at,
+            } => self.db(db)?.document_expire(document, *expires_at).await,
+            LogOp::FieldInsert {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                self.db(db)?
+                    .field_insert(document, key, value, time, self.config.get_history_depth())
+                    .await
+            }
+            LogOp::FieldModify {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                self.db(db)?
+                    .field_modify(document, key, value, time, self.config.get_history_depth())
+                    .await
+            }
+            LogOp::FieldInsertStream {
+                db,
+                document,
+                key,
+                manifest,
+            } => {
+                self.db(db)?
+                    .field_link_stream(document, key, manifest)
+                    .await
+            }
+            LogOp::FieldRemove { db, document, key } => {
+                self.db(db)?
+                    .field_remove(document, key, time, self.config.get_history_depth())
+                    .await
+            }
+            LogOp::DbCreatePartitioned {
+                db,
+                compression,
+                partitioning,
+            } => {
+                if self.dbs.contains_key(db) {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+
+                let mut new_db = if self.ephemeral {
+                    TuringDB::ephemeral()
+                } else {
+                    TuringDB::new().db_create(&self.repo_dir, db).await?;
+                    partitioning
+                        .persist(&TuringDB::build_path(&self.repo_dir, db))
+                        .await?;
+
+                    TuringDB::new()
+                }
+                .set_partitioning(*partitioning);
+                new_db.meta.get_mut().set_compression(*compression);
+
+                match self.dbs.entry(db.to_owned()) {
+                    Entry::Occupied(_) => Err(TuringDbError::AlreadyExists),
+                    Entry::Vacant(entry) => {
+                        entry.insert(Arc::new(new_db));
+
+                        Ok(OpsOutcome::DbCreated)
+                    }
+                }
+            }
+            LogOp::PartitionDrop { db, partition } => {
+                self.db(db)?
+                    .partition_drop(&self.repo_dir, db, *partition)
+                    .await
+            }
+            LogOp::DocumentUpsert {
+                db,
+                document,
+                fields,
+            } => {
+                let current_db = self.db(db)?;
+
+                // A document created by another write since is only replaced
+                let inserted = match current_db
+                    .document_create(&self.repo_dir, db, document, &self.quarantine)
+                    .await
+                {
+                    Ok(_) => true,
+                    Err(TuringDbError::AlreadyExists) => false,
+                    Err(error) => return Err(error),
+                };
+
+                let previous = current_db
+                    .document_replace(document, fields, time, self.config.get_history_depth())
+                    .await?;
+
+                if inserted {
+                    Ok(OpsOutcome::DocumentInserted)
+                } else {
+                    Ok(OpsOutcome::DocumentReplaced { previous })
+                }
+            }
+            LogOp::DocumentPatch {
+                db,
+                document,
+                patch,
+            } => {
+                self.db(db)?
+                    .document_patch(document, patch, time, self.config.get_history_depth())
+                    .await
+            }
+            LogOp::DocumentUpdateIf {
+                db,
+                document,
+                expected_revision,
+                fields,
+            } => {
+                self.db(db)?
+                    .document_replace_if(
+                        document,
+                        *expected_revision,
+                        fields,
+                        time,
+                        self.config.get_history_depth(),
+                    )
+                    .await
+            }
+            LogOp::DocumentPatchIf {
+                db,
+                document,
+                condition,
+                patch,
+            } => {
+                self.db(db)?
+                    .document_patch_if(
+                        document,
+                        condition,
+                        patch,
+                        time,
+                        self.config.get_history_depth(),
+                    )
+                    .await
+            }
+            LogOp::DocumentIncrement {
+                db,
+                document,
+                key,
+                by,
+            } => {
+                self.db(db)?
+                    .document_increment(document, key, by, time, self.config.get_history_depth())
+                    .await
+            }
+            LogOp::WriteBatch { .. } => Err(TuringDbError::Bug("Nested write batch".into())),
+            LogOp::TransactionPrepare { .. } | LogOp::TransactionCommit { .. } => Err(
+                TuringDbError::Bug("A transaction record executed as a single write".into()),
+            ),
+            LogOp::DbSetStructure { db, structure } => {
+                self.db(db)?.restructure(structure.clone()).await;
+
+                Ok(OpsOutcome::DbStructureSet)
+            }
+            LogOp::ViewCreate { name, definition } => {
+                if self.dbs.contains_key(name) || self.views.contains_key(name) {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+
+                self.views
+                    .insert(name.to_owned(), MaterializedView::new(definition.clone()));
+
+                Ok(OpsOutcome::ViewCreated)
+            }
+            LogOp::ViewDrop { name, .. } => match self.views.remove(name) {
+                None => Err(TuringDbError::ViewNotFound),
+                Some(_) => Ok(OpsOutcome::ViewDropped),
+            },
+            LogOp::DbSetTrash { db, retention } => {
+                self.db(db)?.set_trash_retention(*retention).await;
+
+                Ok(OpsOutcome::DbTrashSet)
+            }
+            LogOp::DocumentRestore { db, document } => {
+                self.db(db)?
+                    .document_restore(&self.repo_dir, db, document, &self.quarantine)
+                    .await
+            }
+            LogOp::TrashPurge { db, before } => {
+                self.db(db)?.trash_purge(&self.repo_dir, db, *before).await
+            }
+            LogOp::DbSetCollation { db, collation } => {
+                *self.db(db)?.collation.lock().await = *collation;
+
+                Ok(OpsOutcome::DbCollationSet)
+            }
+            LogOp::IndexCreate { db, key, kind } => {
+                self.db(db)?
+                    .index_create(std::slice::from_ref(key), IndexDeclaration::new(*kind))
+                    .await
+            }
+            LogOp::IndexDrop { db, key } => {
+                self.db(db)?.index_drop(std::slice::from_ref(key)).await
+            }
+            LogOp::DbSetUniqueKey { db, key } => self.db(db)?.set_unique_key(key.as_deref()).await,
+            LogOp::CompoundIndexCreate { db, keys, kind } => {
+                self.db(db)?
+                    .index_create(keys, IndexDeclaration::new(*kind))
+                    .await
+            }
+            LogOp::CompoundIndexDrop { db, keys } => self.db(db)?.index_drop(keys).await,
+            LogOp::DbSetTextIndex { db, definition } => {
+                Ok(self.db(db)?.set_text_index(definition.clone()).await)
+            }
+            LogOp::DbSetTtlIndex { db, index } => {
+                Ok(self.db(db)?.set_ttl_index(index.clone()).await)
+            }
+            LogOp::DbSetPrefixIndex { db, keys } => {
+                Ok(self.db(db)?.set_prefix_index(keys.clone()).await)
+            }
+            LogOp::PartialIndexCreate {
+                db,
+                keys,
+                kind,
+                predicate,
+            } => {
+                self.db(db)?
+                    .index_create(
+                        keys,
+                        IndexDeclaration::new(*kind).set_predicate(predicate.clone()),
+                    )
+                    .await
+            }
+            LogOp::FieldMerge {
+                db,
+                document,
+                key,
+                value,
+                stamp,
+            } => {
+                self.db(db)?
+                    .field_merge(
+                        document,
+                        key,
+                        value.as_ref(),
+                        stamp,
+                        time,
+                        self.config.get_history_depth(),
+                    )
+                    .await
+            }
+            LogOp::CrdtMerge {
+                db,
+                document,
+                key,
+                delta,
+            } => {
+                self.db(db)?
+                    .crdt_merge(document, key, delta, time, self.config.get_history_depth())
                     .await
             }
         }
-    } /*
-      ///Insert a field and its value
-      pub async fn field_set(&self, ops: &TuringDBFieldOps) -> TuringResult<OpsOutcome> {
-          let db_name = ops.get_db_name();
-
-          match self.dbs.get_mut(&db_name.to_path_buf()) {
-              None => Err(TuringDbError::DbNotFound),
-              Some(mut db) => {
-                  db.field_set(
-                      &self.repo_dir,
-                      &ops.get_db_name(),
-                      &ops.get_document_name(),
-                      &ops.get_key(),
-                      &ops.get_value(),
-                  )
-                  .await
-              }
-          }
-      }*/
-    /// TODO Document and database stats
+    }
+    /// Errors returned when replaying an operation whose effects were already persisted
+    fn is_already_applied(error: &TuringDbError) -> bool {
+        matches!(
+            error,
+            TuringDbError::AlreadyExists
+                | TuringDbError::NotFound
+                | TuringDbError::DbNotFound
+                | TuringDbError::DocumentNotFound
+                | TuringDbError::KeyAlreadyExists
+                | TuringDbError::FieldNotFound
+                // A patch fails the same way when the document it failed against is replayed
+                | TuringDbError::FieldTypeMismatch
+                | TuringDbError::NumericOverflow
+                // A conditional write that went through has moved the document past its condition
+                | TuringDbError::RevisionConflict { .. }
+                | TuringDbError::ConditionNotMet
+                | TuringDbError::ViewNotFound
+                | TuringDbError::IndexNotFound
+        )
+    }
 
     fn to_utf8_path(value: OsString) -> TuringResult<Utf8PathBuf> {
         match std::path::PathBuf::from(value).to_str() {
@@ -272,3 +4034,141 @@ pub async fn repo_drop(&self) -> Result<DbOps> {
     async_fs::remove_dir_all(REPO_NAME).await?;
     Ok(DbOps::RepoDropped)
 }*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, DocumentTimes, TimeField};
+    use futures_lite::future::block_on;
+
+    async fn times(engine: &TuringEngine, db: &str) -> Vec<(Utf8PathBuf, DocumentTimes)> {
+        let outcome = engine
+            .documents_between(
+                &TuringDBOps::default().set_db_name(db),
+                TimeField::Created,
+                TAI64N::from_system_time(&std::time::UNIX_EPOCH),
+                TAI64N::now() + Duration::from_secs(60),
+            )
+            .await
+            .unwrap();
+
+        match outcome {
+            OpsOutcome::DocumentTimes(times) => times,
+            outcome => panic!("Expected document times, got {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn a_live_write_that_fails_records_nothing() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            engine
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            engine
+                .document_create(
+                    &TuringDBDocumentOps::default()
+                        .set_db_name("orders")
+                        .set_document_name("order"),
+                )
+                .await
+                .unwrap();
+            let created = times(&engine, "orders").await;
+
+            // Creating the document again, as a write racing the first one would, leaves its times alone
+            let duplicate = LogRecord::new(
+                0,
+                TAI64N::now() + Duration::from_secs(1),
+                LogOp::DocumentCreate {
+                    db: "orders".into(),
+                    document: "order".into(),
+                    expires_at: None,
+                },
+            );
+            assert!(matches!(
+                engine.apply(&duplicate).await,
+                Err(TuringDbError::AlreadyExists)
+            ));
+            assert_eq!(times(&engine, "orders").await, created);
+
+            // Replaying it takes the document as already created
+            assert!(matches!(
+                engine.replay(&duplicate).await,
+                Err(TuringDbError::AlreadyExists)
+            ));
+            assert_ne!(times(&engine, "orders").await, created);
+        });
+    }
+
+    #[test]
+    fn a_conditional_write_that_fails_its_check_is_not_logged() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            engine
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            let ops = TuringDBDocumentOps::default()
+                .set_db_name("orders")
+                .set_document_name("order");
+            engine.document_create(&ops).await.unwrap();
+            let revision = engine.document_view(&ops).await.unwrap().revision();
+            let status = vec![(b"status".to_vec(), TDBCell::new(DataType::U8, &[1]))];
+
+            engine
+                .document_update_if(&ops, revision, status.clone())
+                .await
+                .unwrap();
+            let logged = engine.ops_log.last_lsn().await;
+
+            // The document moved past the revision, so the second write is refused before it is logged
+            assert!(matches!(
+                engine.document_update_if(&ops, revision, status).await,
+                Err(TuringDbError::RevisionConflict { .. })
+            ));
+            assert!(matches!(
+                engine
+                    .document_patch_if(&ops, &Filter::exists(b"missing"), &Patch::new())
+                    .await,
+                Err(TuringDbError::ConditionNotMet)
+            ));
+            assert_eq!(engine.ops_log.last_lsn().await, logged);
+        });
+    }
+
+    #[test]
+    fn modifying_a_removed_field_is_not_logged() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            engine
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            engine
+                .document_create(
+                    &TuringDBDocumentOps::default()
+                        .set_db_name("orders")
+                        .set_document_name("order"),
+                )
+                .await
+                .unwrap();
+            let ops = TuringDBFieldOps::default()
+                .db("orders")
+                .document("order")
+                .key(b"status")
+                .value(DataType::U8, &[1]);
+            engine.field_insert(&ops).await.unwrap();
+            engine.field_modify(&ops).await.unwrap();
+            engine.field_remove(&ops).await.unwrap();
+            let logged = engine.ops_log.last_lsn().await;
+
+            assert!(matches!(
+                engine.field_modify(&ops).await,
+                Err(TuringDbError::FieldNotFound)
+            ));
+            assert_eq!(engine.ops_log.last_lsn().await, logged);
+            assert!(!engine.field_exists(&ops).await.unwrap());
+        });
+    }
+}
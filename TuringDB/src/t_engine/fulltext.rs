@@ -0,0 +1,557 @@
+use crate::{
+    Document, FieldSource, LazyDocument, MetaEncoding, MetaFile, Quarantine, TuringDbError,
+    TuringResult, Value,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+use tai64::TAI64N;
+
+const TEXT_INDEX_META_NAME: &str = "TEXT.meta";
+const TEXT_ENTRIES_META_NAME: &str = "TEXT_ENTRIES.meta";
+
+/// How strongly the number of times a term appears in a document raises its score
+const BM25_K1: f64 = 1.2;
+/// How strongly the score of a term is lowered in documents longer than the average
+const BM25_B: f64 = 0.75;
+/// The most insertions, deletions or substitutions of characters a misspelled search term may hold
+pub const MAX_FUZZY_EDITS: usize = 2;
+
+/// Common English words left out of the index and of searches, in alphabetical order
+const STOP_WORDS: [&str; 64] = [
+    "a", "about", "after", "all", "also", "an", "and", "any", "are", "as", "at", "be", "been",
+    "but", "by", "can", "could", "did", "do", "does", "for", "from", "had", "has", "have", "he",
+    "her", "his", "how", "i", "if", "in", "into", "is", "it", "its", "me", "my", "no", "not", "of",
+    "on", "or", "our", "she", "so", "that", "the", "their", "them", "then", "there", "these",
+    "they", "this", "to", "was", "we", "were", "what", "when", "which", "with", "you",
+];
+
+/// The number of times each term appears in the indexed fields of a document
+type Terms = BTreeMap<String, u32>;
+
+/// Defines the full-text index of a database, the text fields it indexes and how their text is broken
+/// into terms. Text is split into words at every character that is not a letter or a digit,
+/// lowercased, and common English words are left out
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct TextIndexDefinition {
+///     fields: Vec<Vec<u8>>,
+///     stemming: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextIndexDefinition {
+    fields: Vec<Vec<u8>>,
+    stemming: bool,
+}
+
+impl TextIndexDefinition {
+    /// Index the text held in the fields `fields`, without stemming
+    pub fn new(fields: &[&[u8]]) -> Self {
+        Self {
+            fields: fields.iter().map(|key| key.to_vec()).collect(),
+            stemming: false,
+        }
+    }
+    /// Reduce English words to their stem so `runs`, `running` and `run` find each other
+    pub fn set_stemming(mut self, stemming: bool) -> Self {
+        self.stemming = stemming;
+
+        self
+    }
+
+    pub fn get_fields(&self) -> &[Vec<u8>] {
+        &self.fields
+    }
+
+    pub fn get_stemming(&self) -> bool {
+        self.stemming
+    }
+    /// Break text into the terms it is indexed and searched by
+    pub(crate) fn terms(&self, text: &str) -> Vec<String> {
+        text.split(|character: char| !character.is_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .filter(|word| STOP_WORDS.binary_search(&word.as_str()).is_err())
+            .map(|word| match self.stemming {
+                true => TextIndexDefinition::stem(&word),
+                false => word,
+            })
+            .collect()
+    }
+    /// A light English stemmer removing the endings of plurals, of the past and present participles
+    /// and of adverbs. Words that are not ASCII or that are too short to carry an ending are kept as they are
+    fn stem(word: &str) -> String {
+        if word.len() <= 3 || !word.is_ascii() {
+            return word.into();
+        }
+
+        let mut stem = word.to_string();
+
+        if stem.ends_with("sses") {
+            stem.truncate(stem.len() - 2);
+        } else if stem.ends_with("ies") && stem.len() > 4 {
+            stem.truncate(stem.len() - 3);
+            stem.push('y');
+        } else if stem.ends_with('s') && !stem.ends_with("ss") && !stem.ends_with("us") {
+            stem.pop();
+        }
+
+        for ending in ["ingly", "edly", "ing", "ed", "ly"].iter() {
+            let kept = stem.len().saturating_sub(ending.len());
+            // The stem has to keep a vowel, so `sing` and `feed` stay whole
+            if stem.ends_with(ending)
+                && kept >= 3
+                && stem[..kept].contains(TextIndexDefinition::is_vowel)
+            {
+                stem.truncate(kept);
+
+                // `running` stems to `run`, `falling` keeps its double `l`
+                let bytes = stem.as_bytes();
+                let doubled = bytes.len() >= 2
+                    && bytes[bytes.len() - 1] == bytes[bytes.len() - 2]
+                    && !TextIndexDefinition::is_vowel(bytes[bytes.len() - 1] as char)
+                    && !matches!(bytes[bytes.len() - 1], b'l' | b's' | b'z');
+                if doubled && *ending != "ly" {
+                    stem.pop();
+                }
+                break;
+            }
+        }
+
+        stem
+    }
+
+    fn is_vowel(character: char) -> bool {
+        matches!(character, 'a' | 'e' | 'i' | 'o' | 'u' | 'y')
+    }
+}
+
+/// A document found by a full-text search along with how well it matches the search
+/// ```
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// pub struct SearchHit {
+///     document: Utf8PathBuf,
+///     score: f64,
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SearchHit {
+    document: Utf8PathBuf,
+    score: f64,
+}
+
+impl SearchHit {
+    pub fn document(&self) -> &Utf8Path {
+        &self.document
+    }
+    /// The BM25 score of the document, higher for a better match
+    pub fn score(&self) -> f64 {
+        self.score
+    }
+}
+
+impl PartialEq for SearchHit {
+    fn eq(&self, other: &SearchHit) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SearchHit {}
+
+impl PartialOrd for SearchHit {
+    fn partial_cmp(&self, other: &SearchHit) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+/// The better match orders first
+impl Ord for SearchHit {
+    fn cmp(&self, other: &SearchHit) -> Ordering {
+        other
+            .score
+            .partial_cmp(&self.score)
+            .unwrap_or_else(|| self.score.is_nan().cmp(&other.score.is_nan()))
+            .then_with(|| self.document.cmp(&other.document))
+    }
+}
+
+/// The full-text index of a database, the terms held by each document and the documents holding each
+/// term. The postings are built the first time the database is searched and kept up to date
+/// as the documents are written to
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct TextIndex {
+///     definition: TextIndexDefinition,
+///     documents: Option<BTreeMap<Utf8PathBuf, Terms>>,
+///     postings: BTreeMap<String, BTreeMap<Utf8PathBuf, u32>>,
+///     length: u64,
+///     rebuilding: Option<BTreeSet<Utf8PathBuf>>,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct TextIndex {
+    definition: TextIndexDefinition,
+    // `None` until the database is first searched, or once the index could not be kept up to date
+    documents: Option<BTreeMap<Utf8PathBuf, Terms>>,
+    // The number of times each term appears in each document holding it
+    postings: BTreeMap<String, BTreeMap<Utf8PathBuf, u32>>,
+    // The number of terms in every document together
+    length: u64,
+    // The documents written since the index started being built again in the background after its
+    // entries were lost, searches scan the documents meanwhile
+    rebuilding: Option<BTreeSet<Utf8PathBuf>>,
+}
+
+impl TextIndex {
+    pub(crate) fn new(definition: TextIndexDefinition) -> Self {
+        Self {
+            definition,
+            documents: None,
+            postings: BTreeMap::new(),
+            length: 0,
+            rebuilding: None,
+        }
+    }
+
+    pub(crate) fn definition(&self) -> &TextIndexDefinition {
+        &self.definition
+    }
+
+    pub(crate) fn is_built(&self) -> bool {
+        self.documents.is_some()
+    }
+
+    pub(crate) fn is_rebuilding(&self) -> bool {
+        self.rebuilding.is_some()
+    }
+    /// Leave the index to be built in the background, searches scan the documents until it is
+    pub(crate) fn build_in_background(&mut self) {
+        if !self.is_built() {
+            self.rebuilding = Some(BTreeSet::new());
+        }
+    }
+    /// Stop building the index again, returning the documents written since it started
+    pub(crate) fn take_rebuilding(&mut self) -> Option<BTreeSet<Utf8PathBuf>> {
+        self.rebuilding.take()
+    }
+    /// Drop the postings, they are built again the next time the database is searched
+    pub(crate) fn reset(&mut self) {
+        self.documents = None;
+        self.postings.clear();
+        self.length = 0;
+        self.rebuilding = None;
+    }
+    /// Read the indexed fields of every document, which rehydrates documents in the cold tier
+    pub(crate) async fn build(
+        &mut self,
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<()> {
+        let mut built = TextIndex::new(self.definition.clone());
+        built.documents = Some(BTreeMap::new());
+
+        for (document_name, document) in documents {
+            let terms = built.read_terms(&document.open().await?)?;
+            built.insert(&document_name, terms);
+        }
+        *self = built;
+
+        Ok(())
+    }
+    /// Bring the postings up to date with a document, `None` once it is no longer in the database
+    pub(crate) fn record(
+        &mut self,
+        document_name: &Utf8Path,
+        sled_db: Option<&Document>,
+    ) -> TuringResult<()> {
+        if let Some(written) = self.rebuilding.as_mut() {
+            written.insert(document_name.to_path_buf());
+        }
+        if !self.is_built() {
+            return Ok(());
+        }
+
+        let terms = match sled_db {
+            None => Terms::new(),
+            Some(sled_db) => self.read_terms(sled_db)?,
+        };
+        self.insert(document_name, terms);
+
+        Ok(())
+    }
+    /// The terms of the indexed fields of a document, fields that do not hold text are left out
+    fn read_terms<S: FieldSource + ?Sized>(&self, source: &S) -> TuringResult<Terms> {
+        let mut terms = Terms::new();
+
+        for key in self.definition.fields.iter() {
+            if let Some(field_data) = source.read_field(key)? {
+                if let Value::Text(text) = Value::from_field(&field_data) {
+                    for term in self.definition.terms(&text) {
+                        *terms.entry(term).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(terms)
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, terms: Terms) {
+        self.remove(document_name);
+        if terms.is_empty() {
+            return;
+        }
+
+        for (term, count) in terms.iter() {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .insert(document_name.to_path_buf(), *count);
+            self.length += *count as u64;
+        }
+        if let Some(documents) = self.documents.as_mut() {
+            documents.insert(document_name.to_path_buf(), terms);
+        }
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        let terms = match self.documents.as_mut() {
+            None => return,
+            Some(documents) => match documents.remove(document_name) {
+                None => return,
+                Some(terms) => terms,
+            },
+        };
+
+        for (term, count) in terms {
+            if let Some(holders) = self.postings.get_mut(&term) {
+                holders.remove(document_name);
+                if holders.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+            self.length -= count as u64;
+        }
+    }
+    /// Rank the documents holding any term of the search by their BM25 score, best first.
+    /// Terms rare among the documents weigh more than common ones. Up to `max_edits` characters of a
+    /// term of the search may be misspelled, see `TextIndex::fuzzy_terms`, the terms of the index it
+    /// finds that way weigh less the more edits away they are
+    pub(crate) fn search(&self, text: &str, max_edits: usize) -> TuringResult<Vec<SearchHit>> {
+        let documents = match self.documents.as_ref() {
+            None => {
+                return Err(TuringDbError::Bug(
+                    "Full-text index searched before it was built".into(),
+                ))
+            }
+            Some(documents) => documents,
+        };
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let total = documents.len() as f64;
+        let average = self.length as f64 / total;
+        // The terms of the index the search finds, weighed by how close they are to a term of the search
+        let mut terms: BTreeMap<&str, f64> = BTreeMap::new();
+        for term in self.definition.terms(text) {
+            for (held, edits) in self.fuzzy_terms(&term, max_edits) {
+                let weight = terms.entry(held).or_insert(0.0);
+                *weight = weight.max(1.0 / (1.0 + edits as f64));
+            }
+        }
+
+        let mut scores: BTreeMap<&Utf8Path, f64> = BTreeMap::new();
+        for (term, weight) in terms {
+            let holders = match self.postings.get(term) {
+                None => continue,
+                Some(holders) => holders,
+            };
+
+            let held = holders.len() as f64;
+            let rarity = (1.0 + (total - held + 0.5) / (held + 0.5)).ln();
+
+            for (document_name, count) in holders {
+                let count = *count as f64;
+                let length = documents
+                    .get(document_name)
+                    .map(|terms| terms.values().sum::<u32>())
+                    .unwrap_or_default() as f64;
+
+                *scores.entry(document_name.as_path()).or_insert(0.0) +=
+                    weight * rarity * count * (BM25_K1 + 1.0)
+                        / (count + BM25_K1 * (1.0 - BM25_B + BM25_B * length / average));
+            }
+        }
+
+        let mut hits = scores
+            .into_iter()
+            .map(|(document_name, score)| SearchHit {
+                document: document_name.to_path_buf(),
+                score,
+            })
+            .collect::<Vec<SearchHit>>();
+        hits.sort();
+
+        Ok(hits)
+    }
+    /// The terms of the index at most `max_edits` insertions, deletions or substitutions of characters
+    /// away from `term`, along with how many. Short terms allow fewer edits so they do not find every
+    /// short term: one edit from three characters and two from six. The terms are walked in order through
+    /// a Levenshtein automaton, the row of edit distances of each prefix is shared by the terms starting
+    /// with it and the terms starting with a prefix already too far from `term` are passed over
+    fn fuzzy_terms(&self, term: &str, max_edits: usize) -> Vec<(&str, usize)> {
+        let target = term.chars().collect::<Vec<char>>();
+        let max_edits = max_edits.min(MAX_FUZZY_EDITS).min(target.len() / 3);
+
+        if max_edits == 0 {
+            return match self.postings.get_key_value(term) {
+                None => Vec::new(),
+                Some((held, _)) => vec![(held.as_str(), 0)],
+            };
+        }
+
+        let mut found = Vec::new();
+        // The distances from each prefix of `target` after each prefix of the current term
+        let mut rows: Vec<Vec<usize>> = vec![(0..=target.len()).collect()];
+        let mut previous: Vec<char> = Vec::new();
+        let mut dead: Option<&str> = None;
+
+        for held in self.postings.keys() {
+            if let Some(prefix) = dead {
+                if held.starts_with(prefix) {
+                    continue;
+                }
+                dead = None;
+            }
+
+            let characters = held.chars().collect::<Vec<char>>();
+            let shared = previous
+                .iter()
+                .zip(characters.iter())
+                .take_while(|(left, right)| left == right)
+                .count()
+                .min(rows.len() - 1);
+            rows.truncate(shared + 1);
+
+            for (position, character) in characters.iter().enumerate().skip(shared) {
+                let above = &rows[position];
+                let mut row = Vec::with_capacity(target.len() + 1);
+                row.push(above[0] + 1);
+                for (column, expected) in target.iter().enumerate() {
+                    let substitution = above[column] + (expected != character) as usize;
+                    row.push(substitution.min(above[column + 1] + 1).min(row[column] + 1));
+                }
+
+                let closest = row.iter().copied().min().unwrap_or_default();
+                rows.push(row);
+                if closest > max_edits {
+                    let end = held
+                        .char_indices()
+                        .nth(position + 1)
+                        .map_or(held.len(), |(end, _)| end);
+                    dead = Some(&held[..end]);
+                    break;
+                }
+            }
+            previous = characters;
+
+            if dead.is_none() {
+                if let Some(edits) = rows.last().and_then(|row| row.last()) {
+                    if *edits <= max_edits {
+                        found.push((held.as_str(), *edits));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Load the full-text index of a database last committed at `committed`. The postings are only trusted
+    /// when they were persisted by that same commit, an index that was built but whose postings are missing,
+    /// failed their checksum or come from another commit is built again in the background
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+        committed: TAI64N,
+    ) -> TuringResult<Option<TextIndex>> {
+        // Whether the index was built when it was persisted
+        let (definition, built) = match MetaFile::read::<Option<(TextIndexDefinition, bool)>>(
+            &TextIndex::path(db_dir),
+            quarantine,
+        )
+        .await?
+        .flatten()
+        {
+            None => return Ok(None),
+            Some(declared) => declared,
+        };
+
+        let mut text = TextIndex::new(definition);
+        if !built {
+            return Ok(Some(text));
+        }
+
+        match MetaFile::read::<(TAI64N, BTreeMap<Utf8PathBuf, Terms>)>(
+            &TextIndex::entries_path(db_dir),
+            quarantine,
+        )
+        .await?
+        {
+            Some((stamp, documents)) if stamp == committed => {
+                text.documents = Some(BTreeMap::new());
+                for (document_name, terms) in documents {
+                    text.insert(&document_name, terms);
+                }
+            }
+            _ => text.rebuilding = Some(BTreeSet::new()),
+        }
+
+        Ok(Some(text))
+    }
+    /// Persist the full-text index along with a commit of its database made at `committed`
+    pub(crate) async fn persist(
+        db_dir: &Utf8Path,
+        text: Option<&TextIndex>,
+        committed: TAI64N,
+    ) -> TuringResult<()> {
+        let declared = text.map(|text| {
+            (
+                text.definition.clone(),
+                text.is_built() || text.is_rebuilding(),
+            )
+        });
+        let documents = text
+            .and_then(|text| text.documents.clone())
+            .unwrap_or_default();
+
+        MetaFile::write(
+            &TextIndex::entries_path(db_dir),
+            &MetaEncoding::Bincode.encode(&(committed, documents))?,
+        )
+        .await?;
+        MetaFile::write(
+            &TextIndex::path(db_dir),
+            &MetaEncoding::Bincode.encode(&declared)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TEXT_INDEX_META_NAME);
+
+        path
+    }
+
+    pub(crate) fn entries_path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TEXT_ENTRIES_META_NAME);
+
+        path
+    }
+}
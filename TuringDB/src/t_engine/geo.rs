@@ -0,0 +1,283 @@
+use crate::{DataType, FieldData, TDBCell, TuringDbError, TuringResult};
+use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
+
+/// The mean radius of the earth in metres, distances are measured over a sphere of this radius
+const EARTH_RADIUS: f64 = 6_371_008.8;
+/// How many times the world is split in four while covering an area with cells,
+/// cells at the edge of the area are kept whole past it
+const COVER_DEPTH: u32 = 10;
+
+/// A point on the earth in degrees. It is stored as a `DataType::GEO` field holding the
+/// latitude then the longitude as little endian `f64`s
+/// ```
+/// #[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// pub struct GeoPoint {
+///     latitude: f64,
+///     longitude: f64,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct GeoPoint {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GeoPoint {
+    const COORDINATE_LEN: usize = 8;
+    /// Fails with `InvalidInput` unless the latitude is within `-90..=90` and the longitude within `-180..=180`
+    pub fn new(latitude: f64, longitude: f64) -> TuringResult<Self> {
+        if !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        Ok(Self {
+            latitude,
+            longitude,
+        })
+    }
+
+    pub fn latitude(&self) -> f64 {
+        self.latitude
+    }
+
+    pub fn longitude(&self) -> f64 {
+        self.longitude
+    }
+    /// The value of a field holding the point
+    pub fn encode(&self) -> TDBCell {
+        let mut data = Vec::with_capacity(GeoPoint::COORDINATE_LEN * 2);
+        data.extend_from_slice(&self.latitude.to_le_bytes());
+        data.extend_from_slice(&self.longitude.to_le_bytes());
+
+        TDBCell::new(DataType::GEO, &data)
+    }
+    /// Read the point a field holds, coordinates out of range are `InvalidData`
+    pub fn decode(field_data: &FieldData) -> TuringResult<GeoPoint> {
+        if field_data.data_type() != DataType::GEO
+            || field_data.data().len() != GeoPoint::COORDINATE_LEN * 2
+        {
+            return Err(TuringDbError::FieldTypeMismatch);
+        }
+
+        let (latitude, longitude) = field_data.data().split_at(GeoPoint::COORDINATE_LEN);
+        match (latitude.try_into(), longitude.try_into()) {
+            (Ok(latitude), Ok(longitude)) => {
+                GeoPoint::new(f64::from_le_bytes(latitude), f64::from_le_bytes(longitude))
+                    .map_err(|_| TuringDbError::InvalidData)
+            }
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The distance to another point in metres along the surface of the earth
+    pub fn distance(&self, other: &GeoPoint) -> f64 {
+        let (latitude, other_latitude) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let half_latitude = (other_latitude - latitude) / 2.0;
+        let half_longitude = (other.longitude - self.longitude).to_radians() / 2.0;
+
+        let haversine = half_latitude.sin().powi(2)
+            + latitude.cos() * other_latitude.cos() * half_longitude.sin().powi(2);
+
+        2.0 * EARTH_RADIUS * haversine.sqrt().min(1.0).asin()
+    }
+    /// The cell of the point on a Z-order curve, the bits of the longitude and of the latitude
+    /// interleaved so nearby points mostly fall in nearby cells
+    pub(crate) fn cell(&self) -> u64 {
+        let latitude = GeoPoint::quantize(self.latitude, 90.0);
+        let longitude = GeoPoint::quantize(self.longitude, 180.0);
+
+        (0..32).fold(0_u64, |cell, bit| {
+            cell | (((longitude >> bit) & 1) as u64) << (bit * 2 + 1)
+                | (((latitude >> bit) & 1) as u64) << (bit * 2)
+        })
+    }
+
+    fn quantize(degrees: f64, bound: f64) -> u32 {
+        (((degrees + bound) / (bound * 2.0)) * u32::MAX as f64) as u32
+    }
+}
+
+impl PartialEq for GeoPoint {
+    fn eq(&self, other: &GeoPoint) -> bool {
+        self.latitude.to_bits() == other.latitude.to_bits()
+            && self.longitude.to_bits() == other.longitude.to_bits()
+    }
+}
+
+impl Eq for GeoPoint {}
+
+/// An area between two latitudes and two longitudes. An area whose west edge is east of
+/// its east edge crosses the antimeridian
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct GeoBox {
+///     south_west: GeoPoint,
+///     north_east: GeoPoint,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GeoBox {
+    south_west: GeoPoint,
+    north_east: GeoPoint,
+}
+
+impl GeoBox {
+    /// Fails with `InvalidInput` if the south west corner is north of the north east corner
+    pub fn new(south_west: GeoPoint, north_east: GeoPoint) -> TuringResult<Self> {
+        if south_west.latitude > north_east.latitude {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        Ok(Self {
+            south_west,
+            north_east,
+        })
+    }
+
+    pub fn south_west(&self) -> GeoPoint {
+        self.south_west
+    }
+
+    pub fn north_east(&self) -> GeoPoint {
+        self.north_east
+    }
+    /// The smallest area holding every point within `radius` metres of `center`.
+    /// It spans every longitude once the circle reaches a pole
+    pub(crate) fn around(center: &GeoPoint, radius: f64) -> GeoBox {
+        let angle = radius / EARTH_RADIUS;
+        let south = center.latitude - angle.to_degrees();
+        let north = center.latitude + angle.to_degrees();
+
+        let spread = (angle.sin() / center.latitude.to_radians().cos()).asin();
+        let (west, east) = if south <= -90.0 || north >= 90.0 || !spread.is_finite() {
+            (-180.0, 180.0)
+        } else {
+            let spread = spread.to_degrees();
+            if spread >= 180.0 {
+                (-180.0, 180.0)
+            } else {
+                (
+                    GeoBox::wrap(center.longitude - spread),
+                    GeoBox::wrap(center.longitude + spread),
+                )
+            }
+        };
+
+        GeoBox {
+            south_west: GeoPoint {
+                latitude: south.max(-90.0),
+                longitude: west,
+            },
+            north_east: GeoPoint {
+                latitude: north.min(90.0),
+                longitude: east,
+            },
+        }
+    }
+
+    fn wrap(longitude: f64) -> f64 {
+        if longitude < -180.0 {
+            longitude + 360.0
+        } else if longitude > 180.0 {
+            longitude - 360.0
+        } else {
+            longitude
+        }
+    }
+
+    pub fn contains(&self, point: &GeoPoint) -> bool {
+        let (west, east) = (self.south_west.longitude, self.north_east.longitude);
+        let within_longitudes = if west <= east {
+            west <= point.longitude && point.longitude <= east
+        } else {
+            west <= point.longitude || point.longitude <= east
+        };
+
+        self.south_west.latitude <= point.latitude
+            && point.latitude <= self.north_east.latitude
+            && within_longitudes
+    }
+    /// The ranges of cells covering the area in the order of the Z-order curve,
+    /// every point of the area has its cell in one of them
+    pub(crate) fn cells(&self) -> Vec<(u64, u64)> {
+        let latitudes = (
+            GeoPoint::quantize(self.south_west.latitude, 90.0),
+            GeoPoint::quantize(self.north_east.latitude, 90.0),
+        );
+        let west = GeoPoint::quantize(self.south_west.longitude, 180.0);
+        let east = GeoPoint::quantize(self.north_east.longitude, 180.0);
+        let longitudes = if west <= east {
+            vec![(west, east)]
+        } else {
+            vec![(0, east), (west, u32::MAX)]
+        };
+
+        let mut ranges = Vec::new();
+        for longitudes in longitudes {
+            GeoBox::cover(latitudes, longitudes, 0, 0, (0, 0), &mut ranges);
+        }
+        ranges.sort_unstable();
+
+        ranges
+            .into_iter()
+            .fold(Vec::new(), |mut merged: Vec<(u64, u64)>, (start, end)| {
+                match merged.last_mut() {
+                    Some(last) if last.1.saturating_add(1) >= start => last.1 = last.1.max(end),
+                    _ => merged.push((start, end)),
+                }
+
+                merged
+            })
+    }
+    /// Split the quadrant at `depth` whose cells start with `prefix` until its parts are either
+    /// inside or outside the area. `corner` is its south west corner as quantized latitude and longitude
+    fn cover(
+        latitudes: (u32, u32),
+        longitudes: (u32, u32),
+        depth: u32,
+        prefix: u64,
+        corner: (u32, u32),
+        ranges: &mut Vec<(u64, u64)>,
+    ) {
+        let span = (1_u64 << (32 - depth)) - 1;
+        let (south, west) = (corner.0 as u64, corner.1 as u64);
+
+        let outside = south + span < latitudes.0 as u64
+            || south > latitudes.1 as u64
+            || west + span < longitudes.0 as u64
+            || west > longitudes.1 as u64;
+        if outside {
+            return;
+        }
+
+        let inside = south >= latitudes.0 as u64
+            && south + span <= latitudes.1 as u64
+            && west >= longitudes.0 as u64
+            && west + span <= longitudes.1 as u64;
+        if inside || depth == COVER_DEPTH {
+            let shift = 64 - depth * 2;
+            let start = ((prefix as u128) << shift) as u64;
+            let end = (((prefix as u128 + 1) << shift) - 1) as u64;
+            ranges.push((start, end));
+
+            return;
+        }
+
+        let half = 1_u32 << (31 - depth);
+        for quadrant in 0..4_u64 {
+            let corner = (
+                corner.0 + (quadrant & 1) as u32 * half,
+                corner.1 + (quadrant >> 1) as u32 * half,
+            );
+
+            GeoBox::cover(
+                latitudes,
+                longitudes,
+                depth + 1,
+                (prefix << 2) | quadrant,
+                corner,
+                ranges,
+            );
+        }
+    }
+}
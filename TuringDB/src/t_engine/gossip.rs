@@ -0,0 +1,413 @@
+use crate::{HashRing, TuringClient, TuringCommand, TuringDbError, TuringResponse, TuringResult};
+use async_executor::Executor;
+use async_io::Timer;
+use async_lock::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// How often a node sends its members to another node of the cluster by default
+pub const DEFAULT_GOSSIP_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a node goes without a new heartbeat by default before it is `Suspect`
+pub const DEFAULT_SUSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a node goes without a new heartbeat by default before it is `Dead`
+pub const DEFAULT_DEAD_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How a node of the cluster looks to the node listing it
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+/// pub enum Health {
+///     Alive,
+///     Suspect,
+///     Dead,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Health {
+    /// A new heartbeat of the node arrived within the suspect timeout
+    Alive,
+    /// The node may be down or cut off, it keeps its shards
+    Suspect,
+    /// No heartbeat arrived within the dead timeout, the node is left out of the ring
+    Dead,
+}
+
+/// A node of the cluster as the gossip spreads it. `generation` is the time the node started at
+/// and `heartbeat` counts up while it runs, a node restarted with the same address beats
+/// what is known of it from before. `shard` tells whether the node holds documents of
+/// the `HashRing` or only routes commands
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct Member {
+///     address: SocketAddr,
+///     generation: u64,
+///     heartbeat: u64,
+///     shard: bool,
+///     health: Health,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Member {
+    address: SocketAddr,
+    generation: u64,
+    heartbeat: u64,
+    shard: bool,
+    // Worked out by the node listing the member, it is not taken from the gossip
+    health: Health,
+}
+
+impl Member {
+    /// The address the `TuringServer` of the node listens on
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn heartbeat(&self) -> u64 {
+        self.heartbeat
+    }
+
+    pub fn shard(&self) -> bool {
+        self.shard
+    }
+
+    pub fn health(&self) -> Health {
+        self.health
+    }
+    /// Whether the member is a newer record of the same node than `other`
+    fn supersedes(&self, other: &Member) -> bool {
+        (self.generation, self.heartbeat) > (other.generation, other.heartbeat)
+    }
+}
+
+/// The nodes of a cluster as one of them knows them, sorted by address,
+/// along with the number of points each shard is given on the `HashRing`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct Topology {
+///     virtual_nodes: usize,
+///     members: Vec<Member>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Topology {
+    virtual_nodes: usize,
+    members: Vec<Member>,
+}
+
+impl Topology {
+    pub(crate) fn new(virtual_nodes: usize, mut members: Vec<Member>) -> Self {
+        members.sort_by_key(|member| member.address);
+
+        Self {
+            virtual_nodes,
+            members,
+        }
+    }
+
+    pub fn virtual_nodes(&self) -> usize {
+        self.virtual_nodes
+    }
+
+    pub fn members(&self) -> &[Member] {
+        &self.members
+    }
+    /// The members that are not `Dead`
+    pub fn live(&self) -> impl Iterator<Item = &Member> {
+        self.members
+            .iter()
+            .filter(|member| member.health != Health::Dead)
+    }
+    /// The ring of the shards that are not `Dead`. A `Suspect` shard keeps its documents
+    /// so a node slow to answer does not move them around
+    pub fn ring(&self) -> HashRing {
+        self.live().filter(|member| member.shard).fold(
+            HashRing::new().set_virtual_nodes(self.virtual_nodes),
+            |ring, member| ring.add_node(member.address),
+        )
+    }
+}
+
+/// Runs a `TuringServer` as a member of a cluster whose nodes find each other by gossip.
+/// Every gossip interval the node sends the members it knows, itself with a new heartbeat among them,
+/// to one of them picked at random and takes in the members the other node answers with.
+/// A node only has to be given some of the others as seeds, it learns of the rest from them.
+/// Clients read the `Topology` of the cluster from any node with `TuringClient::topology` and route
+/// their commands to the shards of its ring, see `ShardedClient::discover`. Nodes talk over
+/// the wire protocol with admin commands, they share the admin token
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct Gossip {
+///     address: SocketAddr,
+///     seeds: Vec<SocketAddr>,
+///     shard: bool,
+///     virtual_nodes: usize,
+///     admin_token: Option<String>,
+///     interval: Duration,
+///     suspect_timeout: Duration,
+///     dead_timeout: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gossip {
+    // The address the other nodes reach the node at, which may not be the one it listens on
+    address: SocketAddr,
+    seeds: Vec<SocketAddr>,
+    shard: bool,
+    virtual_nodes: usize,
+    admin_token: Option<String>,
+    interval: Duration,
+    suspect_timeout: Duration,
+    dead_timeout: Duration,
+}
+
+impl Gossip {
+    /// The node the other nodes reach at `address`, holding a shard of the ring
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            seeds: Vec::new(),
+            shard: true,
+            virtual_nodes: HashRing::new().get_virtual_nodes(),
+            admin_token: None,
+            interval: DEFAULT_GOSSIP_INTERVAL,
+            suspect_timeout: DEFAULT_SUSPECT_TIMEOUT,
+            dead_timeout: DEFAULT_DEAD_TIMEOUT,
+        }
+    }
+    /// A node of the cluster to gossip with until others are known
+    pub fn add_seed(mut self, address: SocketAddr) -> Self {
+        if address != self.address && !self.seeds.contains(&address) {
+            self.seeds.push(address);
+        }
+
+        self
+    }
+    /// `false` for a node that only routes commands, a router of a sharded cluster for one
+    pub fn set_shard(mut self, shard: bool) -> Self {
+        self.shard = shard;
+
+        self
+    }
+    /// Every node of the cluster has to use the same number of points
+    pub fn set_virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes.max(1);
+
+        self
+    }
+    /// The admin token of the other nodes
+    pub fn set_admin_token(mut self, admin_token: &str) -> Self {
+        self.admin_token = Some(admin_token.into());
+
+        self
+    }
+
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+
+        self
+    }
+    /// Kept a few gossip intervals long, a node only hears of another one every so often
+    pub fn set_suspect_timeout(mut self, suspect_timeout: Duration) -> Self {
+        self.suspect_timeout = suspect_timeout;
+
+        self
+    }
+
+    pub fn set_dead_timeout(mut self, dead_timeout: Duration) -> Self {
+        self.dead_timeout = dead_timeout;
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_seeds(&self) -> &[SocketAddr] {
+        &self.seeds
+    }
+
+    pub fn get_shard(&self) -> bool {
+        self.shard
+    }
+
+    pub fn get_virtual_nodes(&self) -> usize {
+        self.virtual_nodes
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+
+    pub fn get_suspect_timeout(&self) -> Duration {
+        self.suspect_timeout
+    }
+
+    pub fn get_dead_timeout(&self) -> Duration {
+        self.dead_timeout
+    }
+}
+
+/// A running member of a gossiping cluster, started by `TuringServer::run_until` when a `Gossip` is set
+#[derive(Debug)]
+pub(crate) struct GossipNode {
+    config: Gossip,
+    generation: u64,
+    heartbeat: AtomicU64,
+    // The other nodes with the time their heartbeat last went up
+    members: Mutex<HashMap<SocketAddr, (Member, Instant)>>,
+    stopped: AtomicBool,
+}
+
+impl GossipNode {
+    /// Spawn the task gossiping with the other nodes
+    pub(crate) fn start<'a>(config: &Gossip, executor: &Executor<'a>) -> Arc<GossipNode> {
+        let generation = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+
+        let node = Arc::new(GossipNode {
+            config: config.clone(),
+            generation,
+            heartbeat: AtomicU64::new(0),
+            members: Mutex::new(HashMap::new()),
+            stopped: AtomicBool::new(false),
+        });
+        executor.spawn(Arc::clone(&node).gossip_loop()).detach();
+
+        node
+    }
+    /// End the task of the node, for when the server shuts down
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+    /// Take in the members another node sent and answer with the ones this node knows
+    pub(crate) async fn gossip(&self, members: Vec<Member>) -> Topology {
+        self.merge(members).await;
+
+        self.topology().await
+    }
+    /// The node itself along with every node it heard of, with their health as of now
+    pub(crate) async fn topology(&self) -> Topology {
+        let members = self.members.lock().await;
+
+        let mut listed = Vec::with_capacity(members.len() + 1);
+        listed.push(self.myself());
+        for (member, seen) in members.values() {
+            let silent = seen.elapsed();
+            let health = if silent < self.config.suspect_timeout {
+                Health::Alive
+            } else if silent < self.config.dead_timeout {
+                Health::Suspect
+            } else {
+                Health::Dead
+            };
+
+            listed.push(Member { health, ..*member });
+        }
+
+        Topology::new(self.config.virtual_nodes, listed)
+    }
+
+    fn myself(&self) -> Member {
+        Member {
+            address: self.config.address,
+            generation: self.generation,
+            heartbeat: self.heartbeat.load(Ordering::Acquire),
+            shard: self.config.shard,
+            health: Health::Alive,
+        }
+    }
+    /// Keep the newest record of every node, a node is seen again once its heartbeat goes up
+    async fn merge(&self, gossiped: Vec<Member>) {
+        let mut members = self.members.lock().await;
+
+        for member in gossiped {
+            if member.address == self.config.address {
+                continue;
+            }
+
+            let newer = match members.get(&member.address) {
+                Some((known, _)) => member.supersedes(known),
+                None => true,
+            };
+            if newer {
+                members.insert(member.address, (member, Instant::now()));
+            }
+        }
+    }
+    /// The seeds until other nodes are known, then the nodes that are not `Dead`
+    async fn targets(&self) -> Vec<SocketAddr> {
+        let live = self
+            .topology()
+            .await
+            .live()
+            .map(|member| member.address)
+            .filter(|address| *address != self.config.address)
+            .collect::<Vec<SocketAddr>>();
+
+        if live.is_empty() {
+            self.config.seeds.clone()
+        } else {
+            live
+        }
+    }
+    /// Gossip with one node every interval. A node that cannot be reached is tried again
+    /// over a new connection the next time it is picked
+    async fn gossip_loop(self: Arc<Self>) {
+        let mut clients = HashMap::new();
+
+        while !self.stopped.load(Ordering::Acquire) {
+            Timer::after(self.config.interval).await;
+            self.heartbeat.fetch_add(1, Ordering::AcqRel);
+
+            let targets = self.targets().await;
+            if targets.is_empty() {
+                continue;
+            }
+            let target = targets[fastrand::usize(..targets.len())];
+
+            let members = self.topology().await.members;
+            match self.exchange(&mut clients, target, members).await {
+                Ok(topology) => self.merge(topology.members).await,
+                Err(_) => {
+                    clients.remove(&target);
+                }
+            }
+        }
+    }
+
+    async fn exchange(
+        &self,
+        clients: &mut HashMap<SocketAddr, TuringClient>,
+        address: SocketAddr,
+        members: Vec<Member>,
+    ) -> TuringResult<Topology> {
+        let client = match clients.entry(address) {
+            Entry::Occupied(client) => client.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut connected = TuringClient::connect(address).await?;
+                if let Some(admin_token) = &self.config.admin_token {
+                    connected.authenticate(admin_token).await?;
+                }
+
+                entry.insert(connected)
+            }
+        };
+        match client.request(TuringCommand::Gossip { members }).await? {
+            TuringResponse::Topology(topology) => Ok(topology),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+}
@@ -0,0 +1,524 @@
+use crate::{Compression, DataType, Document, FieldData, TuringDB, TuringDbError, TuringResult};
+use serde::{Deserialize, Serialize};
+use sled::{
+    transaction::{TransactionError, TransactionResult},
+    Transactional,
+};
+use std::collections::{BTreeMap, HashMap};
+use tai64::TAI64N;
+
+/// The sled tree inside every document that holds its revisions keyed by the big endian revision number
+pub(crate) const HISTORY_TREE: &str = "__turingdb_revisions";
+/// The history tree written before revisions were delta encoded, every revision held full copies
+const LEGACY_HISTORY_TREE: &str = "__turingdb_history";
+/// The sled tree inside every document counting the deltas written in a row for each field
+pub(crate) const DELTA_RUN_TREE: &str = "__turingdb_delta_runs";
+/// After this many deltas in a row a revision stores the contents it replaced in full,
+/// so at most this many revisions are decoded to rebuild the contents of a field at any revision
+const ANCHOR_INTERVAL: u32 = 16;
+/// The first format version whose documents keep their revisions delta encoded
+pub(crate) const DELTA_HISTORY_FORMAT: u32 = 3;
+/// The sled tree inside every document recording when each removed field was removed
+pub(crate) const TOMBSTONE_TREE: &str = "__turingdb_tombstones";
+/// The key in the history tree holding the current revision of the document.
+/// It is empty so it can never collide with, and always sorts before, the revision keys
+pub(crate) const CURRENT_REVISION_KEY: &[u8] = b"";
+
+/// A single write to a document together with the value it replaced
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Revision {
+///     revision: u64,
+///     timestamp: TAI64N,
+///     key: Vec<u8>,
+///     previous: Option<FieldData>,
+///     current: Option<FieldData>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Revision {
+    revision: u64,
+    timestamp: TAI64N,
+    key: Vec<u8>,
+    previous: Option<FieldData>,
+    current: Option<FieldData>,
+}
+
+impl Revision {
+    pub(crate) fn new(
+        revision: u64,
+        timestamp: TAI64N,
+        key: &[u8],
+        previous: Option<FieldData>,
+        current: Option<FieldData>,
+    ) -> Self {
+        Self {
+            revision,
+            timestamp,
+            key: key.into(),
+            previous,
+            current,
+        }
+    }
+    /// The revision of the document this write produced
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    /// The time of the write
+    pub fn timestamp(&self) -> TAI64N {
+        self.timestamp
+    }
+    /// The key of the field that was written
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+    /// The contents of the field before the write, `None` if the field did not exist
+    pub fn previous(&self) -> Option<&FieldData> {
+        self.previous.as_ref()
+    }
+    /// The contents of the field after the write, `None` if the field was removed
+    pub fn current(&self) -> Option<&FieldData> {
+        self.current.as_ref()
+    }
+
+    pub(crate) fn encode_number(revision: u64) -> [u8; 8] {
+        revision.to_be_bytes()
+    }
+
+    pub(crate) fn decode_number(stored: &[u8]) -> u64 {
+        let mut revision_bytes = [0_u8; 8];
+        revision_bytes.copy_from_slice(&stored[..8]);
+
+        u64::from_be_bytes(revision_bytes)
+    }
+}
+
+/// How a revision is kept in the history tree.
+/// The contents the write replaced are stored as a diff against the contents it wrote,
+/// which are the contents the next revision of the field replaced or the current contents of the field
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub(crate) struct StoredRevision {
+///     revision: u64,
+///     timestamp: TAI64N,
+///     key: Vec<u8>,
+///     previous: StoredPrevious,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct StoredRevision {
+    revision: u64,
+    timestamp: TAI64N,
+    key: Vec<u8>,
+    previous: StoredPrevious,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum StoredPrevious {
+    // The field did not exist before the write
+    Absent,
+    // An anchor holding a full copy of the replaced contents
+    Full(FieldData),
+    // The replaced contents as a diff against the written contents
+    Delta(FieldDelta),
+}
+
+impl StoredRevision {
+    /// Store a write, `delta_run` is the number of deltas written in a row for the field before it.
+    /// Returns the revision along with the number of deltas in a row including it
+    pub(crate) fn new(
+        revision: u64,
+        timestamp: TAI64N,
+        key: &[u8],
+        previous: Option<FieldData>,
+        current: Option<&FieldData>,
+        delta_run: u32,
+    ) -> (Self, u32) {
+        let (previous, delta_run) = match (previous, current) {
+            (None, _) => (StoredPrevious::Absent, 0),
+            (Some(previous), None) => (StoredPrevious::Full(previous), 0),
+            (Some(previous), Some(current)) => {
+                let delta = FieldDelta::diff(current, &previous);
+
+                if delta_run + 1 >= ANCHOR_INTERVAL || !delta.is_smaller_than(&previous) {
+                    (StoredPrevious::Full(previous), 0)
+                } else {
+                    (StoredPrevious::Delta(delta), delta_run + 1)
+                }
+            }
+        };
+
+        let stored = Self {
+            revision,
+            timestamp,
+            key: key.into(),
+            previous,
+        };
+
+        (stored, delta_run)
+    }
+    /// The time of the write
+    pub(crate) fn timestamp(&self) -> TAI64N {
+        self.timestamp
+    }
+    /// The key of the field that was written
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+    /// Check whether the replaced contents can be read without the contents the revision wrote
+    pub(crate) fn is_anchored(&self) -> bool {
+        !matches!(self.previous, StoredPrevious::Delta(_))
+    }
+    /// The contents the write replaced, given the contents it wrote
+    pub(crate) fn previous(&self, current: Option<&FieldData>) -> TuringResult<Option<FieldData>> {
+        match &self.previous {
+            StoredPrevious::Absent => Ok(None),
+            StoredPrevious::Full(previous) => Ok(Some(previous.clone())),
+            StoredPrevious::Delta(delta) => match current {
+                Some(current) => Ok(Some(delta.apply(current)?)),
+                None => Err(TuringDbError::Serialization(
+                    "Revision delta without the contents it was taken against".into(),
+                )),
+            },
+        }
+    }
+    /// Rebuild the revision given the contents it wrote
+    pub(crate) fn resolve(self, current: Option<FieldData>) -> TuringResult<Revision> {
+        let previous = self.previous(current.as_ref())?;
+
+        Ok(Revision::new(
+            self.revision,
+            self.timestamp,
+            &self.key,
+            previous,
+            current,
+        ))
+    }
+
+    pub(crate) fn encode(&self, compression: Compression) -> TuringResult<Vec<u8>> {
+        compression.compress(&bincode::serialize::<StoredRevision>(self)?)
+    }
+
+    pub(crate) fn decode(stored: &[u8]) -> TuringResult<StoredRevision> {
+        Ok(bincode::deserialize::<StoredRevision>(
+            &Compression::decompress(stored)?,
+        )?)
+    }
+    /// Re-encode a revision written with full copies as an anchor
+    fn upgrade(stored: &[u8], compression: Compression) -> TuringResult<Vec<u8>> {
+        let legacy = bincode::deserialize::<Revision>(&Compression::decompress(stored)?)?;
+
+        let previous = match legacy.previous {
+            None => StoredPrevious::Absent,
+            Some(previous) => StoredPrevious::Full(previous),
+        };
+
+        StoredRevision {
+            revision: legacy.revision,
+            timestamp: legacy.timestamp,
+            key: legacy.key,
+            previous,
+        }
+        .encode(compression)
+    }
+}
+
+/// The replaced contents of a field as the bytes that differ from the written contents.
+/// The bytes both share at the start and at the end are kept, everything between them is replaced by `middle`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FieldDelta {
+    data_type: DataType,
+    created: TAI64N,
+    modified: TAI64N,
+    prefix: u64,
+    suffix: u64,
+    middle: Vec<u8>,
+}
+
+impl FieldDelta {
+    /// The diff that turns `current` back into `previous`
+    fn diff(current: &FieldData, previous: &FieldData) -> Self {
+        let current_data = current.data();
+        let previous_data = previous.data();
+
+        let prefix = current_data
+            .iter()
+            .zip(previous_data.iter())
+            .take_while(|(current_byte, previous_byte)| current_byte == previous_byte)
+            .count();
+        let suffix = current_data[prefix..]
+            .iter()
+            .rev()
+            .zip(previous_data[prefix..].iter().rev())
+            .take_while(|(current_byte, previous_byte)| current_byte == previous_byte)
+            .count();
+
+        Self {
+            data_type: previous.data_type(),
+            created: previous.created(),
+            modified: previous.modified(),
+            prefix: prefix as u64,
+            suffix: suffix as u64,
+            middle: previous_data[prefix..previous_data.len() - suffix].to_vec(),
+        }
+    }
+    /// Only deltas that save more than the offsets they add are worth storing
+    fn is_smaller_than(&self, previous: &FieldData) -> bool {
+        self.middle.len() + 16 < previous.data().len()
+    }
+
+    fn apply(&self, current: &FieldData) -> TuringResult<FieldData> {
+        let current_data = current.data();
+        let (prefix, suffix) = (self.prefix as usize, self.suffix as usize);
+
+        if prefix.saturating_add(suffix) > current_data.len() {
+            return Err(TuringDbError::Serialization(
+                "Revision delta is longer than the contents it was taken against".into(),
+            ));
+        }
+
+        let mut data = Vec::with_capacity(prefix + self.middle.len() + suffix);
+        data.extend_from_slice(&current_data[..prefix]);
+        data.extend_from_slice(&self.middle);
+        data.extend_from_slice(&current_data[current_data.len() - suffix..]);
+
+        Ok(FieldData::with_times(
+            self.data_type,
+            &data,
+            self.created,
+            self.modified,
+        ))
+    }
+}
+
+/// Reads the revisions of a document, resolving their deltas
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct History;
+
+impl History {
+    /// The current revision of a document, `0` before its first write
+    pub(crate) fn current_revision(sled_db: &Document) -> TuringResult<u64> {
+        match sled_db.open_tree(HISTORY_TREE)?.get(CURRENT_REVISION_KEY)? {
+            None => Ok(0),
+            Some(stored) => Ok(Revision::decode_number(&stored)),
+        }
+    }
+    /// The time of the latest write to a document, `None` before its first write
+    pub(crate) fn last_written(sled_db: &Document) -> TuringResult<Option<TAI64N>> {
+        let current_revision = History::current_revision(sled_db)?;
+
+        match sled_db
+            .open_tree(HISTORY_TREE)?
+            .get(Revision::encode_number(current_revision))?
+        {
+            None => Ok(None),
+            Some(stored) => Ok(Some(StoredRevision::decode(&stored)?.timestamp())),
+        }
+    }
+    /// Check whether a kept revision of a document was written at `time`.
+    /// Every revision written by an operation carries the time it was logged at,
+    /// so this tells whether a replayed operation already reached the document
+    pub(crate) fn written_at(sled_db: &Document, time: TAI64N) -> TuringResult<bool> {
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        for entry in history.range(Revision::encode_number(0)..).rev() {
+            let (_, stored) = entry?;
+
+            if StoredRevision::decode(&stored)?.timestamp() == time {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+    /// Read the fields of a document at rest, retrying until no write lands during the read.
+    /// Returns what was read along with the revision it reflects, a delta is only valid against
+    /// the contents written by its own revision so later revisions must not be resolved against it
+    pub(crate) fn at_rest<T, F>(sled_db: &Document, mut read: F) -> TuringResult<(u64, T)>
+    where
+        F: FnMut(u64) -> TuringResult<T>,
+    {
+        loop {
+            let current_revision = History::current_revision(sled_db)?;
+            let value = read(current_revision)?;
+
+            if History::current_revision(sled_db)? == current_revision {
+                return Ok((current_revision, value));
+            }
+        }
+    }
+    /// Every revision kept in the history of a document, oldest first.
+    /// Revisions are resolved newest first starting from the current contents of each field
+    pub(crate) fn revisions(sled_db: &Document) -> TuringResult<Vec<Revision>> {
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        let (_, (stored_revisions, mut written)) = History::at_rest(sled_db, |current_revision| {
+            let mut stored_revisions = Vec::new();
+            // The contents of each field after the revision being resolved
+            let mut written: HashMap<Vec<u8>, Option<FieldData>> = HashMap::new();
+
+            for entry in history
+                .range(Revision::encode_number(0)..=Revision::encode_number(current_revision))
+                .rev()
+            {
+                let (_, stored) = entry?;
+                let stored = StoredRevision::decode(&stored)?;
+
+                if !written.contains_key(stored.key()) {
+                    let current = match sled_db.get(stored.key())? {
+                        None => None,
+                        Some(field_bytes) => Some(TuringDB::decode_field(&field_bytes)?),
+                    };
+                    written.insert(stored.key().to_vec(), current);
+                }
+
+                stored_revisions.push(stored);
+            }
+
+            Ok((stored_revisions, written))
+        })?;
+
+        let mut revisions = Vec::with_capacity(stored_revisions.len());
+
+        for stored in stored_revisions {
+            let current = written.get(stored.key()).cloned().flatten();
+            let revision = stored.resolve(current)?;
+
+            written.insert(revision.key().to_vec(), revision.previous().cloned());
+            revisions.push(revision);
+        }
+
+        revisions.reverse();
+
+        Ok(revisions)
+    }
+    /// The contents of a field at `revision`.
+    /// Only the revisions of the field up to the next anchor after `revision` are decoded
+    pub(crate) fn field_at(
+        sled_db: &Document,
+        key: &[u8],
+        revision: u64,
+    ) -> TuringResult<Option<FieldData>> {
+        let (current_revision, current) =
+            History::at_rest(sled_db, |_| match sled_db.get(key)? {
+                None => Ok(None),
+                Some(field_bytes) => Ok(Some(TuringDB::decode_field(&field_bytes)?)),
+            })?;
+
+        if revision >= current_revision {
+            return Ok(current);
+        }
+
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        let mut later = Vec::new();
+
+        for entry in history.range(
+            Revision::encode_number(revision + 1)..=Revision::encode_number(current_revision),
+        ) {
+            let (_, stored) = entry?;
+            let stored = StoredRevision::decode(&stored)?;
+
+            if stored.key() == key {
+                let is_anchored = stored.is_anchored();
+                later.push(stored);
+
+                if is_anchored {
+                    break;
+                }
+            }
+        }
+
+        // An anchor does not need the contents that were written after it
+        let mut contents = current;
+        for stored in later.iter().rev() {
+            contents = stored.previous(contents.as_ref())?;
+        }
+
+        Ok(contents)
+    }
+    /// Rebuild every field of a document as it was at `revision` by undoing every later write
+    pub(crate) fn fields_at(
+        sled_db: &Document,
+        revision: u64,
+    ) -> TuringResult<BTreeMap<Vec<u8>, FieldData>> {
+        let (current_revision, mut fields) = History::at_rest(sled_db, |_| {
+            let mut fields = BTreeMap::new();
+            for field in sled_db.iter() {
+                let (key, stored) = field?;
+                fields.insert(key.to_vec(), TuringDB::decode_field(&stored)?);
+            }
+
+            Ok(fields)
+        })?;
+
+        if revision >= current_revision {
+            return Ok(fields);
+        }
+
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        for entry in history
+            .range(
+                Revision::encode_number(revision + 1)..=Revision::encode_number(current_revision),
+            )
+            .rev()
+        {
+            let (_, stored) = entry?;
+            let undone = StoredRevision::decode(&stored)?;
+
+            match undone.previous(fields.get(undone.key()))? {
+                Some(previous) => fields.insert(undone.key().to_vec(), previous),
+                None => fields.remove(undone.key()),
+            };
+        }
+
+        Ok(fields)
+    }
+    /// Move the revisions of a document written before delta encoding into the current history tree.
+    /// Documents that were already upgraded are left untouched
+    pub(crate) fn upgrade(sled_db: &Document, compression: Compression) -> TuringResult<()> {
+        if !sled_db
+            .tree_names()
+            .iter()
+            .any(|tree_name| tree_name.as_ref() == LEGACY_HISTORY_TREE.as_bytes())
+        {
+            return Ok(());
+        }
+
+        let legacy = sled_db.open_tree(LEGACY_HISTORY_TREE)?;
+        let history = sled_db.open_tree(HISTORY_TREE)?;
+
+        let mut upgraded = Vec::new();
+        for entry in legacy.iter() {
+            let (key, stored) = entry?;
+
+            if key == CURRENT_REVISION_KEY {
+                upgraded.push((key, stored.to_vec()));
+            } else {
+                upgraded.push((key, StoredRevision::upgrade(&stored, compression)?));
+            }
+        }
+
+        let outcome: TransactionResult<(), TuringDbError> =
+            (&legacy, &history).transaction(|(legacy, history)| {
+                for (key, stored) in upgraded.iter() {
+                    history.insert(&key[..], stored.as_slice())?;
+                    legacy.remove(&key[..])?;
+                }
+
+                Ok(())
+            });
+
+        match outcome {
+            Ok(_) => (),
+            Err(TransactionError::Abort(error)) => return Err(error),
+            Err(TransactionError::Storage(error)) => return Err(error.into()),
+        }
+
+        sled_db.drop_tree(LEGACY_HISTORY_TREE)?;
+        sled_db.flush()?;
+
+        Ok(())
+    }
+}
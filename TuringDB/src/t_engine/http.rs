@@ -0,0 +1,656 @@
+use crate::{
+    AggregateGroup, DataType, FieldData, GeoPoint, OpsOutcome, PatchOp, PayloadEncoding, Statement,
+    TDBCell, TuringDBDocumentOps, TuringDbError, TuringEngine, TuringQL, TuringResult, Value,
+};
+use async_executor::Executor;
+use async_io::Async;
+use futures_lite::io::{AsyncReadExt, AsyncWriteExt};
+use serde_json::{json, Map, Value as Json};
+use std::{
+    convert::TryFrom,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+};
+
+/// The port an `HttpGateway` listens on unless another address is set
+pub const DEFAULT_HTTP_PORT: u16 = 4380;
+/// The largest body a request may carry unless another limit is set
+const DEFAULT_MAX_BODY_LEN: usize = 4 * 1024 * 1024;
+/// The largest request line and headers read before a request is rejected
+const MAX_HEAD_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 32;
+
+/// Serves a `TuringEngine` over HTTP/1.1 with JSON bodies, for clients that do not speak the
+/// frame protocol of `TuringServer`. Every connection carries a single request
+/// ```text
+/// GET    /db/{db}/doc/{document}   the fields of the document
+/// PUT    /db/{db}/doc/{document}   create the document, or replace its fields, with the fields of the body
+/// DELETE /db/{db}/doc/{document}   drop the document
+/// POST   /db/{db}/query            run the TuringQL statement of a `{"statement": "SELECT ..."}` body
+/// ```
+/// Names holding a `/` have it percent encoded. Fields are a JSON object keyed by field name.
+/// Booleans, numbers, text and arrays are stored with the matching `DataType` and an object with
+/// a `latitude` and a `longitude` as a `GeoPoint`. Bytes and times are read back as arrays of bytes.
+/// Errors are a `{"error": ".."}` body holding the `Debug` output of the `TuringDbError`.
+///
+/// A body sent with a `Content-Type` of `application/msgpack` or `application/cbor` is read
+/// as MessagePack or CBOR holding the same values as the JSON, any other type but JSON is
+/// rejected with 415. Replies are encoded as the first type of `Accept` the gateway speaks,
+/// as the body of the request when `Accept` is missing or a wildcard, or rejected with 406
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub struct HttpGateway {
+///     address: SocketAddr,
+///     max_body_len: usize,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HttpGateway {
+    address: SocketAddr,
+    max_body_len: usize,
+}
+
+impl Default for HttpGateway {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], DEFAULT_HTTP_PORT)),
+            max_body_len: DEFAULT_MAX_BODY_LEN,
+        }
+    }
+}
+
+/// A response before it is written, its status and JSON body
+type Reply = (u16, Option<Json>);
+
+/// A request once read, with the encoding of its body and the one its reply is sent in
+#[derive(Debug)]
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+    encoding: PayloadEncoding,
+    accept: PayloadEncoding,
+}
+
+impl HttpGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+
+        self
+    }
+    /// The largest body a request may carry, a larger one is answered with `413`
+    pub fn set_max_body_len(mut self, max_body_len: usize) -> Self {
+        self.max_body_len = max_body_len;
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_max_body_len(&self) -> usize {
+        self.max_body_len
+    }
+    /// Accept connections until the listener fails, spawning a task on `executor` for each of them.
+    /// A connection that fails only ends its own task
+    pub async fn run<'a>(
+        &self,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        let listener = Async::<TcpListener>::bind(self.address)?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+
+            executor
+                .spawn(HttpGateway::serve(
+                    Arc::clone(&engine),
+                    stream,
+                    self.max_body_len,
+                ))
+                .detach();
+        }
+    }
+
+    async fn serve(
+        engine: Arc<TuringEngine>,
+        mut stream: Async<TcpStream>,
+        max_body_len: usize,
+    ) -> TuringResult<()> {
+        let (reply, accept) = match HttpGateway::read_request(&mut stream, max_body_len).await? {
+            Err(reply) => (reply, PayloadEncoding::Json),
+            Ok(request) => {
+                let reply = match HttpGateway::route(&engine, &request).await {
+                    Ok(reply) => reply,
+                    Err(error) => HttpGateway::error(error),
+                };
+
+                (reply, request.accept)
+            }
+        };
+
+        HttpGateway::write_reply(&mut stream, reply, accept).await
+    }
+    /// Read a request, or the reply to a request that cannot be read
+    async fn read_request(
+        stream: &mut Async<TcpStream>,
+        max_body_len: usize,
+    ) -> TuringResult<Result<Request, Reply>> {
+        let mut buffer = Vec::new();
+        let mut chunk = [0_u8; 4096];
+
+        let head_len = loop {
+            if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            if buffer.len() > MAX_HEAD_LEN {
+                return Ok(Err((431, None)));
+            }
+
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(TuringDbError::UnexpectedEof);
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        };
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buffer[..head_len]) {
+            Ok(httparse::Status::Complete(_)) => (),
+            _ => return Ok(Err((400, None))),
+        }
+
+        let header = |name: &str| {
+            request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case(name))
+                .map(|header| std::str::from_utf8(header.value).ok())
+        };
+
+        let content_len = match header("content-length") {
+            None => 0,
+            Some(value) => match value.and_then(|value| value.trim().parse::<usize>().ok()) {
+                Some(content_len) => content_len,
+                None => return Ok(Err((400, None))),
+            },
+        };
+        if content_len > max_body_len {
+            return Ok(Err((413, None)));
+        }
+
+        let encoding = match header("content-type") {
+            None => PayloadEncoding::Json,
+            Some(value) => match value.and_then(PayloadEncoding::from_media_type) {
+                Some(encoding) => encoding,
+                None => return Ok(Err((415, None))),
+            },
+        };
+        let accept = match header("accept") {
+            None => encoding,
+            Some(value) => match value.map(|value| HttpGateway::accept(value, encoding)) {
+                Some(Some(accept)) => accept,
+                _ => return Ok(Err((406, None))),
+            },
+        };
+
+        let method = request.method.unwrap_or_default().to_owned();
+        let path = request.path.unwrap_or_default().to_owned();
+
+        let mut body = buffer.split_off(head_len);
+        body.truncate(content_len);
+        let read = body.len();
+        body.resize(content_len, 0);
+        stream.read_exact(&mut body[read..]).await?;
+
+        Ok(Ok(Request {
+            method,
+            path,
+            body,
+            encoding,
+            accept,
+        }))
+    }
+    /// The first media type of an `Accept` header the gateway speaks, quality values
+    /// are not weighed. A wildcard stands for `encoding`, the one of the request body
+    fn accept(header: &str, encoding: PayloadEncoding) -> Option<PayloadEncoding> {
+        header.split(',').find_map(|media_type| {
+            let media_type = media_type.split(';').next().unwrap_or_default().trim();
+
+            if media_type == "*/*" || media_type.eq_ignore_ascii_case("application/*") {
+                Some(encoding)
+            } else {
+                PayloadEncoding::from_media_type(media_type)
+            }
+        })
+    }
+
+    async fn route(engine: &TuringEngine, request: &Request) -> TuringResult<Reply> {
+        let (method, body) = (request.method.as_str(), request.body.as_slice());
+        let path = request.path.split('?').next().unwrap_or_default();
+        let segments = path
+            .trim_start_matches('/')
+            .split('/')
+            .map(HttpGateway::percent_decode)
+            .collect::<Option<Vec<String>>>();
+        let segments = match segments {
+            Some(segments) => segments,
+            None => return Ok((400, None)),
+        };
+
+        match (method, segments.as_slice()) {
+            ("GET", [db_segment, db, doc_segment, document])
+                if db_segment == "db" && doc_segment == "doc" =>
+            {
+                let fields = engine
+                    .document_view(&HttpGateway::document_ops(db, document))
+                    .await?
+                    .field_scan()?;
+
+                Ok((200, Some(HttpGateway::fields_to_json(&fields))))
+            }
+            ("PUT", [db_segment, db, doc_segment, document])
+                if db_segment == "db" && doc_segment == "doc" =>
+            {
+                let fields = match request.encoding.decode::<Json>(body) {
+                    Ok(Json::Object(object)) => HttpGateway::fields_from_json(object)?,
+                    _ => return Err(TuringDbError::InvalidInput),
+                };
+
+                match engine
+                    .document_upsert(&HttpGateway::document_ops(db, document), fields)
+                    .await?
+                {
+                    OpsOutcome::DocumentInserted => Ok((201, None)),
+                    _ => Ok((204, None)),
+                }
+            }
+            ("DELETE", [db_segment, db, doc_segment, document])
+                if db_segment == "db" && doc_segment == "doc" =>
+            {
+                engine
+                    .document_drop(&HttpGateway::document_ops(db, document))
+                    .await?;
+
+                Ok((204, None))
+            }
+            ("POST", [db_segment, db, query_segment])
+                if db_segment == "db" && query_segment == "query" =>
+            {
+                let statement = match request.encoding.decode::<Json>(body) {
+                    Ok(Json::Object(object)) => match object.get("statement") {
+                        Some(Json::String(statement)) => TuringQL::parse(statement)?,
+                        _ => return Err(TuringDbError::InvalidInput),
+                    },
+                    _ => return Err(TuringDbError::InvalidInput),
+                };
+
+                let outcome = match statement {
+                    Statement::Select(query) if query.get_db() == db.as_str() => {
+                        engine.select(&query).await?
+                    }
+                    Statement::Aggregate(aggregation) if aggregation.get_db() == db.as_str() => {
+                        engine.aggregate(&aggregation).await?
+                    }
+                    _ => return Err(TuringDbError::InvalidInput),
+                };
+
+                match outcome {
+                    OpsOutcome::DocumentMatches {
+                        documents,
+                        continuation,
+                    } => {
+                        let documents = documents
+                            .iter()
+                            .map(|(document_name, fields)| {
+                                json!({
+                                    "document": document_name.as_str(),
+                                    "fields": HttpGateway::fields_to_json(fields),
+                                })
+                            })
+                            .collect::<Vec<Json>>();
+
+                        Ok((
+                            200,
+                            Some(json!({ "documents": documents, "continuation": continuation })),
+                        ))
+                    }
+                    OpsOutcome::Aggregated(groups) => Ok((
+                        200,
+                        Some(json!({ "groups": HttpGateway::groups_to_json(&groups) })),
+                    )),
+                    _ => Err(TuringDbError::Bug(
+                        "A statement ran to an outcome other than matches or groups".into(),
+                    )),
+                }
+            }
+            (_, [db_segment, _, doc_segment, _]) if db_segment == "db" && doc_segment == "doc" => {
+                Ok((405, None))
+            }
+            (_, [db_segment, _, query_segment])
+                if db_segment == "db" && query_segment == "query" =>
+            {
+                Ok((405, None))
+            }
+            _ => Ok((404, None)),
+        }
+    }
+
+    async fn write_reply(
+        stream: &mut Async<TcpStream>,
+        (status, body): Reply,
+        accept: PayloadEncoding,
+    ) -> TuringResult<()> {
+        let body = match body {
+            Some(body) => accept.encode(&body)?,
+            None => Vec::new(),
+        };
+
+        let mut head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n",
+            status,
+            HttpGateway::reason(status),
+            body.len()
+        );
+        if !body.is_empty() {
+            head.push_str(&format!("Content-Type: {}\r\n", accept.media_type()));
+        }
+        head.push_str("\r\n");
+
+        let mut response = head.into_bytes();
+        response.extend_from_slice(&body);
+
+        stream.write_all(&response).await?;
+        stream.flush().await?;
+
+        Ok(())
+    }
+
+    fn error(error: TuringDbError) -> Reply {
+        let status = match error {
+            TuringDbError::DbNotFound
+            | TuringDbError::DocumentNotFound
+            | TuringDbError::FieldNotFound
+            | TuringDbError::NotFound => 404,
+            TuringDbError::InvalidInput
+            | TuringDbError::QuerySyntax { .. }
+            | TuringDbError::InvalidContinuation
+            | TuringDbError::InvalidPattern(_)
+            | TuringDbError::FieldTypeMismatch
+            | TuringDbError::NumericOverflow
+            | TuringDbError::SchemaViolation(_) => 400,
+            TuringDbError::QuotaExceeded { .. } => 507,
+            _ => 500,
+        };
+
+        (status, Some(json!({ "error": format!("{:?}", error) })))
+    }
+
+    fn reason(status: u16) -> &'static str {
+        match status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            406 => "Not Acceptable",
+            413 => "Payload Too Large",
+            415 => "Unsupported Media Type",
+            431 => "Request Header Fields Too Large",
+            507 => "Insufficient Storage",
+            _ => "Internal Server Error",
+        }
+    }
+
+    fn document_ops(db: &str, document: &str) -> TuringDBDocumentOps {
+        TuringDBDocumentOps::default()
+            .set_db_name(db)
+            .set_document_name(document)
+    }
+    /// Decode `%XX` escapes, `None` when an escape is malformed or the result is not UTF-8
+    fn percent_decode(segment: &str) -> Option<String> {
+        let bytes = segment.as_bytes();
+        let mut decoded = Vec::with_capacity(bytes.len());
+
+        let mut index = 0;
+        while index < bytes.len() {
+            if bytes[index] == b'%' {
+                let escape = std::str::from_utf8(bytes.get(index + 1..index + 3)?).ok()?;
+                decoded.push(u8::from_str_radix(escape, 16).ok()?);
+                index += 3;
+            } else {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+
+        String::from_utf8(decoded).ok()
+    }
+
+    fn fields_to_json(fields: &[(Vec<u8>, FieldData)]) -> Json {
+        Json::Object(
+            fields
+                .iter()
+                .map(|(key, field_data)| {
+                    (
+                        String::from_utf8_lossy(key).into_owned(),
+                        HttpGateway::field_to_json(field_data),
+                    )
+                })
+                .collect::<Map<String, Json>>(),
+        )
+    }
+
+    fn field_to_json(field_data: &FieldData) -> Json {
+        if let Ok(elements) = PatchOp::elements(field_data) {
+            return Json::Array(
+                elements
+                    .iter()
+                    .map(|cell| {
+                        HttpGateway::field_to_json(&FieldData::new(
+                            cell.get_data_type(),
+                            cell.get_data(),
+                        ))
+                    })
+                    .collect(),
+            );
+        }
+        if field_data.data_type() == DataType::GEO {
+            if let Ok(point) = GeoPoint::decode(field_data) {
+                return json!({ "latitude": point.latitude(), "longitude": point.longitude() });
+            }
+        }
+
+        HttpGateway::value_to_json(&Value::from_field(field_data))
+    }
+
+    fn value_to_json(value: &Value) -> Json {
+        match value {
+            Value::Bool(boolean) => Json::Bool(*boolean),
+            Value::Int(int) => match i64::try_from(*int) {
+                Ok(int) => Json::from(int),
+                Err(_) => Json::String(int.to_string()),
+            },
+            Value::UInt(uint) => match u64::try_from(*uint) {
+                Ok(uint) => Json::from(uint),
+                Err(_) => Json::String(uint.to_string()),
+            },
+            Value::Float(float) => Json::from(*float),
+            Value::Text(text) => Json::String(text.clone()),
+            Value::Time(time) => Json::from(time.to_bytes().to_vec()),
+            Value::Bytes(bytes) => Json::from(bytes.clone()),
+        }
+    }
+
+    fn groups_to_json(groups: &[AggregateGroup]) -> Json {
+        Json::Array(
+            groups
+                .iter()
+                .map(|group| {
+                    json!({
+                        "group": group.get_group().map(HttpGateway::value_to_json),
+                        "values": group
+                            .get_values()
+                            .iter()
+                            .map(|value| value.as_ref().map(HttpGateway::value_to_json))
+                            .collect::<Vec<Option<Json>>>(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    fn fields_from_json(object: Map<String, Json>) -> TuringResult<Vec<(Vec<u8>, TDBCell)>> {
+        object
+            .into_iter()
+            .map(|(key, value)| Ok((key.into_bytes(), HttpGateway::cell_from_json(&value)?)))
+            .collect()
+    }
+
+    fn cell_from_json(value: &Json) -> TuringResult<TDBCell> {
+        match value {
+            Json::Bool(boolean) => Ok(TDBCell::new(DataType::Boolean, &[*boolean as u8])),
+            Json::Number(number) => {
+                if let Some(uint) = number.as_u64() {
+                    Ok(TDBCell::new(DataType::U64, &uint.to_le_bytes()))
+                } else if let Some(int) = number.as_i64() {
+                    Ok(TDBCell::new(DataType::I64, &int.to_le_bytes()))
+                } else {
+                    match number.as_f64() {
+                        Some(float) => Ok(TDBCell::new(DataType::F64, &float.to_le_bytes())),
+                        None => Err(TuringDbError::InvalidInput),
+                    }
+                }
+            }
+            Json::String(text) => Ok(TDBCell::new(DataType::STRING, text.as_bytes())),
+            Json::Array(elements) => {
+                let cells = elements
+                    .iter()
+                    .map(HttpGateway::cell_from_json)
+                    .collect::<TuringResult<Vec<TDBCell>>>()?;
+
+                Ok(TDBCell::new(DataType::ARRAY, &bincode::serialize(&cells)?))
+            }
+            Json::Object(object) => match (object.get("latitude"), object.get("longitude")) {
+                (Some(Json::Number(latitude)), Some(Json::Number(longitude)))
+                    if object.len() == 2 =>
+                {
+                    match (latitude.as_f64(), longitude.as_f64()) {
+                        (Some(latitude), Some(longitude)) => {
+                            Ok(GeoPoint::new(latitude, longitude)?.encode())
+                        }
+                        _ => Err(TuringDbError::InvalidInput),
+                    }
+                }
+                _ => Err(TuringDbError::InvalidInput),
+            },
+            Json::Null => Err(TuringDbError::InvalidInput),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TuringDBOps;
+    use futures_lite::future::block_on;
+
+    async fn send(engine: &TuringEngine, method: &str, path: &str, body: Option<Json>) -> Reply {
+        let request = Request {
+            method: method.into(),
+            path: path.into(),
+            body: match body {
+                Some(body) => PayloadEncoding::Json.encode(&body).unwrap(),
+                None => Vec::new(),
+            },
+            encoding: PayloadEncoding::Json,
+            accept: PayloadEncoding::Json,
+        };
+
+        match HttpGateway::route(engine, &request).await {
+            Ok(reply) => reply,
+            Err(error) => HttpGateway::error(error),
+        }
+    }
+
+    #[test]
+    fn documents_are_put_read_queried_and_deleted() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            engine
+                .db_create(TuringDBOps::default().set_db_name("users"))
+                .await
+                .unwrap();
+            let path = "/db/users/doc/ada%2Flovelace";
+
+            let fields = json!({ "name": "ada", "age": 36, "tags": ["maths"] });
+            assert_eq!(
+                send(&engine, "PUT", path, Some(fields.clone())).await,
+                (201, None)
+            );
+            assert_eq!(send(&engine, "GET", path, None).await, (200, Some(fields)));
+
+            let fields = json!({ "name": "ada", "age": 37 });
+            assert_eq!(
+                send(&engine, "PUT", path, Some(fields.clone())).await,
+                (204, None)
+            );
+            assert_eq!(send(&engine, "GET", path, None).await, (200, Some(fields)));
+
+            let statement = json!({ "statement": "SELECT * FROM users WHERE age > 30" });
+            let (status, body) = send(&engine, "POST", "/db/users/query", Some(statement)).await;
+            assert_eq!(status, 200);
+            assert_eq!(body.unwrap()["documents"][0]["document"], "ada/lovelace");
+
+            assert_eq!(send(&engine, "DELETE", path, None).await, (204, None));
+            assert_eq!(send(&engine, "GET", path, None).await.0, 404);
+        });
+    }
+
+    #[test]
+    fn requests_the_gateway_does_not_serve_are_refused() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+
+            assert_eq!(
+                send(&engine, "GET", "/db/missing/doc/any", None).await.0,
+                404
+            );
+            assert_eq!(
+                send(&engine, "POST", "/db/users/doc/any", None).await,
+                (405, None)
+            );
+            assert_eq!(send(&engine, "GET", "/elsewhere", None).await, (404, None));
+            assert_eq!(
+                send(&engine, "GET", "/db/users/doc/%zz", None).await,
+                (400, None)
+            );
+            assert_eq!(
+                send(&engine, "PUT", "/db/users/doc/any", Some(json!([1])))
+                    .await
+                    .0,
+                400
+            );
+        });
+    }
+
+    #[test]
+    fn the_reply_is_encoded_as_the_client_accepts() {
+        let json = PayloadEncoding::Json;
+
+        assert_eq!(HttpGateway::accept("*/*", json), Some(json));
+        assert_eq!(
+            HttpGateway::accept("text/html, application/cbor;q=0.9", json),
+            Some(PayloadEncoding::Cbor)
+        );
+        assert_eq!(HttpGateway::accept("text/html", json), None);
+    }
+}
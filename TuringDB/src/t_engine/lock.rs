@@ -0,0 +1,97 @@
+use crate::{TuringDbError, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tai64::TAI64N;
+
+/// An advisory lock on a document held until `expires_at` unless it is released first
+/// ```
+/// #[derive(Debug, Clone, Copy)]
+/// pub(crate) struct Lease {
+///     token: u64,
+///     expires_at: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Lease {
+    token: u64,
+    expires_at: TAI64N,
+}
+
+/// The advisory locks held on documents. Nothing stops a write to a locked document,
+/// clients that cooperate lock it first and hand the fencing token of their lease to whatever
+/// they write to, which rejects a token lower than the highest it has seen.
+/// Tokens only grow so a client whose lease expired while it was paused is told apart from
+/// the client that took the lock after it
+/// ```
+/// #[derive(Debug, Default)]
+/// pub(crate) struct DocumentLocks {
+///     last_token: AtomicU64,
+///     held: DashMap<(Utf8PathBuf, Utf8PathBuf), Lease>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct DocumentLocks {
+    last_token: AtomicU64,
+    // By database then document name
+    held: DashMap<(Utf8PathBuf, Utf8PathBuf), Lease>,
+}
+
+impl DocumentLocks {
+    /// Lock a document for `ttl`, returning the fencing token of the lease.
+    /// Fails with `DocumentLocked` while another lease on the document has not expired
+    pub(crate) fn acquire(
+        &self,
+        db: &Utf8Path,
+        document: &Utf8Path,
+        ttl: Duration,
+    ) -> TuringResult<u64> {
+        let now = TAI64N::now();
+        let lease = Lease {
+            token: self.last_token.fetch_add(1, Ordering::Relaxed) + 1,
+            expires_at: now + ttl,
+        };
+
+        match self.held.entry((db.to_path_buf(), document.to_path_buf())) {
+            Entry::Occupied(mut held) => {
+                if held.get().expires_at > now {
+                    return Err(TuringDbError::DocumentLocked);
+                }
+
+                held.insert(lease);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(lease);
+            }
+        }
+
+        Ok(lease.token)
+    }
+    /// Release the lease `token` on a document. Fails with `LockNotHeld` when the lease
+    /// expired and the document was locked again since, or was already released
+    pub(crate) fn release(
+        &self,
+        db: &Utf8Path,
+        document: &Utf8Path,
+        token: u64,
+    ) -> TuringResult<()> {
+        match self
+            .held
+            .remove_if(&(db.to_path_buf(), document.to_path_buf()), |_, lease| {
+                lease.token == token
+            }) {
+            Some(_) => Ok(()),
+            None => Err(TuringDbError::LockNotHeld),
+        }
+    }
+    /// Forget the leases that expired before `cutoff`, returning how many were removed
+    pub(crate) fn remove_expired(&self, cutoff: TAI64N) -> usize {
+        let before = self.held.len();
+        self.held.retain(|_, lease| lease.expires_at >= cutoff);
+
+        before.saturating_sub(self.held.len())
+    }
+}
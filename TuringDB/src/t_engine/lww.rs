@@ -0,0 +1,506 @@
+use crate::{
+    Document, FieldData, OpsOutcome, TDBCell, TuringClient, TuringCommand, TuringDBFieldOps,
+    TuringDbError, TuringEngine, TuringResult, TuringServer,
+};
+use async_channel::{Receiver, Sender};
+use async_executor::Executor;
+use async_io::Timer;
+use camino::Utf8Path;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc, time::Duration};
+use tai64::TAI64N;
+
+/// The sled tree inside every document holding the `Stamp` of each field written under multi-writer replication
+pub(crate) const VERSION_TREE: &str = "__turingdb_versions";
+/// The sled tree inside every document holding the versions of its fields that lost to a concurrent write,
+/// keyed by the key of the field followed by the encoded version
+pub(crate) const CONFLICT_TREE: &str = "__turingdb_conflicts";
+/// How long a writer waits by default before sending a write again to a peer it could not reach
+pub const DEFAULT_RETRY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Orders the writes to a field made on the writers of a multi-writer cluster, the greatest one wins.
+/// Versions compare by their time first, then by the id of the node that made them, then by
+/// the revision of the document the write produced on that node, so every node picks the same winner
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Version {
+///     timestamp: TAI64N,
+///     node: u64,
+///     revision: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Version {
+    timestamp: TAI64N,
+    node: u64,
+    revision: u64,
+}
+
+impl Version {
+    /// The version of a write the node `node` makes on top of `current`, later than `current`
+    /// even when the clock of the node is behind the clock of the node that wrote it
+    pub(crate) fn next(node: u64, revision: u64, current: Option<Version>) -> Version {
+        let mut timestamp = TAI64N::now();
+        if let Some(current) = current {
+            if timestamp <= current.timestamp {
+                timestamp = current.timestamp + Duration::from_nanos(1);
+            }
+        }
+
+        Version {
+            timestamp,
+            node,
+            revision,
+        }
+    }
+    /// The time of the write
+    pub fn timestamp(&self) -> TAI64N {
+        self.timestamp
+    }
+    /// The id of the node that made the write
+    pub fn node(&self) -> u64 {
+        self.node
+    }
+    /// The revision of the document the write produced on the node that made it
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+    /// Encoded so that the bytes of versions sort as the versions do
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = self.timestamp.to_bytes().to_vec();
+        bytes.extend_from_slice(&self.node.to_be_bytes());
+        bytes.extend_from_slice(&self.revision.to_be_bytes());
+
+        bytes
+    }
+}
+
+/// The version of a write along with the version of the field it was made on top of.
+/// A write not made on top of the version a field holds is concurrent with it, the one of the
+/// two that loses is kept as a `Conflict` of the field. A resolving write settles the conflicts
+/// of the field older than itself, the way an application picks the value a field keeps
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Stamp {
+///     version: Version,
+///     replaces: Option<Version>,
+///     resolves: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Stamp {
+    version: Version,
+    replaces: Option<Version>,
+    resolves: bool,
+}
+
+impl Stamp {
+    pub(crate) fn new(version: Version, replaces: Option<Version>, resolves: bool) -> Self {
+        Self {
+            version,
+            replaces,
+            resolves,
+        }
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// The version the field held on the node that made the write, `None` for a field it never wrote
+    pub fn replaces(&self) -> Option<Version> {
+        self.replaces
+    }
+
+    pub fn resolves(&self) -> bool {
+        self.resolves
+    }
+    /// The stamp of the last write a field of the document kept
+    pub(crate) fn read(sled_db: &Document, key: &[u8]) -> TuringResult<Option<Stamp>> {
+        match sled_db.open_tree(VERSION_TREE)?.get(key)? {
+            None => Ok(None),
+            Some(stored) => Ok(Some(bincode::deserialize::<Stamp>(&stored)?)),
+        }
+    }
+
+    pub(crate) fn encode(&self) -> TuringResult<Vec<u8>> {
+        Ok(bincode::serialize::<Stamp>(self)?)
+    }
+}
+
+/// A version of a field that lost to a concurrent write, kept until a resolving write settles it
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Conflict {
+///     key: Vec<u8>,
+///     version: Version,
+///     value: Option<FieldData>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Conflict {
+    key: Vec<u8>,
+    version: Version,
+    value: Option<FieldData>,
+}
+
+impl Conflict {
+    pub(crate) fn new(key: &[u8], version: Version, value: Option<FieldData>) -> Self {
+        Self {
+            key: key.into(),
+            version,
+            value,
+        }
+    }
+    /// The key of the field
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+    /// The contents the write left the field with, `None` for a write that removed it
+    pub fn value(&self) -> Option<&FieldData> {
+        self.value.as_ref()
+    }
+    /// The key of the conflict in the conflict tree, the conflicts of a field sort by version
+    pub(crate) fn tree_key(&self) -> Vec<u8> {
+        let mut tree_key = self.key.clone();
+        tree_key.extend_from_slice(&self.version.to_bytes());
+
+        tree_key
+    }
+
+    pub(crate) fn encode(&self) -> TuringResult<Vec<u8>> {
+        Ok(bincode::serialize::<Conflict>(self)?)
+    }
+
+    pub(crate) fn decode(stored: &[u8]) -> TuringResult<Conflict> {
+        Ok(bincode::deserialize::<Conflict>(stored)?)
+    }
+}
+
+/// Runs a `TuringServer` as one of the writers of a multi-writer cluster. Every node takes writes
+/// and sends them to each of its peers, which apply them in the order the node made them.
+/// Concurrent writes to a field are resolved the same way on every node, the write with the
+/// greatest `Version` is kept and the other one is kept as a `Conflict` the application reads
+/// with `TuringClient::field_conflicts` and settles with `TuringClient::field_resolve`.
+/// A CRDT field, see `Crdt`, merges the concurrent changes of every node instead of keeping conflicts.
+/// Fields are inserted, modified and removed, and databases and documents created and dropped,
+/// every other write, transactions among them, is refused with `NotReplicated`.
+/// The writes for a peer that is down are held in memory until it is back, they are lost
+/// when the node stops first. Nodes talk over the wire protocol with admin commands,
+/// they share the admin token
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct MultiWriter {
+///     node: u64,
+///     peers: Vec<SocketAddr>,
+///     admin_token: Option<String>,
+///     retry_interval: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultiWriter {
+    node: u64,
+    peers: Vec<SocketAddr>,
+    admin_token: Option<String>,
+    retry_interval: Duration,
+}
+
+impl MultiWriter {
+    /// The node `node`, unique within the cluster since it breaks the ties between versions
+    pub fn new(node: u64) -> Self {
+        Self {
+            node,
+            peers: Vec::new(),
+            admin_token: None,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        }
+    }
+    /// Another writer of the cluster, at the address its `TuringServer` listens on
+    pub fn add_peer(mut self, address: SocketAddr) -> Self {
+        if !self.peers.contains(&address) {
+            self.peers.push(address);
+        }
+
+        self
+    }
+    /// The admin token of the other nodes
+    pub fn set_admin_token(mut self, admin_token: &str) -> Self {
+        self.admin_token = Some(admin_token.into());
+
+        self
+    }
+
+    pub fn set_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+
+        self
+    }
+
+    pub fn get_node(&self) -> u64 {
+        self.node
+    }
+
+    pub fn get_peers(&self) -> &[SocketAddr] {
+        &self.peers
+    }
+
+    pub fn get_retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+}
+
+/// A running writer of a multi-writer cluster, started by `TuringServer::run_until` when a `MultiWriter` is set
+#[derive(Debug)]
+pub(crate) struct MultiWriterNode {
+    config: MultiWriter,
+    engine: Arc<TuringEngine>,
+    // The writes waiting to be sent to each peer, in the order the node made them
+    outboxes: HashMap<SocketAddr, Sender<TuringCommand>>,
+}
+
+impl MultiWriterNode {
+    /// Spawn the task sending the writes of the node to each peer
+    pub(crate) fn start<'a>(
+        config: &MultiWriter,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> Arc<MultiWriterNode> {
+        let mut outboxes = HashMap::with_capacity(config.peers.len());
+        let mut receivers = Vec::with_capacity(config.peers.len());
+        for peer in &config.peers {
+            let (sender, receiver) = async_channel::unbounded();
+            outboxes.insert(*peer, sender);
+            receivers.push((*peer, receiver));
+        }
+
+        let node = Arc::new(MultiWriterNode {
+            config: config.clone(),
+            engine,
+            outboxes,
+        });
+        for (peer, receiver) in receivers {
+            executor
+                .spawn(Arc::clone(&node).peer_loop(peer, receiver))
+                .detach();
+        }
+
+        node
+    }
+    /// End the tasks of the node, for when the server shuts down
+    pub(crate) fn stop(&self) {
+        for outbox in self.outboxes.values() {
+            outbox.close();
+        }
+    }
+    /// Run a write a client sent then queue it for every peer
+    pub(crate) async fn write(&self, command: TuringCommand) -> TuringResult<OpsOutcome> {
+        let (outcome, forwarded) = match command {
+            TuringCommand::FieldInsert {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                let ops = MultiWriterNode::ops(&db, &document, &key);
+                if self.field_exists(&ops).await? {
+                    return Err(TuringDbError::KeyAlreadyExists);
+                }
+
+                self.stamp(ops, Some(value), false).await?
+            }
+            TuringCommand::FieldModify {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                let ops = MultiWriterNode::ops(&db, &document, &key);
+                if !self.field_exists(&ops).await? {
+                    return Err(TuringDbError::FieldNotFound);
+                }
+
+                self.stamp(ops, Some(value), false).await?
+            }
+            TuringCommand::FieldRemove { db, document, key } => {
+                let ops = MultiWriterNode::ops(&db, &document, &key);
+                if !self.field_exists(&ops).await? {
+                    return Err(TuringDbError::FieldNotFound);
+                }
+
+                self.stamp(ops, None, false).await?
+            }
+            TuringCommand::FieldResolve {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                self.stamp(MultiWriterNode::ops(&db, &document, &key), value, true)
+                    .await?
+            }
+            TuringCommand::CrdtUpdate {
+                db,
+                document,
+                key,
+                op,
+            } => {
+                let (state, delta) = self
+                    .engine
+                    .crdt_update(
+                        &MultiWriterNode::ops(&db, &document, &key),
+                        op,
+                        self.config.node,
+                    )
+                    .await?;
+
+                (
+                    OpsOutcome::CrdtState(state),
+                    TuringCommand::CrdtMerge {
+                        db,
+                        document,
+                        key,
+                        delta,
+                    },
+                )
+            }
+            command @ TuringCommand::DbCreate { .. }
+            | command @ TuringCommand::DbDrop { .. }
+            | command @ TuringCommand::DocumentCreate { .. }
+            | command @ TuringCommand::DocumentDrop { .. } => (
+                TuringServer::dispatch(&self.engine, command.clone()).await?,
+                TuringCommand::PeerWrite {
+                    command: Box::new(command),
+                },
+            ),
+            _ => return Err(TuringDbError::NotReplicated),
+        };
+
+        for outbox in self.outboxes.values() {
+            outbox.try_send(forwarded.clone()).ok();
+        }
+
+        Ok(outcome)
+    }
+    /// Apply a write a peer made, it is not sent on since the peer sends it to every node
+    pub(crate) async fn merge(&self, command: TuringCommand) -> TuringResult<OpsOutcome> {
+        match command {
+            TuringCommand::FieldMerge {
+                db,
+                document,
+                key,
+                value,
+                stamp,
+            } => {
+                self.engine
+                    .field_merge(&MultiWriterNode::ops(&db, &document, &key), value, stamp)
+                    .await
+            }
+            TuringCommand::CrdtMerge {
+                db,
+                document,
+                key,
+                delta,
+            } => {
+                self.engine
+                    .crdt_merge(&MultiWriterNode::ops(&db, &document, &key), delta)
+                    .await
+            }
+            TuringCommand::PeerWrite { command } => match *command {
+                command @ TuringCommand::DbCreate { .. }
+                | command @ TuringCommand::DbDrop { .. }
+                | command @ TuringCommand::DocumentCreate { .. }
+                | command @ TuringCommand::DocumentDrop { .. } => {
+                    TuringServer::dispatch(&self.engine, command).await
+                }
+                _ => Err(TuringDbError::NotReplicated),
+            },
+            _ => Err(TuringDbError::Bug(
+                "Only the writes of a peer are merged".into(),
+            )),
+        }
+    }
+
+    async fn stamp(
+        &self,
+        ops: TuringDBFieldOps,
+        value: Option<TDBCell>,
+        resolves: bool,
+    ) -> TuringResult<(OpsOutcome, TuringCommand)> {
+        let (value, stamp) = self
+            .engine
+            .field_stamp(&ops, value, self.config.node, resolves)
+            .await?;
+
+        Ok((
+            OpsOutcome::FieldMerged,
+            TuringCommand::FieldMerge {
+                db: ops.get_db_name(),
+                document: ops.get_document_name(),
+                key: ops.get_key(),
+                value,
+                stamp,
+            },
+        ))
+    }
+
+    async fn field_exists(&self, ops: &TuringDBFieldOps) -> TuringResult<bool> {
+        match self.engine.field_get(ops).await {
+            Ok(_) => Ok(true),
+            Err(TuringDbError::FieldNotFound) => Ok(false),
+            Err(error) => Err(error),
+        }
+    }
+
+    fn ops(db: &Utf8Path, document: &Utf8Path, key: &[u8]) -> TuringDBFieldOps {
+        TuringDBFieldOps::default()
+            .db(db.as_str())
+            .document(document.as_str())
+            .key(key)
+    }
+    /// Send the writes of the node to a peer in order. A write is sent again until the peer answers it,
+    /// an error it answers with is the outcome of the write on the peer and is not sent again
+    async fn peer_loop(self: Arc<Self>, address: SocketAddr, outbox: Receiver<TuringCommand>) {
+        let mut client = None;
+
+        while let Ok(command) = outbox.recv().await {
+            while !outbox.is_closed() {
+                match self.send(&mut client, address, command.clone()).await {
+                    Ok(()) | Err(TuringDbError::Server(_)) => break,
+                    Err(_) => {
+                        client = None;
+                        Timer::after(self.config.retry_interval).await;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn send(
+        &self,
+        client: &mut Option<TuringClient>,
+        address: SocketAddr,
+        command: TuringCommand,
+    ) -> TuringResult<()> {
+        if client.is_none() {
+            let mut connected = TuringClient::connect(address).await?;
+            if let Some(admin_token) = &self.config.admin_token {
+                // A token the peer refuses is a transport failure, the writes wait until it is fixed
+                if let Err(error) = connected.authenticate(admin_token).await {
+                    return Err(match error {
+                        TuringDbError::Server(_) => TuringDbError::PermissionDenied,
+                        error => error,
+                    });
+                }
+            }
+            *client = Some(connected);
+        }
+
+        match client {
+            Some(client) => client.request(command).await.map(|_| ()),
+            None => Err(TuringDbError::NotConnected),
+        }
+    }
+}
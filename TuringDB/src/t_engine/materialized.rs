@@ -0,0 +1,374 @@
+use crate::{
+    Accumulator, Aggregation, Collation, Document, FieldData, Filter, LogOp, MetaEncoding,
+    MetaFile, OpsOutcome, Quarantine, Query, Shuffle, TuringDB, TuringDbError, TuringResult,
+};
+use async_lock::RwLock;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const VIEWS_META_NAME: &str = "VIEWS.meta";
+
+/// The fields a materialized view keeps of a document of its database
+type Row = Vec<(Vec<u8>, FieldData)>;
+
+/// Defines a materialized view over the documents of a database matched by a filter.
+/// A view with accumulators is read as the aggregation of its rows
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ViewDefinition {
+///     db: Utf8PathBuf,
+///     filter: Filter,
+///     fields: Option<Vec<Vec<u8>>>,
+///     group_by: Option<Vec<u8>>,
+///     accumulators: Vec<Accumulator>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ViewDefinition {
+    db: Utf8PathBuf,
+    filter: Filter,
+    // `None` keeps every field of a matching document
+    fields: Option<Vec<Vec<u8>>>,
+    group_by: Option<Vec<u8>>,
+    accumulators: Vec<Accumulator>,
+}
+
+impl ViewDefinition {
+    /// Keep every field of every document of the database `db`
+    pub fn new(db: &Utf8Path) -> Self {
+        Self {
+            db: db.into(),
+            filter: Filter::All,
+            fields: None,
+            group_by: None,
+            accumulators: Vec::new(),
+        }
+    }
+
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+
+        self
+    }
+    /// Only keep these fields of the matching documents
+    pub fn set_fields(mut self, fields: Vec<Vec<u8>>) -> Self {
+        self.fields = Some(fields);
+
+        self
+    }
+    /// Put rows with equal values of the field in the same group when the view is read
+    pub fn set_group_by(mut self, key: &[u8]) -> Self {
+        self.group_by = Some(key.into());
+
+        self
+    }
+    /// Add an accumulator, a view with accumulators is read as one result for each group
+    pub fn add_accumulator(mut self, accumulator: Accumulator) -> Self {
+        self.accumulators.push(accumulator);
+
+        self
+    }
+
+    pub fn get_db(&self) -> &Utf8Path {
+        &self.db
+    }
+
+    pub fn get_filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn get_fields(&self) -> Option<&[Vec<u8>]> {
+        self.fields.as_deref()
+    }
+
+    pub fn get_group_by(&self) -> Option<&[u8]> {
+        self.group_by.as_deref()
+    }
+
+    pub fn get_accumulators(&self) -> &[Accumulator] {
+        &self.accumulators
+    }
+    /// The aggregation of the rows of the view named `name`, `None` without accumulators
+    pub(crate) fn aggregation(&self, name: &Utf8Path) -> Option<Aggregation> {
+        if self.accumulators.is_empty() {
+            return None;
+        }
+
+        let mut aggregation = Aggregation::new(name);
+        if let Some(key) = &self.group_by {
+            aggregation = aggregation.set_group_by(key);
+        }
+        for accumulator in self.accumulators.iter() {
+            aggregation = aggregation.add_accumulator(accumulator.clone());
+        }
+
+        Some(aggregation)
+    }
+    /// Check whether a field is returned when the view is read
+    fn projects(&self, key: &[u8]) -> bool {
+        match &self.fields {
+            None => true,
+            Some(fields) => fields.iter().any(|field| field.as_slice() == key),
+        }
+    }
+    /// The fields the aggregation reads are kept even when they are not returned
+    fn keeps(&self, key: &[u8]) -> bool {
+        self.projects(key)
+            || self.group_by.as_deref() == Some(key)
+            || self
+                .accumulators
+                .iter()
+                .any(|accumulator| accumulator.key() == Some(key))
+    }
+}
+
+/// A materialized view and the rows it holds, keyed by the name of the document each row was read from.
+/// The rows are kept up to date as the documents of the database are written to
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct MaterializedView {
+///     definition: ViewDefinition,
+///     rows: RwLock<Option<BTreeMap<Utf8PathBuf, Row>>>,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct MaterializedView {
+    definition: ViewDefinition,
+    // `None` until the view is first read, or once its rows could not be kept up to date
+    rows: RwLock<Option<BTreeMap<Utf8PathBuf, Row>>>,
+}
+
+impl MaterializedView {
+    pub(crate) fn new(definition: ViewDefinition) -> Self {
+        Self {
+            definition,
+            rows: RwLock::new(None),
+        }
+    }
+
+    pub(crate) fn definition(&self) -> &ViewDefinition {
+        &self.definition
+    }
+    /// Bring the rows up to date with the documents an operation wrote to, by reading them again once it
+    /// has been applied. Rows that can not be brought up to date, or whose database is gone,
+    /// are dropped and read again from the database the next time the view is read
+    pub(crate) async fn refresh(&self, db: Option<&TuringDB>, op: &LogOp) {
+        let mut rows = self.rows.write().await;
+
+        // The rows a range filter over text matches change along with the collation of the database
+        if let LogOp::DbSetCollation { .. } = op {
+            *rows = None;
+            return;
+        }
+
+        let (db, document_names) = match (db, op.documents()) {
+            (Some(db), Some(document_names)) => (db, document_names),
+            _ => {
+                *rows = None;
+                return;
+            }
+        };
+        let held = match rows.as_mut() {
+            None => return,
+            Some(held) => held,
+        };
+
+        for document_name in document_names {
+            if self.refresh_row(held, db, document_name).await.is_err() {
+                *rows = None;
+                return;
+            }
+        }
+    }
+
+    async fn refresh_row(
+        &self,
+        rows: &mut BTreeMap<Utf8PathBuf, Row>,
+        db: &TuringDB,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        if !db.list.contains(document_name).await? {
+            rows.remove(document_name);
+
+            return Ok(());
+        }
+
+        let collation = *db.collation.lock().await;
+        match self.row(collation, &db.document(document_name).await?)? {
+            Some(row) => rows.insert(document_name.into(), row),
+            None => rows.remove(document_name),
+        };
+
+        Ok(())
+    }
+    /// The row a document is held as, `None` when the filter of the view compared by `collation` does not match it
+    fn row(&self, collation: Collation, sled_db: &Document) -> TuringResult<Option<Row>> {
+        if !self
+            .definition
+            .filter
+            .matches_collated(sled_db, collation)?
+        {
+            return Ok(None);
+        }
+
+        let mut row = Vec::new();
+        for entry in sled_db.iter() {
+            let (key, stored) = entry?;
+
+            if self.definition.keeps(&key) {
+                row.push((key.to_vec(), TuringDB::decode_field(&stored)?));
+            }
+        }
+
+        Ok(Some(row))
+    }
+    /// Read every document of the database into rows, which rehydrates documents in the cold tier
+    async fn build(&self, db: &TuringDB) -> TuringResult<BTreeMap<Utf8PathBuf, Row>> {
+        let mut rows = BTreeMap::new();
+
+        let collation = *db.collation.lock().await;
+        for (document_name, document) in db.list.documents().await? {
+            if let Some(row) = self.row(collation, &document.open().await?)? {
+                rows.insert(document_name, row);
+            }
+        }
+
+        Ok(rows)
+    }
+    /// Read the rows, building them from the database `db` first when they are not held
+    async fn read<T, F>(&self, db: &TuringDB, read: F) -> TuringResult<T>
+    where
+        F: FnOnce(&BTreeMap<Utf8PathBuf, Row>) -> TuringResult<T>,
+    {
+        {
+            let rows = self.rows.read().await;
+            if let Some(rows) = rows.as_ref() {
+                return read(rows);
+            }
+        }
+
+        let mut rows = self.rows.write().await;
+        if rows.is_none() {
+            *rows = Some(self.build(db).await?);
+        }
+
+        match rows.as_ref() {
+            Some(rows) => read(rows),
+            None => Err(TuringDbError::Bug(
+                "Materialized view missing after build".into(),
+            )),
+        }
+    }
+    /// Run a query over the rows of the view as if they were the documents of a database
+    pub(crate) async fn query(&self, db: &TuringDB, query: &Query) -> TuringResult<OpsOutcome> {
+        let definition = &self.definition;
+        let query = &query.collated(*db.collation.lock().await);
+
+        self.read(db, |rows| {
+            let keyset = query.keyset()?;
+
+            // The rows are held in the order of the names of their documents, a sample visits them at random
+            let rows = rows.iter().collect::<Vec<(&Utf8PathBuf, &Row)>>();
+            let order: Box<dyn Iterator<Item = usize>> = match query.get_sample() {
+                None => Box::new(0..rows.len()),
+                Some(_) => Box::new(Shuffle::new(rows.len())),
+            };
+
+            let mut matches = Vec::new();
+
+            for index in order {
+                let (document_name, row) = rows[index];
+
+                match query.get_sample() {
+                    Some(sample) if matches.len() >= sample => break,
+                    None if query.is_complete(matches.len()) => break,
+                    _ => (),
+                }
+
+                if !query.matches(row.as_slice())? {
+                    continue;
+                }
+
+                let sort_key = query.sort_key(row.as_slice())?;
+                if let Some(keyset) = &keyset {
+                    if !query.follows(keyset, &sort_key, document_name) {
+                        continue;
+                    }
+                }
+
+                let fields = row
+                    .iter()
+                    .filter(|(key, _)| definition.projects(key) && query.selects(key))
+                    .cloned()
+                    .collect();
+
+                matches.push((sort_key, (document_name.clone(), fields)));
+            }
+
+            let (documents, continuation) = query.page(matches)?;
+
+            Ok(OpsOutcome::DocumentMatches {
+                documents,
+                continuation,
+            })
+        })
+        .await
+    }
+    /// Compute the accumulators of an aggregation over each group of the matching rows of the view
+    pub(crate) async fn aggregate(
+        &self,
+        db: &TuringDB,
+        aggregation: &Aggregation,
+    ) -> TuringResult<OpsOutcome> {
+        self.read(db, |rows| {
+            let mut groups = BTreeMap::new();
+
+            for row in rows.values() {
+                if aggregation.get_filter().matches(row.as_slice())? {
+                    aggregation.accumulate(&mut groups, row.as_slice())?;
+                }
+            }
+
+            Ok(OpsOutcome::Aggregated(aggregation.finish(groups)))
+        })
+        .await
+    }
+}
+
+/// The definitions of the materialized views of a repo. Only the definitions are kept on disk,
+/// the rows of a view are read again from its database the first time the view is read
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Views;
+
+impl Views {
+    pub(crate) async fn load(
+        repo_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<BTreeMap<Utf8PathBuf, ViewDefinition>> {
+        Ok(MetaFile::read::<BTreeMap<Utf8PathBuf, ViewDefinition>>(
+            &Views::path(repo_dir),
+            quarantine,
+        )
+        .await?
+        .unwrap_or_default())
+    }
+
+    pub(crate) async fn persist(
+        repo_dir: &Utf8Path,
+        definitions: &BTreeMap<Utf8PathBuf, ViewDefinition>,
+    ) -> TuringResult<()> {
+        MetaFile::write(
+            &Views::path(repo_dir),
+            &MetaEncoding::Bincode.encode(definitions)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(repo_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(VIEWS_META_NAME);
+
+        path
+    }
+}
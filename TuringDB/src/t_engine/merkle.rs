@@ -0,0 +1,337 @@
+use crate::{
+    DocumentIndex, ErrorCode, LogOp, TDBCell, TuringClient, TuringDB, TuringDBOps, TuringDbError,
+    TuringEngine, TuringResult, WireError,
+};
+use async_io::Timer;
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    hash::Hasher,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+/// How many leaves the Merkle tree of a database has, a document belongs to the leaf its name hashes to
+pub const MERKLE_LEAVES: usize = 256;
+/// How long a node waits by default between two passes comparing its databases with a peer
+pub const DEFAULT_REPAIR_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The hashes of the documents of a database rolled up into a binary tree, so two nodes holding
+/// the database find the documents they disagree on by comparing the hashes of the subtrees that differ.
+/// `nodes` holds the root first and the `MERKLE_LEAVES` leaves last, the children of the node at `index`
+/// are at `2 * index + 1` and `2 * index + 2`. An empty leaf hashes to 0
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct MerkleTree {
+///     nodes: Vec<u64>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleTree {
+    nodes: Vec<u64>,
+}
+
+impl MerkleTree {
+    fn build(leaves: Vec<u64>) -> Self {
+        let mut nodes = vec![0; MERKLE_LEAVES - 1];
+        nodes.extend(leaves);
+
+        for index in (0..MERKLE_LEAVES - 1).rev() {
+            let mut hasher = seahash::SeaHasher::new();
+            hasher.write_u64(nodes[2 * index + 1]);
+            hasher.write_u64(nodes[2 * index + 2]);
+            nodes[index] = hasher.finish();
+        }
+
+        Self { nodes }
+    }
+    /// The hash of every document of the database
+    pub fn root(&self) -> u64 {
+        self.nodes[0]
+    }
+    /// The leaves whose hash differs from the one `other` holds, descending only into the subtrees that differ
+    pub fn diff(&self, other: &MerkleTree) -> Vec<usize> {
+        if self.nodes.len() != other.nodes.len() {
+            return (0..MERKLE_LEAVES).collect();
+        }
+
+        let mut differing = Vec::new();
+        let mut pending = vec![0];
+        while let Some(index) = pending.pop() {
+            if self.nodes[index] == other.nodes[index] {
+                continue;
+            }
+
+            if index >= MERKLE_LEAVES - 1 {
+                differing.push(index - (MERKLE_LEAVES - 1));
+            } else {
+                pending.push(2 * index + 2);
+                pending.push(2 * index + 1);
+            }
+        }
+        differing.sort_unstable();
+
+        differing
+    }
+    /// The leaf the document `document_name` belongs to
+    pub fn leaf_of(document_name: &Utf8Path) -> usize {
+        (seahash::hash(document_name.as_str().as_bytes()) % MERKLE_LEAVES as u64) as usize
+    }
+}
+
+/// The hash of the contents of every document of a database. A write marks the documents it touched
+/// and they are hashed again the next time the tree is read, the hashes are kept in memory and
+/// built again the first time the tree is read after the repo is opened
+#[derive(Debug, Default)]
+pub(crate) struct DocumentHashes {
+    hashes: BTreeMap<Utf8PathBuf, u64>,
+    stale: BTreeSet<Utf8PathBuf>,
+    // Cleared when a write may have touched any document
+    built: bool,
+}
+
+impl DocumentHashes {
+    /// Mark the documents an applied operation wrote to
+    pub(crate) fn record(&mut self, op: &LogOp) {
+        match op.documents() {
+            None => self.built = false,
+            Some(documents) => {
+                self.stale
+                    .extend(documents.into_iter().map(Utf8Path::to_path_buf));
+            }
+        }
+    }
+
+    pub(crate) async fn tree(&mut self, list: &DocumentIndex) -> TuringResult<MerkleTree> {
+        self.refresh(list).await?;
+
+        let mut leaves = (0..MERKLE_LEAVES)
+            .map(|_| seahash::SeaHasher::new())
+            .collect::<Vec<seahash::SeaHasher>>();
+        let mut filled = vec![false; MERKLE_LEAVES];
+        // In name order so every node hashes a leaf the same way
+        for (document_name, hash) in &self.hashes {
+            let leaf = MerkleTree::leaf_of(document_name);
+            leaves[leaf].write(document_name.as_str().as_bytes());
+            leaves[leaf].write_u64(*hash);
+            filled[leaf] = true;
+        }
+
+        Ok(MerkleTree::build(
+            leaves
+                .into_iter()
+                .zip(filled)
+                .map(|(hasher, filled)| if filled { hasher.finish() } else { 0 })
+                .collect(),
+        ))
+    }
+    /// The documents of the leaf `leaf` with their hashes, sorted by name
+    pub(crate) async fn leaf(
+        &mut self,
+        list: &DocumentIndex,
+        leaf: usize,
+    ) -> TuringResult<Vec<(Utf8PathBuf, u64)>> {
+        if leaf >= MERKLE_LEAVES {
+            return Err(TuringDbError::InvalidInput);
+        }
+        self.refresh(list).await?;
+
+        Ok(self
+            .hashes
+            .iter()
+            .filter(|(document_name, _)| MerkleTree::leaf_of(document_name) == leaf)
+            .map(|(document_name, hash)| (document_name.clone(), *hash))
+            .collect())
+    }
+
+    async fn refresh(&mut self, list: &DocumentIndex) -> TuringResult<()> {
+        if !self.built {
+            self.hashes.clear();
+            self.stale = list.names().await?.into_iter().collect();
+            self.built = true;
+        }
+
+        for document_name in std::mem::take(&mut self.stale) {
+            let document = match list.get(&document_name).await? {
+                // Dropped since it was written
+                None => {
+                    self.hashes.remove(&document_name);
+                    continue;
+                }
+                Some(document) => document,
+            };
+
+            let sled_db = document.open().await?;
+            let mut hasher = seahash::SeaHasher::new();
+            for entry in sled_db.iter() {
+                let (key, stored) = entry?;
+                let field_data = TuringDB::decode_field(&stored)?;
+
+                hasher.write_usize(key.len());
+                hasher.write(&key);
+                hasher.write(&bincode::serialize(&field_data.data_type())?);
+                hasher.write_usize(field_data.data().len());
+                hasher.write(field_data.data());
+            }
+            self.hashes.insert(document_name, hasher.finish());
+        }
+
+        Ok(())
+    }
+}
+
+/// Keeps the databases of a repo the same as the ones a peer holds by comparing their `MerkleTree`
+/// in the background, a replica against its primary for one. Only the leaves that differ are listed
+/// and only the documents that differ are copied from the peer, a document the peer does not hold
+/// is dropped. The databases only one of the two holds are left alone, and a document written
+/// while it is repaired is compared again on the next pass. The peer is read with admin commands,
+/// the admin token has to be the one of the peer
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub struct AntiEntropy {
+///     peer: SocketAddr,
+///     admin_token: Option<String>,
+///     interval: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AntiEntropy {
+    peer: SocketAddr,
+    admin_token: Option<String>,
+    interval: Duration,
+}
+
+impl AntiEntropy {
+    /// Repair the repo from the server listening on `peer`
+    pub fn new(peer: SocketAddr) -> Self {
+        Self {
+            peer,
+            admin_token: None,
+            interval: DEFAULT_REPAIR_INTERVAL,
+        }
+    }
+    /// The admin token of the peer
+    pub fn set_admin_token(mut self, admin_token: &str) -> Self {
+        self.admin_token = Some(admin_token.into());
+
+        self
+    }
+
+    pub fn set_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+
+        self
+    }
+
+    pub fn get_peer(&self) -> SocketAddr {
+        self.peer
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        self.interval
+    }
+    /// Compare the databases with the peer every interval. Failures to reach the peer are retried
+    /// on the next pass, any other error ends the task
+    pub async fn run(self, engine: Arc<TuringEngine>) -> TuringResult<()> {
+        let mut client = None;
+
+        loop {
+            Timer::after(self.interval).await;
+
+            match self.repair(&mut client, &engine).await {
+                Ok(_) => (),
+                Err(error) if WireError::from(&error).is_retryable() => client = None,
+                Err(error) => return Err(error),
+            }
+        }
+    }
+    /// Compare every database both the repo and the peer hold once, returning how many documents were repaired
+    pub async fn repair_once(&self, engine: &TuringEngine) -> TuringResult<usize> {
+        self.repair(&mut None, engine).await
+    }
+
+    async fn repair(
+        &self,
+        client: &mut Option<TuringClient>,
+        engine: &TuringEngine,
+    ) -> TuringResult<usize> {
+        if client.is_none() {
+            *client = Some(self.connect().await?);
+        }
+        let client = match client {
+            Some(client) => client,
+            None => return Err(TuringDbError::NotConnected),
+        };
+
+        let mut repaired = 0;
+        for db in client.db_list().await? {
+            let ops_db = TuringDBOps::default().set_db_name(db.as_str());
+            let local = match engine.merkle_tree(&ops_db).await {
+                Ok(local) => local,
+                Err(TuringDbError::DbNotFound) => continue,
+                Err(error) => return Err(error),
+            };
+            let remote = client.merkle_tree(db.as_str()).await?;
+
+            let mut ops = Vec::new();
+            for leaf in local.diff(&remote) {
+                let local_hashes = engine
+                    .merkle_leaf(&ops_db, leaf)
+                    .await?
+                    .into_iter()
+                    .collect::<BTreeMap<Utf8PathBuf, u64>>();
+                let remote_hashes = client
+                    .merkle_leaf(db.as_str(), leaf)
+                    .await?
+                    .into_iter()
+                    .collect::<BTreeMap<Utf8PathBuf, u64>>();
+
+                for (document, hash) in &remote_hashes {
+                    if local_hashes.get(document) == Some(hash) {
+                        continue;
+                    }
+
+                    match client.document_get(db.as_str(), document.as_str()).await {
+                        Ok((_, fields)) => ops.push(LogOp::DocumentUpsert {
+                            db: db.clone(),
+                            document: document.clone(),
+                            fields: fields
+                                .into_iter()
+                                .map(|(key, field_data)| {
+                                    (key, TDBCell::new(field_data.data_type(), field_data.data()))
+                                })
+                                .collect(),
+                        }),
+                        // Dropped since the leaf was listed, the next pass drops it here too
+                        Err(TuringDbError::Server(error))
+                            if error.get_code() == ErrorCode::NotFound => {}
+                        Err(error) => return Err(error),
+                    }
+                }
+                for document in local_hashes.keys() {
+                    if !remote_hashes.contains_key(document) {
+                        ops.push(LogOp::DocumentDrop {
+                            db: db.clone(),
+                            document: document.clone(),
+                        });
+                    }
+                }
+            }
+
+            repaired += engine.merkle_repair(ops).await?;
+        }
+
+        Ok(repaired)
+    }
+
+    async fn connect(&self) -> TuringResult<TuringClient> {
+        let mut client = TuringClient::connect(self.peer).await?;
+        if let Some(admin_token) = &self.admin_token {
+            client.authenticate(admin_token).await?;
+        }
+
+        Ok(client)
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{Compression, MetaEncoding, Quarantine, TuringDbError, TuringResult, FORMAT_VERSION};
+use async_fs::OpenOptions;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind};
+use tai64::TAI64N;
+
+const REPO_META_NAME: &str = "REPO.meta";
+const DB_META_NAME: &str = "DB.meta";
+/// Every metadata file starts with a `u64` SeaHash checksum of the rest of the file
+const CHECKSUM_LEN: usize = 8;
+/// Followed by this magic and the `u32` format version the file was written with.
+/// Files written before format versioning have neither and are format `1`
+const META_MAGIC: &[u8; 4] = b"TDBM";
+const FORMAT_HEADER_LEN: usize = 8;
+
+/// The state of the repo recorded by the last successful commit
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct RepoMeta {
+///     checkpoint_lsn: Option<u64>,
+///     committed: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepoMeta {
+    checkpoint_lsn: Option<u64>,
+    committed: TAI64N,
+}
+
+impl RepoMeta {
+    /// Record that every operation up to and including `checkpoint_lsn` is persisted
+    pub(crate) fn new(checkpoint_lsn: Option<u64>) -> Self {
+        Self {
+            checkpoint_lsn,
+            committed: TAI64N::now(),
+        }
+    }
+    /// The last log sequence number whose effects are persisted
+    pub fn checkpoint_lsn(&self) -> Option<u64> {
+        self.checkpoint_lsn
+    }
+    /// The time of the commit
+    pub fn committed(&self) -> TAI64N {
+        self.committed
+    }
+
+    pub(crate) async fn load(
+        repo_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<RepoMeta>> {
+        MetaFile::read::<RepoMeta>(&RepoMeta::path(repo_dir), quarantine).await
+    }
+    /// The format version the repo metadata was last written with
+    pub(crate) async fn format_version(
+        repo_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<u32>> {
+        match MetaFile::read_versioned::<RepoMeta>(&RepoMeta::path(repo_dir), quarantine).await? {
+            Some((format_version, _)) => Ok(Some(format_version)),
+            None => Ok(None),
+        }
+    }
+
+    pub(crate) async fn persist(
+        &self,
+        repo_dir: &Utf8Path,
+        meta_encoding: MetaEncoding,
+    ) -> TuringResult<()> {
+        MetaFile::write(&RepoMeta::path(repo_dir), &meta_encoding.encode(self)?).await
+    }
+
+    pub(crate) fn path(repo_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(REPO_META_NAME);
+
+        path
+    }
+}
+
+/// The state of a database recorded the last time it was committed
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct DbMeta {
+///     created: TAI64N,
+///     committed: TAI64N,
+///     documents: usize,
+///     expiries: BTreeMap<Utf8PathBuf, TAI64N>,
+///     compression: Compression,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DbMeta {
+    created: TAI64N,
+    committed: TAI64N,
+    documents: usize,
+    expiries: BTreeMap<Utf8PathBuf, TAI64N>,
+    compression: Compression,
+}
+
+impl Default for DbMeta {
+    fn default() -> Self {
+        let current_time = TAI64N::now();
+
+        Self {
+            created: current_time,
+            committed: current_time,
+            documents: 0,
+            expiries: BTreeMap::new(),
+            compression: Compression::default(),
+        }
+    }
+}
+
+impl DbMeta {
+    /// The time the database was created
+    pub fn created(&self) -> TAI64N {
+        self.created
+    }
+    /// The time the database was last committed
+    pub fn committed(&self) -> TAI64N {
+        self.committed
+    }
+    /// The number of documents the database held when it was last committed
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+    /// How new values of fields in the database are compressed
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+    /// Change how new values of fields are compressed, existing values are left as they are
+    pub(crate) fn set_compression(&mut self, compression: Compression) {
+        self.compression = compression;
+    }
+    /// The time after which a document is removed by the expiry reaper
+    pub fn expires_at(&self, document_name: &Utf8Path) -> Option<TAI64N> {
+        self.expiries.get(document_name).copied()
+    }
+    /// Set or clear the time after which a document expires
+    pub(crate) fn set_expiry(&mut self, document_name: &Utf8Path, expires_at: Option<TAI64N>) {
+        match expires_at {
+            Some(expires_at) => {
+                self.expiries
+                    .insert(document_name.to_path_buf(), expires_at);
+            }
+            None => {
+                self.expiries.remove(document_name);
+            }
+        }
+    }
+    /// The documents whose expiry time has passed
+    pub(crate) fn expired(&self, now: TAI64N) -> Vec<Utf8PathBuf> {
+        self.expiries
+            .iter()
+            .filter(|(_, expires_at)| **expires_at <= now)
+            .map(|(document_name, _)| document_name.to_path_buf())
+            .collect()
+    }
+    /// Record a commit of the database holding `documents` documents
+    pub(crate) fn stamp(&mut self, documents: usize) -> &DbMeta {
+        self.committed = TAI64N::now();
+        self.documents = documents;
+
+        self
+    }
+
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<DbMeta>> {
+        MetaFile::read::<DbMeta>(&DbMeta::path(db_dir), quarantine).await
+    }
+
+    pub(crate) async fn persist(
+        &self,
+        db_dir: &Utf8Path,
+        meta_encoding: MetaEncoding,
+    ) -> TuringResult<()> {
+        MetaFile::write(&DbMeta::path(db_dir), &meta_encoding.encode(self)?).await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(DB_META_NAME);
+
+        path
+    }
+}
+
+/// Writes metadata files atomically so that a crash never leaves a half written file behind
+/// and checksums them so that corruption is detected when they are read
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct MetaFile;
+
+impl MetaFile {
+    /// Write to a temporary file, sync it then rename it over the current file,
+    /// keeping the current file as the previous good copy
+    pub(crate) async fn write(path: &Utf8Path, bytes: &[u8]) -> TuringResult<()> {
+        MetaFile::write_sealed(path, &MetaFile::seal(bytes)).await
+    }
+    /// Prefix encoded metadata with its format header and checksum, the way it is written to disk
+    pub(crate) fn seal(bytes: &[u8]) -> Vec<u8> {
+        let mut versioned = Vec::with_capacity(FORMAT_HEADER_LEN + bytes.len());
+        versioned.extend_from_slice(META_MAGIC);
+        versioned.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        versioned.extend_from_slice(bytes);
+
+        let mut sealed = Vec::with_capacity(CHECKSUM_LEN + versioned.len());
+        sealed.extend_from_slice(&seahash::hash(&versioned).to_le_bytes());
+        sealed.extend_from_slice(&versioned);
+
+        sealed
+    }
+    /// Atomically write a file that was already sealed, such as a copy fetched from a storage backend
+    pub(crate) async fn write_sealed(path: &Utf8Path, sealed: &[u8]) -> TuringResult<()> {
+        let temp_path = MetaFile::sibling(path, "tmp");
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&temp_path)
+            .await?;
+        file.write_all(sealed).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        match async_fs::rename(path, MetaFile::sibling(path, "bak")).await {
+            Ok(_) => (),
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+            }
+        }
+        async_fs::rename(&temp_path, path).await?;
+
+        MetaFile::sync_parent(path).await
+    }
+    /// Read a metadata file, falling back to the previous good copy if the current one is missing.
+    /// Copies that fail their checksum or cannot be decoded are quarantined
+    pub(crate) async fn read<T: DeserializeOwned>(
+        path: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<T>> {
+        match MetaFile::read_versioned::<T>(path, quarantine).await? {
+            Some((_, value)) => Ok(Some(value)),
+            None => Ok(None),
+        }
+    }
+    /// Read a metadata file along with the format version it was written with.
+    /// A file from a newer format than this build supports is an error rather than corruption
+    pub(crate) async fn read_versioned<T: DeserializeOwned>(
+        path: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<(u32, T)>> {
+        for candidate in [path.to_path_buf(), MetaFile::sibling(path, "bak")].iter() {
+            match async_fs::read(candidate).await {
+                Ok(bytes) => match MetaFile::verify(&bytes) {
+                    Some(verified) => {
+                        let (format_version, encoded) = MetaFile::format_header(verified);
+
+                        if format_version > FORMAT_VERSION {
+                            return Err(TuringDbError::UnsupportedFormat {
+                                found: format_version,
+                                supported: FORMAT_VERSION,
+                            });
+                        }
+
+                        match MetaEncoding::decode::<T>(encoded) {
+                            Ok(value) => return Ok(Some((format_version, value))),
+                            Err(_) => {
+                                quarantine.isolate(candidate).await?;
+                            }
+                        }
+                    }
+                    None => {
+                        quarantine.isolate(candidate).await?;
+                    }
+                },
+                Err(error) => {
+                    if error.kind() != ErrorKind::NotFound {
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+
+        Ok(None)
+    }
+    /// Check the checksum of a metadata file and of its previous good copy without decoding them,
+    /// quarantining the copies that fail. Returns each failed copy along with where it was moved to
+    pub(crate) async fn scrub(
+        path: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Vec<(Utf8PathBuf, Utf8PathBuf)>> {
+        let mut quarantined = Vec::new();
+
+        for candidate in [path.to_path_buf(), MetaFile::sibling(path, "bak")].iter() {
+            match async_fs::read(candidate).await {
+                Ok(bytes) => {
+                    if MetaFile::verify(&bytes).is_none() {
+                        let isolated = quarantine.isolate(candidate).await?;
+                        quarantined.push((candidate.clone(), isolated));
+                    }
+                }
+                Err(error) => {
+                    if error.kind() != ErrorKind::NotFound {
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+
+        Ok(quarantined)
+    }
+    /// Check the SeaHash checksum of the file, returning the bytes it protects
+    fn verify(bytes: &[u8]) -> Option<&[u8]> {
+        if bytes.len() < CHECKSUM_LEN {
+            return None;
+        }
+
+        let mut checksum_bytes = [0_u8; CHECKSUM_LEN];
+        checksum_bytes.copy_from_slice(&bytes[..CHECKSUM_LEN]);
+
+        let verified = &bytes[CHECKSUM_LEN..];
+
+        if seahash::hash(verified) == u64::from_le_bytes(checksum_bytes) {
+            Some(verified)
+        } else {
+            None
+        }
+    }
+
+    /// Split the format version from the encoded metadata
+    fn format_header(verified: &[u8]) -> (u32, &[u8]) {
+        if verified.len() >= FORMAT_HEADER_LEN && &verified[..4] == META_MAGIC {
+            let mut version_bytes = [0_u8; 4];
+            version_bytes.copy_from_slice(&verified[4..FORMAT_HEADER_LEN]);
+
+            (
+                u32::from_le_bytes(version_bytes),
+                &verified[FORMAT_HEADER_LEN..],
+            )
+        } else {
+            (1, verified)
+        }
+    }
+
+    fn sibling(path: &Utf8Path, extension: &str) -> Utf8PathBuf {
+        Utf8PathBuf::from(format!("{}.{}", path, extension))
+    }
+
+    #[cfg(unix)]
+    async fn sync_parent(path: &Utf8Path) -> TuringResult<()> {
+        if let Some(parent) = path.parent() {
+            async_fs::File::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    async fn sync_parent(_path: &Utf8Path) -> TuringResult<()> {
+        Ok(())
+    }
+}
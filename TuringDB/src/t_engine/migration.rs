@@ -0,0 +1,188 @@
+use crate::{
+    Compression, DbMeta, DocumentIndex, History, MetaEncoding, OpsLog, Partitioning, Quarantine,
+    RepoMeta, TimeIndex, TuringDB, TuringDbError, TuringResult, DELTA_HISTORY_FORMAT,
+    RESERVED_DIR_PREFIX, TIME_INDEX_FORMAT,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::stream::StreamExt;
+use std::sync::Arc;
+
+/// The format version of the files this build writes
+pub const FORMAT_VERSION: u32 = 4;
+
+/// An upgrade of a repo from format `from` to format `from + 1`
+#[derive(Debug, Clone, Copy)]
+struct Migration {
+    from: u32,
+    step: MigrationStep,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum MigrationStep {
+    FormatHeaders,
+    DeltaHistories,
+    TimeIndexes,
+}
+
+/// Every migration in the order they are run.
+/// A change to the layout of a persisted file bumps `FORMAT_VERSION` and adds its migration here
+const MIGRATIONS: &[Migration] = &[
+    // Add format version headers to the metadata files and the ops log
+    Migration {
+        from: 1,
+        step: MigrationStep::FormatHeaders,
+    },
+    // Move the history of every document into the delta encoded history tree
+    Migration {
+        from: DELTA_HISTORY_FORMAT - 1,
+        step: MigrationStep::DeltaHistories,
+    },
+    // Build the index of when each document was created and last modified
+    Migration {
+        from: TIME_INDEX_FORMAT - 1,
+        step: MigrationStep::TimeIndexes,
+    },
+];
+
+/// Upgrades repos written by older builds to the current format when they are initialized
+#[derive(Debug)]
+pub(crate) struct Migrator<'a> {
+    repo_dir: &'a Utf8Path,
+    ops_log: &'a OpsLog,
+    quarantine: &'a Arc<Quarantine>,
+    meta_encoding: MetaEncoding,
+}
+
+impl<'a> Migrator<'a> {
+    pub(crate) fn new(
+        repo_dir: &'a Utf8Path,
+        ops_log: &'a OpsLog,
+        quarantine: &'a Arc<Quarantine>,
+        meta_encoding: MetaEncoding,
+    ) -> Self {
+        Self {
+            repo_dir,
+            ops_log,
+            quarantine,
+            meta_encoding,
+        }
+    }
+    /// The format of the repo on disk, `None` for a repo that has never been committed
+    pub(crate) async fn repo_version(&self) -> TuringResult<Option<u32>> {
+        match RepoMeta::format_version(self.repo_dir, self.quarantine).await? {
+            Some(format_version) => Ok(Some(format_version)),
+            None => self.ops_log.format_version().await,
+        }
+    }
+    /// Run every migration needed to bring the repo up to `FORMAT_VERSION`,
+    /// returning the format it was upgraded from if any migration ran
+    pub(crate) async fn migrate(&self) -> TuringResult<Option<u32>> {
+        let found = match self.repo_version().await? {
+            None => return Ok(None),
+            Some(found) => found,
+        };
+
+        if found > FORMAT_VERSION {
+            return Err(TuringDbError::UnsupportedFormat {
+                found,
+                supported: FORMAT_VERSION,
+            });
+        }
+        if found == FORMAT_VERSION {
+            return Ok(None);
+        }
+
+        for migration in MIGRATIONS
+            .iter()
+            .filter(|migration| migration.from >= found)
+        {
+            match migration.step {
+                MigrationStep::FormatHeaders => self.add_format_headers().await?,
+                MigrationStep::DeltaHistories => self.upgrade_histories().await?,
+                MigrationStep::TimeIndexes => self.build_time_indexes().await?,
+            }
+        }
+
+        Ok(Some(found))
+    }
+    /// The contents of the files are unchanged so rewriting them adds the header
+    async fn add_format_headers(&self) -> TuringResult<()> {
+        for database_name in self.database_names().await? {
+            let database_path = TuringDB::build_path(self.repo_dir, &database_name);
+
+            if let Some(db_meta) = DbMeta::load(&database_path, self.quarantine).await? {
+                db_meta.persist(&database_path, self.meta_encoding).await?;
+            }
+        }
+
+        if let Some(repo_meta) = RepoMeta::load(self.repo_dir, self.quarantine).await? {
+            repo_meta.persist(self.repo_dir, self.meta_encoding).await?;
+        }
+
+        self.ops_log.add_format_header().await
+    }
+    /// The names of the database directories in the repo
+    async fn database_names(&self) -> TuringResult<Vec<Utf8PathBuf>> {
+        let mut database_names = Vec::new();
+
+        let mut repo = async_fs::read_dir(self.repo_dir).await?;
+
+        while let Some(database_entry) = repo.try_next().await? {
+            if !database_entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let database_name = match database_entry.file_name().to_str() {
+                None => return Err(TuringDbError::PathReadIsNotUtf8Path),
+                Some(database_name) => Utf8PathBuf::from(database_name),
+            };
+            if database_name.as_str().starts_with(RESERVED_DIR_PREFIX) {
+                continue;
+            }
+
+            database_names.push(database_name);
+        }
+
+        Ok(database_names)
+    }
+    /// Open every document in the repo and move its revisions into the delta encoded history tree.
+    /// The revisions written before are kept as full copies
+    async fn upgrade_histories(&self) -> TuringResult<()> {
+        for database_name in self.database_names().await? {
+            let database_path = TuringDB::build_path(self.repo_dir, &database_name);
+
+            let compression = match DbMeta::load(&database_path, self.quarantine).await? {
+                Some(db_meta) => db_meta.compression(),
+                None => Compression::default(),
+            };
+            let partitioning = Partitioning::load(&database_path, self.quarantine).await?;
+
+            for (_, document) in
+                DocumentIndex::unloaded(partitioning, &database_path, self.quarantine)
+                    .documents()
+                    .await?
+            {
+                History::upgrade(&document.open().await?, compression)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Open every document in the repo to find when it was created and last modified
+    async fn build_time_indexes(&self) -> TuringResult<()> {
+        for database_name in self.database_names().await? {
+            let database_path = TuringDB::build_path(self.repo_dir, &database_name);
+            let partitioning = Partitioning::load(&database_path, self.quarantine).await?;
+
+            let documents = DocumentIndex::unloaded(partitioning, &database_path, self.quarantine)
+                .documents()
+                .await?;
+            TimeIndex::build(documents)
+                .await?
+                .persist(&database_path)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
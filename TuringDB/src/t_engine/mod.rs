@@ -1,5 +1,186 @@
 mod database;
-pub(crate) use database::TuringDB;
+pub(crate) use database::{LazyDocument, TuringDB};
 mod engine;
 pub use engine::*;
 mod fields;
+pub use fields::*;
+mod ops_log;
+pub(crate) use ops_log::OpsLog;
+pub use ops_log::{LogOp, LogRecord};
+mod metadata;
+pub(crate) use metadata::MetaFile;
+pub use metadata::{DbMeta, RepoMeta};
+mod codec;
+pub use codec::*;
+mod config;
+pub use config::*;
+mod quarantine;
+pub use quarantine::Quarantine;
+pub(crate) use quarantine::RESERVED_DIR_PREFIX;
+mod repo_lock;
+pub(crate) use repo_lock::RepoLock;
+mod compression;
+pub use compression::Compression;
+mod history;
+pub use history::Revision;
+pub(crate) use history::{
+    History, StoredRevision, CURRENT_REVISION_KEY, DELTA_HISTORY_FORMAT, DELTA_RUN_TREE,
+    HISTORY_TREE, TOMBSTONE_TREE,
+};
+mod migration;
+pub(crate) use migration::Migrator;
+pub use migration::FORMAT_VERSION;
+mod stream;
+pub(crate) use stream::{ChunkedStream, STREAM_TREE};
+pub use stream::{StreamManifest, STREAM_CHUNK_LEN};
+mod backup;
+pub use backup::{BackupFile, BackupManifest};
+mod view;
+pub use view::DocumentView;
+pub(crate) use view::RevisionPins;
+mod stats;
+pub(crate) use stats::DbUsage;
+pub use stats::{DbStats, IndexStats};
+mod scrub;
+pub use scrub::{IntegrityFinding, IntegrityIssue, IntegrityReport};
+mod snapshot;
+pub use snapshot::SnapshotMeta;
+pub(crate) use snapshot::{snapshot_dir, SnapshotDocument};
+mod partition;
+pub(crate) use partition::DocumentIndex;
+mod bloom;
+pub(crate) use bloom::BloomFilter;
+pub use partition::Partitioning;
+mod storage;
+pub use storage::{LocalBackend, StorageBackend};
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "s3")]
+pub use s3::{S3Backend, S3Config};
+mod remote;
+pub(crate) use remote::RemoteRepo;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+mod uring;
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+pub(crate) use uring::UringLog;
+mod query;
+pub(crate) use query::{FieldSource, Matched, Shuffle};
+pub use query::{Filter, Pattern, Query, QueryPlan, SortOrder, Value};
+mod turingql;
+pub use turingql::{Statement, TuringQL};
+mod patch;
+pub use patch::{Patch, PatchOp};
+mod aggregate;
+pub use aggregate::{Accumulator, AggregateGroup, Aggregation};
+mod batch;
+pub use batch::WriteOp;
+mod cursor;
+pub(crate) use cursor::{Cursor, Cursors, SCAN_BATCH};
+mod transaction;
+pub use transaction::Transaction;
+pub(crate) use transaction::Transactions;
+mod times;
+pub use times::{DocumentTimes, TimeField};
+pub(crate) use times::{TimeIndex, TIME_INDEX_FORMAT};
+mod schema;
+pub use schema::{Schema, SchemaField, SchemaViolation, Structure};
+mod vector;
+pub(crate) use vector::Hnsw;
+pub use vector::{Distance, Embedding, HnswParams, Neighbour, VectorSpace};
+mod reference;
+pub(crate) use reference::MAX_POPULATED;
+pub use reference::{Populated, Reference, Resolution, MAX_POPULATE_DEPTH};
+mod materialized;
+pub use materialized::ViewDefinition;
+pub(crate) use materialized::{MaterializedView, Views};
+mod trash;
+pub(crate) use trash::Trash;
+mod collation;
+pub use collation::{Collation, Locale};
+mod secondary;
+pub use secondary::IndexKind;
+pub(crate) use secondary::{FieldIndex, IndexDeclaration, Indexes};
+mod unique;
+pub(crate) use unique::UniqueKey;
+mod fulltext;
+pub(crate) use fulltext::TextIndex;
+pub use fulltext::{SearchHit, TextIndexDefinition, MAX_FUZZY_EDITS};
+mod ttl;
+pub(crate) use ttl::TtlIndex;
+mod geo;
+pub use geo::{GeoBox, GeoPoint};
+mod prefix;
+pub(crate) use prefix::PrefixIndex;
+mod cold;
+pub(crate) use cold::{
+    ColdDocument, DocumentContents, ARCHIVING_EXTENSION, COLD_DIR, COLD_EXTENSION,
+    REHYDRATING_EXTENSION,
+};
+mod changes;
+pub(crate) use changes::ChangeFeed;
+pub use changes::{Subscription, CHANGE_BUFFER};
+mod server;
+pub use server::{
+    termination_signal, TuringServer, DEFAULT_DRAIN_TIMEOUT, DEFAULT_IDLE_TIMEOUT,
+    DEFAULT_IO_TIMEOUT, DEFAULT_MAX_CONNECTIONS, DEFAULT_SERVER_PORT,
+};
+mod rate_limit;
+pub use rate_limit::RateLimit;
+pub(crate) use rate_limit::RateLimiter;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "http")]
+pub use http::{HttpGateway, DEFAULT_HTTP_PORT};
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "websocket")]
+pub use websocket::{WebSocketGateway, DEFAULT_WEBSOCKET_PING_INTERVAL, DEFAULT_WEBSOCKET_PORT};
+#[cfg(feature = "resp")]
+mod resp;
+#[cfg(feature = "resp")]
+pub use resp::{RespGateway, DEFAULT_RESP_DB, DEFAULT_RESP_PORT, RESP_VALUE_FIELD};
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(feature = "tls")]
+pub use tls::{ClientTls, ServerTls};
+mod client;
+pub use client::TuringClient;
+mod wire_error;
+pub(crate) use wire_error::ErrorKeys;
+pub use wire_error::{ErrorCode, WireError};
+mod wire;
+pub(crate) use wire::{deadline, wire_compression, wire_encoding};
+pub use wire::{
+    ClientHello, Frame, ServerHello, ServerInfo, TuringCommand, TuringResponse, WireMessage,
+    CBOR_ENCODING, COMPRESSION_THRESHOLD, DEFAULT_MAX_FRAME_LEN, JSON_ENCODING, LZ4_COMPRESSION,
+    MSGPACK_ENCODING, PIPELINING, PROTOCOL_FEATURES, PROTOCOL_VERSION, ZSTD_COMPRESSION,
+};
+mod payload;
+pub use payload::PayloadEncoding;
+mod lock;
+pub(crate) use lock::DocumentLocks;
+mod replica;
+pub use replica::{Replica, ReplicaSnapshot, REPLICA_BATCH};
+mod raft;
+pub(crate) use raft::RaftNode;
+pub use raft::{
+    Raft, RaftEntry, DEFAULT_ELECTION_TIMEOUT, DEFAULT_HEARTBEAT_INTERVAL,
+    DEFAULT_SNAPSHOT_THRESHOLD,
+};
+mod lww;
+pub use lww::{Conflict, MultiWriter, Stamp, Version, DEFAULT_RETRY_INTERVAL};
+pub(crate) use lww::{MultiWriterNode, CONFLICT_TREE, VERSION_TREE};
+mod crdt;
+pub use crdt::{Crdt, CrdtOp, LwwMap, OrSet};
+mod shard;
+pub(crate) use shard::Router;
+pub use shard::{HashRing, Route, ShardedClient, DEFAULT_VIRTUAL_NODES};
+mod gossip;
+pub(crate) use gossip::GossipNode;
+pub use gossip::{
+    Gossip, Health, Member, Topology, DEFAULT_DEAD_TIMEOUT, DEFAULT_GOSSIP_INTERVAL,
+    DEFAULT_SUSPECT_TIMEOUT,
+};
+mod merkle;
+pub(crate) use merkle::DocumentHashes;
+pub use merkle::{AntiEntropy, MerkleTree, DEFAULT_REPAIR_INTERVAL, MERKLE_LEAVES};
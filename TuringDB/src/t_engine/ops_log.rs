@@ -0,0 +1,919 @@
+#[cfg(all(target_os = "linux", feature = "io_uring"))]
+use crate::UringLog;
+use crate::{
+    Collation, Compression, Crdt, Filter, IndexKind, IoBackend, Partitioning, Patch, Stamp,
+    StreamManifest, Structure, TDBCell, TextIndexDefinition, TuringDbError, TuringResult, Value,
+    ViewDefinition, WriteACKs, WriteOp, FORMAT_VERSION,
+};
+use async_fs::OpenOptions;
+use async_io::Timer;
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeSet, HashSet},
+    io::ErrorKind,
+    time::{Duration, Instant},
+};
+use tai64::TAI64N;
+
+const OPS_LOG_NAME: &str = "ops.log";
+/// A frame is made up of a `u32` payload length, a `u64` SeaHash checksum of the payload then the payload
+const FRAME_HEADER_LEN: usize = 12;
+/// The log starts with this magic and the `u32` format version it was written with.
+/// Logs written before format versioning have neither and are format `1`
+const LOG_MAGIC: &[u8; 4] = b"TDBL";
+const LOG_HEADER_LEN: usize = 8;
+
+/// A mutation on the repo that is appended to the ops log before it is applied
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LogOp {
+    DbCreate {
+        db: Utf8PathBuf,
+        compression: Compression,
+    },
+    DbDrop {
+        db: Utf8PathBuf,
+    },
+    DbSetCompression {
+        db: Utf8PathBuf,
+        compression: Compression,
+    },
+    DocumentCreate {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        expires_at: Option<TAI64N>,
+    },
+    DocumentDrop {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    DocumentExpire {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        expires_at: Option<TAI64N>,
+    },
+    FieldInsert {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldModify {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldRemove {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    FieldInsertStream {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        manifest: StreamManifest,
+    },
+    DbCreatePartitioned {
+        db: Utf8PathBuf,
+        compression: Compression,
+        partitioning: Partitioning,
+    },
+    PartitionDrop {
+        db: Utf8PathBuf,
+        partition: u16,
+    },
+    DocumentPatch {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        patch: Patch,
+    },
+    DocumentUpsert {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    },
+    WriteBatch {
+        db: Utf8PathBuf,
+        ops: Vec<WriteOp>,
+    },
+    DocumentUpdateIf {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        expected_revision: u64,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    },
+    DocumentPatchIf {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        condition: Filter,
+        patch: Patch,
+    },
+    DocumentIncrement {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        by: Value,
+    },
+    DbSetStructure {
+        db: Utf8PathBuf,
+        structure: Structure,
+    },
+    ViewCreate {
+        name: Utf8PathBuf,
+        definition: ViewDefinition,
+    },
+    ViewDrop {
+        db: Utf8PathBuf,
+        name: Utf8PathBuf,
+    },
+    DbSetTrash {
+        db: Utf8PathBuf,
+        retention: Option<Duration>,
+    },
+    DocumentRestore {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    TrashPurge {
+        db: Utf8PathBuf,
+        before: TAI64N,
+    },
+    DbSetCollation {
+        db: Utf8PathBuf,
+        collation: Collation,
+    },
+    IndexCreate {
+        db: Utf8PathBuf,
+        key: Vec<u8>,
+        kind: IndexKind,
+    },
+    IndexDrop {
+        db: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    DbSetUniqueKey {
+        db: Utf8PathBuf,
+        key: Option<Vec<u8>>,
+    },
+    CompoundIndexCreate {
+        db: Utf8PathBuf,
+        keys: Vec<Vec<u8>>,
+        kind: IndexKind,
+    },
+    CompoundIndexDrop {
+        db: Utf8PathBuf,
+        keys: Vec<Vec<u8>>,
+    },
+    DbSetTextIndex {
+        db: Utf8PathBuf,
+        definition: Option<TextIndexDefinition>,
+    },
+    DbSetTtlIndex {
+        db: Utf8PathBuf,
+        index: Option<(Vec<u8>, Duration)>,
+    },
+    PartialIndexCreate {
+        db: Utf8PathBuf,
+        keys: Vec<Vec<u8>>,
+        kind: IndexKind,
+        predicate: Filter,
+    },
+    DbSetPrefixIndex {
+        db: Utf8PathBuf,
+        keys: BTreeSet<Vec<u8>>,
+    },
+    /// The writes of a transaction over several databases, applied once a `TransactionCommit`
+    /// names the record. Recovery leaves out a prepare that was never committed
+    TransactionPrepare {
+        batches: Vec<(Utf8PathBuf, Vec<WriteOp>)>,
+    },
+    /// Commit the `TransactionPrepare` logged at `prepared`
+    TransactionCommit {
+        dbs: Vec<Utf8PathBuf>,
+        prepared: u64,
+    },
+    /// A write to a field stamped by a writer of a multi-writer cluster, `None` removes the field
+    FieldMerge {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: Option<TDBCell>,
+        stamp: Stamp,
+    },
+    /// Changes merged into a CRDT field, made on this node or on a writer of a multi-writer cluster
+    CrdtMerge {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        delta: Crdt,
+    },
+}
+
+impl LogOp {
+    /// The database the operation is performed on
+    pub fn db(&self) -> &Utf8Path {
+        match self {
+            LogOp::DbCreate { db, .. }
+            | LogOp::DbDrop { db }
+            | LogOp::DbSetCompression { db, .. }
+            | LogOp::DocumentCreate { db, .. }
+            | LogOp::DocumentDrop { db, .. }
+            | LogOp::DocumentExpire { db, .. }
+            | LogOp::FieldInsert { db, .. }
+            | LogOp::FieldModify { db, .. }
+            | LogOp::FieldRemove { db, .. }
+            | LogOp::FieldInsertStream { db, .. }
+            | LogOp::DbCreatePartitioned { db, .. }
+            | LogOp::PartitionDrop { db, .. }
+            | LogOp::DocumentPatch { db, .. }
+            | LogOp::DocumentUpsert { db, .. }
+            | LogOp::WriteBatch { db, .. }
+            | LogOp::DocumentUpdateIf { db, .. }
+            | LogOp::DocumentPatchIf { db, .. }
+            | LogOp::DocumentIncrement { db, .. }
+            | LogOp::DbSetStructure { db, .. }
+            | LogOp::ViewDrop { db, .. }
+            | LogOp::DbSetTrash { db, .. }
+            | LogOp::DocumentRestore { db, .. }
+            | LogOp::TrashPurge { db, .. }
+            | LogOp::DbSetCollation { db, .. }
+            | LogOp::IndexCreate { db, .. }
+            | LogOp::IndexDrop { db, .. }
+            | LogOp::DbSetUniqueKey { db, .. }
+            | LogOp::CompoundIndexCreate { db, .. }
+            | LogOp::CompoundIndexDrop { db, .. }
+            | LogOp::DbSetTextIndex { db, .. }
+            | LogOp::DbSetTtlIndex { db, .. }
+            | LogOp::PartialIndexCreate { db, .. }
+            | LogOp::DbSetPrefixIndex { db, .. }
+            | LogOp::FieldMerge { db, .. }
+            | LogOp::CrdtMerge { db, .. } => db.as_path(),
+            // A view is created over the database it is defined on
+            LogOp::ViewCreate { definition, .. } => definition.get_db(),
+            // A transaction over several databases is reported on the first one it writes to
+            LogOp::TransactionPrepare { batches } => match batches.first() {
+                Some((db, _)) => db.as_path(),
+                None => Utf8Path::new(""),
+            },
+            LogOp::TransactionCommit { dbs, .. } => match dbs.first() {
+                Some(db) => db.as_path(),
+                None => Utf8Path::new(""),
+            },
+        }
+    }
+    /// The documents of the database the operation may write to,
+    /// `None` when it may have changed any of them
+    pub(crate) fn documents(&self) -> Option<Vec<&Utf8Path>> {
+        match self {
+            LogOp::DocumentCreate { document, .. }
+            | LogOp::DocumentDrop { document, .. }
+            | LogOp::FieldInsert { document, .. }
+            | LogOp::FieldModify { document, .. }
+            | LogOp::FieldRemove { document, .. }
+            | LogOp::FieldInsertStream { document, .. }
+            | LogOp::DocumentPatch { document, .. }
+            | LogOp::DocumentUpsert { document, .. }
+            | LogOp::DocumentUpdateIf { document, .. }
+            | LogOp::DocumentPatchIf { document, .. }
+            | LogOp::DocumentIncrement { document, .. }
+            | LogOp::DocumentRestore { document, .. }
+            | LogOp::FieldMerge { document, .. }
+            | LogOp::CrdtMerge { document, .. } => Some(vec![document.as_path()]),
+            LogOp::WriteBatch { ops, .. } => Some(ops.iter().map(WriteOp::document).collect()),
+            LogOp::DbDrop { .. } | LogOp::PartitionDrop { .. } => None,
+            _ => Some(Vec::new()),
+        }
+    }
+}
+
+/// A single entry in the ops log
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct LogRecord {
+///     lsn: u64,
+///     timestamp: TAI64N,
+///     op: LogOp,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogRecord {
+    lsn: u64,
+    timestamp: TAI64N,
+    op: LogOp,
+}
+
+impl LogRecord {
+    pub(crate) fn new(lsn: u64, timestamp: TAI64N, op: LogOp) -> Self {
+        Self { lsn, timestamp, op }
+    }
+    /// The log sequence number of the record
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+    /// The time the operation was logged
+    pub fn timestamp(&self) -> TAI64N {
+        self.timestamp
+    }
+    /// The operation that was logged
+    pub fn op(&self) -> &LogOp {
+        &self.op
+    }
+    /// A record of `op` at the position and time of this record
+    pub(crate) fn with_op(&self, op: LogOp) -> LogRecord {
+        LogRecord {
+            lsn: self.lsn,
+            timestamp: self.timestamp,
+            op,
+        }
+    }
+}
+
+/// The append-only log of all mutations performed on a repo
+#[derive(Debug)]
+pub(crate) struct OpsLog {
+    path: Utf8PathBuf,
+    next_lsn: Mutex<u64>,
+    // An ephemeral log only numbers records and never writes them
+    persistent: bool,
+    // Held by the write that syncs the log on behalf of a group of writes
+    group_sync: Mutex<GroupSync>,
+    // Set when appends go through io_uring
+    #[cfg(all(target_os = "linux", feature = "io_uring"))]
+    uring: Option<UringLog>,
+}
+
+#[derive(Debug)]
+struct GroupSync {
+    // Every record up to and including this one is on disk
+    synced_lsn: Option<u64>,
+    last_sync: Instant,
+}
+
+impl OpsLog {
+    /// Point to the ops log inside the repo directory
+    pub(crate) fn new(repo_dir: &Utf8Path) -> Self {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(OPS_LOG_NAME);
+
+        Self {
+            path,
+            next_lsn: Mutex::new(0),
+            persistent: true,
+            group_sync: Mutex::new(GroupSync {
+                synced_lsn: None,
+                last_sync: Instant::now(),
+            }),
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            uring: None,
+        }
+    }
+    /// A log for an ephemeral repo that numbers records without writing them anywhere
+    pub(crate) fn ephemeral() -> Self {
+        Self {
+            path: Utf8PathBuf::new(),
+            next_lsn: Mutex::new(0),
+            persistent: false,
+            group_sync: Mutex::new(GroupSync {
+                synced_lsn: None,
+                last_sync: Instant::now(),
+            }),
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            uring: None,
+        }
+    }
+    /// Choose how appends reach the disk
+    pub(crate) fn set_io_backend(&mut self, io_backend: IoBackend) {
+        match io_backend {
+            IoBackend::ThreadPool => {
+                #[cfg(all(target_os = "linux", feature = "io_uring"))]
+                {
+                    self.uring = None;
+                }
+            }
+            #[cfg(all(target_os = "linux", feature = "io_uring"))]
+            IoBackend::IoUring => {
+                self.uring = Some(UringLog::new());
+            }
+        }
+    }
+    /// Append an operation to the log, returning its record once it is as durable as `write_acks` requires
+    pub(crate) async fn append(&self, op: LogOp, write_acks: WriteACKs) -> TuringResult<LogRecord> {
+        let record = self.write(op, write_acks).await?;
+
+        if let WriteACKs::GroupCommit { interval } = write_acks {
+            if self.persistent {
+                self.group_sync(record.lsn, interval).await?;
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// Append a record read from the ops log of another repo as it is, keeping its lsn and time
+    /// so this log numbers its records as the other one does. Returns once the record is as durable
+    /// as `write_acks` requires
+    pub(crate) async fn append_record(
+        &self,
+        record: &LogRecord,
+        write_acks: WriteACKs,
+    ) -> TuringResult<()> {
+        {
+            let mut next_lsn = self.next_lsn.lock().await;
+            if record.lsn < *next_lsn {
+                return Err(TuringDbError::Bug(
+                    "A record appended before the end of the ops log".into(),
+                ));
+            }
+
+            self.write_frame(record, write_acks).await?;
+            *next_lsn = record.lsn + 1;
+        }
+
+        if let WriteACKs::GroupCommit { interval } = write_acks {
+            if self.persistent {
+                self.group_sync(record.lsn, interval).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn write(&self, op: LogOp, write_acks: WriteACKs) -> TuringResult<LogRecord> {
+        let mut next_lsn = self.next_lsn.lock().await;
+
+        let record = LogRecord {
+            lsn: *next_lsn,
+            timestamp: TAI64N::now(),
+            op,
+        };
+        self.write_frame(&record, write_acks).await?;
+        *next_lsn += 1;
+
+        Ok(record)
+    }
+    // Only called while holding `next_lsn`
+    async fn write_frame(&self, record: &LogRecord, write_acks: WriteACKs) -> TuringResult<()> {
+        if !self.persistent {
+            return Ok(());
+        }
+
+        let mut frame = OpsLog::encode(record)?;
+
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            if let Some(uring) = &self.uring {
+                uring
+                    .append(
+                        &self.path,
+                        &OpsLog::header(),
+                        &frame,
+                        write_acks == WriteACKs::Synced,
+                    )
+                    .await?;
+
+                return Ok(());
+            }
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        if file.metadata().await?.len() == 0 {
+            frame.splice(0..0, OpsLog::header());
+        }
+        file.write_all(&frame).await?;
+        file.flush().await?;
+        if write_acks == WriteACKs::Synced {
+            file.sync_data().await?;
+        }
+
+        Ok(())
+    }
+    /// Wait until a sync covers `lsn`. The first write waiting for a sync performs it on behalf
+    /// of every write appended before it, at most once every `interval`
+    async fn group_sync(&self, lsn: u64, interval: Duration) -> TuringResult<()> {
+        let mut group_sync = self.group_sync.lock().await;
+
+        if let Some(synced_lsn) = group_sync.synced_lsn {
+            if synced_lsn >= lsn {
+                return Ok(());
+            }
+        }
+
+        Timer::at(group_sync.last_sync + interval).await;
+
+        // Everything appended until now is covered by the sync
+        let upto = self.last_lsn().await;
+        self.sync().await?;
+
+        group_sync.synced_lsn = upto;
+        group_sync.last_sync = Instant::now();
+
+        Ok(())
+    }
+    /// Sync every record appended so far to disk whatever the policy they were written with
+    pub(crate) async fn sync(&self) -> TuringResult<()> {
+        if !self.persistent {
+            return Ok(());
+        }
+
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            if let Some(uring) = &self.uring {
+                if uring.sync().await? {
+                    return Ok(());
+                }
+            }
+        }
+
+        match OpenOptions::new().append(true).open(&self.path).await {
+            Ok(file) => Ok(file.sync_data().await?),
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                Ok(())
+            }
+        }
+    }
+    /// The log sequence number of the last appended record
+    pub(crate) async fn last_lsn(&self) -> Option<u64> {
+        match *self.next_lsn.lock().await {
+            0 => None,
+            next_lsn => Some(next_lsn - 1),
+        }
+    }
+    /// Read every intact record in the log, truncating a torn or corrupted tail left behind by a crash
+    pub(crate) async fn replay(&self) -> TuringResult<Vec<LogRecord>> {
+        let mut next_lsn = self.next_lsn.lock().await;
+
+        if !self.persistent {
+            return Ok(Vec::new());
+        }
+
+        let log_bytes = match async_fs::read(&self.path).await {
+            Ok(log_bytes) => log_bytes,
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                } else {
+                    return Err(error.into());
+                }
+            }
+        };
+
+        let header_len = OpsLog::format_header(&log_bytes)?;
+        let (records, frames_len) = OpsLog::decode(&log_bytes[header_len..]);
+        let valid_len = header_len + frames_len;
+
+        if valid_len < log_bytes.len() {
+            self.close_uring().await;
+
+            let file = OpenOptions::new().write(true).open(&self.path).await?;
+            file.set_len(valid_len as u64).await?;
+            file.sync_all().await?;
+        }
+
+        *next_lsn = match records.last() {
+            Some(record) => record.lsn + 1,
+            None => 0,
+        };
+
+        Ok(OpsLog::committed(records))
+    }
+    /// Leave out every `TransactionPrepare` no `TransactionCommit` names, a crash between
+    /// the two never applies part of a transaction over several databases
+    fn committed(records: Vec<LogRecord>) -> Vec<LogRecord> {
+        let committed = records
+            .iter()
+            .filter_map(|record| match &record.op {
+                LogOp::TransactionCommit { prepared, .. } => Some(*prepared),
+                _ => None,
+            })
+            .collect::<HashSet<u64>>();
+
+        records
+            .into_iter()
+            .filter(|record| match &record.op {
+                LogOp::TransactionPrepare { .. } => committed.contains(&record.lsn),
+                _ => true,
+            })
+            .collect()
+    }
+
+    /// Make sure new records are numbered after `checkpoint_lsn` even when compaction has
+    /// removed every record up to it from the log
+    pub(crate) async fn resume_after(&self, checkpoint_lsn: Option<u64>) {
+        let mut next_lsn = self.next_lsn.lock().await;
+
+        if let Some(checkpoint_lsn) = checkpoint_lsn {
+            if *next_lsn <= checkpoint_lsn {
+                *next_lsn = checkpoint_lsn + 1;
+            }
+        }
+    }
+    /// Rewrite the log keeping only the records that recovery could still need to replay,
+    /// returning the number of records removed
+    pub(crate) async fn compact(&self, checkpoint_lsn: Option<u64>) -> TuringResult<usize> {
+        // Holding the lock keeps appends waiting until the rewritten log is in place
+        let _next_lsn = self.next_lsn.lock().await;
+
+        if !self.persistent {
+            return Ok(0);
+        }
+
+        let log_bytes = match async_fs::read(&self.path).await {
+            Ok(log_bytes) => log_bytes,
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    return Ok(0);
+                } else {
+                    return Err(error.into());
+                }
+            }
+        };
+
+        let header_len = OpsLog::format_header(&log_bytes)?;
+        let (records, _) = OpsLog::decode(&log_bytes[header_len..]);
+        let logged = records.len();
+        let live = OpsLog::live_records(records, checkpoint_lsn);
+
+        if live.len() == logged {
+            return Ok(0);
+        }
+
+        let mut frames = Vec::new();
+        for record in live.iter() {
+            frames.extend_from_slice(&OpsLog::encode(record)?);
+        }
+
+        self.rewrite(&frames).await?;
+
+        Ok(logged - live.len())
+    }
+    /// The format version the log was written with, `None` if there is no log yet
+    pub(crate) async fn format_version(&self) -> TuringResult<Option<u32>> {
+        if !self.persistent {
+            return Ok(None);
+        }
+
+        match async_fs::read(&self.path).await {
+            Ok(log_bytes) => match OpsLog::header_version(&log_bytes) {
+                Some(format_version) => Ok(Some(format_version)),
+                None if log_bytes.is_empty() => Ok(None),
+                None => Ok(Some(1)),
+            },
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    Ok(None)
+                } else {
+                    Err(error.into())
+                }
+            }
+        }
+    }
+    /// Rewrite a log from before format versioning with the current format header
+    pub(crate) async fn add_format_header(&self) -> TuringResult<()> {
+        let _next_lsn = self.next_lsn.lock().await;
+
+        if self.format_version().await? != Some(1) {
+            return Ok(());
+        }
+
+        let log_bytes = async_fs::read(&self.path).await?;
+
+        self.rewrite(&log_bytes).await
+    }
+    /// Forget the log held open by io_uring once it has been truncated or replaced
+    async fn close_uring(&self) {
+        #[cfg(all(target_os = "linux", feature = "io_uring"))]
+        {
+            if let Some(uring) = &self.uring {
+                uring.close().await;
+            }
+        }
+    }
+    /// Atomically replace the log with the current format header followed by `frames`
+    async fn rewrite(&self, frames: &[u8]) -> TuringResult<()> {
+        let temp_path = Utf8PathBuf::from(format!("{}.tmp", self.path));
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&temp_path)
+            .await?;
+        file.write_all(&OpsLog::header()).await?;
+        file.write_all(frames).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        async_fs::rename(&temp_path, &self.path).await?;
+        self.close_uring().await;
+
+        #[cfg(unix)]
+        {
+            if let Some(parent) = self.path.parent() {
+                async_fs::File::open(parent).await?.sync_all().await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn header() -> Vec<u8> {
+        let mut header = Vec::with_capacity(LOG_HEADER_LEN);
+        header.extend_from_slice(LOG_MAGIC);
+        header.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+
+        header
+    }
+    /// The length of the format header at the start of the log, failing if the log
+    /// was written by a newer format than this build supports
+    fn format_header(log_bytes: &[u8]) -> TuringResult<usize> {
+        match OpsLog::header_version(log_bytes) {
+            None => Ok(0),
+            Some(format_version) if format_version > FORMAT_VERSION => {
+                Err(TuringDbError::UnsupportedFormat {
+                    found: format_version,
+                    supported: FORMAT_VERSION,
+                })
+            }
+            Some(_) => Ok(LOG_HEADER_LEN),
+        }
+    }
+
+    fn header_version(log_bytes: &[u8]) -> Option<u32> {
+        if log_bytes.len() < LOG_HEADER_LEN || !log_bytes.starts_with(LOG_MAGIC) {
+            return None;
+        }
+
+        let mut version_bytes = [0_u8; 4];
+        version_bytes.copy_from_slice(&log_bytes[4..LOG_HEADER_LEN]);
+
+        Some(u32::from_le_bytes(version_bytes))
+    }
+    /// Records covered by the checkpoint are already persisted and field operations on
+    /// a document or database that is dropped later in the log would be discarded on replay anyway.
+    /// A document restored from the trash later in the log keeps every operation on it
+    fn live_records(records: Vec<LogRecord>, checkpoint_lsn: Option<u64>) -> Vec<LogRecord> {
+        let mut dropped_dbs: HashSet<Utf8PathBuf> = HashSet::new();
+        let mut dropped_documents: HashSet<(Utf8PathBuf, Utf8PathBuf)> = HashSet::new();
+        // A document in the trash holds the operations made on it before it was dropped
+        let restored_documents = records
+            .iter()
+            .filter_map(|record| match &record.op {
+                LogOp::DocumentRestore { db, document } => Some((db.clone(), document.clone())),
+                _ => None,
+            })
+            .collect::<HashSet<(Utf8PathBuf, Utf8PathBuf)>>();
+
+        let mut live = records
+            .into_iter()
+            .rev()
+            .filter(|record| match checkpoint_lsn {
+                Some(checkpoint_lsn) => record.lsn > checkpoint_lsn,
+                None => true,
+            })
+            .filter(|record| match &record.op {
+                LogOp::DbDrop { db } => {
+                    dropped_dbs.insert(db.clone());
+                    true
+                }
+                LogOp::DocumentDrop { db, document } => {
+                    let dropped = (db.clone(), document.clone());
+                    if !restored_documents.contains(&dropped) {
+                        dropped_documents.insert(dropped);
+                    }
+                    true
+                }
+                LogOp::WriteBatch { db, ops } => {
+                    for op in ops {
+                        if let WriteOp::DocumentDrop { document } = op {
+                            let dropped = (db.clone(), document.clone());
+                            if !restored_documents.contains(&dropped) {
+                                dropped_documents.insert(dropped);
+                            }
+                        }
+                    }
+                    true
+                }
+                LogOp::DocumentExpire { db, document, .. }
+                | LogOp::FieldInsert { db, document, .. }
+                | LogOp::FieldModify { db, document, .. }
+                | LogOp::FieldRemove { db, document, .. }
+                | LogOp::FieldInsertStream { db, document, .. }
+                | LogOp::DocumentPatch { db, document, .. }
+                | LogOp::DocumentUpsert { db, document, .. }
+                | LogOp::DocumentUpdateIf { db, document, .. }
+                | LogOp::DocumentPatchIf { db, document, .. }
+                | LogOp::DocumentIncrement { db, document, .. }
+                | LogOp::FieldMerge { db, document, .. }
+                | LogOp::CrdtMerge { db, document, .. } => {
+                    !dropped_dbs.contains(db)
+                        && !dropped_documents.contains(&(db.clone(), document.clone()))
+                }
+                _ => true,
+            })
+            .collect::<Vec<LogRecord>>();
+
+        live.reverse();
+
+        live
+    }
+    /// Read the records logged after `after` up to and including `upto` without modifying the log
+    pub(crate) async fn read_range(
+        &self,
+        after: Option<u64>,
+        upto: Option<u64>,
+    ) -> TuringResult<Vec<LogRecord>> {
+        let upto = match upto {
+            None => return Ok(Vec::new()),
+            Some(upto) => upto,
+        };
+
+        // Holding the lock keeps a concurrent append from being read half written
+        let _next_lsn = self.next_lsn.lock().await;
+
+        if !self.persistent {
+            return Ok(Vec::new());
+        }
+
+        let log_bytes = match async_fs::read(&self.path).await {
+            Ok(log_bytes) => log_bytes,
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                } else {
+                    return Err(error.into());
+                }
+            }
+        };
+
+        let header_len = OpsLog::format_header(&log_bytes)?;
+        let (records, _) = OpsLog::decode(&log_bytes[header_len..]);
+
+        Ok(records
+            .into_iter()
+            .filter(|record| match after {
+                Some(after) => record.lsn > after,
+                None => true,
+            })
+            .filter(|record| record.lsn <= upto)
+            .collect())
+    }
+
+    fn encode(record: &LogRecord) -> TuringResult<Vec<u8>> {
+        let payload = bincode::serialize::<LogRecord>(record)?;
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&seahash::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        Ok(frame)
+    }
+
+    /// Decode frames until the first incomplete or corrupted one, returning the records
+    /// and the number of bytes that were valid
+    fn decode(log_bytes: &[u8]) -> (Vec<LogRecord>, usize) {
+        let mut records = Vec::new();
+        let mut offset = 0_usize;
+
+        while log_bytes.len() - offset >= FRAME_HEADER_LEN {
+            let mut len_bytes = [0_u8; 4];
+            len_bytes.copy_from_slice(&log_bytes[offset..offset + 4]);
+            let mut checksum_bytes = [0_u8; 8];
+            checksum_bytes.copy_from_slice(&log_bytes[offset + 4..offset + FRAME_HEADER_LEN]);
+
+            let payload_start = offset + FRAME_HEADER_LEN;
+            let payload_end = payload_start + u32::from_le_bytes(len_bytes) as usize;
+
+            if payload_end > log_bytes.len() {
+                break;
+            }
+
+            let payload = &log_bytes[payload_start..payload_end];
+
+            if seahash::hash(payload) != u64::from_le_bytes(checksum_bytes) {
+                break;
+            }
+
+            match bincode::deserialize::<LogRecord>(payload) {
+                Ok(record) => records.push(record),
+                Err(_) => break,
+            }
+
+            offset = payload_end;
+        }
+
+        (records, offset)
+    }
+}
@@ -0,0 +1,478 @@
+use crate::{
+    BloomFilter, ColdDocument, LazyDocument, MetaEncoding, MetaFile, Quarantine, TuringDbError,
+    TuringResult, ARCHIVING_EXTENSION, COLD_DIR, COLD_EXTENSION, REHYDRATING_EXTENSION,
+    RESERVED_DIR_PREFIX,
+};
+use async_lock::RwLock;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::ErrorKind,
+    sync::Arc,
+};
+
+const PARTITIONS_META_NAME: &str = "PARTITIONS.meta";
+/// Partition directories are reserved so they are never loaded as documents
+const PARTITION_DIR_PREFIX: &str = ".partition-";
+
+/// How the documents of a database are laid out inside its directory
+/// ```
+/// #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub enum Partitioning {
+///     #[default]
+///     Flat,
+///     Hash { partitions: u16 },
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Partitioning {
+    /// Every document is a directory directly inside the database directory
+    #[default]
+    Flat,
+    /// Documents are spread over `partitions` directories by the SeaHash of their name.
+    /// A partition is only listed the first time one of its documents is accessed
+    Hash { partitions: u16 },
+}
+
+impl Partitioning {
+    /// The number of partitions, `1` for a flat database
+    pub fn partitions(&self) -> u16 {
+        match self {
+            Partitioning::Flat => 1,
+            Partitioning::Hash { partitions } => (*partitions).max(1),
+        }
+    }
+    /// The partition a document belongs to
+    pub fn partition_of(&self, document_name: &Utf8Path) -> u16 {
+        match self {
+            Partitioning::Flat => 0,
+            Partitioning::Hash { .. } => {
+                (seahash::hash(document_name.as_str().as_bytes()) % self.partitions() as u64) as u16
+            }
+        }
+    }
+    /// The directory holding the documents of a partition
+    pub(crate) fn partition_dir(&self, db_dir: &Utf8Path, partition: u16) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+
+        if let Partitioning::Hash { .. } = self {
+            path.push(format!("{}{:04x}", PARTITION_DIR_PREFIX, partition));
+        }
+
+        path
+    }
+    /// The directory of a document
+    pub(crate) fn document_path(&self, db_dir: &Utf8Path, document_name: &Utf8Path) -> Utf8PathBuf {
+        let mut path = self.partition_dir(db_dir, self.partition_of(document_name));
+        path.push(document_name);
+
+        path
+    }
+    /// Read the partitioning of a database, a database without a partitions file is flat
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Partitioning> {
+        match MetaFile::read::<Partitioning>(&Partitioning::path(db_dir), quarantine).await? {
+            Some(partitioning) => Ok(partitioning),
+            None => Ok(Partitioning::Flat),
+        }
+    }
+    /// Record the partitioning of a new database, nothing is written for a flat database
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path) -> TuringResult<()> {
+        if let Partitioning::Flat = self {
+            return Ok(());
+        }
+
+        MetaFile::write(
+            &Partitioning::path(db_dir),
+            &MetaEncoding::Bincode.encode(self)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(PARTITIONS_META_NAME);
+
+        path
+    }
+}
+
+#[derive(Debug)]
+enum PartitionState {
+    Loaded(HashMap<Utf8PathBuf, Arc<LazyDocument>>),
+    // The directory of the partition has not been listed yet
+    Unloaded {
+        dir: Utf8PathBuf,
+        quarantine: Arc<Quarantine>,
+        // The documents the partition held when it was last committed, if known
+        filter: Option<BloomFilter>,
+    },
+}
+
+/// The documents of a database, grouped by partition.
+/// Partitions of a database loaded from disk are only listed the first time they are needed
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct DocumentIndex {
+///     partitioning: Partitioning,
+///     partitions: Vec<RwLock<PartitionState>>,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct DocumentIndex {
+    partitioning: Partitioning,
+    partitions: Vec<RwLock<PartitionState>>,
+}
+
+impl Default for DocumentIndex {
+    fn default() -> Self {
+        DocumentIndex::new(Partitioning::Flat)
+    }
+}
+
+impl DocumentIndex {
+    /// The index of a new database without any documents
+    pub(crate) fn new(partitioning: Partitioning) -> Self {
+        Self {
+            partitioning,
+            partitions: (0..partitioning.partitions())
+                .map(|_| RwLock::new(PartitionState::Loaded(HashMap::new())))
+                .collect(),
+        }
+    }
+    /// The index of a database on disk whose partitions have not been listed yet
+    pub(crate) fn unloaded(
+        partitioning: Partitioning,
+        db_dir: &Utf8Path,
+        quarantine: &Arc<Quarantine>,
+    ) -> Self {
+        Self {
+            partitioning,
+            partitions: (0..partitioning.partitions())
+                .map(|partition| {
+                    RwLock::new(PartitionState::Unloaded {
+                        dir: partitioning.partition_dir(db_dir, partition),
+                        quarantine: Arc::clone(quarantine),
+                        filter: None,
+                    })
+                })
+                .collect(),
+        }
+    }
+    /// Give each partition not listed yet the filter of the documents it held when last committed,
+    /// filters persisted for another number of partitions are ignored
+    pub(crate) fn set_filters(mut self, filters: Vec<Option<BloomFilter>>) -> Self {
+        if filters.len() != self.partitions.len() {
+            return self;
+        }
+
+        for (partition, loaded) in self.partitions.iter_mut().zip(filters) {
+            if let PartitionState::Unloaded { filter, .. } = partition.get_mut() {
+                *filter = loaded;
+            }
+        }
+
+        self
+    }
+
+    pub(crate) fn partitioning(&self) -> Partitioning {
+        self.partitioning
+    }
+    /// Get a document, listing its partition first if needed. A partition is not listed
+    /// when its filter shows the document is not there
+    pub(crate) async fn get(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<Option<Arc<LazyDocument>>> {
+        if !self.may_contain(document_name).await? {
+            return Ok(None);
+        }
+
+        let partition = self.partition(document_name)?;
+        DocumentIndex::load(partition).await?;
+
+        match &*partition.read().await {
+            PartitionState::Loaded(documents) => Ok(documents.get(document_name).cloned()),
+            PartitionState::Unloaded { .. } => Err(TuringDbError::Bug(
+                "Partition unloaded after it was listed".into(),
+            )),
+        }
+    }
+
+    pub(crate) async fn contains(&self, document_name: &Utf8Path) -> TuringResult<bool> {
+        Ok(self.get(document_name).await?.is_some())
+    }
+    /// Whether a document may be in the database without touching the disk, `false` only when
+    /// it is certainly not there
+    pub(crate) async fn may_contain(&self, document_name: &Utf8Path) -> TuringResult<bool> {
+        match &*self.partition(document_name)?.read().await {
+            PartitionState::Loaded(documents) => Ok(documents.contains_key(document_name)),
+            PartitionState::Unloaded { filter, .. } => Ok(filter
+                .as_ref()
+                .is_none_or(|filter| filter.may_contain(document_name))),
+        }
+    }
+    /// The filter of the documents of each partition to persist with a commit. A partition that
+    /// has not been listed has not been written to, so the filter it was loaded with still holds
+    pub(crate) async fn filters(&self) -> Vec<Option<BloomFilter>> {
+        let mut filters = Vec::with_capacity(self.partitions.len());
+
+        for partition in self.partitions.iter() {
+            filters.push(match &*partition.read().await {
+                PartitionState::Loaded(documents) => Some(BloomFilter::build(documents.keys())),
+                PartitionState::Unloaded { filter, .. } => filter.clone(),
+            });
+        }
+
+        filters
+    }
+    /// Add a document to its partition, returning `false` if it was already there
+    pub(crate) async fn insert(
+        &self,
+        document_name: &Utf8Path,
+        document: LazyDocument,
+    ) -> TuringResult<bool> {
+        let partition = self.partition(document_name)?;
+        DocumentIndex::load(partition).await?;
+
+        match &mut *partition.write().await {
+            PartitionState::Loaded(documents) => {
+                if documents.contains_key(document_name) {
+                    return Ok(false);
+                }
+
+                documents.insert(document_name.to_path_buf(), Arc::new(document));
+
+                Ok(true)
+            }
+            PartitionState::Unloaded { .. } => Err(TuringDbError::Bug(
+                "Partition unloaded after it was listed".into(),
+            )),
+        }
+    }
+
+    pub(crate) async fn remove(
+        &self,
+        document_name: &Utf8Path,
+    ) -> TuringResult<Option<Arc<LazyDocument>>> {
+        let partition = self.partition(document_name)?;
+        DocumentIndex::load(partition).await?;
+
+        match &mut *partition.write().await {
+            PartitionState::Loaded(documents) => Ok(documents.remove(document_name)),
+            PartitionState::Unloaded { .. } => Err(TuringDbError::Bug(
+                "Partition unloaded after it was listed".into(),
+            )),
+        }
+    }
+    /// Remove every document of a partition from the index, returning their names
+    pub(crate) async fn clear_partition(&self, partition: u16) -> TuringResult<Vec<Utf8PathBuf>> {
+        let partition = match self.partitions.get(partition as usize) {
+            None => return Err(TuringDbError::InvalidInput),
+            Some(partition) => partition,
+        };
+        DocumentIndex::load(partition).await?;
+
+        match &mut *partition.write().await {
+            PartitionState::Loaded(documents) => Ok(documents
+                .drain()
+                .map(|(document_name, _)| document_name)
+                .collect()),
+            PartitionState::Unloaded { .. } => Err(TuringDbError::Bug(
+                "Partition unloaded after it was listed".into(),
+            )),
+        }
+    }
+    /// Every document in the database, listing the partitions that have not been listed yet
+    pub(crate) async fn documents(&self) -> TuringResult<Vec<(Utf8PathBuf, Arc<LazyDocument>)>> {
+        let mut all_documents = Vec::new();
+
+        for partition in self.partitions.iter() {
+            DocumentIndex::load(partition).await?;
+
+            if let PartitionState::Loaded(documents) = &*partition.read().await {
+                all_documents.extend(documents.iter().map(|(document_name, document)| {
+                    (document_name.clone(), Arc::clone(document))
+                }));
+            }
+        }
+
+        Ok(all_documents)
+    }
+    /// The names of every document in the database
+    pub(crate) async fn names(&self) -> TuringResult<Vec<Utf8PathBuf>> {
+        Ok(self
+            .documents()
+            .await?
+            .into_iter()
+            .map(|(document_name, _)| document_name)
+            .collect())
+    }
+    /// The documents in the partitions that have already been listed
+    pub(crate) async fn loaded(&self) -> Vec<(Utf8PathBuf, Arc<LazyDocument>)> {
+        let mut loaded = Vec::new();
+
+        for partition in self.partitions.iter() {
+            if let PartitionState::Loaded(documents) = &*partition.read().await {
+                loaded.extend(documents.iter().map(|(document_name, document)| {
+                    (document_name.clone(), Arc::clone(document))
+                }));
+            }
+        }
+
+        loaded
+    }
+    /// The number of documents, `None` while some partitions have not been listed
+    pub(crate) async fn loaded_len(&self) -> Option<usize> {
+        let mut len = 0_usize;
+
+        for partition in self.partitions.iter() {
+            match &*partition.read().await {
+                PartitionState::Loaded(documents) => len += documents.len(),
+                PartitionState::Unloaded { .. } => return None,
+            }
+        }
+
+        Some(len)
+    }
+
+    fn partition(&self, document_name: &Utf8Path) -> TuringResult<&RwLock<PartitionState>> {
+        match self
+            .partitions
+            .get(self.partitioning.partition_of(document_name) as usize)
+        {
+            Some(partition) => Ok(partition),
+            None => Err(TuringDbError::Bug(
+                "Document outside every partition".into(),
+            )),
+        }
+    }
+    /// List the directory of a partition the first time it is needed
+    async fn load(partition: &RwLock<PartitionState>) -> TuringResult<()> {
+        if let PartitionState::Loaded(_) = &*partition.read().await {
+            return Ok(());
+        }
+
+        let mut state = partition.write().await;
+
+        let documents = match &*state {
+            // Listed while waiting for the lock
+            PartitionState::Loaded(_) => return Ok(()),
+            PartitionState::Unloaded {
+                dir, quarantine, ..
+            } => DocumentIndex::list(dir, quarantine).await?,
+        };
+
+        *state = PartitionState::Loaded(documents);
+
+        Ok(())
+    }
+    /// Documents are only opened the first time they are accessed
+    async fn list(
+        dir: &Utf8Path,
+        quarantine: &Arc<Quarantine>,
+    ) -> TuringResult<HashMap<Utf8PathBuf, Arc<LazyDocument>>> {
+        let mut documents = HashMap::new();
+
+        let mut entries = match async_fs::read_dir(dir).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                // Partition directories are only created with their first document
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                return Ok(documents);
+            }
+        };
+
+        while let Some(document_entry) = entries.try_next().await? {
+            if !document_entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            let document_name = match document_entry.file_name().to_str() {
+                None => return Err(TuringDbError::PathReadIsNotUtf8Path),
+                Some(document_name) => Utf8PathBuf::from(document_name),
+            };
+            if document_name.as_str().starts_with(RESERVED_DIR_PREFIX) {
+                continue;
+            }
+
+            let mut document_path: Utf8PathBuf = dir.into();
+            document_path.push(&document_name);
+
+            documents.insert(
+                document_name,
+                Arc::new(LazyDocument::new(document_path, Arc::clone(quarantine))),
+            );
+        }
+
+        DocumentIndex::list_cold(dir, quarantine, &mut documents).await?;
+
+        Ok(documents)
+    }
+    /// Add the archived documents of a partition and clear what a crash left behind while
+    /// documents were moving between tiers. An archive whose document is also on disk was
+    /// either written before archiving was interrupted or left after rehydrating, so the document wins
+    async fn list_cold(
+        dir: &Utf8Path,
+        quarantine: &Arc<Quarantine>,
+        documents: &mut HashMap<Utf8PathBuf, Arc<LazyDocument>>,
+    ) -> TuringResult<()> {
+        let mut cold_dir: Utf8PathBuf = dir.into();
+        cold_dir.push(COLD_DIR);
+
+        let mut entries = match async_fs::read_dir(&cold_dir).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                return Ok(());
+            }
+        };
+
+        while let Some(cold_entry) = entries.try_next().await? {
+            let cold_path = match cold_entry.file_name().to_str() {
+                None => return Err(TuringDbError::PathReadIsNotUtf8Path),
+                Some(file_name) => {
+                    let mut cold_path = cold_dir.clone();
+                    cold_path.push(file_name);
+
+                    cold_path
+                }
+            };
+            let document_name = match cold_path.file_stem() {
+                None => continue,
+                Some(document_name) => Utf8PathBuf::from(document_name),
+            };
+            let mut document_path: Utf8PathBuf = dir.into();
+            document_path.push(&document_name);
+
+            match cold_path.extension() {
+                Some(COLD_EXTENSION) => match documents.entry(document_name) {
+                    Entry::Occupied(_) => ColdDocument::remove(&document_path).await?,
+                    Entry::Vacant(entry) => {
+                        entry.insert(Arc::new(LazyDocument::cold(
+                            document_path,
+                            Arc::clone(quarantine),
+                        )));
+                    }
+                },
+                Some(ARCHIVING_EXTENSION) | Some(REHYDRATING_EXTENSION) => {
+                    async_fs::remove_dir_all(&cold_path).await?;
+                }
+                // Previous good copies of archives and temporary files of unfinished writes
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+}
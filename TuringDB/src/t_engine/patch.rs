@@ -0,0 +1,236 @@
+use crate::{DataType, FieldData, TDBCell, TuringDbError, TuringResult, Value};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use tai64::TAI64N;
+
+/// A change to a single field of a document
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum PatchOp {
+///     Set { key: Vec<u8>, value: TDBCell },
+///     Unset { key: Vec<u8> },
+///     Increment { key: Vec<u8>, by: Value },
+///     Push { key: Vec<u8>, value: TDBCell },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PatchOp {
+    /// Insert the field or replace its value
+    Set { key: Vec<u8>, value: TDBCell },
+    /// Remove the field if it exists
+    Unset { key: Vec<u8> },
+    /// Add a number to a numeric field, keeping its `DataType`. A missing field is created holding `by`
+    /// as an `I64`, a `U64` or an `F64`
+    Increment { key: Vec<u8>, by: Value },
+    /// Append a value to an `ARRAY` field, a missing field is created holding only the value
+    Push { key: Vec<u8>, value: TDBCell },
+}
+
+impl PatchOp {
+    pub fn key(&self) -> &[u8] {
+        match self {
+            PatchOp::Set { key, .. }
+            | PatchOp::Unset { key }
+            | PatchOp::Increment { key, .. }
+            | PatchOp::Push { key, .. } => key,
+        }
+    }
+    /// The new contents of the field given its current contents, `None` removes it
+    pub(crate) fn apply(
+        &self,
+        previous: Option<FieldData>,
+        time: TAI64N,
+    ) -> TuringResult<Option<FieldData>> {
+        let (data_type, data) = match self {
+            PatchOp::Unset { .. } => return Ok(None),
+            PatchOp::Set { value, .. } => (value.get_data_type(), value.get_data().to_vec()),
+            PatchOp::Increment { by, .. } => match &previous {
+                None => PatchOp::number(by)?,
+                Some(field_data) => (field_data.data_type(), PatchOp::add(field_data, by)?),
+            },
+            PatchOp::Push { value, .. } => {
+                let mut elements = match &previous {
+                    None => Vec::new(),
+                    Some(field_data) => PatchOp::elements(field_data)?,
+                };
+                elements.push(value.clone());
+
+                (
+                    DataType::ARRAY,
+                    bincode::serialize::<Vec<TDBCell>>(&elements)?,
+                )
+            }
+        };
+
+        match previous {
+            None => Ok(Some(FieldData::new_at(data_type, &data, time))),
+            Some(mut field_data) => {
+                field_data.update_at(data_type, &data, time);
+
+                Ok(Some(field_data))
+            }
+        }
+    }
+    /// The elements of an `ARRAY` field, stored as a list of cells
+    pub fn elements(field_data: &FieldData) -> TuringResult<Vec<TDBCell>> {
+        if field_data.data_type() != DataType::ARRAY {
+            return Err(TuringDbError::FieldTypeMismatch);
+        }
+
+        Ok(bincode::deserialize::<Vec<TDBCell>>(field_data.data())?)
+    }
+
+    fn number(by: &Value) -> TuringResult<(DataType, Vec<u8>)> {
+        match by {
+            Value::Int(int) => match i64::try_from(*int) {
+                Ok(int) => Ok((DataType::I64, int.to_le_bytes().to_vec())),
+                Err(_) => Err(TuringDbError::NumericOverflow),
+            },
+            Value::UInt(uint) => match u64::try_from(*uint) {
+                Ok(uint) => Ok((DataType::U64, uint.to_le_bytes().to_vec())),
+                Err(_) => Err(TuringDbError::NumericOverflow),
+            },
+            Value::Float(float) => Ok((DataType::F64, float.to_le_bytes().to_vec())),
+            _ => Err(TuringDbError::FieldTypeMismatch),
+        }
+    }
+    /// Add `by` to a numeric field, failing if the sum does not fit the `DataType` of the field.
+    /// Only a float field can be incremented by a float
+    fn add(field_data: &FieldData, by: &Value) -> TuringResult<Vec<u8>> {
+        let current = Value::from_field(field_data);
+
+        if let Value::Float(current) = current {
+            let by = match by {
+                Value::Int(int) => *int as f64,
+                Value::UInt(uint) => *uint as f64,
+                Value::Float(float) => *float,
+                _ => return Err(TuringDbError::FieldTypeMismatch),
+            };
+            let sum = current + by;
+
+            return match field_data.data_type() {
+                DataType::F32 => Ok((sum as f32).to_le_bytes().to_vec()),
+                _ => Ok(sum.to_le_bytes().to_vec()),
+            };
+        }
+
+        let by = match by {
+            Value::Int(int) => *int,
+            Value::UInt(uint) => {
+                i128::try_from(*uint).map_err(|_| TuringDbError::NumericOverflow)?
+            }
+            _ => return Err(TuringDbError::FieldTypeMismatch),
+        };
+
+        let sum = match current {
+            Value::UInt(current) if field_data.data_type() == DataType::U128 => {
+                let sum = if by >= 0 {
+                    current.checked_add(by as u128)
+                } else {
+                    current.checked_sub(by.unsigned_abs())
+                };
+
+                return match sum {
+                    Some(sum) => Ok(sum.to_le_bytes().to_vec()),
+                    None => Err(TuringDbError::NumericOverflow),
+                };
+            }
+            Value::UInt(current) => (current as i128).checked_add(by),
+            Value::Int(current) => current.checked_add(by),
+            _ => return Err(TuringDbError::FieldTypeMismatch),
+        };
+        let sum = sum.ok_or(TuringDbError::NumericOverflow)?;
+
+        let bytes = match field_data.data_type() {
+            DataType::U8 => u8::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::U16 => u16::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::U32 => u32::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::U64 => u64::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::I8 => i8::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::I16 => i16::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::I32 => i32::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::I64 => i64::try_from(sum).map(|sum| sum.to_le_bytes().to_vec()),
+            DataType::I128 => Ok(sum.to_le_bytes().to_vec()),
+            _ => return Err(TuringDbError::FieldTypeMismatch),
+        };
+
+        bytes.map_err(|_| TuringDbError::NumericOverflow)
+    }
+}
+
+/// Changes to several fields of a document applied together, in order, as a single write.
+/// Only the changes go through the ops log, not the fields they produce
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct Patch {
+///     ops: Vec<PatchOp>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Patch {
+    ops: Vec<PatchOp>,
+}
+
+impl Patch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(mut self, key: &[u8], value: TDBCell) -> Self {
+        self.ops.push(PatchOp::Set {
+            key: key.into(),
+            value,
+        });
+
+        self
+    }
+
+    pub fn unset(mut self, key: &[u8]) -> Self {
+        self.ops.push(PatchOp::Unset { key: key.into() });
+
+        self
+    }
+
+    pub fn increment(mut self, key: &[u8], by: Value) -> Self {
+        self.ops.push(PatchOp::Increment {
+            key: key.into(),
+            by,
+        });
+
+        self
+    }
+
+    pub fn push(mut self, key: &[u8], value: TDBCell) -> Self {
+        self.ops.push(PatchOp::Push {
+            key: key.into(),
+            value,
+        });
+
+        self
+    }
+
+    pub fn get_ops(&self) -> &[PatchOp] {
+        &self.ops
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+    /// The keys the operations change, in order
+    pub(crate) fn keys(&self) -> Vec<&[u8]> {
+        self.ops.iter().map(|op| op.key()).collect()
+    }
+    /// The number of bytes the patch may add to the document
+    pub(crate) fn len(&self) -> u64 {
+        self.ops
+            .iter()
+            .map(|op| match op {
+                PatchOp::Set { key, value } | PatchOp::Push { key, value } => {
+                    key.len() + value.get_data().len()
+                }
+                PatchOp::Increment { key, .. } => key.len() + 16,
+                PatchOp::Unset { .. } => 0,
+            })
+            .sum::<usize>() as u64
+    }
+}
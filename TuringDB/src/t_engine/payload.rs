@@ -0,0 +1,94 @@
+use crate::{TuringDbError, TuringResult, CBOR_ENCODING, JSON_ENCODING, MSGPACK_ENCODING};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// How the messages of a `TuringServer` connection and the bodies of the `HttpGateway` are encoded.
+/// A client picks the encoding in its hello or in the `Content-Type` and `Accept` headers,
+/// so clients in languages that speak MessagePack or CBOR do not need to go through bincode or JSON
+/// ```
+/// #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+/// pub enum PayloadEncoding {
+///     #[default]
+///     Bincode,
+///     Json,
+///     MsgPack,
+///     Cbor,
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadEncoding {
+    /// Every peer speaks it, the HTTP gateway does not since bodies are not Rust types
+    #[default]
+    Bincode,
+    Json,
+    /// Structs are encoded as maps keyed by field name
+    MsgPack,
+    Cbor,
+}
+
+impl PayloadEncoding {
+    pub fn encode<T: Serialize>(&self, value: &T) -> TuringResult<Vec<u8>> {
+        match self {
+            PayloadEncoding::Bincode => Ok(bincode::serialize(value)?),
+            PayloadEncoding::Json => serde_json::to_vec(value)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+            PayloadEncoding::MsgPack => rmp_serde::to_vec_named(value)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+            PayloadEncoding::Cbor => serde_cbor::to_vec(value)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+        }
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> TuringResult<T> {
+        match self {
+            PayloadEncoding::Bincode => Ok(bincode::deserialize(bytes)?),
+            PayloadEncoding::Json => serde_json::from_slice(bytes)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+            PayloadEncoding::MsgPack => rmp_serde::from_read_ref(bytes)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+            PayloadEncoding::Cbor => serde_cbor::from_slice(bytes)
+                .map_err(|error| TuringDbError::Serialization(error.to_string())),
+        }
+    }
+    /// The feature of the protocol a hello asks for the encoding with, `None` for bincode
+    /// which a connection falls back to when the server does not speak the one asked for
+    pub fn feature(&self) -> Option<&'static str> {
+        match self {
+            PayloadEncoding::Bincode => None,
+            PayloadEncoding::Json => Some(JSON_ENCODING),
+            PayloadEncoding::MsgPack => Some(MSGPACK_ENCODING),
+            PayloadEncoding::Cbor => Some(CBOR_ENCODING),
+        }
+    }
+    /// The media type of an HTTP body in the encoding
+    pub fn media_type(&self) -> &'static str {
+        match self {
+            PayloadEncoding::Bincode => "application/octet-stream",
+            PayloadEncoding::Json => "application/json",
+            PayloadEncoding::MsgPack => "application/msgpack",
+            PayloadEncoding::Cbor => "application/cbor",
+        }
+    }
+    /// The encoding of an HTTP body with the media type, parameters like `charset` are ignored.
+    /// `None` for bincode and for media types of no encoding
+    #[cfg(feature = "http")]
+    pub(crate) fn from_media_type(media_type: &str) -> Option<PayloadEncoding> {
+        let media_type = media_type.split(';').next().unwrap_or_default().trim();
+
+        [
+            PayloadEncoding::Json,
+            PayloadEncoding::MsgPack,
+            PayloadEncoding::Cbor,
+        ]
+        .iter()
+        .copied()
+        .find(|encoding| encoding.media_type().eq_ignore_ascii_case(media_type))
+        .or_else(|| {
+            // Both are in use for MessagePack
+            if media_type.eq_ignore_ascii_case("application/x-msgpack") {
+                Some(PayloadEncoding::MsgPack)
+            } else {
+                None
+            }
+        })
+    }
+}
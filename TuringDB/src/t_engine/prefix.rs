@@ -0,0 +1,213 @@
+use crate::{
+    Document, FieldSource, LazyDocument, MetaEncoding, MetaFile, Quarantine, TuringResult, Value,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+    sync::Arc,
+};
+use tai64::TAI64N;
+
+const PREFIX_META_NAME: &str = "PREFIX.meta";
+
+/// The text a document holds in each field of the index
+type Held = Vec<(Vec<u8>, String)>;
+
+/// The text fields of a database whose values are suggested as they are typed, along with the text
+/// each document holds in them kept in the order of the text so the values starting with a prefix
+/// are found by walking from it. Text is matched ignoring case and suggested as documents hold it
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct PrefixIndex {
+///     keys: BTreeSet<Vec<u8>>,
+///     documents: Option<BTreeMap<Utf8PathBuf, Held>>,
+///     terms: BTreeMap<(Vec<u8>, String, String), usize>,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct PrefixIndex {
+    keys: BTreeSet<Vec<u8>>,
+    // `None` until suggestions are next asked for, or once the index could not be kept up to date
+    documents: Option<BTreeMap<Utf8PathBuf, Held>>,
+    // How many documents hold each text in each field, by the field, the lowercase text and the text
+    terms: BTreeMap<(Vec<u8>, String, String), usize>,
+}
+
+impl PrefixIndex {
+    pub(crate) fn new(keys: BTreeSet<Vec<u8>>) -> Self {
+        Self {
+            keys,
+            documents: None,
+            terms: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn keys(&self) -> &BTreeSet<Vec<u8>> {
+        &self.keys
+    }
+
+    pub(crate) fn is_built(&self) -> bool {
+        self.documents.is_some()
+    }
+    /// Drop the text held, it is read again the next time suggestions are asked for
+    pub(crate) fn reset(&mut self) {
+        self.documents = None;
+        self.terms.clear();
+    }
+    /// Read the fields from every document, which rehydrates documents in the cold tier
+    pub(crate) async fn build(
+        &mut self,
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<()> {
+        let mut built = PrefixIndex::new(self.keys.clone());
+        built.documents = Some(BTreeMap::new());
+
+        for (document_name, document) in documents {
+            built.record(&document_name, Some(&document.open().await?))?;
+        }
+        *self = built;
+
+        Ok(())
+    }
+    /// Bring the index up to date with a document, `None` once it is no longer in the database
+    pub(crate) fn record(
+        &mut self,
+        document_name: &Utf8Path,
+        sled_db: Option<&Document>,
+    ) -> TuringResult<()> {
+        if !self.is_built() {
+            return Ok(());
+        }
+
+        let mut held = Vec::new();
+        if let Some(sled_db) = sled_db {
+            for key in self.keys.iter() {
+                if let Some(field_data) = sled_db.read_field(key)? {
+                    if let Value::Text(text) = Value::from_field(&field_data) {
+                        held.push((key.clone(), text));
+                    }
+                }
+            }
+        }
+
+        self.remove(document_name);
+        self.insert(document_name, held);
+
+        Ok(())
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, held: Held) {
+        if held.is_empty() {
+            return;
+        }
+
+        for (key, text) in held.iter() {
+            *self
+                .terms
+                .entry((key.clone(), text.to_lowercase(), text.clone()))
+                .or_default() += 1;
+        }
+        if let Some(documents) = self.documents.as_mut() {
+            documents.insert(document_name.to_path_buf(), held);
+        }
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        let held = match self.documents.as_mut() {
+            None => return,
+            Some(documents) => match documents.remove(document_name) {
+                None => return,
+                Some(held) => held,
+            },
+        };
+
+        for (key, text) in held {
+            let term = (key, text.to_lowercase(), text);
+            if let Some(count) = self.terms.get_mut(&term) {
+                *count -= 1;
+                if *count == 0 {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+    /// At most `limit` distinct texts of the field `key` starting with `prefix` ignoring case,
+    /// in the order of their lowercase text. Only the texts suggested are visited
+    pub(crate) fn suggest(&self, key: &[u8], prefix: &str, limit: usize) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+
+        self.terms
+            .range((
+                Bound::Included((key.to_vec(), prefix.clone(), String::new())),
+                Bound::Unbounded,
+            ))
+            .take_while(|((held, lowercase, _), _)| {
+                held.as_slice() == key && lowercase.starts_with(prefix.as_str())
+            })
+            .take(limit)
+            .map(|((_, _, text), _)| text.clone())
+            .collect()
+    }
+    /// Load the prefix index of a database last committed at `committed`. The text held is only
+    /// trusted when it was persisted by that same commit, otherwise it is read again when suggestions
+    /// are next asked for
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+        committed: TAI64N,
+    ) -> TuringResult<Option<PrefixIndex>> {
+        let persisted = MetaFile::read::<
+            Option<(
+                BTreeSet<Vec<u8>>,
+                Option<(TAI64N, BTreeMap<Utf8PathBuf, Held>)>,
+            )>,
+        >(&PrefixIndex::path(db_dir), quarantine)
+        .await?
+        .flatten();
+
+        Ok(persisted.map(|(keys, documents)| {
+            let mut prefix = PrefixIndex::new(keys);
+
+            if let Some((stamp, documents)) = documents {
+                if stamp == committed {
+                    prefix.documents = Some(BTreeMap::new());
+                    for (document_name, held) in documents {
+                        prefix.insert(&document_name, held);
+                    }
+                }
+            }
+
+            prefix
+        }))
+    }
+    /// Persist the prefix index along with a commit of its database made at `committed`
+    pub(crate) async fn persist(
+        db_dir: &Utf8Path,
+        prefix: Option<&PrefixIndex>,
+        committed: TAI64N,
+    ) -> TuringResult<()> {
+        let persisted = prefix.map(|prefix| {
+            (
+                prefix.keys.clone(),
+                prefix
+                    .documents
+                    .as_ref()
+                    .map(|documents| (committed, documents.clone())),
+            )
+        });
+
+        MetaFile::write(
+            &PrefixIndex::path(db_dir),
+            &MetaEncoding::Bincode.encode(&persisted)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(PREFIX_META_NAME);
+
+        path
+    }
+}
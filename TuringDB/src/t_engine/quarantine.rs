@@ -0,0 +1,62 @@
+use crate::TuringResult;
+use async_fs::DirBuilder;
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Directories in the repo starting with `.` are reserved for the engine and are never loaded as databases
+pub(crate) const RESERVED_DIR_PREFIX: &str = ".";
+const QUARANTINE_DIR: &str = ".quarantine";
+
+/// Moves files that fail their integrity checks out of the repo so that they are never loaded again
+/// ```
+/// #[derive(Debug)]
+/// pub struct Quarantine {
+///     repo_dir: Utf8PathBuf,
+///     isolated: Mutex<Vec<Utf8PathBuf>>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Quarantine {
+    repo_dir: Utf8PathBuf,
+    isolated: Mutex<Vec<Utf8PathBuf>>,
+}
+
+impl Quarantine {
+    pub(crate) fn new(repo_dir: &Utf8Path) -> Self {
+        Self {
+            repo_dir: repo_dir.into(),
+            isolated: Mutex::new(Vec::new()),
+        }
+    }
+    /// Move a file or directory into the quarantine directory, returning its new path
+    pub(crate) async fn isolate(&self, path: &Utf8Path) -> TuringResult<Utf8PathBuf> {
+        let mut quarantine_dir = self.repo_dir.clone();
+        quarantine_dir.push(QUARANTINE_DIR);
+        DirBuilder::new()
+            .recursive(true)
+            .create(&quarantine_dir)
+            .await?;
+
+        let relative_path = match path.strip_prefix(&self.repo_dir) {
+            Ok(relative_path) => relative_path.as_str().replace('/', "-"),
+            Err(_) => path.as_str().replace('/', "-"),
+        };
+        let isolated_at = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_nanos(),
+            Err(_) => 0,
+        };
+
+        let mut quarantined_path = quarantine_dir;
+        quarantined_path.push(format!("{}-{}", isolated_at, relative_path));
+        async_fs::rename(path, &quarantined_path).await?;
+
+        self.isolated.lock().await.push(quarantined_path.clone());
+
+        Ok(quarantined_path)
+    }
+    /// All the files that have been quarantined since the engine started
+    pub async fn isolated(&self) -> Vec<Utf8PathBuf> {
+        self.isolated.lock().await.clone()
+    }
+}
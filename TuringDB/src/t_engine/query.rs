@@ -0,0 +1,1018 @@
+use crate::{
+    Collation, DataType, Document, FieldData, GeoBox, GeoPoint, PatchOp, TuringDB, TuringDbError,
+    TuringResult,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    convert::{TryFrom, TryInto},
+    hash::{Hash, Hasher},
+};
+use tai64::TAI64N;
+
+/// A value that the fields of a document are compared against.
+/// The bytes of a field are read as a `Value` using the `DataType` they were written with,
+/// numbers and times being little endian. Values are equal when they compare as equal,
+/// so `Value::Int(1)` equals `Value::Float(1.0)`
+/// ```
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// pub enum Value {
+///     Bool(bool),
+///     Int(i128),
+///     UInt(u128),
+///     Float(f64),
+///     Text(String),
+///     Time(TAI64N),
+///     Bytes(Vec<u8>),
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Value {
+    Bool(bool),
+    Int(i128),
+    UInt(u128),
+    Float(f64),
+    Text(String),
+    Time(TAI64N),
+    Bytes(Vec<u8>),
+}
+
+impl Value {
+    /// Read the contents of a field, bytes that do not fit their `DataType` are kept as bytes
+    pub fn from_field(field_data: &FieldData) -> Value {
+        let data = field_data.data();
+
+        let value = match field_data.data_type() {
+            DataType::Boolean => match data {
+                [byte] => Some(Value::Bool(*byte != 0)),
+                _ => None,
+            },
+            DataType::U8 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::UInt(u8::from_le_bytes(bytes) as u128)),
+            DataType::U16 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::UInt(u16::from_le_bytes(bytes) as u128)),
+            DataType::U32 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::UInt(u32::from_le_bytes(bytes) as u128)),
+            DataType::U64 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::UInt(u64::from_le_bytes(bytes) as u128)),
+            DataType::U128 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::UInt(u128::from_le_bytes(bytes))),
+            DataType::I8 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Int(i8::from_le_bytes(bytes) as i128)),
+            DataType::I16 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Int(i16::from_le_bytes(bytes) as i128)),
+            DataType::I32 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Int(i32::from_le_bytes(bytes) as i128)),
+            DataType::I64 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Int(i64::from_le_bytes(bytes) as i128)),
+            DataType::I128 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Int(i128::from_le_bytes(bytes))),
+            DataType::F32 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Float(f32::from_le_bytes(bytes) as f64)),
+            DataType::F64 => data
+                .try_into()
+                .ok()
+                .map(|bytes| Value::Float(f64::from_le_bytes(bytes))),
+            DataType::STRING => std::str::from_utf8(data)
+                .ok()
+                .map(|text| Value::Text(text.into())),
+            DataType::TAI64N => TAI64N::from_slice(data).ok().map(Value::Time),
+            _ => None,
+        };
+
+        match value {
+            Some(value) => value,
+            None => Value::Bytes(data.to_vec()),
+        }
+    }
+    /// Read each element of an `ARRAY` field, `None` for a field of any other `DataType`
+    pub fn elements(field_data: &FieldData) -> Option<Vec<Value>> {
+        PatchOp::elements(field_data).ok().map(|cells| {
+            cells
+                .iter()
+                .map(|cell| {
+                    Value::from_field(&FieldData::new(cell.get_data_type(), cell.get_data()))
+                })
+                .collect()
+        })
+    }
+    /// Order two values, numbers of any kind compare with each other.
+    /// Values of different kinds are unordered so no comparison between them holds
+    pub fn compare(&self, other: &Value) -> Option<Ordering> {
+        match (self, other) {
+            (Value::Bool(left), Value::Bool(right)) => Some(left.cmp(right)),
+            (Value::Int(left), Value::Int(right)) => Some(left.cmp(right)),
+            (Value::UInt(left), Value::UInt(right)) => Some(left.cmp(right)),
+            (Value::Int(left), Value::UInt(right)) => Some(Value::compare_signed(*left, *right)),
+            (Value::UInt(left), Value::Int(right)) => {
+                Some(Value::compare_signed(*right, *left).reverse())
+            }
+            (Value::Float(left), Value::Float(right)) => left.partial_cmp(right),
+            (Value::Float(left), Value::Int(right)) => left.partial_cmp(&(*right as f64)),
+            (Value::Float(left), Value::UInt(right)) => left.partial_cmp(&(*right as f64)),
+            (Value::Int(left), Value::Float(right)) => (*left as f64).partial_cmp(right),
+            (Value::UInt(left), Value::Float(right)) => (*left as f64).partial_cmp(right),
+            (Value::Text(left), Value::Text(right)) => Some(left.cmp(right)),
+            (Value::Time(left), Value::Time(right)) => left.partial_cmp(right),
+            (Value::Bytes(left), Value::Bytes(right)) => Some(left.cmp(right)),
+            _ => None,
+        }
+    }
+
+    fn rank(&self) -> u8 {
+        match self {
+            Value::Bool(_) => 0,
+            Value::Int(_) | Value::UInt(_) | Value::Float(_) => 1,
+            Value::Text(_) => 2,
+            Value::Time(_) => 3,
+            Value::Bytes(_) => 4,
+        }
+    }
+
+    fn compare_signed(signed: i128, unsigned: u128) -> Ordering {
+        if signed < 0 {
+            Ordering::Less
+        } else {
+            (signed as u128).cmp(&unsigned)
+        }
+    }
+
+    fn hash_number<H: Hasher>(number: f64, state: &mut H) {
+        // `-0.0` equals `0.0`
+        let number = if number == 0.0 { 0.0 } else { number };
+
+        number.to_bits().hash(state)
+    }
+}
+
+impl PartialEq for Value {
+    fn eq(&self, other: &Value) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Value {}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Value) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+/// Any two values are ordered, values of different kinds by their kind
+impl Ord for Value {
+    fn cmp(&self, other: &Value) -> Ordering {
+        match self.compare(other) {
+            Some(ordering) => ordering,
+            None => self.rank().cmp(&other.rank()),
+        }
+    }
+}
+
+/// Values that are equal hash the same, numbers of any kind hash as the float they compare as.
+/// `NaN` is unordered and does not hash as the numbers it compares as equal to, so it is kept out of anything hashed
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.rank().hash(state);
+
+        match self {
+            Value::Bool(value) => value.hash(state),
+            Value::Int(value) => Value::hash_number(*value as f64, state),
+            Value::UInt(value) => Value::hash_number(*value as f64, state),
+            Value::Float(value) => Value::hash_number(*value, state),
+            Value::Text(text) => text.hash(state),
+            Value::Time(time) => time.hash(state),
+            Value::Bytes(bytes) => bytes.hash(state),
+        }
+    }
+}
+
+/// A regular expression text fields are matched against. It is compiled when it is built
+/// or decoded, so a query compiles it once however many documents it checks.
+/// Patterns are equal when they were built from the same source
+/// ```
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// #[serde(try_from = "PatternSource", into = "PatternSource")]
+/// pub struct Pattern {
+///     source: String,
+///     ignore_case: bool,
+///     regex: Regex,
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "PatternSource", into = "PatternSource")]
+pub struct Pattern {
+    source: String,
+    ignore_case: bool,
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Compile a pattern in the syntax of the `regex` crate, it matches anywhere in the text unless anchored
+    pub fn new(source: &str, ignore_case: bool) -> TuringResult<Self> {
+        let regex = RegexBuilder::new(source)
+            .case_insensitive(ignore_case)
+            .build()
+            .map_err(|error| TuringDbError::InvalidPattern(error.to_string()))?;
+
+        Ok(Self {
+            source: source.into(),
+            ignore_case,
+            regex,
+        })
+    }
+
+    pub fn get_source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn is_ignore_case(&self) -> bool {
+        self.ignore_case
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.regex.is_match(text)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.source == other.source && self.ignore_case == other.ignore_case
+    }
+}
+
+impl Eq for Pattern {}
+
+/// What a `Pattern` is encoded as
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PatternSource {
+    source: String,
+    ignore_case: bool,
+}
+
+impl TryFrom<PatternSource> for Pattern {
+    type Error = String;
+
+    fn try_from(pattern: PatternSource) -> Result<Self, Self::Error> {
+        Pattern::new(&pattern.source, pattern.ignore_case).map_err(|error| format!("{:?}", error))
+    }
+}
+
+impl From<Pattern> for PatternSource {
+    fn from(pattern: Pattern) -> Self {
+        Self {
+            source: pattern.source,
+            ignore_case: pattern.ignore_case,
+        }
+    }
+}
+
+/// A predicate over the fields of a document, fields are named by their key
+/// ```
+/// #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Filter {
+///     #[default]
+///     All,
+///     Exists(Vec<u8>),
+///     Eq(Vec<u8>, Value),
+///     Ne(Vec<u8>, Value),
+///     Gt(Vec<u8>, Value),
+///     Ge(Vec<u8>, Value),
+///     Lt(Vec<u8>, Value),
+///     Le(Vec<u8>, Value),
+///     In(Vec<u8>, Vec<Value>),
+///     StartsWith(Vec<u8>, String),
+///     StartsWithIgnoreCase(Vec<u8>, String),
+///     EqIgnoreCase(Vec<u8>, String),
+///     Matches(Vec<u8>, Pattern),
+///     And(Vec<Filter>),
+///     Or(Vec<Filter>),
+///     Not(Box<Filter>),
+///     Contains(Vec<u8>, Value),
+///     Near(Vec<u8>, GeoPoint, u64),
+///     Within(Vec<u8>, GeoBox),
+/// }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Filter {
+    /// Every document
+    #[default]
+    All,
+    /// The document has the field
+    Exists(Vec<u8>),
+    Eq(Vec<u8>, Value),
+    /// The document has the field and it is not equal to the value
+    Ne(Vec<u8>, Value),
+    Gt(Vec<u8>, Value),
+    Ge(Vec<u8>, Value),
+    Lt(Vec<u8>, Value),
+    Le(Vec<u8>, Value),
+    /// The field is equal to one of the values
+    In(Vec<u8>, Vec<Value>),
+    /// The field is text starting with the prefix
+    StartsWith(Vec<u8>, String),
+    /// The field is text starting with the prefix, ignoring case
+    StartsWithIgnoreCase(Vec<u8>, String),
+    /// The field is text equal to the text, ignoring case
+    EqIgnoreCase(Vec<u8>, String),
+    /// The field is text the pattern matches
+    Matches(Vec<u8>, Pattern),
+    And(Vec<Filter>),
+    Or(Vec<Filter>),
+    Not(Box<Filter>),
+    /// The field is an `ARRAY` with an element equal to the value
+    Contains(Vec<u8>, Value),
+    /// The field is a `GeoPoint` within the distance in metres of the point
+    Near(Vec<u8>, GeoPoint, u64),
+    /// The field is a `GeoPoint` inside the area
+    Within(Vec<u8>, GeoBox),
+}
+
+impl Filter {
+    pub fn exists(key: &[u8]) -> Self {
+        Filter::Exists(key.into())
+    }
+
+    pub fn eq(key: &[u8], value: Value) -> Self {
+        Filter::Eq(key.into(), value)
+    }
+
+    pub fn ne(key: &[u8], value: Value) -> Self {
+        Filter::Ne(key.into(), value)
+    }
+
+    pub fn gt(key: &[u8], value: Value) -> Self {
+        Filter::Gt(key.into(), value)
+    }
+
+    pub fn ge(key: &[u8], value: Value) -> Self {
+        Filter::Ge(key.into(), value)
+    }
+
+    pub fn lt(key: &[u8], value: Value) -> Self {
+        Filter::Lt(key.into(), value)
+    }
+
+    pub fn le(key: &[u8], value: Value) -> Self {
+        Filter::Le(key.into(), value)
+    }
+
+    pub fn is_in(key: &[u8], values: Vec<Value>) -> Self {
+        Filter::In(key.into(), values)
+    }
+
+    pub fn starts_with(key: &[u8], prefix: &str) -> Self {
+        Filter::StartsWith(key.into(), prefix.into())
+    }
+
+    pub fn starts_with_ignore_case(key: &[u8], prefix: &str) -> Self {
+        Filter::StartsWithIgnoreCase(key.into(), prefix.into())
+    }
+
+    pub fn eq_ignore_case(key: &[u8], text: &str) -> Self {
+        Filter::EqIgnoreCase(key.into(), text.into())
+    }
+    /// Match an `ARRAY` field holding the value, such as a document tagged with it. Documents holding
+    /// all or any of several values are matched by combining filters with `and` or `or`
+    pub fn contains(key: &[u8], value: Value) -> Self {
+        Filter::Contains(key.into(), value)
+    }
+    /// Match a `GeoPoint` field within `radius` metres of `center`
+    pub fn near(key: &[u8], center: GeoPoint, radius: u64) -> Self {
+        Filter::Near(key.into(), center, radius)
+    }
+
+    pub fn within(key: &[u8], area: GeoBox) -> Self {
+        Filter::Within(key.into(), area)
+    }
+    /// Match a text field against a regular expression, failing with `InvalidPattern` if it does not compile
+    pub fn regex(key: &[u8], pattern: &str) -> TuringResult<Self> {
+        Ok(Filter::Matches(key.into(), Pattern::new(pattern, false)?))
+    }
+
+    pub fn regex_ignore_case(key: &[u8], pattern: &str) -> TuringResult<Self> {
+        Ok(Filter::Matches(key.into(), Pattern::new(pattern, true)?))
+    }
+    /// Match documents matched by both filters
+    pub fn and(self, other: Filter) -> Self {
+        match self {
+            Filter::And(mut filters) => {
+                filters.push(other);
+
+                Filter::And(filters)
+            }
+            filter => Filter::And(vec![filter, other]),
+        }
+    }
+    /// Match documents matched by either filter
+    pub fn or(self, other: Filter) -> Self {
+        match self {
+            Filter::Or(mut filters) => {
+                filters.push(other);
+
+                Filter::Or(filters)
+            }
+            filter => Filter::Or(vec![filter, other]),
+        }
+    }
+    /// Match documents this filter does not match
+    pub fn negate(self) -> Self {
+        Filter::Not(Box::new(self))
+    }
+    /// Check a document or a row against the filter, reading only the fields the filter names
+    pub(crate) fn matches<S: FieldSource + ?Sized>(&self, source: &S) -> TuringResult<bool> {
+        self.matches_collated(source, Collation::Binary)
+    }
+    /// Check a document or a row against the filter, comparing text in range filters by `collation`
+    pub(crate) fn matches_collated<S: FieldSource + ?Sized>(
+        &self,
+        source: &S,
+        collation: Collation,
+    ) -> TuringResult<bool> {
+        match self {
+            Filter::All => Ok(true),
+            Filter::Exists(key) => source.contains_field(key),
+            Filter::Eq(key, value) => Filter::compare(source, key, |field| {
+                field.compare(value) == Some(Ordering::Equal)
+            }),
+            Filter::Ne(key, value) => Filter::compare(source, key, |field| {
+                field.compare(value) != Some(Ordering::Equal)
+            }),
+            Filter::Gt(key, value) => Filter::compare(source, key, |field| {
+                collation.compare(field, value) == Some(Ordering::Greater)
+            }),
+            Filter::Ge(key, value) => Filter::compare(source, key, |field| {
+                matches!(
+                    collation.compare(field, value),
+                    Some(Ordering::Greater) | Some(Ordering::Equal)
+                )
+            }),
+            Filter::Lt(key, value) => Filter::compare(source, key, |field| {
+                collation.compare(field, value) == Some(Ordering::Less)
+            }),
+            Filter::Le(key, value) => Filter::compare(source, key, |field| {
+                matches!(
+                    collation.compare(field, value),
+                    Some(Ordering::Less) | Some(Ordering::Equal)
+                )
+            }),
+            Filter::In(key, values) => Filter::compare(source, key, |field| {
+                values
+                    .iter()
+                    .any(|value| field.compare(value) == Some(Ordering::Equal))
+            }),
+            Filter::StartsWith(key, prefix) => {
+                Filter::compare_text(source, key, |text| text.starts_with(prefix.as_str()))
+            }
+            Filter::StartsWithIgnoreCase(key, prefix) => {
+                let prefix = prefix.to_lowercase();
+
+                Filter::compare_text(source, key, |text| {
+                    text.to_lowercase().starts_with(prefix.as_str())
+                })
+            }
+            Filter::EqIgnoreCase(key, other) => {
+                let other = other.to_lowercase();
+
+                Filter::compare_text(source, key, |text| text.to_lowercase() == other)
+            }
+            Filter::Matches(key, pattern) => {
+                Filter::compare_text(source, key, |text| pattern.is_match(text))
+            }
+            Filter::And(filters) => {
+                for filter in filters {
+                    if !filter.matches_collated(source, collation)? {
+                        return Ok(false);
+                    }
+                }
+
+                Ok(true)
+            }
+            Filter::Or(filters) => {
+                for filter in filters {
+                    if filter.matches_collated(source, collation)? {
+                        return Ok(true);
+                    }
+                }
+
+                Ok(false)
+            }
+            Filter::Not(filter) => Ok(!filter.matches_collated(source, collation)?),
+            Filter::Contains(key, value) => match source.read_field(key)? {
+                None => Ok(false),
+                Some(field_data) => Ok(Value::elements(&field_data).is_some_and(|elements| {
+                    elements
+                        .iter()
+                        .any(|element| element.compare(value) == Some(Ordering::Equal))
+                })),
+            },
+            Filter::Near(key, center, radius) => Filter::compare_point(source, key, |point| {
+                point.distance(center) <= *radius as f64
+            }),
+            Filter::Within(key, area) => {
+                Filter::compare_point(source, key, |point| area.contains(point))
+            }
+        }
+    }
+    /// A document without the field never matches a comparison
+    fn compare<S, F>(source: &S, key: &[u8], holds: F) -> TuringResult<bool>
+    where
+        S: FieldSource + ?Sized,
+        F: Fn(&Value) -> bool,
+    {
+        match source.read_field(key)? {
+            None => Ok(false),
+            Some(field_data) => Ok(holds(&Value::from_field(&field_data))),
+        }
+    }
+    /// Only a field holding a `GeoPoint` matches a comparison over points
+    fn compare_point<S, F>(source: &S, key: &[u8], holds: F) -> TuringResult<bool>
+    where
+        S: FieldSource + ?Sized,
+        F: Fn(&GeoPoint) -> bool,
+    {
+        match source.read_field(key)? {
+            None => Ok(false),
+            Some(field_data) => Ok(GeoPoint::decode(&field_data).is_ok_and(|point| holds(&point))),
+        }
+    }
+    /// Only a text field matches a comparison over text
+    fn compare_text<S, F>(source: &S, key: &[u8], holds: F) -> TuringResult<bool>
+    where
+        S: FieldSource + ?Sized,
+        F: Fn(&str) -> bool,
+    {
+        Filter::compare(source, key, |field| match field {
+            Value::Text(text) => holds(text),
+            _ => false,
+        })
+    }
+}
+
+/// Where the fields a filter, a sort or an aggregation reads come from,
+/// the document itself or a row a materialized view holds of it
+pub(crate) trait FieldSource {
+    fn read_field(&self, key: &[u8]) -> TuringResult<Option<FieldData>>;
+
+    fn contains_field(&self, key: &[u8]) -> TuringResult<bool> {
+        Ok(self.read_field(key)?.is_some())
+    }
+}
+
+impl FieldSource for Document {
+    fn read_field(&self, key: &[u8]) -> TuringResult<Option<FieldData>> {
+        match self.get(key)? {
+            None => Ok(None),
+            Some(stored) => Ok(Some(TuringDB::decode_field(&stored)?)),
+        }
+    }
+    /// A field is found without decoding it
+    fn contains_field(&self, key: &[u8]) -> TuringResult<bool> {
+        Ok(self.contains_key(key)?)
+    }
+}
+
+impl FieldSource for [(Vec<u8>, FieldData)] {
+    fn read_field(&self, key: &[u8]) -> TuringResult<Option<FieldData>> {
+        Ok(self
+            .iter()
+            .find(|(field_key, _)| field_key.as_slice() == key)
+            .map(|(_, field_data)| field_data.clone()))
+    }
+}
+
+/// The direction documents are sorted in by the field a query orders them by
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum SortOrder {
+///     Ascending,
+///     Descending,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+/// The fields of a document matched by a query
+pub(crate) type Matched = (Utf8PathBuf, Vec<(Vec<u8>, FieldData)>);
+
+/// Where a page of results ended, the continuation token of a query is this position encoded as hex
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub(crate) struct Keyset {
+///     key: Option<Value>,
+///     document: Utf8PathBuf,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Keyset {
+    // The value of the field the query orders by, `None` if the document does not have it
+    key: Option<Value>,
+    document: Utf8PathBuf,
+}
+
+impl Keyset {
+    fn encode(&self) -> TuringResult<String> {
+        let bytes = bincode::serialize::<Keyset>(self)?;
+
+        Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
+
+    fn decode(token: &str) -> TuringResult<Keyset> {
+        if !token.len().is_multiple_of(2) || !token.is_ascii() {
+            return Err(TuringDbError::InvalidContinuation);
+        }
+
+        let bytes = (0..token.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&token[index..index + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| TuringDbError::InvalidContinuation)?;
+
+        bincode::deserialize::<Keyset>(&bytes).map_err(|_| TuringDbError::InvalidContinuation)
+    }
+}
+
+/// A read over the documents of a database, the form every query takes once it is parsed.
+///
+/// Matching documents come sorted by the field the query orders by and then by name,
+/// or by name alone. Documents without the field come last in either direction.
+/// A query with a limit returns a continuation token along with a full page,
+/// the same query run after that token picks up where the page ended even if documents
+/// were created or dropped in between
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub struct Query {
+///     db: Utf8PathBuf,
+///     fields: Option<Vec<Vec<u8>>>,
+///     filter: Filter,
+///     order: Option<(Vec<u8>, SortOrder)>,
+///     offset: usize,
+///     limit: Option<usize>,
+///     after: Option<String>,
+///     sample: Option<usize>,
+///     collation: Option<Collation>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Query {
+    db: Utf8PathBuf,
+    // `None` returns every field of a matching document
+    fields: Option<Vec<Vec<u8>>>,
+    filter: Filter,
+    order: Option<(Vec<u8>, SortOrder)>,
+    offset: usize,
+    limit: Option<usize>,
+    after: Option<String>,
+    // Draw this many of the matching documents at random before they are sorted and paged
+    sample: Option<usize>,
+    // `None` orders text by the collation of the database
+    collation: Option<Collation>,
+}
+
+impl Query {
+    /// Match every document of the database `db`
+    pub fn new(db: &Utf8Path) -> Self {
+        Self {
+            db: db.into(),
+            fields: None,
+            filter: Filter::All,
+            order: None,
+            offset: 0,
+            limit: None,
+            after: None,
+            sample: None,
+            collation: None,
+        }
+    }
+    /// Only return these fields of the matching documents
+    pub fn set_fields(mut self, fields: Vec<Vec<u8>>) -> Self {
+        self.fields = Some(fields);
+
+        self
+    }
+
+    pub fn set_filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+
+        self
+    }
+    /// Sort the matching documents by the value of a field
+    pub fn set_order(mut self, key: &[u8], order: SortOrder) -> Self {
+        self.order = Some((key.into(), order));
+
+        self
+    }
+    /// Skip the first `offset` matching documents
+    pub fn set_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+
+        self
+    }
+    /// Stop after `limit` matching documents
+    pub fn set_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+
+        self
+    }
+    /// Only return the documents after the page that ended with `token`
+    pub fn set_after(mut self, token: &str) -> Self {
+        self.after = Some(token.into());
+
+        self
+    }
+
+    /// Only return a uniform random sample of `sample` matching documents, all of them when fewer match.
+    /// The sample is sorted and paged like any other result but has no continuation token
+    pub fn set_sample(mut self, sample: usize) -> Self {
+        self.sample = Some(sample);
+
+        self
+    }
+    /// Sort and compare text in range filters by `collation` instead of the collation of the database
+    pub fn set_collation(mut self, collation: Collation) -> Self {
+        self.collation = Some(collation);
+
+        self
+    }
+
+    pub fn get_db(&self) -> &Utf8Path {
+        &self.db
+    }
+
+    pub fn get_fields(&self) -> Option<&[Vec<u8>]> {
+        self.fields.as_deref()
+    }
+
+    pub fn get_filter(&self) -> &Filter {
+        &self.filter
+    }
+
+    pub fn get_order(&self) -> Option<(&[u8], SortOrder)> {
+        self.order
+            .as_ref()
+            .map(|(key, order)| (key.as_slice(), *order))
+    }
+
+    pub fn get_offset(&self) -> usize {
+        self.offset
+    }
+
+    pub fn get_limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn get_after(&self) -> Option<&str> {
+        self.after.as_deref()
+    }
+
+    pub fn get_sample(&self) -> Option<usize> {
+        self.sample
+    }
+
+    pub fn get_collation(&self) -> Option<Collation> {
+        self.collation
+    }
+    /// The query run by a database whose collation is `collation`, unless it has a collation of its own
+    pub(crate) fn collated(&self, collation: Collation) -> Query {
+        let mut query = self.clone();
+        query.collation.get_or_insert(collation);
+
+        query
+    }
+    /// Check a document or a row against the filter of the query
+    pub(crate) fn matches<S: FieldSource + ?Sized>(&self, source: &S) -> TuringResult<bool> {
+        self.filter
+            .matches_collated(source, self.collation.unwrap_or_default())
+    }
+    /// Check whether a field is returned by the query
+    pub(crate) fn selects(&self, key: &[u8]) -> bool {
+        match &self.fields {
+            None => true,
+            Some(fields) => fields.iter().any(|field| field.as_slice() == key),
+        }
+    }
+    /// Decode the continuation token the query starts after, a sample has no pages to continue from
+    pub(crate) fn keyset(&self) -> TuringResult<Option<Keyset>> {
+        match (&self.after, self.sample) {
+            (None, _) => Ok(None),
+            (Some(_), Some(_)) => Err(TuringDbError::InvalidContinuation),
+            (Some(token), None) => Ok(Some(Keyset::decode(token)?)),
+        }
+    }
+    /// Without an order the documents are read in the order of their names, so reading
+    /// can stop once there is one more match than the page needs
+    pub(crate) fn is_complete(&self, matched: usize) -> bool {
+        self.order.is_none() && self.is_filled(matched)
+    }
+    /// There is one more match than the page needs
+    pub(crate) fn is_filled(&self, matched: usize) -> bool {
+        match self.limit {
+            Some(limit) => matched > self.offset.saturating_add(limit),
+            None => false,
+        }
+    }
+    /// Read the value of the field a matching document is sorted by
+    pub(crate) fn sort_key<S: FieldSource + ?Sized>(
+        &self,
+        source: &S,
+    ) -> TuringResult<Option<Value>> {
+        let key = match &self.order {
+            None => return Ok(None),
+            Some((key, _)) => key,
+        };
+
+        Ok(source
+            .read_field(key)?
+            .map(|field_data| Value::from_field(&field_data)))
+    }
+    /// Check whether a document comes after the position a continuation token holds
+    pub(crate) fn follows(
+        &self,
+        keyset: &Keyset,
+        key: &Option<Value>,
+        document_name: &Utf8Path,
+    ) -> bool {
+        self.position(key, document_name, &keyset.key, &keyset.document) == Ordering::Greater
+    }
+    /// Sort the documents that matched and cut the page out of them along with the token of the next page.
+    /// Every document in `matched` must follow the token the query starts after
+    pub(crate) fn page(
+        &self,
+        mut matched: Vec<(Option<Value>, Matched)>,
+    ) -> TuringResult<(Vec<Matched>, Option<String>)> {
+        matched.sort_by(|(left_key, (left_name, _)), (right_key, (right_name, _))| {
+            self.position(left_key, left_name, right_key, right_name)
+        });
+
+        let mut remaining = matched.into_iter().skip(self.offset);
+        let page = match self.limit {
+            None => remaining
+                .by_ref()
+                .collect::<Vec<(Option<Value>, Matched)>>(),
+            Some(limit) => remaining.by_ref().take(limit).collect(),
+        };
+
+        let continuation = match (remaining.next(), page.last()) {
+            (Some(_), Some((key, (document_name, _)))) if self.sample.is_none() => Some(
+                Keyset {
+                    key: key.clone(),
+                    document: document_name.clone(),
+                }
+                .encode()?,
+            ),
+            _ => None,
+        };
+
+        Ok((
+            page.into_iter().map(|(_, matched)| matched).collect(),
+            continuation,
+        ))
+    }
+
+    fn position(
+        &self,
+        left_key: &Option<Value>,
+        left_name: &Utf8Path,
+        right_key: &Option<Value>,
+        right_name: &Utf8Path,
+    ) -> Ordering {
+        let collation = self.collation.unwrap_or_default();
+
+        let by_key = match (left_key, right_key) {
+            (Some(left), Some(right)) => match self.order {
+                Some((_, SortOrder::Descending)) => collation.order(right, left),
+                _ => collation.order(left, right),
+            },
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => Ordering::Equal,
+        };
+
+        by_key.then_with(|| left_name.cmp(right_name))
+    }
+}
+
+/// How a query was run: the secondary indexes that narrowed down the documents it checked, none when
+/// it checked every document, whether an index held them in the order the query sorts by, how many
+/// documents were left to check, how many of them were opened and how many the query returned
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct QueryPlan {
+///     indexes: Vec<Vec<Vec<u8>>>,
+///     in_order: bool,
+///     estimated: usize,
+///     examined: usize,
+///     returned: usize,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct QueryPlan {
+    indexes: Vec<Vec<Vec<u8>>>,
+    in_order: bool,
+    estimated: usize,
+    examined: usize,
+    returned: usize,
+}
+
+impl QueryPlan {
+    pub(crate) fn set_indexes(&mut self, indexes: Vec<Vec<Vec<u8>>>, in_order: bool) {
+        self.indexes = indexes;
+        self.in_order = in_order;
+    }
+
+    pub(crate) fn set_estimated(&mut self, estimated: usize) {
+        self.estimated = estimated;
+    }
+
+    pub(crate) fn record_examined(&mut self) {
+        self.examined += 1;
+    }
+
+    pub(crate) fn set_returned(&mut self, returned: usize) {
+        self.returned = returned;
+    }
+    /// The fields of each index the query used, empty when it checked every document
+    pub fn get_indexes(&self) -> &[Vec<Vec<u8>>] {
+        &self.indexes
+    }
+
+    pub fn is_scan(&self) -> bool {
+        self.indexes.is_empty()
+    }
+    /// Whether an index held the documents in the order the query sorts them by,
+    /// so the query stopped opening them once its page was full
+    pub fn is_in_order(&self) -> bool {
+        self.in_order
+    }
+    /// The number of documents the indexes left to check
+    pub fn get_estimated(&self) -> usize {
+        self.estimated
+    }
+    /// The number of documents opened to check them against the filter
+    pub fn get_examined(&self) -> usize {
+        self.examined
+    }
+    /// The number of documents the query returned
+    pub fn get_returned(&self) -> usize {
+        self.returned
+    }
+}
+
+/// The indices `0..len` in a uniformly random order, shuffled only as far as they are taken
+/// so that taking a few of many only costs as much as the few
+#[derive(Debug)]
+pub(crate) struct Shuffle {
+    indices: Vec<usize>,
+    taken: usize,
+}
+
+impl Shuffle {
+    pub(crate) fn new(len: usize) -> Self {
+        Self {
+            indices: (0..len).collect(),
+            taken: 0,
+        }
+    }
+}
+
+impl Iterator for Shuffle {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.taken == self.indices.len() {
+            return None;
+        }
+
+        let chosen = fastrand::usize(self.taken..self.indices.len());
+        self.indices.swap(self.taken, chosen);
+        self.taken += 1;
+
+        Some(self.indices[self.taken - 1])
+    }
+}
@@ -0,0 +1,1297 @@
+use crate::{
+    LogOp, MetaEncoding, MetaFile, OpsOutcome, Quarantine, TuringClient, TuringCommand,
+    TuringDbError, TuringEngine, TuringResponse, TuringResult, TuringServer,
+};
+use async_channel::{Receiver, Sender};
+use async_executor::Executor;
+use async_fs::OpenOptions;
+use async_io::Timer;
+use async_lock::Mutex;
+use camino::Utf8PathBuf;
+use futures_lite::{future, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// How long a follower waits without hearing from a leader by default before it stands for election,
+/// each wait is drawn between the timeout and twice the timeout so candidates rarely split the vote
+pub const DEFAULT_ELECTION_TIMEOUT: Duration = Duration::from_millis(300);
+/// How often a leader sends its entries, or an empty append as a heartbeat, by default
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_millis(50);
+/// How many applied entries are kept by default before the log is cut at the last applied one
+pub const DEFAULT_SNAPSHOT_THRESHOLD: u64 = 1024;
+/// The most entries a leader sends a follower at once
+const RAFT_BATCH: usize = 64;
+/// The file of the repo the term, vote and last applied entry of the node are kept in
+const RAFT_META_NAME: &str = "raft.meta";
+/// The file of the repo the entries of the node are appended to
+const RAFT_LOG_NAME: &str = "raft.log";
+/// The length and checksum in front of each entry of the Raft log
+const FRAME_HEADER_LEN: usize = 12;
+
+/// A command of the Raft log along with the term of the leader that took it.
+/// A new leader appends an entry without a command so the entries of earlier terms get committed
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub struct RaftEntry {
+///     term: u64,
+///     command: Option<TuringCommand>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaftEntry {
+    term: u64,
+    command: Option<TuringCommand>,
+}
+
+impl RaftEntry {
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn command(&self) -> Option<&TuringCommand> {
+        self.command.as_ref()
+    }
+}
+
+/// Runs a `TuringServer` as one node of a Raft cluster of 3 to 5 servers. The write commands
+/// clients send are appended to a log the leader replicates, and applied on every node once a
+/// majority of the nodes holds them, so the cluster keeps every acknowledged write as long as
+/// a majority of it is up. Followers serve reads from their own repo, which may lag the leader,
+/// and refuse writes with `NotLeader` naming the leader. Transactions are not replicated and
+/// refused. Nodes talk over the wire protocol with admin commands, they share the admin token
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct Raft {
+///     id: u64,
+///     peers: Vec<(u64, SocketAddr)>,
+///     admin_token: Option<String>,
+///     election_timeout: Duration,
+///     heartbeat_interval: Duration,
+///     snapshot_threshold: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Raft {
+    id: u64,
+    // Every other node of the cluster by id, the same on every node
+    peers: Vec<(u64, SocketAddr)>,
+    admin_token: Option<String>,
+    election_timeout: Duration,
+    heartbeat_interval: Duration,
+    snapshot_threshold: u64,
+}
+
+impl Raft {
+    /// The node `id`, unique within the cluster
+    pub fn new(id: u64) -> Self {
+        Self {
+            id,
+            peers: Vec::new(),
+            admin_token: None,
+            election_timeout: DEFAULT_ELECTION_TIMEOUT,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            snapshot_threshold: DEFAULT_SNAPSHOT_THRESHOLD,
+        }
+    }
+    /// Another node of the cluster and the address its `TuringServer` listens on
+    pub fn add_peer(mut self, id: u64, address: SocketAddr) -> Self {
+        self.peers.retain(|(peer, _)| *peer != id);
+        self.peers.push((id, address));
+
+        self
+    }
+    /// The admin token of the other nodes
+    pub fn set_admin_token(mut self, admin_token: &str) -> Self {
+        self.admin_token = Some(admin_token.into());
+
+        self
+    }
+
+    pub fn set_election_timeout(mut self, election_timeout: Duration) -> Self {
+        self.election_timeout = election_timeout;
+
+        self
+    }
+    /// Kept well below the election timeout so followers hear from the leader before standing
+    pub fn set_heartbeat_interval(mut self, heartbeat_interval: Duration) -> Self {
+        self.heartbeat_interval = heartbeat_interval;
+
+        self
+    }
+    /// How many applied entries are kept before the repo is committed and the log cut,
+    /// a follower missing entries that were cut is sent a copy of the documents instead
+    pub fn set_snapshot_threshold(mut self, snapshot_threshold: u64) -> Self {
+        self.snapshot_threshold = snapshot_threshold;
+
+        self
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub fn get_peers(&self) -> &[(u64, SocketAddr)] {
+        &self.peers
+    }
+
+    pub fn get_election_timeout(&self) -> Duration {
+        self.election_timeout
+    }
+
+    pub fn get_heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    pub fn get_snapshot_threshold(&self) -> u64 {
+        self.snapshot_threshold
+    }
+}
+
+/// What a node keeps on disk besides its entries, written before it answers a vote or an append
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct RaftMeta {
+    current_term: u64,
+    voted_for: Option<u64>,
+    // The last entry cut from the log, applied to the repo and committed with it
+    snapshot_index: u64,
+    snapshot_term: u64,
+    // The last entry applied to the repo and the last record of the ops log once it was
+    last_applied: u64,
+    applied_lsn: Option<u64>,
+}
+
+/// The entries of a node on disk. Each entry is appended as a frame holding its index and
+/// replaces the entries from that index on when the log is read back, so entries that conflict
+/// with the ones of a leader are replaced without rewriting the log. The log is only rewritten
+/// when it is cut
+#[derive(Debug)]
+struct RaftLog {
+    path: Utf8PathBuf,
+}
+
+impl RaftLog {
+    /// Append `entries`, the first of them at `index`, and wait until they are on disk
+    async fn append(&self, index: u64, entries: &[RaftEntry]) -> TuringResult<()> {
+        let mut frames = Vec::new();
+        for (index, entry) in (index..).zip(entries) {
+            frames.extend(RaftLog::encode(index, entry)?);
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&frames).await?;
+        file.flush().await?;
+        file.sync_data().await?;
+
+        Ok(())
+    }
+    /// Atomically replace the log with `entries`, the first of them following `snapshot_index`
+    async fn rewrite(&self, snapshot_index: u64, entries: &[RaftEntry]) -> TuringResult<()> {
+        let mut frames = Vec::new();
+        for (index, entry) in (snapshot_index + 1..).zip(entries) {
+            frames.extend(RaftLog::encode(index, entry)?);
+        }
+        let temp_path = Utf8PathBuf::from(format!("{}.tmp", self.path));
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&temp_path)
+            .await?;
+        file.write_all(&frames).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        async_fs::rename(&temp_path, &self.path).await?;
+
+        #[cfg(unix)]
+        {
+            if let Some(parent) = self.path.parent() {
+                async_fs::File::open(parent).await?.sync_all().await?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Read back the entries following `snapshot_index`, truncating a torn or corrupted tail left behind by a crash
+    async fn read(&self, snapshot_index: u64) -> TuringResult<Vec<RaftEntry>> {
+        let log_bytes = match async_fs::read(&self.path).await {
+            Ok(log_bytes) => log_bytes,
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    return Ok(Vec::new());
+                } else {
+                    return Err(error.into());
+                }
+            }
+        };
+
+        let (frames, valid_len) = RaftLog::decode(&log_bytes);
+        if valid_len < log_bytes.len() {
+            let file = OpenOptions::new().write(true).open(&self.path).await?;
+            file.set_len(valid_len as u64).await?;
+            file.sync_all().await?;
+        }
+
+        let mut entries = Vec::new();
+        for (index, entry) in frames {
+            // Applied and cut from the log before the log was rewritten
+            if index <= snapshot_index {
+                continue;
+            }
+
+            let offset = (index - snapshot_index - 1) as usize;
+            if offset > entries.len() {
+                return Err(TuringDbError::Bug(
+                    "An entry of the Raft log is missing".into(),
+                ));
+            }
+            entries.truncate(offset);
+            entries.push(entry);
+        }
+
+        Ok(entries)
+    }
+
+    fn encode(index: u64, entry: &RaftEntry) -> TuringResult<Vec<u8>> {
+        let payload = bincode::serialize(&(index, entry))?;
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&seahash::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        Ok(frame)
+    }
+    /// Decode frames until the first incomplete or corrupted one, returning the entries
+    /// along with their index and the number of bytes that were valid
+    fn decode(log_bytes: &[u8]) -> (Vec<(u64, RaftEntry)>, usize) {
+        let mut frames = Vec::new();
+        let mut offset = 0_usize;
+
+        while log_bytes.len() - offset >= FRAME_HEADER_LEN {
+            let mut len_bytes = [0_u8; 4];
+            len_bytes.copy_from_slice(&log_bytes[offset..offset + 4]);
+            let mut checksum_bytes = [0_u8; 8];
+            checksum_bytes.copy_from_slice(&log_bytes[offset + 4..offset + FRAME_HEADER_LEN]);
+
+            let payload_start = offset + FRAME_HEADER_LEN;
+            let payload_end = payload_start + u32::from_le_bytes(len_bytes) as usize;
+
+            if payload_end > log_bytes.len() {
+                break;
+            }
+
+            let payload = &log_bytes[payload_start..payload_end];
+
+            if seahash::hash(payload) != u64::from_le_bytes(checksum_bytes) {
+                break;
+            }
+
+            match bincode::deserialize::<(u64, RaftEntry)>(payload) {
+                Ok(frame) => frames.push(frame),
+                Err(_) => break,
+            }
+
+            offset = payload_end;
+        }
+
+        (frames, offset)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// The state of a node, entries are numbered from 1 and `entries[0]` follows `snapshot_index`
+#[derive(Debug)]
+struct Core {
+    meta: RaftMeta,
+    entries: Vec<RaftEntry>,
+    role: Role,
+    leader: Option<u64>,
+    commit_index: u64,
+    election_deadline: Instant,
+    // The peers that voted for the node while it is a candidate
+    votes: HashSet<u64>,
+    // The next entry to send each peer and the last one it is known to hold, while leading
+    next_index: HashMap<u64, u64>,
+    match_index: HashMap<u64, u64>,
+    // The clients waiting for the entries the node appended while leading
+    waiting: HashMap<u64, Sender<TuringResult<OpsOutcome>>>,
+}
+
+impl Core {
+    fn last_index(&self) -> u64 {
+        self.meta.snapshot_index + self.entries.len() as u64
+    }
+
+    fn last_term(&self) -> u64 {
+        self.entries
+            .last()
+            .map_or(self.meta.snapshot_term, |entry| entry.term)
+    }
+    /// `None` for an entry cut from the log or not appended yet
+    fn term_at(&self, index: u64) -> Option<u64> {
+        if index == self.meta.snapshot_index {
+            return Some(self.meta.snapshot_term);
+        }
+
+        self.entry(index).map(|entry| entry.term)
+    }
+
+    fn entry(&self, index: u64) -> Option<&RaftEntry> {
+        match index.checked_sub(self.meta.snapshot_index + 1) {
+            None => None,
+            Some(offset) => self.entries.get(offset as usize),
+        }
+    }
+    /// Follow a leader of a newer term, the clients waiting on the entries the node appended
+    /// are told it no longer leads. Whether those entries are kept depends on the new leader
+    fn step_down(&mut self, term: u64) {
+        if term > self.meta.current_term {
+            self.meta.current_term = term;
+            self.meta.voted_for = None;
+        }
+        self.role = Role::Follower;
+        self.votes.clear();
+        self.waiting.clear();
+    }
+}
+
+/// A message a node sends a peer
+enum Outgoing {
+    Command(Box<TuringCommand>),
+    // Sent once the entries the peer is missing were cut from the log, built outside the lock
+    Snapshot,
+}
+
+/// A running node of a Raft cluster, started by `TuringServer::run_until` when a `Raft` is set
+#[derive(Debug)]
+pub(crate) struct RaftNode {
+    config: Raft,
+    engine: Arc<TuringEngine>,
+    path: Utf8PathBuf,
+    log: RaftLog,
+    core: Mutex<Core>,
+    // Taken before `core` while entries are applied or the repo is replaced by a snapshot
+    applying: Mutex<()>,
+    // Wakes the task of each peer so an append does not wait for the next heartbeat
+    wakers: HashMap<u64, (Sender<()>, Receiver<()>)>,
+    stopped: AtomicBool,
+}
+
+impl RaftNode {
+    /// Read the state the node kept on disk then spawn its election timer and a task per peer.
+    /// The entries past the last applied one are applied once a leader says they are committed.
+    /// The writes of a node all come from the entries it applies, so an ops log that moved past
+    /// the record noted along with the last applied entry means the next entry reached the repo
+    /// before the node noted it, and it is not applied twice
+    pub(crate) async fn start<'a>(
+        config: &Raft,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<Arc<RaftNode>> {
+        let repo_dir = engine.get_repo_dir().await.clone();
+        let mut path = repo_dir.clone();
+        path.push(RAFT_META_NAME);
+        let mut log_path = repo_dir.clone();
+        log_path.push(RAFT_LOG_NAME);
+        let log = RaftLog { path: log_path };
+
+        let mut meta = MetaFile::read::<RaftMeta>(&path, &Quarantine::new(&repo_dir))
+            .await?
+            .unwrap_or_default();
+        let entries = log.read(meta.snapshot_index).await?;
+
+        meta.last_applied = meta.last_applied.max(meta.snapshot_index);
+        let applied_lsn = engine.synced_lsn().await?;
+        if applied_lsn > meta.applied_lsn
+            && meta.last_applied < meta.snapshot_index + entries.len() as u64
+        {
+            meta.last_applied += 1;
+            meta.applied_lsn = applied_lsn;
+        }
+        let applied = meta.last_applied;
+
+        let node = Arc::new(RaftNode {
+            engine,
+            path,
+            log,
+            core: Mutex::new(Core {
+                meta,
+                entries,
+                role: Role::Follower,
+                leader: None,
+                commit_index: applied,
+                election_deadline: RaftNode::deadline(config),
+                votes: HashSet::new(),
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+                waiting: HashMap::new(),
+            }),
+            applying: Mutex::new(()),
+            wakers: config
+                .peers
+                .iter()
+                .map(|(peer, _)| (*peer, async_channel::bounded(1)))
+                .collect(),
+            stopped: AtomicBool::new(false),
+            config: config.clone(),
+        });
+
+        executor.spawn(Arc::clone(&node).election_loop()).detach();
+        for (peer, address) in node.config.peers.iter() {
+            executor
+                .spawn(Arc::clone(&node).peer_loop(*peer, *address))
+                .detach();
+        }
+
+        Ok(node)
+    }
+    /// End the tasks of the node, for when the server shuts down
+    pub(crate) fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        for (sender, _) in self.wakers.values() {
+            sender.close();
+        }
+    }
+    /// Append a write command to the log and wait until it is applied, returning its outcome.
+    /// Only the leader takes writes
+    pub(crate) async fn propose(&self, command: TuringCommand) -> TuringResult<OpsOutcome> {
+        let applied = {
+            let mut core = self.core.lock().await;
+            if core.role != Role::Leader {
+                return Err(TuringDbError::NotLeader {
+                    leader: self.leader_address(&core),
+                });
+            }
+
+            let index = core.last_index() + 1;
+            let entry = RaftEntry {
+                term: core.meta.current_term,
+                command: Some(command),
+            };
+            self.log.append(index, std::slice::from_ref(&entry)).await?;
+            core.entries.push(entry);
+
+            let (sender, applied) = async_channel::bounded(1);
+            core.waiting.insert(index, sender);
+            self.advance_commit(&mut core);
+
+            applied
+        };
+        self.wake_peers();
+        self.apply_committed().await?;
+
+        match applied.recv().await {
+            Ok(outcome) => outcome,
+            // The node stopped leading before the entry was committed
+            Err(_) => Err(TuringDbError::NotLeader { leader: None }),
+        }
+    }
+    /// Answer a `RaftRequestVote`, a vote goes to the first candidate of a term
+    /// whose log holds every entry the node holds
+    pub(crate) async fn request_vote(
+        &self,
+        term: u64,
+        candidate: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    ) -> TuringResult<TuringResponse> {
+        let mut core = self.core.lock().await;
+        if term > core.meta.current_term {
+            core.step_down(term);
+        }
+
+        let up_to_date = (last_log_term, last_log_index) >= (core.last_term(), core.last_index());
+        let granted = term == core.meta.current_term
+            && up_to_date
+            && core.meta.voted_for.is_none_or(|voted| voted == candidate);
+        if granted {
+            core.meta.voted_for = Some(candidate);
+            core.election_deadline = RaftNode::deadline(&self.config);
+        }
+        self.persist(&core).await?;
+
+        Ok(TuringResponse::RaftVote {
+            term: core.meta.current_term,
+            granted,
+        })
+    }
+    /// Answer a `RaftAppendEntries`, entries that conflict with the ones of the leader are replaced.
+    /// A refusal tells the leader the last entry it may try from next
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn append_entries(
+        &self,
+        term: u64,
+        leader: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<RaftEntry>,
+        leader_commit: u64,
+    ) -> TuringResult<TuringResponse> {
+        let response = {
+            let mut core = self.core.lock().await;
+            if term < core.meta.current_term {
+                return Ok(TuringResponse::RaftAppended {
+                    term: core.meta.current_term,
+                    success: false,
+                    match_index: 0,
+                });
+            }
+            self.follow(&mut core, term, leader);
+
+            let refused = |core: &Core, match_index: u64| TuringResponse::RaftAppended {
+                term: core.meta.current_term,
+                success: false,
+                match_index,
+            };
+            if prev_log_index > core.last_index() {
+                return Ok(refused(&core, core.last_index()));
+            }
+            if prev_log_index >= core.meta.snapshot_index
+                && core.term_at(prev_log_index) != Some(prev_log_term)
+            {
+                return Ok(refused(&core, prev_log_index.saturating_sub(1)));
+            }
+
+            let last_new = prev_log_index + entries.len() as u64;
+            // The entries the node did not hold yet, from the first one on
+            let mut appended = None;
+            for (index, entry) in (prev_log_index + 1..).zip(entries) {
+                // Already applied and cut from the log
+                if index <= core.meta.snapshot_index {
+                    continue;
+                }
+
+                match core.term_at(index) {
+                    Some(term) if term == entry.term => (),
+                    Some(_) => {
+                        let kept = (index - core.meta.snapshot_index - 1) as usize;
+                        core.entries.truncate(kept);
+                        core.waiting.retain(|waiting, _| *waiting < index);
+                        appended.get_or_insert(index);
+                        core.entries.push(entry);
+                    }
+                    None => {
+                        appended.get_or_insert(index);
+                        core.entries.push(entry);
+                    }
+                }
+            }
+            self.persist(&core).await?;
+            if let Some(appended) = appended {
+                let offset = (appended - core.meta.snapshot_index - 1) as usize;
+                self.log.append(appended, &core.entries[offset..]).await?;
+            }
+
+            if leader_commit > core.commit_index {
+                core.commit_index = leader_commit.min(last_new).max(core.commit_index);
+            }
+
+            TuringResponse::RaftAppended {
+                term: core.meta.current_term,
+                success: true,
+                match_index: last_new,
+            }
+        };
+        self.apply_committed().await?;
+
+        Ok(response)
+    }
+    /// Answer a `RaftInstallSnapshot` by replacing every database of the repo with the documents
+    /// of the leader as of `last_index`, the entries the node holds past it are kept when they agree
+    pub(crate) async fn install_snapshot(
+        &self,
+        term: u64,
+        leader: u64,
+        last_index: u64,
+        last_term: u64,
+        ops: Vec<LogOp>,
+    ) -> TuringResult<TuringResponse> {
+        let _applying = self.applying.lock().await;
+
+        {
+            let mut core = self.core.lock().await;
+            if term < core.meta.current_term {
+                return Ok(TuringResponse::RaftAppended {
+                    term: core.meta.current_term,
+                    success: false,
+                    match_index: 0,
+                });
+            }
+            self.follow(&mut core, term, leader);
+            self.persist(&core).await?;
+
+            if last_index <= core.meta.last_applied {
+                return Ok(TuringResponse::RaftAppended {
+                    term: core.meta.current_term,
+                    success: true,
+                    match_index: last_index,
+                });
+            }
+        }
+
+        self.engine.restore_from(ops).await?;
+        self.engine.repo_commit().await?;
+        let applied_lsn = self.engine.synced_lsn().await?;
+
+        let mut core = self.core.lock().await;
+        if core.term_at(last_index) == Some(last_term) {
+            let cut = (last_index - core.meta.snapshot_index) as usize;
+            core.entries.drain(..cut);
+        } else {
+            core.entries.clear();
+        }
+        core.meta.snapshot_index = last_index;
+        core.meta.snapshot_term = last_term;
+        core.commit_index = core.commit_index.max(last_index);
+        core.meta.last_applied = last_index;
+        core.meta.applied_lsn = applied_lsn;
+        self.persist(&core).await?;
+        self.log.rewrite(last_index, &core.entries).await?;
+
+        Ok(TuringResponse::RaftAppended {
+            term: core.meta.current_term,
+            success: true,
+            match_index: last_index,
+        })
+    }
+    /// Stand for election whenever the election timeout passes without hearing from a leader
+    async fn election_loop(self: Arc<Self>) -> TuringResult<()> {
+        while !self.stopped.load(Ordering::Acquire) {
+            Timer::after(self.config.heartbeat_interval).await;
+
+            let mut core = self.core.lock().await;
+            if core.role == Role::Leader || Instant::now() < core.election_deadline {
+                continue;
+            }
+
+            core.meta.current_term += 1;
+            core.meta.voted_for = Some(self.config.id);
+            core.role = Role::Candidate;
+            core.leader = None;
+            core.votes.clear();
+            core.election_deadline = RaftNode::deadline(&self.config);
+            self.persist(&core).await?;
+
+            // A node without peers is its own majority
+            if self.is_majority(1) {
+                self.lead(&mut core).await?;
+            }
+            drop(core);
+            self.wake_peers();
+        }
+
+        Ok(())
+    }
+    /// Ask a peer for its vote while the node is a candidate and send it entries while it leads,
+    /// at every heartbeat or as soon as the node is woken. A peer that cannot be reached is tried
+    /// again at the next heartbeat over a new connection
+    async fn peer_loop(self: Arc<Self>, peer: u64, address: SocketAddr) -> TuringResult<()> {
+        let woken = match self.wakers.get(&peer) {
+            Some((_, woken)) => woken.clone(),
+            None => return Err(TuringDbError::Bug("A peer without a waker".into())),
+        };
+        let mut client = None;
+
+        while !self.stopped.load(Ordering::Acquire) {
+            future::or(Timer::after(self.config.heartbeat_interval), async {
+                woken.recv().await.ok();
+
+                Instant::now()
+            })
+            .await;
+
+            let (term, outgoing) = {
+                let core = self.core.lock().await;
+                let term = core.meta.current_term;
+
+                match core.role {
+                    Role::Follower => continue,
+                    Role::Candidate if core.votes.contains(&peer) => continue,
+                    Role::Candidate => (
+                        term,
+                        Outgoing::Command(Box::new(TuringCommand::RaftRequestVote {
+                            term,
+                            candidate: self.config.id,
+                            last_log_index: core.last_index(),
+                            last_log_term: core.last_term(),
+                        })),
+                    ),
+                    Role::Leader => (term, self.append_for(&core, peer)),
+                }
+            };
+            let command = match outgoing {
+                Outgoing::Command(command) => *command,
+                Outgoing::Snapshot => match self.snapshot_for(term).await? {
+                    Some(command) => command,
+                    None => continue,
+                },
+            };
+
+            match self.send(&mut client, address, command).await {
+                Ok(response) => self.handle(peer, term, response).await?,
+                Err(_) => client = None,
+            }
+        }
+
+        Ok(())
+    }
+    /// The entries a peer is missing from `next_index` on, or a snapshot once they were cut
+    fn append_for(&self, core: &Core, peer: u64) -> Outgoing {
+        let next_index = core
+            .next_index
+            .get(&peer)
+            .copied()
+            .unwrap_or_else(|| core.last_index() + 1);
+        let prev_log_index = next_index - 1;
+
+        let prev_log_term = match core.term_at(prev_log_index) {
+            Some(prev_log_term) => prev_log_term,
+            None => return Outgoing::Snapshot,
+        };
+        let entries = core
+            .entries
+            .iter()
+            .skip((next_index - core.meta.snapshot_index - 1) as usize)
+            .take(RAFT_BATCH)
+            .cloned()
+            .collect();
+
+        Outgoing::Command(Box::new(TuringCommand::RaftAppendEntries {
+            term: core.meta.current_term,
+            leader: self.config.id,
+            prev_log_index,
+            prev_log_term,
+            entries,
+            leader_commit: core.commit_index,
+        }))
+    }
+    /// Every document of the repo as of the last applied entry. `None` once the node no longer leads
+    async fn snapshot_for(&self, term: u64) -> TuringResult<Option<TuringCommand>> {
+        let _applying = self.applying.lock().await;
+
+        let (last_index, last_term) = {
+            let core = self.core.lock().await;
+            if core.role != Role::Leader || core.meta.current_term != term {
+                return Ok(None);
+            }
+
+            match core.term_at(core.meta.last_applied) {
+                Some(last_term) => (core.meta.last_applied, last_term),
+                None => return Err(TuringDbError::Bug("The last applied entry was cut".into())),
+            }
+        };
+
+        Ok(Some(TuringCommand::RaftInstallSnapshot {
+            term,
+            leader: self.config.id,
+            last_index,
+            last_term,
+            ops: self.engine.dump().await?,
+        }))
+    }
+
+    async fn send(
+        &self,
+        client: &mut Option<TuringClient>,
+        address: SocketAddr,
+        command: TuringCommand,
+    ) -> TuringResult<TuringResponse> {
+        if client.is_none() {
+            let mut connected = TuringClient::connect(address)
+                .await?
+                .set_timeout(self.config.election_timeout);
+            if let Some(admin_token) = &self.config.admin_token {
+                connected.authenticate(admin_token).await?;
+            }
+            *client = Some(connected);
+        }
+
+        match client {
+            Some(client) => client.request(command).await,
+            None => Err(TuringDbError::NotConnected),
+        }
+    }
+    /// Count a vote or move the entries a peer is known to hold, `term` is the one the request was sent in
+    async fn handle(&self, peer: u64, term: u64, response: TuringResponse) -> TuringResult<()> {
+        let mut core = self.core.lock().await;
+
+        match response {
+            TuringResponse::RaftVote {
+                term: peer_term,
+                granted,
+            } => {
+                if peer_term > core.meta.current_term {
+                    core.step_down(peer_term);
+                    self.persist(&core).await?;
+                } else if core.role == Role::Candidate && core.meta.current_term == term && granted
+                {
+                    core.votes.insert(peer);
+                    if self.is_majority(core.votes.len() + 1) {
+                        self.lead(&mut core).await?;
+                        drop(core);
+                        self.wake_peers();
+                    }
+                }
+            }
+            TuringResponse::RaftAppended {
+                term: peer_term,
+                success,
+                match_index,
+            } => {
+                if peer_term > core.meta.current_term {
+                    core.step_down(peer_term);
+                    self.persist(&core).await?;
+                } else if core.role == Role::Leader && core.meta.current_term == term {
+                    if success {
+                        let matched = core.match_index.entry(peer).or_insert(0);
+                        *matched = (*matched).max(match_index);
+                        core.next_index.insert(peer, match_index + 1);
+                        self.advance_commit(&mut core);
+                        drop(core);
+                        self.apply_committed().await?;
+                    } else {
+                        let next_index = core.next_index.get(&peer).copied().unwrap_or(1);
+                        core.next_index.insert(
+                            peer,
+                            next_index.saturating_sub(1).min(match_index + 1).max(1),
+                        );
+                    }
+                }
+            }
+            // An error from the peer, such as a wrong admin token, is tried again at the next heartbeat
+            _ => (),
+        }
+
+        Ok(())
+    }
+    /// Lead the current term, appending an entry without a command so that entries
+    /// of earlier terms are committed along with it
+    async fn lead(&self, core: &mut Core) -> TuringResult<()> {
+        core.role = Role::Leader;
+        core.leader = Some(self.config.id);
+        let next_index = core.last_index() + 1;
+        core.next_index = self
+            .config
+            .peers
+            .iter()
+            .map(|(peer, _)| (*peer, next_index))
+            .collect();
+        core.match_index = self
+            .config
+            .peers
+            .iter()
+            .map(|(peer, _)| (*peer, 0))
+            .collect();
+
+        let entry = RaftEntry {
+            term: core.meta.current_term,
+            command: None,
+        };
+        self.log
+            .append(core.last_index() + 1, std::slice::from_ref(&entry))
+            .await?;
+        core.entries.push(entry);
+        self.advance_commit(core);
+
+        Ok(())
+    }
+    /// Follow the leader of `term` and wait for it again for a whole election timeout
+    fn follow(&self, core: &mut Core, term: u64, leader: u64) {
+        if term > core.meta.current_term || core.role != Role::Follower {
+            core.step_down(term);
+        }
+        core.leader = Some(leader);
+        core.election_deadline = RaftNode::deadline(&self.config);
+    }
+    /// Commit the last entry of the current term a majority of the nodes holds,
+    /// and every entry before it
+    fn advance_commit(&self, core: &mut Core) {
+        for index in (core.commit_index + 1..=core.last_index()).rev() {
+            if core.term_at(index) != Some(core.meta.current_term) {
+                break;
+            }
+
+            let holding = core
+                .match_index
+                .values()
+                .filter(|matched| **matched >= index)
+                .count();
+            if self.is_majority(holding + 1) {
+                core.commit_index = index;
+                break;
+            }
+        }
+    }
+    /// Apply the committed entries in order, then cut the log once it holds more applied entries
+    /// than the snapshot threshold. A write that fails, fails the same way on every node
+    async fn apply_committed(&self) -> TuringResult<()> {
+        let _applying = self.applying.lock().await;
+
+        loop {
+            let (index, command) = {
+                let core = self.core.lock().await;
+                if core.meta.last_applied >= core.commit_index {
+                    break;
+                }
+
+                let index = core.meta.last_applied + 1;
+                match core.entry(index) {
+                    Some(entry) => (index, entry.command.clone()),
+                    None => return Err(TuringDbError::Bug("A committed entry was cut".into())),
+                }
+            };
+
+            let outcome = match command {
+                Some(command) => TuringServer::dispatch(&self.engine, command).await,
+                None => Ok(OpsOutcome::BatchWritten { ops: 0 }),
+            };
+            let applied_lsn = self.engine.synced_lsn().await?;
+
+            let mut core = self.core.lock().await;
+            core.meta.last_applied = index;
+            core.meta.applied_lsn = applied_lsn;
+            self.persist(&core).await?;
+            if let Some(waiting) = core.waiting.remove(&index) {
+                waiting.try_send(outcome).ok();
+            }
+        }
+
+        let last_applied = {
+            let core = self.core.lock().await;
+            if core.meta.last_applied - core.meta.snapshot_index <= self.config.snapshot_threshold {
+                return Ok(());
+            }
+
+            core.meta.last_applied
+        };
+
+        // The repo holds every applied entry once it is committed, they are no longer needed
+        self.engine.repo_commit().await?;
+
+        let mut core = self.core.lock().await;
+        let snapshot_term = match core.term_at(last_applied) {
+            Some(snapshot_term) => snapshot_term,
+            None => return Err(TuringDbError::Bug("The last applied entry was cut".into())),
+        };
+        let cut = (last_applied - core.meta.snapshot_index) as usize;
+        core.entries.drain(..cut);
+        core.meta.snapshot_index = last_applied;
+        core.meta.snapshot_term = snapshot_term;
+        // Entries the log still holds up to the snapshot are skipped when it is read back
+        self.persist(&core).await?;
+
+        self.log.rewrite(last_applied, &core.entries).await
+    }
+
+    async fn persist(&self, core: &Core) -> TuringResult<()> {
+        MetaFile::write(&self.path, &MetaEncoding::Bincode.encode(&core.meta)?).await
+    }
+
+    fn wake_peers(&self) {
+        for (sender, _) in self.wakers.values() {
+            sender.try_send(()).ok();
+        }
+    }
+
+    fn is_majority(&self, nodes: usize) -> bool {
+        nodes * 2 > self.config.peers.len() + 1
+    }
+
+    fn leader_address(&self, core: &Core) -> Option<SocketAddr> {
+        self.config
+            .peers
+            .iter()
+            .find(|(peer, _)| Some(*peer) == core.leader)
+            .map(|(_, address)| *address)
+    }
+    /// A new election deadline drawn between the election timeout and twice the timeout
+    fn deadline(config: &Raft) -> Instant {
+        Instant::now() + config.election_timeout + config.election_timeout.mul_f64(fastrand::f64())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TuringDBOps;
+    use camino::Utf8Path;
+    use futures_lite::future::block_on;
+
+    fn repo_dir() -> Utf8PathBuf {
+        let mut repo_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap();
+        repo_dir.push(format!("turingdb-raft-{}", fastrand::u64(..)));
+
+        repo_dir
+    }
+
+    async fn open(repo_dir: &Utf8Path, create: bool) -> Arc<TuringEngine> {
+        let mut engine = TuringEngine::with_path(repo_dir).await.unwrap();
+        if create {
+            engine.repo_create().await.unwrap();
+        }
+        engine.repo_init().await.unwrap();
+
+        Arc::new(engine)
+    }
+    // The tasks of the node only run once the executor is, the tests drive the node by hand
+    async fn node(engine: Arc<TuringEngine>, executor: &Executor<'_>) -> Arc<RaftNode> {
+        let config = Raft::new(1)
+            .add_peer(2, "127.0.0.1:7002".parse().unwrap())
+            .add_peer(3, "127.0.0.1:7003".parse().unwrap());
+
+        RaftNode::start(&config, engine, executor).await.unwrap()
+    }
+
+    fn entries(terms: &[u64]) -> Vec<RaftEntry> {
+        terms
+            .iter()
+            .map(|term| RaftEntry {
+                term: *term,
+                command: None,
+            })
+            .collect()
+    }
+
+    fn terms(entries: &[RaftEntry]) -> Vec<u64> {
+        entries.iter().map(RaftEntry::term).collect()
+    }
+
+    fn granted(response: TuringResponse) -> bool {
+        match response {
+            TuringResponse::RaftVote { granted, .. } => granted,
+            response => panic!("Answered a vote with {:?}", response),
+        }
+    }
+
+    fn appended(response: TuringResponse) -> bool {
+        match response {
+            TuringResponse::RaftAppended { success, .. } => success,
+            response => panic!("Answered an append with {:?}", response),
+        }
+    }
+
+    #[test]
+    fn a_candidate_leads_once_a_majority_votes_for_it() {
+        block_on(async {
+            let repo_dir = repo_dir();
+            let executor = Executor::new();
+            let node = node(open(&repo_dir, true).await, &executor).await;
+
+            {
+                let mut core = node.core.lock().await;
+                core.meta.current_term = 1;
+                core.meta.voted_for = Some(1);
+                core.role = Role::Candidate;
+            }
+
+            let refused = TuringResponse::RaftVote {
+                term: 1,
+                granted: false,
+            };
+            node.handle(2, 1, refused).await.unwrap();
+            assert_eq!(node.core.lock().await.role, Role::Candidate);
+
+            let granted = TuringResponse::RaftVote {
+                term: 1,
+                granted: true,
+            };
+            node.handle(3, 1, granted).await.unwrap();
+            let core = node.core.lock().await;
+            assert_eq!(core.role, Role::Leader);
+            assert_eq!(core.leader, Some(1));
+            // The entry without a command that commits the entries of earlier terms
+            assert_eq!(terms(&core.entries), vec![1]);
+            drop(core);
+
+            // A peer of a newer term ends the leadership
+            let newer = TuringResponse::RaftAppended {
+                term: 2,
+                success: false,
+                match_index: 0,
+            };
+            node.handle(2, 1, newer).await.unwrap();
+            let core = node.core.lock().await;
+            assert_eq!(core.role, Role::Follower);
+            assert_eq!(core.meta.current_term, 2);
+            drop(core);
+
+            async_fs::remove_dir_all(&repo_dir).await.ok();
+        });
+    }
+
+    #[test]
+    fn a_vote_goes_to_the_first_up_to_date_candidate_of_a_term() {
+        block_on(async {
+            let repo_dir = repo_dir();
+            let executor = Executor::new();
+            let node = node(open(&repo_dir, true).await, &executor).await;
+
+            let response = node
+                .append_entries(1, 2, 0, 0, entries(&[1, 1]), 0)
+                .await
+                .unwrap();
+            assert!(appended(response));
+
+            // The log of the candidate misses an entry of the node
+            let behind = node.request_vote(2, 3, 1, 1).await.unwrap();
+            assert!(!granted(behind));
+
+            let up_to_date = node.request_vote(2, 2, 2, 1).await.unwrap();
+            assert!(granted(up_to_date));
+
+            let second = node.request_vote(2, 3, 2, 1).await.unwrap();
+            assert!(!granted(second));
+
+            // A candidate of an older term is refused
+            let stale = node.request_vote(1, 3, 5, 1).await.unwrap();
+            assert!(!granted(stale));
+
+            async_fs::remove_dir_all(&repo_dir).await.ok();
+        });
+    }
+
+    #[test]
+    fn entries_conflicting_with_the_leader_are_truncated() {
+        block_on(async {
+            let repo_dir = repo_dir();
+            let executor = Executor::new();
+            let node = node(open(&repo_dir, true).await, &executor).await;
+
+            let response = node
+                .append_entries(1, 2, 0, 0, entries(&[1, 1, 1]), 0)
+                .await
+                .unwrap();
+            assert!(appended(response));
+
+            // The entry before the new ones does not match the log of the node
+            let response = node
+                .append_entries(2, 3, 2, 2, entries(&[2]), 0)
+                .await
+                .unwrap();
+            assert!(!appended(response));
+            assert_eq!(terms(&node.core.lock().await.entries), vec![1, 1, 1]);
+
+            let response = node
+                .append_entries(2, 3, 1, 1, entries(&[2]), 0)
+                .await
+                .unwrap();
+            assert!(appended(response));
+
+            let core = node.core.lock().await;
+            assert_eq!(terms(&core.entries), vec![1, 2]);
+            assert_eq!(core.term_at(2), Some(2));
+            assert_eq!(core.term_at(3), None);
+            drop(core);
+
+            // The replacing entry was appended to the log after the ones it replaces
+            assert_eq!(terms(&node.log.read(0).await.unwrap()), vec![1, 2]);
+
+            async_fs::remove_dir_all(&repo_dir).await.ok();
+        });
+    }
+
+    #[test]
+    fn a_restarted_node_keeps_its_log_and_what_it_applied() {
+        block_on(async {
+            let repo_dir = repo_dir();
+
+            {
+                let executor = Executor::new();
+                let node = node(open(&repo_dir, true).await, &executor).await;
+
+                let response = node
+                    .append_entries(1, 2, 0, 0, entries(&[1, 1, 1]), 2)
+                    .await
+                    .unwrap();
+                assert!(appended(response));
+                assert_eq!(node.core.lock().await.meta.last_applied, 2);
+            }
+
+            let engine = {
+                let engine = open(&repo_dir, false).await;
+                let executor = Executor::new();
+                let node = node(Arc::clone(&engine), &executor).await;
+
+                let core = node.core.lock().await;
+                assert_eq!(terms(&core.entries), vec![1, 1, 1]);
+                assert_eq!(core.meta.current_term, 1);
+                assert_eq!(core.meta.voted_for, None);
+                assert_eq!(core.meta.last_applied, 2);
+                assert_eq!(core.commit_index, 2);
+                drop(core);
+
+                engine
+            };
+
+            // The third entry reached the repo before the node could note it was applied
+            engine
+                .db_create(TuringDBOps::default().set_db_name("applied"))
+                .await
+                .unwrap();
+            drop(engine);
+
+            let executor = Executor::new();
+            let node = node(open(&repo_dir, false).await, &executor).await;
+            let core = node.core.lock().await;
+            assert_eq!(core.meta.last_applied, 3);
+            assert_eq!(core.commit_index, 3);
+            drop(core);
+
+            async_fs::remove_dir_all(&repo_dir).await.ok();
+        });
+    }
+
+    #[test]
+    fn a_torn_entry_is_cut_from_the_log() {
+        block_on(async {
+            let repo_dir = repo_dir();
+            async_fs::create_dir_all(&repo_dir).await.unwrap();
+            let log = RaftLog {
+                path: repo_dir.join(RAFT_LOG_NAME),
+            };
+
+            log.append(1, &entries(&[1, 1])).await.unwrap();
+            let len = async_fs::metadata(&log.path).await.unwrap().len();
+
+            let mut file = OpenOptions::new()
+                .append(true)
+                .open(&log.path)
+                .await
+                .unwrap();
+            file.write_all(&[7, 0, 0, 0, 1, 2]).await.unwrap();
+            file.flush().await.unwrap();
+
+            assert_eq!(terms(&log.read(0).await.unwrap()), vec![1, 1]);
+            assert_eq!(async_fs::metadata(&log.path).await.unwrap().len(), len);
+
+            // Entries up to a snapshot are skipped, the log is rewritten from it on
+            log.append(3, &entries(&[2])).await.unwrap();
+            assert_eq!(terms(&log.read(1).await.unwrap()), vec![1, 2]);
+            log.rewrite(2, &entries(&[2])).await.unwrap();
+            assert_eq!(terms(&log.read(2).await.unwrap()), vec![2]);
+
+            async_fs::remove_dir_all(&repo_dir).await.ok();
+        });
+    }
+}
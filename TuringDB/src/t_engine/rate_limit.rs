@@ -0,0 +1,126 @@
+use dashmap::DashMap;
+use std::{
+    net::IpAddr,
+    time::{Duration, Instant},
+};
+
+/// Past this many clients, those whose bucket refilled are forgotten
+const MAX_TRACKED_CLIENTS: usize = 16 * 1024;
+
+/// How many commands a client may send, as a token bucket per client address.
+/// A client may send `burst` commands at once, then `per_second` more every second
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub struct RateLimit {
+///     per_second: u32,
+///     burst: u32,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    per_second: u32,
+    burst: u32,
+}
+
+impl RateLimit {
+    pub fn new(per_second: u32, burst: u32) -> Self {
+        Self { per_second, burst }
+    }
+
+    pub fn get_per_second(&self) -> u32 {
+        self.per_second
+    }
+
+    pub fn get_burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// The buckets of the clients of a server
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: DashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: DashMap::new(),
+        }
+    }
+    /// Take a token from the bucket of `client`, or tell how long until it holds one again
+    pub(crate) fn acquire(&self, client: IpAddr) -> Result<(), Duration> {
+        let now = Instant::now();
+        let burst = self.limit.burst as f64;
+        let per_second = self.limit.per_second as f64;
+
+        if self.buckets.len() > MAX_TRACKED_CLIENTS {
+            self.buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.refilled).as_secs_f64() * per_second
+                    < burst
+            });
+        }
+
+        let mut bucket = self.buckets.entry(client).or_insert(Bucket {
+            tokens: burst,
+            refilled: now,
+        });
+        bucket.tokens = (bucket.tokens
+            + now.duration_since(bucket.refilled).as_secs_f64() * per_second)
+            .min(burst);
+        bucket.refilled = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+
+            return Ok(());
+        }
+
+        if self.limit.per_second == 0 {
+            return Err(Duration::from_secs(u32::MAX as u64));
+        }
+
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn a_client_past_its_burst_is_throttled_alone() {
+        let limiter = RateLimiter::new(RateLimit::new(1, 3));
+        let client = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+
+        for _ in 0..3 {
+            assert!(limiter.acquire(client).is_ok());
+        }
+        // The bucket refills one token a second, so the client waits at most that long
+        let wait = limiter.acquire(client).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+
+        assert!(limiter.acquire(other).is_ok());
+    }
+
+    #[test]
+    fn a_bucket_refills_over_time() {
+        let limiter = RateLimiter::new(RateLimit::new(1000, 1));
+        let client = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        assert!(limiter.acquire(client).is_ok());
+        assert!(limiter.acquire(client).is_err());
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(limiter.acquire(client).is_ok());
+    }
+}
@@ -0,0 +1,121 @@
+use crate::{DataType, FieldData, TDBCell, TuringDbError, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+
+/// The deepest a chain of references is followed when a document is populated
+pub const MAX_POPULATE_DEPTH: usize = 8;
+/// The most documents a single populated read resolves, the references past it are left unresolved
+pub(crate) const MAX_POPULATED: usize = 1024;
+
+/// A field pointing to a document, possibly in another database. It is stored as a
+/// `DataType::REFERENCE` field holding `db/document`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Reference {
+///     db: Utf8PathBuf,
+///     document: Utf8PathBuf,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Reference {
+    db: Utf8PathBuf,
+    document: Utf8PathBuf,
+}
+
+impl Reference {
+    pub fn new(db: &Utf8Path, document: &Utf8Path) -> Self {
+        Self {
+            db: db.into(),
+            document: document.into(),
+        }
+    }
+
+    pub fn db(&self) -> &Utf8Path {
+        &self.db
+    }
+
+    pub fn document(&self) -> &Utf8Path {
+        &self.document
+    }
+    /// The value of a field holding the reference
+    pub fn encode(&self) -> TDBCell {
+        let target = format!("{}/{}", self.db, self.document);
+
+        TDBCell::new(DataType::REFERENCE, target.as_bytes())
+    }
+    /// Read the reference a field holds, the database name ends at the first `/`
+    pub fn decode(field_data: &FieldData) -> TuringResult<Reference> {
+        if field_data.data_type() != DataType::REFERENCE {
+            return Err(TuringDbError::FieldTypeMismatch);
+        }
+
+        let target = match std::str::from_utf8(field_data.data()) {
+            Ok(target) => target,
+            Err(_) => return Err(TuringDbError::InvalidData),
+        };
+
+        match target.split_once('/') {
+            Some((db, document)) if !db.is_empty() && !document.is_empty() => {
+                Ok(Reference::new(Utf8Path::new(db), Utf8Path::new(document)))
+            }
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+}
+
+/// A document read along with the documents its reference fields point to
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct Populated {
+///     fields: Vec<(Vec<u8>, FieldData)>,
+///     references: Vec<(Vec<u8>, Resolution)>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Populated {
+    fields: Vec<(Vec<u8>, FieldData)>,
+    // What each reference field resolved to, keyed by the field holding the reference
+    references: Vec<(Vec<u8>, Resolution)>,
+}
+
+impl Populated {
+    pub(crate) fn new(
+        fields: Vec<(Vec<u8>, FieldData)>,
+        references: Vec<(Vec<u8>, Resolution)>,
+    ) -> Self {
+        Self { fields, references }
+    }
+
+    pub fn fields(&self) -> &[(Vec<u8>, FieldData)] {
+        &self.fields
+    }
+
+    pub fn references(&self) -> &[(Vec<u8>, Resolution)] {
+        &self.references
+    }
+}
+
+/// What a reference field resolved to when its document was populated
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub enum Resolution {
+///     Resolved(Box<Populated>),
+///     NotFound,
+///     Cycle,
+///     DepthReached,
+///     LimitReached,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Resolution {
+    /// The referenced document, populated in turn
+    Resolved(Box<Populated>),
+    /// The referenced database or document does not exist
+    NotFound,
+    /// The referenced document is one of the documents being populated above it
+    Cycle,
+    /// The reference is deeper than the depth the read asked for
+    DepthReached,
+    /// The read already resolved `MAX_POPULATED` documents
+    LimitReached,
+}
@@ -0,0 +1,313 @@
+use crate::{
+    BloomFilter, ColdDocument, Collation, DbMeta, Indexes, MetaEncoding, MetaFile, Partitioning,
+    PrefixIndex, Quarantine, RepoMeta, StorageBackend, Structure, TextIndex, TimeIndex, TtlIndex,
+    TuringDB, TuringDbError, TuringResult, UniqueKey, Views, COLD_EXTENSION,
+};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind, sync::Arc};
+use tai64::TAI64N;
+
+const REMOTE_META_NAME: &str = "REMOTE.meta";
+
+/// The documents a storage backend holds, along with the time of the write each copy reflects.
+/// Kept with the local copy of the repo and pushed last so the backend never lists a document
+/// it does not hold
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// pub(crate) struct RemoteManifest {
+///     documents: BTreeMap<Utf8PathBuf, BTreeMap<Utf8PathBuf, Option<TAI64N>>>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct RemoteManifest {
+    documents: BTreeMap<Utf8PathBuf, BTreeMap<Utf8PathBuf, Option<TAI64N>>>,
+}
+
+/// Keeps the durable copy of a repo in a storage backend while the repo directory acts as a local cache.
+///
+/// A commit pushes the metadata of the repo and an archive of every document written since it was
+/// last pushed. A cache without a repo in it is filled from the backend, documents arrive in the
+/// cold tier and are only rehydrated into sled the first time they are accessed
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct RemoteRepo {
+///     backend: Arc<dyn StorageBackend>,
+///     manifest: Mutex<Option<RemoteManifest>>,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct RemoteRepo {
+    backend: Arc<dyn StorageBackend>,
+    // `None` until the repo has been pushed once, the first push sends every document
+    manifest: Mutex<Option<RemoteManifest>>,
+}
+
+impl RemoteRepo {
+    pub(crate) fn new(backend: Arc<dyn StorageBackend>) -> Self {
+        Self {
+            backend,
+            manifest: Mutex::new(None),
+        }
+    }
+    /// Check whether the backend already holds a repo
+    pub(crate) async fn exists(&self) -> TuringResult<bool> {
+        Ok(self.get(REMOTE_META_NAME).await?.is_some())
+    }
+    /// Fill an empty cache from the backend. A cache that already holds a repo is newer than
+    /// the backend, since the backend only changes when the cache is committed, and is kept as it is
+    pub(crate) async fn pull(
+        &self,
+        repo_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<()> {
+        let mut manifest = self.manifest.lock().await;
+
+        if async_fs::metadata(RepoMeta::path(repo_dir)).await.is_ok() {
+            *manifest =
+                MetaFile::read::<RemoteManifest>(&RemoteRepo::path(repo_dir), quarantine).await?;
+
+            return Ok(());
+        }
+
+        let sealed_manifest = match self.get(REMOTE_META_NAME).await? {
+            // Nothing has been pushed yet
+            None => return Ok(()),
+            Some(sealed_manifest) => sealed_manifest,
+        };
+
+        let repo_meta_key = RemoteRepo::key(repo_dir, &RepoMeta::path(repo_dir))?;
+
+        // The metadata of every database, documents are fetched once their partitioning is known
+        for key in self.list("").await? {
+            if key == REMOTE_META_NAME
+                || key == repo_meta_key
+                || key.ends_with(&format!(".{}", COLD_EXTENSION))
+            {
+                continue;
+            }
+
+            if let Some(sealed) = self.get(&key).await? {
+                RemoteRepo::write_local(&RemoteRepo::local_path(repo_dir, &key), &sealed).await?;
+            }
+        }
+
+        MetaFile::write_sealed(&RemoteRepo::path(repo_dir), &sealed_manifest).await?;
+        let pulled = match MetaFile::read::<RemoteManifest>(&RemoteRepo::path(repo_dir), quarantine)
+            .await?
+        {
+            None => return Err(TuringDbError::Storage("Corrupted remote manifest".into())),
+            Some(pulled) => pulled,
+        };
+
+        for (db_name, documents) in pulled.documents.iter() {
+            let db_dir = TuringDB::build_path(repo_dir, db_name);
+            let partitioning = Partitioning::load(&db_dir, quarantine).await?;
+
+            for document_name in documents.keys() {
+                let sealed = match self
+                    .get(&RemoteRepo::document_key(db_name, document_name))
+                    .await?
+                {
+                    None => {
+                        return Err(TuringDbError::Storage(format!(
+                            "Document `{}/{}` is missing from the backend",
+                            db_name, document_name
+                        )))
+                    }
+                    Some(sealed) => sealed,
+                };
+
+                let document_path = partitioning.document_path(&db_dir, document_name);
+                RemoteRepo::write_local(&ColdDocument::path(&document_path), &sealed).await?;
+            }
+        }
+
+        // The repo metadata comes last so an interrupted pull is started over
+        if let Some(sealed) = self.get(&repo_meta_key).await? {
+            RemoteRepo::write_local(&RepoMeta::path(repo_dir), &sealed).await?;
+        }
+
+        *manifest = Some(pulled);
+
+        Ok(())
+    }
+    /// Push what changed since the last push, the whole repo the first time
+    pub(crate) async fn push(
+        &self,
+        repo_dir: &Utf8Path,
+        dbs: &DashMap<Utf8PathBuf, Arc<TuringDB>>,
+    ) -> TuringResult<()> {
+        let mut manifest = self.manifest.lock().await;
+
+        let every_document = manifest.is_none();
+        let mut pushed = manifest.clone().unwrap_or_default();
+
+        let db_names = dbs
+            .iter()
+            .map(|db| db.key().to_path_buf())
+            .collect::<Vec<Utf8PathBuf>>();
+
+        for db_name in db_names.iter() {
+            let db = match dbs.get(db_name) {
+                None => continue,
+                Some(db) => Arc::clone(db.value()),
+            };
+
+            let documents = if every_document {
+                db.list.documents().await?
+            } else {
+                db.list.loaded().await
+            };
+            let pushed_documents = pushed.documents.entry(db_name.clone()).or_default();
+
+            for (document_name, document) in documents {
+                let last_pushed = pushed_documents.get(&document_name).copied();
+
+                if let Some((last_written, sealed)) = document
+                    .archive_for_push(last_pushed, every_document)
+                    .await?
+                {
+                    self.put(&RemoteRepo::document_key(db_name, &document_name), sealed)
+                        .await?;
+                    pushed_documents.insert(document_name, last_written);
+                }
+            }
+
+            // Documents dropped since the last push
+            let mut dropped = Vec::new();
+            for document_name in pushed_documents.keys() {
+                if !db.list.contains(document_name).await? {
+                    dropped.push(document_name.clone());
+                }
+            }
+            for document_name in dropped {
+                self.delete(&RemoteRepo::document_key(db_name, &document_name))
+                    .await?;
+                pushed_documents.remove(&document_name);
+            }
+
+            let db_dir = TuringDB::build_path(repo_dir, db_name);
+            self.push_file(repo_dir, &DbMeta::path(&db_dir)).await?;
+            self.push_file(repo_dir, &Partitioning::path(&db_dir))
+                .await?;
+            self.push_file(repo_dir, &TimeIndex::path(&db_dir)).await?;
+            self.push_file(repo_dir, &Structure::path(&db_dir)).await?;
+            self.push_file(repo_dir, &Collation::path(&db_dir)).await?;
+            self.push_file(repo_dir, &Indexes::path(&db_dir)).await?;
+            self.push_file(repo_dir, &Indexes::entries_path(&db_dir))
+                .await?;
+            self.push_file(repo_dir, &UniqueKey::path(&db_dir)).await?;
+            self.push_file(repo_dir, &TextIndex::path(&db_dir)).await?;
+            self.push_file(repo_dir, &TextIndex::entries_path(&db_dir))
+                .await?;
+            self.push_file(repo_dir, &TtlIndex::path(&db_dir)).await?;
+            self.push_file(repo_dir, &BloomFilter::path(&db_dir))
+                .await?;
+            self.push_file(repo_dir, &PrefixIndex::path(&db_dir))
+                .await?;
+        }
+
+        // Databases dropped since the last push
+        let dropped = pushed
+            .documents
+            .keys()
+            .filter(|db_name| !dbs.contains_key(*db_name))
+            .cloned()
+            .collect::<Vec<Utf8PathBuf>>();
+        for db_name in dropped {
+            for key in self.list(&format!("{}/", db_name)).await? {
+                self.delete(&key).await?;
+            }
+            pushed.documents.remove(&db_name);
+        }
+
+        self.push_file(repo_dir, &Views::path(repo_dir)).await?;
+        self.push_file(repo_dir, &RepoMeta::path(repo_dir)).await?;
+
+        let sealed_manifest = MetaFile::seal(&MetaEncoding::Bincode.encode(&pushed)?);
+        self.put(REMOTE_META_NAME, sealed_manifest.clone()).await?;
+        MetaFile::write_sealed(&RemoteRepo::path(repo_dir), &sealed_manifest).await?;
+
+        *manifest = Some(pushed);
+
+        Ok(())
+    }
+
+    /// Push a metadata file as it is on disk, under its path relative to the repo directory
+    async fn push_file(&self, repo_dir: &Utf8Path, path: &Utf8Path) -> TuringResult<()> {
+        match async_fs::read(path).await {
+            Ok(sealed) => self.put(&RemoteRepo::key(repo_dir, path)?, sealed).await,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    async fn get(&self, key: &str) -> TuringResult<Option<Vec<u8>>> {
+        let backend = Arc::clone(&self.backend);
+        let key = key.to_owned();
+
+        blocking::unblock(move || backend.get(&key)).await
+    }
+
+    async fn put(&self, key: &str, bytes: Vec<u8>) -> TuringResult<()> {
+        let backend = Arc::clone(&self.backend);
+        let key = key.to_owned();
+
+        blocking::unblock(move || backend.put(&key, &bytes)).await
+    }
+
+    async fn delete(&self, key: &str) -> TuringResult<()> {
+        let backend = Arc::clone(&self.backend);
+        let key = key.to_owned();
+
+        blocking::unblock(move || backend.delete(&key)).await
+    }
+
+    async fn list(&self, prefix: &str) -> TuringResult<Vec<String>> {
+        let backend = Arc::clone(&self.backend);
+        let prefix = prefix.to_owned();
+
+        blocking::unblock(move || backend.list(&prefix)).await
+    }
+
+    async fn write_local(path: &Utf8Path, sealed: &[u8]) -> TuringResult<()> {
+        if let Some(parent) = path.parent() {
+            async_fs::DirBuilder::new()
+                .recursive(true)
+                .create(parent)
+                .await?;
+        }
+
+        MetaFile::write_sealed(path, sealed).await
+    }
+
+    fn key(repo_dir: &Utf8Path, path: &Utf8Path) -> TuringResult<String> {
+        match path.strip_prefix(repo_dir) {
+            Ok(relative) => Ok(relative.as_str().replace('\\', "/")),
+            Err(_) => Err(TuringDbError::Bug("Metadata file outside the repo".into())),
+        }
+    }
+
+    fn document_key(db_name: &Utf8Path, document_name: &Utf8Path) -> String {
+        format!("{}/{}.{}", db_name, document_name, COLD_EXTENSION)
+    }
+
+    fn local_path(repo_dir: &Utf8Path, key: &str) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(key);
+
+        path
+    }
+
+    fn path(repo_dir: &Utf8Path) -> Utf8PathBuf {
+        RemoteRepo::local_path(repo_dir, REMOTE_META_NAME)
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{ErrorCode, LogOp, TuringClient, TuringDbError, TuringEngine, TuringResult, WireError};
+use async_io::Timer;
+use serde::{Deserialize, Serialize};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tai64::TAI64N;
+
+/// The most records a replica reads from the ops log of its primary at a time
+pub const REPLICA_BATCH: usize = 256;
+/// How long a replica that applied every record of its primary waits before asking again by default
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The databases, documents and views of a primary as the writes that create them again,
+/// holding every write of its ops log up to the position `lsn` and none after it.
+/// `None` when the primary has not logged anything yet
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ReplicaSnapshot {
+///     lsn: Option<u64>,
+///     ops: Vec<LogOp>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplicaSnapshot {
+    lsn: Option<u64>,
+    ops: Vec<LogOp>,
+}
+
+impl ReplicaSnapshot {
+    pub(crate) fn new(lsn: Option<u64>, ops: Vec<LogOp>) -> Self {
+        Self { lsn, ops }
+    }
+    /// The position in the ops log of the primary the snapshot was taken at
+    pub fn lsn(&self) -> Option<u64> {
+        self.lsn
+    }
+    /// The writes creating every database with its settings and indexes, its documents and the views
+    pub fn ops(&self) -> &[LogOp] {
+        &self.ops
+    }
+}
+
+/// Keeps a repo a copy of the repo a primary `TuringServer` serves. An empty repo is first seeded
+/// with the documents of the primary, then the records of the ops log of the primary are logged
+/// and applied in the order the primary logged them. A replica that fell behind the records
+/// the primary still holds in its ops log copies the databases and views of the primary again in place of its own.
+/// The repo refuses writes from clients until `TuringEngine::replica_promote`, reads are served
+/// as usual and may lag behind the primary. A client bounds the lag it accepts with
+/// `TuringClient::set_max_staleness`, see `TuringEngine::replica_staleness`
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub struct Replica {
+///     primary: SocketAddr,
+///     admin_token: Option<String>,
+///     poll_interval: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Replica {
+    primary: SocketAddr,
+    // Reading the ops log of the primary is an admin command
+    admin_token: Option<String>,
+    poll_interval: Duration,
+}
+
+impl Replica {
+    /// Follow the primary serving on `primary`
+    pub fn new(primary: SocketAddr) -> Self {
+        Self {
+            primary,
+            admin_token: None,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+        }
+    }
+    /// The admin token of the primary
+    pub fn set_admin_token(mut self, admin_token: &str) -> Self {
+        self.admin_token = Some(admin_token.into());
+
+        self
+    }
+    /// How long the replica waits before asking again once it applied every record of the primary,
+    /// and before reconnecting once the primary cannot be reached
+    pub fn set_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+
+        self
+    }
+
+    pub fn get_primary(&self) -> SocketAddr {
+        self.primary
+    }
+
+    pub fn get_poll_interval(&self) -> Duration {
+        self.poll_interval
+    }
+    /// Follow the primary until the repo is promoted. Failures to reach the primary are retried
+    /// after the poll interval, any other error ends the replica and leaves the repo refusing writes
+    pub async fn run(self, engine: Arc<TuringEngine>) -> TuringResult<()> {
+        engine.replica_follow();
+
+        let mut client = loop {
+            match self.connect().await {
+                Ok(client) => break client,
+                Err(error) if WireError::from(&error).is_retryable() => {
+                    Timer::after(self.poll_interval).await;
+                }
+                Err(error) => return Err(error),
+            }
+        };
+
+        while engine.is_replica() {
+            match self.follow(&mut client, &engine).await {
+                // More records may be waiting
+                Ok(true) => (),
+                Ok(false) => {
+                    Timer::after(self.poll_interval).await;
+                }
+                Err(error) if WireError::from(&error).is_retryable() => {
+                    Timer::after(self.poll_interval).await;
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn connect(&self) -> TuringResult<TuringClient> {
+        let mut client = TuringClient::connect(self.primary).await?;
+        if let Some(admin_token) = &self.admin_token {
+            client.authenticate(admin_token).await?;
+        }
+
+        Ok(client)
+    }
+    /// Apply the next records of the primary, seeding the repo first while it has not logged anything
+    /// and copying it again once compaction removed records it has not read yet.
+    /// `false` once every record the primary logged so far is applied
+    async fn follow(&self, client: &mut TuringClient, engine: &TuringEngine) -> TuringResult<bool> {
+        let after = match engine.replica_lsn().await {
+            Some(lsn) => Some(lsn),
+            None => {
+                Replica::seed(client, engine).await?;
+
+                match engine.replica_lsn().await {
+                    // The primary has not logged anything yet either
+                    None => return Ok(false),
+                    lsn => lsn,
+                }
+            }
+        };
+
+        let asked = TAI64N::now();
+        let records = match client.log_read(after, REPLICA_BATCH).await {
+            Ok(records) => records,
+            // The only error the ops log of the primary is not found with is `LogGap`
+            Err(TuringDbError::Server(error)) if error.get_code() == ErrorCode::NotFound => {
+                Replica::resync(client, engine).await?;
+
+                return Ok(true);
+            }
+            Err(error) => return Err(error),
+        };
+        for record in &records {
+            engine.replica_apply(record).await?;
+        }
+        if records.len() < REPLICA_BATCH {
+            // Every write the primary made before it was asked is applied
+            engine.replica_synced(asked).await;
+        }
+
+        Ok(records.len() == REPLICA_BATCH)
+    }
+    /// Copy every document of the primary into the repo
+    async fn seed(client: &mut TuringClient, engine: &TuringEngine) -> TuringResult<()> {
+        let (lsn, ops) = Replica::copy(client).await?;
+        engine.replica_seed(lsn, ops).await?;
+
+        Ok(())
+    }
+    /// Replace the databases and views of the repo with the ones of the primary
+    async fn resync(client: &mut TuringClient, engine: &TuringEngine) -> TuringResult<()> {
+        let (lsn, ops) = Replica::copy(client).await?;
+        engine.replica_resync(lsn, ops).await?;
+
+        Ok(())
+    }
+    /// The ops creating every database, document and view of the primary, and the position
+    /// in its ops log they hold every write up to
+    async fn copy(client: &mut TuringClient) -> TuringResult<(Option<u64>, Vec<LogOp>)> {
+        let snapshot = client.replica_snapshot().await?;
+
+        Ok((snapshot.lsn, snapshot.ops))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Collation, DataType, Locale, TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps};
+    use camino::{Utf8Path, Utf8PathBuf};
+    use futures_lite::future::block_on;
+
+    fn repo_dir() -> Utf8PathBuf {
+        let mut repo_dir = Utf8PathBuf::from_path_buf(std::env::temp_dir()).unwrap();
+        repo_dir.push(format!("turingdb-replica-{}", fastrand::u64(..)));
+
+        repo_dir
+    }
+
+    async fn open(repo_dir: &Utf8Path) -> TuringEngine {
+        let mut engine = TuringEngine::with_path(repo_dir).await.unwrap();
+        engine.repo_create().await.unwrap();
+        engine.repo_init().await.unwrap();
+
+        engine
+    }
+
+    fn field(value: &str) -> TuringDBFieldOps {
+        TuringDBFieldOps::default()
+            .db("orders")
+            .document("order")
+            .key(b"status")
+            .value(DataType::STRING, value.as_bytes())
+    }
+
+    async fn status(engine: &TuringEngine) -> Vec<u8> {
+        let ops = TuringDBDocumentOps::default()
+            .set_db_name("orders")
+            .set_document_name("order");
+        let fields = engine
+            .document_view(&ops)
+            .await
+            .unwrap()
+            .field_scan()
+            .unwrap();
+
+        match fields.into_iter().find(|(key, _)| key == b"status") {
+            Some((_, field_data)) => field_data.data().to_vec(),
+            None => panic!("The status of the order is missing"),
+        }
+    }
+
+    #[test]
+    fn a_replica_applies_the_ops_log_of_its_primary_and_refuses_writes() {
+        block_on(async {
+            let (primary_dir, replica_dir) = (repo_dir(), repo_dir());
+            let primary = open(&primary_dir).await;
+            let replica = open(&replica_dir).await;
+            replica.replica_follow();
+
+            primary
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            primary
+                .document_create(
+                    &TuringDBDocumentOps::default()
+                        .set_db_name("orders")
+                        .set_document_name("order"),
+                )
+                .await
+                .unwrap();
+            primary.field_insert(&field("placed")).await.unwrap();
+            primary.field_modify(&field("shipped")).await.unwrap();
+
+            let records = primary.log_read(None, REPLICA_BATCH).await.unwrap();
+            assert_eq!(records.len(), 4);
+            assert_eq!(replica.replica_staleness().await, None);
+            for record in records.iter() {
+                replica.replica_apply(record).await.unwrap();
+            }
+            // A record the replica already holds is skipped
+            replica.replica_apply(&records[2]).await.unwrap();
+            assert_eq!(status(&replica).await, b"shipped");
+            assert_eq!(replica.replica_lsn().await, primary.replica_lsn().await);
+            assert!(replica.replica_staleness().await.is_some());
+
+            assert!(matches!(
+                replica.field_modify(&field("lost")).await,
+                Err(TuringDbError::ReadOnlyReplica)
+            ));
+            replica.replica_promote().await.unwrap();
+            replica.field_modify(&field("delivered")).await.unwrap();
+            assert_eq!(status(&replica).await, b"delivered");
+
+            drop((primary, replica));
+            async_fs::remove_dir_all(&primary_dir).await.ok();
+            async_fs::remove_dir_all(&replica_dir).await.ok();
+        });
+    }
+
+    #[test]
+    fn a_replica_behind_the_ops_log_of_its_primary_resyncs_from_a_snapshot() {
+        block_on(async {
+            let (primary_dir, replica_dir) = (repo_dir(), repo_dir());
+            let primary = open(&primary_dir).await;
+            let replica = open(&replica_dir).await;
+            replica.replica_follow();
+
+            primary
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            primary
+                .document_create(
+                    &TuringDBDocumentOps::default()
+                        .set_db_name("orders")
+                        .set_document_name("order"),
+                )
+                .await
+                .unwrap();
+            primary.field_insert(&field("placed")).await.unwrap();
+
+            let snapshot = primary.replica_snapshot().await.unwrap();
+            replica
+                .replica_seed(snapshot.lsn(), snapshot.ops().to_vec())
+                .await
+                .unwrap();
+            assert_eq!(status(&replica).await, b"placed");
+            assert_eq!(replica.replica_lsn().await, primary.replica_lsn().await);
+
+            // The primary compacts away the records the replica has not read yet
+            primary.field_modify(&field("shipped")).await.unwrap();
+            primary
+                .db_set_collation(
+                    &TuringDBOps::default().set_db_name("orders"),
+                    Collation::Locale(Locale::Danish),
+                )
+                .await
+                .unwrap();
+            primary.log_compact().await.unwrap();
+
+            let after = replica.replica_lsn().await;
+            assert!(matches!(
+                primary.log_read(after, REPLICA_BATCH).await,
+                Err(TuringDbError::LogGap)
+            ));
+
+            let snapshot = primary.replica_snapshot().await.unwrap();
+            replica
+                .replica_resync(snapshot.lsn(), snapshot.ops().to_vec())
+                .await
+                .unwrap();
+            assert_eq!(status(&replica).await, b"shipped");
+            assert_eq!(
+                replica
+                    .db_collation(&TuringDBOps::default().set_db_name("orders"))
+                    .await
+                    .unwrap(),
+                Collation::Locale(Locale::Danish)
+            );
+            assert_eq!(replica.replica_lsn().await, primary.replica_lsn().await);
+
+            // The replica follows the ops log of the primary again from the snapshot on
+            primary.field_modify(&field("delivered")).await.unwrap();
+            let records = primary
+                .log_read(replica.replica_lsn().await, REPLICA_BATCH)
+                .await
+                .unwrap();
+            assert_eq!(records.len(), 1);
+            for record in records.iter() {
+                replica.replica_apply(record).await.unwrap();
+            }
+            assert_eq!(status(&replica).await, b"delivered");
+
+            drop((primary, replica));
+            async_fs::remove_dir_all(&primary_dir).await.ok();
+            async_fs::remove_dir_all(&replica_dir).await.ok();
+        });
+    }
+}
@@ -0,0 +1,165 @@
+use crate::{TuringDbError, TuringResult};
+use async_fs::OpenOptions;
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::io::AsyncWriteExt;
+use serde::{Deserialize, Serialize};
+use std::{io::ErrorKind, time::Duration};
+use tai64::TAI64N;
+
+const REPO_LOCK_NAME: &str = "REPO.lock";
+/// A lock file that cannot be decoded is only removed once it has not been written for this long,
+/// as it may belong to a running process that is still writing it
+const UNREADABLE_LOCK_SECS: u64 = 10;
+/// On platforms where the liveness of the owning process cannot be checked,
+/// a lock that has not been refreshed by a commit for this long is considered stale
+#[cfg(not(target_os = "linux"))]
+const STALE_LOCK_SECS: u64 = 24 * 60 * 60;
+
+/// The process holding the repo lock
+/// ```
+/// #[derive(Debug, Serialize, Deserialize)]
+/// struct LockOwner {
+///     pid: u32,
+///     refreshed: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+struct LockOwner {
+    pid: u32,
+    refreshed: TAI64N,
+}
+
+impl LockOwner {
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            refreshed: TAI64N::now(),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_stale(&self) -> bool {
+        !std::path::Path::new(&format!("/proc/{}", self.pid)).exists()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn is_stale(&self) -> bool {
+        match TAI64N::now().duration_since(&self.refreshed) {
+            Ok(elapsed) => elapsed.as_secs() > STALE_LOCK_SECS,
+            Err(_) => false,
+        }
+    }
+}
+
+/// An advisory lock preventing more than one process from opening the same repo.
+/// The lock file is removed when this is dropped
+#[derive(Debug)]
+pub(crate) struct RepoLock {
+    path: Utf8PathBuf,
+}
+
+impl RepoLock {
+    /// Acquire the lock, replacing it if the process that holds it is no longer running
+    pub(crate) async fn acquire(repo_dir: &Utf8Path) -> TuringResult<RepoLock> {
+        let mut path: Utf8PathBuf = repo_dir.into();
+        path.push(REPO_LOCK_NAME);
+
+        loop {
+            // The lock only appears under its name once it is written in full
+            let temp_path = RepoLock::write_temp(&path).await?;
+            let linked = async_fs::hard_link(&temp_path, &path).await;
+            async_fs::remove_file(&temp_path).await?;
+
+            match linked {
+                Ok(_) => return Ok(RepoLock { path }),
+                Err(error) => {
+                    if error.kind() != ErrorKind::AlreadyExists {
+                        return Err(error.into());
+                    }
+                }
+            }
+
+            let contents = match async_fs::read(&path).await {
+                Ok(contents) => contents,
+                Err(error) => {
+                    if error.kind() == ErrorKind::NotFound {
+                        continue;
+                    }
+
+                    return Err(error.into());
+                }
+            };
+
+            match bincode::deserialize::<LockOwner>(&contents) {
+                Ok(holder) => {
+                    if !holder.is_stale() {
+                        return Err(TuringDbError::RepoLocked { pid: holder.pid });
+                    }
+                }
+                Err(_) => {
+                    if !RepoLock::is_abandoned(&path).await? {
+                        blocking::unblock(|| std::thread::sleep(Duration::from_millis(100))).await;
+                        continue;
+                    }
+                }
+            }
+
+            match async_fs::remove_file(&path).await {
+                Ok(_) => (),
+                Err(error) => {
+                    if error.kind() != ErrorKind::NotFound {
+                        return Err(error.into());
+                    }
+                }
+            }
+        }
+    }
+    /// Record that the process holding the lock is still alive
+    pub(crate) async fn refresh(&self) -> TuringResult<()> {
+        let temp_path = RepoLock::write_temp(&self.path).await?;
+        async_fs::rename(&temp_path, &self.path).await?;
+
+        Ok(())
+    }
+    /// Write the current process as the owner into a temporary file next to the lock
+    async fn write_temp(path: &Utf8Path) -> TuringResult<Utf8PathBuf> {
+        let owner = bincode::serialize::<LockOwner>(&LockOwner::current())?;
+        let temp_path = Utf8PathBuf::from(format!("{}.{}.tmp", path, std::process::id()));
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temp_path)
+            .await?;
+        file.write_all(&owner).await?;
+        file.flush().await?;
+        file.sync_all().await?;
+
+        Ok(temp_path)
+    }
+    /// Whether a lock file that cannot be decoded has gone unwritten for long enough to be removed
+    async fn is_abandoned(path: &Utf8Path) -> TuringResult<bool> {
+        let modified = match async_fs::metadata(path).await {
+            Ok(metadata) => metadata.modified()?,
+            Err(error) => {
+                if error.kind() == ErrorKind::NotFound {
+                    return Ok(true);
+                }
+
+                return Err(error.into());
+            }
+        };
+
+        match modified.elapsed() {
+            Ok(elapsed) => Ok(elapsed.as_secs() >= UNREADABLE_LOCK_SECS),
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+impl Drop for RepoLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,562 @@
+use crate::{
+    DataType, OpsOutcome, TDBCell, TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps,
+    TuringDbError, TuringEngine, TuringResult,
+};
+use async_executor::Executor;
+use async_io::Async;
+use futures_lite::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use std::{
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+};
+
+/// The port a `RespGateway` listens on unless another address is set, the one Redis clients expect
+pub const DEFAULT_RESP_PORT: u16 = 6379;
+/// The database holding the keys of a `RespGateway` unless another one is set
+pub const DEFAULT_RESP_DB: &str = "resp";
+/// The field of a document holding the value of its key
+pub const RESP_VALUE_FIELD: &[u8] = b"value";
+/// The largest value a command may carry unless another limit is set
+const DEFAULT_MAX_VALUE_LEN: usize = 4 * 1024 * 1024;
+/// The most arguments a command may have
+const MAX_ARGUMENTS: usize = 1024;
+/// The longest header of an array or a bulk string, digits and line ending included
+const MAX_LINE_LEN: u64 = 32;
+/// How many keys `SCAN` walks unless the client asks for another count
+const DEFAULT_SCAN_COUNT: usize = 10;
+
+/// Serves the documents of one database over the subset of RESP, the protocol of Redis,
+/// that simple key value workloads need. Every key is the name of a document and its value
+/// is kept as `DataType::BINARY` in the `RESP_VALUE_FIELD` field of the document
+/// ```text
+/// GET key                                 the value of the key, nil when it is not set
+/// SET key value                           set the key, replacing every field of its document
+/// DEL key [key ..]                        drop the documents, answered with how many existed
+/// SCAN cursor [MATCH pattern] [COUNT n]   walk the keys in order, the cursor is 0 once done
+/// PING [message]                          PONG, or the message
+/// QUIT                                    close the connection
+/// ```
+/// Keys must be UTF-8. `MATCH` patterns support `*`, `?` and `\` escapes.
+/// Commands are read as arrays of bulk strings, the way client libraries send them,
+/// inline commands are not supported. The database must exist before clients connect.
+/// Errors are `-ERR` followed by the `Debug` output of the `TuringDbError`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct RespGateway {
+///     address: SocketAddr,
+///     db: String,
+///     max_value_len: usize,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RespGateway {
+    address: SocketAddr,
+    db: String,
+    max_value_len: usize,
+}
+
+impl Default for RespGateway {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], DEFAULT_RESP_PORT)),
+            db: DEFAULT_RESP_DB.into(),
+            max_value_len: DEFAULT_MAX_VALUE_LEN,
+        }
+    }
+}
+
+/// A reply before it is written
+#[derive(Debug)]
+enum Reply {
+    Status(&'static str),
+    Error(String),
+    Integer(i64),
+    Bulk(Option<Vec<u8>>),
+    Array(Vec<Reply>),
+}
+
+impl Reply {
+    fn encode(&self, buffer: &mut Vec<u8>) {
+        match self {
+            Reply::Status(status) => {
+                buffer.push(b'+');
+                buffer.extend_from_slice(status.as_bytes());
+            }
+            Reply::Error(error) => {
+                buffer.push(b'-');
+                buffer.extend(error.bytes().map(|byte| {
+                    if byte == b'\r' || byte == b'\n' {
+                        b' '
+                    } else {
+                        byte
+                    }
+                }));
+            }
+            Reply::Integer(integer) => buffer.extend_from_slice(format!(":{}", integer).as_bytes()),
+            Reply::Bulk(None) => buffer.extend_from_slice(b"$-1"),
+            Reply::Bulk(Some(bytes)) => {
+                buffer.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                buffer.extend_from_slice(bytes);
+            }
+            Reply::Array(replies) => {
+                buffer.extend_from_slice(format!("*{}\r\n", replies.len()).as_bytes());
+                for reply in replies {
+                    reply.encode(buffer);
+                }
+
+                return;
+            }
+        }
+
+        buffer.extend_from_slice(b"\r\n");
+    }
+}
+
+impl RespGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+
+        self
+    }
+    /// The database whose documents are the keys
+    pub fn set_db(mut self, db: &str) -> Self {
+        self.db = db.into();
+
+        self
+    }
+    /// The largest key or value a command may carry, a larger one ends the connection
+    pub fn set_max_value_len(mut self, max_value_len: usize) -> Self {
+        self.max_value_len = max_value_len;
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_db(&self) -> &str {
+        &self.db
+    }
+
+    pub fn get_max_value_len(&self) -> usize {
+        self.max_value_len
+    }
+    /// Accept connections until the listener fails, spawning a task on `executor` for each of them.
+    /// A connection that fails only ends its own task
+    pub async fn run<'a>(
+        &self,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        let listener = Async::<TcpListener>::bind(self.address)?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+
+            executor
+                .spawn(self.clone().serve(Arc::clone(&engine), stream))
+                .detach();
+        }
+    }
+    /// Answer the commands of a client in order until it closes the connection.
+    /// A command that cannot be read is answered with a protocol error and ends the connection
+    async fn serve(self, engine: Arc<TuringEngine>, stream: Async<TcpStream>) -> TuringResult<()> {
+        let mut stream = BufReader::new(stream);
+
+        loop {
+            let arguments = match self.read_command(&mut stream).await {
+                Ok(Some(arguments)) => arguments,
+                Ok(None) => return Ok(()),
+                Err(TuringDbError::InvalidInput) | Err(TuringDbError::FrameTooLarge { .. }) => {
+                    let reply = Reply::Error("ERR Protocol error".into());
+
+                    return RespGateway::write_reply(stream.get_mut(), &reply).await;
+                }
+                Err(error) => return Err(error),
+            };
+
+            let quit = match arguments.first() {
+                Some(name) => name.eq_ignore_ascii_case(b"QUIT"),
+                None => false,
+            };
+            let reply = match self.execute(&engine, &arguments).await {
+                Ok(reply) => reply,
+                Err(error) => RespGateway::error(error),
+            };
+            RespGateway::write_reply(stream.get_mut(), &reply).await?;
+
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+    /// Read the arguments of the next command, `None` once the client closed the connection.
+    /// Fails with `InvalidInput` when the command is not an array of bulk strings
+    async fn read_command(
+        &self,
+        stream: &mut BufReader<Async<TcpStream>>,
+    ) -> TuringResult<Option<Vec<Vec<u8>>>> {
+        let count = match RespGateway::read_line(stream).await? {
+            None => return Ok(None),
+            Some(line) => RespGateway::header(&line, b'*')?,
+        };
+        if count > MAX_ARGUMENTS {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        let mut arguments = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = match RespGateway::read_line(stream).await? {
+                None => return Err(TuringDbError::UnexpectedEof),
+                Some(line) => RespGateway::header(&line, b'$')?,
+            };
+            if len > self.max_value_len {
+                return Err(TuringDbError::FrameTooLarge {
+                    len: len as u64,
+                    max: self.max_value_len as u64,
+                });
+            }
+
+            let mut argument = vec![0_u8; len + 2];
+            stream.read_exact(&mut argument).await?;
+            if !argument.ends_with(b"\r\n") {
+                return Err(TuringDbError::InvalidInput);
+            }
+            argument.truncate(len);
+            arguments.push(argument);
+        }
+
+        Ok(Some(arguments))
+    }
+    /// A line without its ending, `None` when the connection closed before it started
+    async fn read_line(stream: &mut BufReader<Async<TcpStream>>) -> TuringResult<Option<Vec<u8>>> {
+        let mut line = Vec::new();
+        (&mut *stream)
+            .take(MAX_LINE_LEN)
+            .read_until(b'\n', &mut line)
+            .await?;
+
+        if line.is_empty() {
+            return Ok(None);
+        }
+        if !line.ends_with(b"\r\n") {
+            return Err(TuringDbError::InvalidInput);
+        }
+        line.truncate(line.len() - 2);
+
+        Ok(Some(line))
+    }
+    /// The length following the `kind` byte of an array or bulk string header
+    fn header(line: &[u8], kind: u8) -> TuringResult<usize> {
+        match line.split_first() {
+            Some((first, digits)) if *first == kind => std::str::from_utf8(digits)
+                .ok()
+                .and_then(|digits| digits.parse::<usize>().ok())
+                .ok_or(TuringDbError::InvalidInput),
+            _ => Err(TuringDbError::InvalidInput),
+        }
+    }
+
+    async fn execute(&self, engine: &TuringEngine, arguments: &[Vec<u8>]) -> TuringResult<Reply> {
+        let (name, arguments) = match arguments.split_first() {
+            Some(command) => command,
+            None => return Ok(Reply::Error("ERR empty command".into())),
+        };
+
+        match (name.to_ascii_uppercase().as_slice(), arguments) {
+            (b"PING", []) => Ok(Reply::Status("PONG")),
+            (b"PING", [message]) => Ok(Reply::Bulk(Some(message.clone()))),
+            (b"QUIT", []) => Ok(Reply::Status("OK")),
+            (b"GET", [key]) => {
+                let ops = TuringDBFieldOps::default()
+                    .db(&self.db)
+                    .document(RespGateway::key(key)?)
+                    .key(RESP_VALUE_FIELD);
+
+                match engine.field_get(&ops).await {
+                    Ok(OpsOutcome::FieldContents(field_data)) => {
+                        Ok(Reply::Bulk(Some(field_data.data().to_vec())))
+                    }
+                    Ok(_) => Err(TuringDbError::Bug(
+                        "Getting a field ran to an outcome other than its contents".into(),
+                    )),
+                    Err(TuringDbError::DocumentNotFound) | Err(TuringDbError::FieldNotFound) => {
+                        Ok(Reply::Bulk(None))
+                    }
+                    Err(error) => Err(error),
+                }
+            }
+            (b"SET", [key, value]) => {
+                engine
+                    .document_upsert(
+                        &self.document_ops(RespGateway::key(key)?),
+                        vec![(
+                            RESP_VALUE_FIELD.to_vec(),
+                            TDBCell::new(DataType::BINARY, value),
+                        )],
+                    )
+                    .await?;
+
+                Ok(Reply::Status("OK"))
+            }
+            (b"DEL", keys) if !keys.is_empty() => {
+                let mut dropped = 0;
+                for key in keys {
+                    // A missing key is not written to the ops log as a drop
+                    let ops = self.document_ops(RespGateway::key(key)?);
+                    if !engine.document_may_exist(&ops).await? {
+                        continue;
+                    }
+
+                    match engine.document_drop(&ops).await {
+                        Ok(_) => dropped += 1,
+                        Err(TuringDbError::DocumentNotFound) => (),
+                        Err(error) => return Err(error),
+                    }
+                }
+
+                Ok(Reply::Integer(dropped))
+            }
+            (b"SCAN", [cursor, options @ ..]) => self.scan(engine, cursor, options).await,
+            (b"PING", _)
+            | (b"QUIT", _)
+            | (b"GET", _)
+            | (b"SET", _)
+            | (b"DEL", _)
+            | (b"SCAN", _) => Ok(Reply::Error(format!(
+                "ERR wrong number of arguments for '{}' command",
+                String::from_utf8_lossy(name).to_lowercase()
+            ))),
+            _ => Ok(Reply::Error(format!(
+                "ERR unknown command '{}'",
+                String::from_utf8_lossy(name)
+            ))),
+        }
+    }
+    /// The cursor is the position of the next key in the sorted names of the documents,
+    /// so keys set or dropped during a scan may be skipped or returned twice
+    async fn scan(
+        &self,
+        engine: &TuringEngine,
+        cursor: &[u8],
+        options: &[Vec<u8>],
+    ) -> TuringResult<Reply> {
+        let cursor = match std::str::from_utf8(cursor)
+            .ok()
+            .and_then(|cursor| cursor.parse::<usize>().ok())
+        {
+            Some(cursor) => cursor,
+            None => return Ok(Reply::Error("ERR invalid cursor".into())),
+        };
+
+        let mut pattern = None;
+        let mut count = DEFAULT_SCAN_COUNT;
+        for option in options.chunks(2) {
+            match option {
+                [name, value] if name.eq_ignore_ascii_case(b"MATCH") => pattern = Some(value),
+                [name, value] if name.eq_ignore_ascii_case(b"COUNT") => {
+                    count = match std::str::from_utf8(value)
+                        .ok()
+                        .and_then(|value| value.parse::<usize>().ok())
+                    {
+                        Some(count) if count > 0 => count,
+                        _ => return Ok(Reply::Error("ERR syntax error".into())),
+                    }
+                }
+                _ => return Ok(Reply::Error("ERR syntax error".into())),
+            }
+        }
+
+        let ops = TuringDBOps::default().set_db_name(&self.db);
+        let documents = match engine.document_list_sorted(&ops).await? {
+            OpsOutcome::DocumentList(documents) => documents,
+            OpsOutcome::DbEmpty => Vec::new(),
+            _ => {
+                return Err(TuringDbError::Bug(
+                    "Listing documents ran to an outcome other than a list".into(),
+                ))
+            }
+        };
+
+        let end = cursor.saturating_add(count).min(documents.len());
+        let keys = documents
+            .get(cursor..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|document| document.as_str().as_bytes())
+            .filter(|key| match pattern {
+                Some(pattern) => RespGateway::glob_matches(pattern, key),
+                None => true,
+            })
+            .map(|key| Reply::Bulk(Some(key.to_vec())))
+            .collect::<Vec<Reply>>();
+        let next = if end < documents.len() { end } else { 0 };
+
+        Ok(Reply::Array(vec![
+            Reply::Bulk(Some(next.to_string().into_bytes())),
+            Reply::Array(keys),
+        ]))
+    }
+
+    async fn write_reply(stream: &mut Async<TcpStream>, reply: &Reply) -> TuringResult<()> {
+        let mut buffer = Vec::new();
+        reply.encode(&mut buffer);
+
+        stream.write_all(&buffer).await?;
+        stream.flush().await?;
+
+        Ok(())
+    }
+
+    fn error(error: TuringDbError) -> Reply {
+        Reply::Error(format!("ERR {:?}", error))
+    }
+
+    fn key(key: &[u8]) -> TuringResult<&str> {
+        std::str::from_utf8(key).map_err(|_| TuringDbError::InvalidInput)
+    }
+
+    fn document_ops(&self, key: &str) -> TuringDBDocumentOps {
+        TuringDBDocumentOps::default()
+            .set_db_name(&self.db)
+            .set_document_name(key)
+    }
+    /// Match a key against a glob pattern where `*` is any run of bytes, `?` any one byte
+    /// and `\` makes the byte after it literal
+    fn glob_matches(pattern: &[u8], key: &[u8]) -> bool {
+        let mut pattern_index = 0;
+        let mut key_index = 0;
+        // The last `*` seen and the byte of the key it would stop before
+        let mut star = None;
+
+        while key_index < key.len() {
+            let step = match pattern.get(pattern_index) {
+                Some(b'*') => {
+                    star = Some((pattern_index, key_index));
+                    pattern_index += 1;
+                    continue;
+                }
+                Some(b'?') => 1,
+                Some(b'\\')
+                    if pattern_index + 1 < pattern.len()
+                        && pattern[pattern_index + 1] == key[key_index] =>
+                {
+                    2
+                }
+                Some(b'\\') if pattern_index + 1 < pattern.len() => 0,
+                Some(byte) if *byte == key[key_index] => 1,
+                _ => 0,
+            };
+
+            if step > 0 {
+                pattern_index += step;
+                key_index += 1;
+                continue;
+            }
+
+            match star {
+                Some((star_index, star_key_index)) => {
+                    pattern_index = star_index + 1;
+                    key_index = star_key_index + 1;
+                    star = Some((star_index, key_index));
+                }
+                None => return false,
+            }
+        }
+
+        pattern[pattern_index..].iter().all(|byte| *byte == b'*')
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::block_on;
+
+    /// The reply to a command as it is written to the client
+    async fn send(gateway: &RespGateway, engine: &TuringEngine, command: &[&str]) -> Vec<u8> {
+        let arguments = command
+            .iter()
+            .map(|argument| argument.as_bytes().to_vec())
+            .collect::<Vec<Vec<u8>>>();
+        let reply = match gateway.execute(engine, &arguments).await {
+            Ok(reply) => reply,
+            Err(error) => RespGateway::error(error),
+        };
+
+        let mut buffer = Vec::new();
+        reply.encode(&mut buffer);
+
+        buffer
+    }
+
+    #[test]
+    fn keys_are_set_read_scanned_and_deleted() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            let gateway = RespGateway::new();
+            engine
+                .db_create(TuringDBOps::default().set_db_name(DEFAULT_RESP_DB))
+                .await
+                .unwrap();
+
+            assert_eq!(send(&gateway, &engine, &["PING"]).await, b"+PONG\r\n");
+            assert_eq!(
+                send(&gateway, &engine, &["GET", "user:1"]).await,
+                b"$-1\r\n"
+            );
+            for (key, value) in [("user:1", "ada"), ("user:2", "alan"), ("order:1", "tea")].iter() {
+                assert_eq!(
+                    send(&gateway, &engine, &["SET", key, value]).await,
+                    b"+OK\r\n"
+                );
+            }
+            assert_eq!(
+                send(&gateway, &engine, &["SET", "user:1", "grace"]).await,
+                b"+OK\r\n"
+            );
+            assert_eq!(
+                send(&gateway, &engine, &["GET", "user:1"]).await,
+                b"$5\r\ngrace\r\n"
+            );
+
+            assert_eq!(
+                send(&gateway, &engine, &["SCAN", "0", "MATCH", "user:*"]).await,
+                b"*2\r\n$1\r\n0\r\n*2\r\n$6\r\nuser:1\r\n$6\r\nuser:2\r\n".to_vec()
+            );
+            // The cursor is where the next call starts, 0 once every key was walked
+            assert_eq!(
+                send(&gateway, &engine, &["SCAN", "0", "COUNT", "2"]).await,
+                b"*2\r\n$1\r\n2\r\n*2\r\n$7\r\norder:1\r\n$6\r\nuser:1\r\n".to_vec()
+            );
+
+            assert_eq!(
+                send(&gateway, &engine, &["DEL", "user:1", "user:3", "order:1"]).await,
+                b":2\r\n"
+            );
+            assert_eq!(
+                send(&gateway, &engine, &["GET", "order:1"]).await,
+                b"$-1\r\n"
+            );
+            assert_eq!(
+                send(&gateway, &engine, &["GET"]).await,
+                b"-ERR wrong number of arguments for 'get' command\r\n".to_vec()
+            );
+        });
+    }
+
+    #[test]
+    fn patterns_match_like_redis_globs() {
+        assert!(RespGateway::glob_matches(b"user:*", b"user:1"));
+        assert!(RespGateway::glob_matches(b"*:?", b"order:7"));
+        assert!(!RespGateway::glob_matches(b"*:?", b"order:17"));
+        assert!(RespGateway::glob_matches(b"a\\*b", b"a*b"));
+        assert!(!RespGateway::glob_matches(b"a\\*b", b"axb"));
+        assert!(RespGateway::glob_matches(b"*", b""));
+    }
+}
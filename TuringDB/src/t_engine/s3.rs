@@ -0,0 +1,242 @@
+use crate::{StorageBackend, TuringDbError, TuringResult};
+use rusty_s3::{actions::ListObjectsV2, Bucket, Credentials, S3Action, UrlStyle};
+use std::{io::Read, time::Duration};
+
+/// How long a signed request stays valid, requests are signed right before they are sent
+const SIGNATURE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Where an S3 compatible bucket is and how to reach it
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct S3Config {
+///     endpoint: String,
+///     region: String,
+///     bucket: String,
+///     access_key: String,
+///     secret_key: String,
+///     prefix: String,
+///     path_style: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct S3Config {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    // Prepended to every key so several repos can share a bucket
+    prefix: String,
+    // Address the bucket as part of the path instead of the host name, as most self hosted stores expect
+    path_style: bool,
+}
+
+impl S3Config {
+    pub fn new(endpoint: &str, region: &str, bucket: &str) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            region: region.into(),
+            bucket: bucket.into(),
+            access_key: String::default(),
+            secret_key: String::default(),
+            prefix: String::default(),
+            path_style: false,
+        }
+    }
+
+    pub fn set_credentials(mut self, access_key: &str, secret_key: &str) -> Self {
+        self.access_key = access_key.into();
+        self.secret_key = secret_key.into();
+
+        self
+    }
+    /// Keep the repo under `prefix` inside the bucket
+    pub fn set_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.trim_matches('/').into();
+
+        self
+    }
+    /// Address the bucket as `endpoint/bucket` rather than `bucket.endpoint`
+    pub fn set_path_style(mut self) -> Self {
+        self.path_style = true;
+
+        self
+    }
+
+    pub fn get_endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    pub fn get_region(&self) -> &str {
+        &self.region
+    }
+
+    pub fn get_bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+}
+
+/// Keeps the objects of a repo in an S3 compatible bucket
+/// ```
+/// #[derive(Debug)]
+/// pub struct S3Backend {
+///     bucket: Bucket,
+///     credentials: Credentials,
+///     prefix: String,
+///     agent: ureq::Agent,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct S3Backend {
+    bucket: Bucket,
+    credentials: Credentials,
+    prefix: String,
+    agent: ureq::Agent,
+}
+
+impl S3Backend {
+    pub fn new(config: &S3Config) -> TuringResult<Self> {
+        let endpoint = match config.endpoint.parse() {
+            Ok(endpoint) => endpoint,
+            Err(_) => return Err(TuringDbError::InvalidInput),
+        };
+        let url_style = if config.path_style {
+            UrlStyle::Path
+        } else {
+            UrlStyle::VirtualHost
+        };
+
+        let bucket = match Bucket::new(
+            endpoint,
+            url_style,
+            config.bucket.clone(),
+            config.region.clone(),
+        ) {
+            Ok(bucket) => bucket,
+            Err(error) => return Err(TuringDbError::Storage(error.to_string())),
+        };
+
+        Ok(Self {
+            bucket,
+            credentials: Credentials::new(config.access_key.clone(), config.secret_key.clone()),
+            prefix: config.prefix.clone(),
+            agent: ureq::Agent::new(),
+        })
+    }
+
+    fn object_key(&self, key: &str) -> String {
+        if self.prefix.is_empty() {
+            key.into()
+        } else {
+            format!("{}/{}", self.prefix, key)
+        }
+    }
+
+    fn repo_key<'a>(&self, object_key: &'a str) -> &'a str {
+        if self.prefix.is_empty() {
+            object_key
+        } else {
+            object_key
+                .strip_prefix(&self.prefix)
+                .map(|key| key.trim_start_matches('/'))
+                .unwrap_or(object_key)
+        }
+    }
+
+    fn request_error(error: ureq::Error) -> TuringDbError {
+        match error {
+            ureq::Error::Status(status, response) => {
+                TuringDbError::Storage(format!("{} {}", status, response.status_text()))
+            }
+            ureq::Error::Transport(transport) => TuringDbError::Storage(transport.to_string()),
+        }
+    }
+}
+
+impl StorageBackend for S3Backend {
+    fn get(&self, key: &str) -> TuringResult<Option<Vec<u8>>> {
+        let url = self
+            .bucket
+            .get_object(Some(&self.credentials), &self.object_key(key))
+            .sign(SIGNATURE_TTL);
+
+        match self.agent.get(url.as_str()).call() {
+            Ok(response) => {
+                let mut bytes = Vec::new();
+                response.into_reader().read_to_end(&mut bytes)?;
+
+                Ok(Some(bytes))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(error) => Err(S3Backend::request_error(error)),
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> TuringResult<()> {
+        let url = self
+            .bucket
+            .put_object(Some(&self.credentials), &self.object_key(key))
+            .sign(SIGNATURE_TTL);
+
+        match self.agent.put(url.as_str()).send_bytes(bytes) {
+            Ok(_) => Ok(()),
+            Err(error) => Err(S3Backend::request_error(error)),
+        }
+    }
+
+    fn delete(&self, key: &str) -> TuringResult<()> {
+        let url = self
+            .bucket
+            .delete_object(Some(&self.credentials), &self.object_key(key))
+            .sign(SIGNATURE_TTL);
+
+        match self.agent.delete(url.as_str()).call() {
+            Ok(_) | Err(ureq::Error::Status(404, _)) => Ok(()),
+            Err(error) => Err(S3Backend::request_error(error)),
+        }
+    }
+
+    fn list(&self, prefix: &str) -> TuringResult<Vec<String>> {
+        let object_prefix = self.object_key(prefix);
+        let mut keys = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut action = self.bucket.list_objects_v2(Some(&self.credentials));
+            action.query_mut().insert("prefix", object_prefix.as_str());
+            if let Some(token) = &continuation_token {
+                action
+                    .query_mut()
+                    .insert("continuation-token", token.as_str());
+            }
+            let url = action.sign(SIGNATURE_TTL);
+
+            let body = match self.agent.get(url.as_str()).call() {
+                Ok(response) => response.into_string()?,
+                Err(error) => return Err(S3Backend::request_error(error)),
+            };
+            let listed = match ListObjectsV2::parse_response(&body) {
+                Ok(listed) => listed,
+                Err(error) => return Err(TuringDbError::Storage(error.to_string())),
+            };
+
+            keys.extend(
+                listed
+                    .contents
+                    .iter()
+                    .map(|object| self.repo_key(&object.key).to_owned()),
+            );
+
+            match listed.next_continuation_token {
+                Some(token) => continuation_token = Some(token),
+                None => break,
+            }
+        }
+
+        Ok(keys)
+    }
+}
@@ -1,57 +1,494 @@
-// Get structure from file instead of making it a `pub` type
-#[allow(unused_variables)]
-#[derive(Debug, Serialize, Deserialize)]
-enum Structure {
+use crate::{
+    DataType, FieldData, MetaEncoding, MetaFile, Quarantine, TDBCell, TuringDbError, TuringResult,
+    Value, VectorSpace,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use tai64::TAI64N;
+
+const STRUCTURE_META_NAME: &str = "STRUCTURE.meta";
+
+/// How the fields of the documents in a database are laid out
+/// ```
+/// #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Structure {
+///     #[default]
+///     Schemaless,
+///     Schema(Schema),
+///     Vector(VectorSpace),
+/// }
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Structure {
+    /// A document holds any fields of any type
+    #[default]
     Schemaless,
-    Schema,
-    Vector,
+    /// Every write to a document is checked against the schema before it is logged
+    Schema(Schema),
+    /// Every document holds an embedding that nearest neighbour searches are run against
+    Vector(VectorSpace),
 }
-enum SchemaType {
-    /// fields `MUST` contain values `field_name: DataType`
-    StructType,
-    /// fields `CAN` contain values `field_name(DataType)` or `field_name{_: DataType`
-    EnumType,
-    TupleStructType,
-    VecType(DataType),
-    ArrayType {
-        length: usize,
-        data: DataType,
-    },
-    //TODO std::collections
+
+/// The fields the documents of a database hold
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct Schema {
+///     fields: Vec<SchemaField>,
+///     closed: bool,
+///     coerce: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schema {
+    fields: Vec<SchemaField>,
+    // Fields the schema does not declare are rejected
+    closed: bool,
+    // Numeric values are converted to the declared numeric type when they fit it
+    coerce: bool,
+}
+
+impl Schema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_field(mut self, field: SchemaField) -> Self {
+        self.fields.push(field);
+
+        self
+    }
+    /// Reject fields the schema does not declare
+    pub fn set_closed(mut self, closed: bool) -> Self {
+        self.closed = closed;
+
+        self
+    }
+    /// Convert a numeric value written to a numeric field of another type to the declared type
+    /// instead of rejecting it, as long as the value fits the declared type
+    pub fn set_coerce(mut self, coerce: bool) -> Self {
+        self.coerce = coerce;
+
+        self
+    }
+
+    pub fn get_fields(&self) -> &[SchemaField] {
+        &self.fields
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    pub fn is_coerce(&self) -> bool {
+        self.coerce
+    }
+
+    fn field(&self, key: &[u8]) -> Option<&SchemaField> {
+        self.fields.iter().find(|field| field.key == key)
+    }
+    /// The contents of a numeric value encoded as `data_type`, `None` if it does not fit
+    fn coerce(value: &TDBCell, data_type: DataType) -> Option<Vec<u8>> {
+        let value = Value::from_field(&FieldData::new_at(
+            value.get_data_type(),
+            value.get_data(),
+            TAI64N::now(),
+        ));
+
+        let float = match value {
+            Value::Int(int) => int as f64,
+            Value::UInt(uint) => uint as f64,
+            Value::Float(float) => float,
+            _ => return None,
+        };
+        let int = match value {
+            Value::Int(int) => Some(int),
+            Value::UInt(uint) => i128::try_from(uint).ok(),
+            Value::Float(float) if float.fract() == 0.0 && float.abs() < i128::MAX as f64 => {
+                Some(float as i128)
+            }
+            _ => None,
+        };
+
+        match data_type {
+            DataType::F32 => Some((float as f32).to_le_bytes().to_vec()),
+            DataType::F64 => Some(float.to_le_bytes().to_vec()),
+            DataType::U8 => u8::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::U16 => u16::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::U32 => u32::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::U64 => u64::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::U128 => match value {
+                Value::UInt(uint) => Some(uint.to_le_bytes().to_vec()),
+                _ => u128::try_from(int?)
+                    .ok()
+                    .map(|int| int.to_le_bytes().to_vec()),
+            },
+            DataType::I8 => i8::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::I16 => i16::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::I32 => i32::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::I64 => i64::try_from(int?)
+                .ok()
+                .map(|int| int.to_le_bytes().to_vec()),
+            DataType::I128 => Some(int?.to_le_bytes().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+/// A field declared by a schema
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct SchemaField {
+///     key: Vec<u8>,
+///     data_type: DataType,
+///     required: bool,
+///     default: Option<TDBCell>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaField {
+    key: Vec<u8>,
+    data_type: DataType,
+    required: bool,
+    default: Option<TDBCell>,
 }
 
-struct List;
-
-enum DataType {
-    Char,
-    CoW,
-    StaticStr,
-    Usize,
-    Isize,
-    U8,
-    U16,
-    U32,
-    U64,
-    U128,
-    I8,
-    I16,
-    I32,
-    I64,
-    I128,
-    F32,
-    F64,
-    Bool(BoolFormat),
-    DateTimeType(DateTimeFormat),
+impl SchemaField {
+    pub fn new(key: &[u8], data_type: DataType) -> Self {
+        Self {
+            key: key.into(),
+            data_type,
+            required: false,
+            default: None,
+        }
+    }
+    /// Every document must hold the field, it can not be removed
+    pub fn set_required(mut self, required: bool) -> Self {
+        self.required = required;
+
+        self
+    }
+    /// The value a new document, or a document replaced without the field, holds for the field
+    pub fn set_default(mut self, default: TDBCell) -> Self {
+        self.default = Some(default);
+
+        self
+    }
+
+    pub fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn get_data_type(&self) -> DataType {
+        self.data_type
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.required
+    }
+
+    pub fn get_default(&self) -> Option<&TDBCell> {
+        self.default.as_ref()
+    }
 }
 
-enum DateTimeFormat {
-    TAI64NType,
-    TAI64Type,
-    UTC,
-    LocalUTC,
+/// How a write breaks the schema of a database, returned in `TuringDbError::SchemaViolation`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub enum SchemaViolation {
+///     MissingField { key: Vec<u8> },
+///     UndeclaredField { key: Vec<u8> },
+///     TypeMismatch { key: Vec<u8>, expected: DataType, found: DataType },
+///     DuplicateField { key: Vec<u8> },
+///     DimensionMismatch { key: Vec<u8>, expected: u32, found: u32 },
+///     Streamed { key: Vec<u8> },
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum SchemaViolation {
+    /// A required field is missing or would be removed
+    MissingField { key: Vec<u8> },
+    /// The schema is closed and does not declare the field
+    UndeclaredField { key: Vec<u8> },
+    /// The value of the field is not of the declared type and could not be coerced to it
+    TypeMismatch {
+        key: Vec<u8>,
+        expected: DataType,
+        found: DataType,
+    },
+    /// The schema declares the field more than once
+    DuplicateField { key: Vec<u8> },
+    /// The embedding does not have as many dimensions as the vector space
+    DimensionMismatch {
+        key: Vec<u8>,
+        expected: u32,
+        found: u32,
+    },
+    /// The field can not be written as a stream
+    Streamed { key: Vec<u8> },
 }
 
-enum BoolFormat {
-    True,
-    False,
+impl Structure {
+    /// Check that a schema declares each field once and that every default is of the declared type,
+    /// and that a vector space has dimensions
+    pub(crate) fn check(&self) -> TuringResult<()> {
+        let schema = match self {
+            Structure::Schemaless => return Ok(()),
+            Structure::Schema(schema) => schema,
+            Structure::Vector(space) if space.is_valid() => return Ok(()),
+            Structure::Vector(_) => return Err(TuringDbError::InvalidInput),
+        };
+
+        let mut violations = Vec::new();
+        for (index, field) in schema.fields.iter().enumerate() {
+            if schema.fields[..index]
+                .iter()
+                .any(|declared| declared.key == field.key)
+            {
+                violations.push(SchemaViolation::DuplicateField {
+                    key: field.key.clone(),
+                });
+            }
+
+            if let Some(default) = &field.default {
+                if default.get_data_type() != field.data_type {
+                    violations.push(SchemaViolation::TypeMismatch {
+                        key: field.key.clone(),
+                        expected: field.data_type,
+                        found: default.get_data_type(),
+                    });
+                }
+            }
+        }
+
+        Structure::outcome(violations)
+    }
+    /// Check a value written to a single field, returning it coerced to the declared type if the schema coerces
+    pub(crate) fn check_value(&self, key: &[u8], value: TDBCell) -> TuringResult<TDBCell> {
+        let outcome = match self {
+            Structure::Schemaless => Ok(value),
+            Structure::Schema(schema) => Structure::value(schema, key, value),
+            Structure::Vector(space) => {
+                match space.check_embedding(key, value.get_data_type(), value.get_data()) {
+                    None => Ok(value),
+                    Some(violation) => Err(violation),
+                }
+            }
+        };
+
+        match outcome {
+            Ok(value) => Ok(value),
+            Err(violation) => Err(TuringDbError::SchemaViolation(vec![violation])),
+        }
+    }
+    /// Check every field of a document written as a whole, returning the fields coerced to their declared
+    /// types followed by the defaults of the declared fields missing from them. Every violation is reported at once
+    pub(crate) fn check_fields(
+        &self,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    ) -> TuringResult<Vec<(Vec<u8>, TDBCell)>> {
+        let schema = match self {
+            Structure::Schemaless => return Ok(fields),
+            Structure::Schema(schema) => schema,
+            Structure::Vector(space) => {
+                Structure::outcome(
+                    fields
+                        .iter()
+                        .filter_map(|(key, value)| {
+                            space.check_embedding(key, value.get_data_type(), value.get_data())
+                        })
+                        .collect(),
+                )?;
+
+                return Ok(fields);
+            }
+        };
+
+        let mut checked = Vec::with_capacity(fields.len());
+        let mut violations = Vec::new();
+        for (key, value) in fields {
+            match Structure::value(schema, &key, value) {
+                Ok(value) => checked.push((key, value)),
+                Err(violation) => violations.push(violation),
+            }
+        }
+
+        for field in &schema.fields {
+            if checked.iter().any(|(key, _)| *key == field.key)
+                || violations.iter().any(|violation| match violation {
+                    SchemaViolation::TypeMismatch { key, .. } => *key == field.key,
+                    _ => false,
+                })
+            {
+                continue;
+            }
+
+            match &field.default {
+                Some(default) => checked.push((field.key.clone(), default.clone())),
+                None if field.required => violations.push(SchemaViolation::MissingField {
+                    key: field.key.clone(),
+                }),
+                None => (),
+            }
+        }
+
+        Structure::outcome(violations)?;
+
+        Ok(checked)
+    }
+    /// Check the fields a write leaves in a document without coercing them, `None` for a field it removes
+    pub(crate) fn check_written<'a>(
+        &self,
+        written: impl IntoIterator<Item = (&'a [u8], Option<&'a FieldData>)>,
+    ) -> TuringResult<()> {
+        let violations = match self {
+            Structure::Schemaless => return Ok(()),
+            Structure::Schema(schema) => written
+                .into_iter()
+                .filter_map(|(key, field_data)| {
+                    Structure::written(schema, key, field_data.map(FieldData::data_type))
+                })
+                .collect(),
+            Structure::Vector(space) => written
+                .into_iter()
+                .filter_map(|(key, field_data)| {
+                    field_data.and_then(|field_data| {
+                        space.check_embedding(key, field_data.data_type(), field_data.data())
+                    })
+                })
+                .collect(),
+        };
+
+        Structure::outcome(violations)
+    }
+    /// Check a field about to be written as a stream, only its type is known before it is written
+    pub(crate) fn check_stream(&self, key: &[u8], data_type: DataType) -> TuringResult<()> {
+        let violation = match self {
+            Structure::Schemaless => None,
+            Structure::Schema(schema) => Structure::written(schema, key, Some(data_type)),
+            Structure::Vector(space) if key == space.get_key() => {
+                Some(SchemaViolation::Streamed { key: key.into() })
+            }
+            Structure::Vector(_) => None,
+        };
+
+        Structure::outcome(violation.into_iter().collect())
+    }
+    /// Check that a document created or replaced as a whole holds every required field,
+    /// `holds` tells whether the document holds a field
+    pub(crate) fn check_required<F>(&self, holds: F) -> TuringResult<()>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        let schema = match self {
+            Structure::Schemaless | Structure::Vector(_) => return Ok(()),
+            Structure::Schema(schema) => schema,
+        };
+
+        let violations = schema
+            .fields
+            .iter()
+            .filter(|field| field.required && !holds(&field.key))
+            .map(|field| SchemaViolation::MissingField {
+                key: field.key.clone(),
+            })
+            .collect::<Vec<SchemaViolation>>();
+
+        Structure::outcome(violations)
+    }
+
+    fn value(schema: &Schema, key: &[u8], value: TDBCell) -> Result<TDBCell, SchemaViolation> {
+        let field = match schema.field(key) {
+            None if schema.closed => {
+                return Err(SchemaViolation::UndeclaredField { key: key.into() })
+            }
+            None => return Ok(value),
+            Some(field) => field,
+        };
+
+        if value.get_data_type() == field.data_type {
+            return Ok(value);
+        }
+        if schema.coerce {
+            if let Some(data) = Schema::coerce(&value, field.data_type) {
+                return Ok(TDBCell::new(field.data_type, &data));
+            }
+        }
+
+        Err(SchemaViolation::TypeMismatch {
+            key: key.into(),
+            expected: field.data_type,
+            found: value.get_data_type(),
+        })
+    }
+
+    fn written(
+        schema: &Schema,
+        key: &[u8],
+        data_type: Option<DataType>,
+    ) -> Option<SchemaViolation> {
+        match (schema.field(key), data_type) {
+            (None, Some(_)) if schema.closed => {
+                Some(SchemaViolation::UndeclaredField { key: key.into() })
+            }
+            (Some(field), None) if field.required => {
+                Some(SchemaViolation::MissingField { key: key.into() })
+            }
+            (Some(field), Some(found)) if found != field.data_type => {
+                Some(SchemaViolation::TypeMismatch {
+                    key: key.into(),
+                    expected: field.data_type,
+                    found,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn outcome(violations: Vec<SchemaViolation>) -> TuringResult<()> {
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(TuringDbError::SchemaViolation(violations))
+        }
+    }
+    /// Read the structure of a database, a database without a structure file is schemaless
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Structure> {
+        match MetaFile::read::<Structure>(&Structure::path(db_dir), quarantine).await? {
+            Some(structure) => Ok(structure),
+            None => Ok(Structure::Schemaless),
+        }
+    }
+
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(
+            &Structure::path(db_dir),
+            &MetaEncoding::Bincode.encode(self)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(STRUCTURE_META_NAME);
+
+        path
+    }
 }
@@ -0,0 +1,137 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tai64::TAI64N;
+
+/// A problem the scrubber found with a persisted file
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum IntegrityIssue {
+    /// A metadata file did not match its checksum or could not be decoded
+    MetaCorrupted,
+    /// sled reported the document as corrupted
+    DocumentCorrupted,
+    /// The stored bytes of a field could not be decompressed or deserialized
+    FieldUndecodable { key: Vec<u8> },
+    /// A chunk of a streamed field did not match its checksum.
+    /// `key` is `None` for chunks of a stream that was never linked to a field
+    ChunkCorrupted { key: Option<Vec<u8>>, chunk: u64 },
+}
+
+/// A single finding of the scrubber
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct IntegrityFinding {
+///     path: Utf8PathBuf,
+///     issue: IntegrityIssue,
+///     quarantined: Option<Utf8PathBuf>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct IntegrityFinding {
+    path: Utf8PathBuf,
+    issue: IntegrityIssue,
+    quarantined: Option<Utf8PathBuf>,
+}
+
+impl IntegrityFinding {
+    pub(crate) fn new(path: &Utf8Path, issue: IntegrityIssue) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            issue,
+            quarantined: None,
+        }
+    }
+
+    pub(crate) fn set_quarantined(mut self, quarantined: Utf8PathBuf) -> Self {
+        self.quarantined = Some(quarantined);
+
+        self
+    }
+    /// The file or document the issue was found in
+    pub fn path(&self) -> &Utf8Path {
+        &self.path
+    }
+    /// What is wrong with it
+    pub fn issue(&self) -> &IntegrityIssue {
+        &self.issue
+    }
+    /// Where the file was moved to if it was quarantined
+    pub fn quarantined(&self) -> Option<&Utf8Path> {
+        self.quarantined.as_deref()
+    }
+}
+
+/// The outcome of a full pass of the integrity scrubber
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct IntegrityReport {
+///     started: TAI64N,
+///     finished: TAI64N,
+///     files_checked: usize,
+///     documents_checked: usize,
+///     findings: Vec<IntegrityFinding>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    started: TAI64N,
+    finished: TAI64N,
+    files_checked: usize,
+    documents_checked: usize,
+    findings: Vec<IntegrityFinding>,
+}
+
+impl IntegrityReport {
+    pub(crate) fn new() -> Self {
+        let started = TAI64N::now();
+
+        Self {
+            started,
+            finished: started,
+            files_checked: 0,
+            documents_checked: 0,
+            findings: Vec::new(),
+        }
+    }
+
+    pub(crate) fn file_checked(&mut self) {
+        self.files_checked += 1;
+    }
+
+    pub(crate) fn document_checked(&mut self) {
+        self.documents_checked += 1;
+    }
+
+    pub(crate) fn record(&mut self, finding: IntegrityFinding) {
+        self.findings.push(finding);
+    }
+
+    pub(crate) fn finish(mut self) -> Self {
+        self.finished = TAI64N::now();
+
+        self
+    }
+    /// The time the pass started
+    pub fn started(&self) -> TAI64N {
+        self.started
+    }
+    /// The time the pass finished
+    pub fn finished(&self) -> TAI64N {
+        self.finished
+    }
+    /// The number of metadata files checked
+    pub fn files_checked(&self) -> usize {
+        self.files_checked
+    }
+    /// The number of documents checked
+    pub fn documents_checked(&self) -> usize {
+        self.documents_checked
+    }
+    /// Every problem found during the pass
+    pub fn findings(&self) -> &[IntegrityFinding] {
+        &self.findings
+    }
+    /// Check whether the pass found nothing wrong
+    pub fn is_healthy(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
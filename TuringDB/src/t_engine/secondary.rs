@@ -0,0 +1,1218 @@
+use crate::{
+    Collation, Document, FieldSource, Filter, GeoBox, GeoPoint, IndexStats, LazyDocument,
+    MetaEncoding, MetaFile, Quarantine, SortOrder, TuringDbError, TuringResult, Value,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ops::Bound,
+    sync::Arc,
+};
+use tai64::TAI64N;
+
+const INDEXES_META_NAME: &str = "INDEXES.meta";
+const INDEX_ENTRIES_META_NAME: &str = "INDEX_ENTRIES.meta";
+
+/// The values an index holds of a document, one for each field of the index in the order of the fields.
+/// `None` for a field the document does not hold. An inverted index holds each element of its field instead,
+/// a geo index the latitude and the longitude of the point its field holds
+type Entry = Vec<Option<Value>>;
+
+/// How an index holds its documents. An ordered index keeps them in the order of the values of its
+/// fields and serves ranges, prefixes and sorts. A hash index only finds the documents holding a value
+/// of each of its fields, but finds them however many documents it holds without walking an order.
+/// An inverted index is over a single `ARRAY` field, such as tags, and holds each document under every
+/// element of it so it finds the documents containing a value. A geo index is over a single `GeoPoint`
+/// field and keeps the points by cell along a Z-order curve so it finds the points near a point or in an area
+/// ```
+/// #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub enum IndexKind {
+///     Hash,
+///     #[default]
+///     Ordered,
+///     Inverted,
+///     Geo,
+/// }
+/// ```
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum IndexKind {
+    Hash,
+    #[default]
+    Ordered,
+    Inverted,
+    Geo,
+}
+
+/// How an index was declared, along with the predicate of a partial index: it only holds the documents
+/// matching the predicate, so it is smaller and cheaper to keep up to date, but only serves filters
+/// requiring what the predicate requires
+/// ```
+/// #[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+/// pub(crate) struct IndexDeclaration {
+///     kind: IndexKind,
+///     predicate: Option<Filter>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct IndexDeclaration {
+    kind: IndexKind,
+    predicate: Option<Filter>,
+}
+
+impl IndexDeclaration {
+    pub(crate) fn new(kind: IndexKind) -> Self {
+        Self {
+            kind,
+            predicate: None,
+        }
+    }
+
+    pub(crate) fn set_predicate(mut self, predicate: Filter) -> Self {
+        self.predicate = Some(predicate);
+
+        self
+    }
+
+    pub(crate) fn get_kind(&self) -> IndexKind {
+        self.kind
+    }
+
+    pub(crate) fn get_predicate(&self) -> Option<&Filter> {
+        self.predicate.as_ref()
+    }
+    /// Whether a document belongs in the index
+    fn holds<S: FieldSource + ?Sized>(&self, source: &S) -> TuringResult<bool> {
+        match self.predicate.as_ref() {
+            None => Ok(true),
+            Some(predicate) => predicate.matches(source),
+        }
+    }
+    /// Whether the index holds every document a filter matches when comparing text by `collation`:
+    /// the filter requires what the predicate requires. The predicate compares text by its bytes,
+    /// so a predicate over a range of text only serves filters that do too
+    fn serves(&self, filter: &Filter, collation: Collation) -> bool {
+        match self.predicate.as_ref() {
+            None => true,
+            Some(predicate) => {
+                IndexDeclaration::implies(filter, predicate)
+                    && (collation == Collation::Binary || !IndexDeclaration::collates(predicate))
+            }
+        }
+    }
+    /// Whether every document `filter` matches also matches `predicate`. Only the conditions a filter
+    /// requires all of are recognized, a predicate the filter implies in any other way is not
+    fn implies(filter: &Filter, predicate: &Filter) -> bool {
+        match (filter, predicate) {
+            (_, Filter::All) => true,
+            (_, Filter::And(predicates)) => predicates
+                .iter()
+                .all(|predicate| IndexDeclaration::implies(filter, predicate)),
+            _ if filter == predicate => true,
+            (Filter::And(filters), _) => filters
+                .iter()
+                .any(|filter| IndexDeclaration::implies(filter, predicate)),
+            (Filter::Or(filters), _) => filters
+                .iter()
+                .all(|filter| IndexDeclaration::implies(filter, predicate)),
+            _ => false,
+        }
+    }
+    /// Whether a filter compares text in a range, where a collation changes what it matches
+    fn collates(filter: &Filter) -> bool {
+        match filter {
+            Filter::Gt(_, value)
+            | Filter::Ge(_, value)
+            | Filter::Lt(_, value)
+            | Filter::Le(_, value) => {
+                matches!(value, Value::Text(_))
+            }
+            Filter::And(filters) | Filter::Or(filters) => {
+                filters.iter().any(IndexDeclaration::collates)
+            }
+            Filter::Not(filter) => IndexDeclaration::collates(filter),
+            _ => false,
+        }
+    }
+}
+
+/// The documents of a database that hold any of the fields of an index, or of a partial index those
+/// matching its predicate that do. An ordered index keeps them
+/// in the order of the values of the fields: by the first field, then by the second among documents
+/// holding the same first value and so on. A hash index keeps them by the values of all the fields,
+/// an inverted index by each element of its field and a geo index by the cell of its point.
+/// The documents a filter on the fields matches are found without opening the others
+/// ```
+/// #[derive(Debug, Clone, Default)]
+/// pub(crate) struct FieldIndex {
+///     declaration: IndexDeclaration,
+///     documents: BTreeMap<Utf8PathBuf, Entry>,
+///     ordered: BTreeSet<(Entry, Utf8PathBuf)>,
+///     hashed: HashMap<Entry, BTreeSet<Utf8PathBuf>>,
+///     inverted: HashMap<Value, BTreeSet<Utf8PathBuf>>,
+///     located: BTreeSet<(u64, Utf8PathBuf)>,
+///     unordered: BTreeSet<Utf8PathBuf>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FieldIndex {
+    declaration: IndexDeclaration,
+    documents: BTreeMap<Utf8PathBuf, Entry>,
+    // Only kept by an ordered index
+    ordered: BTreeSet<(Entry, Utf8PathBuf)>,
+    // The documents holding each entry, only kept by a hash index
+    hashed: HashMap<Entry, BTreeSet<Utf8PathBuf>>,
+    // The documents holding each element, only kept by an inverted index
+    inverted: HashMap<Value, BTreeSet<Utf8PathBuf>>,
+    // The documents by the cell of their point, only kept by a geo index
+    located: BTreeSet<(u64, Utf8PathBuf)>,
+    // The documents holding `NaN` in any of the fields, it is unordered and no comparison with it holds.
+    // They are kept out of the order and may match any filter
+    unordered: BTreeSet<Utf8PathBuf>,
+}
+
+/// What a filter requires of the field of an index that follows the fields it requires a value of
+#[derive(Debug, Clone, Copy)]
+enum Constraint<'a> {
+    Any,
+    Exists,
+    In(&'a [Value]),
+    Range(Bound<&'a Value>, Bound<&'a Value>),
+    StartsWith(&'a str),
+}
+
+/// How the documents matching a filter are narrowed down through an index
+#[derive(Debug, Clone)]
+enum Plan<'a> {
+    // The values of the leading fields of an ordered index and what is required of the field after them
+    Scan(Vec<&'a Value>, Constraint<'a>),
+    // The values each field of a hash index may hold
+    Lookup(Vec<Vec<&'a Value>>),
+    // The elements the field of an inverted index holds every one of
+    Elements(Vec<&'a Value>),
+    // The area the point of a geo index is in, and the circle inside it when it is near a point
+    Area(GeoBox, Option<(&'a GeoPoint, u64)>),
+}
+
+/// An index serving a filter, with how well it serves it, its fields and its plan
+type Candidate<'a> = ((usize, bool), &'a Vec<Vec<u8>>, &'a FieldIndex, Plan<'a>);
+
+impl FieldIndex {
+    fn new(declaration: &IndexDeclaration) -> Self {
+        Self {
+            declaration: declaration.clone(),
+            ..FieldIndex::default()
+        }
+    }
+
+    fn from_documents(
+        declaration: &IndexDeclaration,
+        documents: BTreeMap<Utf8PathBuf, Entry>,
+    ) -> Self {
+        let mut index = FieldIndex::new(declaration);
+        for (document_name, entry) in documents {
+            index.insert(&document_name, entry);
+        }
+
+        index
+    }
+    /// Build the index of the fields `keys` by opening every document, which rehydrates documents in the cold tier
+    pub(crate) async fn build(
+        keys: &[Vec<u8>],
+        declaration: &IndexDeclaration,
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<Self> {
+        let mut index = FieldIndex::new(declaration);
+
+        for (document_name, document) in documents {
+            if let Some(entry) = index.entry(keys, &document.open().await?)? {
+                index.insert(&document_name, entry);
+            }
+        }
+
+        Ok(index)
+    }
+    /// The values of the fields `keys` a document holds, `None` when it holds none of them
+    /// or does not belong in a partial index. An inverted index holds the distinct elements of
+    /// its field, a document whose field is not an `ARRAY` or has no elements is left out.
+    /// A geo index holds the point of its field, a document whose field is not a `GeoPoint` is left out
+    fn entry<S: FieldSource + ?Sized>(
+        &self,
+        keys: &[Vec<u8>],
+        source: &S,
+    ) -> TuringResult<Option<Entry>> {
+        if !self.declaration.holds(source)? {
+            return Ok(None);
+        }
+
+        if self.declaration.kind == IndexKind::Inverted {
+            let elements = match keys.first() {
+                None => None,
+                Some(key) => source
+                    .read_field(key)?
+                    .and_then(|field_data| Value::elements(&field_data)),
+            };
+
+            return Ok(elements
+                .map(|elements| {
+                    elements
+                        .into_iter()
+                        .collect::<BTreeSet<Value>>()
+                        .into_iter()
+                        .map(Some)
+                        .collect::<Entry>()
+                })
+                .filter(|entry| !entry.is_empty()));
+        }
+
+        if self.declaration.kind == IndexKind::Geo {
+            let point = match keys.first() {
+                None => None,
+                Some(key) => source
+                    .read_field(key)?
+                    .and_then(|field_data| GeoPoint::decode(&field_data).ok()),
+            };
+
+            return Ok(point.map(|point| {
+                vec![
+                    Some(Value::Float(point.latitude())),
+                    Some(Value::Float(point.longitude())),
+                ]
+            }));
+        }
+
+        let mut entry = Vec::with_capacity(keys.len());
+        for key in keys {
+            entry.push(
+                source
+                    .read_field(key)?
+                    .map(|field_data| Value::from_field(&field_data)),
+            );
+        }
+
+        if entry.iter().all(Option::is_none) {
+            return Ok(None);
+        }
+
+        Ok(Some(entry))
+    }
+
+    /// Bring the index of the fields `keys` up to date with a document, `None` once it is no longer in the database
+    pub(crate) fn record(
+        &mut self,
+        keys: &[Vec<u8>],
+        document_name: &Utf8Path,
+        sled_db: Option<&Document>,
+    ) -> TuringResult<()> {
+        match sled_db
+            .map(|sled_db| self.entry(keys, sled_db))
+            .transpose()?
+        {
+            Some(Some(entry)) => self.insert(document_name, entry),
+            _ => self.remove(document_name),
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, entry: Entry) {
+        self.remove(document_name);
+
+        if entry.iter().flatten().any(FieldIndex::is_unordered) {
+            self.unordered.insert(document_name.to_path_buf());
+        } else {
+            match self.declaration.kind {
+                IndexKind::Ordered => {
+                    self.ordered
+                        .insert((entry.clone(), document_name.to_path_buf()));
+                }
+                IndexKind::Hash => {
+                    self.hashed
+                        .entry(entry.clone())
+                        .or_default()
+                        .insert(document_name.to_path_buf());
+                }
+                IndexKind::Inverted => {
+                    for element in entry.iter().flatten() {
+                        self.inverted
+                            .entry(element.clone())
+                            .or_default()
+                            .insert(document_name.to_path_buf());
+                    }
+                }
+                IndexKind::Geo => {
+                    if let Some(point) = FieldIndex::point(&entry) {
+                        self.located
+                            .insert((point.cell(), document_name.to_path_buf()));
+                    }
+                }
+            }
+        }
+        self.documents.insert(document_name.to_path_buf(), entry);
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        if let Some(entry) = self.documents.remove(document_name) {
+            self.unordered.remove(document_name);
+
+            match self.declaration.kind {
+                IndexKind::Ordered => {
+                    self.ordered.remove(&(entry, document_name.to_path_buf()));
+                }
+                IndexKind::Hash => {
+                    if let Some(held) = self.hashed.get_mut(&entry) {
+                        held.remove(document_name);
+                        if held.is_empty() {
+                            self.hashed.remove(&entry);
+                        }
+                    }
+                }
+                IndexKind::Inverted => {
+                    for element in entry.iter().flatten() {
+                        if let Some(held) = self.inverted.get_mut(element) {
+                            held.remove(document_name);
+                            if held.is_empty() {
+                                self.inverted.remove(element);
+                            }
+                        }
+                    }
+                }
+                IndexKind::Geo => {
+                    if let Some(point) = FieldIndex::point(&entry) {
+                        self.located
+                            .remove(&(point.cell(), document_name.to_path_buf()));
+                    }
+                }
+            }
+        }
+    }
+    /// The documents whose field holds every one of the elements of an inverted index,
+    /// along with the documents kept out of it
+    fn containing(&self, elements: &[&Value]) -> BTreeSet<Utf8PathBuf> {
+        let mut documents = elements
+            .iter()
+            .map(|element| self.inverted.get(*element).cloned().unwrap_or_default())
+            .reduce(|containing, held| containing.intersection(&held).cloned().collect())
+            .unwrap_or_default();
+        documents.extend(self.unordered.iter().cloned());
+
+        documents
+    }
+    /// The documents whose point is in `area` and within the circle when there is one. Only the cells
+    /// covering the area are walked and the points in them are checked without opening the documents
+    fn located(&self, area: &GeoBox, circle: Option<(&GeoPoint, u64)>) -> BTreeSet<Utf8PathBuf> {
+        let mut documents = BTreeSet::new();
+
+        for (start, end) in area.cells() {
+            for (_, document_name) in self
+                .located
+                .range((
+                    Bound::Included((start, Utf8PathBuf::new())),
+                    Bound::Unbounded,
+                ))
+                .take_while(|(cell, _)| *cell <= end)
+            {
+                let point = match self
+                    .documents
+                    .get(document_name)
+                    .and_then(FieldIndex::point)
+                {
+                    None => continue,
+                    Some(point) => point,
+                };
+
+                let held = area.contains(&point)
+                    && circle
+                        .is_none_or(|(center, radius)| point.distance(center) <= radius as f64);
+                if held {
+                    documents.insert(document_name.clone());
+                }
+            }
+        }
+
+        documents
+    }
+    /// The point a geo index holds in an entry
+    fn point(entry: &Entry) -> Option<GeoPoint> {
+        match entry.as_slice() {
+            [Some(Value::Float(latitude)), Some(Value::Float(longitude))] => {
+                GeoPoint::new(*latitude, *longitude).ok()
+            }
+            _ => None,
+        }
+    }
+    /// The documents holding one of the values given for each field of a hash index,
+    /// along with the documents kept out of it
+    fn lookup(&self, values: &[Vec<&Value>]) -> BTreeSet<Utf8PathBuf> {
+        let mut documents = self.unordered.clone();
+
+        // Every combination of the values of the fields
+        let mut entries: Vec<Entry> = vec![Vec::new()];
+        for held in values {
+            entries = entries
+                .iter()
+                .flat_map(|entry| {
+                    held.iter().map(move |value| {
+                        let mut entry = entry.clone();
+                        entry.push(Some((*value).clone()));
+
+                        entry
+                    })
+                })
+                .collect();
+        }
+
+        for entry in entries {
+            if let Some(held) = self.hashed.get(&entry) {
+                documents.extend(held.iter().cloned());
+            }
+        }
+
+        documents
+    }
+    /// The documents holding the values of `prefix` in the leading fields whose value of the field after
+    /// them meets `constraint`, along with the documents kept out of the order
+    fn scan(&self, prefix: &[&Value], constraint: Constraint) -> BTreeSet<Utf8PathBuf> {
+        let mut documents = self.unordered.clone();
+
+        match constraint {
+            Constraint::In(values) => {
+                for value in values {
+                    let equal = Constraint::Range(Bound::Included(value), Bound::Included(value));
+                    documents.append(&mut self.range(prefix, equal));
+                }
+            }
+            _ => documents.append(&mut self.range(prefix, constraint)),
+        }
+
+        documents
+    }
+
+    fn range(&self, prefix: &[&Value], constraint: Constraint) -> BTreeSet<Utf8PathBuf> {
+        let position = prefix.len();
+
+        let mut start = prefix
+            .iter()
+            .map(|value| Some((*value).clone()))
+            .collect::<Entry>();
+        match constraint {
+            Constraint::Range(Bound::Included(from), _)
+            | Constraint::Range(Bound::Excluded(from), _) => start.push(Some(from.clone())),
+            Constraint::StartsWith(text) => start.push(Some(Value::Text(text.into()))),
+            _ => (),
+        }
+
+        // Documents without the field sort before the others among those holding the prefix
+        self.ordered
+            .range((
+                Bound::Included((start, Utf8PathBuf::new())),
+                Bound::Unbounded,
+            ))
+            .take_while(|(entry, _)| {
+                FieldIndex::has_prefix(entry, prefix)
+                    && match (constraint, entry.get(position)) {
+                        (Constraint::Range(_, Bound::Included(to)), Some(Some(value))) => {
+                            value <= to
+                        }
+                        (Constraint::Range(_, Bound::Excluded(to)), Some(Some(value))) => {
+                            value < to
+                        }
+                        (Constraint::StartsWith(text), Some(Some(Value::Text(held)))) => {
+                            held.starts_with(text)
+                        }
+                        (Constraint::StartsWith(_), _) => false,
+                        _ => true,
+                    }
+            })
+            .filter(|(entry, _)| match (constraint, entry.get(position)) {
+                (Constraint::Any, _) => true,
+                (Constraint::Range(Bound::Excluded(from), _), Some(Some(value))) => value != from,
+                (_, Some(Some(_))) => true,
+                _ => false,
+            })
+            .map(|(_, document_name)| document_name.clone())
+            .collect()
+    }
+    /// The documents holding the values of `prefix` in the leading fields, in the order of the field after
+    /// them. Documents without that field come last in either order, as a query sorts them
+    fn sorted(&self, prefix: &[&Value], order: SortOrder) -> Vec<Utf8PathBuf> {
+        let position = prefix.len();
+        let start = prefix
+            .iter()
+            .map(|value| Some((*value).clone()))
+            .collect::<Entry>();
+
+        let mut held = Vec::new();
+        let mut missing = Vec::new();
+        for (entry, document_name) in self
+            .ordered
+            .range((
+                Bound::Included((start, Utf8PathBuf::new())),
+                Bound::Unbounded,
+            ))
+            .take_while(|(entry, _)| FieldIndex::has_prefix(entry, prefix))
+        {
+            match entry.get(position) {
+                Some(Some(_)) => held.push(document_name.clone()),
+                _ => missing.push(document_name.clone()),
+            }
+        }
+
+        if order == SortOrder::Descending {
+            held.reverse();
+        }
+        held.append(&mut missing);
+
+        held
+    }
+
+    fn has_prefix(entry: &[Option<Value>], prefix: &[&Value]) -> bool {
+        prefix.iter().enumerate().all(
+            |(position, value)| matches!(entry.get(position), Some(Some(held)) if held == *value),
+        )
+    }
+
+    fn is_unordered(value: &Value) -> bool {
+        matches!(value, Value::Float(float) if float.is_nan())
+    }
+}
+
+/// The secondary indexes of a database, each over one or more fields in a defined order.
+/// The indexes are kept up to date as the documents are written to and narrow down
+/// the documents a query or an aggregation opens
+/// ```
+/// #[derive(Debug, Default)]
+/// pub(crate) struct Indexes {
+///     fields: BTreeMap<Vec<Vec<u8>>, Option<FieldIndex>>,
+///     declarations: BTreeMap<Vec<Vec<u8>>, IndexDeclaration>,
+///     rebuilding: BTreeMap<Vec<Vec<u8>>, BTreeSet<Utf8PathBuf>>,
+///     hits: BTreeMap<Vec<Vec<u8>>, u64>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct Indexes {
+    // Keyed by the fields of each index, `None` for an index that is built the next time a query uses it
+    fields: BTreeMap<Vec<Vec<u8>>, Option<FieldIndex>>,
+    declarations: BTreeMap<Vec<Vec<u8>>, IndexDeclaration>,
+    // The indexes built in the background, those just declared and those whose entries were lost,
+    // along with the documents written since. Queries scan the documents instead of building them
+    rebuilding: BTreeMap<Vec<Vec<u8>>, BTreeSet<Utf8PathBuf>>,
+    // How many times each index narrowed down the documents of a query or an aggregation since the
+    // database was loaded
+    hits: BTreeMap<Vec<Vec<u8>>, u64>,
+}
+
+impl Indexes {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub(crate) fn contains(&self, keys: &[Vec<u8>]) -> bool {
+        self.fields.contains_key(keys)
+    }
+    /// The fields and the kind of each index, the indexes in the order of the names of their fields
+    pub(crate) fn list(&self) -> Vec<(Vec<Vec<u8>>, IndexKind)> {
+        self.declarations
+            .iter()
+            .map(|(keys, declaration)| (keys.clone(), declaration.get_kind()))
+            .collect()
+    }
+
+    pub(crate) fn declaration(&self, keys: &[Vec<u8>]) -> Option<&IndexDeclaration> {
+        self.declarations.get(keys)
+    }
+    /// The fields of every index along with how it is declared
+    pub(crate) fn declarations(&self) -> Vec<(Vec<Vec<u8>>, IndexDeclaration)> {
+        self.declarations
+            .iter()
+            .map(|(keys, declaration)| (keys.clone(), declaration.clone()))
+            .collect()
+    }
+    /// Index fields in the order they are given, the index is built in the background without holding up
+    /// writes and queries scan the documents until it is. An index needs at least one field and holds each
+    /// field once, an inverted or a geo index exactly one
+    pub(crate) fn declare(
+        &mut self,
+        keys: &[Vec<u8>],
+        declaration: IndexDeclaration,
+    ) -> TuringResult<()> {
+        let distinct = keys.iter().collect::<BTreeSet<&Vec<u8>>>();
+        if keys.is_empty() || distinct.len() != keys.len() {
+            return Err(TuringDbError::InvalidInput);
+        }
+        if matches!(declaration.kind, IndexKind::Inverted | IndexKind::Geo) && keys.len() != 1 {
+            return Err(TuringDbError::InvalidInput);
+        }
+
+        if self.fields.contains_key(keys) {
+            return Err(TuringDbError::AlreadyExists);
+        }
+        self.fields.insert(keys.to_vec(), None);
+        self.declarations.insert(keys.to_vec(), declaration);
+        self.rebuilding.insert(keys.to_vec(), BTreeSet::new());
+
+        Ok(())
+    }
+
+    pub(crate) fn drop_index(&mut self, keys: &[Vec<u8>]) -> TuringResult<()> {
+        self.rebuilding.remove(keys);
+        self.declarations.remove(keys);
+        self.hits.remove(keys);
+
+        match self.fields.remove(keys) {
+            None => Err(TuringDbError::IndexNotFound),
+            Some(_) => Ok(()),
+        }
+    }
+    /// Count a query or an aggregation the indexes narrowed down the documents of
+    pub(crate) fn hit(&mut self, used: &BTreeSet<Vec<Vec<u8>>>) {
+        for keys in used {
+            *self.hits.entry(keys.clone()).or_default() += 1;
+        }
+    }
+    /// How each index is declared and used, the indexes in the order of the names of their fields
+    pub(crate) fn stats(&self) -> Vec<IndexStats> {
+        self.declarations
+            .iter()
+            .map(|(keys, declaration)| {
+                let held = self.fields.get(keys).and_then(Option::as_ref);
+
+                IndexStats::new(keys.clone(), declaration.get_kind())
+                    .set_partial(declaration.get_predicate().is_some())
+                    .set_documents(held.map(|index| index.documents.len()))
+                    .set_hits(self.hits.get(keys).copied().unwrap_or_default())
+            })
+            .collect()
+    }
+    /// Drop the entries of every index, they are built again the next time a query uses them
+    pub(crate) fn reset(&mut self) {
+        for index in self.fields.values_mut() {
+            *index = None;
+        }
+        self.rebuilding.clear();
+    }
+    /// The indexes yet to be built in the background
+    pub(crate) fn rebuilding(&self) -> Vec<(Vec<Vec<u8>>, IndexDeclaration)> {
+        self.rebuilding
+            .keys()
+            .filter_map(|keys| {
+                self.declaration(keys)
+                    .map(|declaration| (keys.clone(), declaration.clone()))
+            })
+            .collect()
+    }
+    /// Stop building an index in the background, returning the documents written since it started.
+    /// `None` when it was dropped or its entries reset meanwhile
+    pub(crate) fn take_rebuilding(&mut self, keys: &[Vec<u8>]) -> Option<BTreeSet<Utf8PathBuf>> {
+        self.rebuilding.remove(keys)
+    }
+    /// Bring the built indexes up to date with a document, `None` once it is no longer in the database
+    pub(crate) fn record(
+        &mut self,
+        document_name: &Utf8Path,
+        sled_db: Option<&Document>,
+    ) -> TuringResult<()> {
+        for written in self.rebuilding.values_mut() {
+            written.insert(document_name.to_path_buf());
+        }
+
+        for (keys, index) in self.fields.iter_mut() {
+            if let Some(index) = index {
+                index.record(keys, document_name, sled_db)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// The indexes yet to be built over any field a filter reads or a query is sorted by, a hash, an inverted
+    /// or a geo index only once the filter reads every one of its fields and a partial index only once the filter requires
+    /// what its predicate requires. Those built again in the background are left out
+    pub(crate) fn unbuilt(
+        &self,
+        filter: &Filter,
+        order: Option<&[u8]>,
+        collation: Collation,
+    ) -> Vec<(Vec<Vec<u8>>, IndexDeclaration)> {
+        let mut read = Vec::new();
+        Indexes::keys_read(filter, &mut |key| read.push(key.to_vec()));
+        let mut used = read.clone();
+        used.extend(order.map(<[u8]>::to_vec));
+
+        self.declarations
+            .iter()
+            .filter(|(keys, declaration)| {
+                matches!(self.fields.get(*keys), Some(None))
+                    && !self.rebuilding.contains_key(*keys)
+                    && declaration.serves(filter, collation)
+                    && match declaration.kind {
+                        IndexKind::Ordered => keys.iter().any(|key| used.contains(key)),
+                        IndexKind::Hash | IndexKind::Inverted | IndexKind::Geo => {
+                            keys.iter().all(|key| read.contains(key))
+                        }
+                    }
+            })
+            .map(|(keys, declaration)| (keys.clone(), declaration.clone()))
+            .collect()
+    }
+
+    pub(crate) fn set_built(&mut self, keys: &[Vec<u8>], index: FieldIndex) {
+        if let Some(held) = self.fields.get_mut(keys) {
+            *held = Some(index);
+        }
+    }
+    /// The documents that may match a filter, found through the indexes. Every document the filter
+    /// matches is among them, but not every one of them matches. `None` when the indexes can not
+    /// narrow down the documents and every one of them has to be checked
+    pub(crate) fn candidates(
+        &self,
+        filter: &Filter,
+        collation: Collation,
+        used: &mut BTreeSet<Vec<Vec<u8>>>,
+    ) -> Option<BTreeSet<Utf8PathBuf>> {
+        match filter {
+            // Every filter has to be narrowed down to narrow down the documents any of them match
+            Filter::Or(filters) => {
+                let mut candidates = BTreeSet::new();
+                let mut used_by_all = BTreeSet::new();
+                for filter in filters {
+                    candidates.append(&mut self.candidates(filter, collation, &mut used_by_all)?);
+                }
+                used.append(&mut used_by_all);
+
+                Some(candidates)
+            }
+            // The filters a document has to match all of are narrowed down together by the index
+            // that serves most of them, any alternatives among them narrow the documents down further
+            _ => {
+                let mut conditions = Vec::new();
+                let mut alternatives = Vec::new();
+                Indexes::conjuncts(filter, &mut conditions, &mut alternatives);
+
+                let narrowed = self.conjunction(filter, &conditions, collation, used);
+                alternatives
+                    .into_iter()
+                    .filter_map(|filter| {
+                        let mut used_by_alternative = BTreeSet::new();
+                        let candidates =
+                            self.candidates(filter, collation, &mut used_by_alternative)?;
+                        used.append(&mut used_by_alternative);
+
+                        Some(candidates)
+                    })
+                    .fold(
+                        narrowed,
+                        |narrowed: Option<BTreeSet<Utf8PathBuf>>, candidates| {
+                            Some(match narrowed {
+                                None => candidates,
+                                Some(narrowed) => {
+                                    narrowed.intersection(&candidates).cloned().collect()
+                                }
+                            })
+                        },
+                    )
+            }
+        }
+    }
+    /// The documents a query sorted by the field `key` may match, in the order of the field, when an ordered
+    /// index holds them in that order: an index whose fields before `key` the filter requires a value of each of.
+    /// The flag tells whether every document the filter matches is among them, the documents an index
+    /// over `key` first does not hold sort last as they do not hold the field
+    pub(crate) fn sorted(
+        &self,
+        filter: &Filter,
+        key: &[u8],
+        order: SortOrder,
+        collation: Collation,
+        used: &mut BTreeSet<Vec<Vec<u8>>>,
+    ) -> Option<(Vec<Utf8PathBuf>, bool)> {
+        // An index holds text in the order of its bytes
+        if collation != Collation::Binary {
+            return None;
+        }
+
+        let mut conditions = Vec::new();
+        Indexes::conjuncts(filter, &mut conditions, &mut Vec::new());
+
+        for (keys, index) in self.fields.iter() {
+            let index = match index {
+                Some(index)
+                    if index.declaration.kind == IndexKind::Ordered
+                        && index.unordered.is_empty()
+                        && index.declaration.serves(filter, collation) =>
+                {
+                    index
+                }
+                _ => continue,
+            };
+            let position = match keys.iter().position(|held| held.as_slice() == key) {
+                None => continue,
+                Some(position) => position,
+            };
+
+            let prefix = keys[..position]
+                .iter()
+                .map_while(|key| Indexes::equal(&conditions, key))
+                .collect::<Vec<&Value>>();
+            if prefix.len() < position {
+                continue;
+            }
+
+            if prefix.iter().any(|value| FieldIndex::is_unordered(value)) {
+                return Some((Vec::new(), true));
+            }
+            used.insert(keys.clone());
+
+            return Some((index.sorted(&prefix, order), position > 0));
+        }
+
+        None
+    }
+    /// Narrow down the documents matching every one of `conditions` through the index that serves most
+    /// of them: the index whose most leading fields they require a value of, then whose field after
+    /// those they constrain. A hash index serves them only when they require a value of each of its
+    /// fields, and is picked over an ordered index serving as many. An inverted index serves the elements
+    /// they require its field to contain and a geo index the area they require its point to be in.
+    /// A partial index is only used when the filter requires what its predicate requires
+    fn conjunction(
+        &self,
+        filter: &Filter,
+        conditions: &[&Filter],
+        collation: Collation,
+        used: &mut BTreeSet<Vec<Vec<u8>>>,
+    ) -> Option<BTreeSet<Utf8PathBuf>> {
+        let mut best: Option<Candidate> = None;
+
+        for (keys, index) in self.fields.iter() {
+            let index = match index {
+                Some(index) if index.declaration.serves(filter, collation) => index,
+                _ => continue,
+            };
+
+            let (served, plan) = match index.declaration.kind {
+                IndexKind::Hash => {
+                    match keys
+                        .iter()
+                        .map(|key| Indexes::values(conditions, key))
+                        .collect::<Option<Vec<Vec<&Value>>>>()
+                    {
+                        None => continue,
+                        Some(values) => (keys.len() * 2, Plan::Lookup(values)),
+                    }
+                }
+                IndexKind::Inverted => {
+                    let elements = keys
+                        .iter()
+                        .flat_map(|key| Indexes::elements(conditions, key))
+                        .collect::<Vec<&Value>>();
+                    if elements.is_empty() {
+                        continue;
+                    }
+
+                    (2, Plan::Elements(elements))
+                }
+                IndexKind::Geo => match keys.first().and_then(|key| Indexes::area(conditions, key))
+                {
+                    None => continue,
+                    Some((area, circle)) => (2, Plan::Area(area, circle)),
+                },
+                IndexKind::Ordered => {
+                    let prefix = keys
+                        .iter()
+                        .map_while(|key| Indexes::equal(conditions, key))
+                        .collect::<Vec<&Value>>();
+                    let constraint = match keys.get(prefix.len()) {
+                        None => Constraint::Any,
+                        Some(key) => Indexes::constraint(conditions, key, collation),
+                    };
+
+                    (
+                        prefix.len() * 2 + !matches!(constraint, Constraint::Any) as usize,
+                        Plan::Scan(prefix, constraint),
+                    )
+                }
+            };
+
+            let rank = (served, index.declaration.kind != IndexKind::Ordered);
+            if rank > best.as_ref().map_or((0, false), |(best, ..)| *best) {
+                best = Some((rank, keys, index, plan));
+            }
+        }
+
+        let (_, keys, index, plan) = best?;
+        used.insert(keys.clone());
+
+        let (prefix, constraint) = match plan {
+            Plan::Lookup(values) => return Some(index.lookup(&values)),
+            Plan::Elements(elements) => {
+                // `NaN` is not equal to any element
+                if elements
+                    .iter()
+                    .any(|element| FieldIndex::is_unordered(element))
+                {
+                    return Some(BTreeSet::new());
+                }
+
+                return Some(index.containing(&elements));
+            }
+            Plan::Area(area, circle) => return Some(index.located(&area, circle)),
+            Plan::Scan(prefix, constraint) => (prefix, constraint),
+        };
+
+        // A value that is unordered matches nothing it is compared to
+        let unordered = prefix.iter().any(|value| FieldIndex::is_unordered(value))
+            || match constraint {
+                Constraint::Range(from, to) => [from, to].iter().any(|bound| {
+                    matches!(bound, Bound::Included(value) | Bound::Excluded(value)
+                        if FieldIndex::is_unordered(value))
+                }),
+                _ => false,
+            };
+        if unordered {
+            return Some(BTreeSet::new());
+        }
+
+        Some(index.scan(&prefix, constraint))
+    }
+    /// The value a condition requires the field `key` to equal
+    fn equal<'a>(conditions: &[&'a Filter], key: &[u8]) -> Option<&'a Value> {
+        conditions.iter().find_map(|condition| match condition {
+            Filter::Eq(held, value) if held.as_slice() == key => Some(value),
+            _ => None,
+        })
+    }
+    /// The values a condition requires the field `key` to hold one of, leaving out those that are unordered
+    /// as they match nothing. `None` when no condition requires a value of it
+    fn values<'a>(conditions: &[&'a Filter], key: &[u8]) -> Option<Vec<&'a Value>> {
+        conditions.iter().find_map(|condition| {
+            let values = match condition {
+                Filter::Eq(held, value) if held.as_slice() == key => vec![value],
+                Filter::In(held, values) if held.as_slice() == key => values.iter().collect(),
+                _ => return None,
+            };
+
+            Some(
+                values
+                    .into_iter()
+                    .filter(|value| !FieldIndex::is_unordered(value))
+                    .collect(),
+            )
+        })
+    }
+    /// The elements the conditions require the field `key` to contain
+    fn elements<'a>(conditions: &[&'a Filter], key: &[u8]) -> Vec<&'a Value> {
+        conditions
+            .iter()
+            .filter_map(|condition| match condition {
+                Filter::Contains(held, element) if held.as_slice() == key => Some(element),
+                _ => None,
+            })
+            .collect()
+    }
+    /// The area a condition requires the point in the field `key` to be in,
+    /// along with the circle inside it when it requires the point to be near another
+    fn area<'a>(
+        conditions: &[&'a Filter],
+        key: &[u8],
+    ) -> Option<(GeoBox, Option<(&'a GeoPoint, u64)>)> {
+        conditions.iter().find_map(|condition| match condition {
+            Filter::Near(held, center, radius) if held.as_slice() == key => Some((
+                GeoBox::around(center, *radius as f64),
+                Some((center, *radius)),
+            )),
+            Filter::Within(held, area) if held.as_slice() == key => Some((*area, None)),
+            _ => None,
+        })
+    }
+    /// What the conditions require of the field `key` other than a value. An index holds text in the order
+    /// of its bytes, so it does not serve a range of text compared by a language
+    fn constraint<'a>(
+        conditions: &[&'a Filter],
+        key: &[u8],
+        collation: Collation,
+    ) -> Constraint<'a> {
+        let mut from = Bound::Unbounded;
+        let mut to = Bound::Unbounded;
+        let mut starts_with = None;
+        let mut exists = false;
+
+        for condition in conditions {
+            match condition {
+                Filter::In(held, values) if held.as_slice() == key => {
+                    return Constraint::In(values)
+                }
+                Filter::Gt(held, value)
+                | Filter::Ge(held, value)
+                | Filter::Lt(held, value)
+                | Filter::Le(held, value)
+                    if held.as_slice() == key
+                        && matches!(value, Value::Text(_))
+                        && collation != Collation::Binary => {}
+                Filter::Gt(held, value) if held.as_slice() == key => from = Bound::Excluded(value),
+                Filter::Ge(held, value) if held.as_slice() == key => from = Bound::Included(value),
+                Filter::Lt(held, value) if held.as_slice() == key => to = Bound::Excluded(value),
+                Filter::Le(held, value) if held.as_slice() == key => to = Bound::Included(value),
+                Filter::StartsWith(held, prefix) if held.as_slice() == key => {
+                    starts_with = Some(prefix.as_str())
+                }
+                Filter::Exists(held) if held.as_slice() == key => exists = true,
+                _ => (),
+            }
+        }
+
+        match (from, to, starts_with) {
+            (Bound::Unbounded, Bound::Unbounded, Some(prefix)) => Constraint::StartsWith(prefix),
+            (Bound::Unbounded, Bound::Unbounded, None) if exists => Constraint::Exists,
+            (Bound::Unbounded, Bound::Unbounded, None) => Constraint::Any,
+            (from, to, _) => Constraint::Range(from, to),
+        }
+    }
+    /// Split a filter into the conditions on a single field a document has to match all of,
+    /// and the alternatives it has to match one of each
+    fn conjuncts<'a>(
+        filter: &'a Filter,
+        conditions: &mut Vec<&'a Filter>,
+        alternatives: &mut Vec<&'a Filter>,
+    ) {
+        match filter {
+            Filter::And(filters) => {
+                for filter in filters {
+                    Indexes::conjuncts(filter, conditions, alternatives);
+                }
+            }
+            Filter::Or(_) => alternatives.push(filter),
+            Filter::Exists(_)
+            | Filter::Eq(..)
+            | Filter::In(..)
+            | Filter::Gt(..)
+            | Filter::Ge(..)
+            | Filter::Lt(..)
+            | Filter::Le(..)
+            | Filter::StartsWith(..)
+            | Filter::Contains(..)
+            | Filter::Near(..)
+            | Filter::Within(..) => conditions.push(filter),
+            _ => (),
+        }
+    }
+
+    fn keys_read<F: FnMut(&[u8])>(filter: &Filter, read: &mut F) {
+        match filter {
+            Filter::Exists(key)
+            | Filter::Eq(key, _)
+            | Filter::In(key, _)
+            | Filter::Gt(key, _)
+            | Filter::Ge(key, _)
+            | Filter::Lt(key, _)
+            | Filter::Le(key, _)
+            | Filter::StartsWith(key, _)
+            | Filter::Contains(key, _)
+            | Filter::Near(key, ..)
+            | Filter::Within(key, _) => read(key),
+            Filter::And(filters) | Filter::Or(filters) => {
+                for filter in filters {
+                    Indexes::keys_read(filter, read);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Load the indexes of a database last committed at `committed`. The entries are only trusted when
+    /// they were persisted by that same commit, indexes that were built but whose entries are missing,
+    /// failed their checksum or come from another commit are built again in the background
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+        committed: TAI64N,
+    ) -> TuringResult<Indexes> {
+        // How each index was declared and whether it was built when it was persisted
+        let declared = MetaFile::read::<BTreeMap<Vec<Vec<u8>>, (IndexDeclaration, bool)>>(
+            &Indexes::path(db_dir),
+            quarantine,
+        )
+        .await?
+        .unwrap_or_default();
+
+        let mut entries = match MetaFile::read::<(
+            TAI64N,
+            BTreeMap<Vec<Vec<u8>>, BTreeMap<Utf8PathBuf, Entry>>,
+        )>(&Indexes::entries_path(db_dir), quarantine)
+        .await?
+        {
+            Some((stamp, entries)) if stamp == committed => entries,
+            _ => BTreeMap::new(),
+        };
+
+        let mut indexes = Indexes::default();
+        for (keys, (declaration, built)) in declared {
+            match entries.remove(&keys) {
+                Some(documents) => {
+                    indexes.fields.insert(
+                        keys.clone(),
+                        Some(FieldIndex::from_documents(&declaration, documents)),
+                    );
+                }
+                None => {
+                    if built {
+                        indexes.rebuilding.insert(keys.clone(), BTreeSet::new());
+                    }
+                    indexes.fields.insert(keys.clone(), None);
+                }
+            }
+            indexes.declarations.insert(keys, declaration);
+        }
+
+        Ok(indexes)
+    }
+    /// Persist the indexes along with a commit of their database made at `committed`
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path, committed: TAI64N) -> TuringResult<()> {
+        let declared = self
+            .declarations
+            .iter()
+            .map(|(keys, declaration)| {
+                (
+                    keys.clone(),
+                    (
+                        declaration.clone(),
+                        matches!(self.fields.get(keys), Some(Some(_)))
+                            || self.rebuilding.contains_key(keys),
+                    ),
+                )
+            })
+            .collect::<BTreeMap<Vec<Vec<u8>>, (IndexDeclaration, bool)>>();
+        let entries = self
+            .fields
+            .iter()
+            .filter_map(|(keys, index)| {
+                index
+                    .as_ref()
+                    .map(|index| (keys.clone(), index.documents.clone()))
+            })
+            .collect::<BTreeMap<Vec<Vec<u8>>, BTreeMap<Utf8PathBuf, Entry>>>();
+
+        MetaFile::write(
+            &Indexes::entries_path(db_dir),
+            &MetaEncoding::Bincode.encode(&(committed, entries))?,
+        )
+        .await?;
+        MetaFile::write(
+            &Indexes::path(db_dir),
+            &MetaEncoding::Bincode.encode(&declared)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(INDEXES_META_NAME);
+
+        path
+    }
+
+    pub(crate) fn entries_path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(INDEX_ENTRIES_META_NAME);
+
+        path
+    }
+}
@@ -0,0 +1,1534 @@
+#[cfg(feature = "tls")]
+use crate::ServerTls;
+use crate::{
+    deadline, wire_compression, wire_encoding, ClientHello, Compression, ErrorKeys, Frame, Gossip,
+    GossipNode, HashRing, Member, MultiWriter, MultiWriterNode, OpsOutcome, PayloadEncoding, Raft,
+    RaftNode, RateLimit, RateLimiter, Router, ServerHello, ServerInfo, TuringCommand,
+    TuringDBDocumentOps, TuringDBFieldOps, TuringDBOps, TuringDbError, TuringEngine,
+    TuringResponse, TuringResult, WireMessage, DEFAULT_MAX_FRAME_LEN, PIPELINING,
+};
+use async_channel::{Receiver, Sender};
+use async_executor::Executor;
+use async_io::{Async, Timer};
+use async_lock::Semaphore;
+#[cfg(unix)]
+use camino::{Utf8Path, Utf8PathBuf};
+use dashmap::DashSet;
+use futures_lite::{
+    future::{self, Future},
+    io::{self, AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, BufReader},
+};
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    io::ErrorKind,
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixListener,
+    },
+};
+use std::{
+    io::Write,
+    net::{IpAddr, SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+/// The port a `TuringServer` listens on unless another address is set
+pub const DEFAULT_SERVER_PORT: u16 = 4343;
+/// How many connections a `TuringServer` serves at once unless another limit is set
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+/// How long a connection may wait between commands by default before it is closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+/// How long reading a command or writing a response may take by default
+pub const DEFAULT_IO_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a shutdown waits by default for connections to finish the commands they are running
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often a shutdown checks whether every connection closed
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+/// How long accepting waits after it failed, such as when the process ran out of file descriptors
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+/// How many commands of a pipelining connection run at once, reading more waits for one to finish
+const MAX_IN_FLIGHT: usize = 64;
+
+/// Serves a `TuringEngine` over TCP, and over a Unix socket as well when one is set,
+/// every connection is handled on its own task. A client opens a connection with
+/// a `ClientHello` then sends a `TuringCommand` in a `Frame` and gets a `TuringResponse` back in one
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct TuringServer {
+///     address: SocketAddr,
+///     max_frame_len: u32,
+///     max_connections: usize,
+///     idle_timeout: Duration,
+///     io_timeout: Duration,
+///     drain_timeout: Duration,
+///     rate_limit: Option<RateLimit>,
+///     admin_token: Option<String>,
+///     raft: Option<Raft>,
+///     multi_writer: Option<MultiWriter>,
+///     router: Option<HashRing>,
+///     gossip: Option<Gossip>,
+///     #[cfg(feature = "tls")]
+///     tls: Option<ServerTls>,
+///     #[cfg(unix)]
+///     unix_socket: Option<(Utf8PathBuf, u32)>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TuringServer {
+    address: SocketAddr,
+    max_frame_len: u32,
+    // Shared by the TCP listener and the Unix socket
+    max_connections: usize,
+    idle_timeout: Duration,
+    io_timeout: Duration,
+    drain_timeout: Duration,
+    // Applied to every TCP client address on its own
+    rate_limit: Option<RateLimit>,
+    // Sent by clients in `Authenticate` to be served admin commands, none are served without it
+    admin_token: Option<String>,
+    // Writes go through the log of the Raft cluster when set
+    raft: Option<Raft>,
+    // Writes are sent to the other writers of the cluster when set
+    multi_writer: Option<MultiWriter>,
+    // The commands of clients are forwarded to the nodes of the ring when set
+    router: Option<HashRing>,
+    // The server takes part in the gossip of a cluster when set
+    gossip: Option<Gossip>,
+    // Every connection is accepted over TLS when set
+    #[cfg(feature = "tls")]
+    tls: Option<ServerTls>,
+    // The path of the socket and the permissions it is given
+    #[cfg(unix)]
+    unix_socket: Option<(Utf8PathBuf, u32)>,
+}
+
+impl Default for TuringServer {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], DEFAULT_SERVER_PORT)),
+            max_frame_len: DEFAULT_MAX_FRAME_LEN,
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+            io_timeout: DEFAULT_IO_TIMEOUT,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            rate_limit: None,
+            admin_token: None,
+            raft: None,
+            multi_writer: None,
+            router: None,
+            gossip: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(unix)]
+            unix_socket: None,
+        }
+    }
+}
+
+/// What the connections of a running server share
+#[derive(Debug)]
+struct Running {
+    engine: Arc<TuringEngine>,
+    // The connections holding a slot
+    active: AtomicUsize,
+    // Nothing is ever sent, connections close once the channel is closed
+    closing: Receiver<()>,
+    started: Instant,
+    admin_token: Option<String>,
+    raft: Option<Arc<RaftNode>>,
+    multi_writer: Option<Arc<MultiWriterNode>>,
+    router: Option<Router>,
+    gossip: Option<Arc<GossipNode>>,
+}
+
+/// The state of one connection, its slot is given back once it is dropped
+#[derive(Debug)]
+struct Connection {
+    running: Arc<Running>,
+    max_frame_len: u32,
+    idle_timeout: Duration,
+    io_timeout: Duration,
+    // Agreed on by the handshake
+    compression: Option<Compression>,
+    encoding: PayloadEncoding,
+    // The address of a TCP client and the buckets it takes its tokens from
+    rate_limit: Option<(IpAddr, Arc<RateLimiter>)>,
+    // Set once the client sent the admin token
+    admin: AtomicBool,
+    // The transactions the connection began and has not finished, rolled back once it closes
+    transactions: DashSet<u64>,
+    // The document leases the connection took and has not released, released once it closes
+    leases: DashSet<(Utf8PathBuf, Utf8PathBuf, u64)>,
+    _slot: ConnectionSlot,
+}
+
+/// Counts a connection as active until it is dropped
+#[derive(Debug)]
+struct ConnectionSlot {
+    running: Arc<Running>,
+}
+
+impl ConnectionSlot {
+    /// `None` when `max_connections` connections are already active
+    fn acquire(running: &Arc<Running>, max_connections: usize) -> Option<ConnectionSlot> {
+        running
+            .active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                if count < max_connections {
+                    Some(count + 1)
+                } else {
+                    None
+                }
+            })
+            .ok()?;
+
+        Some(ConnectionSlot {
+            running: Arc::clone(running),
+        })
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.running.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for transaction in self.transactions.iter() {
+            self.running.engine.transaction_discard(*transaction);
+        }
+        for lease in self.leases.iter() {
+            let (db, document, token) = lease.key();
+            self.running.engine.lease_discard(db, document, *token);
+        }
+    }
+}
+
+/// Completes once the process receives SIGINT or SIGTERM, or Ctrl-C on Windows,
+/// to be passed to `TuringServer::run_until`. A process may only wait for the signals once
+pub fn termination_signal() -> TuringResult<impl Future<Output = ()>> {
+    let (sender, signals) = async_channel::bounded(1);
+    ctrlc::set_handler(move || {
+        sender.try_send(()).ok();
+    })
+    .map_err(|error| TuringDbError::Other(error.to_string()))?;
+
+    Ok(async move {
+        signals.recv().await.ok();
+    })
+}
+
+impl TuringServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+
+        self
+    }
+    /// The largest command a client may send, a larger one ends its connection
+    pub fn set_max_frame_len(mut self, max_frame_len: u32) -> Self {
+        self.max_frame_len = max_frame_len;
+
+        self
+    }
+    /// How many connections are served at once, over TCP and the Unix socket together.
+    /// A connection past the limit is sent `TooManyConnections` and closed right away
+    pub fn set_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+
+        self
+    }
+    /// How long a client may send nothing between commands before its connection is closed.
+    /// A client keeps an idle connection open by sending `TuringCommand::Ping`
+    pub fn set_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+
+        self
+    }
+    /// How long the rest of a command may take to arrive once it started, and a response
+    /// or a TLS handshake may take to complete, before the connection is closed
+    pub fn set_io_timeout(mut self, io_timeout: Duration) -> Self {
+        self.io_timeout = io_timeout;
+
+        self
+    }
+    /// How long a shutdown waits for connections to finish the commands they are running
+    /// before the repo is closed all the same
+    pub fn set_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+
+        self
+    }
+    /// Limit how many commands each client address may send, a command past the limit
+    /// is answered with `Throttled` and not run. Clients of the Unix socket are not limited
+    pub fn set_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+
+        self
+    }
+    /// Serve admin commands to the connections that send `token` in `TuringCommand::Authenticate`.
+    /// Without a token no connection is served admin commands
+    pub fn set_admin_token(mut self, token: &str) -> Self {
+        self.admin_token = Some(token.into());
+
+        self
+    }
+    /// Run the server as a node of a Raft cluster, see `Raft`. The admin token of the server
+    /// has to be set and shared by every node
+    pub fn set_raft(mut self, raft: Raft) -> Self {
+        self.raft = Some(raft);
+
+        self
+    }
+    /// Run the server as a writer of a multi-writer cluster, see `MultiWriter`. The admin token
+    /// of the server has to be set and shared by every node, a server does not run as both a node
+    /// of a Raft cluster and a writer
+    pub fn set_multi_writer(mut self, multi_writer: MultiWriter) -> Self {
+        self.multi_writer = Some(multi_writer);
+
+        self
+    }
+    /// Run the server as a router of a sharded cluster, forwarding the commands of clients
+    /// to the nodes of `ring` as `ShardedClient` sends them. Admin commands are run on the repo
+    /// of the router itself, the commands a sharded cluster does not serve fail with `NotSharded`
+    pub fn set_router(mut self, ring: HashRing) -> Self {
+        self.router = Some(ring);
+
+        self
+    }
+    /// Take part in the gossip of a cluster, see `Gossip`, and answer `Topology` to clients.
+    /// The admin token of the server has to be set and shared by every node
+    pub fn set_gossip(mut self, gossip: Gossip) -> Self {
+        self.gossip = Some(gossip);
+
+        self
+    }
+    /// Accept TCP connections over TLS only, presenting the certificate of `tls`
+    #[cfg(feature = "tls")]
+    pub fn set_tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(tls);
+
+        self
+    }
+    /// Also listen on a Unix socket at `path` whose file is given `permissions` such as `0o660`,
+    /// so only the users and groups it allows can connect. Connections over the socket do not use TLS.
+    /// A socket left at `path` by a server that did not shut down cleanly is replaced
+    #[cfg(unix)]
+    pub fn set_unix_socket(mut self, path: &Utf8Path, permissions: u32) -> Self {
+        self.unix_socket = Some((path.to_path_buf(), permissions));
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_max_frame_len(&self) -> u32 {
+        self.max_frame_len
+    }
+
+    pub fn get_max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    pub fn get_idle_timeout(&self) -> Duration {
+        self.idle_timeout
+    }
+
+    pub fn get_io_timeout(&self) -> Duration {
+        self.io_timeout
+    }
+
+    pub fn get_drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    pub fn get_rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    pub fn get_admin_token(&self) -> Option<&str> {
+        self.admin_token.as_deref()
+    }
+
+    pub fn get_raft(&self) -> Option<&Raft> {
+        self.raft.as_ref()
+    }
+
+    pub fn get_multi_writer(&self) -> Option<&MultiWriter> {
+        self.multi_writer.as_ref()
+    }
+
+    pub fn get_router(&self) -> Option<&HashRing> {
+        self.router.as_ref()
+    }
+
+    pub fn get_gossip(&self) -> Option<&Gossip> {
+        self.gossip.as_ref()
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn get_tls(&self) -> Option<&ServerTls> {
+        self.tls.as_ref()
+    }
+
+    #[cfg(unix)]
+    pub fn get_unix_socket(&self) -> Option<(&Utf8Path, u32)> {
+        self.unix_socket
+            .as_ref()
+            .map(|(path, permissions)| (path.as_path(), *permissions))
+    }
+    /// Accept connections until a listener fails, spawning a task on `executor` for each of them.
+    /// A connection that fails, including one whose TLS handshake fails, only ends its own task
+    pub async fn run<'a>(
+        &self,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        self.run_until(engine, executor, future::pending()).await
+    }
+    /// Like `run` until `shutdown` completes, `termination_signal()` for one. The server then stops
+    /// accepting connections and closes the idle ones, waits up to the drain timeout for the others
+    /// to answer the commands they are running, then commits the repo and releases its lock
+    pub async fn run_until<'a>(
+        &self,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+        shutdown: impl Future<Output = ()>,
+    ) -> TuringResult<()> {
+        if self.raft.is_some() && self.multi_writer.is_some() {
+            return Err(TuringDbError::InvalidInput);
+        }
+        let raft = match &self.raft {
+            None => None,
+            Some(raft) => Some(RaftNode::start(raft, Arc::clone(&engine), executor).await?),
+        };
+        let multi_writer = self.multi_writer.as_ref().map(|multi_writer| {
+            MultiWriterNode::start(multi_writer, Arc::clone(&engine), executor)
+        });
+        let gossip = self
+            .gossip
+            .as_ref()
+            .map(|gossip| GossipNode::start(gossip, executor));
+        let (closing_sender, closing) = async_channel::bounded(1);
+        let running = Arc::new(Running {
+            engine,
+            active: AtomicUsize::new(0),
+            closing,
+            started: Instant::now(),
+            admin_token: self.admin_token.clone(),
+            raft,
+            multi_writer,
+            router: self.router.as_ref().map(Router::new),
+            gossip,
+        });
+
+        future::or(self.accept(&running, executor), async {
+            shutdown.await;
+
+            Ok(())
+        })
+        .await?;
+
+        closing_sender.close();
+        let draining = Instant::now();
+        while running.active.load(Ordering::Acquire) > 0 && draining.elapsed() < self.drain_timeout
+        {
+            Timer::after(DRAIN_POLL_INTERVAL).await;
+        }
+        if let Some(raft) = &running.raft {
+            raft.stop();
+        }
+        if let Some(multi_writer) = &running.multi_writer {
+            multi_writer.stop();
+        }
+        if let Some(gossip) = &running.gossip {
+            gossip.stop();
+        }
+
+        #[cfg(unix)]
+        {
+            if let Some((path, _)) = &self.unix_socket {
+                async_fs::remove_file(path).await.ok();
+            }
+        }
+        running.engine.repo_close().await?;
+
+        Ok(())
+    }
+
+    async fn accept<'a>(
+        &self,
+        running: &Arc<Running>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        let tcp = self.accept_tcp(running, executor);
+
+        #[cfg(unix)]
+        {
+            if let Some((path, permissions)) = &self.unix_socket {
+                let listener = TuringServer::bind_unix(path, *permissions).await?;
+
+                return future::or(tcp, self.accept_unix(listener, running, executor)).await;
+            }
+        }
+
+        tcp.await
+    }
+
+    async fn accept_tcp<'a>(
+        &self,
+        running: &Arc<Running>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        let listener = Async::<TcpListener>::bind(self.address)?;
+        let rate_limiter = self
+            .rate_limit
+            .map(|rate_limit| Arc::new(RateLimiter::new(rate_limit)));
+        #[cfg(feature = "tls")]
+        let acceptor = match &self.tls {
+            None => None,
+            Some(tls) => Some(tls.acceptor().await?),
+        };
+
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    TuringServer::accept_failed(error).await;
+                    continue;
+                }
+            };
+            let slot = match ConnectionSlot::acquire(running, self.max_connections) {
+                Some(slot) => slot,
+                None => {
+                    // Over TLS the error could only be sent after a handshake, the connection is dropped
+                    #[cfg(feature = "tls")]
+                    {
+                        if acceptor.is_some() {
+                            continue;
+                        }
+                    }
+
+                    self.reject(stream.get_ref());
+                    continue;
+                }
+            };
+            let rate_limit = rate_limiter
+                .as_ref()
+                .map(|rate_limiter| (peer.ip(), Arc::clone(rate_limiter)));
+            let connection = self.connection(running, slot, rate_limit);
+
+            #[cfg(feature = "tls")]
+            {
+                if let Some(acceptor) = &acceptor {
+                    let acceptor = acceptor.clone();
+                    executor
+                        .spawn(async move {
+                            let stream = deadline(connection.io_timeout, async {
+                                Ok(acceptor.accept(stream).await?)
+                            })
+                            .await?;
+
+                            connection.serve(stream).await
+                        })
+                        .detach();
+
+                    continue;
+                }
+            }
+
+            executor.spawn(connection.serve(stream)).detach();
+        }
+    }
+
+    #[cfg(unix)]
+    async fn accept_unix<'a>(
+        &self,
+        listener: Async<UnixListener>,
+        running: &Arc<Running>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(error) => {
+                    TuringServer::accept_failed(error).await;
+                    continue;
+                }
+            };
+
+            match ConnectionSlot::acquire(running, self.max_connections) {
+                Some(slot) => executor
+                    .spawn(self.connection(running, slot, None).serve(stream))
+                    .detach(),
+                None => self.reject(stream.get_ref()),
+            }
+        }
+    }
+    /// A failed accept leaves the listener usable, so the server keeps accepting once the
+    /// connections holding the resources it ran out of are closed
+    async fn accept_failed(error: std::io::Error) {
+        eprintln!("Accepting a connection failed: {}", error);
+
+        Timer::after(ACCEPT_BACKOFF).await;
+    }
+    /// Bind the socket then set its permissions. A file at the path that is not a socket
+    /// is never removed, binding fails with `AlreadyExists` instead
+    #[cfg(unix)]
+    async fn bind_unix(path: &Utf8Path, permissions: u32) -> TuringResult<Async<UnixListener>> {
+        match async_fs::symlink_metadata(path).await {
+            Ok(metadata) if metadata.file_type().is_socket() => async_fs::remove_file(path).await?,
+            Ok(_) => return Err(TuringDbError::AlreadyExists),
+            Err(error) if error.kind() == ErrorKind::NotFound => (),
+            Err(error) => return Err(error.into()),
+        }
+
+        let listener = Async::<UnixListener>::bind(path)?;
+        async_fs::set_permissions(path, Permissions::from_mode(permissions)).await?;
+
+        Ok(listener)
+    }
+
+    fn connection(
+        &self,
+        running: &Arc<Running>,
+        slot: ConnectionSlot,
+        rate_limit: Option<(IpAddr, Arc<RateLimiter>)>,
+    ) -> Connection {
+        Connection {
+            running: Arc::clone(running),
+            max_frame_len: self.max_frame_len,
+            idle_timeout: self.idle_timeout,
+            io_timeout: self.io_timeout,
+            compression: None,
+            encoding: PayloadEncoding::Bincode,
+            rate_limit,
+            admin: AtomicBool::new(false),
+            transactions: DashSet::new(),
+            leases: DashSet::new(),
+            _slot: slot,
+        }
+    }
+    /// Tell a client past the connection limit why it is closed without waiting on it,
+    /// the frame is small enough for the send buffer of a new connection to take at once
+    fn reject(&self, mut stream: impl Write) {
+        let error = TuringDbError::TooManyConnections {
+            max: self.max_connections as u64,
+        };
+
+        if let Ok(frame) = Frame::encode(&TuringResponse::from(Err(error))) {
+            if let Ok(bytes) = frame.to_bytes() {
+                stream.write_all(&bytes).ok();
+            }
+        }
+    }
+
+    pub(crate) async fn dispatch(
+        engine: &TuringEngine,
+        command: TuringCommand,
+    ) -> TuringResult<OpsOutcome> {
+        match command {
+            TuringCommand::DbCreate { db } => {
+                engine
+                    .db_create(TuringDBOps::default().set_db_name(db.as_str()))
+                    .await
+            }
+            TuringCommand::DocumentCreate { db, document } => {
+                engine
+                    .document_create(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name(db.as_str())
+                            .set_document_name(document.as_str()),
+                    )
+                    .await
+            }
+            TuringCommand::DocumentDrop { db, document } => {
+                engine
+                    .document_drop(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name(db.as_str())
+                            .set_document_name(document.as_str()),
+                    )
+                    .await
+            }
+            TuringCommand::FieldInsert {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                engine
+                    .field_insert(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key)
+                            .value(value.get_data_type(), value.get_data()),
+                    )
+                    .await
+            }
+            TuringCommand::FieldGet { db, document, key } => {
+                engine
+                    .field_get(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key),
+                    )
+                    .await
+            }
+            TuringCommand::DocumentGet { db, document } => {
+                let view = engine
+                    .document_view(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name(db.as_str())
+                            .set_document_name(document.as_str()),
+                    )
+                    .await?;
+
+                Ok(OpsOutcome::DocumentRevision {
+                    revision: view.revision(),
+                    fields: view.field_scan()?,
+                })
+            }
+            TuringCommand::DocumentUpdateIf {
+                db,
+                document,
+                expected_revision,
+                fields,
+            } => {
+                engine
+                    .document_update_if(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name(db.as_str())
+                            .set_document_name(document.as_str()),
+                        expected_revision,
+                        fields,
+                    )
+                    .await
+            }
+            TuringCommand::Query(query) => engine.select(&query).await,
+            TuringCommand::Execute(statement) => engine.execute_statement(&statement).await,
+            TuringCommand::DbDrop { db } => {
+                engine
+                    .db_drop(TuringDBOps::default().set_db_name(db.as_str()))
+                    .await
+            }
+            TuringCommand::DbList => Ok(engine.db_list_sorted()),
+            TuringCommand::DocumentList { db } => {
+                engine
+                    .document_list_sorted(&TuringDBOps::default().set_db_name(db.as_str()))
+                    .await
+            }
+            TuringCommand::FieldModify {
+                db,
+                document,
+                key,
+                value,
+            } => {
+                engine
+                    .field_modify(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key)
+                            .value(value.get_data_type(), value.get_data()),
+                    )
+                    .await
+            }
+            TuringCommand::FieldRemove { db, document, key } => {
+                engine
+                    .field_remove(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key),
+                    )
+                    .await
+            }
+            TuringCommand::FieldConflicts { db, document, key } => {
+                engine
+                    .field_conflicts(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key),
+                    )
+                    .await
+            }
+            // Only a writer of a multi-writer cluster stamps the writes that settle conflicts
+            TuringCommand::FieldResolve { .. } => Err(TuringDbError::NotReplicated),
+            TuringCommand::CrdtUpdate {
+                db,
+                document,
+                key,
+                op,
+            } => {
+                let (state, _) = engine
+                    .crdt_update(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key),
+                        op,
+                        0,
+                    )
+                    .await?;
+
+                Ok(OpsOutcome::CrdtState(state))
+            }
+            TuringCommand::CrdtGet { db, document, key } => {
+                engine
+                    .crdt_get(
+                        &TuringDBFieldOps::default()
+                            .db(db.as_str())
+                            .document(document.as_str())
+                            .key(&key),
+                    )
+                    .await
+            }
+            TuringCommand::Ping | TuringCommand::Authenticate { .. } => Err(TuringDbError::Bug(
+                "Pings and authentication are answered by the connection".into(),
+            )),
+            TuringCommand::Topology => Err(TuringDbError::Bug(
+                "Topology requests are answered by the connection".into(),
+            )),
+            TuringCommand::Bounded { .. } => Err(TuringDbError::Bug(
+                "Bounded reads are answered by the connection".into(),
+            )),
+            TuringCommand::DocumentLock { .. } | TuringCommand::DocumentUnlock { .. } => Err(
+                TuringDbError::Bug("Lock commands are answered by the connection".into()),
+            ),
+            TuringCommand::RepoStats
+            | TuringCommand::DbStats { .. }
+            | TuringCommand::DocumentStats { .. }
+            | TuringCommand::Commit
+            | TuringCommand::Compact
+            | TuringCommand::Backup { .. }
+            | TuringCommand::ServerInfo
+            | TuringCommand::ReplicaSnapshot
+            | TuringCommand::LogRead { .. }
+            | TuringCommand::ReplicaPromote
+            | TuringCommand::RaftRequestVote { .. }
+            | TuringCommand::RaftAppendEntries { .. }
+            | TuringCommand::RaftInstallSnapshot { .. }
+            | TuringCommand::FieldMerge { .. }
+            | TuringCommand::PeerWrite { .. }
+            | TuringCommand::Gossip { .. }
+            | TuringCommand::MerkleTree { .. }
+            | TuringCommand::MerkleLeaf { .. }
+            | TuringCommand::CrdtMerge { .. } => Err(TuringDbError::Bug(
+                "Admin commands are answered by the connection".into(),
+            )),
+            TuringCommand::TransactionBegin { .. }
+            | TuringCommand::TransactionWrite { .. }
+            | TuringCommand::TransactionWriteTo { .. }
+            | TuringCommand::TransactionCommit { .. }
+            | TuringCommand::TransactionRollback { .. }
+            | TuringCommand::TransactionSavepoint { .. }
+            | TuringCommand::TransactionRollbackTo { .. } => Err(TuringDbError::Bug(
+                "Transaction commands are answered by the connection".into(),
+            )),
+        }
+    }
+}
+
+impl Connection {
+    /// Answer the commands of a connection after the handshake. A frame that cannot be read
+    /// is answered with the error before the connection is closed since the rest of the stream
+    /// can no longer be split into frames. A connection left idle is closed without an answer
+    async fn serve<S: AsyncRead + AsyncWrite + Unpin>(mut self, stream: S) -> TuringResult<()> {
+        let mut stream = BufReader::new(stream);
+        let hello = self.handshake(&mut stream).await?;
+        self.compression = wire_compression(hello.get_features());
+        self.encoding = wire_encoding(hello.get_features());
+
+        if hello
+            .get_features()
+            .iter()
+            .any(|feature| feature == PIPELINING)
+        {
+            self.serve_pipelined(stream).await
+        } else {
+            self.serve_in_order(stream).await
+        }
+    }
+    /// Run each command once the previous one was answered
+    async fn serve_in_order<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        mut stream: BufReader<S>,
+    ) -> TuringResult<()> {
+        loop {
+            let frame = match self.next_frame(&mut stream).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(error) => {
+                    self.respond(stream.get_mut(), 0, Err(error.clone()))
+                        .await?;
+
+                    return Err(error);
+                }
+            };
+
+            let response = self.execute(&frame).await;
+            self.send(stream.get_mut(), frame.request_id(), &response)
+                .await?;
+        }
+    }
+    /// Run up to `MAX_IN_FLIGHT` commands at once while more are read, answering each one
+    /// as soon as it is done. Once the client stops sending, the commands still running
+    /// are answered before the connection is closed
+    async fn serve_pipelined<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: BufReader<S>,
+    ) -> TuringResult<()> {
+        // Frames the client sent right after its hello may already sit in the buffer
+        let buffered = io::Cursor::new(stream.buffer().to_vec());
+        let (reader, writer) = io::split(stream.into_inner());
+        let in_flight = Semaphore::new(MAX_IN_FLIGHT);
+        let (responses_sender, responses) = async_channel::unbounded();
+        let tasks = Executor::new();
+
+        let reading = self.read_commands(
+            BufReader::new(buffered.chain(reader)),
+            &in_flight,
+            &tasks,
+            responses_sender,
+        );
+        tasks
+            .run(future::try_zip(
+                reading,
+                self.write_responses(writer, responses),
+            ))
+            .await?;
+
+        Ok(())
+    }
+    /// Run every command read on a task of `tasks`. A frame that cannot be read is answered
+    /// with the error and ends the reading, the commands still running are answered all the same
+    async fn read_commands<'t, R: AsyncBufRead + Unpin>(
+        &'t self,
+        mut reader: R,
+        in_flight: &'t Semaphore,
+        tasks: &Executor<'t>,
+        responses: Sender<(u64, TuringResponse)>,
+    ) -> TuringResult<()> {
+        loop {
+            let frame = match self.next_frame(&mut reader).await {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(error) => {
+                    responses
+                        .send((0, TuringResponse::from(Err(error))))
+                        .await
+                        .ok();
+
+                    return Ok(());
+                }
+            };
+
+            let permit = in_flight.acquire().await;
+            let responses = responses.clone();
+            tasks
+                .spawn(async move {
+                    let response = self.execute(&frame).await;
+                    responses.send((frame.request_id(), response)).await.ok();
+
+                    drop(permit);
+                })
+                .detach();
+        }
+    }
+    // Ends once the reading ended and every command it started was answered,
+    // when the last sender of `responses` is dropped
+    async fn write_responses<W: AsyncWrite + Unpin>(
+        &self,
+        mut writer: W,
+        responses: Receiver<(u64, TuringResponse)>,
+    ) -> TuringResult<()> {
+        while let Ok((request_id, response)) = responses.recv().await {
+            self.send(&mut writer, request_id, &response).await?;
+        }
+
+        Ok(())
+    }
+    /// The next frame of the client, `None` once it closed the connection, stayed idle
+    /// for the idle timeout or the server shuts down. A client that keeps pinging is never idle,
+    /// so a connection whose client went away without closing it is reaped after the idle timeout.
+    /// Once a frame started it must arrive within the I/O timeout
+    async fn next_frame<R: AsyncBufRead + Unpin>(
+        &self,
+        stream: &mut R,
+    ) -> TuringResult<Option<Frame>> {
+        let idle = future::or(
+            deadline(self.idle_timeout, async {
+                Ok(stream.fill_buf().await?.is_empty())
+            }),
+            async {
+                self.running.closing.recv().await.ok();
+
+                Ok(true)
+            },
+        )
+        .await;
+        match idle {
+            Ok(false) => (),
+            Ok(true) | Err(TuringDbError::TimedOut) => return Ok(None),
+            Err(error) => return Err(error),
+        }
+
+        match deadline(self.io_timeout, Frame::read(stream, self.max_frame_len)).await? {
+            Some(frame) => Ok(Some(
+                frame.decompress(self.compression, self.max_frame_len)?,
+            )),
+            None => Ok(None),
+        }
+    }
+    /// Run a command unless its client is over the rate limit, pings are never limited
+    async fn execute(&self, frame: &Frame) -> TuringResponse {
+        match frame.decode_with::<TuringCommand>(self.encoding) {
+            Ok(TuringCommand::Ping) => TuringResponse::Pong,
+            Ok(command) => {
+                if let Some((client, rate_limiter)) = &self.rate_limit {
+                    if let Err(retry_after) = rate_limiter.acquire(*client) {
+                        return TuringResponse::Throttled {
+                            retry_after_ms: retry_after.as_millis() as u64,
+                        };
+                    }
+                }
+
+                let keys = ErrorKeys::of(&command);
+                let response = match command {
+                    TuringCommand::Authenticate { token } => {
+                        self.authenticate(&token).map(|()| TuringResponse::Done)
+                    }
+                    TuringCommand::Topology => self.topology().await,
+                    TuringCommand::Gossip { members } => self.gossip(members).await,
+                    command if self.running.router.is_some() && !command.is_admin() => {
+                        self.route(command).await
+                    }
+                    TuringCommand::Bounded {
+                        max_staleness_ms,
+                        command,
+                    } => {
+                        self.bounded(Duration::from_millis(max_staleness_ms), *command)
+                            .await
+                    }
+                    command if command.is_raft() => self.raft(command).await,
+                    command if command.is_peer_write() => self.peer_write(command).await,
+                    command if command.is_admin() => self.admin(command).await,
+                    command if command.is_transaction() => self.transaction(command).await,
+                    command if command.is_lock() => self.lock(command).await,
+                    command if command.is_write() && self.running.raft.is_some() => {
+                        self.replicate(command).await
+                    }
+                    command if command.is_write() && self.running.multi_writer.is_some() => {
+                        self.write_everywhere(command).await
+                    }
+                    command => TuringServer::dispatch(&self.running.engine, command)
+                        .await
+                        .map(|outcome| TuringResponse::from(Ok(outcome))),
+                };
+
+                match response {
+                    Ok(response) => response,
+                    Err(error) => TuringResponse::error(&error, keys.offending(&error)),
+                }
+            }
+            Err(error) => TuringResponse::from(Err(error)),
+        }
+    }
+    /// Tokens are compared in constant time so their bytes cannot be guessed one at a time
+    fn authenticate(&self, token: &str) -> TuringResult<()> {
+        let admin_token = match &self.running.admin_token {
+            Some(admin_token) => admin_token.as_bytes(),
+            None => return Err(TuringDbError::PermissionDenied),
+        };
+
+        let differences = admin_token
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0_u8, |differences, (left, right)| {
+                differences | (left ^ right)
+            });
+        if differences != 0 || admin_token.len() != token.len() {
+            return Err(TuringDbError::PermissionDenied);
+        }
+        self.admin.store(true, Ordering::Release);
+
+        Ok(())
+    }
+
+    async fn admin(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        if !self.admin.load(Ordering::Acquire) {
+            return Err(TuringDbError::PermissionDenied);
+        }
+
+        let engine = &self.running.engine;
+        let outcome = match command {
+            TuringCommand::RepoStats => engine.stats().await?,
+            TuringCommand::DbStats { db } => {
+                engine
+                    .db_stats(&TuringDBOps::default().set_db_name(db.as_str()))
+                    .await?
+            }
+            TuringCommand::DocumentStats { db, document } => {
+                let fields = engine
+                    .document_view(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name(db.as_str())
+                            .set_document_name(document.as_str()),
+                    )
+                    .await?
+                    .field_scan()?;
+
+                return Ok(TuringResponse::DocumentStats {
+                    fields: fields.len(),
+                    bytes: fields
+                        .iter()
+                        .map(|(key, field_data)| (key.len() + field_data.data().len()) as u64)
+                        .sum(),
+                });
+            }
+            TuringCommand::Commit => engine.repo_commit().await?,
+            TuringCommand::Compact => engine.log_compact().await?,
+            TuringCommand::Backup { target_dir } => engine.backup_to(&target_dir).await?,
+            TuringCommand::ServerInfo => {
+                return Ok(TuringResponse::ServerInfo(ServerInfo::new(
+                    self.running.started.elapsed(),
+                    self.running.active.load(Ordering::Acquire),
+                )))
+            }
+            TuringCommand::ReplicaSnapshot => {
+                return Ok(TuringResponse::ReplicaSnapshot(
+                    engine.replica_snapshot().await?,
+                ))
+            }
+            TuringCommand::LogRead { after, limit } => {
+                return Ok(TuringResponse::Records(
+                    engine.log_read(after, limit as usize).await?,
+                ))
+            }
+            TuringCommand::ReplicaPromote => engine.replica_promote().await?,
+            TuringCommand::MerkleTree { db } => {
+                return Ok(TuringResponse::MerkleTree(
+                    engine
+                        .merkle_tree(&TuringDBOps::default().set_db_name(db.as_str()))
+                        .await?,
+                ))
+            }
+            TuringCommand::MerkleLeaf { db, leaf } => {
+                return Ok(TuringResponse::DocumentHashes(
+                    engine
+                        .merkle_leaf(
+                            &TuringDBOps::default().set_db_name(db.as_str()),
+                            leaf as usize,
+                        )
+                        .await?,
+                ))
+            }
+            _ => {
+                return Err(TuringDbError::Bug(
+                    "Only admin commands are answered as one".into(),
+                ))
+            }
+        };
+
+        Ok(TuringResponse::from(Ok(outcome)))
+    }
+    /// Forward a command to the nodes of a sharded cluster
+    async fn route(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        match &self.running.router {
+            Some(router) => router.forward(command).await,
+            None => Err(TuringDbError::Bug("Only a router forwards commands".into())),
+        }
+    }
+    /// Answer a peer of the Raft cluster, the peers authenticate with the admin token
+    async fn raft(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        if !self.admin.load(Ordering::Acquire) {
+            return Err(TuringDbError::PermissionDenied);
+        }
+        let raft = match &self.running.raft {
+            Some(raft) => raft,
+            None => return Err(TuringDbError::NotReplicated),
+        };
+
+        match command {
+            TuringCommand::RaftRequestVote {
+                term,
+                candidate,
+                last_log_index,
+                last_log_term,
+            } => {
+                raft.request_vote(term, candidate, last_log_index, last_log_term)
+                    .await
+            }
+            TuringCommand::RaftAppendEntries {
+                term,
+                leader,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+            } => {
+                raft.append_entries(
+                    term,
+                    leader,
+                    prev_log_index,
+                    prev_log_term,
+                    entries,
+                    leader_commit,
+                )
+                .await
+            }
+            TuringCommand::RaftInstallSnapshot {
+                term,
+                leader,
+                last_index,
+                last_term,
+                ops,
+            } => {
+                raft.install_snapshot(term, leader, last_index, last_term, ops)
+                    .await
+            }
+            _ => Err(TuringDbError::Bug(
+                "Only Raft commands are answered as one".into(),
+            )),
+        }
+    }
+    /// Run a read once the repo is known to lag behind its primary by at most `max_staleness`
+    async fn bounded(
+        &self,
+        max_staleness: Duration,
+        command: TuringCommand,
+    ) -> TuringResult<TuringResponse> {
+        if !command.is_read() {
+            return Err(TuringDbError::InvalidInput);
+        }
+        self.running.engine.replica_within(max_staleness).await?;
+
+        TuringServer::dispatch(&self.running.engine, command)
+            .await
+            .map(|outcome| TuringResponse::from(Ok(outcome)))
+    }
+    /// The nodes of the cluster, answered by a router as well so clients can route past it
+    async fn topology(&self) -> TuringResult<TuringResponse> {
+        match &self.running.gossip {
+            Some(gossip) => Ok(TuringResponse::Topology(gossip.topology().await)),
+            None => Err(TuringDbError::NotClustered),
+        }
+    }
+    /// Take in the members another node of the cluster sent, the nodes authenticate with the admin token
+    async fn gossip(&self, members: Vec<Member>) -> TuringResult<TuringResponse> {
+        if !self.admin.load(Ordering::Acquire) {
+            return Err(TuringDbError::PermissionDenied);
+        }
+
+        match &self.running.gossip {
+            Some(gossip) => Ok(TuringResponse::Topology(gossip.gossip(members).await)),
+            None => Err(TuringDbError::NotClustered),
+        }
+    }
+    /// Run a write once the Raft cluster committed it, a follower refuses it with `NotLeader`
+    async fn replicate(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        match &self.running.raft {
+            Some(raft) => Ok(TuringResponse::from(Ok(raft.propose(command).await?))),
+            None => Err(TuringDbError::Bug(
+                "Only writes of a Raft node are replicated".into(),
+            )),
+        }
+    }
+    /// Apply a write another writer of the cluster sent, the writers authenticate with the admin token
+    async fn peer_write(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        if !self.admin.load(Ordering::Acquire) {
+            return Err(TuringDbError::PermissionDenied);
+        }
+
+        match &self.running.multi_writer {
+            Some(multi_writer) => Ok(TuringResponse::from(Ok(multi_writer
+                .merge(command)
+                .await?))),
+            None => Err(TuringDbError::NotReplicated),
+        }
+    }
+    /// Run a write then send it to the other writers of the cluster
+    async fn write_everywhere(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        match &self.running.multi_writer {
+            Some(multi_writer) => Ok(TuringResponse::from(Ok(multi_writer
+                .write(command)
+                .await?))),
+            None => Err(TuringDbError::Bug(
+                "Only writes of a multi-writer node are sent to its peers".into(),
+            )),
+        }
+    }
+    /// A connection only reaches the transactions it began, any other id is `TransactionNotFound`.
+    /// Transactions are refused on a node of a Raft or a multi-writer cluster since their commit is not replicated
+    async fn transaction(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        if self.running.raft.is_some() || self.running.multi_writer.is_some() {
+            return Err(TuringDbError::NotReplicated);
+        }
+        let engine = &self.running.engine;
+        let owned = |transaction: u64| {
+            if self.transactions.contains(&transaction) {
+                Ok(())
+            } else {
+                Err(TuringDbError::TransactionNotFound)
+            }
+        };
+
+        let outcome = match command {
+            TuringCommand::TransactionBegin { db } => {
+                let outcome = engine
+                    .transaction_begin(&TuringDBOps::default().set_db_name(db.as_str()))
+                    .await?;
+                if let OpsOutcome::TransactionBegun { transaction } = outcome {
+                    self.transactions.insert(transaction);
+                }
+
+                outcome
+            }
+            TuringCommand::TransactionWrite { transaction, op } => {
+                owned(transaction)?;
+                engine.transaction_write(transaction, op).await?
+            }
+            TuringCommand::TransactionWriteTo {
+                transaction,
+                db,
+                op,
+            } => {
+                owned(transaction)?;
+                engine
+                    .transaction_write_to(
+                        transaction,
+                        &TuringDBOps::default().set_db_name(db.as_str()),
+                        op,
+                    )
+                    .await?
+            }
+            // The connection keeps a transaction whose commit failed while the engine keeps it open,
+            // so the client can commit it again or roll it back
+            TuringCommand::TransactionCommit { transaction } => {
+                owned(transaction)?;
+                let outcome = engine.transaction_commit(transaction).await;
+                if !engine.transaction_open(transaction) {
+                    self.transactions.remove(&transaction);
+                }
+
+                outcome?
+            }
+            TuringCommand::TransactionRollback { transaction } => {
+                owned(transaction)?;
+                let outcome = engine.transaction_rollback(transaction).await;
+                if !engine.transaction_open(transaction) {
+                    self.transactions.remove(&transaction);
+                }
+
+                outcome?
+            }
+            TuringCommand::TransactionSavepoint { transaction, name } => {
+                owned(transaction)?;
+                engine.transaction_savepoint(transaction, &name).await?
+            }
+            TuringCommand::TransactionRollbackTo { transaction, name } => {
+                owned(transaction)?;
+                engine.transaction_rollback_to(transaction, &name).await?
+            }
+            _ => {
+                return Err(TuringDbError::Bug(
+                    "Only transaction commands are answered as one".into(),
+                ))
+            }
+        };
+
+        Ok(TuringResponse::from(Ok(outcome)))
+    }
+    /// Take or release a document lock, keeping the leases of the connection
+    /// so the ones it still holds are released when it closes
+    async fn lock(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        let engine = &self.running.engine;
+
+        let outcome = match command {
+            TuringCommand::DocumentLock {
+                db,
+                document,
+                ttl_ms,
+            } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(db.as_str())
+                    .set_document_name(document.as_str());
+                let outcome = engine
+                    .document_lock(&ops, Duration::from_millis(ttl_ms))
+                    .await?;
+                if let OpsOutcome::DocumentLeased { token } = outcome {
+                    self.leases.insert((db, document, token));
+                }
+
+                outcome
+            }
+            TuringCommand::DocumentUnlock {
+                db,
+                document,
+                token,
+            } => {
+                let ops = TuringDBDocumentOps::default()
+                    .set_db_name(db.as_str())
+                    .set_document_name(document.as_str());
+                self.leases.remove(&(db, document, token));
+                engine.document_unlock(&ops, token).await?
+            }
+            _ => {
+                return Err(TuringDbError::Bug(
+                    "Only lock commands are answered as one".into(),
+                ))
+            }
+        };
+
+        Ok(TuringResponse::from(Ok(outcome)))
+    }
+    /// Read the `ClientHello` and answer it, a client speaking another version of the protocol
+    /// is sent `UnsupportedFormat` and disconnected. A client that sends an older frame header
+    /// gets the same error while reading the frame
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut BufReader<S>,
+    ) -> TuringResult<ServerHello> {
+        let frame = match deadline(
+            self.io_timeout,
+            Frame::read(&mut *stream, self.max_frame_len),
+        )
+        .await
+        {
+            Ok(None) => return Err(TuringDbError::UnexpectedEof),
+            Ok(Some(frame)) => frame,
+            Err(error) => {
+                self.respond(stream.get_mut(), 0, Err(error.clone()))
+                    .await?;
+
+                return Err(error);
+            }
+        };
+
+        match frame.decode::<ClientHello>().and_then(ServerHello::answer) {
+            Ok(hello) => {
+                self.send(stream.get_mut(), frame.request_id(), &hello)
+                    .await?;
+
+                Ok(hello)
+            }
+            Err(error) => {
+                self.respond(stream.get_mut(), frame.request_id(), Err(error.clone()))
+                    .await?;
+
+                Err(error)
+            }
+        }
+    }
+
+    async fn respond<S: AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        request_id: u64,
+        outcome: TuringResult<OpsOutcome>,
+    ) -> TuringResult<()> {
+        self.send(stream, request_id, &TuringResponse::from(outcome))
+            .await
+    }
+
+    async fn send<S: AsyncWrite + Unpin, M: WireMessage>(
+        &self,
+        stream: &mut S,
+        request_id: u64,
+        message: &M,
+    ) -> TuringResult<()> {
+        let frame = Frame::encode_with(message, self.encoding)?
+            .set_request_id(request_id)
+            .compress(self.compression)?;
+
+        deadline(self.io_timeout, frame.write(stream)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ErrorCode, TuringClient};
+    use futures_lite::future::block_on;
+
+    fn free_address() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+    }
+    /// Connect once the server listens, giving up after a second
+    async fn connect(address: SocketAddr) -> TuringResult<TuringClient> {
+        let mut attempts = 0;
+        loop {
+            match TuringClient::connect(address).await {
+                Err(TuringDbError::ConnectionRefused) if attempts < 100 => {
+                    attempts += 1;
+                    Timer::after(Duration::from_millis(10)).await;
+                }
+                connected => return connected,
+            }
+        }
+    }
+
+    #[test]
+    fn a_connection_past_the_limit_is_refused_until_a_slot_frees() {
+        let executor = Executor::new();
+        let address = free_address();
+        let server = TuringServer::new()
+            .set_address(address)
+            .set_max_connections(1);
+        let (stop, stopped) = async_channel::bounded::<()>(1);
+
+        let serving = server.run_until(Arc::new(TuringEngine::ephemeral()), &executor, async {
+            stopped.recv().await.ok();
+        });
+        let connecting = async {
+            let mut first = connect(address).await.unwrap();
+            first.ping().await.unwrap();
+
+            match TuringClient::connect(address).await {
+                Err(TuringDbError::Server(error)) => {
+                    assert_eq!(error.get_code(), ErrorCode::Unavailable);
+                    assert!(error.is_retryable());
+                }
+                outcome => panic!("A second connection got {:?}", outcome.map(|_| ())),
+            }
+
+            // The slot frees once the server sees the first connection close
+            drop(first);
+            let mut attempts = 0;
+            let mut third = loop {
+                match TuringClient::connect(address).await {
+                    Ok(client) => break client,
+                    Err(_) if attempts < 100 => {
+                        attempts += 1;
+                        Timer::after(Duration::from_millis(10)).await;
+                    }
+                    Err(error) => panic!("No slot freed up: {:?}", error),
+                }
+            };
+            third.ping().await.unwrap();
+
+            stop.close();
+        };
+
+        let (served, ()) = block_on(executor.run(future::zip(serving, connecting)));
+        served.unwrap();
+    }
+}
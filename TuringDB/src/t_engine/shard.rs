@@ -0,0 +1,353 @@
+use crate::{TuringClient, TuringCommand, TuringDbError, TuringResponse, TuringResult};
+use async_lock::Mutex;
+use camino::Utf8PathBuf;
+use std::{
+    collections::{BTreeMap, HashMap},
+    net::SocketAddr,
+};
+
+/// How many points each node is given on a `HashRing` by default,
+/// more points spread the documents more evenly across the nodes
+pub const DEFAULT_VIRTUAL_NODES: usize = 64;
+
+/// Where a command is sent in a sharded cluster
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub enum Route {
+///     Shard(SocketAddr),
+///     Every,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    /// The node holding the document the command names
+    Shard(SocketAddr),
+    /// Every node, for the commands on whole databases
+    Every,
+}
+
+/// Spreads the documents of every database across the nodes of a sharded cluster by consistent
+/// hashing of the database and document names. Each node is given `virtual_nodes` points on the
+/// ring and a document belongs to the node of the first point at or after its hash, so adding or
+/// removing a node only moves the documents of the points next to its own.
+/// Documents are not moved between nodes when the ring changes, that is left to the operator
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct HashRing {
+///     virtual_nodes: usize,
+///     nodes: Vec<SocketAddr>,
+///     points: BTreeMap<u64, SocketAddr>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    nodes: Vec<SocketAddr>,
+    points: BTreeMap<u64, SocketAddr>,
+}
+
+impl Default for HashRing {
+    fn default() -> Self {
+        Self {
+            virtual_nodes: DEFAULT_VIRTUAL_NODES,
+            nodes: Vec::new(),
+            points: BTreeMap::new(),
+        }
+    }
+}
+
+impl HashRing {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Every client and router of the cluster has to use the same number of points
+    pub fn set_virtual_nodes(mut self, virtual_nodes: usize) -> Self {
+        self.virtual_nodes = virtual_nodes.max(1);
+        self.place();
+
+        self
+    }
+    /// A `TuringServer` holding a shard, listening on `address`
+    pub fn add_node(mut self, address: SocketAddr) -> Self {
+        if !self.nodes.contains(&address) {
+            self.nodes.push(address);
+            self.place();
+        }
+
+        self
+    }
+
+    pub fn remove_node(mut self, address: SocketAddr) -> Self {
+        self.nodes.retain(|node| *node != address);
+        self.place();
+
+        self
+    }
+
+    pub fn get_virtual_nodes(&self) -> usize {
+        self.virtual_nodes
+    }
+
+    pub fn get_nodes(&self) -> &[SocketAddr] {
+        &self.nodes
+    }
+    /// The node holding the document `document` of the database `db`, `None` on an empty ring
+    pub fn node_for(&self, db: &str, document: &str) -> Option<SocketAddr> {
+        let mut key = Vec::with_capacity(db.len() + 1 + document.len());
+        key.extend_from_slice(db.as_bytes());
+        // Keeps `ab` + `c` apart from `a` + `bc`
+        key.push(0);
+        key.extend_from_slice(document.as_bytes());
+        let hash = seahash::hash(&key);
+
+        self.points
+            .range(hash..)
+            .next()
+            .or_else(|| self.points.iter().next())
+            .map(|(_, node)| *node)
+    }
+    /// Where `command` is sent, `None` for the commands a sharded cluster does not serve,
+    /// queries, TuringQL, transactions, locks and admin commands among them
+    pub fn route(&self, command: &TuringCommand) -> Option<Route> {
+        match command {
+            TuringCommand::DocumentCreate { db, document }
+            | TuringCommand::DocumentDrop { db, document }
+            | TuringCommand::FieldInsert { db, document, .. }
+            | TuringCommand::FieldGet { db, document, .. }
+            | TuringCommand::FieldModify { db, document, .. }
+            | TuringCommand::FieldRemove { db, document, .. }
+            | TuringCommand::DocumentGet { db, document }
+            | TuringCommand::DocumentUpdateIf { db, document, .. }
+            | TuringCommand::FieldConflicts { db, document, .. }
+            | TuringCommand::FieldResolve { db, document, .. }
+            | TuringCommand::CrdtUpdate { db, document, .. }
+            | TuringCommand::CrdtGet { db, document, .. } => self
+                .node_for(db.as_str(), document.as_str())
+                .map(Route::Shard),
+            TuringCommand::Bounded { command, .. } => self.route(command),
+            TuringCommand::DbCreate { .. }
+            | TuringCommand::DbDrop { .. }
+            | TuringCommand::DbList
+            | TuringCommand::DocumentList { .. } => {
+                if self.nodes.is_empty() {
+                    None
+                } else {
+                    Some(Route::Every)
+                }
+            }
+            _ => None,
+        }
+    }
+    /// The response of every node to a command sent to all of them as one. The names of databases
+    /// or documents are gathered from every node, any other command answers as the first node did
+    pub(crate) fn merge(responses: Vec<TuringResponse>) -> TuringResult<TuringResponse> {
+        if responses
+            .iter()
+            .all(|response| matches!(response, TuringResponse::Names(_)))
+        {
+            let mut names = responses
+                .into_iter()
+                .flat_map(|response| match response {
+                    TuringResponse::Names(names) => names,
+                    _ => Vec::new(),
+                })
+                .collect::<Vec<Utf8PathBuf>>();
+            names.sort();
+            names.dedup();
+
+            return Ok(TuringResponse::Names(names));
+        }
+
+        match responses.into_iter().next() {
+            Some(response) => Ok(response),
+            None => Err(TuringDbError::NotSharded),
+        }
+    }
+
+    fn place(&mut self) {
+        self.points.clear();
+
+        for node in &self.nodes {
+            for point in 0..self.virtual_nodes {
+                let hash = seahash::hash(format!("{}#{}", node, point).as_bytes());
+                self.points.insert(hash, *node);
+            }
+        }
+    }
+}
+
+/// A client of a sharded cluster holding a connection to every node of its `HashRing`.
+/// The commands on a document go to the node holding it, the ones on whole databases
+/// go to every node. A database is created on every node and a failure on one of them
+/// leaves it on the others, creating it again then fails with `AlreadyExists` on those
+/// ```
+/// #[derive(Debug)]
+/// pub struct ShardedClient {
+///     ring: HashRing,
+///     clients: HashMap<SocketAddr, TuringClient>,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ShardedClient {
+    ring: HashRing,
+    clients: HashMap<SocketAddr, TuringClient>,
+}
+
+impl ShardedClient {
+    /// Connect to every node of `ring`
+    pub async fn connect(ring: HashRing) -> TuringResult<ShardedClient> {
+        let mut clients = HashMap::with_capacity(ring.nodes.len());
+        for node in &ring.nodes {
+            clients.insert(*node, TuringClient::connect(*node).await?);
+        }
+
+        Ok(ShardedClient { ring, clients })
+    }
+    /// Connect to the shards of the cluster `seed` gossips with, the ring is the one of its `Topology`.
+    /// The ring is not followed as the cluster changes, discovering it again picks up the changes
+    pub async fn discover(seed: SocketAddr) -> TuringResult<ShardedClient> {
+        let topology = TuringClient::connect(seed).await?.topology().await?;
+
+        ShardedClient::connect(topology.ring()).await
+    }
+
+    pub fn get_ring(&self) -> &HashRing {
+        &self.ring
+    }
+    /// Authenticate with every node, they share the admin token
+    pub async fn authenticate(&mut self, token: &str) -> TuringResult<()> {
+        for client in self.clients.values_mut() {
+            client.authenticate(token).await?;
+        }
+
+        Ok(())
+    }
+    /// The connection to the node holding a document, for any command on the document
+    pub fn shard(&mut self, db: &str, document: &str) -> TuringResult<&mut TuringClient> {
+        let node = self
+            .ring
+            .node_for(db, document)
+            .ok_or(TuringDbError::NotSharded)?;
+
+        self.clients.get_mut(&node).ok_or(TuringDbError::NotSharded)
+    }
+    /// Send a command to the node or nodes `HashRing::route` sends it to
+    pub async fn request(&mut self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        match self.ring.route(&command) {
+            None => Err(TuringDbError::NotSharded),
+            Some(Route::Shard(node)) => match self.clients.get_mut(&node) {
+                Some(client) => client.request(command).await,
+                None => Err(TuringDbError::NotSharded),
+            },
+            Some(Route::Every) => {
+                let mut responses = Vec::with_capacity(self.clients.len());
+                for node in &self.ring.nodes {
+                    if let Some(client) = self.clients.get_mut(node) {
+                        responses.push(client.request(command.clone()).await?);
+                    }
+                }
+
+                HashRing::merge(responses)
+            }
+        }
+    }
+
+    pub async fn db_create(&mut self, db: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DbCreate { db: db.into() })
+            .await?;
+
+        ShardedClient::done(response)
+    }
+
+    pub async fn db_drop(&mut self, db: &str) -> TuringResult<()> {
+        let response = self
+            .request(TuringCommand::DbDrop { db: db.into() })
+            .await?;
+
+        ShardedClient::done(response)
+    }
+    /// The databases of every node, sorted by name
+    pub async fn db_list(&mut self) -> TuringResult<Vec<Utf8PathBuf>> {
+        match self.request(TuringCommand::DbList).await? {
+            TuringResponse::Names(names) => Ok(names),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+    /// The documents of a database on every node, sorted by name
+    pub async fn document_list(&mut self, db: &str) -> TuringResult<Vec<Utf8PathBuf>> {
+        match self
+            .request(TuringCommand::DocumentList { db: db.into() })
+            .await?
+        {
+            TuringResponse::Names(names) => Ok(names),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+
+    fn done(response: TuringResponse) -> TuringResult<()> {
+        match response {
+            TuringResponse::Done => Ok(()),
+            _ => Err(TuringDbError::InvalidData),
+        }
+    }
+}
+
+/// Forwards the commands of a `TuringServer` in router mode to the nodes of its `HashRing`,
+/// over one connection per node opened the first time a command is sent to it
+#[derive(Debug)]
+pub(crate) struct Router {
+    ring: HashRing,
+    clients: HashMap<SocketAddr, Mutex<Option<TuringClient>>>,
+}
+
+impl Router {
+    pub(crate) fn new(ring: &HashRing) -> Self {
+        Self {
+            clients: ring
+                .nodes
+                .iter()
+                .map(|node| (*node, Mutex::new(None)))
+                .collect(),
+            ring: ring.clone(),
+        }
+    }
+    /// Run a command on the node or nodes holding what it names. An error a node answers with
+    /// is passed on to the client as the node sent it
+    pub(crate) async fn forward(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        let outcome = match self.ring.route(&command) {
+            None => Err(TuringDbError::NotSharded),
+            Some(Route::Shard(node)) => self.send(node, command).await,
+            Some(Route::Every) => self.broadcast(command).await,
+        };
+
+        match outcome {
+            Err(TuringDbError::Server(error)) => Ok(TuringResponse::Error(error)),
+            outcome => outcome,
+        }
+    }
+
+    async fn broadcast(&self, command: TuringCommand) -> TuringResult<TuringResponse> {
+        let mut responses = Vec::with_capacity(self.ring.nodes.len());
+        for node in &self.ring.nodes {
+            responses.push(self.send(*node, command.clone()).await?);
+        }
+
+        HashRing::merge(responses)
+    }
+
+    async fn send(&self, node: SocketAddr, command: TuringCommand) -> TuringResult<TuringResponse> {
+        let mut client = match self.clients.get(&node) {
+            Some(client) => client.lock().await,
+            None => return Err(TuringDbError::NotSharded),
+        };
+
+        if client.is_none() {
+            *client = Some(TuringClient::connect(node).await?);
+        }
+        match client.as_mut() {
+            Some(client) => client.request(command).await,
+            None => Err(TuringDbError::NotConnected),
+        }
+    }
+}
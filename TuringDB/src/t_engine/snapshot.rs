@@ -0,0 +1,227 @@
+use crate::{
+    DbMeta, Document, LogRecord, MetaEncoding, MetaFile, Quarantine, TuringDbError, TuringResult,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use tai64::TAI64N;
+
+const SNAPSHOTS_DIR: &str = ".snapshots";
+const SNAPSHOT_META_NAME: &str = "SNAPSHOT.meta";
+const SNAPSHOT_DOCUMENT_EXTENSION: &str = "snapshot";
+/// The tree sled keeps the fields of a document in
+const DEFAULT_TREE: &[u8] = b"__sled__default";
+
+/// The name of a tree of a document with its keys and values
+type Tree = (Vec<u8>, Vec<(Vec<u8>, Vec<u8>)>);
+
+/// Describes a point-in-time copy of a repo.
+///
+/// The documents are copied while writers keep running so the copy is fuzzy, the operations
+/// logged while it was being taken are kept in `redo` and replayed on restore to make it consistent
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct SnapshotMeta {
+///     name: String,
+///     taken: TAI64N,
+///     start_lsn: Option<u64>,
+///     end_lsn: Option<u64>,
+///     databases: Vec<(Utf8PathBuf, DbMeta)>,
+///     documents: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+///     redo: Vec<LogRecord>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotMeta {
+    name: String,
+    taken: TAI64N,
+    start_lsn: Option<u64>,
+    end_lsn: Option<u64>,
+    databases: Vec<(Utf8PathBuf, DbMeta)>,
+    documents: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+    redo: Vec<LogRecord>,
+}
+
+impl SnapshotMeta {
+    pub(crate) fn new(
+        name: &str,
+        start_lsn: Option<u64>,
+        end_lsn: Option<u64>,
+        databases: Vec<(Utf8PathBuf, DbMeta)>,
+        documents: Vec<(Utf8PathBuf, Utf8PathBuf)>,
+        redo: Vec<LogRecord>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            taken: TAI64N::now(),
+            start_lsn,
+            end_lsn,
+            databases,
+            documents,
+            redo,
+        }
+    }
+    /// The name of the snapshot
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// The time the snapshot finished
+    pub fn taken(&self) -> TAI64N {
+        self.taken
+    }
+    /// The last log sequence number the snapshot is consistent with once restored
+    pub fn end_lsn(&self) -> Option<u64> {
+        self.end_lsn
+    }
+    /// The databases in the snapshot and their metadata
+    pub fn databases(&self) -> &[(Utf8PathBuf, DbMeta)] {
+        &self.databases
+    }
+    /// The documents in the snapshot as `(database, document)`
+    pub fn documents(&self) -> &[(Utf8PathBuf, Utf8PathBuf)] {
+        &self.documents
+    }
+    /// The operations to replay after the documents are restored
+    pub(crate) fn redo(&self) -> &[LogRecord] {
+        &self.redo
+    }
+
+    pub(crate) async fn load(
+        snapshot_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<SnapshotMeta>> {
+        MetaFile::read::<SnapshotMeta>(&SnapshotMeta::path(snapshot_dir), quarantine).await
+    }
+
+    pub(crate) async fn persist(&self, snapshot_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(
+            &SnapshotMeta::path(snapshot_dir),
+            &MetaEncoding::Bincode.encode(self)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(snapshot_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = snapshot_dir.into();
+        path.push(SNAPSHOT_META_NAME);
+
+        path
+    }
+}
+
+/// The contents of every tree in a document
+/// ```
+/// #[derive(Debug, Serialize, Deserialize)]
+/// pub(crate) struct SnapshotDocument {
+///     trees: Vec<Tree>,
+/// }
+/// ```
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SnapshotDocument {
+    trees: Vec<Tree>,
+}
+
+impl SnapshotDocument {
+    /// Copy the contents of a document
+    pub(crate) fn capture(document: &Document) -> TuringResult<Self> {
+        let mut trees = Vec::new();
+
+        for tree_name in document.tree_names() {
+            let tree = document.open_tree(&tree_name)?;
+            let mut fields = Vec::new();
+
+            for field in tree.iter() {
+                let (key, value) = field?;
+                fields.push((key.to_vec(), value.to_vec()));
+            }
+
+            trees.push((tree_name.to_vec(), fields));
+        }
+
+        Ok(Self { trees })
+    }
+    /// The stored value of a field of the copied document
+    pub(crate) fn field(&self, key: &[u8]) -> Option<&[u8]> {
+        let (_, fields) = self
+            .trees
+            .iter()
+            .find(|(tree_name, _)| tree_name.as_slice() == DEFAULT_TREE)?;
+
+        fields
+            .iter()
+            .find(|(field_key, _)| field_key.as_slice() == key)
+            .map(|(_, stored)| stored.as_slice())
+    }
+    /// Write the copied contents into a document
+    pub(crate) fn restore_into(&self, document: &Document) -> TuringResult<()> {
+        for (tree_name, fields) in &self.trees {
+            let tree = document.open_tree(tree_name)?;
+
+            let mut batch = sled::Batch::default();
+            fields
+                .iter()
+                .for_each(|(key, value)| batch.insert(key.as_slice(), value.as_slice()));
+
+            tree.apply_batch(batch)?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) async fn load(
+        snapshot_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<SnapshotDocument> {
+        let path = SnapshotDocument::path(snapshot_dir, db_name, document_name);
+
+        match MetaFile::read::<SnapshotDocument>(&path, quarantine).await? {
+            Some(snapshot_document) => Ok(snapshot_document),
+            None => Err(TuringDbError::DocumentCorrupted { at: None, bt: () }),
+        }
+    }
+
+    pub(crate) async fn persist(
+        &self,
+        snapshot_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        let path = SnapshotDocument::path(snapshot_dir, db_name, document_name);
+
+        if let Some(parent) = path.parent() {
+            async_fs::DirBuilder::new()
+                .recursive(true)
+                .create(parent)
+                .await?;
+        }
+
+        MetaFile::write(&path, &MetaEncoding::Bincode.encode(self)?).await
+    }
+
+    pub(crate) fn path(
+        snapshot_dir: &Utf8Path,
+        db_name: &Utf8Path,
+        document_name: &Utf8Path,
+    ) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = snapshot_dir.into();
+        path.push(db_name);
+        path.push(document_name);
+        path.set_extension(SNAPSHOT_DOCUMENT_EXTENSION);
+
+        path
+    }
+}
+
+/// Build the directory of a snapshot, rejecting names that would escape the snapshots directory
+pub(crate) fn snapshot_dir(repo_dir: &Utf8Path, name: &str) -> TuringResult<Utf8PathBuf> {
+    if name.is_empty() || name.contains('/') || name.contains('\\') || name.starts_with('.') {
+        return Err(TuringDbError::InvalidInput);
+    }
+
+    let mut path: Utf8PathBuf = repo_dir.into();
+    path.push(SNAPSHOTS_DIR);
+    path.push(name);
+
+    Ok(path)
+}
@@ -0,0 +1,146 @@
+use crate::IndexKind;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// The size of a database
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct DbStats {
+///     documents: usize,
+///     entries: u64,
+///     disk_bytes: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DbStats {
+    documents: usize,
+    entries: u64,
+    disk_bytes: u64,
+}
+
+impl DbStats {
+    pub(crate) fn new(documents: usize, entries: u64, disk_bytes: u64) -> Self {
+        Self {
+            documents,
+            entries,
+            disk_bytes,
+        }
+    }
+    /// The number of documents in the database
+    pub fn documents(&self) -> usize {
+        self.documents
+    }
+    /// The number of fields across every document in the database
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+    /// The bytes the documents of the database take up on disk
+    pub fn disk_bytes(&self) -> u64 {
+        self.disk_bytes
+    }
+}
+
+/// How a secondary index of a database is used, to tell the indexes queries rely on from those
+/// that only cost writes
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct IndexStats {
+///     keys: Vec<Vec<u8>>,
+///     kind: IndexKind,
+///     partial: bool,
+///     documents: Option<usize>,
+///     hits: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct IndexStats {
+    keys: Vec<Vec<u8>>,
+    kind: IndexKind,
+    partial: bool,
+    documents: Option<usize>,
+    hits: u64,
+}
+
+impl IndexStats {
+    pub(crate) fn new(keys: Vec<Vec<u8>>, kind: IndexKind) -> Self {
+        Self {
+            keys,
+            kind,
+            partial: false,
+            documents: None,
+            hits: 0,
+        }
+    }
+
+    pub(crate) fn set_partial(mut self, partial: bool) -> Self {
+        self.partial = partial;
+
+        self
+    }
+
+    pub(crate) fn set_documents(mut self, documents: Option<usize>) -> Self {
+        self.documents = documents;
+
+        self
+    }
+
+    pub(crate) fn set_hits(mut self, hits: u64) -> Self {
+        self.hits = hits;
+
+        self
+    }
+    /// The fields of the index in the order it holds them
+    pub fn keys(&self) -> &[Vec<u8>] {
+        &self.keys
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        self.kind
+    }
+    /// Whether the index only holds the documents matching a predicate
+    pub fn is_partial(&self) -> bool {
+        self.partial
+    }
+    /// The number of documents the index holds, `None` while it is not built
+    pub fn documents(&self) -> Option<usize> {
+        self.documents
+    }
+    /// The number of queries and aggregations the index narrowed down the documents of since the
+    /// database was loaded
+    pub fn hits(&self) -> u64 {
+        self.hits
+    }
+}
+
+/// The running size of a database used to enforce its quota.
+/// It is measured from disk the first time it is needed and after documents are dropped,
+/// in between every write adds the bytes it stored so the size can only be overestimated
+#[derive(Debug, Default)]
+pub(crate) struct DbUsage {
+    measured: AtomicBool,
+    disk_bytes: AtomicU64,
+}
+
+impl DbUsage {
+    /// Check whether the usage has to be measured before it can be read
+    pub(crate) fn is_measured(&self) -> bool {
+        self.measured.load(Ordering::SeqCst)
+    }
+    /// Replace the usage with a fresh measurement
+    pub(crate) fn measured(&self, disk_bytes: u64) {
+        self.disk_bytes.store(disk_bytes, Ordering::SeqCst);
+        self.measured.store(true, Ordering::SeqCst);
+    }
+    /// Measure the usage again the next time it is needed
+    pub(crate) fn invalidate(&self) {
+        self.measured.store(false, Ordering::SeqCst);
+    }
+    /// Account for a write that stored `bytes` bytes
+    pub(crate) fn record_write(&self, bytes: u64) {
+        self.disk_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn disk_bytes(&self) -> u64 {
+        self.disk_bytes.load(Ordering::SeqCst)
+    }
+}
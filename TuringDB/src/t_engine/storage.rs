@@ -0,0 +1,128 @@
+use crate::{TuringDbError, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{fmt::Debug, io::ErrorKind};
+
+/// Where a repo keeps the durable copy of its metadata and documents.
+///
+/// Keys are `/` separated paths relative to the root of the repo. Every method blocks,
+/// the engine calls them from the blocking thread pool
+pub trait StorageBackend: Debug + Send + Sync {
+    /// Read the object at `key`, `None` if there is no such object
+    fn get(&self, key: &str) -> TuringResult<Option<Vec<u8>>>;
+    /// Write the object at `key`, replacing any object already there
+    fn put(&self, key: &str, bytes: &[u8]) -> TuringResult<()>;
+    /// Remove the object at `key`, removing an object that does not exist is not an error
+    fn delete(&self, key: &str) -> TuringResult<()>;
+    /// The keys of every object whose key starts with `prefix`
+    fn list(&self, prefix: &str) -> TuringResult<Vec<String>>;
+}
+
+/// Keeps the objects of a repo as files under a directory, for example a mounted network share
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub struct LocalBackend {
+///     root: Utf8PathBuf,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocalBackend {
+    root: Utf8PathBuf,
+}
+
+impl LocalBackend {
+    pub fn new(root: &Utf8Path) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn path(&self, key: &str) -> Utf8PathBuf {
+        let mut path = self.root.clone();
+        path.push(key);
+
+        path
+    }
+
+    fn walk(&self, dir: &Utf8Path, keys: &mut Vec<String>) -> TuringResult<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                return Ok(());
+            }
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let path = match Utf8PathBuf::from_path_buf(entry.path()) {
+                Ok(path) => path,
+                Err(_) => return Err(TuringDbError::PathReadIsNotUtf8Path),
+            };
+
+            if entry.file_type()?.is_dir() {
+                self.walk(&path, keys)?;
+            } else if path.extension() == Some("tmp") {
+                // Left behind by a put that did not complete
+                continue;
+            } else if let Ok(key) = path.strip_prefix(&self.root) {
+                keys.push(key.as_str().replace('\\', "/"));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StorageBackend for LocalBackend {
+    fn get(&self, key: &str) -> TuringResult<Option<Vec<u8>>> {
+        match std::fs::read(self.path(key)) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                Ok(None)
+            }
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> TuringResult<()> {
+        let path = self.path(key);
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        // Written aside and renamed over the object so a reader never sees half of it
+        let temp_path = Utf8PathBuf::from(format!("{}.tmp", path));
+        std::fs::write(&temp_path, bytes)?;
+        std::fs::rename(&temp_path, &path)?;
+
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> TuringResult<()> {
+        match std::fs::remove_file(self.path(key)) {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    fn list(&self, prefix: &str) -> TuringResult<Vec<String>> {
+        let mut keys = Vec::new();
+        self.walk(&self.root, &mut keys)?;
+
+        keys.retain(|key| key.starts_with(prefix));
+        keys.sort();
+
+        Ok(keys)
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{DataType, Document, TuringDbError, TuringResult};
+use futures_lite::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use serde::{Deserialize, Serialize};
+
+/// The sled tree inside every document holding the chunks of streamed fields
+pub(crate) const CHUNK_TREE: &str = "__turingdb_chunks";
+/// The sled tree inside every document mapping the key of a streamed field to its manifest
+pub(crate) const STREAM_TREE: &str = "__turingdb_streams";
+/// The size of every chunk except the last one
+pub const STREAM_CHUNK_LEN: usize = 1024 * 1024;
+/// Every chunk starts with a `u64` SeaHash checksum of its bytes
+const CHUNK_CHECKSUM_LEN: usize = 8;
+
+/// Describes a field whose value was streamed into a document in chunks
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct StreamManifest {
+///     upload_id: u64,
+///     data_type: DataType,
+///     chunks: u64,
+///     len: u64,
+///     checksum: u64,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StreamManifest {
+    upload_id: u64,
+    data_type: DataType,
+    chunks: u64,
+    len: u64,
+    checksum: u64,
+}
+
+impl StreamManifest {
+    /// The type the bytes of the field were declared as
+    pub fn data_type(&self) -> DataType {
+        self.data_type
+    }
+    /// The number of chunks the value was split into
+    pub fn chunks(&self) -> u64 {
+        self.chunks
+    }
+    /// The length of the value in bytes
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+    /// Check whether the streamed value was empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn upload_id(&self) -> u64 {
+        self.upload_id
+    }
+
+    pub(crate) fn encode(&self) -> TuringResult<Vec<u8>> {
+        Ok(bincode::serialize::<StreamManifest>(self)?)
+    }
+
+    pub(crate) fn decode(stored: &[u8]) -> TuringResult<StreamManifest> {
+        Ok(bincode::deserialize::<StreamManifest>(stored)?)
+    }
+}
+
+/// Writes and reads the chunks of streamed fields
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChunkedStream;
+
+impl ChunkedStream {
+    /// Split everything `reader` produces into checksummed chunks staged under a new upload id.
+    /// The chunks are flushed to disk before the manifest describing them is returned
+    pub(crate) async fn write(
+        document: &Document,
+        data_type: DataType,
+        mut reader: impl AsyncRead + Unpin,
+    ) -> TuringResult<StreamManifest> {
+        let chunk_tree = document.open_tree(CHUNK_TREE)?;
+        let upload_id = document.generate_id()?;
+
+        let mut buffer = vec![0_u8; STREAM_CHUNK_LEN];
+        let mut chunks = 0_u64;
+        let mut len = 0_u64;
+        let mut chunk_checksums = Vec::new();
+
+        loop {
+            let filled = ChunkedStream::fill(&mut reader, &mut buffer).await?;
+
+            if filled == 0 {
+                break;
+            }
+
+            let chunk_checksum = seahash::hash(&buffer[..filled]);
+
+            let mut chunk = Vec::with_capacity(CHUNK_CHECKSUM_LEN + filled);
+            chunk.extend_from_slice(&chunk_checksum.to_le_bytes());
+            chunk.extend_from_slice(&buffer[..filled]);
+            chunk_tree.insert(&ChunkedStream::chunk_key(upload_id, chunks)[..], chunk)?;
+
+            chunk_checksums.extend_from_slice(&chunk_checksum.to_le_bytes());
+            chunks += 1;
+            len += filled as u64;
+
+            if filled < STREAM_CHUNK_LEN {
+                break;
+            }
+        }
+
+        chunk_tree.flush_async().await?;
+
+        Ok(StreamManifest {
+            upload_id,
+            data_type,
+            chunks,
+            len,
+            checksum: seahash::hash(&chunk_checksums),
+        })
+    }
+    /// Write the chunks of a streamed field to `writer` in order, verifying every chunk
+    /// and the manifest checksum before the last byte is written
+    pub(crate) async fn read(
+        document: &Document,
+        manifest: &StreamManifest,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> TuringResult<u64> {
+        let chunk_tree = document.open_tree(CHUNK_TREE)?;
+
+        let mut chunk_checksums = Vec::new();
+        let mut written = 0_u64;
+
+        for index in 0..manifest.chunks {
+            let chunk =
+                match chunk_tree.get(&ChunkedStream::chunk_key(manifest.upload_id, index)[..])? {
+                    None => return Err(TuringDbError::ChunkCorrupted { chunk: index }),
+                    Some(chunk) => chunk,
+                };
+
+            if chunk.len() < CHUNK_CHECKSUM_LEN {
+                return Err(TuringDbError::ChunkCorrupted { chunk: index });
+            }
+
+            let mut checksum_bytes = [0_u8; CHUNK_CHECKSUM_LEN];
+            checksum_bytes.copy_from_slice(&chunk[..CHUNK_CHECKSUM_LEN]);
+            let bytes = &chunk[CHUNK_CHECKSUM_LEN..];
+
+            if seahash::hash(bytes) != u64::from_le_bytes(checksum_bytes) {
+                return Err(TuringDbError::ChunkCorrupted { chunk: index });
+            }
+
+            chunk_checksums.extend_from_slice(&checksum_bytes);
+
+            if index + 1 == manifest.chunks && seahash::hash(&chunk_checksums) != manifest.checksum
+            {
+                return Err(TuringDbError::ChunkCorrupted { chunk: index });
+            }
+
+            writer.write_all(bytes).await?;
+            written += bytes.len() as u64;
+        }
+
+        writer.flush().await?;
+
+        Ok(written)
+    }
+    /// Check the checksum of every chunk in a document, returning the upload id and index of each
+    /// chunk that fails
+    pub(crate) fn corrupt_chunks(document: &Document) -> TuringResult<Vec<(u64, u64)>> {
+        let chunk_tree = document.open_tree(CHUNK_TREE)?;
+
+        let mut corrupt = Vec::new();
+
+        for chunk in chunk_tree.iter() {
+            let (chunk_key, chunk) = chunk?;
+
+            if chunk_key.len() != 16 {
+                continue;
+            }
+
+            let mut upload_id_bytes = [0_u8; 8];
+            let mut index_bytes = [0_u8; 8];
+            upload_id_bytes.copy_from_slice(&chunk_key[..8]);
+            index_bytes.copy_from_slice(&chunk_key[8..]);
+
+            let intact = chunk.len() >= CHUNK_CHECKSUM_LEN && {
+                let mut checksum_bytes = [0_u8; CHUNK_CHECKSUM_LEN];
+                checksum_bytes.copy_from_slice(&chunk[..CHUNK_CHECKSUM_LEN]);
+
+                seahash::hash(&chunk[CHUNK_CHECKSUM_LEN..]) == u64::from_le_bytes(checksum_bytes)
+            };
+
+            if !intact {
+                corrupt.push((
+                    u64::from_be_bytes(upload_id_bytes),
+                    u64::from_be_bytes(index_bytes),
+                ));
+            }
+        }
+
+        Ok(corrupt)
+    }
+    /// Remove every chunk staged under an upload
+    pub(crate) fn discard(document: &Document, upload_id: u64) -> TuringResult<()> {
+        let chunk_tree = document.open_tree(CHUNK_TREE)?;
+
+        for chunk in chunk_tree.scan_prefix(upload_id.to_be_bytes()) {
+            let (chunk_key, _) = chunk?;
+            chunk_tree.remove(chunk_key)?;
+        }
+
+        Ok(())
+    }
+    /// Read until `buffer` is full or the reader is exhausted
+    async fn fill(reader: &mut (impl AsyncRead + Unpin), buffer: &mut [u8]) -> TuringResult<usize> {
+        let mut filled = 0_usize;
+
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]).await? {
+                0 => break,
+                read => filled += read,
+            }
+        }
+
+        Ok(filled)
+    }
+
+    fn chunk_key(upload_id: u64, index: u64) -> [u8; 16] {
+        let mut chunk_key = [0_u8; 16];
+        chunk_key[..8].copy_from_slice(&upload_id.to_be_bytes());
+        chunk_key[8..].copy_from_slice(&index.to_be_bytes());
+
+        chunk_key
+    }
+}
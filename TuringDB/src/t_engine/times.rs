@@ -0,0 +1,244 @@
+use crate::{LazyDocument, LogOp, MetaEncoding, MetaFile, Quarantine, TuringDB, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Bound,
+    sync::Arc,
+    time::Duration,
+};
+use tai64::TAI64N;
+
+const TIMES_META_NAME: &str = "TIMES.meta";
+/// The format version that added the time index of each database
+pub(crate) const TIME_INDEX_FORMAT: u32 = 4;
+
+/// When a document was created and when it was last written
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct DocumentTimes {
+///     created: TAI64N,
+///     modified: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DocumentTimes {
+    created: TAI64N,
+    modified: TAI64N,
+}
+
+impl DocumentTimes {
+    pub fn created(&self) -> TAI64N {
+        self.created
+    }
+
+    pub fn modified(&self) -> TAI64N {
+        self.modified
+    }
+}
+
+/// Which time of a document a range of time is matched against
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum TimeField {
+///     Created,
+///     Modified,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeField {
+    Created,
+    Modified,
+}
+
+/// The times of every document of a database kept in time order, so the documents created or
+/// modified in a range of time are found without opening any of them
+/// ```
+/// #[derive(Debug, Default)]
+/// pub(crate) struct TimeIndex {
+///     documents: BTreeMap<Utf8PathBuf, DocumentTimes>,
+///     created: BTreeSet<(TAI64N, Utf8PathBuf)>,
+///     modified: BTreeSet<(TAI64N, Utf8PathBuf)>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct TimeIndex {
+    documents: BTreeMap<Utf8PathBuf, DocumentTimes>,
+    created: BTreeSet<(TAI64N, Utf8PathBuf)>,
+    modified: BTreeSet<(TAI64N, Utf8PathBuf)>,
+}
+
+impl TimeIndex {
+    fn from_documents(documents: BTreeMap<Utf8PathBuf, DocumentTimes>) -> Self {
+        let mut times = TimeIndex::default();
+        for (document_name, document_times) in documents {
+            times.insert(&document_name, document_times);
+        }
+
+        times
+    }
+    /// Build the index by opening every document, a document is taken to be created when its oldest
+    /// field was and modified when its newest field was. A document without fields gets the current time
+    pub(crate) async fn build(
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<Self> {
+        let now = TAI64N::now();
+        let mut times = TimeIndex::default();
+
+        for (document_name, document) in documents {
+            let sled_db = document.open().await?;
+
+            let mut document_times: Option<DocumentTimes> = None;
+            for entry in sled_db.iter() {
+                let (_, stored) = entry?;
+                let field_data = TuringDB::decode_field(&stored)?;
+
+                document_times = Some(match document_times {
+                    None => DocumentTimes {
+                        created: field_data.created(),
+                        modified: field_data.modified(),
+                    },
+                    Some(document_times) => DocumentTimes {
+                        created: document_times.created.min(field_data.created()),
+                        modified: document_times.modified.max(field_data.modified()),
+                    },
+                });
+            }
+
+            let document_times = document_times.unwrap_or(DocumentTimes {
+                created: now,
+                modified: now,
+            });
+            times.insert(&document_name, document_times);
+        }
+
+        Ok(times)
+    }
+    /// Record the writes an operation made to the documents of the database at `time`.
+    /// The writes of a batch are recorded at the times they were applied at
+    pub(crate) fn record(&mut self, op: &LogOp, time: TAI64N) {
+        match op {
+            LogOp::DocumentCreate { document, .. } => self.insert(
+                document,
+                DocumentTimes {
+                    created: time,
+                    modified: time,
+                },
+            ),
+            LogOp::DocumentDrop { document, .. } => self.remove(document),
+            LogOp::FieldInsert { document, .. }
+            | LogOp::FieldModify { document, .. }
+            | LogOp::FieldRemove { document, .. }
+            | LogOp::FieldInsertStream { document, .. }
+            | LogOp::DocumentPatch { document, .. }
+            | LogOp::DocumentUpsert { document, .. }
+            | LogOp::DocumentUpdateIf { document, .. }
+            | LogOp::DocumentPatchIf { document, .. }
+            | LogOp::DocumentIncrement { document, .. }
+            | LogOp::DocumentRestore { document, .. }
+            | LogOp::FieldMerge { document, .. }
+            | LogOp::CrdtMerge { document, .. } => self.modify(document, time),
+            LogOp::WriteBatch { db, ops } => {
+                for (index, op) in ops.iter().enumerate() {
+                    self.record(&op.log_op(db), time + Duration::from_nanos(index as u64));
+                }
+            }
+            _ => (),
+        }
+    }
+    /// The documents whose `field` time is at or after `from` and before `to`, oldest first
+    pub(crate) fn range(
+        &self,
+        field: TimeField,
+        from: TAI64N,
+        to: TAI64N,
+    ) -> Vec<(Utf8PathBuf, DocumentTimes)> {
+        if from >= to {
+            return Vec::new();
+        }
+
+        let ordered = match field {
+            TimeField::Created => &self.created,
+            TimeField::Modified => &self.modified,
+        };
+
+        ordered
+            .range((
+                Bound::Included((from, Utf8PathBuf::new())),
+                Bound::Excluded((to, Utf8PathBuf::new())),
+            ))
+            .filter_map(|(_, document_name)| {
+                self.documents
+                    .get(document_name)
+                    .map(|document_times| (document_name.clone(), *document_times))
+            })
+            .collect()
+    }
+    /// A write moves the modified time of a document forward, never back, so replaying
+    /// an older write leaves a newer time in place. An unknown document is taken to be created by the write
+    fn modify(&mut self, document_name: &Utf8Path, time: TAI64N) {
+        let document_times = match self.documents.get(document_name) {
+            None => DocumentTimes {
+                created: time,
+                modified: time,
+            },
+            Some(document_times) if document_times.modified >= time => return,
+            Some(document_times) => DocumentTimes {
+                created: document_times.created,
+                modified: time,
+            },
+        };
+
+        self.insert(document_name, document_times);
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, document_times: DocumentTimes) {
+        self.remove(document_name);
+
+        self.created
+            .insert((document_times.created, document_name.to_path_buf()));
+        self.modified
+            .insert((document_times.modified, document_name.to_path_buf()));
+        self.documents
+            .insert(document_name.to_path_buf(), document_times);
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        if let Some(document_times) = self.documents.remove(document_name) {
+            self.created
+                .remove(&(document_times.created, document_name.to_path_buf()));
+            self.modified
+                .remove(&(document_times.modified, document_name.to_path_buf()));
+        }
+    }
+
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<TimeIndex> {
+        match MetaFile::read::<BTreeMap<Utf8PathBuf, DocumentTimes>>(
+            &TimeIndex::path(db_dir),
+            quarantine,
+        )
+        .await?
+        {
+            Some(documents) => Ok(TimeIndex::from_documents(documents)),
+            None => Ok(TimeIndex::default()),
+        }
+    }
+
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(
+            &TimeIndex::path(db_dir),
+            &MetaEncoding::Bincode.encode(&self.documents)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TIMES_META_NAME);
+
+        path
+    }
+}
@@ -0,0 +1,125 @@
+use crate::{TuringDbError, TuringResult};
+use async_rustls::{
+    rustls::{
+        internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys},
+        ClientConfig, NoClientAuth, ServerConfig,
+    },
+    webpki::DNSNameRef,
+    TlsAcceptor, TlsConnector,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::sync::Arc;
+
+/// The certificate a `TuringServer` presents to its clients, both files in PEM.
+/// The private key is either PKCS #8 or PKCS #1 RSA
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct ServerTls {
+///     certificate_chain: Utf8PathBuf,
+///     private_key: Utf8PathBuf,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerTls {
+    certificate_chain: Utf8PathBuf,
+    private_key: Utf8PathBuf,
+}
+
+impl ServerTls {
+    pub fn new(certificate_chain: &Utf8Path, private_key: &Utf8Path) -> Self {
+        Self {
+            certificate_chain: certificate_chain.to_path_buf(),
+            private_key: private_key.to_path_buf(),
+        }
+    }
+
+    pub fn get_certificate_chain(&self) -> &Utf8Path {
+        &self.certificate_chain
+    }
+
+    pub fn get_private_key(&self) -> &Utf8Path {
+        &self.private_key
+    }
+    /// Read the certificate and its key, failing with `TuringDbError::Tls` if they cannot be used
+    pub(crate) async fn acceptor(&self) -> TuringResult<TlsAcceptor> {
+        let certificate_chain = async_fs::read(&self.certificate_chain).await?;
+        let certificate_chain = certs(&mut certificate_chain.as_slice())
+            .map_err(|_| TuringDbError::Tls("The certificate chain is not valid PEM".into()))?;
+
+        let private_key = async_fs::read(&self.private_key).await?;
+        let mut keys = pkcs8_private_keys(&mut private_key.as_slice())
+            .map_err(|_| TuringDbError::Tls("The private key is not valid PEM".into()))?;
+        if keys.is_empty() {
+            keys = rsa_private_keys(&mut private_key.as_slice())
+                .map_err(|_| TuringDbError::Tls("The private key is not valid PEM".into()))?;
+        }
+        let private_key = match keys.into_iter().next() {
+            Some(private_key) => private_key,
+            None => return Err(TuringDbError::Tls("No private key was found".into())),
+        };
+
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config
+            .set_single_cert(certificate_chain, private_key)
+            .map_err(|error| TuringDbError::Tls(error.to_string()))?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// How a `TuringClient` checks the certificate of the server, against the root certificates
+/// of a PEM file and for the name the server is known by
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct ClientTls {
+///     root_certificates: Utf8PathBuf,
+///     server_name: String,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientTls {
+    root_certificates: Utf8PathBuf,
+    server_name: String,
+}
+
+impl ClientTls {
+    pub fn new(root_certificates: &Utf8Path, server_name: &str) -> Self {
+        Self {
+            root_certificates: root_certificates.to_path_buf(),
+            server_name: server_name.into(),
+        }
+    }
+
+    pub fn get_root_certificates(&self) -> &Utf8Path {
+        &self.root_certificates
+    }
+
+    pub fn get_server_name(&self) -> &str {
+        &self.server_name
+    }
+    /// Read the root certificates, failing with `TuringDbError::Tls` if none can be used
+    pub(crate) async fn connector(&self) -> TuringResult<TlsConnector> {
+        let root_certificates = async_fs::read(&self.root_certificates).await?;
+
+        let mut config = ClientConfig::new();
+        match config
+            .root_store
+            .add_pem_file(&mut root_certificates.as_slice())
+        {
+            Ok((added, _)) if added > 0 => (),
+            _ => {
+                return Err(TuringDbError::Tls(
+                    "No root certificate could be read".into(),
+                ))
+            }
+        }
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    /// Fails with `TuringDbError::Tls` unless the server name is a DNS name
+    pub(crate) fn dns_name(&self) -> TuringResult<DNSNameRef<'_>> {
+        DNSNameRef::try_from_ascii_str(&self.server_name)
+            .map_err(|_| TuringDbError::Tls(format!("`{}` is not a DNS name", self.server_name)))
+    }
+}
@@ -0,0 +1,434 @@
+use crate::{
+    OpsOutcome, Patch, TDBCell, TuringDBOps, TuringDbError, TuringEngine, TuringResult, WriteOp,
+};
+use camino::Utf8PathBuf;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tai64::TAI64N;
+
+/// The writes a transaction staged, none of them is applied until it commits
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct Staged {
+///     db: Utf8PathBuf,
+///     ops: Vec<(Utf8PathBuf, WriteOp)>,
+///     savepoints: Vec<(String, usize)>,
+///     last_used: TAI64N,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct Staged {
+    // The database the transaction began on, writes go to it unless they name another one
+    db: Utf8PathBuf,
+    ops: Vec<(Utf8PathBuf, WriteOp)>,
+    // Each savepoint with the number of writes staged when it was set, oldest first
+    savepoints: Vec<(String, usize)>,
+    last_used: TAI64N,
+}
+
+impl Staged {
+    /// The staged writes grouped by database, the databases in the order they were first written to
+    pub(crate) fn into_batches(self) -> Vec<(Utf8PathBuf, Vec<WriteOp>)> {
+        let mut batches: Vec<(Utf8PathBuf, Vec<WriteOp>)> = Vec::new();
+
+        for (db, op) in self.ops {
+            match batches.iter_mut().find(|(batch_db, _)| batch_db == &db) {
+                Some((_, batch)) => batch.push(op),
+                None => batches.push((db, vec![op])),
+            }
+        }
+
+        batches
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.ops.len()
+    }
+}
+
+/// The transactions open on the engine, kept by id so clients over the wire can name them
+/// ```
+/// #[derive(Debug, Default)]
+/// pub(crate) struct Transactions {
+///     next_id: AtomicU64,
+///     open: DashMap<u64, Staged>,
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct Transactions {
+    next_id: AtomicU64,
+    open: DashMap<u64, Staged>,
+}
+
+impl Transactions {
+    /// Open a transaction on the database `db` and return its id
+    pub(crate) fn begin(&self, db: Utf8PathBuf) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.open.insert(
+            id,
+            Staged {
+                db,
+                ops: Vec::new(),
+                savepoints: Vec::new(),
+                last_used: TAI64N::now(),
+            },
+        );
+
+        id
+    }
+    /// Stage a write to the database `db`, or to the database the transaction began on when `None`,
+    /// returning how many writes the transaction holds
+    pub(crate) fn stage(
+        &self,
+        id: u64,
+        db: Option<Utf8PathBuf>,
+        op: WriteOp,
+    ) -> TuringResult<usize> {
+        match self.open.get_mut(&id) {
+            None => Err(TuringDbError::TransactionNotFound),
+            Some(mut staged) => {
+                let db = db.unwrap_or_else(|| staged.db.clone());
+                staged.ops.push((db, op));
+                staged.last_used = TAI64N::now();
+
+                Ok(staged.ops.len())
+            }
+        }
+    }
+    /// Mark the writes staged so far, setting a savepoint again under the same name moves it
+    pub(crate) fn savepoint(&self, id: u64, name: &str) -> TuringResult<()> {
+        match self.open.get_mut(&id) {
+            None => Err(TuringDbError::TransactionNotFound),
+            Some(mut staged) => {
+                let len = staged.ops.len();
+                staged.savepoints.retain(|(savepoint, _)| savepoint != name);
+                staged.savepoints.push((name.to_owned(), len));
+                staged.last_used = TAI64N::now();
+
+                Ok(())
+            }
+        }
+    }
+    /// Drop the writes staged after the savepoint `name` and the savepoints set after it,
+    /// the savepoint itself is kept. Returns how many writes were dropped
+    pub(crate) fn rollback_to(&self, id: u64, name: &str) -> TuringResult<usize> {
+        let mut staged = match self.open.get_mut(&id) {
+            None => return Err(TuringDbError::TransactionNotFound),
+            Some(staged) => staged,
+        };
+
+        let position = match staged
+            .savepoints
+            .iter()
+            .rposition(|(savepoint, _)| savepoint == name)
+        {
+            None => return Err(TuringDbError::SavepointNotFound),
+            Some(position) => position,
+        };
+        let len = staged.savepoints[position].1;
+        staged.savepoints.truncate(position + 1);
+        let dropped = staged.ops.len() - len;
+        staged.ops.truncate(len);
+        staged.last_used = TAI64N::now();
+
+        Ok(dropped)
+    }
+    /// Close a transaction, handing back what it staged
+    pub(crate) fn take(&self, id: u64) -> Option<Staged> {
+        self.open.remove(&id).map(|(_, staged)| staged)
+    }
+    /// Open again a transaction taken to be committed, holding what it staged
+    pub(crate) fn reopen(&self, id: u64, mut staged: Staged) {
+        staged.last_used = TAI64N::now();
+        self.open.insert(id, staged);
+    }
+
+    pub(crate) fn is_open(&self, id: u64) -> bool {
+        self.open.contains_key(&id)
+    }
+    /// Close every transaction that has not been used since `cutoff` without applying
+    /// what it staged, returning how many were closed
+    pub(crate) fn close_idle(&self, cutoff: TAI64N) -> usize {
+        let before = self.open.len();
+        self.open.retain(|_, staged| staged.last_used >= cutoff);
+
+        before.saturating_sub(self.open.len())
+    }
+}
+
+/// A transaction over the documents of a database, opened with `TuringEngine::begin`.
+/// Writes are staged as they are made and applied all at once by `commit`, through
+/// `TuringEngine::write_batches` so they reach the ops log as a single record, or as a prepare
+/// and a commit record when `write_to` staged writes to other databases of the repo.
+/// A transaction dropped before it commits is rolled back
+/// ```
+/// #[derive(Debug)]
+/// pub struct Transaction<'a> {
+///     engine: &'a TuringEngine,
+///     id: u64,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Transaction<'a> {
+    engine: &'a TuringEngine,
+    id: u64,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(engine: &'a TuringEngine, id: u64) -> Self {
+        Self { engine, id }
+    }
+
+    pub fn get_id(&self) -> u64 {
+        self.id
+    }
+
+    pub async fn create(&self, document: &str) -> TuringResult<()> {
+        self.write(WriteOp::DocumentCreate {
+            document: document.into(),
+        })
+        .await
+    }
+
+    pub async fn drop_document(&self, document: &str) -> TuringResult<()> {
+        self.write(WriteOp::DocumentDrop {
+            document: document.into(),
+        })
+        .await
+    }
+    /// Replace every field of a document, creating it when it does not exist
+    pub async fn upsert(
+        &self,
+        document: &str,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    ) -> TuringResult<()> {
+        self.write(WriteOp::DocumentUpsert {
+            document: document.into(),
+            fields,
+        })
+        .await
+    }
+
+    pub async fn patch(&self, document: &str, patch: Patch) -> TuringResult<()> {
+        self.write(WriteOp::DocumentPatch {
+            document: document.into(),
+            patch,
+        })
+        .await
+    }
+
+    pub async fn insert(&self, document: &str, key: &[u8], value: TDBCell) -> TuringResult<()> {
+        self.write(WriteOp::FieldInsert {
+            document: document.into(),
+            key: key.to_vec(),
+            value,
+        })
+        .await
+    }
+
+    pub async fn update(&self, document: &str, key: &[u8], value: TDBCell) -> TuringResult<()> {
+        self.write(WriteOp::FieldModify {
+            document: document.into(),
+            key: key.to_vec(),
+            value,
+        })
+        .await
+    }
+
+    pub async fn remove(&self, document: &str, key: &[u8]) -> TuringResult<()> {
+        self.write(WriteOp::FieldRemove {
+            document: document.into(),
+            key: key.to_vec(),
+        })
+        .await
+    }
+    /// Stage any write, checked against the database only once the transaction commits
+    pub async fn write(&self, op: WriteOp) -> TuringResult<()> {
+        self.engine.transaction_write(self.id, op).await?;
+
+        Ok(())
+    }
+    /// Stage a write to another database of the repo, the transaction then commits
+    /// across every database it writes to or none of them
+    pub async fn write_to(&self, db: &str, op: WriteOp) -> TuringResult<()> {
+        self.engine
+            .transaction_write_to(self.id, &TuringDBOps::default().set_db_name(db), op)
+            .await?;
+
+        Ok(())
+    }
+    /// Mark the writes staged so far so `rollback_to` can drop the ones staged afterwards
+    pub async fn savepoint(&self, name: &str) -> TuringResult<()> {
+        self.engine.transaction_savepoint(self.id, name).await?;
+
+        Ok(())
+    }
+    /// Drop the writes staged since the savepoint `name` was set, keeping the ones before it
+    /// and the savepoint itself so the step can be staged again
+    pub async fn rollback_to(&self, name: &str) -> TuringResult<()> {
+        self.engine.transaction_rollback_to(self.id, name).await?;
+
+        Ok(())
+    }
+    /// Apply every staged write atomically, either all of them are applied or none is
+    pub async fn commit(self) -> TuringResult<OpsOutcome> {
+        self.engine.transaction_commit(self.id).await
+    }
+    /// Drop the staged writes without applying any of them
+    pub fn rollback(self) {}
+}
+
+impl<'a> Drop for Transaction<'a> {
+    fn drop(&mut self) {
+        self.engine.transaction_discard(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DataType, TuringDBDocumentOps};
+    use futures_lite::future::block_on;
+
+    fn create(document: &str) -> WriteOp {
+        WriteOp::DocumentCreate {
+            document: document.into(),
+        }
+    }
+
+    async fn exists(engine: &TuringEngine, db: &str, document: &str) -> bool {
+        let ops = TuringDBDocumentOps::default()
+            .set_db_name(db)
+            .set_document_name(document);
+
+        match engine.document_view(&ops).await {
+            Ok(_) => true,
+            Err(TuringDbError::DocumentNotFound) => false,
+            Err(error) => panic!("Reading {}/{} failed with {:?}", db, document, error),
+        }
+    }
+
+    #[test]
+    fn writes_staged_after_a_savepoint_are_rolled_back() {
+        let transactions = Transactions::default();
+        let id = transactions.begin("orders".into());
+
+        transactions.stage(id, None, create("first")).unwrap();
+        transactions.savepoint(id, "created").unwrap();
+        transactions
+            .stage(id, Some("stock".into()), create("second"))
+            .unwrap();
+        transactions.savepoint(id, "stocked").unwrap();
+        assert_eq!(transactions.stage(id, None, create("third")).unwrap(), 3);
+
+        assert_eq!(transactions.rollback_to(id, "created").unwrap(), 2);
+        // The savepoints set after the one rolled back to are gone, the savepoint itself is kept
+        assert!(matches!(
+            transactions.rollback_to(id, "stocked"),
+            Err(TuringDbError::SavepointNotFound)
+        ));
+        assert_eq!(transactions.rollback_to(id, "created").unwrap(), 0);
+
+        transactions
+            .stage(id, Some("stock".into()), create("fourth"))
+            .unwrap();
+        transactions.stage(id, None, create("fifth")).unwrap();
+        let staged = transactions.take(id).unwrap();
+        assert_eq!(
+            staged.into_batches(),
+            vec![
+                ("orders".into(), vec![create("first"), create("fifth")]),
+                ("stock".into(), vec![create("fourth")]),
+            ]
+        );
+        assert!(matches!(
+            transactions.stage(id, None, create("sixth")),
+            Err(TuringDbError::TransactionNotFound)
+        ));
+    }
+
+    #[test]
+    fn a_reopened_transaction_keeps_what_it_staged() {
+        let transactions = Transactions::default();
+        let id = transactions.begin("orders".into());
+        transactions.stage(id, None, create("first")).unwrap();
+
+        let staged = transactions.take(id).unwrap();
+        assert!(!transactions.is_open(id));
+        transactions.reopen(id, staged.clone());
+        assert!(transactions.is_open(id));
+        assert_eq!(
+            transactions.take(id).unwrap().into_batches(),
+            staged.into_batches()
+        );
+    }
+
+    #[test]
+    fn idle_transactions_are_closed() {
+        let transactions = Transactions::default();
+        let idle = transactions.begin("orders".into());
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let cutoff = TAI64N::now();
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        let used = transactions.begin("orders".into());
+
+        assert_eq!(transactions.close_idle(cutoff), 1);
+        assert!(transactions.take(idle).is_none());
+        assert!(transactions.take(used).is_some());
+    }
+
+    #[test]
+    fn a_transaction_commits_across_databases_or_not_at_all() {
+        block_on(async {
+            let engine = TuringEngine::ephemeral();
+            for db in ["orders", "stock"].iter() {
+                engine
+                    .db_create(TuringDBOps::default().set_db_name(db))
+                    .await
+                    .unwrap();
+            }
+
+            let transaction = engine
+                .begin(&TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            transaction.create("order").await.unwrap();
+            transaction.write_to("stock", create("item")).await.unwrap();
+            transaction.commit().await.unwrap();
+            assert!(exists(&engine, "orders", "order").await);
+            assert!(exists(&engine, "stock", "item").await);
+
+            // The write to a document missing from the other database fails the whole commit
+            let transaction = engine
+                .begin(&TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            transaction.create("refund").await.unwrap();
+            transaction
+                .write_to(
+                    "stock",
+                    WriteOp::FieldModify {
+                        document: "missing".into(),
+                        key: b"count".to_vec(),
+                        value: TDBCell::new(DataType::U8, &[1]),
+                    },
+                )
+                .await
+                .unwrap();
+            let id = transaction.get_id();
+            assert!(transaction.commit().await.is_err());
+            assert!(!exists(&engine, "orders", "refund").await);
+            // A write that can never apply closes the transaction instead of keeping it for a retry
+            assert!(!engine.transaction_open(id));
+
+            // A transaction dropped before it commits applies nothing
+            let transaction = engine
+                .begin(&TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            transaction.create("abandoned").await.unwrap();
+            drop(transaction);
+            assert!(!exists(&engine, "orders", "abandoned").await);
+        });
+    }
+}
@@ -0,0 +1,202 @@
+use crate::{ColdDocument, MetaEncoding, MetaFile, Quarantine, TuringDbError, TuringResult};
+use camino::{Utf8Path, Utf8PathBuf};
+use futures_lite::stream::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, io::ErrorKind, time::Duration};
+use tai64::TAI64N;
+
+const TRASH_META_NAME: &str = "TRASH.meta";
+/// The trash of a database lives in its directory, reserved so it is never loaded as a document
+const TRASH_DIR: &str = ".trash";
+const TRASHED_EXTENSION: &str = "trashed";
+
+/// The documents dropped from a database that soft deletes, each kept as an archive in the trash
+/// until it is restored or purged once it has been there longer than the retention of the database
+/// ```
+/// #[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// pub(crate) struct Trash {
+///     retention: Option<Duration>,
+///     documents: BTreeMap<Utf8PathBuf, TAI64N>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct Trash {
+    // `None` removes dropped documents outright
+    retention: Option<Duration>,
+    // When each document in the trash was dropped
+    documents: BTreeMap<Utf8PathBuf, TAI64N>,
+}
+
+impl Trash {
+    /// How long a dropped document is kept in the trash, `None` when the database does not soft delete
+    pub(crate) fn retention(&self) -> Option<Duration> {
+        self.retention
+    }
+
+    pub(crate) fn set_retention(&mut self, retention: Option<Duration>) {
+        self.retention = retention;
+    }
+
+    pub(crate) fn contains(&self, document_name: &Utf8Path) -> bool {
+        self.documents.contains_key(document_name)
+    }
+    /// The documents in the trash along with when they were dropped, oldest first
+    pub(crate) fn documents(&self) -> Vec<(Utf8PathBuf, TAI64N)> {
+        let mut documents = self
+            .documents
+            .iter()
+            .map(|(document_name, trashed)| (document_name.clone(), *trashed))
+            .collect::<Vec<(Utf8PathBuf, TAI64N)>>();
+
+        documents.sort_by(|(left_name, left), (right_name, right)| {
+            left.cmp(right).then_with(|| left_name.cmp(right_name))
+        });
+
+        documents
+    }
+    /// The documents dropped at or before `before`
+    pub(crate) fn trashed_before(&self, before: TAI64N) -> Vec<Utf8PathBuf> {
+        self.documents
+            .iter()
+            .filter(|(_, trashed)| **trashed <= before)
+            .map(|(document_name, _)| document_name.clone())
+            .collect()
+    }
+    /// Put the archive of a document dropped at `time` in the trash,
+    /// replacing a document of the same name that was dropped before it
+    pub(crate) async fn put(
+        &mut self,
+        db_dir: &Utf8Path,
+        document_name: &Utf8Path,
+        cold_document: &ColdDocument,
+        time: TAI64N,
+    ) -> TuringResult<()> {
+        async_fs::DirBuilder::new()
+            .recursive(true)
+            .create(Trash::dir(db_dir))
+            .await?;
+
+        MetaFile::write(
+            &Trash::document_path(db_dir, document_name),
+            &MetaEncoding::Bincode.encode(cold_document)?,
+        )
+        .await?;
+        self.documents.insert(document_name.into(), time);
+
+        Ok(())
+    }
+    /// Read the archive of a document in the trash without taking it out
+    pub(crate) async fn archive(
+        &self,
+        db_dir: &Utf8Path,
+        document_name: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<ColdDocument> {
+        if !self.contains(document_name) {
+            return Err(TuringDbError::DocumentNotFound);
+        }
+
+        match MetaFile::read::<ColdDocument>(
+            &Trash::document_path(db_dir, document_name),
+            quarantine,
+        )
+        .await?
+        {
+            Some(cold_document) => Ok(cold_document),
+            None => Err(TuringDbError::DocumentCorrupted { at: None, bt: () }),
+        }
+    }
+    /// Remove a document from the trash along with its archive
+    pub(crate) async fn purge(
+        &mut self,
+        db_dir: &Utf8Path,
+        document_name: &Utf8Path,
+    ) -> TuringResult<()> {
+        let path = Trash::document_path(db_dir, document_name);
+
+        for candidate in [path.clone(), Utf8PathBuf::from(format!("{}.bak", path))].iter() {
+            if let Err(error) = async_fs::remove_file(candidate).await {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+            }
+        }
+        self.documents.remove(document_name);
+
+        Ok(())
+    }
+    /// Load the trash of a database and bring it in line with the archives in its directory.
+    /// A crash after a document was moved into or out of the trash but before the database was
+    /// committed leaves an archive the trash does not know of, or a document it no longer holds
+    pub(crate) async fn load(db_dir: &Utf8Path, quarantine: &Quarantine) -> TuringResult<Trash> {
+        let mut trash = MetaFile::read::<Trash>(&Trash::path(db_dir), quarantine)
+            .await?
+            .unwrap_or_default();
+
+        let mut entries = match async_fs::read_dir(Trash::dir(db_dir)).await {
+            Ok(entries) => entries,
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    return Err(error.into());
+                }
+
+                trash.documents.clear();
+                return Ok(trash);
+            }
+        };
+
+        let mut archived = BTreeMap::new();
+        while let Some(trash_entry) = entries.try_next().await? {
+            let file_name = match trash_entry.file_name().to_str() {
+                None => return Err(TuringDbError::PathReadIsNotUtf8Path),
+                Some(file_name) => file_name.to_owned(),
+            };
+            let document_name = match file_name.strip_suffix(&format!(".{}", TRASHED_EXTENSION)) {
+                None => continue,
+                Some(document_name) => Utf8PathBuf::from(document_name),
+            };
+
+            let trashed = match trash.documents.get(&document_name) {
+                Some(trashed) => *trashed,
+                None => match MetaFile::read::<ColdDocument>(
+                    &Trash::document_path(db_dir, &document_name),
+                    quarantine,
+                )
+                .await?
+                {
+                    None => continue,
+                    Some(cold_document) => cold_document.archived(),
+                },
+            };
+            archived.insert(document_name, trashed);
+        }
+        trash.documents = archived;
+
+        Ok(trash)
+    }
+
+    pub(crate) async fn persist(&self, db_dir: &Utf8Path) -> TuringResult<()> {
+        MetaFile::write(&Trash::path(db_dir), &MetaEncoding::Bincode.encode(self)?).await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TRASH_META_NAME);
+
+        path
+    }
+
+    fn dir(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TRASH_DIR);
+
+        path
+    }
+
+    fn document_path(db_dir: &Utf8Path, document_name: &Utf8Path) -> Utf8PathBuf {
+        let mut path = Trash::dir(db_dir);
+        path.push(format!("{}.{}", document_name, TRASHED_EXTENSION));
+
+        path
+    }
+}
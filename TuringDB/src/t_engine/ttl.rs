@@ -0,0 +1,198 @@
+use crate::{
+    Document, FieldSource, LazyDocument, MetaEncoding, MetaFile, Quarantine, TuringResult, Value,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::Duration,
+};
+use tai64::TAI64N;
+
+const TTL_META_NAME: &str = "TTL.meta";
+
+/// A timestamp field of a database whose documents expire `expire_after` past the time the field holds,
+/// along with the documents holding it in time order so the reaper finds the expired ones without
+/// scanning. Documents whose field does not hold a time never expire through it
+/// ```
+/// #[derive(Debug, Clone)]
+/// pub(crate) struct TtlIndex {
+///     key: Vec<u8>,
+///     expire_after: Duration,
+///     documents: Option<BTreeMap<Utf8PathBuf, TAI64N>>,
+///     ordered: BTreeSet<(TAI64N, Utf8PathBuf)>,
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub(crate) struct TtlIndex {
+    key: Vec<u8>,
+    expire_after: Duration,
+    // `None` until the reaper next runs, or once the index could not be kept up to date
+    documents: Option<BTreeMap<Utf8PathBuf, TAI64N>>,
+    ordered: BTreeSet<(TAI64N, Utf8PathBuf)>,
+}
+
+impl TtlIndex {
+    pub(crate) fn new(key: &[u8], expire_after: Duration) -> Self {
+        Self {
+            key: key.into(),
+            expire_after,
+            documents: None,
+            ordered: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub(crate) fn expire_after(&self) -> Duration {
+        self.expire_after
+    }
+    /// Change how long after the time in their field the documents expire, the documents held
+    /// are kept since only the cutoff the reaper looks up changes
+    pub(crate) fn set_expire_after(&mut self, expire_after: Duration) {
+        self.expire_after = expire_after;
+    }
+
+    pub(crate) fn is_built(&self) -> bool {
+        self.documents.is_some()
+    }
+    /// Drop the documents held, they are read again the next time the reaper runs
+    pub(crate) fn reset(&mut self) {
+        self.documents = None;
+        self.ordered.clear();
+    }
+    /// Read the field from every document, which rehydrates documents in the cold tier
+    pub(crate) async fn build(
+        &mut self,
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<()> {
+        let mut built = TtlIndex::new(&self.key, self.expire_after);
+        built.documents = Some(BTreeMap::new());
+
+        for (document_name, document) in documents {
+            built.record(&document_name, Some(&document.open().await?))?;
+        }
+        *self = built;
+
+        Ok(())
+    }
+    /// Bring the index up to date with a document, `None` once it is no longer in the database
+    pub(crate) fn record(
+        &mut self,
+        document_name: &Utf8Path,
+        sled_db: Option<&Document>,
+    ) -> TuringResult<()> {
+        if !self.is_built() {
+            return Ok(());
+        }
+
+        let time = match sled_db {
+            None => None,
+            Some(sled_db) => match sled_db.read_field(&self.key)? {
+                Some(field_data) => match Value::from_field(&field_data) {
+                    Value::Time(time) => Some(time),
+                    _ => None,
+                },
+                None => None,
+            },
+        };
+
+        self.remove(document_name);
+        if let Some(time) = time {
+            self.insert(document_name, time);
+        }
+
+        Ok(())
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, time: TAI64N) {
+        self.ordered.insert((time, document_name.to_path_buf()));
+        if let Some(documents) = self.documents.as_mut() {
+            documents.insert(document_name.to_path_buf(), time);
+        }
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        if let Some(time) = self
+            .documents
+            .as_mut()
+            .and_then(|documents| documents.remove(document_name))
+        {
+            self.ordered.remove(&(time, document_name.to_path_buf()));
+        }
+    }
+    /// The documents whose field holds a time at least `expire_after` before `now`, oldest first.
+    /// Only the expired documents are visited as they come first in time order
+    pub(crate) fn expired(&self, now: TAI64N) -> Vec<Utf8PathBuf> {
+        let cutoff = now - self.expire_after;
+
+        self.ordered
+            .iter()
+            .take_while(|(time, _)| *time <= cutoff)
+            .map(|(_, document_name)| document_name.clone())
+            .collect()
+    }
+    /// Load the TTL index of a database last committed at `committed`. The documents held are only
+    /// trusted when they were persisted by that same commit, otherwise they are read again by the reaper
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+        committed: TAI64N,
+    ) -> TuringResult<Option<TtlIndex>> {
+        let persisted = MetaFile::read::<
+            Option<(
+                Vec<u8>,
+                Duration,
+                Option<(TAI64N, BTreeMap<Utf8PathBuf, TAI64N>)>,
+            )>,
+        >(&TtlIndex::path(db_dir), quarantine)
+        .await?
+        .flatten();
+
+        Ok(persisted.map(|(key, expire_after, documents)| {
+            let mut ttl = TtlIndex::new(&key, expire_after);
+
+            if let Some((stamp, documents)) = documents {
+                if stamp == committed {
+                    ttl.documents = Some(BTreeMap::new());
+                    for (document_name, time) in documents {
+                        ttl.insert(&document_name, time);
+                    }
+                }
+            }
+
+            ttl
+        }))
+    }
+    /// Persist the TTL index along with a commit of its database made at `committed`
+    pub(crate) async fn persist(
+        db_dir: &Utf8Path,
+        ttl: Option<&TtlIndex>,
+        committed: TAI64N,
+    ) -> TuringResult<()> {
+        let persisted = ttl.map(|ttl| {
+            (
+                ttl.key.clone(),
+                ttl.expire_after,
+                ttl.documents
+                    .as_ref()
+                    .map(|documents| (committed, documents.clone())),
+            )
+        });
+
+        MetaFile::write(
+            &TtlIndex::path(db_dir),
+            &MetaEncoding::Bincode.encode(&persisted)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(TTL_META_NAME);
+
+        path
+    }
+}
@@ -0,0 +1,801 @@
+use crate::{
+    Accumulator, Aggregation, Collation, Filter, GeoBox, GeoPoint, Pattern, Query, SortOrder,
+    TuringDbError, TuringResult, Value,
+};
+use camino::Utf8Path;
+
+/// #### TuringQL, the text form of a `Query` or an `Aggregation`
+/// ```text
+/// SELECT * FROM db WHERE field > 5 AND name IN ('a', 'b') LIMIT 10
+/// SELECT name, `total price` FROM `sales/2021` WHERE NOT (EXISTS archived OR flag = true)
+/// SELECT * FROM db ORDER BY price DESC LIMIT 20 AFTER '<continuation token>'
+/// SELECT category, count(*), avg(price) FROM db WHERE price > 0 GROUP BY category
+/// SELECT * FROM db WHERE name STARTS WITH 'ada' NOCASE AND email MATCHES '@example\.(com|org)$'
+/// SELECT * FROM db WHERE active = true SAMPLE 100 ORDER BY name
+/// SELECT * FROM db WHERE name >= 'm' COLLATE 'sv' ORDER BY name
+/// SELECT * FROM db WHERE tags CONTAINS 'rust' AND (tags CONTAINS 'async' OR tags CONTAINS 'io')
+/// SELECT * FROM db WHERE location NEAR (48.8566, 2.3522, 5000)
+/// SELECT * FROM db WHERE location WITHIN (48.80, 2.25, 48.90, 2.42)
+/// ```
+/// Keywords are case insensitive. Names that are not made up of letters, digits and `_`
+/// or that collide with a keyword go between backquotes. Literals are integers, floats,
+/// `'text'` with `''` for a quote, `true`, `false` and `x'00ff'` for bytes.
+/// `NOCASE` after `= 'text'`, `MATCHES 'pattern'` or `STARTS WITH 'prefix'` ignores case.
+/// `COLLATE 'language'` sorts text and compares it in range filters the way the language orders it.
+/// `NEAR (latitude, longitude, metres)` and `WITHIN (south, west, north, east)` filter `GeoPoint` fields
+pub struct TuringQL;
+
+impl TuringQL {
+    /// Parse a TuringQL statement into the query or aggregation it describes
+    pub fn parse(statement: &str) -> TuringResult<Statement> {
+        let tokens = Lexer::new(statement).tokens()?;
+
+        Parser {
+            tokens,
+            position: 0,
+            end: statement.len(),
+        }
+        .statement()
+    }
+
+    fn error(at: usize, reason: &str) -> TuringDbError {
+        TuringDbError::QuerySyntax {
+            at,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A parsed TuringQL statement, a statement with aggregate functions or `GROUP BY` is an aggregation
+/// ```
+/// #[derive(Debug, Clone, PartialEq)]
+/// pub enum Statement {
+///     Select(Query),
+///     Aggregate(Aggregation),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Select(Query),
+    Aggregate(Aggregation),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Field(String),
+    Aggregate(Accumulator),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Text(String),
+    Bytes(Vec<u8>),
+    Number(String),
+    Symbol(&'static str),
+}
+
+struct Lexer<'a> {
+    statement: &'a str,
+    offset: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(statement: &'a str) -> Self {
+        Self {
+            statement,
+            offset: 0,
+        }
+    }
+
+    fn tokens(mut self) -> TuringResult<Vec<(usize, Token)>> {
+        let mut tokens = Vec::new();
+
+        while let Some(current) = self.skip_whitespace() {
+            let start = self.offset;
+
+            let token = match current {
+                '`' => Token::Quoted(self.delimited('`')?),
+                '\'' => Token::Text(self.delimited('\'')?),
+                'x' | 'X' if self.rest()[1..].starts_with('\'') => {
+                    self.offset += 1;
+                    Token::Bytes(Lexer::hex(start, &self.delimited('\'')?)?)
+                }
+                '0'..='9' => Token::Number(self.number()),
+                '-' if self.rest()[1..].starts_with(|next: char| next.is_ascii_digit()) => {
+                    Token::Number(self.number())
+                }
+                current if current.is_alphabetic() || current == '_' => {
+                    Token::Word(self.take_while(|next| next.is_alphanumeric() || next == '_'))
+                }
+                _ => Token::Symbol(self.symbol()?),
+            };
+
+            tokens.push((start, token));
+        }
+
+        Ok(tokens)
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.statement[self.offset..]
+    }
+
+    fn skip_whitespace(&mut self) -> Option<char> {
+        let rest = self.rest();
+        let trimmed = rest.trim_start();
+        self.offset += rest.len() - trimmed.len();
+
+        trimmed.chars().next()
+    }
+
+    fn take_while<F>(&mut self, keep: F) -> String
+    where
+        F: Fn(char) -> bool,
+    {
+        let rest = self.rest();
+        let len = rest.find(|next| !keep(next)).unwrap_or(rest.len());
+        self.offset += len;
+
+        rest[..len].to_owned()
+    }
+    /// Read up to the closing delimiter, a doubled delimiter stands for the delimiter itself
+    fn delimited(&mut self, delimiter: char) -> TuringResult<String> {
+        let start = self.offset;
+        self.offset += delimiter.len_utf8();
+
+        let mut contents = String::new();
+
+        loop {
+            let rest = self.rest();
+
+            match rest.find(delimiter) {
+                None => return Err(TuringQL::error(start, "Unterminated quote")),
+                Some(index) => {
+                    contents.push_str(&rest[..index]);
+                    self.offset += index + delimiter.len_utf8();
+
+                    if self.rest().starts_with(delimiter) {
+                        contents.push(delimiter);
+                        self.offset += delimiter.len_utf8();
+                    } else {
+                        return Ok(contents);
+                    }
+                }
+            }
+        }
+    }
+
+    fn number(&mut self) -> String {
+        let mut number = String::new();
+
+        if self.rest().starts_with('-') {
+            number.push('-');
+            self.offset += 1;
+        }
+        number.push_str(&self.take_while(|next| next.is_ascii_digit() || next == '.'));
+
+        // An exponent, as in `1.5e-3`
+        let rest = self.rest();
+        if rest.starts_with(['e', 'E']) {
+            let exponent = rest[1..].trim_start_matches(['-', '+']);
+
+            if exponent.starts_with(|next: char| next.is_ascii_digit()) {
+                let sign_len = rest.len() - 1 - exponent.len();
+                number.push_str(&rest[..1 + sign_len]);
+                self.offset += 1 + sign_len;
+                number.push_str(&self.take_while(|next| next.is_ascii_digit()));
+            }
+        }
+
+        number
+    }
+
+    fn symbol(&mut self) -> TuringResult<&'static str> {
+        const SYMBOLS: [&str; 11] = ["<=", ">=", "!=", "<>", "=", "<", ">", "(", ")", ",", "*"];
+
+        let rest = self.rest();
+
+        for symbol in SYMBOLS.iter() {
+            if rest.starts_with(symbol) {
+                self.offset += symbol.len();
+
+                return Ok(symbol);
+            }
+        }
+
+        // `;` may only end the statement
+        if rest.trim_end() == ";" {
+            self.offset = self.statement.len();
+
+            return Ok(";");
+        }
+
+        Err(TuringQL::error(self.offset, "Unexpected character"))
+    }
+
+    fn hex(start: usize, digits: &str) -> TuringResult<Vec<u8>> {
+        if !digits.len().is_multiple_of(2) {
+            return Err(TuringQL::error(start, "Odd number of hex digits"));
+        }
+
+        (0..digits.len())
+            .step_by(2)
+            .map(|index| match digits.get(index..index + 2) {
+                Some(pair) => u8::from_str_radix(pair, 16)
+                    .map_err(|_| TuringQL::error(start, "Invalid hex digit")),
+                None => Err(TuringQL::error(start, "Invalid hex digit")),
+            })
+            .collect()
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    // The offset reported for errors at the end of the statement
+    end: usize,
+}
+
+impl Parser {
+    fn statement(mut self) -> TuringResult<Statement> {
+        self.keyword("SELECT")?;
+
+        let items = if self.symbol("*") {
+            None
+        } else {
+            let mut items = vec![self.item()?];
+            while self.symbol(",") {
+                items.push(self.item()?);
+            }
+
+            Some(items)
+        };
+
+        self.keyword("FROM")?;
+        let db_name = self.name()?;
+
+        let filter = if self.is_keyword("WHERE") {
+            self.position += 1;
+            self.or()?
+        } else {
+            Filter::All
+        };
+
+        let aggregates = match &items {
+            None => false,
+            Some(items) => items
+                .iter()
+                .any(|(_, item)| matches!(item, Item::Aggregate(_))),
+        };
+
+        if aggregates || self.is_keyword("GROUP") {
+            let aggregation = self.aggregation(&db_name, items, filter)?;
+            self.end()?;
+
+            return Ok(Statement::Aggregate(aggregation));
+        }
+
+        let mut query = Query::new(Utf8Path::new(&db_name)).set_filter(filter);
+
+        if let Some(items) = items {
+            let fields = items
+                .into_iter()
+                .filter_map(|(_, item)| match item {
+                    Item::Field(name) => Some(name.into_bytes()),
+                    Item::Aggregate(_) => None,
+                })
+                .collect();
+
+            query = query.set_fields(fields);
+        }
+
+        let query = self.paging(query)?;
+        self.end()?;
+
+        Ok(Statement::Select(query))
+    }
+    /// Every plain field an aggregation selects must be the field it groups by
+    fn aggregation(
+        &mut self,
+        db_name: &str,
+        items: Option<Vec<(usize, Item)>>,
+        filter: Filter,
+    ) -> TuringResult<Aggregation> {
+        let mut aggregation = Aggregation::new(Utf8Path::new(db_name)).set_filter(filter);
+
+        let group_by = if self.is_keyword("GROUP") {
+            self.position += 1;
+            self.keyword("BY")?;
+
+            Some(self.name()?)
+        } else {
+            None
+        };
+
+        let items = match items {
+            Some(items) => items,
+            None => return Err(self.error("Expected an aggregate function instead of `*`")),
+        };
+
+        for (offset, item) in items {
+            match item {
+                Item::Aggregate(accumulator) => {
+                    aggregation = aggregation.add_accumulator(accumulator)
+                }
+                Item::Field(name) => {
+                    if group_by.as_ref() != Some(&name) {
+                        return Err(TuringQL::error(
+                            offset,
+                            "Only the field grouped by can be selected with aggregate functions",
+                        ));
+                    }
+                }
+            }
+        }
+
+        if let Some(group_by) = group_by {
+            aggregation = aggregation.set_group_by(group_by.as_bytes());
+        }
+
+        Ok(aggregation)
+    }
+    /// A field name or an aggregate function such as `count(*)` or `sum(price)`
+    fn item(&mut self) -> TuringResult<(usize, Item)> {
+        let offset = match self.tokens.get(self.position) {
+            Some((offset, _)) => *offset,
+            None => self.end,
+        };
+
+        let function = match (
+            self.tokens.get(self.position),
+            self.tokens.get(self.position + 1),
+        ) {
+            (Some((_, Token::Word(word))), Some((_, Token::Symbol("(")))) => word.to_lowercase(),
+            _ => return Ok((offset, Item::Field(self.name()?))),
+        };
+        self.position += 2;
+
+        let accumulator = match function.as_str() {
+            "count" => {
+                self.expect_symbol("*")?;
+                Accumulator::Count
+            }
+            "sum" => Accumulator::Sum(self.name()?.into_bytes()),
+            "avg" => Accumulator::Avg(self.name()?.into_bytes()),
+            "min" => Accumulator::Min(self.name()?.into_bytes()),
+            "max" => Accumulator::Max(self.name()?.into_bytes()),
+            _ => return Err(TuringQL::error(offset, "Unknown aggregate function")),
+        };
+        self.expect_symbol(")")?;
+
+        Ok((offset, Item::Aggregate(accumulator)))
+    }
+
+    fn paging(&mut self, mut query: Query) -> TuringResult<Query> {
+        if self.is_keyword("COLLATE") {
+            self.position += 1;
+
+            let collation = match self.next() {
+                Some(Token::Text(tag)) => Collation::from_tag(&tag).ok(),
+                _ => None,
+            };
+            match collation {
+                Some(collation) => query = query.set_collation(collation),
+                None => {
+                    self.position -= 1;
+
+                    return Err(self.error("Expected a collation such as 'sv' or 'binary'"));
+                }
+            }
+        }
+
+        if self.is_keyword("SAMPLE") {
+            self.position += 1;
+            query = query.set_sample(self.count()?);
+        }
+
+        if self.is_keyword("ORDER") {
+            self.position += 1;
+            self.keyword("BY")?;
+
+            let key = self.name()?;
+            let order = if self.is_keyword("DESC") {
+                self.position += 1;
+                SortOrder::Descending
+            } else {
+                if self.is_keyword("ASC") {
+                    self.position += 1;
+                }
+                SortOrder::Ascending
+            };
+
+            query = query.set_order(key.as_bytes(), order);
+        }
+
+        if self.is_keyword("LIMIT") {
+            self.position += 1;
+            query = query.set_limit(self.count()?);
+        }
+
+        if self.is_keyword("OFFSET") {
+            self.position += 1;
+            query = query.set_offset(self.count()?);
+        }
+
+        if self.is_keyword("AFTER") {
+            self.position += 1;
+
+            match self.next() {
+                Some(Token::Text(token)) => query = query.set_after(&token),
+                _ => {
+                    self.position -= 1;
+
+                    return Err(self.error("Expected a continuation token"));
+                }
+            }
+        }
+
+        Ok(query)
+    }
+
+    fn end(&mut self) -> TuringResult<()> {
+        self.symbol(";");
+
+        match self.tokens.get(self.position) {
+            None => Ok(()),
+            Some((offset, _)) => Err(TuringQL::error(*offset, "Expected the end of the query")),
+        }
+    }
+
+    fn or(&mut self) -> TuringResult<Filter> {
+        let mut filter = self.and()?;
+
+        while self.is_keyword("OR") {
+            self.position += 1;
+            filter = filter.or(self.and()?);
+        }
+
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> TuringResult<Filter> {
+        let mut filter = self.unary()?;
+
+        while self.is_keyword("AND") {
+            self.position += 1;
+            filter = filter.and(self.unary()?);
+        }
+
+        Ok(filter)
+    }
+
+    fn unary(&mut self) -> TuringResult<Filter> {
+        if self.is_keyword("NOT") {
+            self.position += 1;
+
+            return Ok(self.unary()?.negate());
+        }
+
+        if self.symbol("(") {
+            let filter = self.or()?;
+            self.expect_symbol(")")?;
+
+            return Ok(filter);
+        }
+
+        if self.is_keyword("EXISTS") {
+            self.position += 1;
+
+            return Ok(Filter::exists(self.name()?.as_bytes()));
+        }
+
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> TuringResult<Filter> {
+        let name = self.name()?;
+        let key = name.as_bytes();
+
+        let negated = self.is_keyword("NOT");
+        if negated {
+            self.position += 1;
+        }
+
+        if self.is_keyword("IN") {
+            self.position += 1;
+            self.expect_symbol("(")?;
+
+            let mut values = vec![self.value()?];
+            while self.symbol(",") {
+                values.push(self.value()?);
+            }
+            self.expect_symbol(")")?;
+
+            let filter = Filter::is_in(key, values);
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if self.is_keyword("CONTAINS") {
+            self.position += 1;
+
+            let filter = Filter::contains(key, self.value()?);
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if self.is_keyword("NEAR") {
+            self.position += 1;
+            self.expect_symbol("(")?;
+
+            let center = self.point()?;
+            self.expect_symbol(",")?;
+            let radius = self.count()? as u64;
+            self.expect_symbol(")")?;
+
+            let filter = Filter::near(key, center, radius);
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if self.is_keyword("WITHIN") {
+            self.position += 1;
+            self.expect_symbol("(")?;
+
+            let at = self.position;
+            let south_west = self.point()?;
+            self.expect_symbol(",")?;
+            let north_east = self.point()?;
+            self.expect_symbol(")")?;
+
+            let area = match GeoBox::new(south_west, north_east) {
+                Ok(area) => area,
+                Err(_) => {
+                    self.position = at;
+
+                    return Err(self.error("The south edge is north of the north edge"));
+                }
+            };
+            let filter = Filter::within(key, area);
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if self.is_keyword("MATCHES") {
+            self.position += 1;
+
+            let at = self.position;
+            let pattern = self.text()?;
+            let pattern = match Pattern::new(&pattern, self.nocase()) {
+                Ok(pattern) => pattern,
+                Err(_) => {
+                    self.position = at;
+
+                    return Err(self.error("Invalid regular expression"));
+                }
+            };
+            let filter = Filter::Matches(key.into(), pattern);
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if self.is_keyword("STARTS") {
+            self.position += 1;
+            self.keyword("WITH")?;
+
+            let prefix = self.text()?;
+            let filter = if self.nocase() {
+                Filter::starts_with_ignore_case(key, &prefix)
+            } else {
+                Filter::starts_with(key, &prefix)
+            };
+
+            return Ok(if negated { filter.negate() } else { filter });
+        }
+        if negated {
+            return Err(self
+                .error("Expected `IN`, `CONTAINS`, `NEAR`, `WITHIN`, `MATCHES` or `STARTS WITH`"));
+        }
+
+        let filter = match self.next() {
+            Some(Token::Symbol("=")) => match self.value()? {
+                Value::Text(text) if self.nocase() => Filter::eq_ignore_case(key, &text),
+                value => Filter::eq(key, value),
+            },
+            Some(Token::Symbol("!=")) | Some(Token::Symbol("<>")) => Filter::ne(key, self.value()?),
+            Some(Token::Symbol(">")) => Filter::gt(key, self.value()?),
+            Some(Token::Symbol(">=")) => Filter::ge(key, self.value()?),
+            Some(Token::Symbol("<")) => Filter::lt(key, self.value()?),
+            Some(Token::Symbol("<=")) => Filter::le(key, self.value()?),
+            _ => {
+                self.position -= 1;
+
+                return Err(self.error("Expected a comparison"));
+            }
+        };
+
+        Ok(filter)
+    }
+
+    fn value(&mut self) -> TuringResult<Value> {
+        let value = match self.next() {
+            Some(Token::Text(text)) => Some(Value::Text(text)),
+            Some(Token::Bytes(bytes)) => Some(Value::Bytes(bytes)),
+            Some(Token::Number(number)) => Parser::number(&number),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("TRUE") => Some(Value::Bool(true)),
+            Some(Token::Word(word)) if word.eq_ignore_ascii_case("FALSE") => {
+                Some(Value::Bool(false))
+            }
+            _ => None,
+        };
+
+        match value {
+            Some(value) => Ok(value),
+            None => {
+                self.position -= 1;
+
+                Err(self.error("Expected a value"))
+            }
+        }
+    }
+
+    /// A latitude and a longitude in degrees separated by a comma
+    fn point(&mut self) -> TuringResult<GeoPoint> {
+        let at = self.position;
+        let latitude = self.degrees()?;
+        self.expect_symbol(",")?;
+        let longitude = self.degrees()?;
+
+        match GeoPoint::new(latitude, longitude) {
+            Ok(point) => Ok(point),
+            Err(_) => {
+                self.position = at;
+
+                Err(self.error("Coordinates out of range"))
+            }
+        }
+    }
+
+    fn degrees(&mut self) -> TuringResult<f64> {
+        match self.next() {
+            Some(Token::Number(number)) => match number.parse::<f64>() {
+                Ok(degrees) => Ok(degrees),
+                Err(_) => {
+                    self.position -= 1;
+
+                    Err(self.error("Expected a number"))
+                }
+            },
+            _ => {
+                self.position -= 1;
+
+                Err(self.error("Expected a number"))
+            }
+        }
+    }
+
+    fn text(&mut self) -> TuringResult<String> {
+        match self.next() {
+            Some(Token::Text(text)) => Ok(text),
+            _ => {
+                self.position -= 1;
+
+                Err(self.error("Expected text"))
+            }
+        }
+    }
+    /// Consume a `NOCASE` if one follows
+    fn nocase(&mut self) -> bool {
+        let nocase = self.is_keyword("NOCASE");
+        if nocase {
+            self.position += 1;
+        }
+
+        nocase
+    }
+    /// Integers too large for an `i128` are read as a `u128`
+    fn number(number: &str) -> Option<Value> {
+        if let Ok(int) = number.parse::<i128>() {
+            return Some(Value::Int(int));
+        }
+        if let Ok(uint) = number.parse::<u128>() {
+            return Some(Value::UInt(uint));
+        }
+
+        number.parse::<f64>().ok().map(Value::Float)
+    }
+
+    fn count(&mut self) -> TuringResult<usize> {
+        match self.next() {
+            Some(Token::Number(number)) => match number.parse::<usize>() {
+                Ok(count) => Ok(count),
+                Err(_) => {
+                    self.position -= 1;
+
+                    Err(self.error("Expected a whole number"))
+                }
+            },
+            _ => {
+                self.position -= 1;
+
+                Err(self.error("Expected a whole number"))
+            }
+        }
+    }
+    /// A field or database name, keywords are only names between backquotes
+    fn name(&mut self) -> TuringResult<String> {
+        match self.next() {
+            Some(Token::Quoted(name)) => Ok(name),
+            Some(Token::Word(word)) if !Parser::reserved(&word) => Ok(word),
+            _ => {
+                self.position -= 1;
+
+                Err(self.error("Expected a name"))
+            }
+        }
+    }
+
+    fn reserved(word: &str) -> bool {
+        const KEYWORDS: [&str; 27] = [
+            "SELECT", "FROM", "WHERE", "GROUP", "ORDER", "BY", "ASC", "DESC", "LIMIT", "OFFSET",
+            "AFTER", "AND", "OR", "NOT", "IN", "EXISTS", "TRUE", "FALSE", "MATCHES", "STARTS",
+            "WITH", "NOCASE", "SAMPLE", "COLLATE", "CONTAINS", "NEAR", "WITHIN",
+        ];
+
+        KEYWORDS
+            .iter()
+            .any(|keyword| word.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .map(|(_, token)| token.clone());
+        self.position += 1;
+
+        token
+    }
+
+    fn is_keyword(&self, keyword: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Word(word))) => word.eq_ignore_ascii_case(keyword),
+            _ => false,
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str) -> TuringResult<()> {
+        if self.is_keyword(keyword) {
+            self.position += 1;
+
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", keyword)))
+        }
+    }
+
+    fn symbol(&mut self, symbol: &str) -> bool {
+        match self.tokens.get(self.position) {
+            Some((_, Token::Symbol(found))) if *found == symbol => {
+                self.position += 1;
+
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: &str) -> TuringResult<()> {
+        if self.symbol(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected `{}`", symbol)))
+        }
+    }
+
+    fn error(&self, reason: &str) -> TuringDbError {
+        let offset = match self.tokens.get(self.position) {
+            Some((offset, _)) => *offset,
+            None => self.end,
+        };
+
+        TuringQL::error(offset, reason)
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    FieldSource, LazyDocument, MetaEncoding, MetaFile, Quarantine, TuringDbError, TuringResult,
+    Value,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{collections::BTreeMap, sync::Arc};
+
+const UNIQUE_META_NAME: &str = "UNIQUE.meta";
+
+/// The field of a database no two documents may hold the same value of, along with the document
+/// holding each value. Values are unique as `Value` compares them, so `1` and `1.0` collide.
+/// `NaN` equals nothing, so any number of documents may hold it
+/// ```
+/// #[derive(Debug, Clone, Default)]
+/// pub(crate) struct UniqueKey {
+///     key: Vec<u8>,
+///     documents: BTreeMap<Utf8PathBuf, Value>,
+///     owners: BTreeMap<Value, Utf8PathBuf>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UniqueKey {
+    key: Vec<u8>,
+    documents: BTreeMap<Utf8PathBuf, Value>,
+    // The document holding each value, `NaN` is left out
+    owners: BTreeMap<Value, Utf8PathBuf>,
+}
+
+impl UniqueKey {
+    fn new(key: &[u8]) -> Self {
+        Self {
+            key: key.into(),
+            documents: BTreeMap::new(),
+            owners: BTreeMap::new(),
+        }
+    }
+    /// Read the field `key` of every document, which rehydrates documents in the cold tier.
+    /// Fails with `AlreadyExists` if two documents already hold the same value
+    pub(crate) async fn build(
+        key: &[u8],
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<Self> {
+        let mut unique = UniqueKey::new(key);
+
+        for (document_name, document) in documents {
+            if let Some(field_data) = document.open().await?.read_field(key)? {
+                let value = Value::from_field(&field_data);
+
+                if unique.owner(&value).is_some() {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+                unique.insert(&document_name, value);
+            }
+        }
+
+        Ok(unique)
+    }
+
+    pub(crate) fn key(&self) -> &[u8] {
+        &self.key
+    }
+    /// The document holding `value`
+    pub(crate) fn owner(&self, value: &Value) -> Option<&Utf8Path> {
+        if UniqueKey::is_unordered(value) {
+            return None;
+        }
+
+        self.owners.get(value).map(Utf8PathBuf::as_path)
+    }
+    /// Check the values a write leaves the documents it writes to holding, `None` for a document
+    /// left without the field. A value may move between the documents of the same write
+    pub(crate) fn check(&self, written: &BTreeMap<Utf8PathBuf, Option<Value>>) -> TuringResult<()> {
+        let mut claimed: BTreeMap<&Value, &Utf8Path> = BTreeMap::new();
+
+        for (document_name, value) in written {
+            let value = match value {
+                Some(value) if !UniqueKey::is_unordered(value) => value,
+                _ => continue,
+            };
+
+            if claimed.insert(value, document_name.as_path()).is_some() {
+                return Err(TuringDbError::AlreadyExists);
+            }
+
+            if let Some(owner) = self.owner(value) {
+                // The owner keeps the value unless the same write gives it another one
+                let released = owner == document_name.as_path()
+                    || matches!(written.get(owner), Some(other) if other.as_ref() != Some(value));
+
+                if !released {
+                    return Err(TuringDbError::AlreadyExists);
+                }
+            }
+        }
+
+        Ok(())
+    }
+    /// Record the value a document holds once a write was applied, `None` once it no longer holds one
+    pub(crate) fn record(&mut self, document_name: &Utf8Path, value: Option<Value>) {
+        self.remove(document_name);
+
+        if let Some(value) = value {
+            self.insert(document_name, value);
+        }
+    }
+    /// Forget the documents that are no longer in the database
+    pub(crate) fn retain<F: FnMut(&Utf8Path) -> bool>(&mut self, mut listed: F) {
+        let removed = self
+            .documents
+            .keys()
+            .filter(|document_name| !listed(document_name))
+            .cloned()
+            .collect::<Vec<Utf8PathBuf>>();
+
+        for document_name in removed {
+            self.remove(&document_name);
+        }
+    }
+
+    fn insert(&mut self, document_name: &Utf8Path, value: Value) {
+        if !UniqueKey::is_unordered(&value) {
+            self.owners
+                .insert(value.clone(), document_name.to_path_buf());
+        }
+        self.documents.insert(document_name.to_path_buf(), value);
+    }
+
+    fn remove(&mut self, document_name: &Utf8Path) {
+        if let Some(value) = self.documents.remove(document_name) {
+            if self.owner(&value) == Some(document_name) {
+                self.owners.remove(&value);
+            }
+        }
+    }
+
+    fn is_unordered(value: &Value) -> bool {
+        matches!(value, Value::Float(float) if float.is_nan())
+    }
+
+    pub(crate) async fn load(
+        db_dir: &Utf8Path,
+        quarantine: &Quarantine,
+    ) -> TuringResult<Option<UniqueKey>> {
+        let persisted = MetaFile::read::<Option<(Vec<u8>, BTreeMap<Utf8PathBuf, Value>)>>(
+            &UniqueKey::path(db_dir),
+            quarantine,
+        )
+        .await?
+        .flatten();
+
+        Ok(persisted.map(|(key, documents)| {
+            let mut unique = UniqueKey::new(&key);
+            for (document_name, value) in documents {
+                unique.insert(&document_name, value);
+            }
+
+            unique
+        }))
+    }
+
+    pub(crate) async fn persist(db_dir: &Utf8Path, unique: Option<&UniqueKey>) -> TuringResult<()> {
+        let persisted = unique.map(|unique| (unique.key.clone(), unique.documents.clone()));
+
+        MetaFile::write(
+            &UniqueKey::path(db_dir),
+            &MetaEncoding::Bincode.encode(&persisted)?,
+        )
+        .await
+    }
+
+    pub(crate) fn path(db_dir: &Utf8Path) -> Utf8PathBuf {
+        let mut path: Utf8PathBuf = db_dir.into();
+        path.push(UNIQUE_META_NAME);
+
+        path
+    }
+}
@@ -0,0 +1,119 @@
+use crate::{TuringDbError, TuringResult};
+use async_lock::Mutex;
+use camino::{Utf8Path, Utf8PathBuf};
+use std::{fmt, fs::File};
+
+/// Appends to the ops log through io_uring instead of the blocking thread pool.
+/// The log is kept open between appends so an append costs a single submission
+/// rather than an open, a write and a close
+pub(crate) struct UringLog {
+    // The ring is only set up with the first append so a kernel without io_uring fails on use
+    state: Mutex<Option<UringState>>,
+}
+
+struct UringState {
+    ring: rio::Rio,
+    // The open log along with the path it was opened from and its length
+    log: Option<(Utf8PathBuf, File, u64)>,
+}
+
+impl fmt::Debug for UringLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UringLog").finish()
+    }
+}
+
+impl UringLog {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(None),
+        }
+    }
+    /// Append `frame` to the end of the log at `path`, writing `header` first if the log is empty
+    pub(crate) async fn append(
+        &self,
+        path: &Utf8Path,
+        header: &[u8],
+        frame: &[u8],
+        sync: bool,
+    ) -> TuringResult<()> {
+        let mut state = self.state.lock().await;
+
+        if state.is_none() {
+            *state = Some(UringState {
+                ring: rio::new()?,
+                log: None,
+            });
+        }
+        let state = match &mut *state {
+            Some(state) => state,
+            None => return Err(TuringDbError::Bug("io_uring set up without a ring".into())),
+        };
+
+        let reopen = match &state.log {
+            Some((log_path, _, _)) => log_path != path,
+            None => true,
+        };
+        if reopen {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(false)
+                .open(path)?;
+            let len = file.metadata()?.len();
+
+            state.log = Some((path.to_path_buf(), file, len));
+        }
+
+        let (_, file, len) = match &mut state.log {
+            Some(log) => log,
+            None => {
+                return Err(TuringDbError::Bug(
+                    "io_uring log closed after opening".into(),
+                ))
+            }
+        };
+
+        let mut bytes = Vec::with_capacity(header.len() + frame.len());
+        if *len == 0 {
+            bytes.extend_from_slice(header);
+        }
+        bytes.extend_from_slice(frame);
+
+        // A completion may write less than it was given
+        let mut written = 0_usize;
+        while written < bytes.len() {
+            written += state
+                .ring
+                .write_at(file, &&bytes[written..], *len + written as u64)
+                .await?;
+        }
+        *len += bytes.len() as u64;
+
+        if sync {
+            state.ring.fdatasync(file).await?;
+        }
+
+        Ok(())
+    }
+    /// Sync the open log, returning `false` if no log is open
+    pub(crate) async fn sync(&self) -> TuringResult<bool> {
+        match &*self.state.lock().await {
+            Some(UringState {
+                ring,
+                log: Some((_, file, _)),
+            }) => {
+                ring.fdatasync(file).await?;
+
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+    /// Close the open log after it was replaced or truncated so the next append opens it again
+    pub(crate) async fn close(&self) {
+        if let Some(state) = &mut *self.state.lock().await {
+            state.log = None;
+        }
+    }
+}
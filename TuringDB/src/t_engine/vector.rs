@@ -0,0 +1,595 @@
+use crate::{
+    DataType, Document, FieldData, LazyDocument, SchemaViolation, TDBCell, TuringDB, TuringDbError,
+    TuringResult,
+};
+use camino::{Utf8Path, Utf8PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::{BinaryHeap, HashMap, HashSet},
+    convert::TryInto,
+    sync::Arc,
+};
+
+// The highest layer a node of an HNSW index is put on
+const HNSW_MAX_LEVEL: usize = 16;
+
+/// How far apart two embeddings are
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub enum Distance {
+///     Cosine,
+///     L2,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Distance {
+    /// One minus the cosine of the angle between the embeddings, a zero embedding is at distance one of any other
+    Cosine,
+    /// The euclidean distance between the embeddings
+    L2,
+}
+
+impl Distance {
+    pub fn between(&self, left: &[f32], right: &[f32]) -> f32 {
+        match self {
+            Distance::Cosine => {
+                let mut dot = 0_f32;
+                let mut left_norm = 0_f32;
+                let mut right_norm = 0_f32;
+                for (left, right) in left.iter().zip(right) {
+                    dot += left * right;
+                    left_norm += left * left;
+                    right_norm += right * right;
+                }
+
+                let norms = left_norm.sqrt() * right_norm.sqrt();
+                if norms == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot / norms
+                }
+            }
+            Distance::L2 => left
+                .iter()
+                .zip(right)
+                .map(|(left, right)| (left - right) * (left - right))
+                .sum::<f32>()
+                .sqrt(),
+        }
+    }
+}
+
+/// How the HNSW index of a vector database is built and searched, larger values find
+/// nearer neighbours at the cost of memory and time
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct HnswParams {
+///     m: u16,
+///     ef_construction: u16,
+///     ef_search: u16,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HnswParams {
+    // The number of neighbours each node keeps on a layer, twice as many on the bottom layer
+    m: u16,
+    // The number of candidates considered when a node is inserted
+    ef_construction: u16,
+    // The number of candidates considered when the index is searched
+    ef_search: u16,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 200,
+            ef_search: 64,
+        }
+    }
+}
+
+impl HnswParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_m(mut self, m: u16) -> Self {
+        self.m = m;
+
+        self
+    }
+
+    pub fn set_ef_construction(mut self, ef_construction: u16) -> Self {
+        self.ef_construction = ef_construction;
+
+        self
+    }
+
+    pub fn set_ef_search(mut self, ef_search: u16) -> Self {
+        self.ef_search = ef_search;
+
+        self
+    }
+
+    pub fn get_m(&self) -> u16 {
+        self.m
+    }
+
+    pub fn get_ef_construction(&self) -> u16 {
+        self.ef_construction
+    }
+
+    pub fn get_ef_search(&self) -> u16 {
+        self.ef_search
+    }
+}
+
+/// The embeddings held by the documents of a vector database, each document holds
+/// its embedding in the field `key`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct VectorSpace {
+///     key: Vec<u8>,
+///     dimensions: u32,
+///     distance: Distance,
+///     hnsw: Option<HnswParams>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VectorSpace {
+    key: Vec<u8>,
+    dimensions: u32,
+    distance: Distance,
+    // Without an index every embedding is compared to the query
+    hnsw: Option<HnswParams>,
+}
+
+impl VectorSpace {
+    pub fn new(key: &[u8], dimensions: u32, distance: Distance) -> Self {
+        Self {
+            key: key.into(),
+            dimensions,
+            distance,
+            hnsw: None,
+        }
+    }
+    /// Search the embeddings through an HNSW index kept in memory instead of comparing the query to every
+    /// embedding. The index is built the first time the database is searched and kept up to date as it is written
+    pub fn set_hnsw(mut self, hnsw: HnswParams) -> Self {
+        self.hnsw = Some(hnsw);
+
+        self
+    }
+
+    pub fn get_key(&self) -> &[u8] {
+        &self.key
+    }
+
+    pub fn get_dimensions(&self) -> u32 {
+        self.dimensions
+    }
+
+    pub fn get_distance(&self) -> Distance {
+        self.distance
+    }
+
+    pub fn get_hnsw(&self) -> Option<HnswParams> {
+        self.hnsw
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        let hnsw_valid = match self.hnsw {
+            None => true,
+            Some(hnsw) => hnsw.m >= 2 && hnsw.ef_construction > 0 && hnsw.ef_search > 0,
+        };
+
+        self.dimensions > 0 && hnsw_valid
+    }
+    /// Check a value written to the field `key`, only the embedding field is held to the space
+    pub(crate) fn check_embedding(
+        &self,
+        key: &[u8],
+        data_type: DataType,
+        data: &[u8],
+    ) -> Option<SchemaViolation> {
+        if key != self.key.as_slice() {
+            return None;
+        }
+
+        if data_type != DataType::VECTOR {
+            return Some(SchemaViolation::TypeMismatch {
+                key: key.into(),
+                expected: DataType::VECTOR,
+                found: data_type,
+            });
+        }
+        let found = (data.len() / Embedding::DIMENSION_LEN) as u32;
+        if !data.len().is_multiple_of(Embedding::DIMENSION_LEN) || found != self.dimensions {
+            return Some(SchemaViolation::DimensionMismatch {
+                key: key.into(),
+                expected: self.dimensions,
+                found,
+            });
+        }
+
+        None
+    }
+}
+
+/// Encodes embeddings as `DataType::VECTOR` fields holding little endian `f32`s
+#[derive(Debug, Clone, Copy)]
+pub struct Embedding;
+
+impl Embedding {
+    const DIMENSION_LEN: usize = 4;
+
+    pub fn encode(vector: &[f32]) -> TDBCell {
+        let mut data = Vec::with_capacity(vector.len() * Embedding::DIMENSION_LEN);
+        for dimension in vector {
+            data.extend_from_slice(&dimension.to_le_bytes());
+        }
+
+        TDBCell::new(DataType::VECTOR, &data)
+    }
+
+    pub fn decode(field_data: &FieldData) -> TuringResult<Vec<f32>> {
+        if field_data.data_type() != DataType::VECTOR
+            || !field_data
+                .data()
+                .len()
+                .is_multiple_of(Embedding::DIMENSION_LEN)
+        {
+            return Err(TuringDbError::FieldTypeMismatch);
+        }
+
+        Ok(field_data
+            .data()
+            .chunks_exact(Embedding::DIMENSION_LEN)
+            .filter_map(|bytes| bytes.try_into().ok().map(f32::from_le_bytes))
+            .collect())
+    }
+    /// Read the embedding a document holds in `key`, `None` if it holds none
+    pub(crate) fn read(sled_db: &Document, key: &[u8]) -> TuringResult<Option<Vec<f32>>> {
+        match sled_db.get(key)? {
+            None => Ok(None),
+            Some(stored) => match Embedding::decode(&TuringDB::decode_field(&stored)?) {
+                Ok(vector) => Ok(Some(vector)),
+                Err(TuringDbError::FieldTypeMismatch) => Ok(None),
+                Err(error) => Err(error),
+            },
+        }
+    }
+}
+
+/// A document found near the query of a nearest neighbour search
+/// ```
+/// #[derive(Debug, Clone, Serialize, Deserialize)]
+/// pub struct Neighbour {
+///     document: Utf8PathBuf,
+///     distance: f32,
+/// }
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Neighbour {
+    document: Utf8PathBuf,
+    distance: f32,
+}
+
+impl Neighbour {
+    pub(crate) fn new(document: Utf8PathBuf, distance: f32) -> Self {
+        Self { document, distance }
+    }
+
+    pub fn document(&self) -> &Utf8Path {
+        &self.document
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+}
+
+impl PartialEq for Neighbour {
+    fn eq(&self, other: &Neighbour) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Neighbour {}
+
+impl PartialOrd for Neighbour {
+    fn partial_cmp(&self, other: &Neighbour) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Neighbour {
+    fn cmp(&self, other: &Neighbour) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or_else(|| self.distance.is_nan().cmp(&other.distance.is_nan()))
+            .then_with(|| self.document.cmp(&other.document))
+    }
+}
+
+/// A node of an HNSW index at some distance from a query
+#[derive(Debug, Clone, Copy)]
+struct Scored {
+    distance: f32,
+    node: usize,
+}
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Scored) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Scored) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Scored) -> Ordering {
+        self.distance
+            .partial_cmp(&other.distance)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| self.node.cmp(&other.node))
+    }
+}
+
+#[derive(Debug)]
+struct HnswNode {
+    document: Utf8PathBuf,
+    vector: Vec<f32>,
+    // The neighbours of the node on each layer it is on, from the bottom layer up
+    layers: Vec<Vec<usize>>,
+    // A removed node is still walked through while searching but never returned
+    removed: bool,
+}
+
+/// A hierarchical navigable small world graph over the embeddings of a vector database,
+/// the nearest neighbours of a query are found without comparing it to every embedding.
+/// A rewritten embedding is inserted as a new node, the index is rebuilt once most of its nodes are removed
+/// ```
+/// #[derive(Debug)]
+/// pub(crate) struct Hnsw {
+///     params: HnswParams,
+///     distance: Distance,
+///     nodes: Vec<HnswNode>,
+///     live: HashMap<Utf8PathBuf, usize>,
+///     entry: Option<usize>,
+/// }
+/// ```
+#[derive(Debug)]
+pub(crate) struct Hnsw {
+    params: HnswParams,
+    distance: Distance,
+    nodes: Vec<HnswNode>,
+    // The node holding the current embedding of each document
+    live: HashMap<Utf8PathBuf, usize>,
+    // The node on the highest layer, every search starts from it
+    entry: Option<usize>,
+}
+
+impl Hnsw {
+    pub(crate) fn new(distance: Distance, params: HnswParams) -> Self {
+        Self {
+            params,
+            distance,
+            nodes: Vec::new(),
+            live: HashMap::new(),
+            entry: None,
+        }
+    }
+    /// Build the index of a vector space by reading the embedding of every document
+    pub(crate) async fn build(
+        space: &VectorSpace,
+        params: HnswParams,
+        documents: Vec<(Utf8PathBuf, Arc<LazyDocument>)>,
+    ) -> TuringResult<Self> {
+        let mut hnsw = Hnsw::new(space.distance, params);
+
+        for (document_name, document) in documents {
+            if let Some(vector) = Embedding::read(&document.open().await?, &space.key)? {
+                hnsw.insert(&document_name, vector);
+            }
+        }
+
+        Ok(hnsw)
+    }
+
+    pub(crate) fn insert(&mut self, document_name: &Utf8Path, vector: Vec<f32>) {
+        self.remove(document_name);
+
+        let level = self.level(document_name);
+        let node = self.nodes.len();
+        self.nodes.push(HnswNode {
+            document: document_name.into(),
+            vector,
+            layers: vec![Vec::new(); level + 1],
+            removed: false,
+        });
+        self.live.insert(document_name.into(), node);
+
+        let entry = match self.entry {
+            None => {
+                self.entry = Some(node);
+                return;
+            }
+            Some(entry) => entry,
+        };
+        let top = self.nodes[entry].layers.len() - 1;
+        let query = self.nodes[node].vector.clone();
+
+        let mut entry_points = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            entry_points = Hnsw::nodes_of(self.search_layer(&query, &entry_points, 1, layer));
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(
+                &query,
+                &entry_points,
+                self.params.ef_construction as usize,
+                layer,
+            );
+            let neighbours = found
+                .iter()
+                .take(self.params.m as usize)
+                .map(|scored| scored.node)
+                .collect::<Vec<usize>>();
+
+            for neighbour in &neighbours {
+                self.connect(*neighbour, node, layer);
+            }
+            self.nodes[node].layers[layer] = neighbours;
+            entry_points = Hnsw::nodes_of(found);
+        }
+
+        if level > top {
+            self.entry = Some(node);
+        }
+    }
+
+    pub(crate) fn remove(&mut self, document_name: &Utf8Path) {
+        if let Some(node) = self.live.remove(document_name) {
+            self.nodes[node].removed = true;
+
+            if self.nodes.len() > 2 * self.live.len() {
+                self.rebuild();
+            }
+        }
+    }
+    /// The `k` documents whose embeddings are nearest to `query`, nearest first
+    pub(crate) fn search(&self, query: &[f32], k: usize) -> Vec<Neighbour> {
+        let entry = match self.entry {
+            None => return Vec::new(),
+            Some(entry) => entry,
+        };
+        let top = self.nodes[entry].layers.len() - 1;
+
+        let mut entry_points = vec![entry];
+        for layer in (1..=top).rev() {
+            entry_points = Hnsw::nodes_of(self.search_layer(query, &entry_points, 1, layer));
+        }
+
+        // Removed nodes take up candidates, there are never more of them than live ones
+        let ef =
+            (self.params.ef_search as usize).max(k) * self.nodes.len() / self.live.len().max(1);
+
+        self.search_layer(query, &entry_points, ef, 0)
+            .into_iter()
+            .filter(|scored| !self.nodes[scored.node].removed)
+            .take(k)
+            .map(|scored| Neighbour::new(self.nodes[scored.node].document.clone(), scored.distance))
+            .collect()
+    }
+    /// The `ef` nodes of `layer` nearest to `query` found by walking from `entry_points`, nearest first
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited = entry_points.iter().copied().collect::<HashSet<usize>>();
+        let mut candidates = BinaryHeap::new();
+        let mut found = BinaryHeap::new();
+
+        for node in entry_points {
+            let scored = self.score(query, *node);
+            candidates.push(Reverse(scored));
+            found.push(scored);
+        }
+
+        while let Some(Reverse(nearest)) = candidates.pop() {
+            if let Some(furthest) = found.peek() {
+                if found.len() >= ef && nearest.distance > furthest.distance {
+                    break;
+                }
+            }
+
+            for neighbour in &self.nodes[nearest.node].layers[layer] {
+                if !visited.insert(*neighbour) {
+                    continue;
+                }
+
+                let scored = self.score(query, *neighbour);
+                let closer = match found.peek() {
+                    None => true,
+                    Some(furthest) => scored.distance < furthest.distance,
+                };
+                if found.len() < ef || closer {
+                    candidates.push(Reverse(scored));
+                    found.push(scored);
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        found.into_sorted_vec()
+    }
+    /// Link `neighbour` to `node` on `layer`, keeping only the nearest neighbours of `node` once it has too many
+    fn connect(&mut self, node: usize, neighbour: usize, layer: usize) {
+        let max = if layer == 0 {
+            2 * self.params.m as usize
+        } else {
+            self.params.m as usize
+        };
+
+        self.nodes[node].layers[layer].push(neighbour);
+        if self.nodes[node].layers[layer].len() <= max {
+            return;
+        }
+
+        let vector = self.nodes[node].vector.clone();
+        let mut neighbours = self.nodes[node].layers[layer]
+            .iter()
+            .map(|neighbour| self.score(&vector, *neighbour))
+            .collect::<Vec<Scored>>();
+        neighbours.sort();
+        neighbours.truncate(max);
+
+        self.nodes[node].layers[layer] = Hnsw::nodes_of(neighbours);
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        self.live.clear();
+        self.entry = None;
+
+        for node in nodes {
+            if !node.removed {
+                self.insert(&node.document, node.vector);
+            }
+        }
+    }
+    /// The highest layer a document is put on, drawn from an exponential distribution by hashing its name
+    /// so that rebuilding the index puts it on the same layer
+    fn level(&self, document_name: &Utf8Path) -> usize {
+        let hash = seahash::hash(document_name.as_str().as_bytes());
+        let uniform = (hash as f64 + 1.0) / (u64::MAX as f64 + 2.0);
+        let level = -uniform.ln() / (self.params.m as f64).ln();
+
+        (level as usize).min(HNSW_MAX_LEVEL)
+    }
+
+    fn score(&self, query: &[f32], node: usize) -> Scored {
+        Scored {
+            distance: self.distance.between(query, &self.nodes[node].vector),
+            node,
+        }
+    }
+
+    fn nodes_of(scored: Vec<Scored>) -> Vec<usize> {
+        scored.into_iter().map(|scored| scored.node).collect()
+    }
+}
@@ -0,0 +1,112 @@
+use crate::{Document, FieldData, History, TuringDbError, TuringResult, STREAM_TREE};
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+
+/// The revisions of a document that open views are reading at.
+/// History newer than the oldest pinned revision is kept until the view is dropped
+#[derive(Debug, Default)]
+pub(crate) struct RevisionPins {
+    pins: Mutex<BTreeMap<u64, usize>>,
+}
+
+impl RevisionPins {
+    /// Pin the revision returned by `current` while no other pin can be added or removed,
+    /// so a write that commits after `current` runs always sees the pin before it prunes history
+    pub(crate) fn pin<F>(self: &Arc<Self>, current: F) -> TuringResult<RevisionPin>
+    where
+        F: FnOnce() -> TuringResult<u64>,
+    {
+        let mut pins = self.lock_pins()?;
+
+        let revision = current()?;
+        *pins.entry(revision).or_insert(0) += 1;
+
+        Ok(RevisionPin {
+            revision,
+            pins: Arc::clone(self),
+        })
+    }
+    /// The oldest revision any view is reading at
+    pub(crate) fn oldest(&self) -> TuringResult<Option<u64>> {
+        Ok(self.lock_pins()?.keys().next().copied())
+    }
+
+    fn lock_pins(&self) -> TuringResult<std::sync::MutexGuard<'_, BTreeMap<u64, usize>>> {
+        match self.pins.lock() {
+            Ok(pins) => Ok(pins),
+            Err(_) => Err(TuringDbError::Bug("Revision pins lock poisoned".into())),
+        }
+    }
+}
+
+/// Releases its revision when dropped
+#[derive(Debug)]
+pub(crate) struct RevisionPin {
+    revision: u64,
+    pins: Arc<RevisionPins>,
+}
+
+impl Drop for RevisionPin {
+    fn drop(&mut self) {
+        if let Ok(mut pins) = self.pins.lock_pins() {
+            if let Some(count) = pins.get_mut(&self.revision) {
+                *count -= 1;
+
+                if *count == 0 {
+                    pins.remove(&self.revision);
+                }
+            }
+        }
+    }
+}
+
+/// A read-only view of a document frozen at the revision it was opened at.
+///
+/// Reading through the view never blocks writers. Writes made after the view was opened are
+/// undone using the history of the document, which is kept for as long as the view is alive.
+/// Streamed fields are not versioned and are not visible through a view
+/// ```
+/// #[derive(Debug)]
+/// pub struct DocumentView {
+///     document: Document,
+///     pin: RevisionPin,
+/// }
+/// ```
+#[derive(Debug)]
+pub struct DocumentView {
+    document: Document,
+    pin: RevisionPin,
+}
+
+impl DocumentView {
+    pub(crate) fn open(document: Document, pins: &Arc<RevisionPins>) -> TuringResult<Self> {
+        let pin = pins.pin(|| History::current_revision(&document))?;
+
+        Ok(Self { document, pin })
+    }
+    /// The revision of the document the view is frozen at
+    pub fn revision(&self) -> u64 {
+        self.pin.revision
+    }
+    /// Get the contents of a field as they were when the view was opened
+    pub fn field_get(&self, key: &[u8]) -> TuringResult<FieldData> {
+        match History::field_at(&self.document, key, self.revision())? {
+            Some(field_data) => Ok(field_data),
+            None => {
+                if self.document.open_tree(STREAM_TREE)?.contains_key(key)? {
+                    Err(TuringDbError::StreamedField)
+                } else {
+                    Err(TuringDbError::FieldNotFound)
+                }
+            }
+        }
+    }
+    /// Scan every field of the document as it was when the view was opened, sorted by key
+    pub fn field_scan(&self) -> TuringResult<Vec<(Vec<u8>, FieldData)>> {
+        Ok(History::fields_at(&self.document, self.revision())?
+            .into_iter()
+            .collect())
+    }
+}
@@ -0,0 +1,539 @@
+use crate::{deadline, LogRecord, TuringDbError, TuringEngine, TuringResult, CHANGE_BUFFER};
+use async_channel::{Receiver, Sender};
+use async_executor::Executor;
+use async_io::{Async, Timer};
+use camino::Utf8Path;
+use futures_lite::{
+    future,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use serde_json::{json, Value as Json};
+use std::{
+    convert::TryInto,
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// The port a `WebSocketGateway` listens on unless another address is set
+pub const DEFAULT_WEBSOCKET_PORT: u16 = 4381;
+/// How often a `WebSocketGateway` pings its clients unless another interval is set
+pub const DEFAULT_WEBSOCKET_PING_INTERVAL: Duration = Duration::from_secs(30);
+/// Appended to the key of a client to answer its handshake, from RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+/// The largest message a client may send, subscriptions are short
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+/// The largest handshake read before a connection is dropped
+const MAX_HEAD_LEN: usize = 16 * 1024;
+const MAX_HEADERS: usize = 32;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+/// Pushes the writes applied to databases to WebSocket clients as they happen.
+///
+/// A client subscribes by sending `{"subscribe": "db"}` for every write to a database
+/// or `{"subscribe": "db", "document": "name"}` for the writes to one document, and may
+/// subscribe any number of times on one connection. Each write is then sent as
+/// `{"lsn": 7, "db": "db", "documents": ["name"], "op": {..}}` where `op` is the operation
+/// as the ops log holds it and `documents` is `null` when any document may have changed.
+/// A client that falls more than `CHANGE_BUFFER` writes behind is sent a close frame
+/// and subscribes again on a new connection.
+/// Clients are pinged every ping interval and one that sends nothing, not even a pong,
+/// for two intervals is disconnected, which ends its subscriptions
+/// ```
+/// #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// pub struct WebSocketGateway {
+///     address: SocketAddr,
+///     ping_interval: Duration,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WebSocketGateway {
+    address: SocketAddr,
+    ping_interval: Duration,
+}
+
+impl Default for WebSocketGateway {
+    fn default() -> Self {
+        Self {
+            address: SocketAddr::from(([127, 0, 0, 1], DEFAULT_WEBSOCKET_PORT)),
+            ping_interval: DEFAULT_WEBSOCKET_PING_INTERVAL,
+        }
+    }
+}
+
+/// What the reading half of a connection asks the writing half to send
+#[derive(Debug)]
+enum Control {
+    Text(String),
+    Ping,
+    Pong(Vec<u8>),
+    Close,
+}
+
+impl WebSocketGateway {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+
+        self
+    }
+
+    pub fn set_ping_interval(mut self, ping_interval: Duration) -> Self {
+        self.ping_interval = ping_interval;
+
+        self
+    }
+
+    pub fn get_address(&self) -> SocketAddr {
+        self.address
+    }
+
+    pub fn get_ping_interval(&self) -> Duration {
+        self.ping_interval
+    }
+    /// Accept connections until the listener fails, spawning a task on `executor` for each of them.
+    /// A connection that fails only ends its own task
+    pub async fn run<'a>(
+        &self,
+        engine: Arc<TuringEngine>,
+        executor: &Executor<'a>,
+    ) -> TuringResult<()> {
+        let listener = Async::<TcpListener>::bind(self.address)?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+
+            executor
+                .spawn(WebSocketGateway::serve(
+                    Arc::clone(&engine),
+                    stream,
+                    self.ping_interval,
+                ))
+                .detach();
+        }
+    }
+    /// Messages from the client are read while changes are written to it,
+    /// the connection ends with whichever half ends first
+    async fn serve(
+        engine: Arc<TuringEngine>,
+        stream: Async<TcpStream>,
+        ping_interval: Duration,
+    ) -> TuringResult<()> {
+        if !WebSocketGateway::handshake(&stream).await? {
+            return Ok(());
+        }
+
+        let (changes_sender, changes) = async_channel::bounded(CHANGE_BUFFER);
+        let (control_sender, control) = async_channel::unbounded();
+
+        future::or(
+            WebSocketGateway::read_messages(
+                &engine,
+                &stream,
+                changes_sender,
+                control_sender,
+                ping_interval,
+            ),
+            WebSocketGateway::write_messages(&stream, changes, control, ping_interval),
+        )
+        .await
+    }
+    /// Answer the opening handshake, `false` when the request was not a WebSocket upgrade
+    async fn handshake(stream: &Async<TcpStream>) -> TuringResult<bool> {
+        let mut stream = stream;
+        let mut buffer = Vec::new();
+        let mut chunk = [0_u8; 1024];
+
+        let head_len = loop {
+            if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            if buffer.len() > MAX_HEAD_LEN {
+                return Ok(false);
+            }
+
+            let read = stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(TuringDbError::UnexpectedEof);
+            }
+            buffer.extend_from_slice(&chunk[..read]);
+        };
+
+        let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+        let mut request = httparse::Request::new(&mut headers);
+        let key = match request.parse(&buffer[..head_len]) {
+            Ok(httparse::Status::Complete(_)) => request
+                .headers
+                .iter()
+                .find(|header| header.name.eq_ignore_ascii_case("sec-websocket-key"))
+                .and_then(|header| std::str::from_utf8(header.value).ok())
+                .map(|key| key.trim().to_owned()),
+            _ => None,
+        };
+
+        let response = match key {
+            None => "HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_owned(),
+            Some(key) => {
+                let accept = sha1::Sha1::from(format!("{}{}", key, WEBSOCKET_GUID))
+                    .digest()
+                    .bytes();
+
+                format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    base64::encode(accept)
+                )
+            }
+        };
+        stream.write_all(response.as_bytes()).await?;
+        stream.flush().await?;
+
+        Ok(response.starts_with("HTTP/1.1 101"))
+    }
+
+    async fn read_messages(
+        engine: &TuringEngine,
+        stream: &Async<TcpStream>,
+        changes: Sender<LogRecord>,
+        control: Sender<Control>,
+        ping_interval: Duration,
+    ) -> TuringResult<()> {
+        let mut stream = stream;
+        let mut message = Vec::new();
+
+        loop {
+            // A client that answers none of two pings is gone
+            let (fin, opcode, payload) =
+                deadline(ping_interval * 2, WebSocketGateway::read_frame(&mut stream)).await?;
+
+            match opcode {
+                OPCODE_CONTINUATION | OPCODE_TEXT | OPCODE_BINARY => {
+                    message.extend_from_slice(&payload);
+                    if message.len() > MAX_MESSAGE_LEN {
+                        return Err(TuringDbError::InvalidData);
+                    }
+                    if !fin {
+                        continue;
+                    }
+
+                    let reply =
+                        WebSocketGateway::subscribe(engine, &message, changes.clone()).await;
+                    message.clear();
+                    WebSocketGateway::send(&control, Control::Text(reply.to_string())).await;
+                }
+                OPCODE_PING => WebSocketGateway::send(&control, Control::Pong(payload)).await,
+                OPCODE_PONG => (),
+                OPCODE_CLOSE => {
+                    WebSocketGateway::send(&control, Control::Close).await;
+
+                    // The writing half ends the connection once the close frame is sent
+                    return future::pending().await;
+                }
+                _ => return Err(TuringDbError::InvalidData),
+            }
+        }
+    }
+
+    async fn write_messages(
+        stream: &Async<TcpStream>,
+        changes: Receiver<LogRecord>,
+        control: Receiver<Control>,
+        ping_interval: Duration,
+    ) -> TuringResult<()> {
+        let mut stream = stream;
+        let mut next_ping = Instant::now() + ping_interval;
+
+        loop {
+            let messages = future::or(
+                async {
+                    match control.recv().await {
+                        Ok(control) => control,
+                        Err(_) => Control::Close,
+                    }
+                },
+                async {
+                    match changes.recv().await {
+                        Ok(record) => Control::Text(WebSocketGateway::change(&record).to_string()),
+                        // The subscriber fell behind and was dropped
+                        Err(_) => Control::Close,
+                    }
+                },
+            );
+            let outgoing = future::or(messages, async {
+                Timer::at(next_ping).await;
+
+                Control::Ping
+            })
+            .await;
+
+            let (opcode, payload) = match outgoing {
+                Control::Text(text) => (OPCODE_TEXT, text.into_bytes()),
+                Control::Ping => {
+                    next_ping = Instant::now() + ping_interval;
+
+                    (OPCODE_PING, Vec::new())
+                }
+                Control::Pong(payload) => (OPCODE_PONG, payload),
+                Control::Close => (OPCODE_CLOSE, Vec::new()),
+            };
+            stream
+                .write_all(&WebSocketGateway::frame(opcode, &payload))
+                .await?;
+            stream.flush().await?;
+
+            if opcode == OPCODE_CLOSE {
+                return Ok(());
+            }
+        }
+    }
+
+    async fn subscribe(engine: &TuringEngine, message: &[u8], changes: Sender<LogRecord>) -> Json {
+        let request = match serde_json::from_slice::<Json>(message) {
+            Ok(Json::Object(request)) => request,
+            _ => return json!({ "error": format!("{:?}", TuringDbError::InvalidInput) }),
+        };
+
+        let (db, document) = match (request.get("subscribe"), request.get("document")) {
+            (Some(Json::String(db)), None) => (db, None),
+            (Some(Json::String(db)), Some(Json::String(document))) => (db, Some(document)),
+            _ => return json!({ "error": format!("{:?}", TuringDbError::InvalidInput) }),
+        };
+
+        match engine
+            .subscribe_with(Utf8Path::new(db), document.map(Utf8Path::new), changes)
+            .await
+        {
+            Ok(()) => json!({ "subscribed": db, "document": document }),
+            Err(error) => json!({ "error": format!("{:?}", error) }),
+        }
+    }
+
+    fn change(record: &LogRecord) -> Json {
+        let op = record.op();
+
+        json!({
+            "lsn": record.lsn(),
+            "db": op.db().as_str(),
+            "documents": op.documents().map(|documents| {
+                documents
+                    .iter()
+                    .map(|document| document.as_str())
+                    .collect::<Vec<&str>>()
+            }),
+            // Values that JSON cannot hold, such as integers past 64 bits, leave the operation out
+            "op": serde_json::to_value(op).unwrap_or(Json::Null),
+        })
+    }
+    // The control channel is unbounded and outlives the reading half
+    async fn send(control: &Sender<Control>, message: Control) {
+        control.send(message).await.ok();
+    }
+    /// Read a frame sent by a client, which are always masked
+    async fn read_frame(stream: &mut &Async<TcpStream>) -> TuringResult<(bool, u8, Vec<u8>)> {
+        let mut header = [0_u8; 2];
+        stream.read_exact(&mut header).await?;
+
+        let fin = header[0] & 0x80 != 0;
+        let opcode = header[0] & 0x0f;
+        if header[1] & 0x80 == 0 {
+            return Err(TuringDbError::InvalidData);
+        }
+
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0_u8; 2];
+                stream.read_exact(&mut len).await?;
+
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0_u8; 8];
+                stream.read_exact(&mut len).await?;
+
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if len > MAX_MESSAGE_LEN as u64 {
+            return Err(TuringDbError::InvalidData);
+        }
+
+        let mut mask = [0_u8; 4];
+        stream.read_exact(&mut mask).await?;
+
+        let mut payload = vec![0_u8; len as usize];
+        stream.read_exact(&mut payload).await?;
+        for (index, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[index % 4];
+        }
+
+        Ok((fin, opcode, payload))
+    }
+    /// A whole unmasked frame, as servers send them
+    fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = Vec::with_capacity(payload.len() + 10);
+        frame.push(0x80 | opcode);
+
+        match payload.len() {
+            len if len < 126 => frame.push(len as u8),
+            len => match len.try_into() {
+                Ok(len) => {
+                    frame.push(126);
+                    frame.extend_from_slice(&u16::to_be_bytes(len));
+                }
+                Err(_) => {
+                    frame.push(127);
+                    frame.extend_from_slice(&(len as u64).to_be_bytes());
+                }
+            },
+        }
+        frame.extend_from_slice(payload);
+
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{TuringDBDocumentOps, TuringDBOps};
+    use futures_lite::future::block_on;
+
+    /// A frame as clients send them, masked
+    fn masked(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [7_u8, 1, 3, 5];
+        let mut frame = vec![0x80 | opcode, 0x80 | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(index, byte)| byte ^ mask[index % 4]),
+        );
+
+        frame
+    }
+    /// The opcode and payload of the next frame from the gateway, short and unmasked
+    async fn read(stream: &mut &Async<TcpStream>) -> (u8, Vec<u8>) {
+        let mut header = [0_u8; 2];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut payload = vec![0_u8; header[1] as usize];
+        stream.read_exact(&mut payload).await.unwrap();
+
+        (header[0] & 0x0f, payload)
+    }
+
+    async fn read_json(stream: &mut &Async<TcpStream>) -> Json {
+        let (opcode, payload) = read(stream).await;
+        assert_eq!(opcode, OPCODE_TEXT);
+
+        serde_json::from_slice(&payload).unwrap()
+    }
+
+    #[test]
+    fn a_subscriber_is_sent_the_writes_to_its_database() {
+        block_on(async {
+            let engine = Arc::new(TuringEngine::ephemeral());
+            engine
+                .db_create(TuringDBOps::default().set_db_name("orders"))
+                .await
+                .unwrap();
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let address = listener.get_ref().local_addr().unwrap();
+
+            let serving = async {
+                let (stream, _) = listener.accept().await.unwrap();
+
+                WebSocketGateway::serve(Arc::clone(&engine), stream, Duration::from_secs(60)).await
+            };
+            let subscribing = async {
+                let client = Async::<TcpStream>::connect(address).await.unwrap();
+                let mut stream = &client;
+
+                // The sample handshake of RFC 6455
+                stream
+                    .write_all(
+                        b"GET /changes HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\n\
+                        Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                        Sec-WebSocket-Version: 13\r\n\r\n",
+                    )
+                    .await
+                    .unwrap();
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0_u8; 1];
+                    stream.read_exact(&mut byte).await.unwrap();
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8(head).unwrap();
+                assert!(head.starts_with("HTTP/1.1 101"));
+                assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+                stream
+                    .write_all(&masked(OPCODE_TEXT, b"{\"subscribe\": \"orders\"}"))
+                    .await
+                    .unwrap();
+                assert_eq!(read_json(&mut stream).await["subscribed"], "orders");
+
+                engine
+                    .document_create(
+                        &TuringDBDocumentOps::default()
+                            .set_db_name("orders")
+                            .set_document_name("order"),
+                    )
+                    .await
+                    .unwrap();
+                let change = read_json(&mut stream).await;
+                assert_eq!(change["db"], "orders");
+                assert_eq!(change["documents"], json!(["order"]));
+
+                stream.write_all(&masked(OPCODE_PING, b"hi")).await.unwrap();
+                assert_eq!(read(&mut stream).await, (OPCODE_PONG, b"hi".to_vec()));
+                stream.write_all(&masked(OPCODE_CLOSE, &[])).await.unwrap();
+                assert_eq!(read(&mut stream).await, (OPCODE_CLOSE, Vec::new()));
+            };
+
+            let (served, ()) = future::zip(serving, subscribing).await;
+            served.unwrap();
+        });
+    }
+
+    #[test]
+    fn a_request_that_is_not_an_upgrade_is_refused() {
+        block_on(async {
+            let listener = Async::<TcpListener>::bind(([127, 0, 0, 1], 0)).unwrap();
+            let address = listener.get_ref().local_addr().unwrap();
+
+            let refusing = async {
+                let (stream, _) = listener.accept().await.unwrap();
+
+                WebSocketGateway::handshake(&stream).await.unwrap()
+            };
+            let requesting = async {
+                let client = Async::<TcpStream>::connect(address).await.unwrap();
+                let mut stream = &client;
+                stream
+                    .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                    .await
+                    .unwrap();
+                let mut response = Vec::new();
+                stream.read_to_end(&mut response).await.unwrap();
+
+                response
+            };
+
+            let (upgraded, response) = future::zip(refusing, requesting).await;
+            assert!(!upgraded);
+            assert!(response.starts_with(b"HTTP/1.1 400"));
+        });
+    }
+}
@@ -0,0 +1,1185 @@
+use crate::{
+    AggregateGroup, BackupManifest, Compression, Conflict, Crdt, CrdtOp, DbStats, FieldData, LogOp,
+    LogRecord, Matched, Member, MerkleTree, OpsOutcome, PayloadEncoding, Query, RaftEntry,
+    ReplicaSnapshot, Stamp, TDBCell, Topology, TuringDbError, TuringResult, Version, WireError,
+    WriteOp,
+};
+use async_io::Timer;
+use camino::Utf8PathBuf;
+use futures_lite::{
+    future::{self, Future},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{convert::TryInto, io::ErrorKind, time::Duration};
+
+/// The bytes every frame starts with
+const FRAME_MAGIC: [u8; 4] = *b"TDBW";
+/// The magic, the version, the length of the payload, the opcode then the request id
+const FRAME_HEADER_LEN: usize = 18;
+/// The version of the frame format and of the messages frames carry.
+/// Version 2 starts every connection with a `ClientHello` answered by a `ServerHello`,
+/// version 3 adds the request id to the header, version 4 sends errors as a `WireError`
+pub const PROTOCOL_VERSION: u8 = 4;
+/// The server runs the commands of a connection concurrently and answers each one as soon as
+/// it is done, the client matches responses to commands by their request id
+pub const PIPELINING: &str = "pipelining";
+/// Payloads of at least `COMPRESSION_THRESHOLD` bytes are compressed with LZ4
+pub const LZ4_COMPRESSION: &str = "lz4";
+/// Payloads of at least `COMPRESSION_THRESHOLD` bytes are compressed with zstd,
+/// preferred over LZ4 when both peers speak both
+pub const ZSTD_COMPRESSION: &str = "zstd";
+/// The messages after the hellos are encoded as JSON instead of bincode
+pub const JSON_ENCODING: &str = "json";
+/// The messages after the hellos are encoded as MessagePack instead of bincode
+pub const MSGPACK_ENCODING: &str = "msgpack";
+/// The messages after the hellos are encoded as CBOR instead of bincode
+pub const CBOR_ENCODING: &str = "cbor";
+/// The optional features of the protocol this build speaks,
+/// a feature is only used on a connection once both peers listed it in their hello.
+/// A client lists at most one of the encodings, the one it asks for
+pub const PROTOCOL_FEATURES: &[&str] = &[
+    PIPELINING,
+    LZ4_COMPRESSION,
+    ZSTD_COMPRESSION,
+    JSON_ENCODING,
+    MSGPACK_ENCODING,
+    CBOR_ENCODING,
+];
+const PAYLOAD_ENCODINGS: &[&str] = &[JSON_ENCODING, MSGPACK_ENCODING, CBOR_ENCODING];
+/// The shortest payload compressed on a connection that agreed on a compression,
+/// shorter ones cost more to compress than they save
+pub const COMPRESSION_THRESHOLD: usize = 4 * 1024;
+/// Frames are compressed as they are sent, a fast level keeps up with the connection
+const WIRE_ZSTD_LEVEL: i32 = 1;
+/// The largest payload a frame is allowed to carry unless another limit is set
+pub const DEFAULT_MAX_FRAME_LEN: u32 = 16 * 1024 * 1024;
+
+/// A message carried in a frame, its opcode is written in the header
+/// so a peer knows what a frame holds before decoding it
+pub trait WireMessage: Serialize + DeserializeOwned {
+    fn opcode(&self) -> u8;
+}
+
+/// A request sent to a `TuringServer`
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum TuringCommand {
+///     DbCreate { db: Utf8PathBuf },
+///     DocumentCreate { db: Utf8PathBuf, document: Utf8PathBuf },
+///     DocumentDrop { db: Utf8PathBuf, document: Utf8PathBuf },
+///     FieldInsert { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8>, value: TDBCell },
+///     FieldGet { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8> },
+///     Query(Query),
+///     Execute(String),
+///     DbDrop { db: Utf8PathBuf },
+///     DbList,
+///     DocumentList { db: Utf8PathBuf },
+///     FieldModify { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8>, value: TDBCell },
+///     FieldRemove { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8> },
+///     Ping,
+///     DocumentGet { db: Utf8PathBuf, document: Utf8PathBuf },
+///     DocumentUpdateIf {
+///         db: Utf8PathBuf,
+///         document: Utf8PathBuf,
+///         expected_revision: u64,
+///         fields: Vec<(Vec<u8>, TDBCell)>,
+///     },
+///     TransactionBegin { db: Utf8PathBuf },
+///     TransactionWrite { transaction: u64, op: WriteOp },
+///     TransactionWriteTo { transaction: u64, db: Utf8PathBuf, op: WriteOp },
+///     TransactionCommit { transaction: u64 },
+///     TransactionRollback { transaction: u64 },
+///     TransactionSavepoint { transaction: u64, name: String },
+///     TransactionRollbackTo { transaction: u64, name: String },
+///     DocumentLock { db: Utf8PathBuf, document: Utf8PathBuf, ttl_ms: u64 },
+///     DocumentUnlock { db: Utf8PathBuf, document: Utf8PathBuf, token: u64 },
+///     FieldConflicts { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8> },
+///     FieldResolve { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8>, value: Option<TDBCell> },
+///     Topology,
+///     Bounded { max_staleness_ms: u64, command: Box<TuringCommand> },
+///     CrdtUpdate { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8>, op: CrdtOp },
+///     CrdtGet { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8> },
+///     Authenticate { token: String },
+///     RepoStats,
+///     DbStats { db: Utf8PathBuf },
+///     DocumentStats { db: Utf8PathBuf, document: Utf8PathBuf },
+///     Commit,
+///     Compact,
+///     Backup { target_dir: Utf8PathBuf },
+///     ServerInfo,
+///     ReplicaSnapshot,
+///     LogRead { after: Option<u64>, limit: u32 },
+///     ReplicaPromote,
+///     RaftRequestVote { term: u64, candidate: u64, last_log_index: u64, last_log_term: u64 },
+///     RaftAppendEntries {
+///         term: u64,
+///         leader: u64,
+///         prev_log_index: u64,
+///         prev_log_term: u64,
+///         entries: Vec<RaftEntry>,
+///         leader_commit: u64,
+///     },
+///     RaftInstallSnapshot { term: u64, leader: u64, last_index: u64, last_term: u64, ops: Vec<LogOp> },
+///     FieldMerge {
+///         db: Utf8PathBuf,
+///         document: Utf8PathBuf,
+///         key: Vec<u8>,
+///         value: Option<TDBCell>,
+///         stamp: Stamp,
+///     },
+///     PeerWrite { command: Box<TuringCommand> },
+///     Gossip { members: Vec<Member> },
+///     MerkleTree { db: Utf8PathBuf },
+///     MerkleLeaf { db: Utf8PathBuf, leaf: u32 },
+///     CrdtMerge { db: Utf8PathBuf, document: Utf8PathBuf, key: Vec<u8>, delta: Crdt },
+/// }
+/// ```
+/// The commands from `RepoStats` on are admin commands, a connection is only served them
+/// once it sent the admin token of the server in `Authenticate`. A transaction belongs to
+/// the connection that began it and is rolled back when the connection closes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TuringCommand {
+    DbCreate {
+        db: Utf8PathBuf,
+    },
+    DocumentCreate {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    DocumentDrop {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    FieldInsert {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldGet {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    Query(Query),
+    /// A TuringQL statement
+    Execute(String),
+    DbDrop {
+        db: Utf8PathBuf,
+    },
+    DbList,
+    DocumentList {
+        db: Utf8PathBuf,
+    },
+    FieldModify {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: TDBCell,
+    },
+    FieldRemove {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    /// Answered with `Pong` by the connection without reaching the engine, keeps an idle
+    /// connection from being closed and tells the client the server still answers
+    Ping,
+    /// Answered with `Document`, the fields of the document along with the revision they are at
+    DocumentGet {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    /// Replace every field of a document only if it is still at `expected_revision`, as read
+    /// with `DocumentGet`. Answered with `Revision` holding the revision the write left,
+    /// or with a `Conflict` error when another write got there first
+    DocumentUpdateIf {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        expected_revision: u64,
+        fields: Vec<(Vec<u8>, TDBCell)>,
+    },
+    /// Answered with `Transaction` holding the id the other transaction commands name it by
+    TransactionBegin {
+        db: Utf8PathBuf,
+    },
+    /// Stage a write, applied only once the transaction commits
+    TransactionWrite {
+        transaction: u64,
+        op: WriteOp,
+    },
+    /// Stage a write to another database than the one the transaction began on,
+    /// the commit then applies the writes to every database or to none
+    TransactionWriteTo {
+        transaction: u64,
+        db: Utf8PathBuf,
+        op: WriteOp,
+    },
+    /// Apply every staged write atomically, answered with `Done` once they are
+    TransactionCommit {
+        transaction: u64,
+    },
+    TransactionRollback {
+        transaction: u64,
+    },
+    /// Mark the writes staged so far, see `Transaction::savepoint`
+    TransactionSavepoint {
+        transaction: u64,
+        name: String,
+    },
+    /// Drop the writes staged since a savepoint, see `Transaction::rollback_to`
+    TransactionRollbackTo {
+        transaction: u64,
+        name: String,
+    },
+    /// Take an advisory lock on a document for `ttl_ms` milliseconds, answered with `Lease`.
+    /// The lease is released when the connection closes
+    DocumentLock {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        ttl_ms: u64,
+    },
+    DocumentUnlock {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        token: u64,
+    },
+    /// The version a field holds on a writer of a multi-writer cluster along with the versions
+    /// of it that lost to a concurrent write, answered with `Conflicts`
+    FieldConflicts {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    /// Write the value the application picked for a field holding conflicts, `None` removes it.
+    /// The conflicts older than the write are settled on every writer of the cluster
+    FieldResolve {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: Option<TDBCell>,
+    },
+    /// The nodes of the cluster as the server knows them through gossip, answered with `Topology`
+    Topology,
+    /// Run a read only when the data the server holds may lag behind the primary by at most
+    /// `max_staleness_ms` milliseconds, answered as the read is. A replica lagging further fails
+    /// with `StaleReplica`, a server that does not follow a primary always runs the read
+    Bounded {
+        max_staleness_ms: u64,
+        command: Box<TuringCommand>,
+    },
+    /// Change a CRDT field, answered with `Crdt`. On a writer of a multi-writer cluster
+    /// the change is merged into the field on every writer
+    CrdtUpdate {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        op: CrdtOp,
+    },
+    /// The contents of a CRDT field, answered with `Crdt`
+    CrdtGet {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+    },
+    /// Make the connection an admin one, answered with `Done` when `token` is the admin token
+    /// of the server and with `PermissionDenied` otherwise
+    Authenticate {
+        token: String,
+    },
+    /// The stats of every database, answered with `RepoStats`
+    RepoStats,
+    DbStats {
+        db: Utf8PathBuf,
+    },
+    DocumentStats {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+    },
+    /// Commit the repo
+    Commit,
+    /// Commit the repo and compact the ops log
+    Compact,
+    /// Back the repo up into a directory of the server that does not exist yet
+    Backup {
+        target_dir: Utf8PathBuf,
+    },
+    ServerInfo,
+    /// The databases and documents a replica seeds itself with, answered with `ReplicaSnapshot`
+    ReplicaSnapshot,
+    /// At most `limit` records of the ops log following `after`, or from the first one when `None`,
+    /// answered with `Records`. Fails with `LogGap` once compaction dropped records past `after`,
+    /// a replica then copies the repo again from `ReplicaSnapshot`
+    LogRead {
+        after: Option<u64>,
+        limit: u32,
+    },
+    /// Stop following the primary and accept writes
+    ReplicaPromote,
+    /// Sent by a candidate of a Raft cluster to the other nodes, answered with `RaftVote`
+    RaftRequestVote {
+        term: u64,
+        candidate: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    /// Sent by the leader of a Raft cluster, without entries as a heartbeat. Answered with `RaftAppended`
+    RaftAppendEntries {
+        term: u64,
+        leader: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<RaftEntry>,
+        leader_commit: u64,
+    },
+    /// The documents of the leader as of `last_index`, sent to a follower missing entries
+    /// the leader cut from its log. Answered with `RaftAppended`
+    RaftInstallSnapshot {
+        term: u64,
+        leader: u64,
+        last_index: u64,
+        last_term: u64,
+        ops: Vec<LogOp>,
+    },
+    /// A write to a field sent by a writer of a multi-writer cluster to its peers
+    FieldMerge {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        value: Option<TDBCell>,
+        stamp: Stamp,
+    },
+    /// A write to a database or a document sent by a writer of a multi-writer cluster to its peers
+    PeerWrite {
+        command: Box<TuringCommand>,
+    },
+    /// The members a node of a gossiping cluster knows, sent to another node.
+    /// Answered with the `Topology` the other node knows once it took them in
+    Gossip {
+        members: Vec<Member>,
+    },
+    /// The Merkle tree over the documents of a database, answered with `MerkleTree`
+    MerkleTree {
+        db: Utf8PathBuf,
+    },
+    /// The documents of a leaf of the Merkle tree of a database, answered with `DocumentHashes`
+    MerkleLeaf {
+        db: Utf8PathBuf,
+        leaf: u32,
+    },
+    /// The changes a writer of a multi-writer cluster made to a CRDT field, sent to its peers
+    CrdtMerge {
+        db: Utf8PathBuf,
+        document: Utf8PathBuf,
+        key: Vec<u8>,
+        delta: Crdt,
+    },
+}
+
+impl TuringCommand {
+    /// Whether the command is only served to admin connections
+    pub fn is_admin(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::RepoStats
+                | TuringCommand::DbStats { .. }
+                | TuringCommand::DocumentStats { .. }
+                | TuringCommand::Commit
+                | TuringCommand::Compact
+                | TuringCommand::Backup { .. }
+                | TuringCommand::ServerInfo
+                | TuringCommand::ReplicaSnapshot
+                | TuringCommand::LogRead { .. }
+                | TuringCommand::ReplicaPromote
+                | TuringCommand::RaftRequestVote { .. }
+                | TuringCommand::RaftAppendEntries { .. }
+                | TuringCommand::RaftInstallSnapshot { .. }
+                | TuringCommand::FieldMerge { .. }
+                | TuringCommand::PeerWrite { .. }
+                | TuringCommand::Gossip { .. }
+                | TuringCommand::MerkleTree { .. }
+                | TuringCommand::MerkleLeaf { .. }
+                | TuringCommand::CrdtMerge { .. }
+        )
+    }
+    /// Whether the command is sent between the nodes of a Raft cluster, an admin command
+    pub fn is_raft(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::RaftRequestVote { .. }
+                | TuringCommand::RaftAppendEntries { .. }
+                | TuringCommand::RaftInstallSnapshot { .. }
+        )
+    }
+    /// Whether the command is sent between the writers of a multi-writer cluster, an admin command
+    pub fn is_peer_write(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::FieldMerge { .. }
+                | TuringCommand::PeerWrite { .. }
+                | TuringCommand::CrdtMerge { .. }
+        )
+    }
+    /// Whether the command only reads the databases of the repo, the commands `Bounded` runs.
+    /// TuringQL statements only select and aggregate, so they are reads as well
+    pub fn is_read(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::FieldGet { .. }
+                | TuringCommand::Query(_)
+                | TuringCommand::Execute(_)
+                | TuringCommand::DbList
+                | TuringCommand::DocumentList { .. }
+                | TuringCommand::DocumentGet { .. }
+                | TuringCommand::FieldConflicts { .. }
+                | TuringCommand::CrdtGet { .. }
+        )
+    }
+    /// Whether the command may write to the repo
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::DbCreate { .. }
+                | TuringCommand::DocumentCreate { .. }
+                | TuringCommand::DocumentDrop { .. }
+                | TuringCommand::FieldInsert { .. }
+                | TuringCommand::DbDrop { .. }
+                | TuringCommand::FieldModify { .. }
+                | TuringCommand::FieldRemove { .. }
+                | TuringCommand::DocumentUpdateIf { .. }
+                | TuringCommand::FieldResolve { .. }
+                | TuringCommand::CrdtUpdate { .. }
+        )
+    }
+    /// Whether the command takes or releases a document lock, served by the connection holding the lease
+    pub fn is_lock(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::DocumentLock { .. } | TuringCommand::DocumentUnlock { .. }
+        )
+    }
+    /// Whether the command names a transaction, served by the connection that began it
+    pub fn is_transaction(&self) -> bool {
+        matches!(
+            self,
+            TuringCommand::TransactionBegin { .. }
+                | TuringCommand::TransactionWrite { .. }
+                | TuringCommand::TransactionWriteTo { .. }
+                | TuringCommand::TransactionCommit { .. }
+                | TuringCommand::TransactionRollback { .. }
+                | TuringCommand::TransactionSavepoint { .. }
+                | TuringCommand::TransactionRollbackTo { .. }
+        )
+    }
+}
+
+impl WireMessage for TuringCommand {
+    fn opcode(&self) -> u8 {
+        match self {
+            TuringCommand::DbCreate { .. } => 0x01,
+            TuringCommand::DocumentCreate { .. } => 0x02,
+            TuringCommand::DocumentDrop { .. } => 0x03,
+            TuringCommand::FieldInsert { .. } => 0x04,
+            TuringCommand::FieldGet { .. } => 0x05,
+            TuringCommand::Query(_) => 0x06,
+            TuringCommand::Execute(_) => 0x07,
+            TuringCommand::DbDrop { .. } => 0x08,
+            TuringCommand::DbList => 0x09,
+            TuringCommand::DocumentList { .. } => 0x0a,
+            TuringCommand::FieldModify { .. } => 0x0b,
+            TuringCommand::FieldRemove { .. } => 0x0c,
+            TuringCommand::Ping => 0x0d,
+            TuringCommand::DocumentGet { .. } => 0x0e,
+            TuringCommand::DocumentUpdateIf { .. } => 0x0f,
+            TuringCommand::TransactionBegin { .. } => 0x11,
+            TuringCommand::TransactionWrite { .. } => 0x12,
+            TuringCommand::TransactionCommit { .. } => 0x13,
+            TuringCommand::TransactionRollback { .. } => 0x14,
+            TuringCommand::TransactionSavepoint { .. } => 0x15,
+            TuringCommand::TransactionRollbackTo { .. } => 0x16,
+            TuringCommand::TransactionWriteTo { .. } => 0x17,
+            TuringCommand::DocumentLock { .. } => 0x18,
+            TuringCommand::DocumentUnlock { .. } => 0x19,
+            TuringCommand::FieldConflicts { .. } => 0x1a,
+            TuringCommand::FieldResolve { .. } => 0x1b,
+            TuringCommand::Topology => 0x1c,
+            TuringCommand::Bounded { .. } => 0x1d,
+            TuringCommand::CrdtUpdate { .. } => 0x1e,
+            TuringCommand::CrdtGet { .. } => 0x1f,
+            TuringCommand::Authenticate { .. } => 0x20,
+            TuringCommand::RepoStats => 0x21,
+            TuringCommand::DbStats { .. } => 0x22,
+            TuringCommand::DocumentStats { .. } => 0x23,
+            TuringCommand::Commit => 0x24,
+            TuringCommand::Compact => 0x25,
+            TuringCommand::Backup { .. } => 0x26,
+            TuringCommand::ServerInfo => 0x27,
+            TuringCommand::ReplicaSnapshot => 0x28,
+            TuringCommand::LogRead { .. } => 0x29,
+            TuringCommand::ReplicaPromote => 0x2a,
+            TuringCommand::RaftRequestVote { .. } => 0x2b,
+            TuringCommand::RaftAppendEntries { .. } => 0x2c,
+            TuringCommand::RaftInstallSnapshot { .. } => 0x2d,
+            TuringCommand::FieldMerge { .. } => 0x2e,
+            TuringCommand::PeerWrite { .. } => 0x2f,
+            TuringCommand::Gossip { .. } => 0x30,
+            TuringCommand::MerkleTree { .. } => 0x31,
+            TuringCommand::MerkleLeaf { .. } => 0x32,
+            TuringCommand::CrdtMerge { .. } => 0x33,
+        }
+    }
+}
+
+/// The reply to a `TuringCommand`, the outcome of the operation it ran on the engine.
+/// Errors are sent as a `WireError` with the code of the error, whether it is retryable
+/// and the key it is about
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+/// pub enum TuringResponse {
+///     Done,
+///     Field(FieldData),
+///     Documents {
+///         documents: Vec<Matched>,
+///         continuation: Option<String>,
+///     },
+///     Aggregated(Vec<AggregateGroup>),
+///     Error(WireError),
+///     Names(Vec<Utf8PathBuf>),
+///     Pong,
+///     Throttled { retry_after_ms: u64 },
+///     RepoStats(Vec<(Utf8PathBuf, DbStats)>),
+///     DbStats(DbStats),
+///     DocumentStats { fields: usize, bytes: u64 },
+///     Backup(BackupManifest),
+///     ServerInfo(ServerInfo),
+///     Transaction { transaction: u64 },
+///     Document { revision: u64, fields: Vec<(Vec<u8>, FieldData)> },
+///     Revision { revision: u64 },
+///     Lease { token: u64 },
+///     ReplicaSnapshot(ReplicaSnapshot),
+///     Records(Vec<LogRecord>),
+///     RaftVote { term: u64, granted: bool },
+///     RaftAppended { term: u64, success: bool, match_index: u64 },
+///     Conflicts { version: Option<Version>, conflicts: Vec<Conflict> },
+///     Topology(Topology),
+///     MerkleTree(MerkleTree),
+///     DocumentHashes(Vec<(Utf8PathBuf, u64)>),
+///     Crdt(Crdt),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TuringResponse {
+    /// The operation succeeded without returning data
+    Done,
+    Field(FieldData),
+    Documents {
+        documents: Vec<Matched>,
+        continuation: Option<String>,
+    },
+    Aggregated(Vec<AggregateGroup>),
+    Error(WireError),
+    /// The databases of the repo or the documents of a database
+    Names(Vec<Utf8PathBuf>),
+    Pong,
+    /// The client sent more commands than its rate limit allows, the command was not run
+    /// and may be sent again after `retry_after_ms` milliseconds
+    Throttled {
+        retry_after_ms: u64,
+    },
+    RepoStats(Vec<(Utf8PathBuf, DbStats)>),
+    DbStats(DbStats),
+    /// How many fields a document holds and the bytes of their keys and values
+    DocumentStats {
+        fields: usize,
+        bytes: u64,
+    },
+    Backup(BackupManifest),
+    ServerInfo(ServerInfo),
+    /// The id of the transaction a `TransactionBegin` opened
+    Transaction {
+        transaction: u64,
+    },
+    Document {
+        revision: u64,
+        fields: Vec<(Vec<u8>, FieldData)>,
+    },
+    /// The revision a document is at after a write
+    Revision {
+        revision: u64,
+    },
+    /// The fencing token of a document lock
+    Lease {
+        token: u64,
+    },
+    ReplicaSnapshot(ReplicaSnapshot),
+    /// Records of the ops log in the order they were logged
+    Records(Vec<LogRecord>),
+    RaftVote {
+        term: u64,
+        granted: bool,
+    },
+    /// `match_index` is the last entry the follower holds once it accepted the entries,
+    /// or the last one the leader may try from next when it refused them
+    RaftAppended {
+        term: u64,
+        success: bool,
+        match_index: u64,
+    },
+    /// The version a field holds, `None` for a field no writer stamped, along with its conflicts oldest first
+    Conflicts {
+        version: Option<Version>,
+        conflicts: Vec<Conflict>,
+    },
+    Topology(Topology),
+    MerkleTree(MerkleTree),
+    /// The documents of a leaf of a Merkle tree with their hashes, sorted by name
+    DocumentHashes(Vec<(Utf8PathBuf, u64)>),
+    /// The contents of a CRDT field
+    Crdt(Crdt),
+}
+
+impl WireMessage for TuringResponse {
+    fn opcode(&self) -> u8 {
+        match self {
+            TuringResponse::Done => 0x81,
+            TuringResponse::Field(_) => 0x82,
+            TuringResponse::Documents { .. } => 0x83,
+            TuringResponse::Aggregated(_) => 0x84,
+            TuringResponse::Error(_) => 0x85,
+            TuringResponse::Names(_) => 0x86,
+            TuringResponse::Pong => 0x87,
+            TuringResponse::Throttled { .. } => 0x88,
+            TuringResponse::RepoStats(_) => 0x89,
+            TuringResponse::DbStats(_) => 0x8a,
+            TuringResponse::DocumentStats { .. } => 0x8b,
+            TuringResponse::Backup(_) => 0x8c,
+            TuringResponse::ServerInfo(_) => 0x8d,
+            TuringResponse::Transaction { .. } => 0x8e,
+            TuringResponse::Document { .. } => 0x8f,
+            TuringResponse::Revision { .. } => 0x91,
+            TuringResponse::Lease { .. } => 0x92,
+            TuringResponse::ReplicaSnapshot(_) => 0x93,
+            TuringResponse::Records(_) => 0x94,
+            TuringResponse::RaftVote { .. } => 0x95,
+            TuringResponse::RaftAppended { .. } => 0x96,
+            TuringResponse::Conflicts { .. } => 0x97,
+            TuringResponse::Topology(_) => 0x98,
+            TuringResponse::MerkleTree(_) => 0x99,
+            TuringResponse::DocumentHashes(_) => 0x9a,
+            TuringResponse::Crdt(_) => 0x9b,
+        }
+    }
+}
+
+/// The first message a client sends on a connection, with the version of the protocol
+/// it speaks and the optional features it supports
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ClientHello {
+///     version: u8,
+///     features: Vec<String>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientHello {
+    version: u8,
+    features: Vec<String>,
+}
+
+impl ClientHello {
+    /// List every feature but the encodings other than `encoding`
+    pub(crate) fn new(encoding: PayloadEncoding) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            features: PROTOCOL_FEATURES
+                .iter()
+                .filter(|feature| {
+                    !PAYLOAD_ENCODINGS.contains(feature) || encoding.feature() == Some(**feature)
+                })
+                .map(|feature| (*feature).to_owned())
+                .collect(),
+        }
+    }
+
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn get_features(&self) -> &[String] {
+        &self.features
+    }
+}
+
+impl WireMessage for ClientHello {
+    fn opcode(&self) -> u8 {
+        0x10
+    }
+}
+
+/// The answer of the server to a `ClientHello` it accepts. `features` are the ones both sides
+/// support and `auth_required` tells the client to authenticate before its commands are served
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ServerHello {
+///     version: u8,
+///     features: Vec<String>,
+///     auth_required: bool,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerHello {
+    version: u8,
+    features: Vec<String>,
+    auth_required: bool,
+}
+
+impl ServerHello {
+    /// Accept a client speaking this version of the protocol, any other version is
+    /// `UnsupportedFormat`. The server does not authenticate clients
+    pub(crate) fn answer(hello: ClientHello) -> TuringResult<ServerHello> {
+        if hello.version != PROTOCOL_VERSION {
+            return Err(TuringDbError::UnsupportedFormat {
+                found: hello.version as u32,
+                supported: PROTOCOL_VERSION as u32,
+            });
+        }
+
+        Ok(ServerHello {
+            version: PROTOCOL_VERSION,
+            features: hello
+                .features
+                .into_iter()
+                .filter(|feature| PROTOCOL_FEATURES.contains(&feature.as_str()))
+                .collect(),
+            auth_required: false,
+        })
+    }
+
+    pub fn get_version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn get_features(&self) -> &[String] {
+        &self.features
+    }
+
+    pub fn get_auth_required(&self) -> bool {
+        self.auth_required
+    }
+}
+
+impl WireMessage for ServerHello {
+    fn opcode(&self) -> u8 {
+        0x90
+    }
+}
+
+/// What a server tells an admin about itself
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+/// pub struct ServerInfo {
+///     version: String,
+///     protocol_version: u8,
+///     uptime: Duration,
+///     connections: usize,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServerInfo {
+    // The version of the crate the server was built from
+    version: String,
+    protocol_version: u8,
+    uptime: Duration,
+    // The open connections, the one asking included
+    connections: usize,
+}
+
+impl ServerInfo {
+    pub(crate) fn new(uptime: Duration, connections: usize) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").into(),
+            protocol_version: PROTOCOL_VERSION,
+            uptime,
+            connections,
+        }
+    }
+
+    pub fn get_version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn get_protocol_version(&self) -> u8 {
+        self.protocol_version
+    }
+
+    pub fn get_uptime(&self) -> Duration {
+        self.uptime
+    }
+
+    pub fn get_connections(&self) -> usize {
+        self.connections
+    }
+}
+
+impl From<TuringResult<OpsOutcome>> for TuringResponse {
+    fn from(outcome: TuringResult<OpsOutcome>) -> Self {
+        match outcome {
+            Ok(OpsOutcome::FieldContents(field_data)) => TuringResponse::Field(field_data),
+            Ok(OpsOutcome::DocumentMatches {
+                documents,
+                continuation,
+            }) => TuringResponse::Documents {
+                documents,
+                continuation,
+            },
+            Ok(OpsOutcome::Aggregated(groups)) => TuringResponse::Aggregated(groups),
+            Ok(OpsOutcome::DbList(names)) | Ok(OpsOutcome::DocumentList(names)) => {
+                TuringResponse::Names(names)
+            }
+            Ok(OpsOutcome::RepoEmpty) | Ok(OpsOutcome::DbEmpty) => {
+                TuringResponse::Names(Vec::new())
+            }
+            Ok(OpsOutcome::RepoStats(stats)) => TuringResponse::RepoStats(stats),
+            Ok(OpsOutcome::DbStats(stats)) => TuringResponse::DbStats(stats),
+            Ok(OpsOutcome::BackupTaken(manifest)) => TuringResponse::Backup(manifest),
+            Ok(OpsOutcome::TransactionBegun { transaction }) => {
+                TuringResponse::Transaction { transaction }
+            }
+            Ok(OpsOutcome::DocumentRevision { revision, fields }) => {
+                TuringResponse::Document { revision, fields }
+            }
+            Ok(OpsOutcome::DocumentUpdated { revision }) => TuringResponse::Revision { revision },
+            Ok(OpsOutcome::DocumentLeased { token }) => TuringResponse::Lease { token },
+            Ok(OpsOutcome::FieldConflicts { version, conflicts }) => {
+                TuringResponse::Conflicts { version, conflicts }
+            }
+            Ok(OpsOutcome::CrdtState(state)) => TuringResponse::Crdt(state),
+            Ok(_) => TuringResponse::Done,
+            Err(error) => TuringResponse::error(&error, None),
+        }
+    }
+}
+
+impl TuringResponse {
+    /// The response to a command that failed with `error`, `key` names what the error is about
+    pub(crate) fn error(error: &TuringDbError, key: Option<String>) -> Self {
+        match error {
+            TuringDbError::Throttled { retry_after_ms } => TuringResponse::Throttled {
+                retry_after_ms: *retry_after_ms,
+            },
+            error => TuringResponse::Error(WireError::from(error).set_key(key)),
+        }
+    }
+}
+
+/// A message as it travels between a client and a server. On the wire a frame is
+/// the magic `TDBW`, the protocol version as a byte, the length of the payload as a little endian
+/// `u32`, the opcode of the message as a byte, the request id as a little endian `u64` then
+/// the payload, the message encoded with bincode or the encoding the handshake agreed on.
+/// The hellos are always bincode. A response carries the request id of its command
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq)]
+/// pub struct Frame {
+///     opcode: u8,
+///     request_id: u64,
+///     payload: Vec<u8>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    opcode: u8,
+    request_id: u64,
+    payload: Vec<u8>,
+}
+
+impl Frame {
+    /// A frame with the request id `0` until another one is set
+    pub fn encode<M: WireMessage>(message: &M) -> TuringResult<Frame> {
+        Frame::encode_with(message, PayloadEncoding::Bincode)
+    }
+    /// Encode the message as the handshake of the connection agreed on
+    pub fn encode_with<M: WireMessage>(
+        message: &M,
+        encoding: PayloadEncoding,
+    ) -> TuringResult<Frame> {
+        Ok(Frame {
+            opcode: message.opcode(),
+            request_id: 0,
+            payload: encoding.encode(message)?,
+        })
+    }
+
+    pub fn set_request_id(mut self, request_id: u64) -> Self {
+        self.request_id = request_id;
+
+        self
+    }
+    /// Fails with `InvalidData` when the payload is not the message the opcode announced
+    pub fn decode<M: WireMessage>(&self) -> TuringResult<M> {
+        self.decode_with(PayloadEncoding::Bincode)
+    }
+
+    pub fn decode_with<M: WireMessage>(&self, encoding: PayloadEncoding) -> TuringResult<M> {
+        let message: M = encoding.decode(&self.payload)?;
+        if message.opcode() != self.opcode {
+            return Err(TuringDbError::InvalidData);
+        }
+
+        Ok(message)
+    }
+
+    /// Compress the payload on a connection that agreed on `compression`. Every payload
+    /// of such a connection starts with the tag of its compression, payloads shorter than
+    /// `COMPRESSION_THRESHOLD` are tagged as not compressed
+    pub(crate) fn compress(self, compression: Option<Compression>) -> TuringResult<Frame> {
+        let compression = match compression {
+            None => return Ok(self),
+            Some(_) if self.payload.len() < COMPRESSION_THRESHOLD => Compression::None,
+            Some(compression) => compression,
+        };
+
+        Ok(Frame {
+            payload: compression.compress(&self.payload)?,
+            ..self
+        })
+    }
+    /// Undo `compress`, a payload that would decompress to more than `max_len` bytes is rejected
+    pub(crate) fn decompress(
+        self,
+        compression: Option<Compression>,
+        max_len: u32,
+    ) -> TuringResult<Frame> {
+        if compression.is_none() {
+            return Ok(self);
+        }
+
+        Ok(Frame {
+            payload: Compression::decompress_within(&self.payload, max_len as usize)?,
+            ..self
+        })
+    }
+
+    pub fn opcode(&self) -> u8 {
+        self.opcode
+    }
+
+    pub fn request_id(&self) -> u64 {
+        self.request_id
+    }
+    /// Read the next frame, `None` when the peer closed the connection between two frames.
+    /// A frame cut short is `UnexpectedEof` and a payload longer than `max_len` is rejected
+    /// with `FrameTooLarge` before any of it is read
+    pub async fn read<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        max_len: u32,
+    ) -> TuringResult<Option<Frame>> {
+        let mut header = [0_u8; FRAME_HEADER_LEN];
+        let mut filled = 0;
+        while filled < FRAME_HEADER_LEN {
+            match reader.read(&mut header[filled..]).await {
+                Ok(0) if filled == 0 => return Ok(None),
+                Ok(0) => return Err(TuringDbError::UnexpectedEof),
+                Ok(read) => filled += read,
+                Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        if header[0..4] != FRAME_MAGIC {
+            return Err(TuringDbError::InvalidData);
+        }
+        if header[4] != PROTOCOL_VERSION {
+            return Err(TuringDbError::UnsupportedFormat {
+                found: header[4] as u32,
+                supported: PROTOCOL_VERSION as u32,
+            });
+        }
+
+        let (len, request_id) = match (header[5..9].try_into(), header[10..18].try_into()) {
+            (Ok(len), Ok(request_id)) => (u32::from_le_bytes(len), u64::from_le_bytes(request_id)),
+            _ => return Err(TuringDbError::Bug("Frame header is too short".into())),
+        };
+        if len > max_len {
+            return Err(TuringDbError::FrameTooLarge {
+                len: len as u64,
+                max: max_len as u64,
+            });
+        }
+
+        let mut payload = vec![0_u8; len as usize];
+        reader.read_exact(&mut payload).await?;
+
+        Ok(Some(Frame {
+            opcode: header[9],
+            request_id,
+            payload,
+        }))
+    }
+
+    pub async fn write<W: AsyncWrite + Unpin>(&self, writer: &mut W) -> TuringResult<()> {
+        writer.write_all(&self.to_bytes()?).await?;
+        writer.flush().await?;
+
+        Ok(())
+    }
+    /// The header followed by the payload, as the frame is sent
+    pub fn to_bytes(&self) -> TuringResult<Vec<u8>> {
+        let len = match self.payload.len().try_into() {
+            Ok(len) => len,
+            Err(_) => {
+                return Err(TuringDbError::FrameTooLarge {
+                    len: self.payload.len() as u64,
+                    max: u32::MAX as u64,
+                })
+            }
+        };
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + self.payload.len());
+        frame.extend_from_slice(&FRAME_MAGIC);
+        frame.push(PROTOCOL_VERSION);
+        frame.extend_from_slice(&u32::to_le_bytes(len));
+        frame.push(self.opcode);
+        frame.extend_from_slice(&u64::to_le_bytes(self.request_id));
+        frame.extend_from_slice(&self.payload);
+
+        Ok(frame)
+    }
+}
+
+/// The compression of a connection whose handshake agreed on `features`
+pub(crate) fn wire_compression(features: &[String]) -> Option<Compression> {
+    if features.iter().any(|feature| feature == ZSTD_COMPRESSION) {
+        Some(Compression::Zstd {
+            level: WIRE_ZSTD_LEVEL,
+        })
+    } else if features.iter().any(|feature| feature == LZ4_COMPRESSION) {
+        Some(Compression::Lz4)
+    } else {
+        None
+    }
+}
+
+/// The encoding of a connection whose handshake agreed on `features`
+pub(crate) fn wire_encoding(features: &[String]) -> PayloadEncoding {
+    if features.iter().any(|feature| feature == JSON_ENCODING) {
+        PayloadEncoding::Json
+    } else if features.iter().any(|feature| feature == MSGPACK_ENCODING) {
+        PayloadEncoding::MsgPack
+    } else if features.iter().any(|feature| feature == CBOR_ENCODING) {
+        PayloadEncoding::Cbor
+    } else {
+        PayloadEncoding::Bincode
+    }
+}
+
+/// Fail with `TimedOut` unless `operation` completes within `timeout`
+pub(crate) async fn deadline<T>(
+    timeout: Duration,
+    operation: impl Future<Output = TuringResult<T>>,
+) -> TuringResult<T> {
+    future::or(operation, async {
+        Timer::after(timeout).await;
+
+        Err(TuringDbError::TimedOut)
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::future::block_on;
+
+    fn command() -> TuringCommand {
+        TuringCommand::FieldGet {
+            db: "orders".into(),
+            document: "order".into(),
+            key: b"status".to_vec(),
+        }
+    }
+
+    #[test]
+    fn a_frame_reads_back_as_it_was_written() {
+        block_on(async {
+            let frame = Frame::encode(&command()).unwrap().set_request_id(7);
+            let mut bytes = Vec::new();
+            frame.write(&mut bytes).await.unwrap();
+            Frame::encode(&TuringCommand::Ping)
+                .unwrap()
+                .write(&mut bytes)
+                .await
+                .unwrap();
+
+            let mut reader = bytes.as_slice();
+            let read = Frame::read(&mut reader, DEFAULT_MAX_FRAME_LEN)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(read, frame);
+            assert_eq!(read.request_id(), 7);
+            assert_eq!(read.decode::<TuringCommand>().unwrap(), command());
+
+            let read = Frame::read(&mut reader, DEFAULT_MAX_FRAME_LEN)
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(read.decode::<TuringCommand>().unwrap(), TuringCommand::Ping);
+            // The peer closed the connection between two frames
+            assert!(Frame::read(&mut reader, DEFAULT_MAX_FRAME_LEN)
+                .await
+                .unwrap()
+                .is_none());
+        });
+    }
+
+    #[test]
+    fn a_malformed_frame_is_rejected() {
+        block_on(async {
+            let bytes = Frame::encode(&command()).unwrap().to_bytes().unwrap();
+
+            let mut cut_short = &bytes[..bytes.len() - 1];
+            assert!(matches!(
+                Frame::read(&mut cut_short, DEFAULT_MAX_FRAME_LEN).await,
+                Err(TuringDbError::UnexpectedEof)
+            ));
+            let mut header_cut_short = &bytes[..FRAME_HEADER_LEN - 1];
+            assert!(matches!(
+                Frame::read(&mut header_cut_short, DEFAULT_MAX_FRAME_LEN).await,
+                Err(TuringDbError::UnexpectedEof)
+            ));
+            assert!(matches!(
+                Frame::read(&mut bytes.as_slice(), 4).await,
+                Err(TuringDbError::FrameTooLarge { max: 4, .. })
+            ));
+
+            let mut bad_magic = bytes.clone();
+            bad_magic[0] = b'X';
+            assert!(matches!(
+                Frame::read(&mut bad_magic.as_slice(), DEFAULT_MAX_FRAME_LEN).await,
+                Err(TuringDbError::InvalidData)
+            ));
+            let mut older = bytes.clone();
+            older[4] = PROTOCOL_VERSION - 1;
+            assert!(matches!(
+                Frame::read(&mut older.as_slice(), DEFAULT_MAX_FRAME_LEN).await,
+                Err(TuringDbError::UnsupportedFormat { .. })
+            ));
+        });
+    }
+
+    #[test]
+    fn a_payload_that_is_not_the_message_of_its_opcode_is_rejected() {
+        let frame = Frame::encode(&TuringCommand::Ping).unwrap();
+        let mut bytes = frame.to_bytes().unwrap();
+        bytes[9] = command().opcode();
+
+        let frame = block_on(Frame::read(&mut bytes.as_slice(), DEFAULT_MAX_FRAME_LEN))
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            frame.decode::<TuringCommand>(),
+            Err(TuringDbError::InvalidData)
+        ));
+    }
+}
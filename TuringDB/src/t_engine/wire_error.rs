@@ -0,0 +1,242 @@
+use crate::{TuringCommand, TuringDbError};
+use serde::{Deserialize, Serialize};
+
+/// What kind of error a server answered a command with, so clients can act on it
+/// without reading the message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum ErrorCode {
+    /// A database, document, field or other named item does not exist
+    NotFound,
+    AlreadyExists,
+    /// The command is malformed or does not apply to what it names
+    InvalidInput,
+    /// The command expected another revision or condition than the one it found
+    Conflict,
+    /// The connection is not allowed to run the command
+    Unauthorized,
+    /// Stored data or a payload could not be read back
+    Corrupt,
+    QuotaExceeded,
+    /// A payload is larger than the server accepts
+    TooLarge,
+    /// The repo or the protocol does not support the command
+    Unsupported,
+    /// The repo follows a primary and only serves reads
+    ReadOnly,
+    /// The node is not the leader of its Raft cluster, the key holds the address of the leader when known
+    NotLeader,
+    /// The server cannot run the command right now, sending it again later may succeed
+    Unavailable,
+    /// A failure of the server itself
+    Internal,
+}
+
+/// An error as a server sends it in `TuringResponse::Error`. `message` holds the `Debug` output
+/// of the `TuringDbError`, `retryable` tells whether sending the same command again later
+/// may succeed and `key` names the database, document or field the error is about when known
+/// ```
+/// #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+/// pub struct WireError {
+///     code: ErrorCode,
+///     message: String,
+///     retryable: bool,
+///     key: Option<String>,
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct WireError {
+    code: ErrorCode,
+    message: String,
+    retryable: bool,
+    key: Option<String>,
+}
+
+impl WireError {
+    pub(crate) fn set_key(mut self, key: Option<String>) -> Self {
+        self.key = key;
+
+        self
+    }
+
+    pub fn get_code(&self) -> ErrorCode {
+        self.code
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn is_retryable(&self) -> bool {
+        self.retryable
+    }
+
+    pub fn get_key(&self) -> Option<&str> {
+        self.key.as_deref()
+    }
+}
+
+impl From<&TuringDbError> for WireError {
+    fn from(error: &TuringDbError) -> Self {
+        let code = match error {
+            TuringDbError::DbNotFound
+            | TuringDbError::DocumentNotFound
+            | TuringDbError::FieldNotFound
+            | TuringDbError::NotFound
+            | TuringDbError::DocumentNoLongerExists
+            | TuringDbError::SnapshotNotFound
+            | TuringDbError::RevisionNotFound
+            | TuringDbError::PartitionNotFound
+            | TuringDbError::CursorNotFound
+            | TuringDbError::ViewNotFound
+            | TuringDbError::IndexNotFound
+            | TuringDbError::TransactionNotFound
+            | TuringDbError::SavepointNotFound
+            | TuringDbError::LogGap => ErrorCode::NotFound,
+            TuringDbError::KeyAlreadyExists | TuringDbError::AlreadyExists => {
+                ErrorCode::AlreadyExists
+            }
+            TuringDbError::PathReadIsNotUtf8Path
+            | TuringDbError::DbNameMissing
+            | TuringDbError::InvalidPathUnicodeName
+            | TuringDbError::InvalidInput
+            | TuringDbError::Serialization(_)
+            | TuringDbError::StreamedField
+            | TuringDbError::QuerySyntax { .. }
+            | TuringDbError::InvalidContinuation
+            | TuringDbError::FieldTypeMismatch
+            | TuringDbError::NumericOverflow
+            | TuringDbError::InvalidPattern(_)
+            | TuringDbError::SchemaViolation(_)
+            | TuringDbError::NotVectorDatabase
+            | TuringDbError::UniqueKeyNotSet
+            | TuringDbError::CrdtMismatch => ErrorCode::InvalidInput,
+            TuringDbError::RevisionConflict { .. }
+            | TuringDbError::ConditionNotMet
+            | TuringDbError::DocumentLocked
+            | TuringDbError::LockNotHeld => ErrorCode::Conflict,
+            TuringDbError::PermissionDenied => ErrorCode::Unauthorized,
+            TuringDbError::ReadOnlyReplica => ErrorCode::ReadOnly,
+            TuringDbError::InvalidData
+            | TuringDbError::UnexpectedEof
+            | TuringDbError::DocumentCorrupted { .. }
+            | TuringDbError::Compression(_)
+            | TuringDbError::ChunkCorrupted { .. } => ErrorCode::Corrupt,
+            TuringDbError::QuotaExceeded { .. } => ErrorCode::QuotaExceeded,
+            TuringDbError::FrameTooLarge { .. } => ErrorCode::TooLarge,
+            TuringDbError::EphemeralRepo
+            | TuringDbError::UnsupportedFormat { .. }
+            | TuringDbError::NotReplicated
+            | TuringDbError::NotSharded
+            | TuringDbError::NotClustered => ErrorCode::Unsupported,
+            TuringDbError::NotLeader { .. } => ErrorCode::NotLeader,
+            TuringDbError::ConnectionRefused
+            | TuringDbError::ConnectionReset
+            | TuringDbError::ConnectionAborted
+            | TuringDbError::NotConnected
+            | TuringDbError::BrokenPipe
+            | TuringDbError::WouldBlock
+            | TuringDbError::TimedOut
+            | TuringDbError::Interrupted
+            | TuringDbError::RepoLocked { .. }
+            | TuringDbError::Storage(_)
+            | TuringDbError::TooManyConnections { .. }
+            | TuringDbError::Throttled { .. }
+            | TuringDbError::StaleReplica { .. } => ErrorCode::Unavailable,
+            TuringDbError::Server(error) => error.code,
+            TuringDbError::UserHomeDirMissing
+            | TuringDbError::UserHomeDirIsInvalidUtf8Path
+            | TuringDbError::AddrInUse
+            | TuringDbError::AddrNotAvailable
+            | TuringDbError::WriteZero
+            | TuringDbError::Other(_)
+            | TuringDbError::SystemViolation(_)
+            | TuringDbError::Bug(_)
+            | TuringDbError::Tls(_) => ErrorCode::Internal,
+        };
+
+        Self {
+            code,
+            message: format!("{:?}", error),
+            retryable: code == ErrorCode::Unavailable,
+            key: None,
+        }
+    }
+}
+
+/// The names a command touches, kept while it runs to tell which one an error is about
+#[derive(Debug, Default)]
+pub(crate) struct ErrorKeys {
+    db: Option<String>,
+    document: Option<String>,
+    field: Option<String>,
+}
+
+impl ErrorKeys {
+    pub(crate) fn of(command: &TuringCommand) -> Self {
+        match command {
+            TuringCommand::DbCreate { db }
+            | TuringCommand::DbDrop { db }
+            | TuringCommand::DocumentList { db }
+            | TuringCommand::DbStats { db }
+            | TuringCommand::MerkleTree { db }
+            | TuringCommand::MerkleLeaf { db, .. }
+            | TuringCommand::TransactionBegin { db }
+            | TuringCommand::TransactionWriteTo { db, .. } => ErrorKeys {
+                db: Some(db.to_string()),
+                ..Default::default()
+            },
+            TuringCommand::DocumentCreate { db, document }
+            | TuringCommand::DocumentDrop { db, document }
+            | TuringCommand::DocumentStats { db, document }
+            | TuringCommand::DocumentGet { db, document }
+            | TuringCommand::DocumentUpdateIf { db, document, .. }
+            | TuringCommand::DocumentLock { db, document, .. }
+            | TuringCommand::DocumentUnlock { db, document, .. } => ErrorKeys {
+                db: Some(db.to_string()),
+                document: Some(document.to_string()),
+                field: None,
+            },
+            TuringCommand::FieldInsert {
+                db, document, key, ..
+            }
+            | TuringCommand::FieldGet { db, document, key }
+            | TuringCommand::FieldModify {
+                db, document, key, ..
+            }
+            | TuringCommand::FieldRemove { db, document, key }
+            | TuringCommand::FieldConflicts { db, document, key }
+            | TuringCommand::CrdtGet { db, document, key }
+            | TuringCommand::CrdtUpdate {
+                db, document, key, ..
+            }
+            | TuringCommand::CrdtMerge {
+                db, document, key, ..
+            }
+            | TuringCommand::FieldResolve {
+                db, document, key, ..
+            } => ErrorKeys {
+                db: Some(db.to_string()),
+                document: Some(document.to_string()),
+                field: Some(String::from_utf8_lossy(key).into_owned()),
+            },
+            TuringCommand::Bounded { command, .. } => ErrorKeys::of(command),
+            _ => ErrorKeys::default(),
+        }
+    }
+    /// The name `error` is about, when the command names it
+    pub(crate) fn offending(&self, error: &TuringDbError) -> Option<String> {
+        match error {
+            TuringDbError::DbNotFound => self.db.clone(),
+            TuringDbError::DocumentNotFound
+            | TuringDbError::DocumentNoLongerExists
+            | TuringDbError::DocumentLocked
+            | TuringDbError::LockNotHeld => self.document.clone(),
+            TuringDbError::FieldNotFound
+            | TuringDbError::KeyAlreadyExists
+            | TuringDbError::StreamedField
+            | TuringDbError::CrdtMismatch => self.field.clone(),
+            TuringDbError::NotLeader { leader } => leader.map(|leader| leader.to_string()),
+            _ => None,
+        }
+    }
+}